@@ -4,7 +4,7 @@ use blart::{
     },
     TreeMap,
 };
-use criterion::{criterion_group, criterion_main, BenchmarkGroup, Criterion};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkGroup, Criterion};
 
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 type Measurement = criterion_perf_events::Perf;
@@ -81,9 +81,60 @@ pub fn raw_api_benches(c: &mut Criterion<Measurement>) {
             ],
         ),
         "large_prefixes",
+    );
+    // number of keys = 4096, every inner node is a `Node16` with 16 children
+    setup_tree_run_benches_cleanup(c, generate_key_fixed_length([15; 3]), "dense_node16");
+    // number of keys = 4096, with long compressed paths below the root `Node16`s
+    setup_tree_run_benches_cleanup(
+        c,
+        generate_key_with_prefix(
+            [15; 3],
+            [
+                PrefixExpansion {
+                    base_index: 0,
+                    expanded_length: 64,
+                },
+                PrefixExpansion {
+                    base_index: 1,
+                    expanded_length: 64,
+                },
+            ],
+        ),
+        "dense_node16_long_prefixes",
     )
 }
 
+pub fn build_benches(c: &mut Criterion<Measurement>) {
+    // number of keys = 65536, every inner node grows from a `Node4` to a `Node16`
+    // when the keys are inserted one at a time
+    let entries: Vec<_> = generate_key_fixed_length([15; 4])
+        .enumerate()
+        .map(|(idx, key)| (key, idx))
+        .collect();
+
+    let mut group = c.benchmark_group("build_sorted");
+    group.bench_function("insert_each", |b| {
+        b.iter_batched(
+            || entries.clone(),
+            |entries| {
+                let mut tree = TreeMap::new();
+                for (key, value) in entries {
+                    let _ = tree.try_insert(key, value).unwrap();
+                }
+                tree
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("from_sorted_iter", |b| {
+        b.iter_batched(
+            || entries.clone(),
+            |entries| TreeMap::try_from_sorted_iter(entries).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 fn create_criterion_configuration() -> Criterion<Measurement> {
     use perfcnt::linux::{HardwareEventType, PerfCounterBuilderLinux};
@@ -121,6 +172,6 @@ fn create_criterion_configuration() -> Criterion<Measurement> {
 criterion_group! {
     name = benches;
     config = create_criterion_configuration();
-    targets = raw_api_benches
+    targets = raw_api_benches, build_benches
 }
 criterion_main!(benches);
@@ -1,5 +1,5 @@
 use argh::FromArgs;
-use blart::{visitor::TreeStatsCollector, TreeMap};
+use blart::TreeMap;
 use std::{
     error::Error,
     ffi::CString,
@@ -41,21 +41,15 @@ fn main() -> Result<(), Box<dyn Error>> {
 }
 
 fn collect_and_output_stats(tree: TreeMap<Box<[u8]>, ()>) -> Result<(), Box<dyn Error>> {
-    let root = tree.into_raw();
-
-    // SAFETY: There are no concurrent mutation to the tree node or its children
-    let stats = unsafe { TreeStatsCollector::collect(root.unwrap()) };
-
-    // SAFETY: This root pointer was created from earlier invocation of
-    // `TreeMap::into_raw`, it is safe. No other tree is using this root.
-    let _tree = unsafe { TreeMap::from_raw(root) };
+    let stats = tree.stats();
 
     println!("{stats:#?}");
 
-    let overhead_bytes_per_key_byte =
-        (stats.total_inner_node_bytes as f64) / (stats.total_key_bytes as f64);
+    let overhead_bytes_per_key_byte = stats.overhead_per_key_byte();
+    let fill_factor = stats.average_fill_factor();
 
     println!("{overhead_bytes_per_key_byte} bytes of overhead, per byte of key stored in tree");
+    println!("{fill_factor} of the inner node child slots are occupied");
 
     Ok(())
 }
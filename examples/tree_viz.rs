@@ -1,8 +1,5 @@
 use argh::FromArgs;
-use blart::{
-    visitor::{DotPrinter, DotPrinterSettings},
-    TreeMap,
-};
+use blart::{visitor::DotPrinterSettings, TreeMap};
 use std::{
     error::Error,
     fmt::Display,
@@ -74,22 +71,12 @@ fn write_tree(
     output: &mut dyn Write,
     tree: TreeMap<Box<[u8]>, String>,
 ) -> Result<(), Box<dyn Error>> {
-    let root = tree.into_raw();
-
-    // SAFETY: There are no concurrent mutation to the tree node or its children
-    unsafe {
-        DotPrinter::print_tree(
-            output,
-            &root.unwrap(),
-            DotPrinterSettings {
-                display_node_address: false,
-            },
-        )?
-    };
-
-    // SAFETY: This tree was converted to raw pointer and back immediately. No other
-    // access
-    let _tree = unsafe { TreeMap::from_raw(root) };
+    tree.write_dot(
+        output,
+        DotPrinterSettings {
+            display_node_address: false,
+        },
+    )?;
 
     Ok(())
 }
@@ -0,0 +1,152 @@
+//! The allocator used for the nodes of a tree.
+//!
+//! With the `allocator_api` feature, which requires a nightly compiler, this
+//! module re-exports the unstable [`Allocator`] trait and [`Global`] allocator
+//! from the standard library, so that any allocator implementing the standard
+//! trait can be used with a tree. Otherwise, it provides a minimal stand-in
+//! with the same names and method signatures, so that code written against
+//! these items compiles with or without the feature.
+
+#[cfg(feature = "allocator_api")]
+pub use std::alloc::{AllocError, Allocator, Global};
+
+#[cfg(not(feature = "allocator_api"))]
+mod stable {
+    use crate::nightly_rust_apis::non_null_slice_from_raw_parts;
+    use std::{alloc::Layout, error::Error, fmt, ptr::NonNull};
+
+    /// The error returned when an [`Allocator`] could not satisfy an
+    /// allocation request.
+    #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+    pub struct AllocError;
+
+    impl fmt::Display for AllocError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("memory allocation failed")
+        }
+    }
+
+    impl Error for AllocError {}
+
+    /// An implementation of `Allocator` can allocate and deallocate blocks of
+    /// memory, described by a [`Layout`].
+    ///
+    /// This is a stand-in for the unstable `std::alloc::Allocator` trait, with
+    /// only the methods that are needed to allocate tree nodes.
+    ///
+    /// # Safety
+    ///
+    ///  - Memory blocks returned from an allocator must point to valid memory
+    ///    and retain their validity until they are deallocated, or the
+    ///    allocator is dropped.
+    ///  - Moving or copying the allocator must not invalidate the memory blocks
+    ///    returned from it.
+    pub unsafe trait Allocator {
+        /// Attempt to allocate a block of memory which fits the given
+        /// `layout`.
+        ///
+        /// # Errors
+        ///
+        /// Returns an [`AllocError`] if the memory could not be allocated.
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError>;
+
+        /// Deallocate the memory referenced by `ptr`.
+        ///
+        /// # Safety
+        ///
+        ///  - `ptr` must denote a block of memory currently allocated by this
+        ///    allocator.
+        ///  - `layout` must be the same layout that was used to allocate the
+        ///    block of memory.
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout);
+    }
+
+    // SAFETY: This forwards to the referenced allocator, which upholds the
+    // requirements of the trait.
+    unsafe impl<A: Allocator + ?Sized> Allocator for &A {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            (**self).allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            // SAFETY: Covered by the safety requirements of this function
+            unsafe { (**self).deallocate(ptr, layout) }
+        }
+    }
+
+    /// The global memory allocator, which forwards to the allocator registered
+    /// with the `#[global_allocator]` attribute, or the default of the
+    /// platform.
+    #[derive(Copy, Clone, Default, Debug)]
+    pub struct Global;
+
+    // SAFETY: The memory blocks are owned by the global allocator, which lives for
+    // the whole program, and `Global` holds no state to invalidate when it moves.
+    unsafe impl Allocator for Global {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            if layout.size() == 0 {
+                // The global allocator cannot make zero sized allocations, and they do not
+                // need any memory anyway.
+                let dangling = sptr::invalid_mut(layout.align());
+                // SAFETY: The alignment of a layout is never zero
+                let dangling = unsafe { NonNull::new_unchecked(dangling) };
+                return Ok(non_null_slice_from_raw_parts(dangling, 0));
+            }
+
+            // SAFETY: The layout has a non-zero size
+            let ptr = unsafe { std::alloc::alloc(layout) };
+            let ptr = NonNull::new(ptr).ok_or(AllocError)?;
+
+            Ok(non_null_slice_from_raw_parts(ptr, layout.size()))
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            if layout.size() != 0 {
+                // SAFETY: The block was allocated with `std::alloc::alloc` and the same
+                // layout, by the safety requirements of this function.
+                unsafe { std::alloc::dealloc(ptr.as_ptr(), layout) }
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "allocator_api"))]
+pub use stable::*;
+
+/// An [`Allocator`] which can tell whether the memory allocated by another
+/// instance of the same type can be deallocated by this one.
+///
+/// [`TreeMap::append`][crate::TreeMap::append] moves the nodes of the other map
+/// over as they are when the allocators of the two maps are interchangeable,
+/// and otherwise moves each entry into a new leaf. The provided method never
+/// reports two allocators as interchangeable, so an allocator can implement
+/// this trait without overriding it.
+///
+/// # Safety
+///
+///  - [`InterchangeableAllocator::is_interchangeable_with`] must only return
+///    `true` if every block of memory allocated by `other` can be deallocated
+///    by `self`.
+pub unsafe trait InterchangeableAllocator: Allocator {
+    /// Return `true` if every block of memory allocated by `other` can be
+    /// deallocated by this allocator.
+    fn is_interchangeable_with(&self, other: &Self) -> bool {
+        let _ = other;
+        false
+    }
+}
+
+// SAFETY: Every instance of `Global` deallocates with the global allocator
+unsafe impl InterchangeableAllocator for Global {
+    fn is_interchangeable_with(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+// SAFETY: This forwards to the referenced allocators, which uphold the
+// requirements of the trait.
+unsafe impl<A: InterchangeableAllocator + ?Sized> InterchangeableAllocator for &A {
+    fn is_interchangeable_with(&self, other: &Self) -> bool {
+        (**self).is_interchangeable_with(*other)
+    }
+}
@@ -12,6 +12,9 @@ use std::{
     sync::Arc,
 };
 
+mod fallible;
+pub use fallible::*;
+
 mod ordered;
 pub use ordered::*;
 
@@ -0,0 +1,158 @@
+use crate::AsBytes;
+use std::{borrow::Borrow, convert::Infallible, fmt};
+
+/// Any type implementing `TryAsBytes` can be decomposed into bytes, though
+/// some values of the type may not have a byte representation.
+///
+/// This is the fallible counterpart of [`AsBytes`], for key types where the
+/// encoding can fail, for example strings which are not in a required unicode
+/// normalization form, or composite keys with a part that is out of range.
+/// Every [`AsBytes`] type implements this trait with an [`Infallible`] error.
+///
+/// The result must only depend on the value, so that a value which was
+/// successfully converted once is always converted to the same bytes.
+pub trait TryAsBytes {
+    /// The error returned for a value which has no byte representation.
+    type Error;
+
+    /// View the current value as a byte array, or return the reason that it
+    /// has none.
+    fn try_as_bytes(&self) -> Result<&[u8], Self::Error>;
+}
+
+impl<T> TryAsBytes for T
+where
+    T: AsBytes + ?Sized,
+{
+    type Error = Infallible;
+
+    fn try_as_bytes(&self) -> Result<&[u8], Self::Error> {
+        Ok(self.as_bytes())
+    }
+}
+
+/// A key whose [`TryAsBytes`] conversion is known to succeed, so that it can
+/// be stored in a [`TreeMap`][crate::map::TreeMap].
+///
+/// # Examples
+///
+/// ```rust
+/// use blart::{Checked, TreeMap, TryAsBytes};
+///
+/// /// A tag made of lowercase ASCII letters
+/// #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// struct Tag(String);
+///
+/// #[derive(Debug, PartialEq)]
+/// struct InvalidTag;
+///
+/// impl TryAsBytes for Tag {
+///     type Error = InvalidTag;
+///
+///     fn try_as_bytes(&self) -> Result<&[u8], Self::Error> {
+///         if self.0.bytes().all(|b| b.is_ascii_lowercase()) {
+///             Ok(self.0.as_bytes())
+///         } else {
+///             Err(InvalidTag)
+///         }
+///     }
+/// }
+///
+/// let mut map = TreeMap::<Checked<Tag>, u32>::new();
+/// map.try_insert(Checked::new(Tag("blue".into())).unwrap(), 1)
+///     .unwrap();
+///
+/// assert_eq!(Checked::new(Tag("Blue".into())).unwrap_err(), InvalidTag);
+/// assert_eq!(map.try_get(&Tag("blue".into())), Ok(Some(&1)));
+/// assert_eq!(map.try_get(&Tag("Blue".into())), Err(InvalidTag));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Checked<K>(K);
+
+impl<K> Checked<K>
+where
+    K: TryAsBytes,
+{
+    /// Check that the key has a byte representation, returning the
+    /// conversion error if it does not.
+    pub fn new(key: K) -> Result<Self, K::Error> {
+        key.try_as_bytes()?;
+        Ok(Checked(key))
+    }
+}
+
+impl<K> Checked<K> {
+    /// Return a reference to the checked key.
+    pub fn get(&self) -> &K {
+        &self.0
+    }
+
+    /// Return the checked key.
+    pub fn into_inner(self) -> K {
+        self.0
+    }
+}
+
+impl<K> Borrow<K> for Checked<K> {
+    fn borrow(&self) -> &K {
+        &self.0
+    }
+}
+
+impl<K> AsBytes for Checked<K>
+where
+    K: TryAsBytes,
+{
+    fn as_bytes(&self) -> &[u8] {
+        match self.0.try_as_bytes() {
+            Ok(bytes) => bytes,
+            // PANIC SAFETY: The conversion succeeded when the key was checked, and it
+            // only depends on the value of the key.
+            Err(_) => panic!("a checked key should always have a byte representation"),
+        }
+    }
+}
+
+impl<K> fmt::Display for Checked<K>
+where
+    K: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    struct Even(Vec<u8>);
+
+    impl TryAsBytes for Even {
+        type Error = usize;
+
+        fn try_as_bytes(&self) -> Result<&[u8], Self::Error> {
+            match self.0.iter().position(|byte| byte % 2 != 0) {
+                Some(idx) => Err(idx),
+                None => Ok(&self.0),
+            }
+        }
+    }
+
+    #[test]
+    fn infallible_for_as_bytes_types() {
+        assert_eq!("hello".try_as_bytes(), Ok(&b"hello"[..]));
+        assert_eq!([1u8, 2, 3].try_as_bytes(), Ok(&[1u8, 2, 3][..]));
+    }
+
+    #[test]
+    fn checked_only_wraps_keys_with_bytes() {
+        assert_eq!(Checked::new(Even(vec![2, 4, 5, 6])), Err(2));
+
+        let checked = Checked::new(Even(vec![2, 4, 6])).unwrap();
+        assert_eq!(checked.as_bytes(), &[2, 4, 6]);
+        assert_eq!(checked.get(), &Even(vec![2, 4, 6]));
+        assert_eq!(checked.into_inner(), Even(vec![2, 4, 6]));
+    }
+}
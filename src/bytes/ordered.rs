@@ -305,6 +305,106 @@ impl_ordered_bytes_nonzero_ints!(
     [NonZeroUsize; usize, NonZeroIsize; isize]
 );
 
+/// This struct represents a conversion of integers to a byte string where each
+/// byte holds a single nibble (4 bits) of the [`ToBE`] or [`ToUIntBE`]
+/// representation, starting from the most significant nibble.
+///
+/// Every byte of the converted value is less than 16, so the inner nodes of a
+/// tree using these keys have at most 16 children, and
+/// [`InnerNode48`](crate::InnerNode48) and
+/// [`InnerNode256`](crate::InnerNode256) nodes are never created. This trades
+/// twice as many levels for smaller nodes, which can be a better fit for dense
+/// numeric key spaces on memory constrained targets.
+pub struct ToNibbles<N>(PhantomData<N>);
+
+/// Split every byte of the input into two bytes, the high nibble followed by
+/// the low nibble.
+fn spread_nibbles<const N: usize, const M: usize>(bytes: [u8; N]) -> [u8; M] {
+    debug_assert_eq!(N * 2, M);
+
+    let mut nibbles = [0; M];
+    for (pair, byte) in nibbles.chunks_exact_mut(2).zip(bytes) {
+        pair[0] = byte >> 4;
+        pair[1] = byte & 0x0F;
+    }
+    nibbles
+}
+
+/// Combine every pair of nibble bytes back into a single byte, the inverse of
+/// [`spread_nibbles`].
+fn gather_nibbles<const N: usize, const M: usize>(nibbles: [u8; M]) -> [u8; N] {
+    debug_assert_eq!(N * 2, M);
+
+    let mut bytes = [0; N];
+    for (byte, pair) in bytes.iter_mut().zip(nibbles.chunks_exact(2)) {
+        *byte = (pair[0] << 4) | (pair[1] & 0x0F);
+    }
+    bytes
+}
+
+macro_rules! impl_ordered_bytes_nibbles {
+    ($([$unsigned:ty, $signed:ty]),*) => {
+        $(
+            // SAFETY: This is safe to implement because splitting each byte of the big endian
+            // representation into its nibbles is reversible, and comparing the nibbles from the most
+            // significant to the least significant gives the same ordering as comparing the bytes.
+            unsafe impl BytesMapping for ToNibbles<$unsigned> {
+                type Domain = $unsigned;
+                type Bytes = [u8; 2 * std::mem::size_of::<$unsigned>()];
+
+                fn to_bytes(value: Self::Domain) -> Self::Bytes {
+                    spread_nibbles(ToBE::<$unsigned>::to_bytes(value))
+                }
+
+                fn from_bytes(bytes: Self::Bytes) -> Self::Domain {
+                    ToBE::<$unsigned>::from_bytes(gather_nibbles(bytes))
+                }
+            }
+
+            // SAFETY: The nibble representation has a constant length for all values of the type, thus
+            // there can be no prefixes
+            unsafe impl NoPrefixesBytes for Mapped<ToNibbles<$unsigned>> {}
+
+            // SAFETY: The nibble representation preserves the ordering of the big endian
+            // representation, which matches the natural ordering of the integer type
+            unsafe impl OrderedBytes for Mapped<ToNibbles<$unsigned>> {}
+
+            // SAFETY: This is safe to implement because `ToUIntBE` is an ordering preserving,
+            // reversible conversion, and splitting the bytes into nibbles preserves the ordering and
+            // is reversible as well.
+            unsafe impl BytesMapping for ToNibbles<$signed> {
+                type Domain = $signed;
+                type Bytes = [u8; 2 * std::mem::size_of::<$signed>()];
+
+                fn to_bytes(value: Self::Domain) -> Self::Bytes {
+                    spread_nibbles(ToUIntBE::<$signed>::to_bytes(value))
+                }
+
+                fn from_bytes(bytes: Self::Bytes) -> Self::Domain {
+                    ToUIntBE::<$signed>::from_bytes(gather_nibbles(bytes))
+                }
+            }
+
+            // SAFETY: The nibble representation has a constant length for all values of the type, thus
+            // there can be no prefixes
+            unsafe impl NoPrefixesBytes for Mapped<ToNibbles<$signed>> {}
+
+            // SAFETY: The nibble representation preserves the ordering of the `ToUIntBE`
+            // representation, which matches the natural ordering of the integer type
+            unsafe impl OrderedBytes for Mapped<ToNibbles<$signed>> {}
+        )*
+    };
+}
+
+impl_ordered_bytes_nibbles!(
+    [u8, i8],
+    [u16, i16],
+    [u32, i32],
+    [u64, i64],
+    [u128, i128],
+    [usize, isize]
+);
+
 /// This struct represents a conversion of IP addresses (V4 and V6) into their
 /// component bytes. The ordering of IP addresses is already the lexicographic
 /// ordering of the component bytes, so it will be preserved.
@@ -358,6 +458,96 @@ unsafe impl NoPrefixesBytes for Mapped<ToOctets<Ipv6Addr>> {}
 // see https://doc.rust-lang.org/1.69.0/src/core/net/ip_addr.rs.html#1908
 unsafe impl OrderedBytes for Mapped<ToOctets<Ipv6Addr>> {}
 
+/// This struct represents a conversion of a tuple of values into the
+/// concatenation of their byte strings, so that the lexicographic ordering of
+/// the tuple matches the lexicographic ordering of the bytes.
+///
+/// The type parameter is a tuple of [`BytesMapping`]s, one for each element of
+/// the tuple. Every mapping must produce a fixed length byte array, otherwise
+/// the bytes of one element could run into the next one and change the
+/// ordering.
+///
+/// # Examples
+///
+/// ```rust
+/// use blart::{Concat, Mapped, ToBE, ToUIntBE, TreeMap};
+///
+/// type Key = Mapped<Concat<(ToUIntBE<i32>, ToBE<u16>)>>;
+///
+/// let mut map = TreeMap::<Key, char>::new();
+/// map.insert(Key::new((1, 0)), 'c');
+/// map.insert(Key::new((-1, u16::MAX)), 'b');
+/// map.insert(Key::new((-1, 7)), 'a');
+///
+/// assert!(map.values().copied().eq(['a', 'b', 'c']));
+/// assert_eq!(
+///     map.into_keys().next().map(Mapped::get),
+///     Some((-1, 7))
+/// );
+/// ```
+pub struct Concat<T>(PhantomData<T>);
+
+macro_rules! impl_ordered_bytes_tuples {
+    ($([$($mapping:ident, $len:ident, $value:ident);+]),*) => {
+        $(
+            // SAFETY: This is safe to implement because each element is converted with an order
+            // preserving mapping to a byte array of fixed length, so comparing the concatenated
+            // bytes compares the elements in order, the same as the tuple ordering. The elements
+            // can be split apart again using the fixed lengths.
+            unsafe impl<$($mapping, const $len: usize),+> BytesMapping for Concat<($($mapping,)+)>
+            where
+                $($mapping: BytesMapping<Bytes = [u8; $len]>),+
+            {
+                type Domain = ($($mapping::Domain,)+);
+                type Bytes = Box<[u8]>;
+
+                fn to_bytes(value: Self::Domain) -> Self::Bytes {
+                    let ($($value,)+) = value;
+                    let mut bytes = Vec::with_capacity(0 $(+ $len)+);
+                    $(bytes.extend_from_slice(&$mapping::to_bytes($value));)+
+                    bytes.into_boxed_slice()
+                }
+
+                fn from_bytes(bytes: Self::Bytes) -> Self::Domain {
+                    let mut offset = 0;
+                    $(
+                        // PANIC SAFETY: The bytes were created by `to_bytes`, which concatenates arrays
+                        // of exactly these lengths
+                        let $value = $mapping::from_bytes(
+                            bytes[offset..offset + $len]
+                                .try_into()
+                                .expect("slice should have the length of the element bytes"),
+                        );
+                        offset += $len;
+                    )+
+                    debug_assert_eq!(offset, bytes.len());
+                    ($($value,)+)
+                }
+            }
+
+            // SAFETY: Every element is converted to a byte array of fixed length, so all values
+            // of the tuple are converted to the same number of bytes, thus there can be no prefixes
+            unsafe impl<$($mapping, const $len: usize),+> NoPrefixesBytes for Mapped<Concat<($($mapping,)+)>>
+            where
+                $($mapping: BytesMapping<Bytes = [u8; $len]>),+
+            {}
+
+            // SAFETY: The concatenated bytes are ordered the same as the tuple, see the
+            // `BytesMapping` implementation above
+            unsafe impl<$($mapping, const $len: usize),+> OrderedBytes for Mapped<Concat<($($mapping,)+)>>
+            where
+                $($mapping: BytesMapping<Bytes = [u8; $len]>),+
+            {}
+        )*
+    };
+}
+
+impl_ordered_bytes_tuples!(
+    [A, LEN_A, a; B, LEN_B, b],
+    [A, LEN_A, a; B, LEN_B, b; C, LEN_C, c],
+    [A, LEN_A, a; B, LEN_B, b; C, LEN_C, c; D, LEN_D, d]
+);
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -468,6 +658,45 @@ mod tests {
         [NonZeroUsize, usize, NonZeroIsize, isize; test_ordered_nonzero_uisize]
     );
 
+    #[test]
+    fn test_ordered_nibbles() {
+        assert_bytes_isomorphism_contract::<ToNibbles<u8>>(0x0F, 0xF0);
+        assert_bytes_isomorphism_contract::<ToNibbles<u16>>(0x00FF, 0x0100);
+        assert_bytes_isomorphism_contract::<ToNibbles<u32>>(u32::MAX, u32::MIN);
+        assert_bytes_isomorphism_contract::<ToNibbles<u64>>(1 << 60, (1 << 60) - 1);
+        assert_bytes_isomorphism_contract::<ToNibbles<i32>>(-1, 0);
+        assert_bytes_isomorphism_contract::<ToNibbles<i128>>(i128::MIN, i128::MAX);
+        assert_bytes_isomorphism_contract::<ToNibbles<isize>>(-16, 15);
+
+        assert_eq!(ToNibbles::<u16>::to_bytes(0xA5C3), [0xA, 0x5, 0xC, 0x3]);
+
+        check_is_ordered_bytes::<Mapped<ToNibbles<u32>>>();
+        check_is_ordered_bytes::<Mapped<ToNibbles<i64>>>();
+    }
+
+    #[test]
+    fn test_nibbles_bound_node_fanout() {
+        use crate::{deallocate_tree, visitor::TreeStatsCollector, TreeMap};
+
+        let mut map = TreeMap::new();
+        for value in 0..10_000u32 {
+            map.insert(Mapped::<ToNibbles<u32>>::new(value), value);
+        }
+        assert!(map.keys().map(|key| key.get()).eq(0..10_000));
+
+        let root = map.into_raw().unwrap();
+        // SAFETY: The tree is exclusively owned here, and is not mutated while the
+        // statistics are collected
+        let stats = unsafe { TreeStatsCollector::collect(root) };
+        assert_eq!(stats.leaf_count, 10_000);
+        assert_eq!(stats.node48_count, 0);
+        assert_eq!(stats.node256_count, 0);
+        assert!(stats.node16_count > 0);
+
+        // SAFETY: The tree is not used after this point
+        unsafe { deallocate_tree(root) };
+    }
+
     #[test]
     fn test_orded_ip_types() {
         assert_bytes_isomorphism_contract::<ToOctets<Ipv4Addr>>(
@@ -505,4 +734,32 @@ mod tests {
 
         check_is_ordered_bytes::<Mapped<ToOctets<Ipv6Addr>>>();
     }
+
+    #[test]
+    fn test_ordered_tuples() {
+        assert_bytes_isomorphism_contract::<Concat<(ToUIntBE<i8>, ToBE<u8>)>>((-1, 0), (0, 0));
+        assert_bytes_isomorphism_contract::<Concat<(ToUIntBE<i8>, ToBE<u8>)>>(
+            (-1, u8::MAX),
+            (0, u8::MIN),
+        );
+        assert_bytes_isomorphism_contract::<Concat<(ToBE<u16>, ToUIntBE<i64>)>>(
+            (7, i64::MIN),
+            (7, i64::MAX),
+        );
+        assert_bytes_isomorphism_contract::<Concat<(ToBE<u32>, ToOctets<Ipv4Addr>, ToUIntBE<i16>)>>(
+            (u32::MAX, Ipv4Addr::LOCALHOST, -5),
+            (u32::MAX, Ipv4Addr::LOCALHOST, -6),
+        );
+        assert_bytes_isomorphism_contract::<
+            Concat<(ToBE<u8>, ToUIntBE<i32>, ToNibbles<u8>, ToBE<u128>)>,
+        >((1, -1, 0xF0, 0), (1, -1, 0x0F, u128::MAX));
+
+        assert_eq!(
+            &*Concat::<(ToBE<u16>, ToUIntBE<i8>)>::to_bytes((0x0102, -1)),
+            &[0x01, 0x02, 0x7F]
+        );
+
+        check_is_ordered_bytes::<Mapped<Concat<(ToBE<u64>, ToUIntBE<i64>)>>>();
+        check_is_ordered_bytes::<Mapped<Concat<(ToBE<u8>, ToBE<u8>, ToBE<u8>, ToBE<u8>)>>>();
+    }
 }
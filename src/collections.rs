@@ -1,2 +1,37 @@
 pub mod map;
 pub use map::TreeMap;
+
+pub mod trace;
+
+pub mod undo;
+
+pub mod interval;
+
+pub mod drop_handle;
+
+pub mod snapshot;
+
+pub mod filter;
+
+pub mod indexed;
+
+pub mod path_cache;
+
+pub mod merkle;
+
+pub mod delta_sync;
+
+pub mod compressed;
+
+pub mod suffix;
+
+pub mod set;
+pub use set::TreeSet;
+
+#[cfg(feature = "python")]
+pub mod python;
+
+#[cfg(feature = "concurrent")]
+pub mod concurrent;
+#[cfg(feature = "concurrent")]
+pub use concurrent::ConcurrentTreeMap;
@@ -0,0 +1,538 @@
+//! A [`TreeMap`] wrapper which compresses large byte-string values.
+//!
+//! A [`CompressedTreeMap`] passes every value at least as long as its
+//! threshold through a user-provided [`ValueCodec`] (for example a wrapper
+//! around an LZ4 or Zstandard implementation) before storing it, and keeps the
+//! value uncompressed if compression did not make it smaller. Lookups
+//! decompress the value transparently. The savings are tracked in
+//! [`CompressionStats`].
+
+use crate::{AsBytes, InsertPrefixError, NoPrefixesBytes, TreeMap};
+use std::{
+    borrow::{Borrow, Cow},
+    error::Error,
+    fmt,
+};
+
+/// A compression algorithm for the values of a [`CompressedTreeMap`].
+///
+/// # Examples
+///
+/// An implementation for a compression library usually just forwards to its
+/// API:
+///
+/// ```rust,ignore
+/// use blart::compressed::ValueCodec;
+///
+/// struct Lz4;
+///
+/// impl ValueCodec for Lz4 {
+///     type Error = lz4_flex::block::DecompressError;
+///
+///     fn compress(&self, input: &[u8], output: &mut Vec<u8>) {
+///         output.extend(lz4_flex::compress_prepend_size(input));
+///     }
+///
+///     fn decompress(&self, input: &[u8], output: &mut Vec<u8>) -> Result<(), Self::Error> {
+///         output.extend(lz4_flex::decompress_size_prepended(input)?);
+///         Ok(())
+///     }
+/// }
+/// ```
+pub trait ValueCodec {
+    /// The error returned when a value can not be decompressed.
+    type Error;
+
+    /// Append the compressed form of `input` to `output`.
+    fn compress(&self, input: &[u8], output: &mut Vec<u8>);
+
+    /// Append the original value, given the output of
+    /// [`ValueCodec::compress`], to `output`.
+    fn decompress(&self, input: &[u8], output: &mut Vec<u8>) -> Result<(), Self::Error>;
+}
+
+/// A value as stored in a [`CompressedTreeMap`].
+#[derive(Clone, PartialEq, Eq)]
+pub struct StoredValue {
+    bytes: Box<[u8]>,
+    /// The length of the original value, if `bytes` is compressed.
+    compressed_from: Option<usize>,
+}
+
+impl StoredValue {
+    /// Returns `true` if the value is stored in compressed form.
+    pub fn is_compressed(&self) -> bool {
+        self.compressed_from.is_some()
+    }
+
+    /// Returns the number of bytes used to store the value.
+    pub fn stored_len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Returns the length of the value before compression.
+    pub fn original_len(&self) -> usize {
+        self.compressed_from.unwrap_or(self.bytes.len())
+    }
+
+    /// Return the original value, decompressing it with the given codec if
+    /// needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error from [`ValueCodec::decompress`], or
+    /// [`DecompressError::LengthMismatch`] if the decompressed value does not
+    /// have the original length.
+    pub fn decompress<C: ValueCodec>(
+        &self,
+        codec: &C,
+    ) -> Result<Cow<'_, [u8]>, DecompressError<C::Error>> {
+        match self.compressed_from {
+            None => Ok(Cow::Borrowed(&self.bytes)),
+            Some(original_len) => {
+                let mut output = Vec::with_capacity(original_len);
+                codec
+                    .decompress(&self.bytes, &mut output)
+                    .map_err(DecompressError::Codec)?;
+                if output.len() != original_len {
+                    return Err(DecompressError::LengthMismatch {
+                        expected: original_len,
+                        actual: output.len(),
+                    });
+                }
+                Ok(Cow::Owned(output))
+            },
+        }
+    }
+}
+
+impl fmt::Debug for StoredValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StoredValue")
+            .field("stored_len", &self.stored_len())
+            .field("original_len", &self.original_len())
+            .field("is_compressed", &self.is_compressed())
+            .finish()
+    }
+}
+
+/// An error from decompressing a [`StoredValue`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum DecompressError<E> {
+    /// The codec failed to decompress the value
+    Codec(E),
+    /// The codec produced a value with a different length than the original
+    LengthMismatch {
+        /// The length of the original value
+        expected: usize,
+        /// The length of the decompressed value
+        actual: usize,
+    },
+}
+
+impl<E: fmt::Display> fmt::Display for DecompressError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecompressError::Codec(err) => write!(f, "Failed to decompress value: {err}"),
+            DecompressError::LengthMismatch { expected, actual } => write!(
+                f,
+                "Decompressed value has length {actual}, but the original value had length \
+                 {expected}"
+            ),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for DecompressError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DecompressError::Codec(err) => Some(err),
+            DecompressError::LengthMismatch { .. } => None,
+        }
+    }
+}
+
+/// Counters describing the values stored in a [`CompressedTreeMap`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompressionStats {
+    /// The number of values that are stored compressed
+    pub compressed_values: usize,
+    /// The total length of all values before compression
+    pub original_bytes: usize,
+    /// The total number of bytes used to store all values
+    pub stored_bytes: usize,
+}
+
+impl CompressionStats {
+    /// Returns the number of bytes saved by compression.
+    pub fn saved_bytes(&self) -> usize {
+        self.original_bytes - self.stored_bytes
+    }
+
+    /// Returns the stored size as a fraction of the original size, which is
+    /// `1.0` if no values are stored.
+    pub fn ratio(&self) -> f64 {
+        if self.original_bytes == 0 {
+            1.0
+        } else {
+            self.stored_bytes as f64 / self.original_bytes as f64
+        }
+    }
+
+    fn of(value: &StoredValue) -> Self {
+        CompressionStats {
+            compressed_values: usize::from(value.is_compressed()),
+            original_bytes: value.original_len(),
+            stored_bytes: value.stored_len(),
+        }
+    }
+
+    fn add(&mut self, other: CompressionStats) {
+        self.compressed_values += other.compressed_values;
+        self.original_bytes += other.original_bytes;
+        self.stored_bytes += other.stored_bytes;
+    }
+
+    fn remove(&mut self, other: CompressionStats) {
+        self.compressed_values -= other.compressed_values;
+        self.original_bytes -= other.original_bytes;
+        self.stored_bytes -= other.stored_bytes;
+    }
+}
+
+/// A [`TreeMap`] with byte-string values, which compresses values above a
+/// size threshold.
+///
+/// # Examples
+///
+/// ```rust
+/// use blart::compressed::{CompressedTreeMap, ValueCodec};
+/// use std::convert::Infallible;
+///
+/// /// Strips trailing zeros, which is only useful as an example.
+/// struct TrailingZeros;
+///
+/// impl ValueCodec for TrailingZeros {
+///     type Error = Infallible;
+///
+///     fn compress(&self, input: &[u8], output: &mut Vec<u8>) {
+///         let end = input.iter().rposition(|byte| *byte != 0).map_or(0, |idx| idx + 1);
+///         output.extend((input.len() as u32).to_le_bytes());
+///         output.extend(&input[..end]);
+///     }
+///
+///     fn decompress(&self, input: &[u8], output: &mut Vec<u8>) -> Result<(), Infallible> {
+///         let len = u32::from_le_bytes(input[..4].try_into().unwrap()) as usize;
+///         output.extend(&input[4..]);
+///         output.resize(len, 0);
+///         Ok(())
+///     }
+/// }
+///
+/// let mut map = CompressedTreeMap::<[u8; 1], _>::new(TrailingZeros, 64);
+/// map.insert([1], &[7; 10]);
+/// map.insert([2], &[0; 1000]);
+///
+/// assert_eq!(map.get(&[2]).unwrap().unwrap().len(), 1000);
+/// assert_eq!(map.stats().compressed_values, 1);
+/// assert_eq!(map.stats().stored_bytes, 10 + 4);
+/// ```
+pub struct CompressedTreeMap<K, C> {
+    map: TreeMap<K, StoredValue>,
+    codec: C,
+    threshold: usize,
+    stats: CompressionStats,
+}
+
+impl<K, C: ValueCodec> CompressedTreeMap<K, C> {
+    /// Create a new, empty map which compresses values that are at least
+    /// `threshold` bytes long with the given codec.
+    pub fn new(codec: C, threshold: usize) -> Self {
+        CompressedTreeMap {
+            map: TreeMap::new(),
+            codec,
+            threshold,
+            stats: CompressionStats::default(),
+        }
+    }
+
+    /// Returns a reference to the codec.
+    pub fn codec(&self) -> &C {
+        &self.codec
+    }
+
+    /// Returns the minimum length of the values which are compressed.
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// Returns a reference to the underlying map of stored values.
+    pub fn map(&self) -> &TreeMap<K, StoredValue> {
+        &self.map
+    }
+
+    /// Return the underlying map of stored values.
+    pub fn into_map(self) -> TreeMap<K, StoredValue> {
+        self.map
+    }
+
+    /// Returns counters describing the stored values.
+    pub fn stats(&self) -> CompressionStats {
+        self.stats
+    }
+
+    /// Returns the number of elements in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if the map contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Clear the map, removing all elements.
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.stats = CompressionStats::default();
+    }
+
+    /// Compress the value if it is long enough and compression makes it
+    /// smaller.
+    pub fn encode(&self, value: &[u8]) -> StoredValue {
+        if value.len() >= self.threshold {
+            let mut compressed = Vec::new();
+            self.codec.compress(value, &mut compressed);
+            if compressed.len() < value.len() {
+                return StoredValue {
+                    bytes: compressed.into_boxed_slice(),
+                    compressed_from: Some(value.len()),
+                };
+            }
+        }
+
+        StoredValue {
+            bytes: value.into(),
+            compressed_from: None,
+        }
+    }
+
+    /// Returns the value corresponding to the key, decompressed if needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stored value can not be decompressed.
+    pub fn get<Q>(&self, key: &Q) -> Result<Option<Cow<'_, [u8]>>, DecompressError<C::Error>>
+    where
+        K: Borrow<Q> + AsBytes,
+        Q: AsBytes + ?Sized,
+    {
+        self.map
+            .get(key)
+            .map(|value| value.decompress(&self.codec))
+            .transpose()
+    }
+
+    /// Returns the stored form of the value corresponding to the key.
+    pub fn get_stored<Q>(&self, key: &Q) -> Option<&StoredValue>
+    where
+        K: Borrow<Q> + AsBytes,
+        Q: AsBytes + ?Sized,
+    {
+        self.map.get(key)
+    }
+
+    /// Returns `true` if the map contains a value for the specified key.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q> + AsBytes,
+        Q: AsBytes + ?Sized,
+    {
+        self.map.contains_key(key)
+    }
+
+    /// Insert a key-value pair into the map, returning the previous stored
+    /// value if the key was already present.
+    pub fn insert(&mut self, key: K, value: &[u8]) -> Option<StoredValue>
+    where
+        K: NoPrefixesBytes,
+    {
+        let value = self.encode(value);
+        self.stats.add(CompressionStats::of(&value));
+        let old_value = self.map.insert(key, value)?;
+        self.stats.remove(CompressionStats::of(&old_value));
+        Some(old_value)
+    }
+
+    /// Insert a key-value pair into the map, returning the previous stored
+    /// value if the key was already present.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the given key is a prefix of an existing key, or an
+    /// existing key is a prefix of the given key.
+    pub fn try_insert(
+        &mut self,
+        key: K,
+        value: &[u8],
+    ) -> Result<Option<StoredValue>, InsertPrefixError>
+    where
+        K: AsBytes,
+    {
+        let value = self.encode(value);
+        let added = CompressionStats::of(&value);
+        let old_value = self.map.try_insert(key, value)?;
+        self.stats.add(added);
+        if let Some(old_value) = &old_value {
+            self.stats.remove(CompressionStats::of(old_value));
+        }
+        Ok(old_value)
+    }
+
+    /// Removes a key from the map, returning the stored value if the key was
+    /// previously in the map.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<StoredValue>
+    where
+        K: Borrow<Q> + AsBytes,
+        Q: AsBytes + ?Sized,
+    {
+        let old_value = self.map.remove(key)?;
+        self.stats.remove(CompressionStats::of(&old_value));
+        Some(old_value)
+    }
+}
+
+impl<K, C> fmt::Debug for CompressedTreeMap<K, C>
+where
+    K: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompressedTreeMap")
+            .field("map", &self.map)
+            .field("threshold", &self.threshold)
+            .field("stats", &self.stats)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run-length encoding, which fails on odd-length input.
+    struct RunLength;
+
+    #[derive(Debug, PartialEq, Eq)]
+    struct OddLength;
+
+    impl ValueCodec for RunLength {
+        type Error = OddLength;
+
+        fn compress(&self, input: &[u8], output: &mut Vec<u8>) {
+            let mut rest = input;
+            while let Some(&byte) = rest.first() {
+                let run = rest
+                    .iter()
+                    .take(255)
+                    .take_while(|other| **other == byte)
+                    .count();
+                output.extend([run as u8, byte]);
+                rest = &rest[run..];
+            }
+        }
+
+        fn decompress(&self, input: &[u8], output: &mut Vec<u8>) -> Result<(), OddLength> {
+            let pairs = input.chunks_exact(2);
+            if !pairs.remainder().is_empty() {
+                return Err(OddLength);
+            }
+            for pair in pairs {
+                output.resize(output.len() + usize::from(pair[0]), pair[1]);
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn values_round_trip_and_stats_track_contents() {
+        let mut map = CompressedTreeMap::<[u8; 1], _>::new(RunLength, 16);
+        // Short values are never compressed
+        assert!(map.insert([0], &[1; 8]).is_none());
+        // Compressing this would make it larger
+        let noisy: Vec<u8> = (0..100).collect();
+        assert!(map.insert([1], &noisy).is_none());
+        assert!(map.insert([2], &[5; 1000]).is_none());
+
+        assert!(!map.get_stored(&[0]).unwrap().is_compressed());
+        assert!(!map.get_stored(&[1]).unwrap().is_compressed());
+        let stored = map.get_stored(&[2]).unwrap();
+        assert!(stored.is_compressed());
+        assert_eq!((stored.stored_len(), stored.original_len()), (8, 1000));
+
+        assert_eq!(map.get(&[0]).unwrap().as_deref(), Some(&[1; 8][..]));
+        assert_eq!(map.get(&[1]).unwrap().as_deref(), Some(&noisy[..]));
+        assert_eq!(map.get(&[2]).unwrap().as_deref(), Some(&[5; 1000][..]));
+        assert_eq!(map.get(&[3]).unwrap(), None);
+        assert!(map.contains_key(&[2]));
+
+        assert_eq!(
+            map.stats(),
+            CompressionStats {
+                compressed_values: 1,
+                original_bytes: 1108,
+                stored_bytes: 116,
+            }
+        );
+        assert_eq!(map.stats().saved_bytes(), 992);
+
+        let old_value = map.insert([2], &[1, 2, 3]).unwrap();
+        assert!(old_value.is_compressed());
+        assert_eq!(map.stats().compressed_values, 0);
+        assert_eq!(map.remove(&[1]).unwrap().stored_len(), 100);
+        assert_eq!(map.remove(&[1]), None);
+        assert_eq!(
+            map.stats(),
+            CompressionStats {
+                compressed_values: 0,
+                original_bytes: 11,
+                stored_bytes: 11,
+            }
+        );
+        assert_eq!(map.stats().ratio(), 1.0);
+
+        map.clear();
+        assert!(map.is_empty());
+        assert_eq!(map.stats(), CompressionStats::default());
+    }
+
+    #[test]
+    fn prefix_keys_and_decompress_errors() {
+        let mut map = CompressedTreeMap::<Box<[u8]>, _>::new(RunLength, 0);
+        map.try_insert(Box::new([1, 2]), &[9; 300]).unwrap();
+        assert!(map.try_insert(Box::new([1]), &[9; 300]).is_err());
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.stats().compressed_values, 1);
+        assert_eq!(map.stats().stored_bytes, 4);
+
+        let corrupt = StoredValue {
+            bytes: Box::new([1, 2, 3]),
+            compressed_from: Some(1),
+        };
+        assert_eq!(
+            corrupt.decompress(&RunLength),
+            Err(DecompressError::Codec(OddLength))
+        );
+        let truncated = StoredValue {
+            bytes: Box::new([1, 2]),
+            compressed_from: Some(2),
+        };
+        assert_eq!(
+            truncated.decompress(&RunLength),
+            Err(DecompressError::LengthMismatch {
+                expected: 2,
+                actual: 1
+            })
+        );
+        assert_eq!(map.into_map().len(), 1);
+    }
+}
@@ -0,0 +1,1188 @@
+//! An ordered map which can be read and written from many threads at once.
+//!
+//! [`ConcurrentTreeMap`] synchronizes access to the tree with optimistic lock
+//! coupling, as described in "The ART of Practical Synchronization" (Leis,
+//! Scheibner, Kemper & Neumann, 2016). Every inner node has a versioned lock
+//! word, which is allocated right in front of the node, so that the node types
+//! shared with [`TreeMap`][crate::TreeMap] do not grow:
+//!
+//!  - Readers never write to shared memory. They read the version of a node,
+//!    read the node, and check that the version is unchanged before they follow
+//!    any pointer they read. If a writer changed the node in the meantime, the
+//!    operation restarts from the root.
+//!  - Writers search the tree like readers, and only lock the one or two nodes
+//!    they modify, by upgrading the versions they read. Writers in different
+//!    parts of the tree do not block each other, and readers are never blocked
+//!    by writers in a different part of the tree.
+//!
+//! Since a writer may change a node while a reader reads it, readers never
+//! create a reference to an inner node. They copy the fields they need with
+//! volatile reads, and only use the copies once the version check passed.
+//!
+//! Leaf nodes are never modified once they are linked into the tree. Replacing
+//! the value of a key links in a new leaf instead. Nodes which are unlinked
+//! from the tree are deallocated with epoch based reclamation, once no thread
+//! can still be reading them.
+
+use crate::{
+    deallocate_tree_in, parent_write_child, AsBytes, ConcreteNodePtr, InnerNode, InnerNode256,
+    InnerNode4, InsertPrefixError, LeafNode, NoPrefixesBytes, NodePtr, NodeVersion, OpaqueNodePtr,
+    ResizePolicy, Restart, VersionedAlloc,
+};
+use crossbeam_epoch::{self as epoch, Guard};
+use std::{
+    borrow::Borrow,
+    fmt,
+    marker::PhantomData,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// An ordered map based on an adaptive radix tree, which can be shared
+/// between threads and modified through a shared reference.
+///
+/// Lookups do not take any locks, and inserts and removals only lock the
+/// nodes they change, so readers and writers working on different keys run in
+/// parallel. Values are returned by cloning them, since another thread may
+/// replace or remove the entry as soon as the lookup returns.
+///
+/// The tree always has an [`InnerNode256`] root, so every key must have at
+/// least one byte.
+///
+/// # Examples
+///
+/// ```rust
+/// use blart::ConcurrentTreeMap;
+/// use std::thread;
+///
+/// let map = ConcurrentTreeMap::<u32, u32>::new();
+///
+/// thread::scope(|scope| {
+///     for thread_idx in 0..4 {
+///         let map = &map;
+///         scope.spawn(move || {
+///             for key in (thread_idx * 100)..((thread_idx + 1) * 100) {
+///                 map.insert(key, key * 2);
+///             }
+///         });
+///     }
+/// });
+///
+/// assert_eq!(map.len(), 400);
+/// assert_eq!(map.get(&123), Some(246));
+/// assert_eq!(map.remove(&123), Some(246));
+/// assert_eq!(map.get(&123), None);
+/// ```
+pub struct ConcurrentTreeMap<K, V> {
+    /// The root of the tree, which is never replaced, so that every other node
+    /// has a parent to lock when it is replaced.
+    root: NodePtr<InnerNode256<K, V>>,
+    /// The number of entries present in the tree.
+    num_entries: AtomicUsize,
+    /// The map owns the keys and values in the leaves of the tree.
+    _marker: PhantomData<(K, V)>,
+}
+
+// SAFETY: The map owns the keys and values in the tree, so sending it to
+// another thread sends them as well.
+unsafe impl<K: Send, V: Send> Send for ConcurrentTreeMap<K, V> {}
+
+// SAFETY: A shared map gives out clones of its values to any thread, inserts
+// keys and values from any thread, and drops replaced entries on any thread, so
+// the keys and values must be both `Send` and `Sync`.
+unsafe impl<K: Send + Sync, V: Send + Sync> Sync for ConcurrentTreeMap<K, V> {}
+
+impl<K, V> ConcurrentTreeMap<K, V> {
+    /// Create a new, empty [`ConcurrentTreeMap`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::ConcurrentTreeMap;
+    ///
+    /// let map = ConcurrentTreeMap::<Box<[u8]>, ()>::new();
+    /// assert!(map.is_empty());
+    /// ```
+    pub fn new() -> Self {
+        ConcurrentTreeMap {
+            root: NodePtr::allocate_node_ptr_in(InnerNode256::empty(), &VersionedAlloc),
+            num_entries: AtomicUsize::new(0),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the number of elements in the map.
+    ///
+    /// While other threads modify the map, the result may already be out of
+    /// date when it is returned.
+    pub fn len(&self) -> usize {
+        self.num_entries.load(Ordering::Relaxed)
+    }
+
+    /// Returns true if the map contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns a clone of the value corresponding to the key.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::ConcurrentTreeMap;
+    ///
+    /// let map = ConcurrentTreeMap::<Box<[u8]>, char>::new();
+    ///
+    /// map.try_insert(Box::new([1, 2, 3]), 'a').unwrap();
+    /// assert_eq!(map.get([1, 2, 3].as_ref()), Some('a'));
+    /// assert_eq!(map.get([1, 2, 4].as_ref()), None);
+    /// ```
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q> + AsBytes,
+        Q: AsBytes + ?Sized,
+        V: Clone,
+    {
+        let key_bytes = key.as_bytes();
+        let guard = epoch::pin();
+        loop {
+            // SAFETY: The guard is held until the value is cloned, so the leaf is not
+            // deallocated before that.
+            if let Ok(leaf) = unsafe { self.search(key_bytes, &guard) } {
+                // SAFETY: Leaves are never modified while they are part of the tree, and the
+                // guard keeps the leaf alive for the duration of the clone.
+                return leaf.map(|leaf_ptr| unsafe { leaf_ptr.as_value_ref() }.clone());
+            }
+        }
+    }
+
+    /// Returns true if the map contains a value for the specified key.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q> + AsBytes,
+        Q: AsBytes + ?Sized,
+    {
+        let key_bytes = key.as_bytes();
+        let guard = epoch::pin();
+        loop {
+            // SAFETY: The guard is held for the whole search
+            if let Ok(leaf) = unsafe { self.search(key_bytes, &guard) } {
+                return leaf.is_some();
+            }
+        }
+    }
+
+    /// Search for the leaf holding the given key, without taking any locks.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Restart`] if a concurrent write was observed, and the search
+    /// must be repeated.
+    ///
+    /// # Safety
+    ///
+    ///  - The returned leaf must not be accessed after `_guard` is dropped.
+    unsafe fn search(
+        &self,
+        key: &[u8],
+        _guard: &Guard,
+    ) -> Result<Option<NodePtr<LeafNode<K, V>>>, Restart>
+    where
+        K: AsBytes,
+    {
+        let mut node = self.root.to_opaque();
+        // SAFETY: The root is never deallocated while the map is borrowed
+        let mut version = unsafe { version_of(node) }.read_lock()?;
+        let mut depth = 0;
+
+        loop {
+            // SAFETY: Every inner node reached from the root was validated before it was
+            // followed, and the guard keeps it allocated. The copies read here may be
+            // torn by a concurrent write, which is detected by the version check below.
+            let header = unsafe { node.read_header_optimistic() }.unwrap();
+            // Only the stored bytes of the prefix are compared, the full key is checked
+            // at the leaf
+            let mismatched = header.match_prefix(&key[depth..]) < header.read_prefix().len();
+            depth += header.prefix_size();
+            let key_byte = key.get(depth).copied();
+            // SAFETY: Same as above
+            let child = key_byte.map(|key_byte| unsafe { node.read_child_optimistic(key_byte) });
+
+            // SAFETY: Same as above
+            unsafe { version_of(node) }.check(version)?;
+            // SAFETY: The version of the node was checked after the child was copied
+            let child = child.and_then(|child| unsafe { child.assume_validated() });
+            let Some(child) = child.filter(|_| !mismatched) else {
+                return Ok(None);
+            };
+
+            if let Some(leaf_ptr) = child.cast::<LeafNode<K, V>>() {
+                // SAFETY: The leaf was validated above and is kept allocated by the guard.
+                // Leaves are never modified while they are part of the tree.
+                let leaf_key = unsafe { leaf_ptr.as_key_ref() }.as_bytes();
+                return Ok((leaf_key == key).then_some(leaf_ptr));
+            }
+
+            // SAFETY: The child pointer was validated above
+            let child_version = unsafe { version_of(child) }.read_lock()?;
+            // SAFETY: Same as above
+            unsafe { version_of(node) }.check(version)?;
+
+            node = child;
+            version = child_version;
+            depth += 1;
+        }
+    }
+}
+
+impl<K, V> ConcurrentTreeMap<K, V>
+where
+    K: Send + 'static,
+    V: Send + 'static,
+{
+    /// Inserts a key-value pair into the map.
+    ///
+    /// If the map did not have this key present, `None` is returned. If the
+    /// map did have this key present, the value is updated, and a clone of the
+    /// old value is returned.
+    ///
+    /// # Panics
+    ///
+    ///  - Panics if the key has no bytes.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::ConcurrentTreeMap;
+    ///
+    /// let map = ConcurrentTreeMap::<u128, char>::new();
+    ///
+    /// assert!(map.insert(123, 'a').is_none());
+    /// assert!(map.insert(234, 'b').is_none());
+    /// assert_eq!(map.insert(234, 'c'), Some('b'));
+    ///
+    /// assert_eq!(map.len(), 2);
+    /// ```
+    pub fn insert(&self, key: K, value: V) -> Option<V>
+    where
+        K: NoPrefixesBytes,
+        V: Clone,
+    {
+        match self.try_insert(key, value) {
+            Ok(value) => value,
+            Err(_err) => panic!("the key of an entry in a `ConcurrentTreeMap` cannot be empty"),
+        }
+    }
+
+    /// Inserts a key-value pair into the map.
+    ///
+    /// If the map did not have this key present, `Ok(None)` is returned. If
+    /// the map did have this key present, the value is updated, and a clone
+    /// of the old value is returned.
+    ///
+    /// # Errors
+    ///
+    ///  - If the map has an existing key, such that the new key is a prefix of
+    ///    the existing key or vice versa, then it returns an error. The empty
+    ///    key is a prefix of every key, so it is always rejected.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::ConcurrentTreeMap;
+    ///
+    /// let map = ConcurrentTreeMap::<Box<[u8]>, char>::new();
+    ///
+    /// assert!(map.try_insert(Box::new([1, 2, 3]), 'a').unwrap().is_none());
+    /// assert!(map.try_insert(Box::new([2, 3, 4]), 'b').unwrap().is_none());
+    /// // This function call errors because the key is a prefix of the existing key
+    /// assert!(map.try_insert(Box::new([2, 3, 4, 5]), 'c').is_err());
+    /// assert_eq!(map.try_insert(Box::new([2, 3, 4]), 'd').unwrap(), Some('b'));
+    ///
+    /// assert_eq!(map.len(), 2);
+    /// ```
+    pub fn try_insert(&self, key: K, value: V) -> Result<Option<V>, InsertPrefixError>
+    where
+        K: AsBytes,
+        V: Clone,
+    {
+        let new_leaf = NodePtr::allocate_node_ptr_in(LeafNode::new(key, value), &VersionedAlloc);
+        let guard = epoch::pin();
+        loop {
+            // SAFETY: The new leaf is not linked into the tree until the insert succeeds,
+            // and the guard is held for the whole insert.
+            match unsafe { self.insert_leaf(new_leaf, &guard) } {
+                Ok(Ok(old_value)) => {
+                    if old_value.is_none() {
+                        self.num_entries.fetch_add(1, Ordering::Relaxed);
+                    }
+                    return Ok(old_value);
+                },
+                Ok(Err(err)) => {
+                    // SAFETY: The leaf was never linked into the tree
+                    drop(unsafe { NodePtr::deallocate_node_ptr_in(new_leaf, &VersionedAlloc) });
+                    return Err(err);
+                },
+                Err(Restart) => {},
+            }
+        }
+    }
+
+    /// Link the new leaf into the tree, and return a clone of the value of the
+    /// leaf it replaced.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Restart`] if a concurrent write was observed, and the insert
+    /// must be repeated. The tree is not modified in that case.
+    ///
+    /// # Safety
+    ///
+    ///  - The new leaf must not be linked into the tree yet, and must not be
+    ///    modified or deallocated during this function.
+    unsafe fn insert_leaf(
+        &self,
+        new_leaf: NodePtr<LeafNode<K, V>>,
+        guard: &Guard,
+    ) -> Result<Result<Option<V>, InsertPrefixError>, Restart>
+    where
+        K: AsBytes,
+        V: Clone,
+    {
+        // SAFETY: The new leaf is not shared with any other thread yet
+        let key = unsafe { new_leaf.as_key_ref() }.as_bytes();
+        let prefix_error = || InsertPrefixError {
+            byte_repr: key.into(),
+        };
+
+        let mut parent: Option<(OpaqueNodePtr<K, V>, u64, u8)> = None;
+        let mut node = self.root.to_opaque();
+        // SAFETY: The root is never deallocated while the map is borrowed
+        let mut version = unsafe { version_of(node) }.read_lock()?;
+        let mut depth = 0;
+
+        loop {
+            // SAFETY: Every inner node reached from the root was validated before it was
+            // followed, and the guard keeps it allocated. The copies read here may be
+            // torn by a concurrent write, which is detected before they are used to
+            // modify the tree.
+            let header = unsafe { node.read_header_optimistic() }.unwrap();
+            let prefix_size = header.prefix_size();
+            let mut matched = header.match_prefix(&key[depth..]);
+
+            // The whole prefix has to be compared before the tree can be changed, so the
+            // bytes that are not stored in the header are read from a leaf below the node.
+            // The leaf is read before any lock is taken, since finding it needs to read
+            // the version of this node.
+            let mut full_prefix_key = None;
+            if !header.stores_full_prefix() {
+                // SAFETY: The node was validated when it was followed, and the guard is held
+                let leaf_key = unsafe { any_leaf_key(node, guard) }?;
+                // SAFETY: Same as above
+                unsafe { version_of(node) }.check(version)?;
+
+                // PANIC SAFETY: The node did not change while the leaf was found, so the
+                // leaf is below the node and its key continues past the prefix.
+                let full_prefix = &leaf_key[depth..(depth + prefix_size)];
+                matched = full_prefix
+                    .iter()
+                    .zip(&key[depth..])
+                    .take_while(|(prefix_byte, key_byte)| prefix_byte == key_byte)
+                    .count();
+                full_prefix_key = Some(leaf_key);
+            }
+
+            if matched < prefix_size {
+                // SAFETY: Same as above
+                unsafe { version_of(node) }.check(version)?;
+                let Some(&new_key_byte) = key.get(depth + matched) else {
+                    return Ok(Err(prefix_error()));
+                };
+                let (parent_node, parent_version, parent_key_byte) =
+                    parent.expect("the root node has no prefix");
+
+                // SAFETY: The parent and node were validated when they were followed
+                unsafe { lock_pair(parent_node, parent_version, node, version) }?;
+
+                // SAFETY: Both locks are held, so no other thread modifies the node, and
+                // the leaf key was read while the node had the same version
+                unsafe {
+                    let header = node.header_mut().unwrap();
+                    let old_key_byte = match full_prefix_key {
+                        Some(leaf_key) => leaf_key[depth + matched],
+                        None => header.read_prefix()[matched],
+                    };
+                    match full_prefix_key {
+                        Some(leaf_key) => header.ltrim_prefix_with(matched + 1, &leaf_key[depth..]),
+                        None => header.ltrim_prefix(matched + 1),
+                    }
+
+                    let mut new_node = InnerNode4::empty();
+                    new_node
+                        .header_mut()
+                        .extend_prefix(&key[depth..(depth + matched)]);
+                    new_node.write_child(old_key_byte, node);
+                    new_node.write_child(new_key_byte, new_leaf.to_opaque());
+                    let new_node =
+                        NodePtr::allocate_node_ptr_in(new_node, &VersionedAlloc).to_opaque();
+
+                    parent_write_child(parent_node, parent_key_byte, new_node);
+                    version_of(node).write_unlock();
+                    version_of(parent_node).write_unlock();
+                }
+                return Ok(Ok(None));
+            }
+
+            depth += prefix_size;
+            let key_byte = key.get(depth).copied();
+            // SAFETY: Same as above
+            let child = key_byte.map(|key_byte| unsafe { node.read_child_optimistic(key_byte) });
+            let is_full = header.num_children() >= node.node_type().upper_capacity();
+            // SAFETY: Same as above
+            unsafe { version_of(node) }.check(version)?;
+            if let Some((parent_node, parent_version, _)) = parent {
+                // SAFETY: Same as above
+                unsafe { version_of(parent_node) }.check(parent_version)?;
+            }
+            let Some(key_byte) = key_byte else {
+                return Ok(Err(prefix_error()));
+            };
+            // SAFETY: The version of the node was checked after the child was copied
+            let child = child.and_then(|child| unsafe { child.assume_validated() });
+
+            let Some(child) = child else {
+                if is_full {
+                    let (parent_node, parent_version, parent_key_byte) =
+                        parent.expect("the root node is never full");
+                    // SAFETY: The parent and node were validated when they were followed
+                    unsafe { lock_pair(parent_node, parent_version, node, version) }?;
+                    // SAFETY: Both locks are held, and the old node is only deallocated once
+                    // no thread can be reading it anymore
+                    unsafe {
+                        let new_node = grow_with_child(node, key_byte, new_leaf.to_opaque());
+                        parent_write_child(parent_node, parent_key_byte, new_node);
+                        version_of(node).write_unlock_obsolete();
+                        version_of(parent_node).write_unlock();
+                        retire(node, guard);
+                    }
+                } else {
+                    // SAFETY: The node was validated when it was followed
+                    unsafe { version_of(node) }.upgrade_to_write_lock(version)?;
+                    parent_write_child(node, key_byte, new_leaf.to_opaque());
+                    // SAFETY: Same as above
+                    unsafe { version_of(node) }.write_unlock();
+                }
+                return Ok(Ok(None));
+            };
+
+            if let Some(leaf_ptr) = child.cast::<LeafNode<K, V>>() {
+                // SAFETY: The leaf was validated above and is kept allocated by the guard.
+                // Leaves are never modified while they are part of the tree.
+                let leaf_key = unsafe { leaf_ptr.as_key_ref() }.as_bytes();
+
+                if leaf_key == key {
+                    // SAFETY: The node was validated when it was followed
+                    unsafe { version_of(node) }.upgrade_to_write_lock(version)?;
+                    parent_write_child(node, key_byte, new_leaf.to_opaque());
+                    // SAFETY: The lock is held. The old leaf is only deallocated once no thread
+                    // can be reading it anymore.
+                    unsafe {
+                        version_of(node).write_unlock();
+                        retire(child, guard);
+                    }
+                    // SAFETY: The guard keeps the old leaf allocated
+                    return Ok(Ok(Some(unsafe { leaf_ptr.as_value_ref() }.clone())));
+                }
+
+                let new_depth = depth + 1;
+                let common_len = leaf_key[new_depth..]
+                    .iter()
+                    .zip(&key[new_depth..])
+                    .take_while(|(leaf_byte, key_byte)| leaf_byte == key_byte)
+                    .count();
+                let (Some(&old_key_byte), Some(&new_key_byte)) = (
+                    leaf_key.get(new_depth + common_len),
+                    key.get(new_depth + common_len),
+                ) else {
+                    return Ok(Err(prefix_error()));
+                };
+
+                // SAFETY: The node was validated when it was followed
+                unsafe { version_of(node) }.upgrade_to_write_lock(version)?;
+                let mut new_node = InnerNode4::empty();
+                new_node
+                    .header_mut()
+                    .extend_prefix(&key[new_depth..(new_depth + common_len)]);
+                new_node.write_child(old_key_byte, child);
+                new_node.write_child(new_key_byte, new_leaf.to_opaque());
+                let new_node = NodePtr::allocate_node_ptr_in(new_node, &VersionedAlloc).to_opaque();
+                parent_write_child(node, key_byte, new_node);
+                // SAFETY: The lock is held
+                unsafe { version_of(node) }.write_unlock();
+                return Ok(Ok(None));
+            }
+
+            // SAFETY: The child pointer was validated above
+            let child_version = unsafe { version_of(child) }.read_lock()?;
+            // SAFETY: Same as above
+            unsafe { version_of(node) }.check(version)?;
+
+            parent = Some((node, version, key_byte));
+            node = child;
+            version = child_version;
+            depth += 1;
+        }
+    }
+
+    /// Removes a key from the map, returning a clone of the value at the key
+    /// if the key was previously in the map.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::ConcurrentTreeMap;
+    ///
+    /// let map = ConcurrentTreeMap::<Box<[u8]>, char>::new();
+    ///
+    /// map.try_insert(Box::new([1, 2, 3]), 'a').unwrap();
+    /// map.try_insert(Box::new([2, 3, 4]), 'b').unwrap();
+    ///
+    /// assert_eq!(map.remove([2, 3, 4].as_ref()), Some('b'));
+    /// assert_eq!(map.remove([2, 3, 4].as_ref()), None);
+    /// ```
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q> + AsBytes,
+        Q: AsBytes + ?Sized,
+        V: Clone,
+    {
+        let key_bytes = key.as_bytes();
+        let guard = epoch::pin();
+        loop {
+            if let Ok(old_value) = self.remove_leaf(key_bytes, &guard) {
+                if old_value.is_some() {
+                    self.num_entries.fetch_sub(1, Ordering::Relaxed);
+                }
+                return old_value;
+            }
+        }
+    }
+
+    /// Unlink the leaf holding the given key from the tree, and return a clone
+    /// of its value.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Restart`] if a concurrent write was observed, and the removal
+    /// must be repeated. The tree is not modified in that case.
+    fn remove_leaf(&self, key: &[u8], guard: &Guard) -> Result<Option<V>, Restart>
+    where
+        K: AsBytes,
+        V: Clone,
+    {
+        let mut parent: Option<(OpaqueNodePtr<K, V>, u64, u8)> = None;
+        let mut node = self.root.to_opaque();
+        // SAFETY: The root is never deallocated while the map is borrowed
+        let mut version = unsafe { version_of(node) }.read_lock()?;
+        let mut depth = 0;
+
+        loop {
+            // SAFETY: Every inner node reached from the root was validated before it was
+            // followed, and the guard keeps it allocated. The copies read here may be
+            // torn by a concurrent write, which is detected before they are used to
+            // modify the tree.
+            let header = unsafe { node.read_header_optimistic() }.unwrap();
+            // Only the stored bytes of the prefix are compared, the full key is checked
+            // at the leaf
+            let mismatched = header.match_prefix(&key[depth..]) < header.read_prefix().len();
+            depth += header.prefix_size();
+            let num_children = header.num_children();
+            let key_byte = key.get(depth).copied();
+            // SAFETY: Same as above
+            let child = key_byte.map(|key_byte| unsafe { node.read_child_optimistic(key_byte) });
+
+            // SAFETY: Same as above
+            unsafe { version_of(node) }.check(version)?;
+            // SAFETY: The version of the node was checked after the child was copied
+            let child = child.and_then(|child| unsafe { child.assume_validated() });
+            let (Some(key_byte), Some(child)) = (key_byte, child.filter(|_| !mismatched)) else {
+                return Ok(None);
+            };
+
+            let Some(leaf_ptr) = child.cast::<LeafNode<K, V>>() else {
+                // SAFETY: The child pointer was validated above
+                let child_version = unsafe { version_of(child) }.read_lock()?;
+                // SAFETY: Same as above
+                unsafe { version_of(node) }.check(version)?;
+
+                parent = Some((node, version, key_byte));
+                node = child;
+                version = child_version;
+                depth += 1;
+                continue;
+            };
+
+            // SAFETY: The leaf was validated above and is kept allocated by the guard.
+            // Leaves are never modified while they are part of the tree.
+            if unsafe { leaf_ptr.as_key_ref() }.as_bytes() != key {
+                return Ok(None);
+            }
+
+            match parent {
+                Some((parent_node, parent_version, parent_key_byte)) if num_children == 2 => {
+                    // SAFETY: The parent and node were validated when they were followed
+                    unsafe { lock_pair(parent_node, parent_version, node, version) }?;
+                    // SAFETY: Both locks are held, and the old node is only deallocated once
+                    // no thread can be reading it anymore
+                    unsafe {
+                        let remaining_child = collapse_into_remaining_child(node, key_byte);
+                        parent_write_child(parent_node, parent_key_byte, remaining_child);
+                        version_of(node).write_unlock_obsolete();
+                        version_of(parent_node).write_unlock();
+                        retire(node, guard);
+                    }
+                },
+                Some((parent_node, parent_version, parent_key_byte))
+                    if ResizePolicy::DEFAULT.should_shrink(node.node_type(), num_children - 1) =>
+                {
+                    // SAFETY: The parent and node were validated when they were followed
+                    unsafe { lock_pair(parent_node, parent_version, node, version) }?;
+                    // SAFETY: Both locks are held, and the old node is only deallocated once
+                    // no thread can be reading it anymore
+                    unsafe {
+                        let new_node = shrink_without_child(node, key_byte);
+                        parent_write_child(parent_node, parent_key_byte, new_node);
+                        version_of(node).write_unlock_obsolete();
+                        version_of(parent_node).write_unlock();
+                        retire(node, guard);
+                    }
+                },
+                _ => {
+                    // SAFETY: The node was validated when it was followed
+                    unsafe { version_of(node) }.upgrade_to_write_lock(version)?;
+                    // SAFETY: The lock is held
+                    unsafe {
+                        remove_child(node, key_byte);
+                        version_of(node).write_unlock();
+                    }
+                },
+            }
+
+            // SAFETY: The leaf is unlinked from the tree, and the guard keeps it allocated
+            // until the value is cloned
+            unsafe {
+                let old_value = leaf_ptr.as_value_ref().clone();
+                retire(child, guard);
+                return Ok(Some(old_value));
+            }
+        }
+    }
+}
+
+impl<K, V> Default for ConcurrentTreeMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> fmt::Debug for ConcurrentTreeMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConcurrentTreeMap")
+            .field("len", &self.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<K, V> Drop for ConcurrentTreeMap<K, V> {
+    fn drop(&mut self) {
+        // SAFETY: Since we have a mutable reference to the map, no other thread is
+        // accessing the tree. The nodes which were unlinked from the tree are not
+        // reachable from the root, so they are only deallocated by the collector.
+        unsafe { deallocate_tree_in(self.root.to_opaque(), &VersionedAlloc) }
+    }
+}
+
+/// Return the lock word of the given inner node.
+///
+/// # Safety
+///
+///  - The node must be part of this tree, which allocates every node with
+///    [`VersionedAlloc`], and must not be deallocated for the lifetime `'a`.
+///
+/// # Panics
+///
+///  - Panics if the node is a leaf.
+unsafe fn version_of<'a, K: 'a, V: 'a>(node: OpaqueNodePtr<K, V>) -> &'a NodeVersion {
+    // SAFETY: Covered by the safety requirements of this function
+    unsafe { node.version() }.expect("only inner nodes have a lock")
+}
+
+/// Take the write locks of a parent and child node, if neither changed since
+/// their versions were read.
+///
+/// # Errors
+///
+/// Returns [`Restart`] if either version changed, in which case neither lock
+/// is held.
+///
+/// # Safety
+///
+///  - Both nodes must not be deallocated during this function.
+unsafe fn lock_pair<K, V>(
+    parent: OpaqueNodePtr<K, V>,
+    parent_version: u64,
+    node: OpaqueNodePtr<K, V>,
+    version: u64,
+) -> Result<(), Restart> {
+    // SAFETY: Covered by the safety requirements of this function
+    unsafe {
+        version_of(parent).upgrade_to_write_lock(parent_version)?;
+        if let Err(restart) = version_of(node).upgrade_to_write_lock(version) {
+            version_of(parent).write_unlock();
+            return Err(restart);
+        }
+    }
+    Ok(())
+}
+
+/// Return the key of any leaf below the given inner node.
+///
+/// Every node on the way to the leaf is validated before its child is
+/// followed. The leaf is only known to be below the given node if the version
+/// of that node is checked afterwards.
+///
+/// # Errors
+///
+/// Returns [`Restart`] if a concurrent write was observed.
+///
+/// # Safety
+///
+///  - The node must have been validated when it was reached, and the returned
+///    key must not be accessed after `_guard` is dropped.
+unsafe fn any_leaf_key<'g, K, V>(
+    mut node: OpaqueNodePtr<K, V>,
+    _guard: &'g Guard,
+) -> Result<&'g [u8], Restart>
+where
+    K: AsBytes + 'g,
+    V: 'g,
+{
+    loop {
+        if let Some(leaf_ptr) = node.cast::<LeafNode<K, V>>() {
+            // SAFETY: The leaf was validated before it was followed and is kept allocated
+            // by the guard. Leaves are never modified while they are part of the tree.
+            return Ok(unsafe { leaf_ptr.as_key_ref() }.as_bytes());
+        }
+
+        // SAFETY: The node was validated before it was followed, and is kept
+        // allocated by the guard
+        let version = unsafe { version_of(node) }.read_lock()?;
+        // SAFETY: Same as above
+        let child = unsafe { node.read_any_child_optimistic() };
+        // SAFETY: Same as above
+        unsafe { version_of(node) }.check(version)?;
+
+        // SAFETY: The version of the node was checked after the child was copied
+        node = unsafe { child.assume_validated() }
+            .expect("an inner node below the root always has children");
+    }
+}
+
+/// Allocate a copy of the full inner node in the next larger node type, with
+/// the new child added.
+///
+/// # Safety
+///
+///  - The write lock of the node must be held.
+unsafe fn grow_with_child<K, V>(
+    node: OpaqueNodePtr<K, V>,
+    key_byte: u8,
+    child: OpaqueNodePtr<K, V>,
+) -> OpaqueNodePtr<K, V> {
+    fn grow<N: InnerNode>(
+        node: &N,
+        key_byte: u8,
+        child: OpaqueNodePtr<N::Key, N::Value>,
+    ) -> OpaqueNodePtr<N::Key, N::Value> {
+        let mut new_node = node.grow();
+        new_node.write_child(key_byte, child);
+        NodePtr::allocate_node_ptr_in(new_node, &VersionedAlloc).to_opaque()
+    }
+
+    // SAFETY: The node is locked, so no other thread modifies it
+    unsafe {
+        match node.to_node_ptr() {
+            ConcreteNodePtr::Node4(inner_ptr) => grow(inner_ptr.as_ref(), key_byte, child),
+            ConcreteNodePtr::Node16(inner_ptr) => grow(inner_ptr.as_ref(), key_byte, child),
+            ConcreteNodePtr::Node48(inner_ptr) => grow(inner_ptr.as_ref(), key_byte, child),
+            ConcreteNodePtr::Node256(inner_ptr) => grow(inner_ptr.as_ref(), key_byte, child),
+            ConcreteNodePtr::LeafNode(_) => unreachable!("only inner nodes have children"),
+        }
+    }
+}
+
+/// Remove the child with the given key byte from the inner node.
+///
+/// # Safety
+///
+///  - The write lock of the node must be held.
+unsafe fn remove_child<K, V>(node: OpaqueNodePtr<K, V>, key_byte: u8) {
+    // SAFETY: The node is locked, so no other thread modifies it. Readers which
+    // overlap with this change restart when they check the version.
+    let removed = unsafe {
+        match node.to_node_ptr() {
+            ConcreteNodePtr::Node4(inner_ptr) => inner_ptr.as_mut().remove_child(key_byte),
+            ConcreteNodePtr::Node16(inner_ptr) => inner_ptr.as_mut().remove_child(key_byte),
+            ConcreteNodePtr::Node48(inner_ptr) => inner_ptr.as_mut().remove_child(key_byte),
+            ConcreteNodePtr::Node256(inner_ptr) => inner_ptr.as_mut().remove_child(key_byte),
+            ConcreteNodePtr::LeafNode(_) => unreachable!("only inner nodes have children"),
+        }
+    };
+    debug_assert!(removed.is_some(), "child should be present");
+}
+
+/// Allocate a copy of the inner node in the next smaller node type, without
+/// the child with the given key byte.
+///
+/// # Safety
+///
+///  - The write lock of the node must be held, and the node must be unlinked
+///    from the tree after this function.
+unsafe fn shrink_without_child<K, V>(
+    node: OpaqueNodePtr<K, V>,
+    key_byte: u8,
+) -> OpaqueNodePtr<K, V> {
+    fn shrink<N: InnerNode>(node: &mut N, key_byte: u8) -> OpaqueNodePtr<N::Key, N::Value> {
+        node.remove_child(key_byte)
+            .expect("child should be present");
+        NodePtr::allocate_node_ptr_in(node.shrink(), &VersionedAlloc).to_opaque()
+    }
+
+    // SAFETY: The node is locked, so no other thread modifies it. The node is
+    // unlinked afterwards, so removing the child from it does not change the tree.
+    unsafe {
+        match node.to_node_ptr() {
+            ConcreteNodePtr::Node4(inner_ptr) => shrink(inner_ptr.as_mut(), key_byte),
+            ConcreteNodePtr::Node16(inner_ptr) => shrink(inner_ptr.as_mut(), key_byte),
+            ConcreteNodePtr::Node48(inner_ptr) => shrink(inner_ptr.as_mut(), key_byte),
+            ConcreteNodePtr::Node256(inner_ptr) => shrink(inner_ptr.as_mut(), key_byte),
+            ConcreteNodePtr::LeafNode(_) => unreachable!("only inner nodes have children"),
+        }
+    }
+}
+
+/// Return the child of an inner node with two children which does not have
+/// the given key byte, after moving the prefix of the node and the key byte of
+/// the child to the front of the child's prefix.
+///
+/// # Safety
+///
+///  - The write lock of the node must be held, and the node must be unlinked
+///    from the tree after this function.
+unsafe fn collapse_into_remaining_child<K, V>(
+    node: OpaqueNodePtr<K, V>,
+    removed_key_byte: u8,
+) -> OpaqueNodePtr<K, V> {
+    fn remaining_child<N: InnerNode>(
+        node: &N,
+        removed_key_byte: u8,
+    ) -> (u8, OpaqueNodePtr<N::Key, N::Value>) {
+        // SAFETY: The iterator is consumed in this statement, and the node is locked
+        unsafe { node.iter() }
+            .find(|(key_byte, _)| *key_byte != removed_key_byte)
+            .expect("node should have another child")
+    }
+
+    // SAFETY: The node is locked, so no other thread modifies it
+    let (header, (key_byte, child)) = unsafe {
+        match node.to_node_ptr() {
+            ConcreteNodePtr::Node4(inner_ptr) => {
+                let node = inner_ptr.as_ref();
+                (node.header(), remaining_child(node, removed_key_byte))
+            },
+            ConcreteNodePtr::Node16(inner_ptr) => {
+                let node = inner_ptr.as_ref();
+                (node.header(), remaining_child(node, removed_key_byte))
+            },
+            ConcreteNodePtr::Node48(inner_ptr) => {
+                let node = inner_ptr.as_ref();
+                (node.header(), remaining_child(node, removed_key_byte))
+            },
+            ConcreteNodePtr::Node256(inner_ptr) => {
+                let node = inner_ptr.as_ref();
+                (node.header(), remaining_child(node, removed_key_byte))
+            },
+            ConcreteNodePtr::LeafNode(_) => unreachable!("only inner nodes have children"),
+        }
+    };
+
+    if !child.is::<LeafNode<K, V>>() {
+        // SAFETY: The child is reachable from the locked node, so it is allocated.
+        // Only the holder of the lock of the node can mark the child obsolete.
+        let child_version = unsafe { version_of(child) };
+        child_version
+            .write_lock()
+            .expect("the child of a locked node cannot be obsolete");
+
+        // SAFETY: The lock of the child is held, so no other thread modifies it. The
+        // header of the node is a different allocation.
+        let child_header = unsafe { child.header_mut() }.unwrap();
+        // This needs to go in reverse order, since prepend_prefix always writes to the
+        // front
+        child_header.prepend_prefix(&[key_byte]);
+        child_header.prepend_header_prefix(header);
+
+        child_version.write_unlock();
+    }
+
+    child
+}
+
+/// Deallocate the unlinked node once no thread can be reading it anymore.
+///
+/// Inner nodes are deallocated without their children, which are still part
+/// of the tree.
+///
+/// # Safety
+///
+///  - The node must have been unlinked from the tree, and this function must
+///    only be called once for it.
+unsafe fn retire<K, V>(node: OpaqueNodePtr<K, V>, guard: &Guard)
+where
+    K: Send + 'static,
+    V: Send + 'static,
+{
+    // SAFETY: The node is no longer reachable from the tree, so only threads that
+    // pinned the collector before this call can still be reading it, and the
+    // deferred function runs after all of them unpinned. The keys and values are
+    // `Send` and `'static`, so they can be dropped on any thread at any later time.
+    unsafe {
+        guard.defer_unchecked(move || match node.to_node_ptr() {
+            ConcreteNodePtr::Node4(inner_ptr) => {
+                drop(NodePtr::deallocate_node_ptr_in(inner_ptr, &VersionedAlloc))
+            },
+            ConcreteNodePtr::Node16(inner_ptr) => {
+                drop(NodePtr::deallocate_node_ptr_in(inner_ptr, &VersionedAlloc))
+            },
+            ConcreteNodePtr::Node48(inner_ptr) => {
+                drop(NodePtr::deallocate_node_ptr_in(inner_ptr, &VersionedAlloc))
+            },
+            ConcreteNodePtr::Node256(inner_ptr) => {
+                drop(NodePtr::deallocate_node_ptr_in(inner_ptr, &VersionedAlloc))
+            },
+            ConcreteNodePtr::LeafNode(leaf_ptr) => {
+                drop(NodePtr::deallocate_node_ptr_in(leaf_ptr, &VersionedAlloc))
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_common::{generate_key_with_prefix, PrefixExpansion};
+    use std::{
+        collections::BTreeMap,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread,
+    };
+
+    #[test]
+    fn insert_get_remove() {
+        let map = ConcurrentTreeMap::<Box<[u8]>, u32>::new();
+
+        assert_eq!(map.try_insert(Box::new([1, 2, 3]), 1), Ok(None));
+        assert_eq!(map.try_insert(Box::new([1, 2, 4]), 2), Ok(None));
+        assert_eq!(map.try_insert(Box::new([1, 2, 3]), 3), Ok(Some(1)));
+        assert!(map.try_insert(Box::new([1, 2]), 4).is_err());
+        assert!(map.try_insert(Box::new([1, 2, 4, 5]), 4).is_err());
+        assert!(map.try_insert(Box::new([]), 4).is_err());
+        assert_eq!(map.len(), 2);
+
+        assert_eq!(map.get([1, 2, 3].as_ref()), Some(3));
+        assert_eq!(map.get([1, 2, 4].as_ref()), Some(2));
+        assert_eq!(map.get([1, 2].as_ref()), None);
+        assert!(!map.contains_key([1, 2, 5].as_ref()));
+
+        assert_eq!(map.remove([1, 2, 3].as_ref()), Some(3));
+        assert_eq!(map.remove([1, 2, 3].as_ref()), None);
+        assert_eq!(map.remove([1, 2, 4].as_ref()), Some(2));
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn matches_btree_map_with_long_prefixes() {
+        // Prefixes longer than the bytes stored in the header, so that inserts read
+        // the rest of the prefix from a leaf
+        let keys: Vec<_> = generate_key_with_prefix(
+            [3, 8, 5],
+            [
+                PrefixExpansion {
+                    base_index: 0,
+                    expanded_length: 12,
+                },
+                PrefixExpansion {
+                    base_index: 2,
+                    expanded_length: 20,
+                },
+            ],
+        )
+        .collect();
+
+        let map = ConcurrentTreeMap::new();
+        let mut expected = BTreeMap::new();
+        for (value, key) in keys.iter().enumerate().rev() {
+            assert_eq!(map.try_insert(key.clone(), value), Ok(None));
+            expected.insert(key.clone(), value);
+        }
+        assert_eq!(map.len(), expected.len());
+
+        for (key, value) in &expected {
+            assert_eq!(map.get(key.as_ref()), Some(*value));
+        }
+
+        // Remove every other key, which shrinks and collapses nodes
+        for (idx, key) in keys.iter().enumerate().step_by(2) {
+            assert_eq!(map.remove(key.as_ref()), Some(idx));
+            expected.remove(key);
+        }
+        assert_eq!(map.len(), expected.len());
+        for (idx, key) in keys.iter().enumerate() {
+            assert_eq!(map.get(key.as_ref()), expected.get(key).copied());
+            if idx % 2 == 1 {
+                assert_eq!(map.get(key.as_ref()), Some(idx));
+            }
+        }
+    }
+
+    #[test]
+    fn concurrent_writers_and_readers() {
+        const NUM_THREADS: u32 = 4;
+        const KEYS_PER_THREAD: u32 = 2_000;
+
+        let map = ConcurrentTreeMap::<u32, Arc<u32>>::new();
+
+        thread::scope(|scope| {
+            for thread_idx in 0..NUM_THREADS {
+                let map = &map;
+                scope.spawn(move || {
+                    // Interleave the keys of the threads, so that they write to the same
+                    // nodes
+                    for idx in 0..KEYS_PER_THREAD {
+                        let key = idx * NUM_THREADS + thread_idx;
+                        assert!(map.insert(key, Arc::new(key)).is_none());
+                    }
+                });
+            }
+            scope.spawn(|| {
+                for key in 0..(NUM_THREADS * KEYS_PER_THREAD) {
+                    if let Some(value) = map.get(&key) {
+                        assert_eq!(*value, key);
+                    }
+                }
+            });
+        });
+
+        assert_eq!(map.len(), (NUM_THREADS * KEYS_PER_THREAD) as usize);
+        for key in 0..(NUM_THREADS * KEYS_PER_THREAD) {
+            assert_eq!(map.get(&key).as_deref(), Some(&key));
+        }
+
+        thread::scope(|scope| {
+            for thread_idx in 0..NUM_THREADS {
+                let map = &map;
+                scope.spawn(move || {
+                    for idx in 0..KEYS_PER_THREAD {
+                        let key = idx * NUM_THREADS + thread_idx;
+                        if idx % 2 == 0 {
+                            assert_eq!(map.remove(&key).as_deref(), Some(&key));
+                        } else {
+                            assert_eq!(map.insert(key, Arc::new(key + 1)).as_deref(), Some(&key));
+                        }
+                    }
+                });
+            }
+        });
+
+        assert_eq!(map.len(), (NUM_THREADS * KEYS_PER_THREAD / 2) as usize);
+        for key in 0..(NUM_THREADS * KEYS_PER_THREAD) {
+            let expected = ((key / NUM_THREADS) % 2 == 1).then_some(key + 1);
+            assert_eq!(map.get(&key).as_deref().copied(), expected);
+        }
+    }
+
+    #[test]
+    fn readers_race_with_writers_in_the_same_nodes() {
+        const NUM_WRITERS: u8 = 4;
+        const NUM_READERS: usize = 2;
+        const ROUNDS: usize = 50;
+
+        // All keys share a long prefix, and sit below 4 nodes that each hold up to
+        // 256 children
+        fn key(hi: u8, lo: u8) -> Box<[u8]> {
+            [0xAA; 20].into_iter().chain([hi, lo]).collect()
+        }
+        fn value(hi: u8, lo: u8) -> usize {
+            usize::from(hi) * 256 + usize::from(lo)
+        }
+
+        let map = ConcurrentTreeMap::new();
+        // The key with `hi == 0 && lo == 0` is never removed, the nodes of the other
+        // `hi` repeatedly grow up to 256 children and shrink until they are removed
+        assert_eq!(map.try_insert(key(0, 0), value(0, 0)), Ok(None));
+        let writers_done = AtomicBool::new(false);
+
+        thread::scope(|scope| {
+            let writers: Vec<_> = (0..NUM_WRITERS)
+                .map(|thread_idx| {
+                    let map = &map;
+                    scope.spawn(move || {
+                        let owned_keys = || {
+                            (0..4u8).flat_map(move |hi| {
+                                (1..=255u8)
+                                    .filter(move |lo| lo % NUM_WRITERS == thread_idx)
+                                    .map(move |lo| (hi, lo))
+                            })
+                        };
+                        for _ in 0..ROUNDS {
+                            for (hi, lo) in owned_keys() {
+                                assert_eq!(map.try_insert(key(hi, lo), value(hi, lo)), Ok(None));
+                            }
+                            for (hi, lo) in owned_keys() {
+                                assert_eq!(map.remove(key(hi, lo).as_ref()), Some(value(hi, lo)));
+                            }
+                        }
+                    })
+                })
+                .collect();
+            for _ in 0..NUM_READERS {
+                let (map, writers_done) = (&map, &writers_done);
+                scope.spawn(move || {
+                    while !writers_done.load(Ordering::Relaxed) {
+                        for hi in 0..4 {
+                            for lo in 0..=255 {
+                                let found = map.get(key(hi, lo).as_ref());
+                                if let Some(found) = found {
+                                    assert_eq!(found, value(hi, lo));
+                                }
+                                if (hi, lo) == (0, 0) {
+                                    assert_eq!(found, Some(value(0, 0)));
+                                }
+                            }
+                        }
+                    }
+                });
+            }
+
+            for writer in writers {
+                writer.join().unwrap();
+            }
+            writers_done.store(true, Ordering::Relaxed);
+        });
+
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.get(key(0, 0).as_ref()), Some(value(0, 0)));
+    }
+}
@@ -0,0 +1,540 @@
+//! Anti-entropy synchronization of two replicas of a [`TreeMap`].
+//!
+//! [`delta_sync`] compares the [`ContentDigests`] of a local map with the
+//! digests of a remote map, starting from the root and only descending into
+//! the prefixes where the digests differ. The remote side is reached through a
+//! caller-provided callback, which sends a batch of prefixes to the remote and
+//! returns the [`ContentDigests::summary`] of each one. Each batch holds all
+//! the prefixes at the next level of the comparison, so the number of round
+//! trips is bounded by the length of the keys.
+//!
+//! The result lists exactly the local entries which are missing or different
+//! on the remote, and a set of prefixes which cover exactly the remote entries
+//! that are missing or different locally.
+//!
+//! [`TreeMap`]: crate::TreeMap
+
+use crate::{
+    merkle::{ContentDigests, ContentHasher, PrefixSummary},
+    AsBytes,
+};
+use std::{cmp::Ordering, error::Error, fmt};
+
+/// The entries that two replicas need to transfer to converge, produced by
+/// [`delta_sync`].
+///
+/// When both replicas have an entry for the same key with different values,
+/// the local entry is in [`SyncDelta::send`] and the key is covered by
+/// [`SyncDelta::request`]. Choosing which value to keep is left to the caller.
+#[derive(Debug)]
+pub struct SyncDelta<'a, K, V> {
+    /// The local entries which are missing or different on the remote, in key
+    /// order.
+    pub send: Vec<(&'a K, &'a V)>,
+    /// The prefixes of the remote entries which are missing or different
+    /// locally, in ascending order.
+    ///
+    /// Every remote entry with a key that starts with one of these prefixes
+    /// should be transferred. A remote entry whose key is exactly a prefix is
+    /// included.
+    pub request: Vec<Box<[u8]>>,
+    /// The number of batches of prefixes sent to the remote.
+    pub round_trips: usize,
+}
+
+impl<'a, K, V> SyncDelta<'a, K, V> {
+    /// Returns `true` if the two replicas already hold the same entries.
+    pub fn is_empty(&self) -> bool {
+        self.send.is_empty() && self.request.is_empty()
+    }
+}
+
+/// An error from [`delta_sync`].
+#[derive(Debug)]
+pub enum DeltaSyncError<E> {
+    /// The callback which fetches summaries from the remote failed
+    Transport(E),
+    /// The remote returned summaries which do not match the requested
+    /// prefixes
+    InvalidResponse {
+        /// A description of the problem
+        reason: &'static str,
+    },
+}
+
+impl<E: fmt::Display> fmt::Display for DeltaSyncError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeltaSyncError::Transport(err) => {
+                write!(f, "Failed to fetch summaries from the remote: {err}")
+            },
+            DeltaSyncError::InvalidResponse { reason } => {
+                write!(f, "Invalid response from the remote: {reason}")
+            },
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for DeltaSyncError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DeltaSyncError::Transport(err) => Some(err),
+            DeltaSyncError::InvalidResponse { .. } => None,
+        }
+    }
+}
+
+/// Compare the local digests with the digests of a remote replica, and return
+/// the entries that need to be transferred in either direction.
+///
+/// The `fetch_summaries` callback is given a batch of prefixes, and must
+/// return the summary of each of them on the remote replica, in the same
+/// order. On the remote side, the summaries are produced by
+/// [`ContentDigests::summary`] with the same [`ContentHasher`].
+///
+/// # Errors
+///
+///  - Returns [`DeltaSyncError::Transport`] if the callback fails.
+///  - Returns [`DeltaSyncError::InvalidResponse`] if the callback returns the
+///    wrong number of summaries, or a summary which does not describe keys
+///    under its prefix.
+///
+/// # Examples
+///
+/// ```rust
+/// use blart::{
+///     delta_sync::delta_sync,
+///     merkle::ContentHasher,
+///     TreeMap,
+/// };
+/// use std::convert::Infallible;
+///
+/// /// The 64-bit FNV-1a hash, which is *not* suitable for untrusted replicas.
+/// struct Fnv(u64);
+///
+/// impl ContentHasher for Fnv {
+///     type Digest = [u8; 8];
+///
+///     fn new() -> Self {
+///         Fnv(0xcbf2_9ce4_8422_2325)
+///     }
+///
+///     fn update(&mut self, bytes: &[u8]) {
+///         for byte in bytes {
+///             self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x100_0000_01b3);
+///         }
+///     }
+///
+///     fn finish(self) -> Self::Digest {
+///         self.0.to_le_bytes()
+///     }
+/// }
+///
+/// let mut local = TreeMap::<[u8; 2], u16>::new();
+/// let mut remote = TreeMap::<[u8; 2], u16>::new();
+/// for idx in 0..1000u16 {
+///     local.insert(idx.to_be_bytes(), idx);
+///     remote.insert(idx.to_be_bytes(), idx);
+/// }
+/// local.insert([1, 1], 0);
+/// remote.remove(&[3, 0]);
+///
+/// let local_digests = local.content_digests::<Fnv>();
+/// let remote_digests = remote.content_digests::<Fnv>();
+/// // A real transport would send the prefixes over the network
+/// let delta = delta_sync(&local_digests, |prefixes| {
+///     Ok::<_, Infallible>(prefixes.iter().map(|prefix| remote_digests.summary(prefix)).collect())
+/// })
+/// .unwrap();
+///
+/// assert_eq!(delta.send, [(&[1, 1], &0), (&[3, 0], &768)]);
+/// assert_eq!(delta.request, [Box::from([1, 1])]);
+/// ```
+pub fn delta_sync<'a, K, V, H, E, F>(
+    local: &ContentDigests<'a, K, V, H>,
+    mut fetch_summaries: F,
+) -> Result<SyncDelta<'a, K, V>, DeltaSyncError<E>>
+where
+    K: AsBytes,
+    V: AsBytes,
+    H: ContentHasher,
+    F: FnMut(&[Box<[u8]>]) -> Result<Vec<PrefixSummary<H::Digest>>, E>,
+{
+    let mut delta = SyncDelta {
+        send: Vec::new(),
+        request: Vec::new(),
+        round_trips: 0,
+    };
+    let mut pending: Vec<Box<[u8]>> = vec![Box::new([])];
+
+    while !pending.is_empty() {
+        let remote_summaries = fetch_summaries(&pending).map_err(DeltaSyncError::Transport)?;
+        delta.round_trips += 1;
+        if remote_summaries.len() != pending.len() {
+            return Err(DeltaSyncError::InvalidResponse {
+                reason: "the number of summaries does not match the number of prefixes",
+            });
+        }
+
+        let mut next_pending = Vec::new();
+        for (prefix, remote_summary) in pending.iter().zip(&remote_summaries) {
+            check_summary(prefix, remote_summary)?;
+            let local_summary = local.summary(prefix);
+            Comparison {
+                local,
+                delta: &mut delta,
+                next_pending: &mut next_pending,
+            }
+            .compare(prefix, &local_summary, remote_summary);
+        }
+        pending = next_pending;
+    }
+
+    delta
+        .send
+        .sort_unstable_by(|(a, _), (b, _)| a.as_bytes().cmp(b.as_bytes()));
+    delta.request.sort_unstable();
+    Ok(delta)
+}
+
+/// Check that the remote summary only describes keys which start with the
+/// prefix, so that every comparison step makes progress.
+fn check_summary<D, E>(prefix: &[u8], summary: &PrefixSummary<D>) -> Result<(), DeltaSyncError<E>> {
+    match summary {
+        PrefixSummary::Empty => Ok(()),
+        PrefixSummary::Leaf { key, .. } if key.starts_with(prefix) => Ok(()),
+        PrefixSummary::Branch {
+            common_prefix,
+            children,
+            ..
+        } if common_prefix.starts_with(prefix) => {
+            if children.windows(2).all(|pair| pair[0].0 < pair[1].0) {
+                Ok(())
+            } else {
+                Err(DeltaSyncError::InvalidResponse {
+                    reason: "the children of a summary are not in ascending order",
+                })
+            }
+        },
+        _ => Err(DeltaSyncError::InvalidResponse {
+            reason: "a summary describes keys outside of its prefix",
+        }),
+    }
+}
+
+/// The state of the comparison of one level of prefixes.
+struct Comparison<'d, 'a, K, V, H: ContentHasher> {
+    local: &'d ContentDigests<'a, K, V, H>,
+    delta: &'d mut SyncDelta<'a, K, V>,
+    next_pending: &'d mut Vec<Box<[u8]>>,
+}
+
+impl<'d, 'a, K, V, H> Comparison<'d, 'a, K, V, H>
+where
+    K: AsBytes,
+    V: AsBytes,
+    H: ContentHasher,
+{
+    fn send_all(&mut self, prefix: &[u8]) {
+        self.delta.send.extend(self.local.prefix_entries(prefix));
+    }
+
+    fn request(&mut self, prefix: &[u8]) {
+        self.delta.request.push(prefix.into());
+    }
+
+    fn compare(
+        &mut self,
+        prefix: &[u8],
+        local: &PrefixSummary<H::Digest>,
+        remote: &PrefixSummary<H::Digest>,
+    ) {
+        match (local, remote) {
+            (PrefixSummary::Empty, PrefixSummary::Empty) => return,
+            (_, PrefixSummary::Empty) => return self.send_all(prefix),
+            (PrefixSummary::Empty, _) => return self.request(prefix),
+            _ if local.digest() == remote.digest() => return,
+            _ => {},
+        }
+
+        let (local_prefix, local_children) = split_summary(local);
+        let (remote_prefix, remote_children) = split_summary(remote);
+        let common_len = local_prefix
+            .iter()
+            .zip(remote_prefix)
+            .take_while(|(a, b)| a == b)
+            .count();
+        let local_is_shorter = common_len == local_prefix.len() && common_len < remote_prefix.len();
+        let remote_is_shorter =
+            common_len == remote_prefix.len() && common_len < local_prefix.len();
+
+        match (local_children, remote_children) {
+            // The same key with different values
+            (None, None) if local_prefix == remote_prefix => {
+                self.send_all(prefix);
+                self.request(remote_prefix);
+            },
+            (Some(local_children), Some(remote_children)) if local_prefix == remote_prefix => {
+                self.compare_children(local_prefix, local_children, remote_children);
+            },
+            (Some(local_children), _) if local_is_shorter => {
+                // All the remote entries are under a single child of the local branch
+                let remote_byte = remote_prefix[common_len];
+                for (key_byte, _) in local_children {
+                    let child_prefix = child_prefix(local_prefix, *key_byte);
+                    if *key_byte == remote_byte {
+                        self.next_pending.push(child_prefix);
+                    } else {
+                        self.send_all(&child_prefix);
+                    }
+                }
+                if !local_children
+                    .iter()
+                    .any(|(key_byte, _)| *key_byte == remote_byte)
+                {
+                    self.request(remote_prefix);
+                }
+            },
+            (_, Some(remote_children)) if remote_is_shorter => {
+                // All the local entries are under a single child of the remote branch
+                let local_byte = local_prefix[common_len];
+                for (key_byte, _) in remote_children {
+                    let child_prefix = child_prefix(remote_prefix, *key_byte);
+                    if *key_byte == local_byte {
+                        self.next_pending.push(child_prefix);
+                    } else {
+                        self.request(&child_prefix);
+                    }
+                }
+                if !remote_children
+                    .iter()
+                    .any(|(key_byte, _)| *key_byte == local_byte)
+                {
+                    self.send_all(prefix);
+                }
+            },
+            // The keys diverge before either side branches, or one side has a key which is a
+            // prefix of keys on the other side, so no entries are shared
+            _ => {
+                self.send_all(prefix);
+                self.request(prefix);
+            },
+        }
+    }
+
+    fn compare_children(
+        &mut self,
+        common_prefix: &[u8],
+        local_children: &Children<H::Digest>,
+        remote_children: &Children<H::Digest>,
+    ) {
+        let mut local_children = local_children.iter().peekable();
+        let mut remote_children = remote_children.iter().peekable();
+        loop {
+            let order = match (local_children.peek(), remote_children.peek()) {
+                (None, None) => break,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some((local_byte, _)), Some((remote_byte, _))) => local_byte.cmp(remote_byte),
+            };
+
+            match order {
+                Ordering::Less => {
+                    // PANIC SAFETY: The peek above returned a local child
+                    let (local_byte, _) = local_children.next().unwrap();
+                    self.send_all(&child_prefix(common_prefix, *local_byte));
+                },
+                Ordering::Greater => {
+                    // PANIC SAFETY: The peek above returned a remote child
+                    let (remote_byte, _) = remote_children.next().unwrap();
+                    self.request(&child_prefix(common_prefix, *remote_byte));
+                },
+                Ordering::Equal => {
+                    // PANIC SAFETY: The peek above returned a child on both sides
+                    let (key_byte, local_digest) = local_children.next().unwrap();
+                    // PANIC SAFETY: See above
+                    let (_, remote_digest) = remote_children.next().unwrap();
+                    if local_digest != remote_digest {
+                        self.next_pending
+                            .push(child_prefix(common_prefix, *key_byte));
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// The children of a branch summary.
+type Children<D> = [(u8, D)];
+
+/// Return the prefix shared by all keys of the summary, and the children if
+/// the summary is a branch.
+fn split_summary<D>(summary: &PrefixSummary<D>) -> (&[u8], Option<&Children<D>>) {
+    match summary {
+        PrefixSummary::Empty => (&[], None),
+        PrefixSummary::Leaf { key, .. } => (key, None),
+        PrefixSummary::Branch {
+            common_prefix,
+            children,
+            ..
+        } => (common_prefix, Some(children)),
+    }
+}
+
+fn child_prefix(common_prefix: &[u8], key_byte: u8) -> Box<[u8]> {
+    let mut child_prefix = Vec::with_capacity(common_prefix.len() + 1);
+    child_prefix.extend_from_slice(common_prefix);
+    child_prefix.push(key_byte);
+    child_prefix.into_boxed_slice()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{merkle::tests::Fnv, TreeMap};
+    use std::{collections::BTreeMap, convert::Infallible};
+
+    type Entries = BTreeMap<Box<[u8]>, u32>;
+
+    fn build(entries: &Entries) -> TreeMap<Box<[u8]>, u32> {
+        let mut map = TreeMap::new();
+        for (key, value) in entries {
+            map.try_insert(key.clone(), *value).unwrap();
+        }
+        map
+    }
+
+    /// Check the delta against the difference of the entries computed directly.
+    fn check_delta(local_entries: &Entries, remote_entries: &Entries) -> usize {
+        let (local, remote) = (build(local_entries), build(remote_entries));
+        let local_digests = local.content_digests::<Fnv>();
+        let remote_digests = remote.content_digests::<Fnv>();
+        let delta = delta_sync(&local_digests, |prefixes| {
+            Ok::<_, Infallible>(
+                prefixes
+                    .iter()
+                    .map(|prefix| remote_digests.summary(prefix))
+                    .collect(),
+            )
+        })
+        .unwrap();
+
+        let expected_send: Vec<_> = local_entries
+            .iter()
+            .filter(|(key, value)| remote_entries.get(*key) != Some(value))
+            .collect();
+        let send: Vec<_> = delta
+            .send
+            .iter()
+            .map(|(key, value)| (*key, *value))
+            .collect();
+        assert_eq!(send, expected_send);
+
+        let expected_fetched: Vec<_> = remote_entries
+            .iter()
+            .filter(|(key, value)| local_entries.get(*key) != Some(value))
+            .collect();
+        let fetched: Vec<_> = remote_entries
+            .iter()
+            .filter(|(key, _)| delta.request.iter().any(|prefix| key.starts_with(prefix)))
+            .collect();
+        assert_eq!(fetched, expected_fetched);
+        assert_eq!(delta.is_empty(), local_entries == remote_entries);
+
+        delta.round_trips
+    }
+
+    fn key(bytes: &[u8]) -> Box<[u8]> {
+        let mut key = bytes.to_vec();
+        key.push(0);
+        key.into_boxed_slice()
+    }
+
+    #[test]
+    fn delta_covers_exactly_the_differences() {
+        let mut base = Entries::new();
+        for idx in 0..2000u32 {
+            let [a, b, c, d] = (idx * 7919).to_be_bytes();
+            base.insert(key(&[b % 4, c, d, a]), idx);
+        }
+        base.insert(key(b"a long shared prefix with one branch"), 1);
+        base.insert(key(b"a long shared prefix with two branches"), 2);
+
+        assert_eq!(check_delta(&base, &base), 1);
+        assert_eq!(check_delta(&base, &Entries::new()), 1);
+        assert_eq!(check_delta(&Entries::new(), &base), 1);
+
+        let mut changed = base.clone();
+        changed.insert(key(&[1, 2, 3, 4]), 5);
+        changed.remove(&key(b"a long shared prefix with two branches"));
+        *changed.values_mut().nth(100).unwrap() += 1;
+        let first_key = changed.keys().next().unwrap().clone();
+        changed.remove(&first_key);
+        changed.insert(key(b"a long shared prefix"), 3);
+        changed.insert(key(b"a long shared"), 4);
+        check_delta(&base, &changed);
+        check_delta(&changed, &base);
+    }
+
+    #[test]
+    fn delta_handles_diverging_compressed_paths() {
+        let local: Entries = [(key(b"abcx"), 1), (key(b"abcy"), 2), (key(b"z"), 0)]
+            .into_iter()
+            .collect();
+        let cases: [&[(&[u8], u32)]; 5] = [
+            // The remote branches earlier than the local side
+            &[(b"abcx", 1), (b"abd", 3), (b"z", 0)],
+            // The remote branches later than the local side
+            &[(b"abcxx", 1), (b"abcxy", 3), (b"z", 0)],
+            // The keys diverge inside the compressed path
+            &[(b"abdx", 1), (b"abdy", 2), (b"z", 0)],
+            // A single remote key under a local branch
+            &[(b"abcx", 1), (b"z", 0)],
+            // A single remote key which is not under the local branch
+            &[(b"abq", 7), (b"z", 0)],
+        ];
+        for remote in cases {
+            let remote: Entries = remote.iter().map(|(k, v)| (key(k), *v)).collect();
+            check_delta(&local, &remote);
+            check_delta(&remote, &local);
+        }
+    }
+
+    #[test]
+    fn delta_rejects_invalid_responses() {
+        let map = build(&[(key(b"ab"), 1), (key(b"cd"), 2)].into_iter().collect());
+        let digests = map.content_digests::<Fnv>();
+
+        let result = delta_sync(&digests, |_| Ok::<_, Infallible>(Vec::new()));
+        assert!(matches!(
+            result,
+            Err(DeltaSyncError::InvalidResponse { .. })
+        ));
+
+        // A summary pointing back at the root would never make progress
+        let mut calls = 0;
+        let result = delta_sync(&digests, |prefixes| {
+            calls += 1;
+            Ok::<_, Infallible>(
+                prefixes
+                    .iter()
+                    .map(|_| PrefixSummary::Branch {
+                        common_prefix: Box::new([]),
+                        digest: [0; 8],
+                        children: vec![(b'a', [0; 8]), (b'c', [0; 8])],
+                    })
+                    .collect(),
+            )
+        });
+        assert!(matches!(
+            result,
+            Err(DeltaSyncError::InvalidResponse { .. })
+        ));
+        assert_eq!(calls, 2);
+
+        let result = delta_sync(&digests, |_| Err::<Vec<_>, _>(std::fmt::Error));
+        let err = result.unwrap_err();
+        assert!(matches!(err, DeltaSyncError::Transport(_)));
+        assert!(err.to_string().starts_with("Failed to fetch summaries"));
+    }
+}
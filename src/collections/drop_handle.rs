@@ -0,0 +1,277 @@
+//! Handles for deferring the teardown of a [`TreeMap`] to another thread.
+//!
+//! Dropping a large tree visits and deallocates every node, which can take a
+//! long time. A [`DropHandle`] owns the nodes of a detached tree, and can be
+//! sent to a background thread or a [`DropQueue`] so that the thread which
+//! owned the map does not pay for the teardown.
+
+use crate::{deallocate_tree, OpaqueNodePtr, TreeMap};
+use std::{
+    fmt,
+    sync::mpsc,
+    thread::{self, JoinHandle},
+};
+
+/// The detached nodes of a [`TreeMap`], which are deallocated when this handle
+/// is dropped.
+///
+/// The handle is [`Send`] when the keys and values are, so it can be dropped
+/// on any thread.
+///
+/// # Examples
+///
+/// ```rust
+/// use blart::TreeMap;
+///
+/// let mut map = TreeMap::<[u8; 4], u32>::new();
+/// for idx in 0..1000u32 {
+///     map.insert(idx.to_be_bytes(), idx);
+/// }
+///
+/// let handle = map.into_drop_handle();
+/// assert_eq!(handle.len(), 1000);
+///
+/// handle.drop_in_background().join().unwrap();
+/// ```
+pub struct DropHandle<K, V> {
+    root: Option<OpaqueNodePtr<K, V>>,
+    num_entries: usize,
+}
+
+impl<K, V> DropHandle<K, V> {
+    /// Detach all the nodes of the given map into a new handle.
+    pub fn new(map: TreeMap<K, V>) -> Self {
+        let num_entries = map.len();
+        DropHandle {
+            root: map.into_raw(),
+            num_entries,
+        }
+    }
+
+    /// Returns the number of entries which will be dropped with this handle.
+    pub fn len(&self) -> usize {
+        self.num_entries
+    }
+
+    /// Returns `true` if the handle does not hold any entries.
+    pub fn is_empty(&self) -> bool {
+        self.num_entries == 0
+    }
+
+    /// Drop the handle on a newly spawned thread, returning the handle of the
+    /// thread.
+    pub fn drop_in_background(self) -> JoinHandle<()>
+    where
+        K: Send + 'static,
+        V: Send + 'static,
+    {
+        thread::spawn(move || drop(self))
+    }
+
+    /// Drop the handle on the thread of the given [`DropQueue`], after every
+    /// value already pushed to the queue.
+    pub fn drop_on(self, queue: &DropQueue)
+    where
+        K: Send + 'static,
+        V: Send + 'static,
+    {
+        queue.push(self);
+    }
+}
+
+impl<K, V> From<TreeMap<K, V>> for DropHandle<K, V> {
+    fn from(map: TreeMap<K, V>) -> Self {
+        DropHandle::new(map)
+    }
+}
+
+impl<K, V> Drop for DropHandle<K, V> {
+    fn drop(&mut self) {
+        if let Some(root) = self.root.take() {
+            // SAFETY: The root came from `TreeMap::into_raw`, so the handle uniquely owns
+            // all the nodes of the tree, and it was removed from the handle so that it is
+            // not deallocated twice.
+            unsafe { deallocate_tree(root) }
+        }
+    }
+}
+
+impl<K, V> fmt::Debug for DropHandle<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DropHandle")
+            .field("num_entries", &self.num_entries)
+            .finish()
+    }
+}
+
+// SAFETY: The handle uniquely owns the keys and values of the tree, and does
+// not provide any access to them, so sending the handle only requires sending
+// the keys and values.
+unsafe impl<K: Send, V: Send> Send for DropHandle<K, V> {}
+
+/// A background thread that drops the values pushed to it, in order.
+///
+/// When the queue itself is dropped, it waits for every pushed value to be
+/// dropped.
+///
+/// # Examples
+///
+/// ```rust
+/// use blart::{drop_handle::DropQueue, TreeMap};
+///
+/// let queue = DropQueue::new();
+///
+/// for _ in 0..4 {
+///     let mut map = TreeMap::<[u8; 2], u16>::new();
+///     for idx in 0..1000u16 {
+///         map.insert(idx.to_be_bytes(), idx);
+///     }
+///     map.into_drop_handle().drop_on(&queue);
+/// }
+///
+/// queue.flush();
+/// ```
+pub struct DropQueue {
+    sender: Option<mpsc::Sender<Box<dyn Send>>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl DropQueue {
+    /// Spawn the thread of a new drop queue.
+    ///
+    /// # Panics
+    ///
+    ///  - Panics if the operating system fails to create the thread.
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel::<Box<dyn Send>>();
+        let worker = thread::Builder::new()
+            .name("blart-drop-queue".into())
+            .spawn(move || {
+                for value in receiver {
+                    drop(value);
+                }
+            })
+            // PANIC SAFETY: Documented in the `# Panics` section.
+            .expect("failed to spawn drop queue thread");
+
+        DropQueue {
+            sender: Some(sender),
+            worker: Some(worker),
+        }
+    }
+
+    /// Send the value to the queue thread to be dropped.
+    ///
+    /// If the queue thread has stopped because an earlier destructor panicked,
+    /// the value is dropped on the calling thread instead.
+    pub fn push<T: Send + 'static>(&self, value: T) {
+        if let Some(sender) = &self.sender {
+            if let Err(mpsc::SendError(value)) = sender.send(Box::new(value)) {
+                drop(value);
+            }
+        }
+    }
+
+    /// Block until every value pushed to the queue before this call has been
+    /// dropped.
+    pub fn flush(&self) {
+        let (done_sender, done_receiver) = mpsc::channel::<()>();
+        self.push(done_sender);
+        // The receive fails once the queue thread drops the sender, which happens
+        // after every value pushed before it.
+        let _ = done_receiver.recv();
+    }
+}
+
+impl Default for DropQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for DropQueue {
+    fn drop(&mut self) {
+        // Closing the channel stops the thread after it drops the remaining values
+        drop(self.sender.take());
+        if let Some(worker) = self.worker.take() {
+            // A panic from a destructor on the queue thread is not propagated, in case
+            // this is already running during a panic.
+            let _ = worker.join();
+        }
+    }
+}
+
+impl fmt::Debug for DropQueue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DropQueue").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn shared_value_map(value: &Arc<()>, num_entries: u16) -> TreeMap<[u8; 2], Arc<()>> {
+        let mut map = TreeMap::new();
+        for idx in 0..num_entries {
+            map.insert(idx.to_be_bytes(), Arc::clone(value));
+        }
+        map
+    }
+
+    #[test]
+    fn handle_drops_entries() {
+        let value = Arc::new(());
+        let handle = shared_value_map(&value, 500).into_drop_handle();
+        assert_eq!(handle.len(), 500);
+        assert_eq!(Arc::strong_count(&value), 501);
+
+        handle.drop_in_background().join().unwrap();
+        assert_eq!(Arc::strong_count(&value), 1);
+
+        let handle = DropHandle::from(TreeMap::<[u8; 2], Arc<()>>::new());
+        assert!(handle.is_empty());
+        drop(handle);
+    }
+
+    #[test]
+    fn queue_drops_entries() {
+        let value = Arc::new(());
+        let queue = DropQueue::new();
+
+        shared_value_map(&value, 300)
+            .into_drop_handle()
+            .drop_on(&queue);
+        shared_value_map(&value, 200)
+            .into_drop_handle()
+            .drop_on(&queue);
+        queue.flush();
+        assert_eq!(Arc::strong_count(&value), 1);
+
+        shared_value_map(&value, 100)
+            .into_drop_handle()
+            .drop_on(&queue);
+        drop(queue);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn queue_drops_inline_after_worker_panic() {
+        struct PanicOnDrop;
+
+        impl Drop for PanicOnDrop {
+            fn drop(&mut self) {
+                panic!("destructor panic");
+            }
+        }
+
+        let queue = DropQueue::new();
+        queue.push(PanicOnDrop);
+        queue.flush();
+
+        let value = Arc::new(());
+        queue.push(Arc::clone(&value));
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+}
@@ -0,0 +1,452 @@
+//! A [`TreeMap`] wrapper with a Bloom filter for fast negative lookups.
+//!
+//! A lookup of a key that is not in the tree still descends the tree until the
+//! key diverges from the stored keys, which can be most of the lookup cost in
+//! workloads dominated by misses. A [`FilteredTreeMap`] keeps a Bloom filter
+//! of all its keys, and answers lookups of keys the filter has never seen
+//! without touching the tree.
+//!
+//! Bloom filters cannot forget keys, so removed keys stay in the filter until
+//! it is rebuilt. The filter is rebuilt from the keys of the map when the
+//! removed keys outnumber the remaining ones, when the map outgrows the
+//! filter, or when [`FilteredTreeMap::rebuild_filter`] is called.
+
+use crate::{AsBytes, InsertPrefixError, NoPrefixesBytes, TreeMap};
+use std::{borrow::Borrow, collections::hash_map::DefaultHasher, hash::Hasher};
+
+/// The number of keys the filter of a new map is sized for.
+const INITIAL_FILTER_CAPACITY: usize = 1024;
+
+/// A Bloom filter over byte strings.
+#[derive(Debug, Clone)]
+struct BloomFilter {
+    bits: Box<[u64]>,
+    num_hashes: u32,
+    /// The number of keys the filter was sized for.
+    capacity: usize,
+}
+
+impl BloomFilter {
+    /// Create an empty filter that has roughly the given false positive rate
+    /// when it holds `capacity` keys.
+    fn new(capacity: usize, false_positive_rate: f64) -> Self {
+        let capacity = capacity.max(1);
+        // The optimal size is `-n * ln(p) / ln(2)^2` bits with
+        // `(bits / n) * ln(2)` hash functions.
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = (-(capacity as f64) * false_positive_rate.ln() / (ln2 * ln2)).ceil();
+        let num_words = ((num_bits as usize) / 64).max(1) + 1;
+        let num_hashes = ((num_words * 64) as f64 / capacity as f64 * ln2).round() as u32;
+
+        BloomFilter {
+            bits: vec![0; num_words].into_boxed_slice(),
+            num_hashes: num_hashes.clamp(1, 16),
+            capacity,
+        }
+    }
+
+    /// Return the bit positions of the key, using the double hashing scheme
+    /// from "Less Hashing, Same Performance: Building a Better Bloom Filter".
+    fn bit_positions(&self, key: &[u8]) -> impl Iterator<Item = usize> {
+        let mut hasher = DefaultHasher::new();
+        hasher.write(key);
+        let hash = hasher.finish();
+
+        let num_bits = (self.bits.len() * 64) as u64;
+        let first = hash & 0xFFFF_FFFF;
+        // An odd step visits distinct positions for any number of hashes
+        let step = (hash >> 32) | 1;
+        (0..u64::from(self.num_hashes))
+            .map(move |idx| (first.wrapping_add(idx.wrapping_mul(step)) % num_bits) as usize)
+    }
+
+    fn insert(&mut self, key: &[u8]) {
+        for bit in self.bit_positions(key).collect::<Vec<_>>() {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    fn may_contain(&self, key: &[u8]) -> bool {
+        self.bit_positions(key)
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    fn clear(&mut self) {
+        self.bits.fill(0);
+    }
+}
+
+/// A [`TreeMap`] which keeps a Bloom filter of its keys, so that lookups of
+/// most absent keys return without searching the tree.
+///
+/// The filter never rejects a key that is present.
+///
+/// # Examples
+///
+/// ```rust
+/// use blart::filter::FilteredTreeMap;
+///
+/// let mut map = FilteredTreeMap::<[u8; 4], u32>::new();
+/// for idx in 0..1000u32 {
+///     map.insert((idx * 2).to_be_bytes(), idx);
+/// }
+///
+/// assert_eq!(map.get(&10u32.to_be_bytes()), Some(&5));
+/// assert_eq!(map.get(&11u32.to_be_bytes()), None);
+///
+/// let rejected = (0..1000u32)
+///     .filter(|idx| !map.may_contain(&(idx * 2 + 1).to_be_bytes()))
+///     .count();
+/// assert!(rejected > 900);
+/// ```
+#[derive(Debug)]
+pub struct FilteredTreeMap<K, V> {
+    map: TreeMap<K, V>,
+    filter: BloomFilter,
+    false_positive_rate: f64,
+    /// The number of keys removed from the map since the filter was built,
+    /// which are still set in the filter.
+    stale_keys: usize,
+}
+
+impl<K, V> FilteredTreeMap<K, V> {
+    /// The false positive rate of the filter when it is at its capacity, used
+    /// by [`FilteredTreeMap::new`].
+    pub const DEFAULT_FALSE_POSITIVE_RATE: f64 = 0.01;
+
+    /// Create a new, empty map with the default false positive rate.
+    pub fn new() -> Self {
+        Self::with_false_positive_rate(Self::DEFAULT_FALSE_POSITIVE_RATE)
+    }
+
+    /// Create a new, empty map whose filter targets the given false positive
+    /// rate.
+    ///
+    /// # Panics
+    ///
+    ///  - Panics if `false_positive_rate` is not strictly between 0 and 1.
+    pub fn with_false_positive_rate(false_positive_rate: f64) -> Self {
+        assert!(
+            false_positive_rate > 0.0 && false_positive_rate < 1.0,
+            "false positive rate must be between 0 and 1"
+        );
+
+        FilteredTreeMap {
+            map: TreeMap::new(),
+            filter: BloomFilter::new(INITIAL_FILTER_CAPACITY, false_positive_rate),
+            false_positive_rate,
+            stale_keys: 0,
+        }
+    }
+
+    /// Returns a reference to the underlying map.
+    pub fn map(&self) -> &TreeMap<K, V> {
+        &self.map
+    }
+
+    /// Return the underlying map, discarding the filter.
+    pub fn into_map(self) -> TreeMap<K, V> {
+        self.map
+    }
+
+    /// Returns the number of elements in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if the map contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Returns `false` if the key is definitely not in the map, without
+    /// searching the tree.
+    pub fn may_contain<Q>(&self, key: &Q) -> bool
+    where
+        Q: AsBytes + ?Sized,
+    {
+        self.filter.may_contain(key.as_bytes())
+    }
+
+    /// Returns a reference to the value corresponding to the key.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q> + AsBytes,
+        Q: AsBytes + ?Sized,
+    {
+        Some(self.get_key_value(key)?.1)
+    }
+
+    /// Returns the key-value pair corresponding to the supplied key.
+    pub fn get_key_value<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q> + AsBytes,
+        Q: AsBytes + ?Sized,
+    {
+        if self.may_contain(key) {
+            self.map.get_key_value(key)
+        } else {
+            None
+        }
+    }
+
+    /// Returns a mutable reference to the value corresponding to the key.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q> + AsBytes,
+        Q: AsBytes + ?Sized,
+    {
+        if self.may_contain(key) {
+            self.map.get_mut(key)
+        } else {
+            None
+        }
+    }
+
+    /// Returns `true` if the map contains a value for the specified key.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q> + AsBytes,
+        Q: AsBytes + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+
+    /// Insert a key-value pair into the map, returning the previous value if
+    /// the key was already present.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V>
+    where
+        K: NoPrefixesBytes,
+    {
+        self.filter.insert(key.as_bytes());
+        let old_value = self.map.insert(key, value);
+        self.maybe_grow_filter();
+        old_value
+    }
+
+    /// Insert a key-value pair into the map, returning the previous value if
+    /// the key was already present.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the given key is a prefix of an existing key, or an
+    /// existing key is a prefix of the given key.
+    pub fn try_insert(&mut self, key: K, value: V) -> Result<Option<V>, InsertPrefixError>
+    where
+        K: AsBytes,
+    {
+        let key_bytes: Box<[u8]> = key.as_bytes().into();
+        let old_value = self.map.try_insert(key, value)?;
+        self.filter.insert(&key_bytes);
+        self.maybe_grow_filter();
+        Ok(old_value)
+    }
+
+    /// Removes a key from the map, returning the value at the key if the key
+    /// was previously in the map.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q> + AsBytes,
+        Q: AsBytes + ?Sized,
+    {
+        Some(self.remove_entry(key)?.1)
+    }
+
+    /// Removes a key from the map, returning the stored key and value if the
+    /// key was previously in the map.
+    pub fn remove_entry<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q> + AsBytes,
+        Q: AsBytes + ?Sized,
+    {
+        if !self.may_contain(key) {
+            return None;
+        }
+
+        let entry = self.map.remove_entry(key)?;
+        self.record_removal();
+        Some(entry)
+    }
+
+    /// Removes and returns the first element in the map.
+    pub fn pop_first(&mut self) -> Option<(K, V)>
+    where
+        K: AsBytes,
+    {
+        let entry = self.map.pop_first()?;
+        self.record_removal();
+        Some(entry)
+    }
+
+    /// Removes and returns the last element in the map.
+    pub fn pop_last(&mut self) -> Option<(K, V)>
+    where
+        K: AsBytes,
+    {
+        let entry = self.map.pop_last()?;
+        self.record_removal();
+        Some(entry)
+    }
+
+    /// Clear the map and the filter, removing all elements.
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.filter.clear();
+        self.stale_keys = 0;
+    }
+
+    /// Rebuild the filter from the keys currently in the map, forgetting all
+    /// removed keys.
+    pub fn rebuild_filter(&mut self)
+    where
+        K: AsBytes,
+    {
+        let capacity = self.map.len().max(INITIAL_FILTER_CAPACITY);
+        let mut filter = BloomFilter::new(capacity, self.false_positive_rate);
+        for key in self.map.keys() {
+            filter.insert(key.as_bytes());
+        }
+
+        self.filter = filter;
+        self.stale_keys = 0;
+    }
+
+    /// Returns the number of removed keys that are still present in the
+    /// filter.
+    pub fn stale_keys(&self) -> usize {
+        self.stale_keys
+    }
+
+    fn record_removal(&mut self)
+    where
+        K: AsBytes,
+    {
+        self.stale_keys += 1;
+        if self.stale_keys > self.map.len().max(INITIAL_FILTER_CAPACITY / 2) {
+            self.rebuild_filter();
+        }
+    }
+
+    fn maybe_grow_filter(&mut self)
+    where
+        K: AsBytes,
+    {
+        // Stale keys take up space in the filter the same as live keys
+        if self.map.len() + self.stale_keys > self.filter.capacity {
+            let capacity = self.filter.capacity * 2;
+            let mut filter = BloomFilter::new(capacity, self.false_positive_rate);
+            for key in self.map.keys() {
+                filter.insert(key.as_bytes());
+            }
+
+            self.filter = filter;
+            self.stale_keys = 0;
+        }
+    }
+}
+
+impl<K, V> Default for FilteredTreeMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> From<TreeMap<K, V>> for FilteredTreeMap<K, V>
+where
+    K: AsBytes,
+{
+    fn from(map: TreeMap<K, V>) -> Self {
+        let mut filtered = FilteredTreeMap {
+            map,
+            filter: BloomFilter::new(1, Self::DEFAULT_FALSE_POSITIVE_RATE),
+            false_positive_rate: Self::DEFAULT_FALSE_POSITIVE_RATE,
+            stale_keys: 0,
+        };
+        filtered.rebuild_filter();
+        filtered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(idx: u32) -> [u8; 4] {
+        idx.to_be_bytes()
+    }
+
+    #[test]
+    fn no_false_negatives_while_growing() {
+        let mut map = FilteredTreeMap::new();
+        for idx in 0..10_000 {
+            assert_eq!(map.insert(key(idx * 3), idx), None);
+        }
+        assert!(map.filter.capacity >= 10_000);
+
+        for idx in 0..10_000 {
+            assert!(map.may_contain(&key(idx * 3)));
+            assert_eq!(map.get(&key(idx * 3)), Some(&idx));
+        }
+
+        let false_positives = (0..10_000)
+            .filter(|idx| map.may_contain(&key(idx * 3 + 1)))
+            .count();
+        assert!(false_positives < 500, "{false_positives}");
+        assert!(!map.contains_key(&key(1)));
+        assert_eq!(map.get_mut(&key(1)), None);
+        *map.get_mut(&key(3)).unwrap() += 10;
+        assert_eq!(map.get(&key(3)), Some(&11));
+    }
+
+    #[test]
+    fn removals_rebuild_filter() {
+        let mut map = FilteredTreeMap::new();
+        for idx in 0..2000 {
+            map.insert(key(idx), idx);
+        }
+
+        for idx in 0..1000 {
+            assert_eq!(map.remove(&key(idx)), Some(idx));
+        }
+        assert_eq!(map.stale_keys(), 1000);
+        assert_eq!(map.remove(&key(0)), None);
+
+        // Removing one more key makes the stale keys outnumber the live keys
+        assert_eq!(map.pop_first(), Some((key(1000), 1000)));
+        assert_eq!(map.stale_keys(), 0);
+        assert!(map.pop_last().is_some());
+        assert_eq!(map.len(), 998);
+
+        let stale_hits = (0..1000).filter(|idx| map.may_contain(&key(*idx))).count();
+        assert!(stale_hits < 100, "{stale_hits}");
+        for idx in 1001..1999 {
+            assert_eq!(map.get(&key(idx)), Some(&idx));
+        }
+
+        map.rebuild_filter();
+        assert_eq!(map.get(&key(1500)), Some(&1500));
+
+        map.clear();
+        assert!(map.is_empty());
+        assert!(!map.may_contain(&key(1500)));
+    }
+
+    #[test]
+    fn try_insert_and_from_map() {
+        let mut map = FilteredTreeMap::<Box<[u8]>, char>::with_false_positive_rate(0.001);
+        map.try_insert(Box::new([1, 2, 3]), 'a').unwrap();
+        assert!(map.try_insert(Box::new([1, 2]), 'b').is_err());
+        assert!(!map.may_contain([1, 2].as_ref()));
+        assert_eq!(map.get([1, 2, 3].as_ref()), Some(&'a'));
+
+        let filtered = FilteredTreeMap::from(map.into_map());
+        assert_eq!(filtered.get([1, 2, 3].as_ref()), Some(&'a'));
+        assert_eq!(filtered.map().len(), 1);
+    }
+
+    #[test]
+    #[should_panic = "false positive rate must be between 0 and 1"]
+    fn invalid_false_positive_rate() {
+        let _ = FilteredTreeMap::<[u8; 4], ()>::with_false_positive_rate(1.0);
+    }
+}
@@ -0,0 +1,356 @@
+//! A [`TreeMap`] wrapper with a hash index for point lookups.
+//!
+//! A lookup in the tree visits one node per distinguishing byte of the key. An
+//! [`IndexedTreeMap`] also keeps a hash table that points at the leaf of every
+//! key, so that [`IndexedTreeMap::get`] costs one hash of the key and one
+//! probe, like a [`HashMap`](std::collections::HashMap). Ordered iteration and
+//! range or prefix queries still go through the tree, which is available from
+//! [`IndexedTreeMap::map`].
+//!
+//! The index relies on leaves never moving: a leaf is allocated when its key
+//! is inserted, its value is replaced in place when the key is inserted again,
+//! and it is only deallocated when the key is removed.
+
+use crate::{AsBytes, InsertPrefixError, LeafNode, NoPrefixesBytes, NodePtr, TreeMap};
+use std::{
+    borrow::Borrow,
+    collections::{hash_map::RandomState, HashSet},
+    fmt,
+    hash::{BuildHasher, Hash, Hasher},
+};
+
+/// A pointer to a leaf of the tree, which hashes and compares as the bytes of
+/// the key in the leaf.
+struct LeafRef<K, V>(NodePtr<LeafNode<K, V>>);
+
+impl<K: AsBytes, V> LeafRef<K, V> {
+    fn key_bytes(&self) -> &[u8] {
+        // SAFETY: A `LeafRef` is only stored in the index while its leaf is part of
+        // the tree, and the tree is only mutated through a unique reference to the
+        // `IndexedTreeMap`, so there are no concurrent writes to the leaf.
+        let key = unsafe { self.0.as_key_ref() };
+        key.as_bytes()
+    }
+}
+
+impl<K: AsBytes, V> Borrow<[u8]> for LeafRef<K, V> {
+    fn borrow(&self) -> &[u8] {
+        self.key_bytes()
+    }
+}
+
+impl<K: AsBytes, V> Hash for LeafRef<K, V> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // This must match the hash of the borrowed form for lookups by key bytes
+        self.key_bytes().hash(state)
+    }
+}
+
+impl<K: AsBytes, V> PartialEq for LeafRef<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key_bytes() == other.key_bytes()
+    }
+}
+
+impl<K: AsBytes, V> Eq for LeafRef<K, V> {}
+
+/// A [`TreeMap`] with a hash index from each key to its leaf.
+///
+/// Point lookups use the index, everything else uses the tree. Mutations keep
+/// the two in sync, at the cost of an extra hash table update per insert and
+/// remove.
+///
+/// # Examples
+///
+/// ```rust
+/// use blart::indexed::IndexedTreeMap;
+///
+/// let mut map = IndexedTreeMap::<[u8; 2], char>::new();
+/// map.insert([1, 2], 'a');
+/// map.insert([1, 3], 'b');
+/// map.insert([0, 9], 'c');
+///
+/// assert_eq!(map.get(&[1, 3]), Some(&'b'));
+/// assert_eq!(map.get(&[2, 3]), None);
+///
+/// // Ordered queries still work through the tree
+/// let keys: Vec<_> = map.map().keys().copied().collect();
+/// assert_eq!(keys, [[0, 9], [1, 2], [1, 3]]);
+/// ```
+pub struct IndexedTreeMap<K, V, S = RandomState> {
+    index: HashSet<LeafRef<K, V>, S>,
+    map: TreeMap<K, V>,
+}
+
+impl<K, V> IndexedTreeMap<K, V> {
+    /// Create a new, empty map.
+    pub fn new() -> Self {
+        Self::with_hasher(RandomState::new())
+    }
+}
+
+impl<K, V, S> IndexedTreeMap<K, V, S> {
+    /// Create a new, empty map which uses the given hash builder for the index.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        IndexedTreeMap {
+            index: HashSet::with_hasher(hash_builder),
+            map: TreeMap::new(),
+        }
+    }
+
+    /// Returns a reference to the underlying map.
+    pub fn map(&self) -> &TreeMap<K, V> {
+        &self.map
+    }
+
+    /// Return the underlying map, discarding the index.
+    pub fn into_map(self) -> TreeMap<K, V> {
+        self.map
+    }
+
+    /// Returns the number of elements in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if the map contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Clear the map and the index, removing all elements.
+    pub fn clear(&mut self) {
+        // The index must not outlive the leaves it points to
+        self.index.clear();
+        self.map.clear();
+    }
+}
+
+impl<K, V, S> IndexedTreeMap<K, V, S>
+where
+    K: AsBytes,
+    S: BuildHasher,
+{
+    /// Create a map which indexes all the entries of the given map.
+    pub fn from_map_with_hasher(map: TreeMap<K, V>, hash_builder: S) -> Self {
+        let mut indexed = Self::with_hasher(hash_builder);
+        indexed.index.reserve(map.len());
+        for (key, value) in map {
+            // Keys from a map are unique and prefix-free
+            let _ = indexed.try_insert(key, value);
+        }
+        indexed
+    }
+
+    fn find_leaf(&self, key_bytes: &[u8]) -> Option<NodePtr<LeafNode<K, V>>> {
+        self.index.get(key_bytes).map(|leaf| leaf.0)
+    }
+
+    /// Returns a reference to the value corresponding to the key.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: AsBytes + ?Sized,
+    {
+        Some(self.get_key_value(key)?.1)
+    }
+
+    /// Returns the key-value pair corresponding to the supplied key.
+    pub fn get_key_value<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: AsBytes + ?Sized,
+    {
+        let leaf = self.find_leaf(key.as_bytes())?;
+        // SAFETY: The leaf is part of the tree, and the returned references are bound
+        // to the shared reference of the map, so there are no concurrent writes.
+        Some(unsafe { leaf.as_key_value_ref() })
+    }
+
+    /// Returns a mutable reference to the value corresponding to the key.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: AsBytes + ?Sized,
+    {
+        let leaf = self.find_leaf(key.as_bytes())?;
+        // SAFETY: The leaf is part of the tree, and the returned reference is bound to
+        // the unique reference of the map, so there are no other accesses.
+        Some(unsafe { leaf.as_value_mut() })
+    }
+
+    /// Returns `true` if the map contains a value for the specified key.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: AsBytes + ?Sized,
+    {
+        self.find_leaf(key.as_bytes()).is_some()
+    }
+
+    /// Insert a key-value pair into the map, returning the previous value if
+    /// the key was already present.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V>
+    where
+        K: NoPrefixesBytes,
+    {
+        match self.try_insert(key, value) {
+            Ok(value) => value,
+            Err(_err) => unreachable!(
+                "This branch should be unreachable because of the safety contract of \
+                 `NoPrefixesBytes`"
+            ),
+        }
+    }
+
+    /// Insert a key-value pair into the map, returning the previous value if
+    /// the key was already present.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the given key is a prefix of an existing key, or an
+    /// existing key is a prefix of the given key.
+    pub fn try_insert(&mut self, key: K, value: V) -> Result<Option<V>, InsertPrefixError> {
+        if let Some(leaf) = self.find_leaf(key.as_bytes()) {
+            // SAFETY: The leaf is part of the tree and there are no other accesses to it,
+            // because of the unique reference to the map.
+            let old_value = unsafe { leaf.as_value_mut() };
+            return Ok(Some(std::mem::replace(old_value, value)));
+        }
+
+        let (old_value, leaf) = self.map.try_insert_leaf(key, value)?;
+        debug_assert!(old_value.is_none(), "the key was missing from the index");
+        self.index.insert(LeafRef(leaf));
+        Ok(old_value)
+    }
+
+    /// Removes a key from the map, returning the value at the key if the key
+    /// was previously in the map.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: AsBytes + ?Sized,
+    {
+        Some(self.remove_entry(key)?.1)
+    }
+
+    /// Removes a key from the map, returning the stored key and value if the
+    /// key was previously in the map.
+    pub fn remove_entry<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: AsBytes + ?Sized,
+    {
+        // The index entry is removed first, so it never points to a deallocated leaf
+        self.index.take(key.as_bytes())?;
+        self.map.remove_entry(key)
+    }
+
+    /// Removes and returns the first element in the map.
+    pub fn pop_first(&mut self) -> Option<(K, V)> {
+        let (first_key, _) = self.map.first_key_value()?;
+        self.index.remove(first_key.as_bytes());
+        self.map.pop_first()
+    }
+
+    /// Removes and returns the last element in the map.
+    pub fn pop_last(&mut self) -> Option<(K, V)> {
+        let (last_key, _) = self.map.last_key_value()?;
+        self.index.remove(last_key.as_bytes());
+        self.map.pop_last()
+    }
+}
+
+impl<K, V> Default for IndexedTreeMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: AsBytes, V> From<TreeMap<K, V>> for IndexedTreeMap<K, V> {
+    fn from(map: TreeMap<K, V>) -> Self {
+        Self::from_map_with_hasher(map, RandomState::new())
+    }
+}
+
+impl<K, V, S> fmt::Debug for IndexedTreeMap<K, V, S>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IndexedTreeMap")
+            .field("map", &self.map)
+            .finish_non_exhaustive()
+    }
+}
+
+// SAFETY: The index only holds pointers to leaves owned by the map, so the
+// wrapper is `Send` and `Sync` under the same conditions as the map.
+unsafe impl<K: Send, V: Send, S: Send> Send for IndexedTreeMap<K, V, S> {}
+
+// SAFETY: See the `Send` impl. Shared access to the wrapper only gives shared
+// access to the keys and values.
+unsafe impl<K: Sync, V: Sync, S: Sync> Sync for IndexedTreeMap<K, V, S> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_tracks_tree_mutations() {
+        let mut map = IndexedTreeMap::new();
+        for idx in 0..2000u16 {
+            assert_eq!(map.insert(idx.to_be_bytes(), idx), None);
+        }
+        assert_eq!(map.index.len(), 2000);
+
+        // Overwriting a value keeps the same leaf
+        assert_eq!(map.insert(7u16.to_be_bytes(), 70), Some(7));
+        *map.get_mut(&8u16.to_be_bytes()).unwrap() = 80;
+        assert_eq!(map.map().get(&7u16.to_be_bytes()), Some(&70));
+        assert_eq!(map.get(&8u16.to_be_bytes()), Some(&80));
+
+        for idx in (0..2000u16).step_by(3) {
+            assert!(map.remove(&idx.to_be_bytes()).is_some());
+        }
+        assert_eq!(map.remove(&0u16.to_be_bytes()), None);
+        assert_eq!(map.pop_first(), Some((1u16.to_be_bytes(), 1)));
+        assert_eq!(map.pop_last(), Some((1999u16.to_be_bytes(), 1999)));
+        assert_eq!(map.len(), map.index.len());
+
+        for idx in 0..2000u16 {
+            let key = idx.to_be_bytes();
+            assert_eq!(map.get(&key), map.map().get(&key));
+            assert_eq!(map.contains_key(&key), map.map().contains_key(&key));
+        }
+
+        map.clear();
+        assert!(map.is_empty());
+        assert!(map.index.is_empty());
+        assert_eq!(map.get(&2u16.to_be_bytes()), None);
+    }
+
+    #[test]
+    fn prefix_keys_and_from_map() {
+        let mut tree = TreeMap::<Box<[u8]>, char>::new();
+        tree.try_insert(Box::new([1, 2, 3]), 'a').unwrap();
+        tree.try_insert(Box::new([1, 2, 4]), 'b').unwrap();
+
+        let mut map = IndexedTreeMap::from(tree);
+        assert!(map.try_insert(Box::new([1, 2]), 'c').is_err());
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get([1, 2].as_ref()), None);
+        assert_eq!(
+            map.get_key_value([1, 2, 4].as_ref()),
+            Some((&Box::from([1, 2, 4]), &'b'))
+        );
+        assert_eq!(
+            map.remove_entry([1, 2, 3].as_ref()),
+            Some((Box::from([1, 2, 3]), 'a'))
+        );
+
+        let tree = map.into_map();
+        assert_eq!(tree.len(), 1);
+    }
+}
@@ -0,0 +1,696 @@
+//! A map from half-open byte-key intervals to values, supporting stabbing and
+//! overlap queries.
+//!
+//! The intervals are stored in a [`TreeMap`] ordered by their start and then
+//! their end. To answer queries without scanning every interval that starts
+//! before the query point, the map keeps a max-endpoint augmentation: a
+//! balanced binary tree over the intervals in key order, where every subtree
+//! records the interval with the largest end. Searches skip every subtree
+//! whose largest end is not past the query point.
+//!
+//! The augmentation is a treap, so inserting or removing an interval only
+//! changes the nodes on the path to it, and their max endpoints are updated on
+//! the way back up, in expected `O(log n)` steps.
+
+use crate::TreeMap;
+use std::{fmt, iter::FusedIterator, mem};
+
+/// A half-open interval `[start, end)` of byte-string keys.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Interval {
+    /// The inclusive lower bound of the interval
+    pub start: Box<[u8]>,
+    /// The exclusive upper bound of the interval
+    pub end: Box<[u8]>,
+}
+
+impl Interval {
+    /// Returns `true` if the given point is contained in the interval.
+    pub fn contains(&self, point: &[u8]) -> bool {
+        &*self.start <= point && point < &*self.end
+    }
+}
+
+/// A map from [`Interval`]s to values, which can find every interval
+/// containing a point or overlapping another interval.
+///
+/// Every distinct interval holds a single value, inserting the same interval
+/// again replaces the value.
+///
+/// # Examples
+///
+/// ```rust
+/// use blart::interval::IntervalMap;
+///
+/// let mut map = IntervalMap::new();
+/// map.insert(b"apple", b"banana", 1);
+/// map.insert(b"b", b"c", 2);
+/// map.insert(b"cherry", b"date", 3);
+///
+/// let stabbed: Vec<_> = map.stab(b"bag").into_iter().map(|(_, value)| *value).collect();
+/// assert_eq!(stabbed, [1, 2]);
+///
+/// let overlapping: Vec<_> = map
+///     .overlapping(b"banana", b"cherry")
+///     .into_iter()
+///     .map(|(_, value)| *value)
+///     .collect();
+/// assert_eq!(overlapping, [2]);
+/// ```
+pub struct IntervalMap<V> {
+    /// The slot of every interval in the augmentation, keyed by the escaped
+    /// start bytes followed by the escaped end bytes.
+    map: TreeMap<Box<[u8]>, usize>,
+    /// The max-endpoint augmentation, which holds the values.
+    augmentation: Augmentation<V>,
+}
+
+impl<V> IntervalMap<V> {
+    /// Create a new, empty interval map.
+    pub fn new() -> Self {
+        IntervalMap {
+            map: TreeMap::new(),
+            augmentation: Augmentation::new(),
+        }
+    }
+
+    /// Returns the number of intervals in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if the map contains no intervals.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Insert a value for the interval `[start, end)`, returning the previous
+    /// value if the same interval was already present.
+    ///
+    /// # Panics
+    ///
+    ///  - Panics if `start` is not less than `end`.
+    pub fn insert(&mut self, start: &[u8], end: &[u8], value: V) -> Option<V> {
+        assert!(start < end, "interval start must be less than its end");
+
+        let key = encode_interval(start, end);
+        if let Some(&slot) = self.map.get(key.as_ref()) {
+            return Some(mem::replace(
+                &mut self.augmentation.node_mut(slot).value,
+                value,
+            ));
+        }
+
+        let slot = self.augmentation.insert(key.clone(), value);
+        // PANIC SAFETY: The escaped encoding of the start and end is prefix-free, so
+        // one interval key is never a prefix of another.
+        self.map
+            .try_insert(key, slot)
+            .expect("interval keys should not be prefixes of each other");
+        None
+    }
+
+    /// Returns a reference to the value of the interval `[start, end)`.
+    pub fn get(&self, start: &[u8], end: &[u8]) -> Option<&V> {
+        let slot = self.map.get(encode_interval(start, end).as_ref())?;
+        Some(&self.augmentation.node(*slot).value)
+    }
+
+    /// Remove the interval `[start, end)`, returning its value if it was
+    /// present.
+    pub fn remove(&mut self, start: &[u8], end: &[u8]) -> Option<V> {
+        let slot = self.map.remove(encode_interval(start, end).as_ref())?;
+        Some(self.augmentation.remove(slot))
+    }
+
+    /// Remove all intervals from the map.
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.augmentation.clear();
+    }
+
+    /// Returns an iterator over the intervals and their values, ordered by
+    /// start and then by end.
+    pub fn iter(
+        &self,
+    ) -> impl DoubleEndedIterator<Item = (Interval, &V)> + ExactSizeIterator + FusedIterator {
+        self.map
+            .iter()
+            .map(|(key, slot)| (decode_interval(key), &self.augmentation.node(*slot).value))
+    }
+
+    /// Returns every interval that contains `point`, along with its value,
+    /// ordered by start and then by end.
+    pub fn stab(&self, point: &[u8]) -> Vec<(Interval, &V)> {
+        let point = escape(point);
+        // An interval `[s, e)` contains the point when `s <= point < e`
+        self.query(|start| start <= &*point, &point)
+    }
+
+    /// Returns every interval that overlaps `[start, end)`, along with its
+    /// value, ordered by start and then by end.
+    ///
+    /// An empty query interval, where `start` is not less than `end`, overlaps
+    /// nothing.
+    pub fn overlapping(&self, start: &[u8], end: &[u8]) -> Vec<(Interval, &V)> {
+        if start >= end {
+            return Vec::new();
+        }
+
+        let start = escape(start);
+        let end = escape(end);
+        // An interval `[s, e)` overlaps `[start, end)` when `s < end` and `start < e`
+        self.query(|interval_start| interval_start < &*end, &start)
+    }
+
+    /// Find every interval whose escaped start satisfies `start_before`, and
+    /// whose escaped end is greater than `lower_bound`.
+    ///
+    /// All intervals that satisfy `start_before` must come before all
+    /// intervals that do not, in key order.
+    fn query(
+        &self,
+        start_before: impl Fn(&[u8]) -> bool,
+        lower_bound: &[u8],
+    ) -> Vec<(Interval, &V)> {
+        let mut found = Vec::new();
+        self.augmentation.find_ends_after(
+            self.augmentation.root,
+            &start_before,
+            lower_bound,
+            &mut found,
+        );
+
+        found
+            .into_iter()
+            .map(|node| (decode_interval(&node.key), &node.value))
+            .collect()
+    }
+}
+
+impl<V> Default for IntervalMap<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V> fmt::Debug for IntervalMap<V>
+where
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+/// A node of the augmentation, holding one interval and its value.
+struct AugmentedNode<V> {
+    /// The escaped start bytes followed by the escaped end bytes.
+    key: Box<[u8]>,
+    /// The length of the escaped start at the beginning of `key`.
+    start_len: usize,
+    value: V,
+    /// The heap priority of the node, which is never higher than the priority
+    /// of its parent.
+    priority: u64,
+    left: Option<usize>,
+    right: Option<usize>,
+    /// The slot of the node with the largest end in the subtree of this node.
+    max_end: usize,
+}
+
+impl<V> AugmentedNode<V> {
+    fn start(&self) -> &[u8] {
+        &self.key[..self.start_len]
+    }
+
+    fn end(&self) -> &[u8] {
+        &self.key[self.start_len..]
+    }
+}
+
+/// The max-endpoint augmentation of the intervals: a treap over the intervals
+/// in key order, where every node records the interval with the largest end in
+/// its subtree.
+///
+/// The nodes live in the slots of a vector and refer to each other by slot, so
+/// that the [`TreeMap`] of the intervals can point at them.
+struct Augmentation<V> {
+    slots: Vec<Option<AugmentedNode<V>>>,
+    /// The slots which were freed by removed intervals.
+    free_slots: Vec<usize>,
+    root: Option<usize>,
+    /// The state of the generator for node priorities.
+    rng_state: u64,
+}
+
+impl<V> Augmentation<V> {
+    fn new() -> Self {
+        Augmentation {
+            slots: Vec::new(),
+            free_slots: Vec::new(),
+            root: None,
+            rng_state: 0x9E37_79B9_7F4A_7C15,
+        }
+    }
+
+    fn node(&self, slot: usize) -> &AugmentedNode<V> {
+        // PANIC SAFETY: Slots are only referred to while they hold a node
+        self.slots[slot].as_ref().expect("slot should hold a node")
+    }
+
+    fn node_mut(&mut self, slot: usize) -> &mut AugmentedNode<V> {
+        // PANIC SAFETY: Slots are only referred to while they hold a node
+        self.slots[slot].as_mut().expect("slot should hold a node")
+    }
+
+    /// Return the next node priority, from a xorshift generator.
+    fn next_priority(&mut self) -> u64 {
+        let mut state = self.rng_state;
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        self.rng_state = state;
+        state
+    }
+
+    /// Recompute the max endpoint of the node, from its own interval and the
+    /// max endpoints of its children.
+    fn update_max_end(&mut self, slot: usize) {
+        let node = self.node(slot);
+        let mut max_end = slot;
+        for child in [node.left, node.right].into_iter().flatten() {
+            let child_max_end = self.node(child).max_end;
+            if self.node(child_max_end).end() > self.node(max_end).end() {
+                max_end = child_max_end;
+            }
+        }
+        self.node_mut(slot).max_end = max_end;
+    }
+
+    /// Insert an interval which is not present yet, and return its slot.
+    fn insert(&mut self, key: Box<[u8]>, value: V) -> usize {
+        let node = AugmentedNode {
+            start_len: escaped_len(&key),
+            key,
+            value,
+            priority: self.next_priority(),
+            left: None,
+            right: None,
+            max_end: 0,
+        };
+        let slot = match self.free_slots.pop() {
+            Some(slot) => {
+                self.slots[slot] = Some(node);
+                slot
+            },
+            None => {
+                self.slots.push(Some(node));
+                self.slots.len() - 1
+            },
+        };
+        self.node_mut(slot).max_end = slot;
+
+        self.root = Some(self.insert_into(self.root, slot));
+        slot
+    }
+
+    /// Insert the node in `slot` into the subtree, and return the new root of
+    /// the subtree.
+    fn insert_into(&mut self, subtree: Option<usize>, slot: usize) -> usize {
+        let Some(root) = subtree else {
+            return slot;
+        };
+
+        if self.node(slot).priority > self.node(root).priority {
+            let (left, right) = self.split(Some(root), slot);
+            let node = self.node_mut(slot);
+            node.left = left;
+            node.right = right;
+            self.update_max_end(slot);
+            return slot;
+        }
+
+        if self.node(slot).key < self.node(root).key {
+            let left = self.insert_into(self.node(root).left, slot);
+            self.node_mut(root).left = Some(left);
+        } else {
+            let right = self.insert_into(self.node(root).right, slot);
+            self.node_mut(root).right = Some(right);
+        }
+        self.update_max_end(root);
+        root
+    }
+
+    /// Split the subtree into the nodes whose keys are less than the key of
+    /// the node in `slot`, and the nodes whose keys are greater.
+    fn split(&mut self, subtree: Option<usize>, slot: usize) -> (Option<usize>, Option<usize>) {
+        let Some(root) = subtree else {
+            return (None, None);
+        };
+
+        if self.node(root).key < self.node(slot).key {
+            let (left, right) = self.split(self.node(root).right, slot);
+            self.node_mut(root).right = left;
+            self.update_max_end(root);
+            (Some(root), right)
+        } else {
+            let (left, right) = self.split(self.node(root).left, slot);
+            self.node_mut(root).left = right;
+            self.update_max_end(root);
+            (left, Some(root))
+        }
+    }
+
+    /// Merge two subtrees, where every key in `left` is less than every key in
+    /// `right`, and return the root of the merged subtree.
+    fn merge(&mut self, left: Option<usize>, right: Option<usize>) -> Option<usize> {
+        let (left_root, right_root) = match (left, right) {
+            (Some(left_root), Some(right_root)) => (left_root, right_root),
+            (subtree, None) | (None, subtree) => return subtree,
+        };
+
+        if self.node(left_root).priority > self.node(right_root).priority {
+            let merged = self.merge(self.node(left_root).right, right);
+            self.node_mut(left_root).right = merged;
+            self.update_max_end(left_root);
+            Some(left_root)
+        } else {
+            let merged = self.merge(left, self.node(right_root).left);
+            self.node_mut(right_root).left = merged;
+            self.update_max_end(right_root);
+            Some(right_root)
+        }
+    }
+
+    /// Remove the interval in the given slot, and return its value.
+    fn remove(&mut self, slot: usize) -> V {
+        self.root = self.remove_from(self.root, slot);
+        self.free_slots.push(slot);
+        // PANIC SAFETY: The slot was referred to by the map, so it holds a node
+        self.slots[slot]
+            .take()
+            .expect("slot should hold a node")
+            .value
+    }
+
+    /// Unlink the node in `slot` from the subtree, and return the new root of
+    /// the subtree.
+    fn remove_from(&mut self, subtree: Option<usize>, slot: usize) -> Option<usize> {
+        // PANIC SAFETY: The node is in the tree, so it is found before reaching an
+        // empty subtree.
+        let root = subtree.expect("interval should be in the augmentation");
+        if root == slot {
+            let node = self.node(slot);
+            return self.merge(node.left, node.right);
+        }
+
+        if self.node(slot).key < self.node(root).key {
+            let left = self.remove_from(self.node(root).left, slot);
+            self.node_mut(root).left = left;
+        } else {
+            let right = self.remove_from(self.node(root).right, slot);
+            self.node_mut(root).right = right;
+        }
+        self.update_max_end(root);
+        Some(root)
+    }
+
+    /// Remove all the intervals.
+    fn clear(&mut self) {
+        self.slots.clear();
+        self.free_slots.clear();
+        self.root = None;
+    }
+
+    /// Push the nodes of the subtree whose escaped start satisfies
+    /// `start_before` and whose escaped end is greater than `lower_bound`, in
+    /// key order.
+    ///
+    /// Returns `false` once a node whose start does not satisfy
+    /// `start_before` was reached, since no later node can satisfy it either.
+    fn find_ends_after<'a>(
+        &'a self,
+        subtree: Option<usize>,
+        start_before: &impl Fn(&[u8]) -> bool,
+        lower_bound: &[u8],
+        found: &mut Vec<&'a AugmentedNode<V>>,
+    ) -> bool {
+        let Some(slot) = subtree else {
+            return true;
+        };
+        let node = self.node(slot);
+        if self.node(node.max_end).end() <= lower_bound {
+            // No interval in this subtree ends after the lower bound
+            return true;
+        }
+
+        if !self.find_ends_after(node.left, start_before, lower_bound, found) {
+            return false;
+        }
+        if !start_before(node.start()) {
+            return false;
+        }
+        if node.end() > lower_bound {
+            found.push(node);
+        }
+        self.find_ends_after(node.right, start_before, lower_bound, found)
+    }
+}
+
+/// Escape the bytes so that the encoding is prefix-free and preserves the
+/// lexicographic ordering.
+///
+/// Every `0x00` byte is written as `0x00 0xFF`, and the encoding is terminated
+/// by `0x00 0x00`.
+fn escape(bytes: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(bytes.len() + 2);
+    for byte in bytes {
+        escaped.push(*byte);
+        if *byte == 0 {
+            escaped.push(0xFF);
+        }
+    }
+    escaped.extend_from_slice(&[0, 0]);
+    escaped
+}
+
+/// Return the length of the escaped value at the start of `escaped`, including
+/// the terminator.
+fn escaped_len(escaped: &[u8]) -> usize {
+    let mut idx = 0;
+    loop {
+        if escaped[idx] == 0 {
+            if escaped[idx + 1] == 0 {
+                return idx + 2;
+            }
+            idx += 2;
+        } else {
+            idx += 1;
+        }
+    }
+}
+
+/// Reverse [`escape`], for an escaped value that ends with its terminator.
+fn unescape(escaped: &[u8]) -> Box<[u8]> {
+    let mut bytes = Vec::with_capacity(escaped.len());
+    let mut idx = 0;
+    while idx < escaped.len() - 2 {
+        bytes.push(escaped[idx]);
+        idx += if escaped[idx] == 0 { 2 } else { 1 };
+    }
+    bytes.into_boxed_slice()
+}
+
+fn encode_interval(start: &[u8], end: &[u8]) -> Box<[u8]> {
+    let mut key = escape(start);
+    key.extend(escape(end));
+    key.into_boxed_slice()
+}
+
+fn decode_interval(key: &[u8]) -> Interval {
+    let start_len = escaped_len(key);
+    Interval {
+        start: unescape(&key[..start_len]),
+        end: unescape(&key[start_len..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(results: Vec<(Interval, &usize)>) -> Vec<usize> {
+        results.into_iter().map(|(_, value)| *value).collect()
+    }
+
+    #[test]
+    fn escape_roundtrip_and_ordering() {
+        let values: [&[u8]; 6] = [&[], &[0], &[0, 0], &[0, 1], &[1], &[1, 0, 255]];
+        for a in values {
+            assert_eq!(&*unescape(&escape(a)), a);
+            assert_eq!(escaped_len(&escape(a)), escape(a).len());
+            for b in values {
+                assert_eq!(a.cmp(b), escape(a).cmp(&escape(b)), "{a:?} {b:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn stab_and_overlap_match_brute_force() {
+        let mut map = IntervalMap::new();
+        let mut intervals = Vec::new();
+        for start in 0u8..40 {
+            let end = start + 1 + (start % 13) * 7 % 13;
+            let interval = Interval {
+                start: Box::new([start / 4, start % 4]),
+                end: Box::new([end / 4, end % 4, 0]),
+            };
+            map.insert(&interval.start, &interval.end, intervals.len());
+            intervals.push(interval);
+        }
+        assert_eq!(map.len(), intervals.len());
+
+        for a in 0u8..12 {
+            for b in 0u8..4 {
+                let point = [a, b];
+                let expected: Vec<_> = (0..intervals.len())
+                    .filter(|idx| intervals[*idx].contains(&point))
+                    .collect();
+                assert_eq!(values(map.stab(&point)), expected, "stab {point:?}");
+
+                let query_end = [a + 1, b];
+                let expected: Vec<_> = (0..intervals.len())
+                    .filter(|idx| {
+                        *intervals[*idx].start < query_end[..] && point[..] < *intervals[*idx].end
+                    })
+                    .collect();
+                assert_eq!(
+                    values(map.overlapping(&point, &query_end)),
+                    expected,
+                    "overlapping {point:?}..{query_end:?}"
+                );
+            }
+        }
+    }
+
+    /// Check the heap order of the priorities and the max endpoint of every
+    /// node, and return the number of nodes.
+    fn check_augmentation<V>(augmentation: &Augmentation<V>, subtree: Option<usize>) -> usize {
+        let Some(slot) = subtree else {
+            return 0;
+        };
+        let node = augmentation.node(slot);
+        let mut max_end = node.end();
+        for child in [node.left, node.right].into_iter().flatten() {
+            assert!(augmentation.node(child).priority <= node.priority);
+            max_end = max_end.max(augmentation.node(augmentation.node(child).max_end).end());
+        }
+        assert_eq!(augmentation.node(node.max_end).end(), max_end);
+
+        1 + check_augmentation(augmentation, node.left)
+            + check_augmentation(augmentation, node.right)
+    }
+
+    #[test]
+    fn modifications_update_augmentation() {
+        let mut map = IntervalMap::new();
+        map.insert(&[1], &[5], 0);
+        assert_eq!(values(map.stab(&[3])), [0]);
+
+        map.insert(&[2], &[9], 1);
+        assert_eq!(values(map.stab(&[6])), [1]);
+
+        assert_eq!(map.insert(&[2], &[9], 2), Some(1));
+        assert_eq!(map.remove(&[1], &[5]), Some(0));
+        assert_eq!(values(map.stab(&[3])), [2]);
+        assert_eq!(map.get(&[2], &[9]), Some(&2));
+
+        map.clear();
+        assert!(map.stab(&[3]).is_empty());
+        assert_eq!(
+            check_augmentation(&map.augmentation, map.augmentation.root),
+            0
+        );
+    }
+
+    #[test]
+    fn interleaved_inserts_and_removes_match_brute_force() {
+        let mut map = IntervalMap::new();
+        let mut expected = std::collections::BTreeMap::new();
+        let mut state = 5u32;
+        let mut next = |bound: u32| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            ((state >> 16) % bound) as u8
+        };
+
+        for round in 0..2_000usize {
+            let start = next(50);
+            let end = start + 1 + next(10);
+            if next(3) == 0 {
+                assert_eq!(
+                    map.remove(&[start], &[end]),
+                    expected.remove(&(start, end)),
+                    "remove {start}..{end}"
+                );
+            } else {
+                assert_eq!(
+                    map.insert(&[start], &[end], round),
+                    expected.insert((start, end), round),
+                    "insert {start}..{end}"
+                );
+            }
+
+            if round % 50 == 0 {
+                assert_eq!(
+                    check_augmentation(&map.augmentation, map.augmentation.root),
+                    expected.len()
+                );
+                for point in 0..62 {
+                    let stabbed: Vec<_> = expected
+                        .iter()
+                        .filter(|((start, end), _)| *start <= point && point < *end)
+                        .map(|(_, value)| *value)
+                        .collect();
+                    assert_eq!(values(map.stab(&[point])), stabbed, "stab {point}");
+                }
+            }
+        }
+        assert_eq!(map.len(), expected.len());
+        assert!(map
+            .iter()
+            .map(|(interval, value)| (interval.start[0], interval.end[0], *value))
+            .eq(expected
+                .iter()
+                .map(|((start, end), value)| (*start, *end, *value))));
+    }
+
+    #[test]
+    fn intervals_with_zero_bytes() {
+        let mut map = IntervalMap::new();
+        map.insert(&[], &[0], 'a');
+        map.insert(&[0], &[0, 0, 1], 'b');
+        map.insert(&[0, 0], &[1], 'c');
+
+        let stabbed: Vec<_> = map.stab(&[0, 0]).into_iter().map(|(_, v)| *v).collect();
+        assert_eq!(stabbed, ['b', 'c']);
+
+        let stabbed: Vec<_> = map.stab(&[]).into_iter().map(|(_, v)| *v).collect();
+        assert_eq!(stabbed, ['a']);
+
+        let (interval, _) = map.iter().nth(1).unwrap();
+        assert_eq!(
+            interval,
+            Interval {
+                start: Box::new([0]),
+                end: Box::new([0, 0, 1]),
+            }
+        );
+    }
+
+    #[test]
+    #[should_panic = "interval start must be less than its end"]
+    fn insert_empty_interval_panics() {
+        IntervalMap::new().insert(&[1], &[1], ());
+    }
+}
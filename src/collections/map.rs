@@ -1,35 +1,81 @@
 //! Module containing implementations of the `TreeMap` and associated
 //! iterators/etc.
 
+#[cfg(feature = "rayon")]
+use crate::par_deallocate_tree_in;
 use crate::{
-    deallocate_tree, delete_maximum_unchecked, delete_minimum_unchecked, delete_unchecked,
-    insert_unchecked, maximum_unchecked, minimum_unchecked, search_unchecked,
-    visitor::TreeStatsCollector, AsBytes, DeleteResult, InsertPrefixError, InsertResult, LeafNode,
-    NoPrefixesBytes, NodePtr, OpaqueNodePtr,
+    allocator::{Allocator, Global, InterchangeableAllocator},
+    build_tree_from_entries, clone_tree_in, deallocate_tree_in, delete_bytes_with_policy_unchecked,
+    delete_found_leaf_with_policy_unchecked, delete_maximum_with_policy_unchecked_in,
+    delete_minimum_with_policy_unchecked_in, delete_with_policy_unchecked_in,
+    drop_handle::DropHandle,
+    graft_subtree_unchecked, insert_at_entry_point_unchecked, insert_from_unchecked,
+    insert_recorded, insert_unlinked_leaf_unchecked, maximum_unchecked, merge_trees_in,
+    merkle::{ContentDigests, ContentHasher},
+    minimum_unchecked, search_bytes_unchecked, search_instrumented_unchecked,
+    search_prefix_branches_unchecked, search_prefix_unchecked, search_unchecked,
+    search_with_diagnostics_unchecked, split_tree_in, unlink_bytes_with_policy_unchecked,
+    unlink_prefix_with_policy_unchecked,
+    visitor::{DotPrinter, DotPrinterSettings, TreeStats, TreeStatsCollector},
+    AsBytes, BuildResult, Checked, ConcreteNodePtr, DeleteResult, InnerNode, InsertPrefixError,
+    InsertResult, InsertSearchResult, LeafNode, MergeResult, NoPrefixesBytes, NodePtr,
+    OpaqueNodePtr, PrefixBranches, ResizePolicy, RightmostPath, SearchRecorder, SearchStats,
+    SplitResult, TreeIterator, TryAsBytes,
 };
 use std::{
     borrow::Borrow,
-    fmt::Debug,
+    error::Error,
+    fmt::{self, Debug},
     hash::Hash,
-    mem::ManuallyDrop,
+    io,
+    mem::{self, ManuallyDrop},
     ops::{Index, RangeBounds},
 };
 
 mod iterators;
 pub use iterators::*;
 
+mod entry;
+pub use entry::*;
+
+mod raw_entry;
+pub use raw_entry::*;
+
+mod diagnostics;
+pub use diagnostics::*;
+
+mod display;
+pub use display::*;
+
+/// The previous value for an inserted key, and the leaf now holding the key.
+type InsertLeafResult<K, V> = Result<(Option<V>, NodePtr<LeafNode<K, V>>), InsertPrefixError>;
+
+/// The previous value for an inserted key, the leaf now holding the key, and
+/// the node at the insert point.
+type InsertLeafFromResult<K, V> =
+    Result<(Option<V>, NodePtr<LeafNode<K, V>>, OpaqueNodePtr<K, V>), InsertPrefixError>;
+
 /// An ordered map based on an adaptive radix tree.
-pub struct TreeMap<K, V> {
+///
+/// The nodes of the tree are allocated from `A`, which defaults to the
+/// [`Global`] allocator. Any memory owned by the keys and values themselves is
+/// still allocated from the global allocator.
+pub struct TreeMap<K, V, A: Allocator = Global> {
     /// The number of entries present in the tree.
     num_entries: usize,
     /// A pointer to the tree root, if present.
     root: Option<OpaqueNodePtr<K, V>>,
+    /// The policy used to decide when inner nodes shrink.
+    resize_policy: ResizePolicy,
+    /// The allocator used for the nodes of the tree.
+    alloc: A,
 }
 
 impl<K, V> TreeMap<K, V> {
     /// Create a new, empty [`TreeMap`].
     ///
-    /// This function will not pre-allocate anything.
+    /// This function will not pre-allocate anything, and can be used in a
+    /// constant context, such as to initialize a `static`.
     ///
     /// # Examples
     ///
@@ -39,12 +85,36 @@ impl<K, V> TreeMap<K, V> {
     /// let map = TreeMap::<Box<[u8]>, ()>::new();
     /// assert_eq!(map, TreeMap::new());
     /// assert!(map.is_empty());
+    ///
+    /// static EMPTY: TreeMap<Box<[u8]>, u32> = TreeMap::new();
+    /// assert!(EMPTY.is_empty());
+    /// assert_eq!(EMPTY.get([1, 2, 3].as_ref()), None);
     /// ```
-    pub fn new() -> Self {
-        TreeMap {
-            num_entries: 0,
-            root: None,
-        }
+    pub const fn new() -> Self {
+        Self::with_resize_policy(ResizePolicy::DEFAULT)
+    }
+
+    /// Create a new, empty [`TreeMap`] that shrinks inner nodes according to
+    /// the given [`ResizePolicy`].
+    ///
+    /// This function will not pre-allocate anything.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::{ResizePolicy, TreeMap};
+    ///
+    /// let mut map = TreeMap::<[u8; 2], u16>::with_resize_policy(ResizePolicy::HYSTERESIS);
+    /// for idx in 0..20u16 {
+    ///     map.insert(idx.to_be_bytes(), idx);
+    /// }
+    /// map.remove(&3u16.to_be_bytes());
+    ///
+    /// assert_eq!(map.resize_policy(), ResizePolicy::HYSTERESIS);
+    /// assert_eq!(map.len(), 19);
+    /// ```
+    pub const fn with_resize_policy(resize_policy: ResizePolicy) -> Self {
+        Self::with_resize_policy_in(resize_policy, Global)
     }
 
     /// Convert tree into a pointer to pointer to the root node.
@@ -70,9 +140,28 @@ impl<K, V> TreeMap<K, V> {
         drop_prevent.root
     }
 
+    /// Detach all the nodes of the tree into a [`DropHandle`], which can be
+    /// dropped on another thread.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::{drop_handle::DropQueue, TreeMap};
+    ///
+    /// let queue = DropQueue::new();
+    /// let mut map = TreeMap::<Box<[u8]>, char>::new();
+    /// map.try_insert(Box::new([1, 2, 3]), 'a').unwrap();
+    ///
+    /// map.into_drop_handle().drop_on(&queue);
+    /// ```
+    pub fn into_drop_handle(self) -> DropHandle<K, V> {
+        DropHandle::new(self)
+    }
+
     /// Constructs a tree from a pointer to the root node.
     ///
-    /// If `None` is passed, it constructs an empty tree.
+    /// If `None` is passed, it constructs an empty tree. The tree uses the
+    /// [`ResizePolicy::DEFAULT`] policy.
     ///
     /// # Safety
     ///
@@ -98,6 +187,134 @@ impl<K, V> TreeMap<K, V> {
     /// assert_eq!(map2[[1, 2, 3].as_ref()], 'a');
     /// ```
     pub unsafe fn from_raw(root: Option<OpaqueNodePtr<K, V>>) -> Self {
+        // SAFETY: The safety requirements on this function cover this call, and
+        // the nodes of a tree returned from `into_raw` were allocated by `Global`.
+        unsafe { Self::from_raw_in(root, Global) }
+    }
+
+    /// Creates a map from entries sorted by key, by building the tree bottom
+    /// up instead of inserting one entry at a time.
+    ///
+    /// The sorted keys are split on the byte that separates them, and each
+    /// inner node is allocated with its final size, so nodes are never grown
+    /// or copied during the build. The resulting tree is the same as the one
+    /// created by inserting the entries one by one.
+    ///
+    /// If the entries are not sorted by key, they are sorted first. For equal
+    /// keys, only the last entry is kept, like repeated calls to
+    /// [`TreeMap::insert`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::TreeMap;
+    ///
+    /// let map = TreeMap::from_sorted_iter((0..1000u32).map(|n| (n.to_be_bytes(), n)));
+    ///
+    /// assert_eq!(map.len(), 1000);
+    /// assert_eq!(map.first_key_value(), Some((&0u32.to_be_bytes(), &0)));
+    /// assert_eq!(map.get(&500u32.to_be_bytes()), Some(&500));
+    /// ```
+    pub fn from_sorted_iter<I>(entries: I) -> Self
+    where
+        K: NoPrefixesBytes,
+        I: IntoIterator<Item = (K, V)>,
+    {
+        match Self::try_from_sorted_iter(entries) {
+            Ok(map) => map,
+            Err(_err) => unreachable!(
+                "This branch should be unreachable because of the safety contract of \
+                 `NoPrefixesBytes`"
+            ),
+        }
+    }
+
+    /// Creates a map from entries sorted by key, by building the tree bottom
+    /// up instead of inserting one entry at a time.
+    ///
+    /// See [`TreeMap::from_sorted_iter`] for details.
+    ///
+    /// # Errors
+    ///  - If any key is a prefix of another key, then it returns an error for
+    ///    the longer key, and all the entries are dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::TreeMap;
+    ///
+    /// let words = ["apple", "banana", "cherry"].map(|word| (Box::<[u8]>::from(word.as_bytes()), word.len()));
+    /// let map = TreeMap::try_from_sorted_iter(words).unwrap();
+    /// assert_eq!(map.get(b"banana".as_ref()), Some(&6));
+    ///
+    /// let prefixes = ["app", "apple"].map(|word| (Box::<[u8]>::from(word.as_bytes()), word.len()));
+    /// assert!(TreeMap::try_from_sorted_iter(prefixes).is_err());
+    /// ```
+    pub fn try_from_sorted_iter<I>(entries: I) -> Result<Self, InsertPrefixError>
+    where
+        K: AsBytes,
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut map = TreeMap::new();
+        if let Some(BuildResult { root, num_entries }) =
+            build_tree_from_entries(entries.into_iter().collect())?
+        {
+            map.root = Some(root);
+            map.num_entries = num_entries;
+        }
+        Ok(map)
+    }
+}
+
+impl<K, V, A: Allocator> TreeMap<K, V, A> {
+    /// Create a new, empty [`TreeMap`] which allocates its nodes from the
+    /// given allocator.
+    ///
+    /// This function will not pre-allocate anything.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::{allocator::Global, TreeMap};
+    ///
+    /// let mut map = TreeMap::<[u8; 2], u16>::new_in(Global);
+    /// map.insert(1u16.to_be_bytes(), 1);
+    ///
+    /// assert_eq!(map.len(), 1);
+    /// ```
+    pub const fn new_in(alloc: A) -> Self {
+        Self::with_resize_policy_in(ResizePolicy::DEFAULT, alloc)
+    }
+
+    /// Create a new, empty [`TreeMap`] which allocates its nodes from the
+    /// given allocator and shrinks inner nodes according to the given
+    /// [`ResizePolicy`].
+    ///
+    /// This function will not pre-allocate anything.
+    pub const fn with_resize_policy_in(resize_policy: ResizePolicy, alloc: A) -> Self {
+        TreeMap {
+            num_entries: 0,
+            root: None,
+            resize_policy,
+            alloc,
+        }
+    }
+
+    /// Constructs a tree from a pointer to the root node, whose nodes were
+    /// allocated from the given allocator.
+    ///
+    /// If `None` is passed, it constructs an empty tree. The tree uses the
+    /// [`ResizePolicy::DEFAULT`] policy.
+    ///
+    /// # Safety
+    ///
+    ///  - The pointer passed to this function must not be used in a second call
+    ///    to `from_raw_in`, otherwise multiple safety issues could occur.
+    ///  - Every node of the tree under `root` must have been allocated by
+    ///    `alloc`.
+    ///  - No other function can mutate the content of the tree under `root`
+    ///    while this function executes.
+    pub unsafe fn from_raw_in(root: Option<OpaqueNodePtr<K, V>>, alloc: A) -> Self {
         let num_entries = if let Some(root) = root {
             // SAFETY: The safety requirements on this function cover this call
             unsafe { TreeStatsCollector::count_leaf_nodes(root) }
@@ -105,7 +322,40 @@ impl<K, V> TreeMap<K, V> {
             0
         };
 
-        TreeMap { num_entries, root }
+        TreeMap {
+            num_entries,
+            root,
+            resize_policy: ResizePolicy::DEFAULT,
+            alloc,
+        }
+    }
+
+    /// Convert tree into a pointer to the root node and the allocator of the
+    /// nodes.
+    ///
+    /// If there are no elements in the tree, then the pointer is `None`.
+    pub fn into_raw_with_allocator(self) -> (Option<OpaqueNodePtr<K, V>>, A) {
+        let drop_prevent = ManuallyDrop::new(self);
+        // SAFETY: The allocator is read exactly once, and the original is never
+        // dropped or used again since it is wrapped in `ManuallyDrop`.
+        let alloc = unsafe { std::ptr::read(&drop_prevent.alloc) };
+
+        (drop_prevent.root, alloc)
+    }
+
+    /// Returns a reference to the allocator of the tree nodes.
+    pub const fn allocator(&self) -> &A {
+        &self.alloc
+    }
+
+    /// Returns the policy used to decide when inner nodes shrink.
+    pub const fn resize_policy(&self) -> ResizePolicy {
+        self.resize_policy
+    }
+
+    /// Returns a pointer to the root node of the tree, if present.
+    pub(crate) fn root(&self) -> Option<OpaqueNodePtr<K, V>> {
+        self.root
     }
 
     /// Clear the map, removing all elements.
@@ -130,7 +380,7 @@ impl<K, V> TreeMap<K, V> {
             // no other mutable references to any node in the tree, meaning we can
             // deallocate all of them.
             unsafe {
-                deallocate_tree(root);
+                deallocate_tree_in(root, &self.alloc);
             }
 
             self.num_entries = 0;
@@ -138,27 +388,680 @@ impl<K, V> TreeMap<K, V> {
         }
     }
 
-    /// Returns a reference to the value corresponding to the key.
+    /// Clear the map, removing all elements, and dropping separate parts of
+    /// the tree in parallel on the [`rayon`] thread pool.
+    ///
+    /// This is useful for very large maps, where [`TreeMap::clear`] or
+    /// dropping the map would spend a long time deallocating nodes on a single
+    /// thread.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::TreeMap;
+    ///
+    /// let mut map = TreeMap::<[u8; 4], u32>::new();
+    ///
+    /// for idx in 0..10_000u32 {
+    ///     map.insert(idx.to_be_bytes(), idx);
+    /// }
+    ///
+    /// map.par_clear();
+    /// assert!(map.is_empty());
+    /// ```
+    #[cfg(feature = "rayon")]
+    pub fn par_clear(&mut self)
+    where
+        K: Send,
+        V: Send,
+        A: Sync,
+    {
+        if let Some(root) = self.root.take() {
+            self.num_entries = 0;
+
+            // SAFETY: Since we have a mutable reference to the map, we know that there are
+            // no other mutable references to any node in the tree, meaning we can
+            // deallocate all of them. The root was removed from the map, so the nodes
+            // will not be deallocated again.
+            unsafe {
+                par_deallocate_tree_in(root, &self.alloc);
+            }
+        }
+    }
+
+    /// Returns a reference to the value corresponding to the key.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::TreeMap;
+    ///
+    /// let mut map = TreeMap::<Box<[u8]>, char>::new();
+    ///
+    /// map.try_insert(Box::new([1, 2, 3]), 'a').unwrap();
+    /// assert_eq!(*map.get([1, 2, 3].as_ref()).unwrap(), 'a');
+    /// ```
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q> + AsBytes,
+        Q: AsBytes + ?Sized,
+    {
+        Some(self.get_key_value(key)?.1)
+    }
+
+    /// Returns the bytes of the value corresponding to the key, for values
+    /// which hold a byte slice like `Box<[u8]>` or `Vec<u8>`.
+    ///
+    /// The pointer and length of such values are stored inline in the leaf
+    /// node, so this only follows the leaf's pointer to the value bytes.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::TreeMap;
+    ///
+    /// let mut map = TreeMap::<Box<[u8]>, Box<[u8]>>::new();
+    ///
+    /// map.try_insert(Box::new([1, 2, 3]), Box::new([4, 5])).unwrap();
+    /// assert_eq!(map.get_bytes([1, 2, 3].as_ref()), Some([4, 5].as_ref()));
+    /// ```
+    pub fn get_bytes<Q>(&self, key: &Q) -> Option<&[u8]>
+    where
+        K: Borrow<Q> + AsBytes,
+        Q: AsBytes + ?Sized,
+        V: Borrow<[u8]>,
+    {
+        Some(self.get(key)?.borrow())
+    }
+
+    /// Returns the string of the value corresponding to the key, for values
+    /// which hold a string slice like `Box<str>` or `String`.
+    ///
+    /// The pointer and length of such values are stored inline in the leaf
+    /// node, so this only follows the leaf's pointer to the string bytes.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::TreeMap;
+    ///
+    /// let mut map = TreeMap::<Box<[u8]>, Box<str>>::new();
+    ///
+    /// map.try_insert(Box::new([1, 2, 3]), "abc".into()).unwrap();
+    /// assert_eq!(map.get_str([1, 2, 3].as_ref()), Some("abc"));
+    /// ```
+    pub fn get_str<Q>(&self, key: &Q) -> Option<&str>
+    where
+        K: Borrow<Q> + AsBytes,
+        Q: AsBytes + ?Sized,
+        V: Borrow<str>,
+    {
+        Some(self.get(key)?.borrow())
+    }
+
+    /// Returns the key-value pair corresponding to the supplied key.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::TreeMap;
+    ///
+    /// let mut map = TreeMap::<Box<[u8]>, char>::new();
+    ///
+    /// map.try_insert(Box::new([1, 2, 3]), 'a').unwrap();
+    /// assert_eq!(map.get_key_value([1, 2, 3].as_ref()).unwrap(), (&Box::from([1, 2, 3]), &'a'));
+    /// ```
+    pub fn get_key_value<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q> + AsBytes,
+        Q: AsBytes + ?Sized,
+    {
+        if let Some(root) = self.root {
+            // SAFETY: Since we have an immutable reference to the `TreeMap` object, that
+            // means there can only exist other immutable references aside from this one,
+            // and no mutable references. That means that no mutating operations can occur
+            // on the root node or any child of the root node.
+            let search_result = unsafe { search_unchecked(root, key)? };
+
+            // SAFETY: The lifetime chosen the value reference is bounded by the lifetime of
+            // the immutable reference to the `TreeMap`. The memory of the value will not be
+            // mutated since it is only owned by the `TreeMap` and there can only be other
+            // immutable references at this time (no mutable references to the `TreeMap`).
+            let (key, value) = unsafe { search_result.as_key_value_ref() };
+            Some((key, value))
+        } else {
+            None
+        }
+    }
+
+    /// Returns a reference to the value corresponding to the key, along with
+    /// counters describing the work performed by the search.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::TreeMap;
+    ///
+    /// let mut map = TreeMap::<Box<[u8]>, char>::new();
+    ///
+    /// map.try_insert(Box::new([1, 2, 3]), 'a').unwrap();
+    /// map.try_insert(Box::new([1, 2, 4]), 'b').unwrap();
+    ///
+    /// let (value, stats) = map.get_instrumented([1, 2, 3].as_ref());
+    /// assert_eq!(value, Some(&'a'));
+    /// assert_eq!(stats.node4_visited, 1);
+    /// assert_eq!(stats.leaves_visited, 1);
+    /// assert_eq!(stats.prefix_bytes_compared, 2);
+    /// ```
+    pub fn get_instrumented<Q>(&self, key: &Q) -> (Option<&V>, SearchStats)
+    where
+        K: Borrow<Q> + AsBytes,
+        Q: AsBytes + ?Sized,
+    {
+        if let Some(root) = self.root {
+            // SAFETY: Since we have an immutable reference to the `TreeMap` object, that
+            // means there can only exist other immutable references aside from this one,
+            // and no mutable references. That means that no mutating operations can occur
+            // on the root node or any child of the root node.
+            let (search_result, stats) = unsafe { search_instrumented_unchecked(root, key) };
+
+            let value = search_result.map(|leaf| {
+                // SAFETY: The lifetime chosen the value reference is bounded by the lifetime
+                // of the immutable reference to the `TreeMap`. The memory of the value will
+                // not be mutated since it is only owned by the `TreeMap` and there can only
+                // be other immutable references at this time (no mutable references to the
+                // `TreeMap`).
+                unsafe { leaf.as_value_ref() }
+            });
+            (value, stats)
+        } else {
+            (None, SearchStats::default())
+        }
+    }
+
+    /// Search for the given key, and describe where the search stopped if the
+    /// key is not in the map.
+    ///
+    /// On a miss, this reports how many bytes of the key matched, the node at
+    /// which the search diverged, and the nearest existing key. The nearest key
+    /// shares the longest possible prefix with the search key, and among those
+    /// keys it is the one closest in sorted order to the search key.
+    ///
+    /// The key may be any borrowed form of the map's key type, but the
+    /// ordering on the borrowed form *must* match the ordering on the key type.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::{map::SearchDiagnostics, TreeMap};
+    ///
+    /// let mut map = TreeMap::<Box<[u8]>, char>::new();
+    /// map.try_insert(Box::new(*b"apple"), 'a').unwrap();
+    /// map.try_insert(Box::new(*b"apply"), 'b').unwrap();
+    /// map.try_insert(Box::new(*b"banana"), 'c').unwrap();
+    ///
+    /// match map.search_with_diagnostics(b"appla".as_ref()) {
+    ///     SearchDiagnostics::Missing(miss) => {
+    ///         assert_eq!(miss.matched_bytes, 4);
+    ///         assert_eq!(miss.diverged_depth, 1);
+    ///         assert_eq!(miss.nearest_key.as_ref(), b"apple");
+    ///     },
+    ///     other => panic!("unexpected result {other:?}"),
+    /// }
+    ///
+    /// assert_eq!(
+    ///     map.search_with_diagnostics(b"apple".as_ref()),
+    ///     SearchDiagnostics::Found(&Box::from(*b"apple"), &'a')
+    /// );
+    /// ```
+    pub fn search_with_diagnostics<Q>(&self, key: &Q) -> SearchDiagnostics<'_, K, V>
+    where
+        K: Borrow<Q> + AsBytes,
+        Q: AsBytes + ?Sized,
+    {
+        let root = match self.root {
+            Some(root) => root,
+            None => return SearchDiagnostics::Empty,
+        };
+
+        // SAFETY: Since we have an immutable reference to the `TreeMap` object, that
+        // means there can only exist other immutable references aside from this one,
+        // and no mutable references. That means that no mutating operations can occur
+        // on the root node or any child of the root node.
+        match unsafe { search_with_diagnostics_unchecked(root, key) } {
+            Ok(leaf) => {
+                // SAFETY: The lifetime chosen for the references is bounded by the lifetime
+                // of the immutable reference to the `TreeMap`, which forbids any mutation
+                // of the leaf.
+                let (key, value) = unsafe { leaf.as_key_value_ref() };
+                SearchDiagnostics::Found(key, value)
+            },
+            Err(miss) => {
+                // SAFETY: The lifetime chosen for the references is bounded by the lifetime
+                // of the immutable reference to the `TreeMap`, which forbids any mutation
+                // of the leaf.
+                let (nearest_key, nearest_value) = unsafe { miss.nearest.as_key_value_ref() };
+                SearchDiagnostics::Missing(KeyMiss {
+                    matched_bytes: miss.matched_bytes,
+                    diverged_node_type: miss.diverged_at.node_type(),
+                    diverged_depth: miss.diverged_depth,
+                    nearest_key,
+                    nearest_value,
+                })
+            },
+        }
+    }
+
+    /// Returns a mutable reference to the value corresponding to the key.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::TreeMap;
+    ///
+    /// let mut map = TreeMap::<Box<[u8]>, char>::new();
+    ///
+    /// map.try_insert(Box::new([1, 2, 3]), 'a').unwrap();
+    /// assert_eq!(map[[1, 2, 3].as_ref()], 'a');
+    ///
+    /// *map.get_mut([1, 2, 3].as_ref()).unwrap() = 'b';
+    /// assert_eq!(map[[1, 2, 3].as_ref()], 'b');
+    /// ```
+    ///
+    /// While an element from the tree is mutably referenced, no other operation
+    /// on the tree can happen.
+    ///
+    /// ```rust,compile_fail
+    /// use blart::TreeMap;
+    ///
+    /// let mut map = TreeMap::<Box<[u8]>, char>::new();
+    ///
+    /// map.try_insert(Box::new([1, 2, 3]), 'a').unwrap();
+    ///
+    ///
+    /// let value = map.get_mut(&[1, 2, 3]).unwrap();
+    /// assert_eq!(*value, 'a');
+    ///
+    /// assert_eq!(*map[[1, 2, 3].as_ref()], 'a');
+    ///
+    /// *value = 'b';
+    /// drop(value);
+    /// ```
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q> + AsBytes,
+        Q: AsBytes + ?Sized,
+    {
+        if let Some(root) = self.root {
+            // SAFETY: Since we have a mutable reference to the `TreeMap` object, that
+            // means there cannot exist any other reference (mutable or immutable) to the
+            // same `TreeMap`. Which means that no other mutating operations could be
+            // happening during the `search_unchecked` call.
+            let search_result = unsafe { search_unchecked(root, key)? };
+
+            // SAFETY: The lifetime chosen the value reference is bounded by the lifetime of
+            // the mutable reference to the `TreeMap`. The value pointed to by the returned
+            // mutable reference will not be accessed (read or written) through any other
+            // pointer because of the existing mutable reference on the `TreeMap`.
+            let leaf_node_ref = unsafe { search_result.as_mut() };
+
+            Some(leaf_node_ref.value_mut())
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if the map contains a value for the specified key.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::TreeMap;
+    ///
+    /// let mut map = TreeMap::<Box<[u8]>, char>::new();
+    ///
+    /// map.try_insert(Box::new([1, 2, 3]), 'a').unwrap();
+    ///
+    /// assert!(map.contains_key([1, 2, 3].as_ref()));
+    /// ```
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q> + AsBytes,
+        Q: AsBytes + ?Sized,
+    {
+        // TODO(#18): Optimize this with a specific underlying method which just check
+        // for existing leaf, does not return it
+        self.get(key).is_some()
+    }
+
+    /// Returns a reference to the value corresponding to the key, for lookup
+    /// keys whose byte representation may fail to exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns the [`TryAsBytes::Error`] of the key if it has no byte
+    /// representation. Such a key cannot be in the map.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::TreeMap;
+    ///
+    /// let mut map = TreeMap::<Box<[u8]>, char>::new();
+    ///
+    /// map.try_insert(Box::new([1, 2, 3]), 'a').unwrap();
+    /// assert_eq!(map.try_get([1, 2, 3].as_ref()), Ok(Some(&'a')));
+    /// ```
+    pub fn try_get<Q>(&self, key: &Q) -> Result<Option<&V>, Q::Error>
+    where
+        K: Borrow<Q> + AsBytes,
+        Q: TryAsBytes + ?Sized,
+    {
+        let leaf_node_ptr = self.leaf_by_key_bytes(key.try_as_bytes()?);
+        // SAFETY: The shared reference to the `TreeMap` guarantees that no mutating
+        // operations happen while the returned reference is live.
+        Ok(leaf_node_ptr.map(|leaf_node_ptr| unsafe { leaf_node_ptr.as_value_ref() }))
+    }
+
+    /// Returns `true` if the map contains a value for the specified key, for
+    /// lookup keys whose byte representation may fail to exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns the [`TryAsBytes::Error`] of the key if it has no byte
+    /// representation.
+    pub fn try_contains_key<Q>(&self, key: &Q) -> Result<bool, Q::Error>
+    where
+        K: Borrow<Q> + AsBytes,
+        Q: TryAsBytes + ?Sized,
+    {
+        Ok(self.leaf_by_key_bytes(key.try_as_bytes()?).is_some())
+    }
+
+    /// Creates a raw immutable entry builder, which looks up entries by key
+    /// bytes that were computed by the caller.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::TreeMap;
+    ///
+    /// let mut map = TreeMap::<Box<[u8]>, char>::new();
+    ///
+    /// map.try_insert(Box::new([1, 2, 3]), 'a').unwrap();
+    ///
+    /// let key_bytes = [1, 2, 3];
+    /// assert_eq!(
+    ///     map.raw_entry().from_key_bytes(&key_bytes),
+    ///     Some((&Box::from([1, 2, 3]), &'a'))
+    /// );
+    /// assert_eq!(map.raw_entry().from_key_bytes(&[1, 2]), None);
+    /// ```
+    pub fn raw_entry(&self) -> RawEntryBuilder<'_, K, V, A> {
+        RawEntryBuilder { map: self }
+    }
+
+    /// Creates a raw mutable entry builder, which looks up entries by key
+    /// bytes that were computed by the caller.
+    ///
+    /// If no entry exists for the key bytes, the key itself only needs to be
+    /// constructed when inserting into the vacant entry.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::{map::RawEntryMut, TreeMap};
+    ///
+    /// let mut map = TreeMap::<Box<[u8]>, usize>::new();
+    ///
+    /// for word in ["apple", "banana", "apple"] {
+    ///     let mut key_bytes = word.as_bytes().to_vec();
+    ///     key_bytes.push(0);
+    ///
+    ///     match map.raw_entry_mut().from_key_bytes(&key_bytes) {
+    ///         RawEntryMut::Occupied(mut entry) => *entry.get_mut() += 1,
+    ///         RawEntryMut::Vacant(entry) => {
+    ///             entry.try_insert(key_bytes.clone().into(), 1).unwrap();
+    ///         },
+    ///     }
+    /// }
+    ///
+    /// assert_eq!(map.get(b"apple\0".as_ref()), Some(&2));
+    /// assert_eq!(map.get(b"banana\0".as_ref()), Some(&1));
+    /// ```
+    pub fn raw_entry_mut(&mut self) -> RawEntryBuilderMut<'_, K, V, A> {
+        RawEntryBuilderMut { map: self }
+    }
+
+    /// Gets the entry for the given key in the map, for in-place
+    /// manipulation.
+    ///
+    /// The tree is only searched once, and inserting into a vacant entry
+    /// starts from the point where the search stopped.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::TreeMap;
+    ///
+    /// let mut counts = TreeMap::<u8, usize>::new();
+    ///
+    /// for byte in b"hello world" {
+    ///     *counts.entry(*byte).or_insert(0) += 1;
+    /// }
+    ///
+    /// assert_eq!(counts[&b'l'], 3);
+    /// assert_eq!(counts[&b'o'], 2);
+    /// assert_eq!(counts.len(), 8);
+    /// ```
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V, A>
+    where
+        K: NoPrefixesBytes,
+    {
+        match self.try_entry(key) {
+            Ok(entry) => entry,
+            Err(_err) => unreachable!(
+                "This branch should be unreachable because of the safety contract of \
+                 `NoPrefixesBytes`"
+            ),
+        }
+    }
+
+    /// Gets the entry for the given key in the map, for in-place
+    /// manipulation.
+    ///
+    /// See [`TreeMap::entry`] for details.
+    ///
+    /// # Errors
+    ///  - If the map has an existing key, such that the given key is a prefix
+    ///    of the existing key or vice versa, then it returns an error.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::{map::Entry, TreeMap};
+    ///
+    /// let mut map = TreeMap::<Box<[u8]>, char>::new();
+    /// map.try_insert(Box::new([1, 2, 3]), 'a').unwrap();
+    ///
+    /// assert!(map.try_entry(Box::new([1, 2])).is_err());
+    /// assert!(matches!(
+    ///     map.try_entry(Box::new([1, 2, 3])).unwrap(),
+    ///     Entry::Occupied(_)
+    /// ));
+    ///
+    /// map.try_entry(Box::new([2])).unwrap().or_insert('b');
+    /// assert_eq!(map.get([2].as_ref()), Some(&'b'));
+    /// ```
+    pub fn try_entry(&mut self, key: K) -> Result<Entry<'_, K, V, A>, InsertPrefixError>
+    where
+        K: AsBytes,
+    {
+        Entry::search(self, key)
+    }
+
+    /// Inserts a key-value pair at an insert point found by
+    /// [`Entry::search`], and returns the leaf that holds it.
+    ///
+    /// The insert point must be `None` only if the map is empty.
+    fn insert_at_entry_point(
+        &mut self,
+        insert_point: Option<InsertSearchResult<K, V>>,
+        key: K,
+        value: V,
+    ) -> NodePtr<LeafNode<K, V>>
+    where
+        K: AsBytes,
+    {
+        let leaf_node_ptr = match (self.root, insert_point) {
+            (Some(root), Some(insert_point)) => {
+                // SAFETY: The insert point was found in this tree by the entry, which held
+                // the mutable reference to the `TreeMap` since then, so the tree has not
+                // been modified and there are no other accesses to any node in the tree.
+                let InsertResult {
+                    leaf_node_ptr,
+                    new_root,
+                    ..
+                } = unsafe {
+                    insert_at_entry_point_unchecked(root, insert_point, key, value, &self.alloc)
+                };
+                self.root = Some(new_root);
+                leaf_node_ptr
+            },
+            (None, None) => {
+                let leaf_node_ptr =
+                    NodePtr::allocate_node_ptr_in(LeafNode::new(key, value), &self.alloc);
+                self.root = Some(leaf_node_ptr.to_opaque());
+                leaf_node_ptr
+            },
+            _ => unreachable!("the insert point should only be missing for an empty map"),
+        };
+
+        self.num_entries = self
+            .num_entries
+            .checked_add(1)
+            .expect("should not overflow a usize");
+        leaf_node_ptr
+    }
+
+    /// Returns the leaf holding the key with the given bytes.
+    pub(crate) fn leaf_by_key_bytes(&self, key_bytes: &[u8]) -> Option<NodePtr<LeafNode<K, V>>>
+    where
+        K: AsBytes,
+    {
+        // SAFETY: The shared reference to the `TreeMap` guarantees that no mutating
+        // operations happen during the search.
+        unsafe { search_bytes_unchecked(self.root?, key_bytes) }
+    }
+
+    /// Returns an iterator over the entries whose key starts with `prefix`, in
+    /// ascending key order.
+    ///
+    /// The iterator descends to the subtree that holds every key starting with
+    /// `prefix` and then only walks that subtree.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::TreeMap;
+    ///
+    /// let mut map = TreeMap::<Box<[u8]>, u32>::new();
+    /// map.try_insert(Box::from(&b"foo/a"[..]), 1).unwrap();
+    /// map.try_insert(Box::from(&b"foo/b"[..]), 2).unwrap();
+    /// map.try_insert(Box::from(&b"fox"[..]), 3).unwrap();
+    ///
+    /// let values: Vec<_> = map.prefix_iter(b"foo/").map(|(_, value)| *value).collect();
+    /// assert_eq!(values, [1, 2]);
+    /// assert_eq!(map.prefix_iter(b"fo").count(), 3);
+    /// assert_eq!(map.prefix_iter(b"bar").next(), None);
+    /// ```
+    pub fn prefix_iter(&self, prefix: &[u8]) -> iterators::Prefix<'_, K, V>
+    where
+        K: AsBytes,
+    {
+        iterators::Prefix::new(self, prefix)
+    }
+
+    /// Returns an iterator over the entries whose key starts with `prefix`, in
+    /// ascending key order, with mutable references to the values.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::TreeMap;
+    ///
+    /// let mut map = TreeMap::<Box<[u8]>, u32>::new();
+    /// map.try_insert(Box::from(&b"foo/a"[..]), 1).unwrap();
+    /// map.try_insert(Box::from(&b"foo/b"[..]), 2).unwrap();
+    /// map.try_insert(Box::from(&b"fox"[..]), 3).unwrap();
+    ///
+    /// for (_, value) in map.prefix_iter_mut(b"foo/") {
+    ///     *value *= 10;
+    /// }
+    /// assert_eq!(map.values().copied().collect::<Vec<_>>(), [10, 20, 3]);
+    /// ```
+    pub fn prefix_iter_mut(&mut self, prefix: &[u8]) -> iterators::PrefixMut<'_, K, V>
+    where
+        K: AsBytes,
+    {
+        iterators::PrefixMut::new(self, prefix)
+    }
+
+    /// Returns an iterator over the entries whose key is at most
+    /// `max_edit_distance` single byte insertions, deletions, or substitutions
+    /// away from `key`, in ascending key order.
+    ///
+    /// The search walks the tree while computing the edit distance between
+    /// `key` and the key bytes on the path to each node, and skips every
+    /// subtree where that path is already too far from `key`.
     ///
     /// # Examples
     ///
     /// ```rust
     /// use blart::TreeMap;
     ///
-    /// let mut map = TreeMap::<Box<[u8]>, char>::new();
+    /// let mut map = TreeMap::<Box<[u8]>, u32>::new();
+    /// map.try_insert(Box::from(&b"book\0"[..]), 1).unwrap();
+    /// map.try_insert(Box::from(&b"books\0"[..]), 2).unwrap();
+    /// map.try_insert(Box::from(&b"cake\0"[..]), 3).unwrap();
     ///
-    /// map.try_insert(Box::new([1, 2, 3]), 'a').unwrap();
-    /// assert_eq!(*map.get([1, 2, 3].as_ref()).unwrap(), 'a');
+    /// let values: Vec<_> = map.fuzzy_search(b"boo\0", 1).map(|(_, value)| *value).collect();
+    /// assert_eq!(values, [1]);
+    /// assert_eq!(map.fuzzy_search(b"boo\0", 2).count(), 2);
+    /// assert_eq!(map.fuzzy_search(b"book\0", 0).next(), Some((&Box::from(&b"book\0"[..]), &1)));
     /// ```
-    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    pub fn fuzzy_search(&self, key: &[u8], max_edit_distance: usize) -> iterators::Fuzzy<'_, K, V>
     where
-        K: Borrow<Q> + AsBytes,
-        Q: AsBytes + ?Sized,
+        K: AsBytes,
     {
-        Some(self.get_key_value(key)?.1)
+        iterators::Fuzzy::new(self, key, max_edit_distance)
     }
 
-    /// Returns the key-value pair corresponding to the supplied key.
+    /// Returns the leaves of all the keys in the map which start with
+    /// `prefix`, in ascending key order.
+    fn prefix_leaves(&self, prefix: &[u8]) -> impl Iterator<Item = NodePtr<LeafNode<K, V>>> + '_
+    where
+        K: AsBytes,
+    {
+        let subtree = self.root.and_then(|root| {
+            // SAFETY: The map is borrowed for the lifetime of the iterator, so there are
+            // no concurrent mutations of the tree.
+            unsafe { search_prefix_unchecked(root, prefix) }
+        });
+
+        subtree.into_iter().flat_map(|(subtree, _)| {
+            // SAFETY: The map is borrowed for the lifetime of the iterator, so there
+            // are no mutations of the tree while the iterator is live.
+            unsafe { TreeIterator::new(subtree) }
+        })
+    }
+
+    /// Returns the distinct bytes that immediately follow `prefix` in the keys
+    /// of the map, and whether a key exactly equal to `prefix` exists.
+    ///
+    /// This is cheaper than iterating over every key that starts with
+    /// `prefix`, since only the children of the node at the end of the
+    /// prefix are examined.
     ///
     /// # Examples
     ///
@@ -167,112 +1070,148 @@ impl<K, V> TreeMap<K, V> {
     ///
     /// let mut map = TreeMap::<Box<[u8]>, char>::new();
     ///
-    /// map.try_insert(Box::new([1, 2, 3]), 'a').unwrap();
-    /// assert_eq!(map.get_key_value([1, 2, 3].as_ref()).unwrap(), (&Box::from([1, 2, 3]), &'a'));
+    /// map.try_insert(Box::from(*b"car\0"), 'a').unwrap();
+    /// map.try_insert(Box::from(*b"cat\0"), 'b').unwrap();
+    /// map.try_insert(Box::from(*b"dog\0"), 'c').unwrap();
+    ///
+    /// let branches = map.prefix_branches(b"ca");
+    /// assert_eq!(branches.next_bytes, b"rt");
+    /// assert!(!branches.terminal);
+    ///
+    /// let branches = map.prefix_branches(b"cat\0");
+    /// assert!(branches.next_bytes.is_empty());
+    /// assert!(branches.terminal);
+    ///
+    /// assert_eq!(map.prefix_branches(b"").next_bytes, b"cd");
+    /// assert!(map.prefix_branches(b"e").next_bytes.is_empty());
     /// ```
-    pub fn get_key_value<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    pub fn prefix_branches(&self, prefix: &[u8]) -> PrefixBranches
     where
-        K: Borrow<Q> + AsBytes,
-        Q: AsBytes + ?Sized,
+        K: AsBytes,
     {
-        if let Some(root) = self.root {
+        match self.root {
             // SAFETY: Since we have an immutable reference to the `TreeMap` object, that
             // means there can only exist other immutable references aside from this one,
             // and no mutable references. That means that no mutating operations can occur
             // on the root node or any child of the root node.
-            let search_result = unsafe { search_unchecked(root, key)? };
-
-            // SAFETY: The lifetime chosen the value reference is bounded by the lifetime of
-            // the immutable reference to the `TreeMap`. The memory of the value will not be
-            // mutated since it is only owned by the `TreeMap` and there can only be other
-            // immutable references at this time (no mutable references to the `TreeMap`).
-            let (key, value) = unsafe { search_result.as_key_value_ref() };
-            Some((key, value))
-        } else {
-            None
+            Some(root) => unsafe { search_prefix_branches_unchecked(root, prefix) },
+            None => PrefixBranches::default(),
         }
     }
 
-    /// Returns a mutable reference to the value corresponding to the key.
+    /// Compute the digests of the entries under every prefix of the map with
+    /// the hash function `H`.
+    ///
+    /// See [`ContentDigests`] for how the digests are defined.
     ///
     /// # Examples
     ///
     /// ```rust
-    /// use blart::TreeMap;
+    /// use blart::{merkle::ContentHasher, TreeMap};
     ///
-    /// let mut map = TreeMap::<Box<[u8]>, char>::new();
+    /// /// Sums the input bytes, which is only useful as an example.
+    /// struct Sum(u8);
     ///
-    /// map.try_insert(Box::new([1, 2, 3]), 'a').unwrap();
-    /// assert_eq!(map[[1, 2, 3].as_ref()], 'a');
+    /// impl ContentHasher for Sum {
+    ///     type Digest = [u8; 1];
     ///
-    /// *map.get_mut([1, 2, 3].as_ref()).unwrap() = 'b';
-    /// assert_eq!(map[[1, 2, 3].as_ref()], 'b');
-    /// ```
+    ///     fn new() -> Self {
+    ///         Sum(0)
+    ///     }
     ///
-    /// While an element from the tree is mutably referenced, no other operation
-    /// on the tree can happen.
+    ///     fn update(&mut self, bytes: &[u8]) {
+    ///         self.0 = bytes.iter().fold(self.0, |sum, byte| sum.wrapping_add(*byte));
+    ///     }
     ///
-    /// ```rust,compile_fail
-    /// use blart::TreeMap;
+    ///     fn finish(self) -> Self::Digest {
+    ///         [self.0]
+    ///     }
+    /// }
     ///
-    /// let mut map = TreeMap::<Box<[u8]>, char>::new();
+    /// let mut map = TreeMap::<[u8; 2], u8>::new();
+    /// map.insert([1, 2], 3);
     ///
-    /// map.try_insert(Box::new([1, 2, 3]), 'a').unwrap();
+    /// let digests = map.content_digests::<Sum>();
+    /// assert_eq!(digests.root_digest(), digests.prefix_digest(&[1]));
+    /// ```
+    pub fn content_digests<H>(&self) -> ContentDigests<'_, K, V, H>
+    where
+        K: AsBytes,
+        V: AsBytes,
+        H: ContentHasher,
+    {
+        ContentDigests::new(self)
+    }
+
+    /// Walk the tree and return statistics about its shape and memory usage,
+    /// like the number of nodes of each type, the bytes allocated for the
+    /// nodes, and the maximum depth.
     ///
+    /// # Examples
     ///
-    /// let value = map.get_mut(&[1, 2, 3]).unwrap();
-    /// assert_eq!(*value, 'a');
+    /// ```rust
+    /// use blart::TreeMap;
     ///
-    /// assert_eq!(*map[[1, 2, 3].as_ref()], 'a');
+    /// let mut map = TreeMap::<[u8; 2], u16>::new();
+    /// for idx in 0..20u16 {
+    ///     map.insert(idx.to_be_bytes(), idx);
+    /// }
     ///
-    /// *value = 'b';
-    /// drop(value);
+    /// let stats = map.stats();
+    /// assert_eq!(stats.leaf_count, 20);
+    /// assert_eq!(stats.node48_count, 1);
+    /// assert_eq!(stats.max_depth, 1);
+    /// assert_eq!(stats.total_prefix_bytes, 1);
+    /// assert!(stats.total_allocated_bytes() > 0);
     /// ```
-    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    pub fn stats(&self) -> TreeStats
     where
-        K: Borrow<Q> + AsBytes,
-        Q: AsBytes + ?Sized,
+        K: AsBytes,
     {
-        if let Some(root) = self.root {
-            // SAFETY: Since we have a mutable reference to the `TreeMap` object, that
-            // means there cannot exist any other reference (mutable or immutable) to the
-            // same `TreeMap`. Which means that no other mutating operations could be
-            // happening during the `search_unchecked` call.
-            let search_result = unsafe { search_unchecked(root, key)? };
-
-            // SAFETY: The lifetime chosen the value reference is bounded by the lifetime of
-            // the mutable reference to the `TreeMap`. The value pointed to by the returned
-            // mutable reference will not be accessed (read or written) through any other
-            // pointer because of the existing mutable reference on the `TreeMap`.
-            let leaf_node_ref = unsafe { search_result.as_mut() };
-
-            Some(leaf_node_ref.value_mut())
-        } else {
-            None
+        match self.root {
+            // SAFETY: The shared reference to the map forbids any mutation of the tree
+            // while the stats are collected.
+            Some(root) => unsafe { TreeStatsCollector::collect(root) },
+            None => TreeStats::default(),
         }
     }
 
-    /// Returns true if the map contains a value for the specified key.
+    /// Write the tree in the Graphviz DOT format to the given output, which is
+    /// useful to debug the structure of small trees.
+    ///
+    /// An empty map is written as an empty graph.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from writing to the output.
     ///
     /// # Examples
     ///
     /// ```rust
-    /// use blart::TreeMap;
+    /// use blart::{visitor::DotPrinterSettings, TreeMap};
     ///
-    /// let mut map = TreeMap::<Box<[u8]>, char>::new();
+    /// let mut map = TreeMap::<[u8; 2], char>::new();
+    /// map.insert([1, 2], 'a');
+    /// map.insert([1, 3], 'b');
     ///
-    /// map.try_insert(Box::new([1, 2, 3]), 'a').unwrap();
+    /// let mut dot = Vec::new();
+    /// map.write_dot(&mut dot, DotPrinterSettings::default()).unwrap();
     ///
-    /// assert!(map.contains_key([1, 2, 3].as_ref()));
+    /// let dot = String::from_utf8(dot).unwrap();
+    /// assert!(dot.starts_with("strict digraph G {"));
     /// ```
-    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    pub fn write_dot<W>(&self, mut output: W, settings: DotPrinterSettings) -> io::Result<()>
     where
-        K: Borrow<Q> + AsBytes,
-        Q: AsBytes + ?Sized,
+        K: Debug,
+        V: Debug,
+        W: io::Write,
     {
-        // TODO(#18): Optimize this with a specific underlying method which just check
-        // for existing leaf, does not return it
-        self.get(key).is_some()
+        match self.root {
+            // SAFETY: The shared reference to the map forbids any mutation of the tree
+            // while it is written.
+            Some(root) => unsafe { DotPrinter::print_tree(output, &root, settings) },
+            None => writeln!(output, "strict digraph G {{}}"),
+        }
     }
 
     /// Returns the first key-value pair in the map. The key in this pair is the
@@ -336,7 +1275,9 @@ impl<K, V> TreeMap<K, V> {
             let DeleteResult {
                 deleted_leaf,
                 new_root,
-            } = unsafe { delete_minimum_unchecked(root) };
+            } = unsafe {
+                delete_minimum_with_policy_unchecked_in(root, self.resize_policy, &self.alloc)
+            };
 
             self.root = new_root;
             self.num_entries -= 1;
@@ -409,7 +1350,9 @@ impl<K, V> TreeMap<K, V> {
             let DeleteResult {
                 deleted_leaf,
                 new_root,
-            } = unsafe { delete_maximum_unchecked(root) };
+            } = unsafe {
+                delete_maximum_with_policy_unchecked_in(root, self.resize_policy, &self.alloc)
+            };
 
             self.root = new_root;
             self.num_entries -= 1;
@@ -468,6 +1411,11 @@ impl<K, V> TreeMap<K, V> {
     ///  - If the map has an existing key, such that the new key is a prefix of
     ///    the existing key or vice versa, then it returns an error.
     ///
+    /// # Panics
+    ///
+    /// If the [`AsBytes`] implementation of the key panics, the map is left
+    /// unchanged and the given key and value are dropped.
+    ///
     /// # Examples
     ///
     /// ```rust
@@ -484,6 +1432,206 @@ impl<K, V> TreeMap<K, V> {
     /// assert_eq!(map.len(), 2);
     /// ```
     pub fn try_insert(&mut self, key: K, value: V) -> Result<Option<V>, InsertPrefixError>
+    where
+        K: AsBytes,
+    {
+        self.try_insert_recorded(key, value, &mut ())
+            .map(|(old_value, _)| old_value)
+    }
+
+    /// Inserts a key-value pair into the map, and returns the leaf that holds
+    /// it along with the previous value for the key.
+    ///
+    /// The leaf stays at the same address until the key is removed from the
+    /// map or the map is dropped.
+    pub(crate) fn try_insert_leaf(&mut self, key: K, value: V) -> InsertLeafResult<K, V>
+    where
+        K: AsBytes,
+    {
+        self.try_insert_recorded(key, value, &mut ())
+    }
+
+    /// Inserts a key-value pair into the map, along with counters describing
+    /// the work performed while searching for the insert point.
+    ///
+    /// See [`TreeMap::try_insert`] for details of the return value, panics
+    /// and errors.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::TreeMap;
+    ///
+    /// let mut map = TreeMap::<Box<[u8]>, char>::new();
+    ///
+    /// let (result, stats) = map.try_insert_instrumented(Box::new([1, 2, 3]), 'a');
+    /// assert!(result.unwrap().is_none());
+    /// // Inserting into an empty map does not search at all
+    /// assert_eq!(stats.nodes_visited(), 0);
+    ///
+    /// let (result, stats) = map.try_insert_instrumented(Box::new([1, 2, 4]), 'b');
+    /// assert!(result.unwrap().is_none());
+    /// assert_eq!(stats.leaves_visited, 1);
+    /// ```
+    pub fn try_insert_instrumented(
+        &mut self,
+        key: K,
+        value: V,
+    ) -> (Result<Option<V>, InsertPrefixError>, SearchStats)
+    where
+        K: AsBytes,
+    {
+        let mut stats = SearchStats::default();
+        let result = self
+            .try_insert_recorded(key, value, &mut stats)
+            .map(|(old_value, _)| old_value);
+
+        (result, stats)
+    }
+
+    /// Inserts all the given entries in order, where the keys are expected to
+    /// be sorted and greater than every key already in the map.
+    ///
+    /// This is meant for appending to a map, for example when the keys are
+    /// timestamps or sequence numbers. Each key that is greater than all the
+    /// keys in the map is attached to the rightmost path of the tree, which is
+    /// kept between inserts, so there is no full descent from the root per
+    /// key. A key which is not greater is inserted with [`TreeMap::try_insert`]
+    /// instead, replacing the value of an existing equal key, and the next
+    /// append finds the rightmost path again.
+    ///
+    /// # Errors
+    ///
+    /// Stops at the first key that is a prefix of an existing key, or has an
+    /// existing key as a prefix, and returns an [`InsertPrefixError`]. The
+    /// entries before that key stay in the map, and the entries after it are
+    /// not consumed.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::TreeMap;
+    ///
+    /// let mut map = TreeMap::<[u8; 8], u64>::new();
+    /// map.extend_from_sorted_append((0..1000u64).map(|n| (n.to_be_bytes(), n)))
+    ///     .unwrap();
+    /// map.extend_from_sorted_append((1000..2000u64).map(|n| (n.to_be_bytes(), n)))
+    ///     .unwrap();
+    ///
+    /// assert_eq!(map.len(), 2000);
+    /// assert_eq!(map.last_key_value(), Some((&1999u64.to_be_bytes(), &1999)));
+    /// ```
+    pub fn extend_from_sorted_append<I>(&mut self, entries: I) -> Result<(), InsertPrefixError>
+    where
+        K: AsBytes,
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut rightmost_path = None;
+        for (key, value) in entries {
+            let root = match self.root {
+                Some(root) => root,
+                None => {
+                    self.try_insert(key, value)?;
+                    continue;
+                },
+            };
+
+            // SAFETY: Since we have a mutable reference to the `TreeMap`, we are guaranteed
+            // that there are no other references (mutable or immutable) to this same
+            // object. The path is dropped whenever the tree is modified without it.
+            let path = rightmost_path.get_or_insert_with(|| unsafe { RightmostPath::new(root) });
+            // SAFETY: The tree is not modified while the key reference is live
+            if key.as_bytes() <= unsafe { path.maximum_key() }.as_bytes() {
+                rightmost_path = None;
+                self.try_insert(key, value)?;
+                continue;
+            }
+
+            // SAFETY: The path was found in this tree, which has only been modified through
+            // it since, and the key is greater than the maximum key. The tree is uniquely
+            // borrowed, see above.
+            let InsertResult { new_root, .. } =
+                unsafe { path.append_unchecked(root, key, value, &self.alloc)? };
+            self.root = Some(new_root);
+            self.num_entries = self
+                .num_entries
+                .checked_add(1)
+                .expect("should not overflow a usize");
+        }
+
+        Ok(())
+    }
+
+    /// Inserts a key-value pair into the map, searching for the insert point
+    /// from the given node of the tree instead of its root.
+    ///
+    /// Returns the previous value for the key and the leaf that holds the
+    /// entry, like [`TreeMap::try_insert_leaf`], along with the node at the
+    /// insert point. That node may be replaced by the insert, while the nodes
+    /// above it on the path of the key are unchanged.
+    ///
+    /// # Safety
+    ///
+    ///  - The `start_node` must be a node of this tree on the search path of
+    ///    `key`, after `start_depth` key bytes, and `start_parent` must be its
+    ///    parent and its key byte in the parent, or `None` if it is the root.
+    pub(crate) unsafe fn try_insert_from(
+        &mut self,
+        start_node: OpaqueNodePtr<K, V>,
+        start_depth: usize,
+        start_parent: Option<(OpaqueNodePtr<K, V>, u8)>,
+        key: K,
+        value: V,
+    ) -> InsertLeafFromResult<K, V>
+    where
+        K: AsBytes,
+    {
+        // PANIC SAFETY: The start node is a node of this tree, so the tree has a root
+        let root = self.root.expect("tree should have a root");
+        // SAFETY: Since we have a mutable reference to the `TreeMap`, we are guaranteed
+        // that there are no other references (mutable or immutable) to this same
+        // object. The start node is on the search path of the key, by the safety
+        // requirements of this function.
+        let (
+            InsertResult {
+                existing_leaf,
+                leaf_node_ptr,
+                new_root,
+            },
+            insert_point,
+        ) = unsafe {
+            insert_from_unchecked(
+                root,
+                start_node,
+                start_depth,
+                start_parent,
+                key,
+                value,
+                &self.alloc,
+            )?
+        };
+
+        self.root = Some(new_root);
+        if existing_leaf.is_none() {
+            self.num_entries = self
+                .num_entries
+                .checked_add(1)
+                .expect("should not overflow a usize");
+        }
+
+        Ok((
+            existing_leaf.map(|leaf| leaf.into_entry().1),
+            leaf_node_ptr,
+            insert_point,
+        ))
+    }
+
+    fn try_insert_recorded<R: SearchRecorder>(
+        &mut self,
+        key: K,
+        value: V,
+        recorder: &mut R,
+    ) -> InsertLeafResult<K, V>
     where
         K: AsBytes,
     {
@@ -494,8 +1642,9 @@ impl<K, V> TreeMap<K, V> {
             // other accesses to any node in the tree.
             let InsertResult {
                 existing_leaf,
+                leaf_node_ptr,
                 new_root,
-            } = unsafe { insert_unchecked(root, key, value)? };
+            } = unsafe { insert_recorded(root, key, value, recorder, &self.alloc)? };
 
             self.root = Some(new_root);
 
@@ -508,13 +1657,15 @@ impl<K, V> TreeMap<K, V> {
                     .expect("should not overflow a usize");
             }
 
-            Ok(existing_leaf.map(|leaf| leaf.into_entry().1))
+            Ok((existing_leaf.map(|leaf| leaf.into_entry().1), leaf_node_ptr))
         } else {
-            self.root = Some(NodePtr::allocate_node_ptr(LeafNode::new(key, value)).to_opaque());
+            let leaf_node_ptr =
+                NodePtr::allocate_node_ptr_in(LeafNode::new(key, value), &self.alloc);
+            self.root = Some(leaf_node_ptr.to_opaque());
 
             self.num_entries = 1;
 
-            Ok(None)
+            Ok((None, leaf_node_ptr))
         }
     }
 
@@ -533,37 +1684,214 @@ impl<K, V> TreeMap<K, V> {
     ///
     /// assert_eq!(map.remove_entry([2, 3, 4].as_ref()).unwrap(), (Box::from([2, 3, 4]), 'b'))
     /// ```
-    pub fn remove_entry<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    pub fn remove_entry<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q> + AsBytes,
+        Q: AsBytes + ?Sized,
+    {
+        if let Some(root) = self.root {
+            // SAFETY: Since we have a mutable reference to the `TreeMap`, we are guaranteed
+            // that there are no other references (mutable or immutable) to this same
+            // object. Meaning that our access to the root node is unique and there are no
+            // other accesses to any node in the tree.
+            let DeleteResult {
+                deleted_leaf,
+                new_root,
+            } = unsafe {
+                delete_with_policy_unchecked_in(root, key, self.resize_policy, &self.alloc)?
+            };
+
+            // The `delete_with_policy_unchecked` returns early if the key was not found, we
+            // are guaranteed at this point that the leaf has been removed from
+            // the tree.
+            self.num_entries = self
+                .num_entries
+                .checked_sub(1)
+                .expect("should not underflow, inc/dec should be paired");
+
+            self.root = new_root;
+            Some(deleted_leaf.into_entry())
+        } else {
+            None
+        }
+    }
+
+    /// Removes a key from the map, returning the value at the key if the key
+    /// was previously in the map.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::TreeMap;
+    ///
+    /// let mut map = TreeMap::<Box<[u8]>, char>::new();
+    ///
+    /// map.try_insert(Box::new([1, 2, 3]), 'a').unwrap();
+    /// map.try_insert(Box::new([2, 3, 4]), 'b').unwrap();
+    ///
+    /// assert_eq!(map.remove([2, 3, 4].as_ref()).unwrap(), 'b');
+    /// assert_eq!(map.remove([2, 3, 4].as_ref()), None);
+    /// ```
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q> + AsBytes,
+        Q: AsBytes + ?Sized,
+    {
+        self.remove_entry(key).map(|(_, v)| v)
+    }
+
+    /// Removes a key from the map, returning the value at the key if the key
+    /// was previously in the map, for keys whose byte representation may fail
+    /// to exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns the [`TryAsBytes::Error`] of the key if it has no byte
+    /// representation. The map is unchanged in that case.
+    pub fn try_remove<Q>(&mut self, key: &Q) -> Result<Option<V>, Q::Error>
+    where
+        K: Borrow<Q> + AsBytes,
+        Q: TryAsBytes + ?Sized,
+    {
+        let key_bytes = key.try_as_bytes()?;
+        Ok(self
+            .remove_entry_by_key_bytes(key_bytes)
+            .map(|(_, value)| value))
+    }
+
+    /// Moves the value stored with the `old` key to the `new` key, returning
+    /// the old key.
+    ///
+    /// The entry is removed from its old position and linked in at the new
+    /// one, reusing the existing leaf allocation. The value is neither
+    /// cloned nor dropped.
+    ///
+    /// # Errors
+    ///
+    ///  - Returns [`RenameKeyError::NotFound`] if the `old` key is not present
+    ///    in the map.
+    ///  - Returns [`RenameKeyError::DestinationExists`] if the `new` key is
+    ///    already present in the map, including when it is equal to `old`.
+    ///  - Returns [`RenameKeyError::Prefix`] if the `new` key is a prefix of
+    ///    another key in the map, or another key is a prefix of it.
+    ///
+    /// In all cases the map is left unchanged, and the `new` key is returned in
+    /// the error.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::{map::RenameKeyError, TreeMap};
+    ///
+    /// let mut map = TreeMap::<Box<[u8]>, String>::new();
+    /// map.try_insert(Box::new([1, 2, 3]), "a".to_string()).unwrap();
+    /// map.try_insert(Box::new([4, 5, 6]), "b".to_string()).unwrap();
+    ///
+    /// let old_key = map
+    ///     .rename_key([1, 2, 3].as_ref(), Box::new([7, 8, 9]))
+    ///     .unwrap();
+    /// assert_eq!(old_key.as_ref(), [1, 2, 3]);
+    /// assert_eq!(map.get([7, 8, 9].as_ref()).unwrap(), "a");
+    /// assert_eq!(map.get([1, 2, 3].as_ref()), None);
+    ///
+    /// assert!(matches!(
+    ///     map.rename_key([7, 8, 9].as_ref(), Box::new([4, 5, 6])),
+    ///     Err(RenameKeyError::DestinationExists(_))
+    /// ));
+    /// ```
+    pub fn rename_key<Q>(&mut self, old: &Q, new: K) -> Result<K, RenameKeyError<K>>
     where
         K: Borrow<Q> + AsBytes,
         Q: AsBytes + ?Sized,
     {
-        if let Some(root) = self.root {
-            // SAFETY: Since we have a mutable reference to the `TreeMap`, we are guaranteed
-            // that there are no other references (mutable or immutable) to this same
-            // object. Meaning that our access to the root node is unique and there are no
-            // other accesses to any node in the tree.
-            let DeleteResult {
-                deleted_leaf,
-                new_root,
-            } = unsafe { delete_unchecked(root, key)? };
+        let root = match self.root {
+            Some(root) => root,
+            None => return Err(RenameKeyError::NotFound(new)),
+        };
+        if self.leaf_by_key_bytes(new.as_bytes()).is_some() {
+            return Err(RenameKeyError::DestinationExists(new));
+        }
 
-            // The `delete_unchecked` returns early if the key was not found, we are
-            // guaranteed at this point that the leaf has been removed from the tree.
-            self.num_entries = self
-                .num_entries
-                .checked_sub(1)
-                .expect("should not underflow, inc/dec should be paired");
+        // SAFETY: Since we have a mutable reference to the `TreeMap`, we are guaranteed
+        // that there are no other references (mutable or immutable) to this same
+        // object. Meaning that our access to the root node is unique and there are no
+        // other accesses to any node in the tree.
+        let (remaining_root, leaf_node_ptr) = match unsafe {
+            unlink_bytes_with_policy_unchecked(
+                root,
+                old.as_bytes(),
+                self.resize_policy,
+                &self.alloc,
+            )
+        } {
+            Some(unlinked) => unlinked,
+            None => return Err(RenameKeyError::NotFound(new)),
+        };
 
-            self.root = new_root;
-            Some(deleted_leaf.into_entry())
-        } else {
-            None
+        // SAFETY: The leaf is no longer reachable from the tree, so this is the only
+        // reference to it.
+        let old_key = std::mem::replace(unsafe { leaf_node_ptr.as_mut() }.entry_mut().0, new);
+
+        let remaining_root = match remaining_root {
+            Some(remaining_root) => remaining_root,
+            None => {
+                // The renamed entry was the only one in the map
+                self.root = Some(leaf_node_ptr.to_opaque());
+                return Ok(old_key);
+            },
+        };
+
+        // SAFETY: The tree is uniquely borrowed, see above, and the leaf was just
+        // unlinked from it.
+        match unsafe { insert_unlinked_leaf_unchecked(remaining_root, leaf_node_ptr, &self.alloc) }
+        {
+            Ok(InsertResult { new_root, .. }) => {
+                self.root = Some(new_root);
+                Ok(old_key)
+            },
+            Err((err, leaf_node_ptr)) => {
+                // SAFETY: The insert returns the leaf unchanged and still unlinked
+                let new =
+                    std::mem::replace(unsafe { leaf_node_ptr.as_mut() }.entry_mut().0, old_key);
+
+                // SAFETY: The tree is uniquely borrowed, see above, and the leaf was just
+                // unlinked from it.
+                //
+                // PANIC SAFETY: The old key was in the tree before it was unlinked, so it
+                // does not conflict with any of the remaining keys.
+                let InsertResult { new_root, .. } = unsafe {
+                    insert_unlinked_leaf_unchecked(remaining_root, leaf_node_ptr, &self.alloc)
+                }
+                .unwrap_or_else(|_| unreachable!("old key should fit back in the tree"));
+                self.root = Some(new_root);
+
+                Err(RenameKeyError::Prefix(new, err))
+            },
         }
     }
 
-    /// Removes a key from the map, returning the value at the key if the key
-    /// was previously in the map.
+    /// Moves every entry whose key starts with `old_prefix` so that its key
+    /// starts with `new_prefix` instead, returning the number of entries moved.
+    ///
+    /// The subtree holding the entries is detached and grafted back in at the
+    /// new prefix as a whole, so the leaves and values are neither copied nor
+    /// dropped. The stored keys are rewritten eagerly when the move succeeds:
+    /// `make_key` is called with each old key and the bytes of its new key, and
+    /// must return a key with exactly those bytes.
+    ///
+    /// If `old_prefix` and `new_prefix` are equal, no entry is moved and
+    /// `make_key` is not called.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`InsertPrefixError`] if one of the remaining keys of the map
+    /// starts with `new_prefix`, or is a prefix of it. In that case the map is
+    /// left unchanged.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `make_key` returns a key whose bytes are not the ones it was
+    /// given. The map is unchanged if this happens.
     ///
     /// # Examples
     ///
@@ -571,67 +1899,222 @@ impl<K, V> TreeMap<K, V> {
     /// use blart::TreeMap;
     ///
     /// let mut map = TreeMap::<Box<[u8]>, char>::new();
-    ///
-    /// map.try_insert(Box::new([1, 2, 3]), 'a').unwrap();
-    /// map.try_insert(Box::new([2, 3, 4]), 'b').unwrap();
-    ///
-    /// assert_eq!(map.remove([2, 3, 4].as_ref()).unwrap(), 'b');
-    /// assert_eq!(map.remove([2, 3, 4].as_ref()), None);
+    /// map.try_insert(Box::from(&b"old/a"[..]), 'a').unwrap();
+    /// map.try_insert(Box::from(&b"old/b"[..]), 'b').unwrap();
+    /// map.try_insert(Box::from(&b"other"[..]), 'c').unwrap();
+    ///
+    /// let moved = map
+    ///     .rename_prefix(b"old/", b"new/", |_, new_key| Box::from(new_key))
+    ///     .unwrap();
+    /// assert_eq!(moved, 2);
+    /// assert_eq!(map.get(&b"new/a"[..]), Some(&'a'));
+    /// assert_eq!(map.get(&b"new/b"[..]), Some(&'b'));
+    /// assert_eq!(map.get(&b"old/a"[..]), None);
+    ///
+    /// // Moving the entries under a key which already exists fails
+    /// assert!(map
+    ///     .rename_prefix(b"new/", b"other/", |_, new_key| Box::from(new_key))
+    ///     .is_err());
+    /// assert_eq!(map.len(), 3);
     /// ```
-    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    pub fn rename_prefix<F>(
+        &mut self,
+        old_prefix: &[u8],
+        new_prefix: &[u8],
+        mut make_key: F,
+    ) -> Result<usize, InsertPrefixError>
     where
-        K: Borrow<Q> + AsBytes,
-        Q: AsBytes + ?Sized,
+        K: AsBytes,
+        F: FnMut(&K, &[u8]) -> K,
     {
-        self.remove_entry(key).map(|(_, v)| v)
+        if old_prefix == new_prefix {
+            return Ok(self.prefix_leaves(old_prefix).count());
+        }
+
+        // Build all the new keys before changing the tree, so that a panic in
+        // `make_key` leaves the map as it was.
+        let mut new_key_bytes = Vec::new();
+        let mut renamed = self
+            .prefix_leaves(old_prefix)
+            .map(|leaf_ptr| {
+                // SAFETY: The map is uniquely borrowed and not modified while the key
+                // reference is live.
+                let old_key = unsafe { leaf_ptr.as_key_ref() };
+                new_key_bytes.clear();
+                new_key_bytes.extend_from_slice(new_prefix);
+                new_key_bytes.extend_from_slice(&old_key.as_bytes()[old_prefix.len()..]);
+
+                let new_key = make_key(old_key, &new_key_bytes);
+                assert_eq!(
+                    new_key.as_bytes(),
+                    new_key_bytes.as_slice(),
+                    "the renamed key should have the bytes it was built from"
+                );
+                (leaf_ptr, new_key)
+            })
+            .collect::<Vec<_>>();
+
+        let root = match self.root {
+            Some(root) if !renamed.is_empty() => root,
+            _ => return Ok(0),
+        };
+
+        // SAFETY: Since we have a mutable reference to the `TreeMap`, we are guaranteed
+        // that there are no other references (mutable or immutable) to this same
+        // object. Meaning that our access to the root node is unique and there are no
+        // other accesses to any node in the tree.
+        //
+        // PANIC SAFETY: There are keys starting with `old_prefix`, so there is a
+        // subtree to unlink.
+        let (remaining_root, subtree) = unsafe {
+            unlink_prefix_with_policy_unchecked(root, old_prefix, self.resize_policy, &self.alloc)
+        }
+        .expect("subtree should exist for a prefix with keys");
+
+        let swap_keys = |renamed: &mut [(NodePtr<LeafNode<K, V>>, K)]| {
+            for (leaf_ptr, key) in renamed {
+                // SAFETY: The leaves of the subtree are only reachable through it, and
+                // no other reference to them is live.
+                std::mem::swap(unsafe { leaf_ptr.as_mut() }.entry_mut().0, key);
+            }
+        };
+        swap_keys(&mut renamed);
+
+        let remaining_root = match remaining_root {
+            Some(remaining_root) => remaining_root,
+            None => {
+                // The subtree held the whole map
+                //
+                // SAFETY: The subtree is not linked into any tree, so this is the only
+                // access to its root.
+                if let Some(header) = unsafe { subtree.header_mut() } {
+                    header.prepend_prefix(new_prefix);
+                }
+                self.root = Some(subtree);
+                return Ok(renamed.len());
+            },
+        };
+
+        // SAFETY: The tree is uniquely borrowed, see above, and the subtree was
+        // just unlinked from it.
+        match unsafe { graft_subtree_unchecked(remaining_root, new_prefix, subtree, &self.alloc) } {
+            Ok(new_root) => {
+                self.root = Some(new_root);
+                Ok(renamed.len())
+            },
+            Err(err) => {
+                swap_keys(&mut renamed);
+
+                // SAFETY: The tree is uniquely borrowed, see above, and the subtree
+                // was just unlinked from it.
+                //
+                // PANIC SAFETY: The subtree was at `old_prefix` before it was
+                // unlinked, so it does not conflict with any of the remaining keys.
+                let new_root = unsafe {
+                    graft_subtree_unchecked(remaining_root, old_prefix, subtree, &self.alloc)
+                }
+                .unwrap_or_else(|_| unreachable!("subtree should fit back in the tree"));
+                self.root = Some(new_root);
+
+                Err(err)
+            },
+        }
+    }
+
+    /// Removes the key with the given bytes from the map, returning the stored
+    /// key and value if the key was previously in the map.
+    pub(crate) fn remove_entry_by_key_bytes(&mut self, key_bytes: &[u8]) -> Option<(K, V)>
+    where
+        K: AsBytes,
+    {
+        let root = self.root?;
+        // SAFETY: Since we have a mutable reference to the `TreeMap`, we are guaranteed
+        // that there are no other references (mutable or immutable) to this same
+        // object. Meaning that our access to the root node is unique and there are no
+        // other accesses to any node in the tree.
+        let DeleteResult {
+            deleted_leaf,
+            new_root,
+        } = unsafe {
+            delete_bytes_with_policy_unchecked(root, key_bytes, self.resize_policy, &self.alloc)?
+        };
+
+        self.num_entries = self
+            .num_entries
+            .checked_sub(1)
+            .expect("should not underflow, inc/dec should be paired");
+
+        self.root = new_root;
+        Some(deleted_leaf.into_entry())
+    }
+
+    /// Removes the given leaf from the map, where the path to it is already
+    /// known, returning the stored key and value.
+    ///
+    /// # Safety
+    ///
+    ///  - The leaf must be in this tree, and the parent and grandparent must be
+    ///    as described in [`delete_found_leaf_with_policy_unchecked`].
+    pub(crate) unsafe fn remove_found_leaf(
+        &mut self,
+        leaf_node_ptr: NodePtr<LeafNode<K, V>>,
+        parent_node_ptr: Option<(u8, OpaqueNodePtr<K, V>)>,
+        grandparent_node_ptr: Option<(u8, OpaqueNodePtr<K, V>)>,
+    ) -> (K, V) {
+        // PANIC SAFETY: The leaf is in this tree, so the tree has a root
+        let root = self.root.expect("tree should have a root");
+        // SAFETY: Since we have a mutable reference to the `TreeMap`, we are guaranteed
+        // that there are no other references (mutable or immutable) to this same
+        // object. The path to the leaf is covered by the safety requirements of this
+        // function.
+        let DeleteResult {
+            deleted_leaf,
+            new_root,
+        } = unsafe {
+            delete_found_leaf_with_policy_unchecked(
+                root,
+                leaf_node_ptr,
+                parent_node_ptr,
+                grandparent_node_ptr,
+                self.resize_policy,
+                &self.alloc,
+            )
+        };
+
+        self.num_entries = self
+            .num_entries
+            .checked_sub(1)
+            .expect("should not underflow, inc/dec should be paired");
+
+        self.root = new_root;
+        deleted_leaf.into_entry()
     }
 
     /// Retains only the elements specified by the predicate.
     ///
     /// In other words, remove all pairs (k, v) for which f(&k, &mut v) returns
     /// false. The elements are visited in ascending key order.
-    #[allow(dead_code)]
-    pub(crate) fn retain<F>(&mut self, f: F)
+    ///
+    /// The tree is walked once, removing the rejected elements and shrinking
+    /// the inner nodes in place. If the closure panics, the elements which
+    /// were already rejected are removed and all other elements are kept.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::TreeMap;
+    ///
+    /// let mut map: TreeMap<u8, u8> = (0..8).map(|x| (x, x * 10)).collect();
+    /// // Keep only the elements with even-numbered keys.
+    /// map.retain(|&k, _| k % 2 == 0);
+    /// assert!(map.into_iter().eq(vec![(0, 0), (2, 20), (4, 40), (6, 60)]));
+    /// ```
+    pub fn retain<F>(&mut self, mut f: F)
     where
         F: FnMut(&K, &mut V) -> bool,
     {
-        self.drain_filter(f).for_each(|_| ());
-    }
-
-    /// Moves all elements from other into self, leaving other empty.
-    //
-    // # Examples
-    //
-    // ```rust,should_panic
-    // use blart::TreeMap;
-    //
-    // let mut a = TreeMap::<u128, _>::new();
-    // a.try_insert(1, "a").unwrap();
-    // a.try_insert(2, "b").unwrap();
-    // a.try_insert(3, "c").unwrap(); // Note: Key (3) also present in b.
-    //
-    // let mut b = TreeMap::<u128, _>::new();
-    // b.try_insert(3, "d").unwrap(); // Note: Key (3) also present in a.
-    // b.try_insert(4, "e").unwrap();
-    // b.try_insert(5, "f").unwrap();
-    //
-    // a.append(&mut b);
-    //
-    // assert_eq!(a.len(), 5);
-    // assert_eq!(b.len(), 0);
-    //
-    // assert_eq!(a[&1], "a");
-    // assert_eq!(a[&2], "b");
-    // assert_eq!(a[&3], "d"); // Note: "c" has been overwritten.
-    // assert_eq!(a[&4], "e");
-    // assert_eq!(a[&5], "f");
-    // ```
-    #[allow(dead_code)]
-    pub(crate) fn append(&mut self, other: &mut TreeMap<K, V>)
-    where
-        K: NoPrefixesBytes,
-    {
-        self.extend(other.drain_filter(|_, _| true))
+        self.drain_filter(|key, value| !f(key, value))
+            .for_each(drop);
     }
 
     /// Constructs a double-ended iterator over a sub-range of elements in the
@@ -642,123 +2125,235 @@ impl<K, V> TreeMap<K, V> {
     /// (exclusive). The range may also be entered as `(Bound<T>, Bound<T>)`, so
     /// for example `range((Excluded(4), Included(10)))` will yield a
     /// left-exclusive, right-inclusive range from 4 to 10.
-    //
-    // # Examples
-    //
-    // ```rust,should_panic
-    // use blart::TreeMap;
-    // use std::ops::Bound::Included;
-    //
-    // let mut map = TreeMap::<u8, _>::new();
-    // map.try_insert(3, "a").unwrap();
-    // map.try_insert(5, "b").unwrap();
-    // map.try_insert(8, "c").unwrap();
-    //
-    // for (key, &value) in map.range((Included(&4), Included(&8))) {
-    //     println!("{key:?}: {value}");
-    // }
-    // assert_eq!(map.range(&4..).next(), Some((&5, &"b")));
-    // ```
-    #[allow(dead_code)]
-    pub(crate) fn range<Q, R>(&self, _range: R) -> iterators::Range<K, V>
+    ///
+    /// The iterator seeks to each end of the range using the compressed paths
+    /// of the tree, so the cost of iterating depends on the number of entries
+    /// in the range and not on the size of the map.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::TreeMap;
+    /// use std::ops::Bound::Included;
+    ///
+    /// let mut map = TreeMap::<u8, _>::new();
+    /// map.try_insert(3, "a").unwrap();
+    /// map.try_insert(5, "b").unwrap();
+    /// map.try_insert(8, "c").unwrap();
+    ///
+    /// for (key, &value) in map.range((Included(&4), Included(&8))) {
+    ///     println!("{key:?}: {value}");
+    /// }
+    /// assert_eq!(map.range(&4..).next(), Some((&5, &"b")));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if range `start > end`. Panics if range `start == end` and both
+    /// bounds are `Excluded`. The bounds are compared by their key bytes.
+    pub fn range<Q, R>(&self, range: R) -> iterators::Range<'_, K, V>
     where
         Q: AsBytes + ?Sized,
         K: Borrow<Q> + AsBytes,
         R: RangeBounds<Q>,
     {
-        todo!()
+        iterators::Range::new(self, range)
     }
 
     /// Constructs a mutable double-ended iterator over a sub-range of elements
     /// in the map.
     ///
     /// The simplest way is to use the range syntax `min..max`, thus
-    /// `range+_mut(min..max)` will yield elements from min (inclusive) to max
+    /// `range_mut(min..max)` will yield elements from min (inclusive) to max
     /// (exclusive). The range may also be entered as `(Bound<T>, Bound<T>)`, so
     /// for example `range_mut((Excluded(4), Included(10)))` will yield a
     /// left-exclusive, right-inclusive range from 4 to 10.
-    //
-    // # Examples
-    //
-    // ```rust,should_panic
-    // use blart::TreeMap;
-    //
-    // let mut map: TreeMap<_, i32> = TreeMap::new();
-    //
-    // for (key, value) in [("Alice", 0), ("Bob", 0), ("Carol", 0), ("Cheryl", 0)] {
-    //     let _ = map.try_insert(key, value).unwrap();
-    // }
-    //
-    // for (name, balance) in map.range_mut("B".."Cheryl") {
-    //     *balance += 100;
-    //
-    //     if name.starts_with('C') {
-    //         *balance *= 2;
-    //     }
-    // }
-    //
-    // for (name, balance) in &map {
-    //     println!("{name} => {balance}");
-    // }
-    //
-    // assert_eq!(map["Alice"], 0);
-    // assert_eq!(map["Bob"], 100);
-    // assert_eq!(map["Carol"], 200);
-    // assert_eq!(map["Cheryl"], 200);
-    // ```
-    #[allow(dead_code)]
-    pub(crate) fn range_mut<Q, R>(&mut self, _range: R) -> iterators::RangeMut<K, V>
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::TreeMap;
+    ///
+    /// let mut map: TreeMap<_, i32> = TreeMap::new();
+    ///
+    /// for (key, value) in [("Alice", 0), ("Bob", 0), ("Carol", 0), ("Cheryl", 0)] {
+    ///     let _ = map.try_insert(key, value).unwrap();
+    /// }
+    ///
+    /// for (name, balance) in map.range_mut("B".."Cheryl") {
+    ///     *balance += 100;
+    ///
+    ///     if name.starts_with('C') {
+    ///         *balance *= 2;
+    ///     }
+    /// }
+    ///
+    /// for (name, balance) in &map {
+    ///     println!("{name} => {balance}");
+    /// }
+    ///
+    /// assert_eq!(map["Alice"], 0);
+    /// assert_eq!(map["Bob"], 100);
+    /// assert_eq!(map["Carol"], 200);
+    /// assert_eq!(map["Cheryl"], 0);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if range `start > end`. Panics if range `start == end` and both
+    /// bounds are `Excluded`. The bounds are compared by their key bytes.
+    pub fn range_mut<Q, R>(&mut self, range: R) -> iterators::RangeMut<'_, K, V>
     where
         Q: AsBytes + ?Sized,
         K: Borrow<Q> + AsBytes,
         R: RangeBounds<Q>,
     {
-        todo!()
+        iterators::RangeMut::new(self, range)
+    }
+
+    /// Moves all elements from other into self, leaving other empty.
+    ///
+    /// The trees are merged node by node instead of inserting the entries of
+    /// `other` one at a time: a subtree which only holds keys missing from
+    /// `self` is moved over as it is, and only the inner nodes on paths shared
+    /// by both trees are rebuilt. If a key is present in both maps, the value
+    /// from `other` is kept.
+    ///
+    /// The nodes of `other` are only moved over if the allocator of `self` can
+    /// deallocate them, see [`InterchangeableAllocator`]. Otherwise the entries
+    /// of `other` are moved out of their leaves and inserted one at a time.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::TreeMap;
+    ///
+    /// let mut a = TreeMap::<u128, _>::new();
+    /// a.try_insert(1, "a").unwrap();
+    /// a.try_insert(2, "b").unwrap();
+    /// a.try_insert(3, "c").unwrap(); // Note: Key (3) also present in b.
+    ///
+    /// let mut b = TreeMap::<u128, _>::new();
+    /// b.try_insert(3, "d").unwrap(); // Note: Key (3) also present in a.
+    /// b.try_insert(4, "e").unwrap();
+    /// b.try_insert(5, "f").unwrap();
+    ///
+    /// a.append(&mut b);
+    ///
+    /// assert_eq!(a.len(), 5);
+    /// assert_eq!(b.len(), 0);
+    ///
+    /// assert_eq!(a[&1], "a");
+    /// assert_eq!(a[&2], "b");
+    /// assert_eq!(a[&3], "d"); // Note: "c" has been overwritten.
+    /// assert_eq!(a[&4], "e");
+    /// assert_eq!(a[&5], "f");
+    /// ```
+    pub fn append(&mut self, other: &mut TreeMap<K, V, A>)
+    where
+        K: NoPrefixesBytes,
+        A: InterchangeableAllocator,
+    {
+        let Some(other_root) = other.root.take() else {
+            return;
+        };
+        let other_num_entries = mem::take(&mut other.num_entries);
+
+        if !self.alloc.is_interchangeable_with(&other.alloc) {
+            let mut entries = TreeMap::with_resize_policy_in(other.resize_policy, &other.alloc);
+            entries.root = Some(other_root);
+            entries.num_entries = other_num_entries;
+            // The leaves of `other` are deallocated by its own allocator as the entries are
+            // moved out of them
+            for (key, value) in entries {
+                self.insert(key, value);
+            }
+            return;
+        }
+
+        let Some(root) = self.root.take() else {
+            self.root = Some(other_root);
+            self.num_entries = other_num_entries;
+            return;
+        };
+        let num_entries = mem::take(&mut self.num_entries);
+
+        // SAFETY: The roots were taken out of two different maps, so they are unique
+        // pointers to their trees, and a panic during the merge leaks the nodes instead
+        // of leaving them reachable. The allocator of this map can deallocate the nodes
+        // of `other`, since the allocators are interchangeable.
+        let MergeResult {
+            new_root,
+            replaced_leaves,
+        } = unsafe { merge_trees_in(root, other_root, &self.alloc) };
+
+        self.root = Some(new_root);
+        self.num_entries = num_entries + other_num_entries - replaced_leaves.len();
+        // The replaced values are only dropped once the map is consistent again
+        drop(replaced_leaves);
     }
 
     /// Splits the collection into two at the given key. Returns everything
     /// after the given key, including the key.
-    //
-    // # Examples
-    //
-    // ```rust,should_panic
-    // use blart::TreeMap;
-    //
-    // let mut a = TreeMap::new();
-    // a.try_insert(Box::from([1]), "a").unwrap();
-    // a.try_insert(Box::from([2]), "b").unwrap();
-    // a.try_insert(Box::from([3]), "c").unwrap();
-    // a.try_insert(Box::from([17]), "d").unwrap();
-    // a.try_insert(Box::from([41]), "e").unwrap();
-    //
-    // let b = a.split_off([3].as_ref());
-    //
-    // assert_eq!(a.len(), 2);
-    // assert_eq!(b.len(), 3);
-    //
-    // assert_eq!(a[[1].as_ref()], "a");
-    // assert_eq!(a[[2].as_ref()], "b");
-    //
-    // assert_eq!(b[[3].as_ref()], "c");
-    // assert_eq!(b[[17].as_ref()], "d");
-    // assert_eq!(b[[41].as_ref()], "e");
-    // ```
-    #[allow(dead_code)]
-    pub(crate) fn split_off<Q>(&mut self, split_key: &Q) -> TreeMap<K, V>
+    ///
+    /// The nodes of the tree are moved into the two maps instead of removing
+    /// and reinserting entries, only the inner nodes on the path of the split
+    /// key are rebuilt. Counting the entries of the returned map still takes
+    /// time linear in its length.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::TreeMap;
+    ///
+    /// let mut a = TreeMap::<Box<[u8]>, _>::new();
+    /// a.try_insert(Box::from([1]), "a").unwrap();
+    /// a.try_insert(Box::from([2]), "b").unwrap();
+    /// a.try_insert(Box::from([3]), "c").unwrap();
+    /// a.try_insert(Box::from([17]), "d").unwrap();
+    /// a.try_insert(Box::from([41]), "e").unwrap();
+    ///
+    /// let b = a.split_off([3].as_ref());
+    ///
+    /// assert_eq!(a.len(), 2);
+    /// assert_eq!(b.len(), 3);
+    ///
+    /// assert_eq!(a[[1].as_ref()], "a");
+    /// assert_eq!(a[[2].as_ref()], "b");
+    ///
+    /// assert_eq!(b[[3].as_ref()], "c");
+    /// assert_eq!(b[[17].as_ref()], "d");
+    /// assert_eq!(b[[41].as_ref()], "e");
+    /// ```
+    pub fn split_off<Q>(&mut self, split_key: &Q) -> TreeMap<K, V, A>
     where
         K: Borrow<Q> + AsBytes,
         Q: AsBytes + ?Sized,
+        A: Clone,
     {
-        let mut new_tree = TreeMap::new();
-
-        for (key, value) in
-            self.drain_filter(|key, _| split_key.as_bytes() <= key.borrow().as_bytes())
-        {
-            // PANIC SAFETY: This will not panic because the property of any existing tree
-            // containing no keys that are prefixes of any other key holds when the tree is
-            // split into any portion.
-            let _ = new_tree.try_insert(key, value).unwrap();
-        }
+        let mut new_tree = TreeMap::with_resize_policy_in(self.resize_policy, self.alloc.clone());
+        let Some(root) = self.root.take() else {
+            return new_tree;
+        };
+        let num_entries = mem::take(&mut self.num_entries);
+
+        // SAFETY: The root was taken out of the map, so it is a unique pointer to the
+        // tree, and the nodes were allocated by the allocator of this map.
+        let SplitResult {
+            lower_root,
+            upper_root,
+        } = unsafe { split_tree_in(root, split_key.as_bytes(), &self.alloc) };
+
+        // SAFETY: The upper tree is not modified while the iterator is live
+        let upper_num_entries =
+            upper_root.map_or(0, |root| unsafe { TreeIterator::new(root) }.count());
+
+        self.root = lower_root;
+        self.num_entries = num_entries - upper_num_entries;
+        // The new tree shares a clone of the allocator, which can deallocate the nodes
+        // of the upper tree.
+        new_tree.root = upper_root;
+        new_tree.num_entries = upper_num_entries;
 
         new_tree
     }
@@ -781,25 +2376,30 @@ impl<K, V> TreeMap<K, V> {
     ///
     /// It is unspecified how many more elements will be subjected to the
     /// closure if a panic occurs in the closure, or a panic occurs while
-    /// dropping an element, or if the DrainFilter value is leaked.
-    //
-    // # Examples
-    //
-    // ```rust,should_panic
-    // use blart::TreeMap;
-    //
-    // let mut map: TreeMap<u8, u8> = (0..8).map(|x| (x, x)).collect();
-    // let evens: TreeMap<_, _> = map.drain_filter(|k, _v| k % 2 == 0).collect();
-    // let odds = map;
-    // assert_eq!(evens.keys().copied().collect::<Vec<_>>(), [0, 2, 4, 6]);
-    // assert_eq!(odds.keys().copied().collect::<Vec<_>>(), [1, 3, 5, 7]);
-    // ```
-    #[allow(dead_code)]
-    pub(crate) fn drain_filter<F>(&mut self, _pred: F) -> iterators::DrainFilter<K, V>
+    /// dropping an element, or if the DrainFilter value is leaked. The map
+    /// is empty while the DrainFilter value is leaked.
+    ///
+    /// Like [`retain`], the tree is walked once, and the inner nodes are shrunk
+    /// in place once all of their children were visited.
+    ///
+    /// [`retain`]: TreeMap::retain
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::TreeMap;
+    ///
+    /// let mut map: TreeMap<u8, u8> = (0..8).map(|x| (x, x)).collect();
+    /// let evens: TreeMap<_, _> = map.drain_filter(|k, _v| k % 2 == 0).collect();
+    /// let odds = map;
+    /// assert_eq!(evens.keys().copied().collect::<Vec<_>>(), [0, 2, 4, 6]);
+    /// assert_eq!(odds.keys().copied().collect::<Vec<_>>(), [1, 3, 5, 7]);
+    /// ```
+    pub fn drain_filter<F>(&mut self, pred: F) -> DrainFilter<'_, K, V, F, A>
     where
         F: FnMut(&K, &mut V) -> bool,
     {
-        todo!()
+        DrainFilter::new(self, pred)
     }
 
     /// Creates a consuming iterator visiting all the keys, in sorted order. The
@@ -823,7 +2423,7 @@ impl<K, V> TreeMap<K, V> {
     /// assert_eq!(iter.next().unwrap(), 4);
     /// assert_eq!(iter.next(), None);
     /// ```
-    pub fn into_keys(self) -> iterators::IntoKeys<K, V> {
+    pub fn into_keys(self) -> iterators::IntoKeys<K, V, A> {
         iterators::IntoKeys::new(self)
     }
 
@@ -849,10 +2449,124 @@ impl<K, V> TreeMap<K, V> {
     /// assert_eq!(iter.next().unwrap(), 'z');
     /// assert_eq!(iter.next(), None);
     /// ```
-    pub fn into_values(self) -> iterators::IntoValues<K, V> {
+    pub fn into_values(self) -> iterators::IntoValues<K, V, A> {
         iterators::IntoValues::new(self)
     }
 
+    /// Consumes the map, returning a [`Vec`] of all the entries in sorted order
+    /// by key.
+    ///
+    /// The returned vector is allocated once with exactly enough capacity for
+    /// all the entries, and the nodes of the tree are deallocated as the
+    /// entries are moved out, so the tree and the vector are not both fully
+    /// resident at the same time.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::TreeMap;
+    ///
+    /// let map: TreeMap<_, char> = ['d', 'c', 'b', 'a', 'z'].into_iter()
+    ///     .enumerate()
+    ///     .collect();
+    ///
+    /// let entries = map.into_sorted_vec();
+    ///
+    /// assert_eq!(entries, [(0, 'd'), (1, 'c'), (2, 'b'), (3, 'a'), (4, 'z')]);
+    /// ```
+    pub fn into_sorted_vec(self) -> Vec<(K, V)> {
+        fn drain_inner_node<K, V, N, A>(
+            stack: &mut Vec<OpaqueNodePtr<K, V>>,
+            inner_ptr: NodePtr<N>,
+            alloc: &A,
+        ) where
+            N: InnerNode<Key = K, Value = V>,
+            A: Allocator,
+        {
+            {
+                // SAFETY: The scope of this reference is bounded and we enforce that no
+                // mutation of the reference memory takes place within the lifetime. The
+                // deallocation of the node happens outside of this block, after the
+                // lifetime ends.
+                let inner_node = unsafe { inner_ptr.as_ref() };
+
+                // SAFETY: This iterator only lives for this block, a subset of the shared
+                // lifetime of the `inner_node` variable. The tree is owned by the
+                // `into_sorted_vec` call, so no other mutation of this node can happen.
+                let iter = unsafe { inner_node.iter() };
+                // The children are pushed in reverse order so that the smallest child is
+                // on the top of the stack, which keeps the output in key order.
+                stack.extend(iter.rev().map(|(_, child)| child));
+            }
+
+            // SAFETY: Every node is reached exactly once from its parent, and the map is
+            // consumed so no other reference to the node can exist.
+            drop(unsafe { NodePtr::deallocate_node_ptr_in(inner_ptr, alloc) });
+        }
+
+        let mut entries = Vec::with_capacity(self.num_entries);
+        let (root, alloc) = self.into_raw_with_allocator();
+        let root = match root {
+            Some(root) => root,
+            None => return entries,
+        };
+
+        let mut stack = vec![root];
+        while let Some(next_node_ptr) = stack.pop() {
+            match next_node_ptr.to_node_ptr() {
+                ConcreteNodePtr::Node4(inner_ptr) => {
+                    drain_inner_node(&mut stack, inner_ptr, &alloc)
+                },
+                ConcreteNodePtr::Node16(inner_ptr) => {
+                    drain_inner_node(&mut stack, inner_ptr, &alloc)
+                },
+                ConcreteNodePtr::Node48(inner_ptr) => {
+                    drain_inner_node(&mut stack, inner_ptr, &alloc)
+                },
+                ConcreteNodePtr::Node256(inner_ptr) => {
+                    drain_inner_node(&mut stack, inner_ptr, &alloc)
+                },
+                ConcreteNodePtr::LeafNode(leaf_ptr) => {
+                    // SAFETY: Every leaf is reached exactly once from its parent, and the
+                    // map is consumed so no other reference to the leaf can exist.
+                    let leaf = unsafe { NodePtr::deallocate_node_ptr_in(leaf_ptr, &alloc) };
+                    entries.push(leaf.into_entry());
+                },
+            }
+        }
+
+        entries
+    }
+
+    /// Returns a value which renders the entries of the map with
+    /// [`Display`](fmt::Display), for logs and error messages.
+    ///
+    /// Keys are shown as strings where they are valid UTF-8 and as hex bytes
+    /// otherwise. See [`DisplayLossy`] for the truncation limits.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::TreeMap;
+    ///
+    /// let mut map = TreeMap::<Box<[u8]>, u32>::new();
+    /// map.try_insert(Box::from(&b"apple"[..]), 1).unwrap();
+    /// map.try_insert(Box::from(&b"banana"[..]), 2).unwrap();
+    /// map.try_insert(Box::from(&[0xC0, 0xFF][..]), 3).unwrap();
+    ///
+    /// assert_eq!(
+    ///     map.display_lossy().to_string(),
+    ///     r#"{"apple": 1, "banana": 2, 0xc0ff: 3}"#
+    /// );
+    /// assert_eq!(
+    ///     map.display_lossy().max_entries(1).max_key_bytes(3).to_string(),
+    ///     r#"{"app"...: 1, ... 2 more}"#
+    /// );
+    /// ```
+    pub fn display_lossy(&self) -> DisplayLossy<'_, K, V, A> {
+        DisplayLossy::new(self)
+    }
+
     /// Gets an iterator over the entries of the map, sorted by key.
     ///
     /// # Examples
@@ -988,7 +2702,7 @@ impl<K, V> TreeMap<K, V> {
     ///
     /// assert_eq!(map.len(), 5);
     /// ```
-    pub fn len(&self) -> usize {
+    pub const fn len(&self) -> usize {
         self.num_entries
     }
 
@@ -1002,55 +2716,67 @@ impl<K, V> TreeMap<K, V> {
     /// let map = TreeMap::<Box<[u8]>, ()>::new();
     /// assert!(map.is_empty());
     /// ```
-    pub fn is_empty(&self) -> bool {
+    pub const fn is_empty(&self) -> bool {
         self.num_entries == 0
     }
 }
 
-impl<K, V> Drop for TreeMap<K, V> {
+impl<K, V, A: Allocator> Drop for TreeMap<K, V, A> {
     fn drop(&mut self) {
         self.clear();
     }
 }
 
-impl<K, V> Clone for TreeMap<K, V>
+impl<K, V, A> Clone for TreeMap<K, V, A>
 where
-    K: Clone + AsBytes,
+    K: Clone,
     V: Clone,
+    A: Allocator + Clone,
 {
     fn clone(&self) -> Self {
-        let mut new_tree = TreeMap::new();
+        let mut new_tree = TreeMap::with_resize_policy_in(self.resize_policy, self.alloc.clone());
 
-        for (key, value) in self {
-            // This `panic!` should never happen because the previous tree was constructed
-            // with no prefixes, so putting it into a new tree will also have no prefixes
-            let _ = new_tree.try_insert(key.clone(), value.clone()).unwrap();
+        if let Some(root) = self.root {
+            // SAFETY: The shared reference to the map forbids any mutation of the tree
+            // while it is copied.
+            new_tree.root = Some(unsafe { clone_tree_in(root, &new_tree.alloc) });
+            new_tree.num_entries = self.num_entries;
         }
 
         new_tree
     }
 }
 
-impl<K, V> Debug for TreeMap<K, V>
+impl<K, V, A> Debug for TreeMap<K, V, A>
 where
     K: Debug,
     V: Debug,
+    A: Allocator,
 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_map().entries(self.iter()).finish()
     }
 }
 
+// SAFETY: The `TreeMap` uniquely owns all the nodes of the tree, so sending the
+// map to another thread only requires sending the keys, values, and allocator.
+unsafe impl<K: Send, V: Send, A: Allocator + Send> Send for TreeMap<K, V, A> {}
+
+// SAFETY: A shared reference to the `TreeMap` only allows shared access to the
+// nodes, keys, values, and allocator, and no interior mutability.
+unsafe impl<K: Sync, V: Sync, A: Allocator + Sync> Sync for TreeMap<K, V, A> {}
+
 impl<K, V> Default for TreeMap<K, V> {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl<'a, K, V> Extend<(&'a K, &'a V)> for TreeMap<K, V>
+impl<'a, K, V, A> Extend<(&'a K, &'a V)> for TreeMap<K, V, A>
 where
     K: Copy + NoPrefixesBytes,
     V: Copy,
+    A: Allocator,
 {
     fn extend<T: IntoIterator<Item = (&'a K, &'a V)>>(&mut self, iter: T) {
         for (key, value) in iter {
@@ -1059,9 +2785,10 @@ where
     }
 }
 
-impl<K, V> Extend<(K, V)> for TreeMap<K, V>
+impl<K, V, A> Extend<(K, V)> for TreeMap<K, V, A>
 where
     K: NoPrefixesBytes,
+    A: Allocator,
 {
     fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
         for (key, value) in iter {
@@ -1096,10 +2823,11 @@ where
     }
 }
 
-impl<K, V> Hash for TreeMap<K, V>
+impl<K, V, A> Hash for TreeMap<K, V, A>
 where
     K: Hash,
     V: Hash,
+    A: Allocator,
 {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         crate::nightly_rust_apis::hasher_write_length_prefix(state, self.num_entries);
@@ -1109,10 +2837,11 @@ where
     }
 }
 
-impl<Q, K, V> Index<&Q> for TreeMap<K, V>
+impl<Q, K, V, A> Index<&Q> for TreeMap<K, V, A>
 where
     K: Borrow<Q> + AsBytes,
     Q: AsBytes + ?Sized,
+    A: Allocator,
 {
     type Output = V;
 
@@ -1121,7 +2850,7 @@ where
     }
 }
 
-impl<'a, K, V> IntoIterator for &'a TreeMap<K, V> {
+impl<'a, K, V, A: Allocator> IntoIterator for &'a TreeMap<K, V, A> {
     type IntoIter = iterators::Iter<'a, K, V>;
     type Item = (&'a K, &'a V);
 
@@ -1130,7 +2859,7 @@ impl<'a, K, V> IntoIterator for &'a TreeMap<K, V> {
     }
 }
 
-impl<'a, K, V> IntoIterator for &'a mut TreeMap<K, V> {
+impl<'a, K, V, A: Allocator> IntoIterator for &'a mut TreeMap<K, V, A> {
     type IntoIter = iterators::IterMut<'a, K, V>;
     type Item = (&'a K, &'a mut V);
 
@@ -1139,8 +2868,8 @@ impl<'a, K, V> IntoIterator for &'a mut TreeMap<K, V> {
     }
 }
 
-impl<K, V> IntoIterator for TreeMap<K, V> {
-    type IntoIter = iterators::IntoIter<K, V>;
+impl<K, V, A: Allocator> IntoIterator for TreeMap<K, V, A> {
+    type IntoIter = iterators::IntoIter<K, V, A>;
     type Item = (K, V);
 
     fn into_iter(self) -> Self::IntoIter {
@@ -1148,40 +2877,177 @@ impl<K, V> IntoIterator for TreeMap<K, V> {
     }
 }
 
-impl<K, V> Ord for TreeMap<K, V>
+impl<K, V, A> Ord for TreeMap<K, V, A>
+where
+    K: Ord,
+    V: Ord,
+    A: Allocator,
+{
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.iter().cmp(other.iter())
+    }
+}
+
+impl<K, V, A> PartialOrd for TreeMap<K, V, A>
+where
+    K: PartialOrd,
+    V: PartialOrd,
+    A: Allocator,
+{
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self.iter().partial_cmp(other.iter())
+    }
+}
+
+impl<K, V, A> Eq for TreeMap<K, V, A>
+where
+    K: Eq,
+    V: Eq,
+    A: Allocator,
+{
+}
+
+impl<K, V, A> PartialEq for TreeMap<K, V, A>
+where
+    K: PartialEq,
+    V: PartialEq,
+    A: Allocator,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl<K, V, A> TreeMap<Checked<K>, V, A>
+where
+    K: TryAsBytes,
+    A: Allocator,
+{
+    /// Checks that the key has a byte representation, then inserts it with
+    /// the given value into the map.
+    ///
+    /// If the map did not have this key present, `None` is returned. If the map
+    /// did have this key present, the value is updated, and the old value is
+    /// returned.
+    ///
+    /// # Errors
+    ///
+    ///  - Returns [`TryInsertError::Encoding`] if the key has no byte
+    ///    representation.
+    ///  - Returns [`TryInsertError::Prefix`] if the key is a prefix of another
+    ///    key in the map, or another key is a prefix of it.
+    ///
+    /// In both cases the map is left unchanged.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::{map::TryInsertError, Checked, TreeMap};
+    ///
+    /// let mut map = TreeMap::<Checked<String>, u32>::new();
+    ///
+    /// assert_eq!(map.try_insert_checked("apple".to_string(), 1), Ok(None));
+    /// assert!(matches!(
+    ///     map.try_insert_checked("app".to_string(), 2),
+    ///     Err(TryInsertError::Prefix(_))
+    /// ));
+    /// assert_eq!(map.try_get(&"apple".to_string()), Ok(Some(&1)));
+    /// ```
+    pub fn try_insert_checked(
+        &mut self,
+        key: K,
+        value: V,
+    ) -> Result<Option<V>, TryInsertError<K::Error>> {
+        let key = Checked::new(key).map_err(TryInsertError::Encoding)?;
+        self.try_insert(key, value).map_err(TryInsertError::Prefix)
+    }
+}
+
+/// The error returned by [`TreeMap::try_insert_checked`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TryInsertError<E> {
+    /// The key has no byte representation
+    Encoding(E),
+    /// The key is a prefix of another key in the map, or another key is a
+    /// prefix of it
+    Prefix(InsertPrefixError),
+}
+
+impl<E> fmt::Display for TryInsertError<E>
 where
-    K: Ord,
-    V: Ord,
+    E: fmt::Display,
 {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.iter().cmp(other.iter())
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryInsertError::Encoding(err) => write!(f, "the key could not be encoded: {err}"),
+            TryInsertError::Prefix(err) => fmt::Display::fmt(err, f),
+        }
     }
 }
 
-impl<K, V> PartialOrd for TreeMap<K, V>
+impl<E> Error for TryInsertError<E>
 where
-    K: PartialOrd,
-    V: PartialOrd,
+    E: Error + 'static,
 {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.iter().partial_cmp(other.iter())
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TryInsertError::Encoding(err) => Some(err),
+            TryInsertError::Prefix(err) => Some(err),
+        }
     }
 }
 
-impl<K, V> Eq for TreeMap<K, V>
-where
-    K: Eq,
-    V: Eq,
-{
+/// The error returned by [`TreeMap::rename_key`], which holds the new key that
+/// could not be used.
+pub enum RenameKeyError<K> {
+    /// The old key is not present in the map
+    NotFound(K),
+    /// The new key is already present in the map
+    DestinationExists(K),
+    /// The new key is a prefix of another key in the map, or another key is a
+    /// prefix of it
+    Prefix(K, InsertPrefixError),
 }
 
-impl<K, V> PartialEq for TreeMap<K, V>
-where
-    K: PartialEq,
-    V: PartialEq,
-{
-    fn eq(&self, other: &Self) -> bool {
-        self.iter().eq(other.iter())
+impl<K> RenameKeyError<K> {
+    /// Returns the new key that could not be used.
+    pub fn into_key(self) -> K {
+        match self {
+            RenameKeyError::NotFound(key)
+            | RenameKeyError::DestinationExists(key)
+            | RenameKeyError::Prefix(key, _) => key,
+        }
+    }
+}
+
+impl<K> Debug for RenameKeyError<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenameKeyError::NotFound(_) => f.write_str("NotFound"),
+            RenameKeyError::DestinationExists(_) => f.write_str("DestinationExists"),
+            RenameKeyError::Prefix(_, err) => f.debug_tuple("Prefix").field(err).finish(),
+        }
+    }
+}
+
+impl<K> fmt::Display for RenameKeyError<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenameKeyError::NotFound(_) => f.write_str("the key to rename is not in the map"),
+            RenameKeyError::DestinationExists(_) => {
+                f.write_str("the new key is already in the map")
+            },
+            RenameKeyError::Prefix(_, err) => fmt::Display::fmt(err, f),
+        }
+    }
+}
+
+impl<K> Error for RenameKeyError<K> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RenameKeyError::Prefix(_, err) => Some(err),
+            _ => None,
+        }
     }
 }
 
@@ -1191,6 +3057,7 @@ mod tests {
         cmp::Ordering,
         collections::hash_map::RandomState,
         hash::{BuildHasher, Hasher},
+        ops::Bound,
     };
 
     use super::*;
@@ -1203,6 +3070,20 @@ mod tests {
         assert_eq!(map.len(), 0);
     }
 
+    #[test]
+    fn tree_map_const_and_default_empty() {
+        const EMPTY: TreeMap<Box<[u8]>, u32> = TreeMap::new();
+        static STATIC_EMPTY: TreeMap<Box<[u8]>, u32> = TreeMap::new();
+
+        fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+        let map = EMPTY;
+        assert_send_sync(&map);
+        assert_eq!(STATIC_EMPTY.len(), 0);
+        assert_eq!(map, TreeMap::default());
+        assert!(map.into_raw().is_none());
+    }
+
     #[test]
     fn tree_map_get_non_existent_entry_different_keys_types() {
         let map = TreeMap::<Box<[u8]>, ()>::new();
@@ -1285,6 +3166,29 @@ mod tests {
         map
     }
 
+    #[test]
+    fn into_sorted_vec_returns_entries_in_key_order() {
+        let map = build_tree_map([
+            b"0015", b"0003", b"0010", b"0000", b"0014", b"0001", b"0012", b"0004", b"0011",
+            b"0002", b"0013", b"0005",
+        ]);
+
+        let entries = map.into_sorted_vec();
+        assert_eq!(entries.capacity(), 12);
+        let keys: Vec<_> = entries.iter().map(|(key, _)| key.as_ref()).collect();
+        assert_eq!(
+            keys,
+            [
+                b"0000", b"0001", b"0002", b"0003", b"0004", b"0005", b"0010", b"0011", b"0012",
+                b"0013", b"0014", b"0015"
+            ]
+        );
+        let values: Vec<_> = entries.iter().map(|(_, value)| *value).collect();
+        assert_eq!(values, [3, 5, 9, 1, 7, 11, 2, 8, 6, 10, 4, 0]);
+
+        assert!(TreeMap::<Box<[u8]>, ()>::new().into_sorted_vec().is_empty());
+    }
+
     #[test]
     fn tree_map_iterators() {
         let mut map = build_tree_map([
@@ -1359,6 +3263,44 @@ mod tests {
         );
     }
 
+    #[test]
+    fn tree_map_iterators_have_exact_len_and_are_fused() {
+        let mut map = build_tree_map([b"0000", b"0001", b"0002", b"0010", b"0011", b"0100"]);
+
+        let mut iter = map.iter();
+        assert_eq!(iter.len(), 6);
+        iter.next();
+        iter.next_back();
+        assert_eq!(iter.size_hint(), (4, Some(4)));
+        assert_eq!(iter.by_ref().count(), 4);
+        assert_eq!(iter.len(), 0);
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.next_back(), None);
+
+        assert_eq!(map.keys().len(), 6);
+        assert_eq!(map.values().len(), 6);
+        assert_eq!(map.values_mut().len(), 6);
+        let mut iter_mut = map.iter_mut();
+        iter_mut.next();
+        assert_eq!(iter_mut.len(), 5);
+
+        let mut into_keys = map.clone().into_keys();
+        into_keys.next_back();
+        assert_eq!(into_keys.len(), 5);
+        let mut into_values = map.clone().into_values();
+        into_values.next();
+        assert_eq!(into_values.size_hint(), (5, Some(5)));
+
+        let mut into_iter = map.into_iter();
+        assert_eq!(into_iter.len(), 6);
+        assert_eq!(into_iter.by_ref().count(), 6);
+        assert_eq!(into_iter.next(), None);
+
+        let empty = TreeMap::<Box<[u8]>, usize>::new();
+        assert_eq!(empty.iter().len(), 0);
+        assert_eq!(empty.iter().fuse().next(), None);
+    }
+
     #[test]
     fn tree_into_iterator_removes_values_before_drop() {
         // This struct will panic on drop if the flag inside is true
@@ -1558,4 +3500,835 @@ mod tests {
         assert_eq!(tree.pop_last(), None);
         assert_eq!(tree.remove(&Box::from([])), None);
     }
+
+    /// Drop the map on a thread with a stack that is too small for a recursive
+    /// teardown of the map.
+    fn drop_on_small_stack<K: Send + 'static, V: Send + 'static>(map: TreeMap<K, V>) {
+        std::thread::Builder::new()
+            .stack_size(64 * 1024)
+            .spawn(move || drop(map))
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn drop_deep_tree_does_not_recurse() {
+        use std::sync::Arc;
+
+        let value = Arc::new(());
+
+        // Every key branches off the previous one at its last byte, so the tree has an
+        // inner node for every key
+        let mut map = TreeMap::<Box<[u8]>, _>::new();
+        for len in 0..3000 {
+            let mut key = vec![0; len + 1];
+            key[len] = 1;
+            map.try_insert(key.into_boxed_slice(), Arc::clone(&value))
+                .unwrap();
+        }
+        assert_eq!(map.len(), 3000);
+        assert_eq!(Arc::strong_count(&value), 3001);
+
+        drop_on_small_stack(map);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn drop_tree_with_million_byte_keys() {
+        use std::sync::Arc;
+
+        const KEY_LEN: usize = 1_000_000;
+
+        let value = Arc::new(());
+        let mut map = TreeMap::<Box<[u8]>, _>::new();
+        for idx in 0..16 {
+            // The keys only differ at bytes which are far apart, so the inner nodes
+            // between them have very long compressed paths
+            let mut key = vec![0xCD; KEY_LEN];
+            key[(idx + 1) * (KEY_LEN / 17)] = 0;
+            map.try_insert(key.into_boxed_slice(), Arc::clone(&value))
+                .unwrap();
+        }
+        map.try_insert(vec![0xCD; KEY_LEN].into_boxed_slice(), Arc::clone(&value))
+            .unwrap();
+        assert_eq!(map.len(), 17);
+
+        let mut lookup_key = vec![0xCD; KEY_LEN];
+        lookup_key[5 * (KEY_LEN / 17)] = 0;
+        assert!(map.contains_key(lookup_key.as_slice()));
+        assert_eq!(Arc::strong_count(&value), 18);
+
+        drop_on_small_stack(map.clone());
+        assert_eq!(Arc::strong_count(&value), 18);
+        drop_on_small_stack(map);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn rename_key_reuses_leaf_and_value() {
+        use crate::visitor::WellFormedChecker;
+        use std::sync::Arc;
+
+        let value = Arc::new(());
+        let mut map = TreeMap::<Box<[u8]>, _>::new();
+        for a in 0..16u8 {
+            for b in 0..16u8 {
+                map.try_insert(Box::new([a, b, 0]), Arc::clone(&value))
+                    .unwrap();
+            }
+        }
+        assert_eq!(Arc::strong_count(&value), 257);
+
+        let leaf = map.leaf_by_key_bytes(&[3, 4, 0]).unwrap();
+        let old_key = map
+            .rename_key([3, 4, 0].as_ref(), Box::new([200, 1, 2]))
+            .unwrap();
+        assert_eq!(old_key.as_ref(), [3, 4, 0]);
+        assert_eq!(map.leaf_by_key_bytes(&[200, 1, 2]), Some(leaf));
+        assert!(!map.contains_key([3, 4, 0].as_ref()));
+        assert_eq!(map.len(), 256);
+        assert_eq!(Arc::strong_count(&value), 257);
+        // SAFETY: The tree is not mutated while it is checked
+        assert!(unsafe { WellFormedChecker::check_tree(map.root().unwrap()) }.is_ok());
+
+        // Renaming to a key which extends the old key only conflicts with the old key
+        map.rename_key([200, 1, 2].as_ref(), Box::new([200, 1, 2, 3]))
+            .unwrap();
+        assert_eq!(map.leaf_by_key_bytes(&[200, 1, 2, 3]), Some(leaf));
+
+        let mut single = TreeMap::<Box<[u8]>, _>::new();
+        single
+            .try_insert(Box::new([1]), Arc::clone(&value))
+            .unwrap();
+        single.rename_key([1].as_ref(), Box::new([2])).unwrap();
+        assert_eq!(single.len(), 1);
+        assert!(single.contains_key([2].as_ref()));
+        assert_eq!(Arc::strong_count(&value), 258);
+    }
+
+    #[test]
+    fn rename_key_errors_leave_map_unchanged() {
+        let mut map = TreeMap::<Box<[u8]>, char>::new();
+        map.try_insert(Box::new([1, 2, 3]), 'a').unwrap();
+        map.try_insert(Box::new([1, 2, 4]), 'b').unwrap();
+        map.try_insert(Box::new([5, 6]), 'c').unwrap();
+        let expected = map.clone();
+
+        let err = map.rename_key([9, 9].as_ref(), Box::new([7])).unwrap_err();
+        assert!(matches!(err, RenameKeyError::NotFound(_)));
+        assert_eq!(err.into_key().as_ref(), [7]);
+
+        let err = map
+            .rename_key([1, 2, 3].as_ref(), Box::new([1, 2, 4]))
+            .unwrap_err();
+        assert!(matches!(err, RenameKeyError::DestinationExists(_)));
+        let err = map
+            .rename_key([1, 2, 3].as_ref(), Box::new([1, 2, 3]))
+            .unwrap_err();
+        assert!(matches!(err, RenameKeyError::DestinationExists(_)));
+
+        // The new key is a prefix of another key, and another key is a prefix of it
+        for new_key in [&[1, 2][..], &[5, 6, 7][..]] {
+            let err = map
+                .rename_key([1, 2, 3].as_ref(), Box::from(new_key))
+                .unwrap_err();
+            assert!(matches!(err, RenameKeyError::Prefix(..)), "{err:?}");
+            assert_eq!(err.into_key().as_ref(), new_key);
+        }
+
+        assert_eq!(map, expected);
+        assert_eq!(map.len(), 3);
+
+        let mut empty = TreeMap::<Box<[u8]>, char>::new();
+        assert!(matches!(
+            empty.rename_key([1].as_ref(), Box::new([2])),
+            Err(RenameKeyError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn fallible_keys_return_encoding_errors() {
+        /// A composite key where every part must be less than 100
+        #[derive(Debug, PartialEq, Eq)]
+        struct Parts(Vec<u8>);
+
+        impl TryAsBytes for Parts {
+            type Error = u8;
+
+            fn try_as_bytes(&self) -> Result<&[u8], Self::Error> {
+                match self.0.iter().find(|part| **part >= 100) {
+                    Some(part) => Err(*part),
+                    None => Ok(&self.0),
+                }
+            }
+        }
+
+        let mut map = TreeMap::<Checked<Parts>, char>::new();
+        assert_eq!(map.try_insert_checked(Parts(vec![1, 2]), 'a'), Ok(None));
+        assert_eq!(map.try_insert_checked(Parts(vec![1, 3]), 'b'), Ok(None));
+        assert_eq!(
+            map.try_insert_checked(Parts(vec![1, 200]), 'c'),
+            Err(TryInsertError::Encoding(200))
+        );
+        assert!(matches!(
+            map.try_insert_checked(Parts(vec![1]), 'd'),
+            Err(TryInsertError::Prefix(_))
+        ));
+        assert_eq!(map.len(), 2);
+
+        assert_eq!(map.try_get(&Parts(vec![1, 3])), Ok(Some(&'b')));
+        assert_eq!(map.try_get(&Parts(vec![1, 4])), Ok(None));
+        assert_eq!(map.try_get(&Parts(vec![150])), Err(150));
+        assert_eq!(map.try_contains_key(&Parts(vec![1, 2])), Ok(true));
+        assert_eq!(map.try_remove(&Parts(vec![101, 2])), Err(101));
+        assert_eq!(map.try_remove(&Parts(vec![1, 2])), Ok(Some('a')));
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn extend_from_sorted_append_matches_inserts() {
+        use crate::{
+            tests_common::{generate_key_with_prefix, PrefixExpansion},
+            visitor::WellFormedChecker,
+        };
+
+        // Keys with a long shared prefix in the middle, so that appends split
+        // compressed paths, split leaves, and grow inner nodes along the rightmost path
+        let entries: Vec<_> = generate_key_with_prefix(
+            [7; 4],
+            [PrefixExpansion {
+                base_index: 1,
+                expanded_length: 12,
+            }],
+        )
+        .enumerate()
+        .map(|(idx, key)| (key, idx))
+        .collect();
+        let (entries, last_entries) = entries.split_at(entries.len() - 2);
+
+        let mut map = TreeMap::new();
+        let (first, rest) = entries.split_at(700);
+        map.extend_from_sorted_append(first.iter().cloned())
+            .unwrap();
+        map.extend_from_sorted_append(rest.iter().cloned()).unwrap();
+        assert_eq!(map.len(), entries.len());
+        assert!(map
+            .iter()
+            .eq(entries.iter().map(|(key, value)| (key, value))));
+        // SAFETY: The tree is not mutated while it is checked
+        assert!(unsafe { WellFormedChecker::check_tree(map.root().unwrap()) }.is_ok());
+
+        // Keys which are not greater fall back to a regular insert, and the appends
+        // after them still work
+        let (key, _) = entries[10].clone();
+        map.extend_from_sorted_append(
+            [(key.clone(), usize::MAX)]
+                .into_iter()
+                .chain(last_entries.iter().cloned()),
+        )
+        .unwrap();
+        assert_eq!(map.get(&key), Some(&usize::MAX));
+        assert_eq!(map.len(), entries.len() + 2);
+        let (last_key, last_value) = &last_entries[1];
+        assert_eq!(map.last_key_value(), Some((last_key, last_value)));
+        // SAFETY: The tree is not mutated while it is checked
+        assert!(unsafe { WellFormedChecker::check_tree(map.root().unwrap()) }.is_ok());
+    }
+
+    #[test]
+    fn extend_from_sorted_append_stops_at_prefix_error() {
+        let mut map = TreeMap::<Box<[u8]>, char>::new();
+        let mut entries = vec![
+            (Box::from(&[1, 2][..]), 'a'),
+            (Box::from(&[1, 2, 3][..]), 'b'),
+            (Box::from(&[1, 4][..]), 'c'),
+        ]
+        .into_iter();
+
+        let err = map.extend_from_sorted_append(&mut entries).unwrap_err();
+        assert_eq!(err.byte_repr.as_ref(), [1, 2, 3]);
+        assert_eq!(map.len(), 1);
+        assert_eq!(entries.next(), Some((Box::from(&[1, 4][..]), 'c')));
+    }
+
+    #[test]
+    fn rename_prefix_moves_subtree_and_keeps_leaves() {
+        use crate::visitor::WellFormedChecker;
+        use std::sync::Arc;
+
+        let value = Arc::new(());
+        let mut map = TreeMap::<Box<[u8]>, _>::new();
+        for a in 0..8u8 {
+            for b in 0..8u8 {
+                for c in 0..4u8 {
+                    map.try_insert(Box::new([a, b, c]), Arc::clone(&value))
+                        .unwrap();
+                }
+            }
+        }
+        assert_eq!(Arc::strong_count(&value), 257);
+        let expected_keys = |first: &[u8]| {
+            (0..8u8)
+                .flat_map(|b| (0..4u8).map(move |c| [b, c]))
+                .map(|tail| [first, &tail[..]].concat().into_boxed_slice())
+                .collect::<Vec<_>>()
+        };
+
+        let leaves = map.prefix_leaves(&[3]).collect::<Vec<_>>();
+        let moved = map
+            .rename_prefix(&[3], &[200, 7], |old_key, new_key| {
+                assert_eq!(old_key[0], 3);
+                Box::from(new_key)
+            })
+            .unwrap();
+        assert_eq!(moved, 32);
+        assert_eq!(map.len(), 256);
+        assert_eq!(Arc::strong_count(&value), 257);
+        assert_eq!(map.prefix_leaves(&[200, 7]).collect::<Vec<_>>(), leaves);
+        assert_eq!(map.prefix_leaves(&[3]).count(), 0);
+        assert_eq!(
+            map.prefix_iter(&[200, 7])
+                .map(|(key, _)| key.clone())
+                .collect::<Vec<_>>(),
+            expected_keys(&[200, 7])
+        );
+        // SAFETY: The tree is not mutated while it is checked
+        assert!(unsafe { WellFormedChecker::check_tree(map.root().unwrap()) }.is_ok());
+
+        // Move the subtree under a sibling which only shares its first byte
+        map.rename_prefix(&[200, 7], &[4, 9], |_, new_key| Box::from(new_key))
+            .unwrap();
+        assert_eq!(map.prefix_leaves(&[4, 9]).collect::<Vec<_>>(), leaves);
+        assert_eq!(map.prefix_leaves(&[4]).count(), 64);
+        // SAFETY: The tree is not mutated while it is checked
+        assert!(unsafe { WellFormedChecker::check_tree(map.root().unwrap()) }.is_ok());
+
+        // A prefix with a single key moves just that leaf
+        let leaf = map.leaf_by_key_bytes(&[5, 5, 1]).unwrap();
+        let moved = map
+            .rename_prefix(&[5, 5, 1], &[6, 100], |_, new_key| Box::from(new_key))
+            .unwrap();
+        assert_eq!(moved, 1);
+        assert_eq!(map.leaf_by_key_bytes(&[6, 100]), Some(leaf));
+        assert_eq!(Arc::strong_count(&value), 257);
+
+        // Moving the whole map, and moving the map under one of its own prefixes
+        let mut whole = TreeMap::<Box<[u8]>, _>::new();
+        for key in expected_keys(&[1]) {
+            whole.try_insert(key, Arc::clone(&value)).unwrap();
+        }
+        let moved = whole
+            .rename_prefix(&[1], &[1, 1], |_, new_key| Box::from(new_key))
+            .unwrap();
+        assert_eq!(moved, 32);
+        assert_eq!(
+            whole.keys().cloned().collect::<Vec<_>>(),
+            expected_keys(&[1, 1])
+        );
+        whole
+            .rename_prefix(&[], &[9], |_, new_key| Box::from(new_key))
+            .unwrap();
+        assert_eq!(
+            whole.keys().cloned().collect::<Vec<_>>(),
+            expected_keys(&[9, 1, 1])
+        );
+        // SAFETY: The tree is not mutated while it is checked
+        assert!(unsafe { WellFormedChecker::check_tree(whole.root().unwrap()) }.is_ok());
+        assert_eq!(Arc::strong_count(&value), 289);
+
+        assert_eq!(
+            whole.rename_prefix(&[9, 1], &[9, 1], |_, _| unreachable!()),
+            Ok(32)
+        );
+        assert_eq!(
+            whole.rename_prefix(&[2], &[3], |_, _| unreachable!()),
+            Ok(0)
+        );
+    }
+
+    #[test]
+    fn rename_prefix_conflicts_leave_map_unchanged() {
+        use crate::visitor::WellFormedChecker;
+
+        let mut map = TreeMap::<Box<[u8]>, char>::new();
+        map.try_insert(Box::new([1, 2, 3]), 'a').unwrap();
+        map.try_insert(Box::new([1, 2, 4]), 'b').unwrap();
+        map.try_insert(Box::new([1, 5]), 'c').unwrap();
+        map.try_insert(Box::new([6, 7, 8]), 'd').unwrap();
+        let expected = map.clone();
+        let leaves = map.prefix_leaves(&[1, 2]).collect::<Vec<_>>();
+
+        // The new prefix is taken by a key, is a prefix of a key, or has a key as its
+        // prefix
+        for new_prefix in [&[1, 5][..], &[6][..], &[6, 7, 8, 9][..], &[][..]] {
+            let err = map
+                .rename_prefix(&[1, 2], new_prefix, |_, new_key| Box::from(new_key))
+                .unwrap_err();
+            assert_eq!(err.byte_repr.as_ref(), new_prefix);
+            assert_eq!(map, expected);
+            assert_eq!(map.prefix_leaves(&[1, 2]).collect::<Vec<_>>(), leaves);
+            // SAFETY: The tree is not mutated while it is checked
+            assert!(unsafe { WellFormedChecker::check_tree(map.root().unwrap()) }.is_ok());
+        }
+        assert_eq!(map.len(), 4);
+    }
+
+    #[test]
+    fn visitors_and_clone_on_deep_tree_do_not_recurse() {
+        use crate::visitor::{DotPrinter, DotPrinterSettings, WellFormedChecker};
+
+        std::thread::Builder::new()
+            .stack_size(64 * 1024)
+            .spawn(|| {
+                let mut map = TreeMap::<Box<[u8]>, usize>::new();
+                for len in 0..3000 {
+                    let mut key = vec![0; len + 1];
+                    key[len] = 1;
+                    map.try_insert(key.into_boxed_slice(), len).unwrap();
+                }
+                // PANIC SAFETY: The map was just filled with entries
+                let root = map.root().unwrap();
+
+                // SAFETY: The tree is not mutated while the visitors run
+                let stats = unsafe { TreeStatsCollector::collect(root) };
+                assert_eq!(stats.leaf_count, 3000);
+                assert_eq!(stats.node4_count, 2999);
+                // SAFETY: The tree is not mutated while the visitors run
+                assert_eq!(unsafe { TreeStatsCollector::count_leaf_nodes(root) }, 3000);
+                // SAFETY: The tree is not mutated while the visitors run
+                assert!(unsafe { WellFormedChecker::check_tree(root) }.is_ok());
+
+                let mut output = Vec::new();
+                // SAFETY: The tree is not mutated while the visitors run
+                unsafe {
+                    DotPrinter::print_tree(
+                        &mut output,
+                        &root,
+                        DotPrinterSettings {
+                            display_node_address: false,
+                        },
+                    )
+                    .unwrap()
+                };
+                let output = String::from_utf8(output).unwrap();
+                assert_eq!(output.matches(" -> ").count(), 2999 + 3000 - 1);
+
+                let clone = map.clone();
+                assert_eq!(clone, map);
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn prefix_iter_walks_only_the_subtree() {
+        let mut map = TreeMap::<[u8; 3], usize>::new();
+        assert_eq!(map.prefix_iter(&[1]).next(), None);
+
+        for (idx, key) in (0..4u8)
+            .flat_map(|a| (0..4u8).flat_map(move |b| (0..4u8).map(move |c| [a, b, c])))
+            .enumerate()
+        {
+            map.insert(key, idx);
+        }
+
+        let mut iter = map.prefix_iter(&[2, 3]);
+        assert_eq!(iter.next(), Some((&[2, 3, 0], &44)));
+        assert_eq!(iter.next_back(), Some((&[2, 3, 3], &47)));
+        assert_eq!(iter.map(|(key, _)| key[2]).collect::<Vec<_>>(), [1, 2]);
+
+        assert_eq!(map.prefix_iter(&[]).count(), 64);
+        assert_eq!(map.prefix_iter(&[1, 2, 3]).count(), 1);
+        assert_eq!(map.prefix_iter(&[1, 2, 3, 4]).count(), 0);
+        assert_eq!(map.prefix_iter(&[4]).count(), 0);
+
+        for (_, value) in map.prefix_iter_mut(&[3]) {
+            *value = 0;
+        }
+        assert_eq!(map.values().filter(|value| **value == 0).count(), 17);
+    }
+
+    #[test]
+    fn range_bounds_and_retain() {
+        let mut map: TreeMap<[u8; 1], u8> = (0..10u8).map(|x| ([x * 2], x)).collect();
+        let range_keys = |map: &TreeMap<[u8; 1], u8>, range: (Bound<&[u8; 1]>, Bound<&[u8; 1]>)| {
+            map.range::<[u8; 1], _>(range)
+                .map(|(key, _)| key[0])
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            range_keys(&map, (Bound::Excluded(&[4]), Bound::Included(&[10]))),
+            [6, 8, 10]
+        );
+        assert_eq!(
+            range_keys(&map, (Bound::Included(&[5]), Bound::Excluded(&[6]))),
+            []
+        );
+        assert_eq!(
+            range_keys(&map, (Bound::Unbounded, Bound::Excluded(&[3]))),
+            [0, 2]
+        );
+        assert_eq!(
+            map.range::<[u8; 1], _>([17]..).next_back(),
+            Some((&[18], &9))
+        );
+
+        let mut range = map.range_mut::<[u8; 1], _>([4]..[12]);
+        assert_eq!(range.next().map(|(key, _)| key[0]), Some(4));
+        assert_eq!(range.next_back().map(|(key, _)| key[0]), Some(10));
+        for (_, value) in range {
+            *value = 100;
+        }
+        assert_eq!(map.get(&[6]), Some(&100));
+        assert_eq!(map.get(&[4]), Some(&2));
+
+        map.retain(|key, value| {
+            *value += 1;
+            key[0] % 4 == 0
+        });
+        assert_eq!(
+            map.into_iter().collect::<Vec<_>>(),
+            [([0], 1), ([4], 3), ([8], 101), ([12], 7), ([16], 9)]
+        );
+    }
+
+    #[test]
+    #[should_panic = "range start is greater than range end in TreeMap"]
+    fn range_start_after_end_panics() {
+        let map: TreeMap<[u8; 1], u8> = [([1], 1), ([2], 2)].into();
+        let _ = map.range::<[u8; 1], _>([2]..[1]);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_clear_drops_every_value() {
+        use std::sync::Arc;
+
+        let value = Arc::new(());
+        let mut tree = TreeMap::new();
+        for idx in 0..20_000u32 {
+            tree.insert(idx.to_be_bytes(), Arc::clone(&value));
+        }
+        assert_eq!(Arc::strong_count(&value), 20_001);
+
+        tree.par_clear();
+        assert!(tree.is_empty());
+        assert_eq!(Arc::strong_count(&value), 1);
+
+        // A single leaf cannot be split further
+        tree.insert([0; 4], Arc::clone(&value));
+        tree.par_clear();
+        assert_eq!(Arc::strong_count(&value), 1);
+
+        tree.par_clear();
+    }
+
+    #[test]
+    fn stats_and_dot_output_follow_the_tree() {
+        let mut map = TreeMap::<Box<[u8]>, u32>::new();
+        assert_eq!(map.stats(), TreeStats::default());
+
+        let mut dot = Vec::new();
+        map.write_dot(&mut dot, Default::default()).unwrap();
+        assert_eq!(dot, b"strict digraph G {}\n");
+
+        for key in [&b"abc\0"[..], b"abd\0", b"b\0"] {
+            map.try_insert(key.into(), 0).unwrap();
+        }
+
+        let stats = map.stats();
+        assert_eq!(stats.leaf_count, 3);
+        assert_eq!(stats.node4_count, 2);
+        assert_eq!(stats.total_key_bytes, 10);
+        assert_eq!(stats.total_prefix_bytes, 1);
+        assert_eq!(stats.max_depth, 2);
+
+        dot.clear();
+        map.write_dot(&mut dot, Default::default()).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert_eq!(dot.matches("->").count(), 4);
+    }
+
+    /// An allocator which counts the blocks it has handed out and not yet
+    /// taken back.
+    #[derive(Clone, Default)]
+    struct CountingAllocator(std::rc::Rc<std::cell::Cell<usize>>);
+
+    impl CountingAllocator {
+        fn live_blocks(&self) -> usize {
+            self.0.get()
+        }
+    }
+
+    // SAFETY: The blocks are allocated from and returned to the global allocator.
+    unsafe impl Allocator for CountingAllocator {
+        fn allocate(
+            &self,
+            layout: std::alloc::Layout,
+        ) -> Result<std::ptr::NonNull<[u8]>, crate::allocator::AllocError> {
+            self.0.set(self.0.get() + 1);
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: std::ptr::NonNull<u8>, layout: std::alloc::Layout) {
+            self.0.set(self.0.get() - 1);
+            // SAFETY: The block was allocated by `Global` with the same layout
+            unsafe { Global.deallocate(ptr, layout) }
+        }
+    }
+
+    // SAFETY: Every counter forwards to the global allocator, but the blocks are
+    // only counted right if they go back to the counter that handed them out.
+    unsafe impl InterchangeableAllocator for CountingAllocator {
+        fn is_interchangeable_with(&self, other: &Self) -> bool {
+            std::rc::Rc::ptr_eq(&self.0, &other.0)
+        }
+    }
+
+    #[test]
+    fn custom_allocator_gets_back_every_node() {
+        let alloc = CountingAllocator::default();
+        let mut map = TreeMap::<Box<[u8]>, u32, _>::new_in(alloc.clone());
+
+        for idx in 0..1_000u32 {
+            let mut key = idx.to_be_bytes().to_vec();
+            key.extend_from_slice(&[255; 20]);
+            map.try_insert(key.into_boxed_slice(), idx).unwrap();
+        }
+        assert!(alloc.live_blocks() > 1_000);
+
+        for idx in (0..1_000u32).step_by(3) {
+            let mut key = idx.to_be_bytes().to_vec();
+            key.extend_from_slice(&[255; 20]);
+            assert_eq!(map.remove(key.as_slice()), Some(idx));
+        }
+        map.pop_first();
+        map.pop_last();
+        map.rename_prefix(&[0, 0, 1], &[1, 0, 1], |_, new_key| new_key.into())
+            .unwrap();
+
+        let copy = map.clone();
+        let tail = map.split_off([0, 0, 2].as_ref());
+        assert_eq!(copy.len(), map.len() + tail.len());
+        assert_eq!(tail.allocator().live_blocks(), alloc.live_blocks());
+
+        drop(copy);
+        drop(tail.into_sorted_vec());
+        let entries: Vec<_> = map.into_iter().collect();
+        assert!(!entries.is_empty());
+        assert_eq!(alloc.live_blocks(), 0);
+    }
+
+    #[test]
+    fn append_with_custom_allocators() {
+        let keys = |range: std::ops::Range<u32>| range.map(|idx| (idx.to_be_bytes(), idx));
+
+        let alloc = CountingAllocator::default();
+        let mut map = TreeMap::<[u8; 4], u32, _>::new_in(alloc.clone());
+        map.extend(keys(0..100));
+
+        // Clones share the counter, so the nodes are moved over as they are
+        let mut other = TreeMap::new_in(alloc.clone());
+        other.extend(keys(50..150));
+        let live_blocks = alloc.live_blocks();
+        map.append(&mut other);
+        assert!(other.is_empty());
+        assert_eq!(map.len(), 150);
+        assert!(alloc.live_blocks() < live_blocks);
+
+        // A separate counter must get back its own blocks, so the entries are moved
+        let other_alloc = CountingAllocator::default();
+        let mut other = TreeMap::new_in(other_alloc.clone());
+        other.extend(keys(100..200));
+        map.append(&mut other);
+        assert!(other.is_empty());
+        assert_eq!(other_alloc.live_blocks(), 0);
+        assert!(map.iter().map(|(_, value)| *value).eq(0..200));
+
+        drop(map);
+        assert_eq!(alloc.live_blocks(), 0);
+    }
+
+    #[test]
+    fn append_and_split_off_match_btree_map() {
+        use crate::{tests_common::generate_key_fixed_length, visitor::WellFormedChecker};
+        use std::collections::BTreeMap;
+
+        let keys: Vec<[u8; 4]> = generate_key_fixed_length([7; 4])
+            .map(|key| key.as_ref().try_into().unwrap())
+            .collect();
+
+        let mut map = TreeMap::new();
+        let mut expected = BTreeMap::new();
+        // The rounds overlap with each other, so that the trees share inner nodes and
+        // some of the keys are already present
+        for round in 0..4 {
+            let mut other = TreeMap::new();
+            for key in keys.iter().step_by(round + 2) {
+                other.insert(*key, round);
+                expected.insert(*key, round);
+            }
+
+            map.append(&mut other);
+            assert!(other.is_empty());
+            assert_eq!(map.len(), expected.len());
+            assert!(map.iter().eq(expected.iter()));
+            // SAFETY: The tree is not mutated while it is checked
+            assert!(unsafe { WellFormedChecker::check_tree(map.root().unwrap()) }.is_ok());
+        }
+
+        let missing_key = [100, 0, 0, 0];
+        for split_key in [
+            &keys[1000][..],
+            &keys[0],
+            &keys[keys.len() - 1],
+            &missing_key,
+        ] {
+            let mut lower = map.clone();
+            let upper = lower.split_off(split_key);
+            let mut expected_lower = expected.clone();
+            let expected_upper = expected_lower.split_off(split_key);
+
+            assert_eq!(lower.len(), expected_lower.len());
+            assert_eq!(upper.len(), expected_upper.len());
+            assert!(lower.iter().eq(expected_lower.iter()));
+            assert!(upper.iter().eq(expected_upper.iter()));
+            for root in lower.root().into_iter().chain(upper.root()) {
+                // SAFETY: The tree is not mutated while it is checked
+                assert!(unsafe { WellFormedChecker::check_tree(root) }.is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn drain_filter_and_retain_match_btree_map() {
+        use crate::{tests_common::generate_key_fixed_length, visitor::WellFormedChecker};
+        use std::collections::BTreeMap;
+
+        let mut map = TreeMap::new();
+        let mut expected = BTreeMap::new();
+        for (idx, key) in generate_key_fixed_length([7; 4]).enumerate() {
+            let key: [u8; 4] = key.as_ref().try_into().unwrap();
+            map.insert(key, idx);
+            expected.insert(key, idx);
+        }
+
+        // Only take some of the drained entries, the rest are removed on drop
+        let drained: Vec<_> = map
+            .drain_filter(|key, value| {
+                *value += 1;
+                key[3] % 3 == 0
+            })
+            .take(10)
+            .collect();
+        let expected_drained: Vec<_> = expected
+            .iter_mut()
+            .filter_map(|(key, value)| {
+                *value += 1;
+                (key[3] % 3 == 0).then_some((*key, *value))
+            })
+            .collect();
+        expected.retain(|key, _| key[3] % 3 != 0);
+        assert_eq!(drained, expected_drained[..10]);
+        assert_eq!(map.len(), expected.len());
+        assert!(map.iter().eq(expected.iter()));
+        // SAFETY: The tree is not mutated while it is checked
+        assert!(unsafe { WellFormedChecker::check_tree(map.root().unwrap()) }.is_ok());
+
+        map.retain(|key, _| key[2] < 0x80);
+        expected.retain(|key, _| key[2] < 0x80);
+        assert_eq!(map.len(), expected.len());
+        assert!(map.iter().eq(expected.iter()));
+        // SAFETY: The tree is not mutated while it is checked
+        assert!(unsafe { WellFormedChecker::check_tree(map.root().unwrap()) }.is_ok());
+
+        // A leaked iterator leaves the map empty
+        mem::forget(map.drain_filter(|_, _| true));
+        assert!(map.is_empty());
+        assert_eq!(map.iter().next(), None);
+    }
+
+    #[test]
+    fn drain_filter_panic_keeps_remaining_entries() {
+        let mut map: TreeMap<[u8; 1], u8> = (0..10u8).map(|x| ([x], x)).collect();
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            map.drain_filter(|key, _| {
+                assert_ne!(key[0], 4, "predicate panicked");
+                key[0] % 2 == 0
+            })
+            .for_each(drop);
+        }));
+        assert!(result.is_err());
+
+        assert_eq!(
+            map.keys().map(|key| key[0]).collect::<Vec<_>>(),
+            [1, 3, 4, 5, 6, 7, 8, 9]
+        );
+        assert_eq!(map.len(), 8);
+    }
+
+    #[test]
+    fn drain_filter_panic_on_drop_keeps_remaining_entries() {
+        use crate::visitor::WellFormedChecker;
+
+        let mut map: TreeMap<[u8; 2], u8> = (0..40u8).map(|x| ([x / 8, x], x)).collect();
+
+        // The predicate panics while the rest of the iterator is drained on drop
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let drained: Vec<_> = map
+                .drain_filter(|key, _| {
+                    assert_ne!(key[1], 20, "predicate panicked");
+                    key[1] % 2 == 0
+                })
+                .take(3)
+                .map(|(_, value)| value)
+                .collect();
+            assert_eq!(drained, [0, 2, 4]);
+        }));
+        assert!(result.is_err());
+
+        let expected: Vec<_> = (0..40u8).filter(|x| *x >= 20 || x % 2 == 1).collect();
+        assert_eq!(map.values().copied().collect::<Vec<_>>(), expected);
+        assert_eq!(map.len(), expected.len());
+        // SAFETY: The tree is not mutated while it is checked
+        assert!(unsafe { WellFormedChecker::check_tree(map.root().unwrap()) }.is_ok());
+    }
+
+    #[test]
+    fn into_iter_deallocates_nodes_while_draining() {
+        let alloc = CountingAllocator::default();
+        let mut map = TreeMap::<Box<[u8]>, u32, _>::new_in(alloc.clone());
+        for idx in 0..1_000u32 {
+            map.try_insert(idx.to_be_bytes().into(), idx).unwrap();
+        }
+
+        let mut iter = map.into_iter();
+        assert_eq!(iter.next(), Some((0u32.to_be_bytes().into(), 0)));
+        assert_eq!(iter.next_back(), Some((999u32.to_be_bytes().into(), 999)));
+        let live_blocks = alloc.live_blocks();
+        assert_eq!(iter.by_ref().take(500).count(), 500);
+        assert!(alloc.live_blocks() < live_blocks - 500);
+        assert_eq!(iter.len(), 498);
+        assert_eq!(iter.next_back().map(|(_, value)| value), Some(998));
+        assert!(iter.by_ref().map(|(_, value)| value).eq(501..998));
+        assert_eq!(iter.next(), None);
+        drop(iter);
+        assert_eq!(alloc.live_blocks(), 0);
+
+        // Dropping a partially consumed iterator deallocates the remaining nodes
+        let map: TreeMap<_, _, _> = {
+            let mut map = TreeMap::new_in(alloc.clone());
+            map.extend((0..1_000u32).map(|idx| (idx.to_be_bytes(), idx)));
+            map
+        };
+        let mut iter = map.into_iter();
+        iter.nth(100);
+        iter.nth_back(100);
+        drop(iter);
+        assert_eq!(alloc.live_blocks(), 0);
+    }
 }
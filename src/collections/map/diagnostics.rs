@@ -0,0 +1,33 @@
+use crate::NodeType;
+
+/// The result of a [`TreeMap::search_with_diagnostics`] call.
+///
+/// [`TreeMap::search_with_diagnostics`]: crate::TreeMap::search_with_diagnostics
+#[derive(Debug, PartialEq, Eq)]
+pub enum SearchDiagnostics<'a, K, V> {
+    /// The key is present in the map, with this key and value.
+    Found(&'a K, &'a V),
+    /// The key is not present in the map.
+    Missing(KeyMiss<'a, K, V>),
+    /// The map is empty, so the search did not visit any node.
+    Empty,
+}
+
+/// Describes where a search for a key which is not present in a
+/// [`TreeMap`](crate::TreeMap) stopped.
+#[derive(Debug, PartialEq, Eq)]
+pub struct KeyMiss<'a, K, V> {
+    /// The number of leading bytes of the search key which are shared with
+    /// `nearest_key`
+    pub matched_bytes: usize,
+    /// The type of the node at which the search could not continue
+    pub diverged_node_type: NodeType,
+    /// The number of key bytes consumed by the nodes above the node at which
+    /// the search could not continue
+    pub diverged_depth: usize,
+    /// A key in the map which shares the longest possible prefix with the
+    /// search key
+    pub nearest_key: &'a K,
+    /// The value stored with `nearest_key`
+    pub nearest_value: &'a V,
+}
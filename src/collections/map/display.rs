@@ -0,0 +1,169 @@
+use crate::{
+    allocator::{Allocator, Global},
+    AsBytes, TreeMap,
+};
+use std::fmt;
+
+/// Renders the entries of a [`TreeMap`] for logs and error messages, created
+/// by [`TreeMap::display_lossy`].
+///
+/// Keys which are valid UTF-8 are shown as quoted strings, and all other keys
+/// are shown as hex bytes. Values are shown with their [`Debug`](fmt::Debug)
+/// implementation. By default every entry and every key byte is shown, which
+/// can be limited with [`DisplayLossy::max_entries`] and
+/// [`DisplayLossy::max_key_bytes`].
+pub struct DisplayLossy<'a, K, V, A: Allocator = Global> {
+    map: &'a TreeMap<K, V, A>,
+    max_entries: Option<usize>,
+    max_key_bytes: Option<usize>,
+}
+
+impl<'a, K, V, A: Allocator> DisplayLossy<'a, K, V, A> {
+    pub(crate) fn new(map: &'a TreeMap<K, V, A>) -> Self {
+        DisplayLossy {
+            map,
+            max_entries: None,
+            max_key_bytes: None,
+        }
+    }
+
+    /// Only show the first `max_entries` entries, followed by the number of
+    /// entries left out.
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    /// Only show the first `max_key_bytes` bytes of each key, followed by
+    /// `...` if the key is longer.
+    ///
+    /// A key shown as a string is cut at the last character which fits.
+    pub fn max_key_bytes(mut self, max_key_bytes: usize) -> Self {
+        self.max_key_bytes = Some(max_key_bytes);
+        self
+    }
+}
+
+/// Write the key as a quoted string if it is valid UTF-8, or as hex bytes
+/// otherwise, showing at most `max_key_bytes` bytes.
+fn write_key(
+    f: &mut fmt::Formatter<'_>,
+    key_bytes: &[u8],
+    max_key_bytes: Option<usize>,
+) -> fmt::Result {
+    let (shown_bytes, is_truncated) = match max_key_bytes {
+        Some(max_key_bytes) if key_bytes.len() > max_key_bytes => {
+            (&key_bytes[..max_key_bytes], true)
+        },
+        _ => (key_bytes, false),
+    };
+
+    match std::str::from_utf8(key_bytes) {
+        Ok(_) => {
+            // PANIC SAFETY: The whole key is valid, so the shown bytes can only be
+            // invalid where a character was cut at the end.
+            let shown = match std::str::from_utf8(shown_bytes) {
+                Ok(shown) => shown,
+                Err(err) => std::str::from_utf8(&shown_bytes[..err.valid_up_to()])
+                    .expect("bytes before the first error should be valid"),
+            };
+            write!(f, "{shown:?}")?;
+        },
+        Err(_) => {
+            f.write_str("0x")?;
+            for byte in shown_bytes {
+                write!(f, "{byte:02x}")?;
+            }
+        },
+    }
+
+    if is_truncated {
+        f.write_str("...")?;
+    }
+    Ok(())
+}
+
+impl<'a, K, V, A> fmt::Display for DisplayLossy<'a, K, V, A>
+where
+    K: AsBytes,
+    V: fmt::Debug,
+    A: Allocator,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let num_shown = self.max_entries.map_or(self.map.len(), |max_entries| {
+            max_entries.min(self.map.len())
+        });
+
+        f.write_str("{")?;
+        for (idx, (key, value)) in self.map.iter().take(num_shown).enumerate() {
+            if idx > 0 {
+                f.write_str(", ")?;
+            }
+            write_key(f, key.as_bytes(), self.max_key_bytes)?;
+            write!(f, ": {value:?}")?;
+        }
+
+        let num_hidden = self.map.len() - num_shown;
+        if num_hidden > 0 {
+            if num_shown > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "... {num_hidden} more")?;
+        }
+        f.write_str("}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_shown_as_text_or_hex() {
+        let mut map = TreeMap::<Box<[u8]>, u32>::new();
+        map.try_insert(Box::from(&b"apple"[..]), 1).unwrap();
+        map.try_insert(Box::from(&[0xFF, 0x00][..]), 2).unwrap();
+        map.try_insert(Box::from("h\u{e9}llo\n".as_bytes()), 3)
+            .unwrap();
+
+        assert_eq!(
+            map.display_lossy().to_string(),
+            r#"{"apple": 1, "héllo\n": 3, 0xff00: 2}"#
+        );
+        assert_eq!(
+            TreeMap::<Box<[u8]>, u32>::new().display_lossy().to_string(),
+            "{}"
+        );
+    }
+
+    #[test]
+    fn truncation_limits() {
+        let mut map = TreeMap::<Box<[u8]>, char>::new();
+        map.try_insert(Box::from("h\u{e9}llo".as_bytes()), 'a')
+            .unwrap();
+        map.try_insert(Box::from(&[0xFE, 0xFF, 0x01][..]), 'b')
+            .unwrap();
+        map.try_insert(Box::from(&b"zebra"[..]), 'c').unwrap();
+
+        // The cut falls inside the two bytes of the 'é'
+        assert_eq!(
+            map.display_lossy().max_key_bytes(2).to_string(),
+            r#"{"h"...: 'a', "ze"...: 'c', 0xfeff...: 'b'}"#
+        );
+        assert_eq!(
+            map.display_lossy().max_entries(1).to_string(),
+            r#"{"héllo": 'a', ... 2 more}"#
+        );
+        assert_eq!(
+            map.display_lossy().max_entries(0).to_string(),
+            "{... 3 more}"
+        );
+        assert_eq!(
+            map.display_lossy()
+                .max_entries(5)
+                .max_key_bytes(5)
+                .to_string(),
+            r#"{"héll"...: 'a', "zebra": 'c', 0xfeff01: 'b'}"#
+        );
+    }
+}
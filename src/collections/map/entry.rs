@@ -0,0 +1,301 @@
+use crate::{
+    allocator::{Allocator, Global},
+    search_for_entry_unchecked, AsBytes, EntrySearchResult, InsertPrefixError, InsertSearchResult,
+    LeafNode, NodePtr, TreeMap,
+};
+use std::fmt;
+
+/// A view into a single entry in a [`TreeMap`], which may either be vacant or
+/// occupied.
+///
+/// This is constructed from [`TreeMap::entry`] or [`TreeMap::try_entry`].
+pub enum Entry<'a, K, V, A: Allocator = Global> {
+    /// An occupied entry.
+    Occupied(OccupiedEntry<'a, K, V, A>),
+    /// A vacant entry.
+    Vacant(VacantEntry<'a, K, V, A>),
+}
+
+impl<'a, K: AsBytes, V, A: Allocator> Entry<'a, K, V, A> {
+    /// Search the map for the entry of the given key.
+    pub(crate) fn search(map: &'a mut TreeMap<K, V, A>, key: K) -> Result<Self, InsertPrefixError> {
+        let root = match map.root {
+            Some(root) => root,
+            None => {
+                return Ok(Entry::Vacant(VacantEntry {
+                    map,
+                    key,
+                    insert_point: None,
+                }))
+            },
+        };
+
+        // SAFETY: The mutable reference to the `TreeMap` guarantees that there are no
+        // concurrent writes to the tree during the search.
+        match unsafe { search_for_entry_unchecked(root, &key)? } {
+            EntrySearchResult::Occupied(leaf) => Ok(Entry::Occupied(OccupiedEntry { map, leaf })),
+            EntrySearchResult::Vacant(insert_point) => Ok(Entry::Vacant(VacantEntry {
+                map,
+                key,
+                insert_point: Some(insert_point),
+            })),
+        }
+    }
+
+    /// Ensures a value is in the entry by inserting the default if empty, and
+    /// returns a mutable reference to the value in the entry.
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default),
+        }
+    }
+
+    /// Ensures a value is in the entry by inserting the result of the default
+    /// function if empty, and returns a mutable reference to the value in the
+    /// entry.
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    /// Ensures a value is in the entry by inserting the result of the default
+    /// function if empty, and returns a mutable reference to the value in the
+    /// entry.
+    ///
+    /// The default function is given a reference to the key of the entry.
+    pub fn or_insert_with_key<F: FnOnce(&K) -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let value = default(entry.key());
+                entry.insert(value)
+            },
+        }
+    }
+
+    /// Ensures a value is in the entry by inserting the default value if
+    /// empty, and returns a mutable reference to the value in the entry.
+    pub fn or_default(self) -> &'a mut V
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    /// Provides in-place mutable access to an occupied entry before any
+    /// potential inserts into the map.
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self {
+        match self {
+            Entry::Occupied(mut entry) => {
+                f(entry.get_mut());
+                Entry::Occupied(entry)
+            },
+            Entry::Vacant(entry) => Entry::Vacant(entry),
+        }
+    }
+}
+
+impl<K, V, A: Allocator> Entry<'_, K, V, A> {
+    /// Returns a reference to the key of the entry.
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug, A: Allocator> fmt::Debug for Entry<'_, K, V, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Entry::Occupied(entry) => f.debug_tuple("Entry").field(entry).finish(),
+            Entry::Vacant(entry) => f.debug_tuple("Entry").field(entry).finish(),
+        }
+    }
+}
+
+/// A view into an occupied entry in a [`TreeMap`]. It is part of the [`Entry`]
+/// enum.
+pub struct OccupiedEntry<'a, K, V, A: Allocator = Global> {
+    map: &'a mut TreeMap<K, V, A>,
+    leaf: NodePtr<LeafNode<K, V>>,
+}
+
+impl<'a, K, V, A: Allocator> OccupiedEntry<'a, K, V, A> {
+    /// Gets a reference to the key in the entry.
+    pub fn key(&self) -> &K {
+        // SAFETY: The returned reference is bound to the reference of the entry, which
+        // holds the unique reference to the map.
+        unsafe { self.leaf.as_key_ref() }
+    }
+
+    /// Gets a reference to the value in the entry.
+    pub fn get(&self) -> &V {
+        // SAFETY: The returned reference is bound to the reference of the entry, which
+        // holds the unique reference to the map.
+        unsafe { self.leaf.as_value_ref() }
+    }
+
+    /// Gets a mutable reference to the value in the entry.
+    pub fn get_mut(&mut self) -> &mut V {
+        // SAFETY: The returned reference is bound to the unique reference of the entry,
+        // which holds the unique reference to the map.
+        unsafe { self.leaf.as_value_mut() }
+    }
+
+    /// Converts the entry into a mutable reference to its value, with a
+    /// lifetime bound to the map itself.
+    pub fn into_mut(self) -> &'a mut V {
+        // SAFETY: The entry is consumed, so the returned reference has the same
+        // exclusive access to the leaf that the entry had for the lifetime of the map
+        // borrow.
+        unsafe { self.leaf.as_value_mut() }
+    }
+
+    /// Sets the value of the entry, and returns the entry's old value.
+    pub fn insert(&mut self, value: V) -> V {
+        std::mem::replace(self.get_mut(), value)
+    }
+}
+
+impl<K: AsBytes, V, A: Allocator> OccupiedEntry<'_, K, V, A> {
+    /// Takes the value out of the entry, and returns it.
+    pub fn remove(self) -> V {
+        self.remove_entry().1
+    }
+
+    /// Takes the key and value out of the entry, and returns them.
+    pub fn remove_entry(self) -> (K, V) {
+        // The key bytes are copied, so that the leaf is not borrowed while it is being
+        // removed from the tree
+        let key_bytes = self.key().as_bytes().to_vec();
+        // PANIC SAFETY: The entry holds a unique reference to the map since it found
+        // the leaf, so the key is still in the map
+        self.map
+            .remove_entry_by_key_bytes(&key_bytes)
+            .expect("occupied entry should be present in the map")
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug, A: Allocator> fmt::Debug for OccupiedEntry<'_, K, V, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OccupiedEntry")
+            .field("key", self.key())
+            .field("value", self.get())
+            .finish()
+    }
+}
+
+/// A view into a vacant entry in a [`TreeMap`]. It is part of the [`Entry`]
+/// enum.
+pub struct VacantEntry<'a, K, V, A: Allocator = Global> {
+    map: &'a mut TreeMap<K, V, A>,
+    key: K,
+    /// The point in the tree where the key is inserted, or `None` if the map
+    /// is empty.
+    insert_point: Option<InsertSearchResult<K, V>>,
+}
+
+impl<'a, K, V, A: Allocator> VacantEntry<'a, K, V, A> {
+    /// Gets a reference to the key that would be used when inserting a value
+    /// through the entry.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Take ownership of the key.
+    pub fn into_key(self) -> K {
+        self.key
+    }
+}
+
+impl<'a, K: AsBytes, V, A: Allocator> VacantEntry<'a, K, V, A> {
+    /// Sets the value of the entry with the key of the entry, and returns a
+    /// mutable reference to the value.
+    pub fn insert(self, value: V) -> &'a mut V {
+        let leaf = self
+            .map
+            .insert_at_entry_point(self.insert_point, self.key, value);
+        // SAFETY: The returned reference is bound to the unique reference of the map,
+        // which is consumed along with the entry.
+        unsafe { leaf.as_value_mut() }
+    }
+}
+
+impl<K: fmt::Debug, V, A: Allocator> fmt::Debug for VacantEntry<'_, K, V, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("VacantEntry").field(self.key()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_counts_and_modifies() {
+        let mut map = TreeMap::<[u8; 2], u32>::new();
+        assert!(matches!(map.entry([0, 0]), Entry::Vacant(_)));
+        assert!(map.is_empty());
+
+        // Enough keys under each first byte to grow the inner nodes
+        for round in 0..3u32 {
+            for idx in 0..300u16 {
+                let key = (idx % 100 * 3).to_be_bytes();
+                *map.entry(key).or_insert(round) += 1;
+            }
+        }
+        assert_eq!(map.len(), 100);
+        assert!(map.values().all(|value| *value == 9));
+
+        map.entry(3u16.to_be_bytes())
+            .and_modify(|value| *value *= 10)
+            .or_insert(0);
+        map.entry(4u16.to_be_bytes())
+            .and_modify(|value| *value *= 10)
+            .or_insert_with_key(|key| u32::from(key[1]));
+        assert_eq!(map.get(&3u16.to_be_bytes()), Some(&90));
+        assert_eq!(map.get(&4u16.to_be_bytes()), Some(&4));
+        assert_eq!(*map.entry(5u16.to_be_bytes()).or_default(), 0);
+        assert_eq!(map.len(), 102);
+
+        match map.entry(6u16.to_be_bytes()) {
+            Entry::Occupied(mut entry) => {
+                assert_eq!(entry.key(), &[0, 6]);
+                assert_eq!(entry.insert(1), 9);
+                assert_eq!(entry.remove_entry(), ([0, 6], 1));
+            },
+            Entry::Vacant(_) => panic!("key should be present"),
+        }
+        match map.entry(6u16.to_be_bytes()) {
+            Entry::Occupied(_) => panic!("key should not be present"),
+            Entry::Vacant(entry) => assert_eq!(entry.into_key(), [0, 6]),
+        }
+        assert_eq!(map.len(), 101);
+        assert!(map.keys().zip(map.keys().skip(1)).all(|(a, b)| a < b));
+    }
+
+    #[test]
+    fn try_entry_rejects_prefix_keys() {
+        let mut map = TreeMap::<Box<[u8]>, char>::new();
+        map.try_insert(Box::new([1, 2, 3]), 'a').unwrap();
+        map.try_insert(Box::new([1, 3]), 'b').unwrap();
+
+        // Stops at the leaf, at the inner node prefix, and past the end of a leaf key
+        assert!(map.try_entry(Box::new([1, 2])).is_err());
+        assert!(map.try_entry(Box::new([1])).is_err());
+        assert!(map.try_entry(Box::new([1, 3, 4])).is_err());
+        assert_eq!(map.len(), 2);
+
+        let value = map.try_entry(Box::new([1, 2, 4])).unwrap().or_insert('c');
+        assert_eq!(*value, 'c');
+        assert_eq!(
+            map.try_entry(Box::new([1, 3])).unwrap().key().as_ref(),
+            &[1, 3]
+        );
+        assert_eq!(map.len(), 3);
+    }
+}
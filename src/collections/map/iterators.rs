@@ -1,5 +1,15 @@
-use crate::{LeafNode, NodePtr, TreeIterator, TreeMap};
-use std::marker::PhantomData;
+use crate::{
+    allocator::{Allocator, Global},
+    deallocate_tree_in, search_prefix_unchecked, take_children_in, AsBytes, FuzzyLeaves, LeafNode,
+    NodePtr, OpaqueNodePtr, RetainWalk, TreeIterator, TreeMap,
+};
+use std::{
+    collections::VecDeque,
+    iter::FusedIterator,
+    marker::PhantomData,
+    mem,
+    ops::{Bound, RangeBounds},
+};
 
 macro_rules! impl_ref_mut_iterator {
     ($iter_name:ty, $item:ty $(; $flag:tt)?) => {
@@ -39,6 +49,14 @@ macro_rules! impl_ref_mut_iterator {
                 })
             }
         }
+
+        impl<'m, K, V: 'm> ExactSizeIterator for $iter_name {
+            fn len(&self) -> usize {
+                self.size
+            }
+        }
+
+        impl<'m, K, V: 'm> FusedIterator for $iter_name {}
     };
 
     (items_are_sorted) => {
@@ -82,8 +100,16 @@ pub struct Iter<'m, K, V> {
     size: usize,
 }
 
+// SAFETY: The iterator only gives out shared references to the keys and values,
+// the same as sharing a reference to the `TreeMap`.
+unsafe impl<'m, K: Sync, V: Sync> Send for Iter<'m, K, V> {}
+
+// SAFETY: The iterator only gives out shared references to the keys and values,
+// the same as sharing a reference to the `TreeMap`.
+unsafe impl<'m, K: Sync, V: Sync> Sync for Iter<'m, K, V> {}
+
 impl<'m, K, V> Iter<'m, K, V> {
-    pub(crate) fn new(tree: &'m TreeMap<K, V>) -> Self {
+    pub(crate) fn new<A: Allocator>(tree: &'m TreeMap<K, V, A>) -> Self {
         Self {
             _marker: PhantomData,
             raw_iter: tree.root.map(|root| unsafe {
@@ -125,7 +151,7 @@ pub struct IterMut<'m, K, V> {
 }
 
 impl<'m, K, V> IterMut<'m, K, V> {
-    pub(crate) fn new(tree: &'m mut TreeMap<K, V>) -> Self {
+    pub(crate) fn new<A: Allocator>(tree: &'m mut TreeMap<K, V, A>) -> Self {
         Self {
             _marker: PhantomData,
             raw_iter: tree.root.map(|root| unsafe {
@@ -165,8 +191,16 @@ pub struct Keys<'m, K, V> {
     size: usize,
 }
 
+// SAFETY: The iterator only gives out shared references to the keys and values,
+// the same as sharing a reference to the `TreeMap`.
+unsafe impl<'m, K: Sync, V: Sync> Send for Keys<'m, K, V> {}
+
+// SAFETY: The iterator only gives out shared references to the keys and values,
+// the same as sharing a reference to the `TreeMap`.
+unsafe impl<'m, K: Sync, V: Sync> Sync for Keys<'m, K, V> {}
+
 impl<'m, K, V> Keys<'m, K, V> {
-    pub(crate) fn new(tree: &'m TreeMap<K, V>) -> Self {
+    pub(crate) fn new<A: Allocator>(tree: &'m TreeMap<K, V, A>) -> Self {
         Self {
             _marker: PhantomData,
             raw_iter: tree.root.map(|root| unsafe {
@@ -206,8 +240,16 @@ pub struct Values<'m, K, V> {
     size: usize,
 }
 
+// SAFETY: The iterator only gives out shared references to the keys and values,
+// the same as sharing a reference to the `TreeMap`.
+unsafe impl<'m, K: Sync, V: Sync> Send for Values<'m, K, V> {}
+
+// SAFETY: The iterator only gives out shared references to the keys and values,
+// the same as sharing a reference to the `TreeMap`.
+unsafe impl<'m, K: Sync, V: Sync> Sync for Values<'m, K, V> {}
+
 impl<'m, K, V> Values<'m, K, V> {
-    pub(crate) fn new(tree: &'m TreeMap<K, V>) -> Self {
+    pub(crate) fn new<A: Allocator>(tree: &'m TreeMap<K, V, A>) -> Self {
         Self {
             _marker: PhantomData,
             raw_iter: tree.root.map(|root| unsafe {
@@ -246,7 +288,7 @@ pub struct ValuesMut<'m, K, V> {
 }
 
 impl<'m, K, V> ValuesMut<'m, K, V> {
-    pub(crate) fn new(tree: &'m mut TreeMap<K, V>) -> Self {
+    pub(crate) fn new<A: Allocator>(tree: &'m mut TreeMap<K, V, A>) -> Self {
         Self {
             _marker: PhantomData,
             raw_iter: tree.root.map(|root| unsafe {
@@ -272,222 +314,671 @@ impl<'m, K, V> ValuesMut<'m, K, V> {
 
 impl_ref_mut_iterator!(ValuesMut<'m, K, V>, &'m mut V);
 
-/// An iterator over a sub-range of entries in a `TreeMap`.
+/// An iterator over the entries of a `TreeMap` whose keys start with a
+/// prefix, producing shared references to the key and value.
 ///
-/// This `struct` is created by the [`range`] method on `TreeMap`. See its
-/// documentation for more.
+/// This `struct` is created by the [`prefix_iter`] method on `TreeMap`. See
+/// its documentation for more.
 ///
-/// [`range`]: TreeMap::range
-pub struct Range<'a, K, V>(PhantomData<(&'a K, &'a V)>);
+/// [`prefix_iter`]: TreeMap::prefix_iter
+pub struct Prefix<'m, K, V> {
+    _marker: PhantomData<&'m TreeMap<K, V>>,
+    raw_iter: Option<TreeIterator<K, V>>,
+}
 
-impl<'a, K, V> Iterator for Range<'a, K, V> {
-    type Item = (&'a K, &'a V);
+// SAFETY: The iterator only gives out shared references to the keys and values,
+// the same as sharing a reference to the `TreeMap`.
+unsafe impl<'m, K: Sync, V: Sync> Send for Prefix<'m, K, V> {}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        todo!()
-    }
+// SAFETY: The iterator only gives out shared references to the keys and values,
+// the same as sharing a reference to the `TreeMap`.
+unsafe impl<'m, K: Sync, V: Sync> Sync for Prefix<'m, K, V> {}
 
-    fn last(mut self) -> Option<Self::Item>
+impl<'m, K, V> Prefix<'m, K, V> {
+    pub(crate) fn new<A: Allocator>(tree: &'m TreeMap<K, V, A>, prefix: &[u8]) -> Self
     where
-        Self: Sized,
+        K: AsBytes,
     {
-        self.next_back()
+        Self {
+            _marker: PhantomData,
+            // SAFETY: We have an immutable reference to the `TreeMap` which guarantees that
+            // there are not mutable references to the same `TreeMap` and no mutating
+            // operations on the nodes of this tree.
+            raw_iter: unsafe { prefix_subtree_iter(tree.root, prefix) },
+        }
     }
 
-    fn min(mut self) -> Option<Self::Item>
-    where
-        Self: Sized,
-        Self::Item: Ord,
-    {
-        self.next()
+    fn map_leaf_ptr_to_item(leaf_node_ptr: NodePtr<LeafNode<K, V>>) -> (&'m K, &'m V) {
+        // SAFETY: The references are bounded to the lifetime of the immutable
+        // reference to the `TreeMap` the iterator was created from, so there are no
+        // mutable references to the leaf while they are live.
+        unsafe { leaf_node_ptr.as_key_value_ref() }
     }
+}
+
+/// An iterator over the entries of a `TreeMap` whose keys start with a
+/// prefix, producing a mutable reference to the value.
+///
+/// This `struct` is created by the [`prefix_iter_mut`] method on `TreeMap`.
+/// See its documentation for more.
+///
+/// [`prefix_iter_mut`]: TreeMap::prefix_iter_mut
+pub struct PrefixMut<'m, K, V> {
+    _marker: PhantomData<&'m mut TreeMap<K, V>>,
+    raw_iter: Option<TreeIterator<K, V>>,
+}
 
-    fn max(mut self) -> Option<Self::Item>
+impl<'m, K, V> PrefixMut<'m, K, V> {
+    pub(crate) fn new<A: Allocator>(tree: &'m mut TreeMap<K, V, A>, prefix: &[u8]) -> Self
     where
-        Self: Sized,
-        Self::Item: Ord,
+        K: AsBytes,
     {
-        self.next_back()
+        Self {
+            _marker: PhantomData,
+            // SAFETY: We have a mutable reference to the `TreeMap` which guarantees that
+            // there are no other references (mutable or immutable) to the same `TreeMap`
+            // and thus no mutating operations on the nodes of this tree.
+            raw_iter: unsafe { prefix_subtree_iter(tree.root, prefix) },
+        }
     }
 
-    #[cfg(feature = "nightly")]
-    fn is_sorted(self) -> bool
-    where
-        Self: Sized,
-        Self::Item: PartialOrd,
-    {
-        true
+    fn map_leaf_ptr_to_item(leaf_node_ptr: NodePtr<LeafNode<K, V>>) -> (&'m K, &'m mut V) {
+        // SAFETY: The references are bounded to the lifetime of the mutable reference
+        // to the `TreeMap` the iterator was created from, and each leaf is only
+        // returned once, so there are no other references to the value.
+        unsafe { leaf_node_ptr.as_key_ref_value_mut() }
     }
 }
 
-impl<'a, K, V> DoubleEndedIterator for Range<'a, K, V> {
-    fn next_back(&mut self) -> Option<Self::Item> {
-        todo!()
-    }
+/// Create an iterator over the subtree which holds every key starting with
+/// the prefix, or `None` if no key starts with it.
+///
+/// # Safety
+///
+/// See the safety requirements on [`TreeIterator`].
+unsafe fn prefix_subtree_iter<K: AsBytes, V>(
+    root: Option<OpaqueNodePtr<K, V>>,
+    prefix: &[u8],
+) -> Option<TreeIterator<K, V>> {
+    // SAFETY: Covered by the safety requirements of this function
+    let (subtree, _) = unsafe { search_prefix_unchecked(root?, prefix)? };
+    // SAFETY: Covered by the safety requirements of this function
+    Some(unsafe { TreeIterator::new(subtree) })
 }
 
-/// A mutable iterator over a sub-range of entries in a `TreeMap`.
+macro_rules! impl_prefix_iterator {
+    ($iter_name:ty, $item:ty) => {
+        impl<'m, K, V: 'm> Iterator for $iter_name {
+            type Item = $item;
+
+            fn next(&mut self) -> Option<Self::Item> {
+                self.raw_iter
+                    .as_mut()?
+                    .next()
+                    .map(Self::map_leaf_ptr_to_item)
+            }
+
+            fn size_hint(&self) -> (usize, Option<usize>) {
+                self.raw_iter
+                    .as_ref()
+                    .map_or((0, Some(0)), |raw_iter| raw_iter.size_hint())
+            }
+
+            fn last(mut self) -> Option<Self::Item>
+            where
+                Self: Sized,
+            {
+                self.next_back()
+            }
+        }
+
+        impl<'m, K, V: 'm> DoubleEndedIterator for $iter_name {
+            fn next_back(&mut self) -> Option<Self::Item> {
+                self.raw_iter
+                    .as_mut()?
+                    .next_back()
+                    .map(Self::map_leaf_ptr_to_item)
+            }
+        }
+
+        impl<'m, K, V: 'm> FusedIterator for $iter_name {}
+    };
+}
+
+impl_prefix_iterator!(Prefix<'m, K, V>, (&'m K, &'m V));
+impl_prefix_iterator!(PrefixMut<'m, K, V>, (&'m K, &'m mut V));
+
+/// An iterator over the entries of a `TreeMap` whose keys are within an edit
+/// distance of a search key.
 ///
-/// This `struct` is created by the [`range_mut`] method on `TreeMap`. See
+/// This `struct` is created by the [`fuzzy_search`] method on `TreeMap`. See
 /// its documentation for more.
 ///
-/// [`range_mut`]: TreeMap::range_mut
-pub struct RangeMut<'a, K, V>(PhantomData<(&'a K, &'a mut V)>);
+/// [`fuzzy_search`]: TreeMap::fuzzy_search
+pub struct Fuzzy<'m, K, V> {
+    _marker: PhantomData<&'m TreeMap<K, V>>,
+    raw_iter: Option<FuzzyLeaves<K, V>>,
+}
+
+// SAFETY: The iterator only gives out shared references to the keys and values,
+// the same as sharing a reference to the `TreeMap`.
+unsafe impl<'m, K: Sync, V: Sync> Send for Fuzzy<'m, K, V> {}
 
-impl<'a, K, V> Iterator for RangeMut<'a, K, V> {
-    type Item = (&'a K, &'a mut V);
+// SAFETY: The iterator only gives out shared references to the keys and values,
+// the same as sharing a reference to the `TreeMap`.
+unsafe impl<'m, K: Sync, V: Sync> Sync for Fuzzy<'m, K, V> {}
+
+impl<'m, K, V> Fuzzy<'m, K, V> {
+    pub(crate) fn new<A: Allocator>(
+        tree: &'m TreeMap<K, V, A>,
+        key: &[u8],
+        max_edit_distance: usize,
+    ) -> Self {
+        Self {
+            _marker: PhantomData,
+            // SAFETY: We have an immutable reference to the `TreeMap` which guarantees that
+            // there are not mutable references to the same `TreeMap` and no mutating
+            // operations on the nodes of this tree.
+            raw_iter: tree
+                .root
+                .map(|root| unsafe { FuzzyLeaves::new(root, key, max_edit_distance) }),
+        }
+    }
+}
+
+impl<'m, K: AsBytes, V: 'm> Iterator for Fuzzy<'m, K, V> {
+    type Item = (&'m K, &'m V);
 
     fn next(&mut self) -> Option<Self::Item> {
-        todo!()
+        self.raw_iter.as_mut()?.next().map(|leaf_node_ptr| {
+            // SAFETY: The references are bounded to the lifetime of the immutable
+            // reference to the `TreeMap` the iterator was created from, so there are no
+            // mutable references to the leaf while they are live.
+            unsafe { leaf_node_ptr.as_key_value_ref() }
+        })
     }
+}
+
+impl<'m, K: AsBytes, V: 'm> FusedIterator for Fuzzy<'m, K, V> {}
+
+/// The leaves of a tree whose key bytes are within a pair of bounds, shared by
+/// [`Range`] and [`RangeMut`].
+struct RangeLeaves<K, V> {
+    raw_iter: Option<TreeIterator<K, V>>,
+    start: Bound<Box<[u8]>>,
+    end: Bound<Box<[u8]>>,
+}
 
-    fn last(mut self) -> Option<Self::Item>
+impl<K, V> RangeLeaves<K, V> {
+    /// Create an iterator over the leaves of the tree within the given
+    /// bounds.
+    ///
+    /// The tree iterator seeks to the bounds, so only the leaves on the path to
+    /// each bound have to be checked against them.
+    ///
+    /// # Safety
+    ///
+    /// See the safety requirements on [`TreeIterator`].
+    unsafe fn new<Q, R>(root: Option<OpaqueNodePtr<K, V>>, range: R) -> Self
     where
-        Self: Sized,
+        K: AsBytes,
+        Q: AsBytes + ?Sized,
+        R: RangeBounds<Q>,
     {
-        self.next_back()
+        let start = copy_bound(range.start_bound());
+        let end = copy_bound(range.end_bound());
+        match (&start, &end) {
+            (Bound::Excluded(start), Bound::Excluded(end)) if start == end => {
+                panic!("range start and end are equal and excluded in TreeMap")
+            },
+            (
+                Bound::Included(start) | Bound::Excluded(start),
+                Bound::Included(end) | Bound::Excluded(end),
+            ) if start > end => {
+                panic!("range start is greater than range end in TreeMap")
+            },
+            _ => {},
+        }
+
+        let raw_iter = root.map(|root| {
+            // SAFETY: Covered by the safety requirements of this function
+            unsafe { TreeIterator::seek_range(root, seek_bytes(&start), seek_bytes(&end)) }
+        });
+
+        RangeLeaves {
+            raw_iter,
+            start,
+            end,
+        }
     }
 
-    fn min(mut self) -> Option<Self::Item>
-    where
-        Self: Sized,
-        Self::Item: Ord,
-    {
-        self.next()
+    /// Return true if the key bytes are after the start bound.
+    fn is_after_start(&self, key_bytes: &[u8]) -> bool {
+        match &self.start {
+            Bound::Included(start) => key_bytes >= &**start,
+            Bound::Excluded(start) => key_bytes > &**start,
+            Bound::Unbounded => true,
+        }
     }
 
-    fn max(mut self) -> Option<Self::Item>
-    where
-        Self: Sized,
-        Self::Item: Ord,
-    {
-        self.next_back()
+    /// Return true if the key bytes are before the end bound.
+    fn is_before_end(&self, key_bytes: &[u8]) -> bool {
+        match &self.end {
+            Bound::Included(end) => key_bytes <= &**end,
+            Bound::Excluded(end) => key_bytes < &**end,
+            Bound::Unbounded => true,
+        }
     }
+}
 
-    #[cfg(feature = "nightly")]
-    fn is_sorted(self) -> bool
-    where
-        Self: Sized,
-        Self::Item: PartialOrd,
-    {
-        true
+impl<K: AsBytes, V> Iterator for RangeLeaves<K, V> {
+    type Item = NodePtr<LeafNode<K, V>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let leaf_node_ptr = self.raw_iter.as_mut()?.next()?;
+            // SAFETY: There are no mutations of the tree while the iterator is live, see
+            // the safety requirements on `RangeLeaves::new`.
+            let key_bytes = unsafe { leaf_node_ptr.as_key_ref() }.as_bytes();
+            if !self.is_before_end(key_bytes) {
+                // Every later leaf is past the end as well
+                self.raw_iter = None;
+                return None;
+            }
+            if self.is_after_start(key_bytes) {
+                return Some(leaf_node_ptr);
+            }
+        }
     }
 }
 
-impl<'a, K, V> DoubleEndedIterator for RangeMut<'a, K, V> {
+impl<K: AsBytes, V> DoubleEndedIterator for RangeLeaves<K, V> {
     fn next_back(&mut self) -> Option<Self::Item> {
-        todo!()
+        loop {
+            let leaf_node_ptr = self.raw_iter.as_mut()?.next_back()?;
+            // SAFETY: There are no mutations of the tree while the iterator is live, see
+            // the safety requirements on `RangeLeaves::new`.
+            let key_bytes = unsafe { leaf_node_ptr.as_key_ref() }.as_bytes();
+            if !self.is_after_start(key_bytes) {
+                // Every earlier leaf is before the start as well
+                self.raw_iter = None;
+                return None;
+            }
+            if self.is_before_end(key_bytes) {
+                return Some(leaf_node_ptr);
+            }
+        }
     }
 }
 
-/// An iterator produced by calling [`drain_filter`] on `TreeMap`. See its
+/// Return the key bytes that the tree iterator should seek to for a bound.
+fn seek_bytes(bound: &Bound<Box<[u8]>>) -> Option<&[u8]> {
+    match bound {
+        Bound::Included(key_bytes) | Bound::Excluded(key_bytes) => Some(key_bytes),
+        Bound::Unbounded => None,
+    }
+}
+
+/// Copy the bytes of a key bound, so that the bound can outlive the key.
+fn copy_bound<Q: AsBytes + ?Sized>(bound: Bound<&Q>) -> Bound<Box<[u8]>> {
+    match bound {
+        Bound::Included(key) => Bound::Included(key.as_bytes().into()),
+        Bound::Excluded(key) => Bound::Excluded(key.as_bytes().into()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+macro_rules! impl_range_iterator {
+    ($iter_name:ty, $item:ty) => {
+        impl<'m, K: AsBytes, V: 'm> Iterator for $iter_name {
+            type Item = $item;
+
+            fn next(&mut self) -> Option<Self::Item> {
+                self.leaves.next().map(Self::map_leaf_ptr_to_item)
+            }
+
+            fn last(mut self) -> Option<Self::Item>
+            where
+                Self: Sized,
+            {
+                self.next_back()
+            }
+
+            fn min(mut self) -> Option<Self::Item>
+            where
+                Self: Sized,
+                Self::Item: Ord,
+            {
+                self.next()
+            }
+
+            fn max(mut self) -> Option<Self::Item>
+            where
+                Self: Sized,
+                Self::Item: Ord,
+            {
+                self.next_back()
+            }
+
+            #[cfg(feature = "nightly")]
+            fn is_sorted(self) -> bool
+            where
+                Self: Sized,
+                Self::Item: PartialOrd,
+            {
+                true
+            }
+        }
+
+        impl<'m, K: AsBytes, V: 'm> DoubleEndedIterator for $iter_name {
+            fn next_back(&mut self) -> Option<Self::Item> {
+                self.leaves.next_back().map(Self::map_leaf_ptr_to_item)
+            }
+        }
+
+        impl<'m, K: AsBytes, V: 'm> FusedIterator for $iter_name {}
+    };
+}
+
+/// An iterator over a sub-range of entries in a `TreeMap`.
+///
+/// This `struct` is created by the [`range`] method on `TreeMap`. See its
 /// documentation for more.
 ///
-/// [`drain_filter`]: TreeMap::range_mut
-pub struct DrainFilter<K, V>(PhantomData<(K, V)>);
+/// [`range`]: TreeMap::range
+pub struct Range<'m, K, V> {
+    _marker: PhantomData<&'m TreeMap<K, V>>,
+    leaves: RangeLeaves<K, V>,
+}
 
-impl<K, V> Iterator for DrainFilter<K, V> {
-    type Item = (K, V);
+// SAFETY: The iterator only gives out shared references to the keys and values,
+// the same as sharing a reference to the `TreeMap`.
+unsafe impl<'m, K: Sync, V: Sync> Send for Range<'m, K, V> {}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        todo!()
-    }
+// SAFETY: The iterator only gives out shared references to the keys and values,
+// the same as sharing a reference to the `TreeMap`.
+unsafe impl<'m, K: Sync, V: Sync> Sync for Range<'m, K, V> {}
 
-    fn last(mut self) -> Option<Self::Item>
+impl<'m, K, V> Range<'m, K, V> {
+    pub(crate) fn new<Q, R, A: Allocator>(tree: &'m TreeMap<K, V, A>, range: R) -> Self
     where
-        Self: Sized,
+        K: AsBytes,
+        Q: AsBytes + ?Sized,
+        R: RangeBounds<Q>,
     {
-        self.next_back()
+        Self {
+            _marker: PhantomData,
+            // SAFETY: We have an immutable reference to the `TreeMap` which guarantees that
+            // there are not mutable references to the same `TreeMap` and no mutating
+            // operations on the nodes of this tree.
+            leaves: unsafe { RangeLeaves::new(tree.root, range) },
+        }
     }
 
-    fn min(mut self) -> Option<Self::Item>
-    where
-        Self: Sized,
-        Self::Item: Ord,
-    {
-        self.next()
+    fn map_leaf_ptr_to_item(leaf_node_ptr: NodePtr<LeafNode<K, V>>) -> (&'m K, &'m V) {
+        // SAFETY: The references are bounded to the lifetime of the immutable
+        // reference to the `TreeMap` the iterator was created from, so there are no
+        // mutable references to the leaf while they are live.
+        unsafe { leaf_node_ptr.as_key_value_ref() }
     }
+}
 
-    fn max(mut self) -> Option<Self::Item>
+impl_range_iterator!(Range<'m, K, V>, (&'m K, &'m V));
+
+/// A mutable iterator over a sub-range of entries in a `TreeMap`.
+///
+/// This `struct` is created by the [`range_mut`] method on `TreeMap`. See
+/// its documentation for more.
+///
+/// [`range_mut`]: TreeMap::range_mut
+pub struct RangeMut<'m, K, V> {
+    _marker: PhantomData<&'m mut TreeMap<K, V>>,
+    leaves: RangeLeaves<K, V>,
+}
+
+impl<'m, K, V> RangeMut<'m, K, V> {
+    pub(crate) fn new<Q, R, A: Allocator>(tree: &'m mut TreeMap<K, V, A>, range: R) -> Self
     where
-        Self: Sized,
-        Self::Item: Ord,
+        K: AsBytes,
+        Q: AsBytes + ?Sized,
+        R: RangeBounds<Q>,
     {
-        self.next_back()
+        Self {
+            _marker: PhantomData,
+            // SAFETY: We have a mutable reference to the `TreeMap` which guarantees that
+            // there are no other references (mutable or immutable) to the same `TreeMap`
+            // and thus no mutating operations on the nodes of this tree.
+            leaves: unsafe { RangeLeaves::new(tree.root, range) },
+        }
     }
 
-    #[cfg(feature = "nightly")]
-    fn is_sorted(self) -> bool
-    where
-        Self: Sized,
-        Self::Item: PartialOrd,
-    {
-        true
+    fn map_leaf_ptr_to_item(leaf_node_ptr: NodePtr<LeafNode<K, V>>) -> (&'m K, &'m mut V) {
+        // SAFETY: The references are bounded to the lifetime of the mutable reference
+        // to the `TreeMap` the iterator was created from, and each leaf is only
+        // returned once, so there are no other references to the value.
+        unsafe { leaf_node_ptr.as_key_ref_value_mut() }
     }
 }
 
-impl<K, V> DoubleEndedIterator for DrainFilter<K, V> {
-    fn next_back(&mut self) -> Option<Self::Item> {
-        todo!()
+impl_range_iterator!(RangeMut<'m, K, V>, (&'m K, &'m mut V));
+
+/// An iterator produced by calling [`drain_filter`] on `TreeMap`. See its
+/// documentation for more.
+///
+/// [`drain_filter`]: TreeMap::drain_filter
+pub struct DrainFilter<'m, K, V, F, A: Allocator = Global>
+where
+    F: FnMut(&K, &mut V) -> bool,
+{
+    tree: &'m mut TreeMap<K, V, A>,
+    walk: RetainWalk<K, V>,
+    pred: F,
+    /// The number of entries in the tree before the iterator was created.
+    num_entries: usize,
+    /// The number of entries removed so far.
+    num_removed: usize,
+    /// Set while the predicate runs, so that a panic in the predicate stops
+    /// the predicate from being called again when the iterator is dropped.
+    pred_panicked: bool,
+}
+
+impl<'m, K, V, F, A> DrainFilter<'m, K, V, F, A>
+where
+    F: FnMut(&K, &mut V) -> bool,
+    A: Allocator,
+{
+    pub(crate) fn new(tree: &'m mut TreeMap<K, V, A>, pred: F) -> Self {
+        // The tree is detached from the map until the iterator is dropped, so that
+        // leaking the iterator leaves an empty map instead of a partially compressed
+        // tree.
+        let num_entries = mem::replace(&mut tree.num_entries, 0);
+        let root = tree.root.take();
+
+        Self {
+            tree,
+            // SAFETY: The root was taken out of the map, which we hold a mutable
+            // reference to, so the tree is only accessed through this walk.
+            walk: unsafe { RetainWalk::new(root) },
+            pred,
+            num_entries,
+            num_removed: 0,
+            pred_panicked: false,
+        }
+    }
+}
+
+impl<K, V, F, A> Iterator for DrainFilter<'_, K, V, F, A>
+where
+    F: FnMut(&K, &mut V) -> bool,
+    A: Allocator,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let pred = &mut self.pred;
+        let pred_panicked = &mut self.pred_panicked;
+
+        // SAFETY: The nodes of the tree were allocated by the allocator of the map
+        let leaf_node_ptr = unsafe {
+            self.walk.next_rejected(
+                |key, value| {
+                    *pred_panicked = true;
+                    let remove = pred(key, value);
+                    *pred_panicked = false;
+                    remove
+                },
+                self.tree.resize_policy,
+                &self.tree.alloc,
+            )
+        }?;
+
+        self.num_removed += 1;
+        // SAFETY: The leaf was unlinked from the tree, so this is the only pointer to
+        // it, and it was allocated by the allocator of the map.
+        Some(
+            unsafe { NodePtr::deallocate_node_ptr_in(leaf_node_ptr, &self.tree.alloc) }
+                .into_entry(),
+        )
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.num_entries - self.num_removed))
     }
 }
 
+impl<K, V, F, A> Drop for DrainFilter<'_, K, V, F, A>
+where
+    F: FnMut(&K, &mut V) -> bool,
+    A: Allocator,
+{
+    fn drop(&mut self) {
+        /// Returns the tree to the map when dropped, so that the map keeps its
+        /// remaining entries even if the predicate panics while the rest of
+        /// the iterator is drained.
+        struct RestoreOnDrop<'a, 'm, K, V, F, A>(&'a mut DrainFilter<'m, K, V, F, A>)
+        where
+            F: FnMut(&K, &mut V) -> bool,
+            A: Allocator;
+
+        impl<K, V, F, A> Drop for RestoreOnDrop<'_, '_, K, V, F, A>
+        where
+            F: FnMut(&K, &mut V) -> bool,
+            A: Allocator,
+        {
+            fn drop(&mut self) {
+                let drain = &mut *self.0;
+                // Finish the walk without removing anything else, so that the tree is
+                // compressed again before it is returned to the map.
+                //
+                // SAFETY: The nodes of the tree were allocated by the allocator of the map
+                let leaf_node_ptr = unsafe {
+                    drain.walk.next_rejected(
+                        |_, _| false,
+                        drain.tree.resize_policy,
+                        &drain.tree.alloc,
+                    )
+                };
+                debug_assert!(leaf_node_ptr.is_none(), "no leaf should be removed");
+
+                drain.tree.root = drain.walk.root();
+                drain.tree.num_entries = drain.num_entries - drain.num_removed;
+            }
+        }
+
+        let guard = RestoreOnDrop(self);
+        if !guard.0.pred_panicked {
+            guard.0.for_each(drop);
+        }
+    }
+}
+
+impl<K, V, F, A> FusedIterator for DrainFilter<'_, K, V, F, A>
+where
+    F: FnMut(&K, &mut V) -> bool,
+    A: Allocator,
+{
+}
+
 /// An owning iterator over the keys of a `TreeMap`.
 ///
 /// This `struct` is created by the [`into_keys`] method on `TreeMap`.
 /// See its documentation for more.
 ///
 /// [`into_keys`]: TreeMap::into_keys
-pub struct IntoKeys<K, V>(IntoIter<K, V>);
+pub struct IntoKeys<K, V, A: Allocator = Global>(IntoIter<K, V, A>);
 
-impl<K, V> IntoKeys<K, V> {
-    pub(crate) fn new(tree: TreeMap<K, V>) -> Self {
+impl<K, V, A: Allocator> IntoKeys<K, V, A> {
+    pub(crate) fn new(tree: TreeMap<K, V, A>) -> Self {
         IntoKeys(IntoIter::new(tree))
     }
 }
 
-impl<K, V> Iterator for IntoKeys<K, V> {
+impl<K, V, A: Allocator> Iterator for IntoKeys<K, V, A> {
     type Item = K;
 
     fn next(&mut self) -> Option<Self::Item> {
         Some(self.0.next()?.0)
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
 }
 
-impl<K, V> DoubleEndedIterator for IntoKeys<K, V> {
+impl<K, V, A: Allocator> DoubleEndedIterator for IntoKeys<K, V, A> {
     fn next_back(&mut self) -> Option<Self::Item> {
         Some(self.0.next_back()?.0)
     }
 }
 
+impl<K, V, A: Allocator> ExactSizeIterator for IntoKeys<K, V, A> {
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl<K, V, A: Allocator> FusedIterator for IntoKeys<K, V, A> {}
+
 /// An owning iterator over the values of a `TreeMap`.
 ///
 /// This `struct` is created by the [`into_values`] method on `TreeMap`.
 /// See its documentation for more.
 ///
 /// [`into_values`]: TreeMap::into_values
-pub struct IntoValues<K, V>(IntoIter<K, V>);
+pub struct IntoValues<K, V, A: Allocator = Global>(IntoIter<K, V, A>);
 
-impl<K, V> IntoValues<K, V> {
-    pub(crate) fn new(tree: TreeMap<K, V>) -> Self {
+impl<K, V, A: Allocator> IntoValues<K, V, A> {
+    pub(crate) fn new(tree: TreeMap<K, V, A>) -> Self {
         IntoValues(IntoIter::new(tree))
     }
 }
 
-impl<K, V> Iterator for IntoValues<K, V> {
+impl<K, V, A: Allocator> Iterator for IntoValues<K, V, A> {
     type Item = V;
 
     fn next(&mut self) -> Option<Self::Item> {
         Some(self.0.next()?.1)
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
 }
 
-impl<K, V> DoubleEndedIterator for IntoValues<K, V> {
+impl<K, V, A: Allocator> DoubleEndedIterator for IntoValues<K, V, A> {
     fn next_back(&mut self) -> Option<Self::Item> {
         Some(self.0.next_back()?.1)
     }
 }
 
+impl<K, V, A: Allocator> ExactSizeIterator for IntoValues<K, V, A> {
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl<K, V, A: Allocator> FusedIterator for IntoValues<K, V, A> {}
+
 /// An owning iterator over the entries of a `TreeMap`.
 ///
 /// This `struct` is created by the [`into_iter`] method on `TreeMap`
@@ -495,30 +986,107 @@ impl<K, V> DoubleEndedIterator for IntoValues<K, V> {
 ///
 /// [`into_iter`]: IntoIterator::into_iter
 /// [`IntoIterator`]: core::iter::IntoIterator
-pub struct IntoIter<K, V>(TreeMap<K, V>);
+pub struct IntoIter<K, V, A: Allocator = Global> {
+    /// The subtrees which were not visited yet, in key order. An inner node is
+    /// deallocated as soon as its children are moved into this queue, so the
+    /// nodes do not form a valid tree while the iterator is live.
+    subtrees: VecDeque<OpaqueNodePtr<K, V>>,
+    /// The number of leaves left in the subtrees.
+    num_entries: usize,
+    alloc: A,
+}
 
-impl<K, V> IntoIter<K, V> {
-    pub(crate) fn new(tree: TreeMap<K, V>) -> Self {
-        IntoIter(tree)
+impl<K, V, A: Allocator> IntoIter<K, V, A> {
+    pub(crate) fn new(tree: TreeMap<K, V, A>) -> Self {
+        let num_entries = tree.len();
+        let (root, alloc) = tree.into_raw_with_allocator();
+
+        IntoIter {
+            subtrees: root.into_iter().collect(),
+            num_entries,
+            alloc,
+        }
     }
 }
 
-impl<K, V> Iterator for IntoIter<K, V> {
+impl<K, V, A: Allocator> Iterator for IntoIter<K, V, A> {
     type Item = (K, V);
 
     fn next(&mut self) -> Option<Self::Item> {
-        // TODO(#19): Optimize `IntoIter` by not maintaining a valid tree throughout
-        // iteration
-        self.0.pop_first()
+        loop {
+            let node_ptr = self.subtrees.pop_front()?;
+
+            if let Some(leaf_node_ptr) = node_ptr.cast::<LeafNode<K, V>>() {
+                self.num_entries -= 1;
+                // SAFETY: The leaf was removed from the queue, so this is the only pointer
+                // to it, and it was allocated by the allocator of the tree.
+                return Some(
+                    unsafe { NodePtr::deallocate_node_ptr_in(leaf_node_ptr, &self.alloc) }
+                        .into_entry(),
+                );
+            }
+
+            // SAFETY: The inner node was removed from the queue, so this is the only
+            // pointer to it, and it was allocated by the allocator of the tree.
+            let children = unsafe { take_children_in(node_ptr, &self.alloc) };
+            for (_, child) in children.into_iter().rev() {
+                self.subtrees.push_front(child);
+            }
+        }
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
-        (self.0.len(), Some(self.0.len()))
+        (self.num_entries, Some(self.num_entries))
     }
 }
 
-impl<K, V> DoubleEndedIterator for IntoIter<K, V> {
+impl<K, V, A: Allocator> DoubleEndedIterator for IntoIter<K, V, A> {
     fn next_back(&mut self) -> Option<Self::Item> {
-        self.0.pop_last()
+        loop {
+            let node_ptr = self.subtrees.pop_back()?;
+
+            if let Some(leaf_node_ptr) = node_ptr.cast::<LeafNode<K, V>>() {
+                self.num_entries -= 1;
+                // SAFETY: The leaf was removed from the queue, so this is the only pointer
+                // to it, and it was allocated by the allocator of the tree.
+                return Some(
+                    unsafe { NodePtr::deallocate_node_ptr_in(leaf_node_ptr, &self.alloc) }
+                        .into_entry(),
+                );
+            }
+
+            // SAFETY: The inner node was removed from the queue, so this is the only
+            // pointer to it, and it was allocated by the allocator of the tree.
+            let children = unsafe { take_children_in(node_ptr, &self.alloc) };
+            self.subtrees
+                .extend(children.into_iter().map(|(_, child)| child));
+        }
+    }
+}
+
+impl<K, V, A: Allocator> ExactSizeIterator for IntoIter<K, V, A> {
+    fn len(&self) -> usize {
+        self.num_entries
     }
 }
+
+impl<K, V, A: Allocator> FusedIterator for IntoIter<K, V, A> {}
+
+impl<K, V, A: Allocator> Drop for IntoIter<K, V, A> {
+    fn drop(&mut self) {
+        for node_ptr in self.subtrees.drain(..) {
+            // SAFETY: Every subtree in the queue is distinct, and its nodes are only
+            // reachable from the queue. The nodes were allocated by the allocator of the
+            // tree.
+            unsafe { deallocate_tree_in(node_ptr, &self.alloc) }
+        }
+    }
+}
+
+// SAFETY: The iterator uniquely owns the remaining nodes, so sending it to
+// another thread sends the keys, values, and the allocator.
+unsafe impl<K: Send, V: Send, A: Allocator + Send> Send for IntoIter<K, V, A> {}
+
+// SAFETY: The iterator does not allow any access to the remaining nodes
+// through a shared reference.
+unsafe impl<K: Sync, V: Sync, A: Allocator + Sync> Sync for IntoIter<K, V, A> {}
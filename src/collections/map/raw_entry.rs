@@ -0,0 +1,318 @@
+use crate::{
+    allocator::{Allocator, Global},
+    AsBytes, InsertPrefixError, LeafNode, NoPrefixesBytes, NodePtr, TreeMap,
+};
+use std::fmt;
+
+/// A builder for computing where in a [`TreeMap`] a key-value pair would be
+/// stored, using key bytes that were computed by the caller.
+///
+/// See the [`TreeMap::raw_entry`] docs for usage examples.
+pub struct RawEntryBuilder<'a, K, V, A: Allocator = Global> {
+    pub(crate) map: &'a TreeMap<K, V, A>,
+}
+
+impl<'a, K: AsBytes, V, A: Allocator> RawEntryBuilder<'a, K, V, A> {
+    /// Access an entry by the bytes of its key.
+    pub fn from_key_bytes(self, key_bytes: &[u8]) -> Option<(&'a K, &'a V)> {
+        let leaf = self.map.leaf_by_key_bytes(key_bytes)?;
+        // SAFETY: The returned references are bound to the shared reference of the map,
+        // so there are no concurrent writes to the leaf.
+        Some(unsafe { leaf.as_key_value_ref() })
+    }
+}
+
+impl<K, V, A: Allocator> fmt::Debug for RawEntryBuilder<'_, K, V, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RawEntryBuilder").finish_non_exhaustive()
+    }
+}
+
+/// A builder for computing where in a [`TreeMap`] a key-value pair would be
+/// stored, using key bytes that were computed by the caller.
+///
+/// See the [`TreeMap::raw_entry_mut`] docs for usage examples.
+pub struct RawEntryBuilderMut<'a, K, V, A: Allocator = Global> {
+    pub(crate) map: &'a mut TreeMap<K, V, A>,
+}
+
+impl<'a, K: AsBytes, V, A: Allocator> RawEntryBuilderMut<'a, K, V, A> {
+    /// Access an entry by the bytes of its key.
+    pub fn from_key_bytes(self, key_bytes: &[u8]) -> RawEntryMut<'a, K, V, A> {
+        match self.map.leaf_by_key_bytes(key_bytes) {
+            Some(leaf) => RawEntryMut::Occupied(RawOccupiedEntryMut {
+                map: self.map,
+                leaf,
+            }),
+            None => RawEntryMut::Vacant(RawVacantEntryMut { map: self.map }),
+        }
+    }
+}
+
+impl<K, V, A: Allocator> fmt::Debug for RawEntryBuilderMut<'_, K, V, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RawEntryBuilderMut").finish_non_exhaustive()
+    }
+}
+
+/// A view into a single entry in a [`TreeMap`], which may either be vacant or
+/// occupied.
+///
+/// This is constructed from [`TreeMap::raw_entry_mut`].
+pub enum RawEntryMut<'a, K, V, A: Allocator = Global> {
+    /// An occupied entry.
+    Occupied(RawOccupiedEntryMut<'a, K, V, A>),
+    /// A vacant entry.
+    Vacant(RawVacantEntryMut<'a, K, V, A>),
+}
+
+impl<K: fmt::Debug, V: fmt::Debug, A: Allocator> fmt::Debug for RawEntryMut<'_, K, V, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RawEntryMut::Occupied(entry) => f.debug_tuple("RawEntryMut").field(entry).finish(),
+            RawEntryMut::Vacant(entry) => f.debug_tuple("RawEntryMut").field(entry).finish(),
+        }
+    }
+}
+
+/// A view into an occupied entry in a [`TreeMap`]. It is part of the
+/// [`RawEntryMut`] enum.
+pub struct RawOccupiedEntryMut<'a, K, V, A: Allocator = Global> {
+    map: &'a mut TreeMap<K, V, A>,
+    leaf: NodePtr<LeafNode<K, V>>,
+}
+
+impl<'a, K, V, A: Allocator> RawOccupiedEntryMut<'a, K, V, A> {
+    /// Gets a reference to the key in the entry.
+    pub fn key(&self) -> &K {
+        // SAFETY: The returned reference is bound to the reference of the entry, which
+        // holds the unique reference to the map.
+        unsafe { self.leaf.as_key_ref() }
+    }
+
+    /// Gets a reference to the value in the entry.
+    pub fn get(&self) -> &V {
+        // SAFETY: The returned reference is bound to the reference of the entry, which
+        // holds the unique reference to the map.
+        unsafe { self.leaf.as_value_ref() }
+    }
+
+    /// Gets a reference to the key and value in the entry.
+    pub fn get_key_value(&self) -> (&K, &V) {
+        // SAFETY: The returned references are bound to the reference of the entry,
+        // which holds the unique reference to the map.
+        unsafe { self.leaf.as_key_value_ref() }
+    }
+
+    /// Gets a mutable reference to the value in the entry.
+    pub fn get_mut(&mut self) -> &mut V {
+        // SAFETY: The returned reference is bound to the unique reference of the entry,
+        // which holds the unique reference to the map.
+        unsafe { self.leaf.as_value_mut() }
+    }
+
+    /// Converts the entry into a mutable reference to its value, with a
+    /// lifetime bound to the map itself.
+    pub fn into_mut(self) -> &'a mut V {
+        // SAFETY: The entry is consumed, so the returned reference has the same
+        // exclusive access to the leaf that the entry had for the lifetime of the map
+        // borrow.
+        unsafe { self.leaf.as_value_mut() }
+    }
+
+    /// Converts the entry into a reference to its key and a mutable reference
+    /// to its value, with lifetimes bound to the map itself.
+    pub fn into_key_value(self) -> (&'a K, &'a mut V) {
+        // SAFETY: The entry is consumed, so the returned references have the same
+        // exclusive access to the leaf that the entry had for the lifetime of the map
+        // borrow.
+        unsafe { self.leaf.as_key_ref_value_mut() }
+    }
+
+    /// Sets the value of the entry, and returns the entry's old value.
+    pub fn insert(&mut self, value: V) -> V {
+        std::mem::replace(self.get_mut(), value)
+    }
+}
+
+impl<K: AsBytes, V, A: Allocator> RawOccupiedEntryMut<'_, K, V, A> {
+    /// Takes the value out of the entry, and returns it.
+    pub fn remove(self) -> V {
+        self.remove_entry().1
+    }
+
+    /// Takes the key and value out of the entry, and returns them.
+    pub fn remove_entry(self) -> (K, V) {
+        // The key bytes are copied, so that the leaf is not borrowed while it is being
+        // removed from the tree
+        let key_bytes = self.key().as_bytes().to_vec();
+        // PANIC SAFETY: The entry holds a unique reference to the map since it found
+        // the leaf, so the key is still in the map
+        self.map
+            .remove_entry_by_key_bytes(&key_bytes)
+            .expect("occupied entry should be present in the map")
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug, A: Allocator> fmt::Debug for RawOccupiedEntryMut<'_, K, V, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RawOccupiedEntryMut")
+            .field("key", self.key())
+            .field("value", self.get())
+            .finish()
+    }
+}
+
+/// A view into a vacant entry in a [`TreeMap`]. It is part of the
+/// [`RawEntryMut`] enum.
+///
+/// The key passed to the insertion methods must have the same bytes that the
+/// entry was looked up with. Otherwise, the key-value pair is inserted under
+/// the bytes of the given key, replacing and dropping any value already stored
+/// for that key.
+pub struct RawVacantEntryMut<'a, K, V, A: Allocator = Global> {
+    map: &'a mut TreeMap<K, V, A>,
+}
+
+impl<'a, K: AsBytes, V, A: Allocator> RawVacantEntryMut<'a, K, V, A> {
+    /// Sets the value of the entry with the given key, and returns references
+    /// to the inserted key and value.
+    ///
+    /// # Errors
+    ///  - If the map has an existing key, such that the new key is a prefix of
+    ///    the existing key or vice versa, then it returns an error.
+    pub fn try_insert(self, key: K, value: V) -> Result<(&'a K, &'a mut V), InsertPrefixError> {
+        let (_, leaf) = self.map.try_insert_leaf(key, value)?;
+        // SAFETY: The returned references are bound to the unique reference of the map,
+        // which is consumed along with the entry.
+        Ok(unsafe { leaf.as_key_ref_value_mut() })
+    }
+
+    /// Sets the value of the entry with the given key, and returns references
+    /// to the inserted key and value.
+    pub fn insert(self, key: K, value: V) -> (&'a K, &'a mut V)
+    where
+        K: NoPrefixesBytes,
+    {
+        match self.try_insert(key, value) {
+            Ok(entry) => entry,
+            Err(_err) => unreachable!(
+                "This branch should be unreachable because of the safety contract of \
+                 `NoPrefixesBytes`"
+            ),
+        }
+    }
+}
+
+impl<K, V, A: Allocator> fmt::Debug for RawVacantEntryMut<'_, K, V, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RawVacantEntryMut").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_entry_lookup_by_key_bytes() {
+        let mut map = TreeMap::<Box<[u8]>, usize>::new();
+        for idx in 0..300usize {
+            map.try_insert(Box::new([1, (idx / 256) as u8, idx as u8]), idx)
+                .unwrap();
+        }
+
+        assert_eq!(
+            map.raw_entry().from_key_bytes(&[1, 0, 7]),
+            Some((&Box::from([1, 0, 7]), &7))
+        );
+        assert_eq!(map.raw_entry().from_key_bytes(&[1, 1, 100]), None);
+        assert_eq!(map.raw_entry().from_key_bytes(&[1, 0]), None);
+        assert_eq!(map.raw_entry().from_key_bytes(&[1, 0, 7, 0]), None);
+        assert_eq!(
+            TreeMap::<Box<[u8]>, usize>::new()
+                .raw_entry()
+                .from_key_bytes(&[1]),
+            None
+        );
+    }
+
+    #[test]
+    fn raw_entry_mut_occupied_and_vacant() {
+        let mut map = TreeMap::<[u8; 4], u32>::new();
+
+        for idx in 0..100u32 {
+            match map.raw_entry_mut().from_key_bytes(&idx.to_be_bytes()) {
+                RawEntryMut::Occupied(_) => panic!("key {idx} should not be present"),
+                RawEntryMut::Vacant(entry) => {
+                    let (key, value) = entry.insert(idx.to_be_bytes(), idx * 2);
+                    assert_eq!(*key, idx.to_be_bytes());
+                    *value += 1;
+                },
+            }
+        }
+        assert_eq!(map.len(), 100);
+        assert_eq!(map.get(&10u32.to_be_bytes()), Some(&21));
+
+        match map.raw_entry_mut().from_key_bytes(&10u32.to_be_bytes()) {
+            RawEntryMut::Occupied(mut entry) => {
+                assert_eq!(entry.key(), &10u32.to_be_bytes());
+                assert_eq!(entry.get_key_value(), (&10u32.to_be_bytes(), &21));
+                assert_eq!(entry.insert(5), 21);
+                *entry.get_mut() += 1;
+                assert_eq!(*entry.into_mut(), 6);
+            },
+            RawEntryMut::Vacant(_) => panic!("key should be present"),
+        }
+        assert_eq!(map.get(&10u32.to_be_bytes()), Some(&6));
+
+        for idx in (0..100u32).step_by(2) {
+            match map.raw_entry_mut().from_key_bytes(&idx.to_be_bytes()) {
+                RawEntryMut::Occupied(entry) => {
+                    assert_eq!(entry.remove_entry().0, idx.to_be_bytes())
+                },
+                RawEntryMut::Vacant(_) => panic!("key {idx} should be present"),
+            }
+        }
+        assert_eq!(map.len(), 50);
+        assert!(map.values().all(|value| value % 4 == 3));
+        assert!(matches!(
+            map.raw_entry_mut().from_key_bytes(&4u32.to_be_bytes()),
+            RawEntryMut::Vacant(_)
+        ));
+
+        for idx in (1..100u32).step_by(2) {
+            match map.raw_entry_mut().from_key_bytes(&idx.to_be_bytes()) {
+                RawEntryMut::Occupied(entry) => {
+                    entry.remove();
+                },
+                RawEntryMut::Vacant(_) => panic!("key {idx} should be present"),
+            }
+        }
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn raw_vacant_try_insert_rejects_prefix_keys() {
+        let mut map = TreeMap::<Box<[u8]>, char>::new();
+        map.try_insert(Box::new([1, 2, 3]), 'a').unwrap();
+
+        match map.raw_entry_mut().from_key_bytes(&[1, 2]) {
+            RawEntryMut::Vacant(entry) => {
+                assert!(entry.try_insert(Box::new([1, 2]), 'b').is_err());
+            },
+            RawEntryMut::Occupied(_) => panic!("key should not be present"),
+        }
+        assert_eq!(map.len(), 1);
+
+        match map.raw_entry_mut().from_key_bytes(&[2]) {
+            RawEntryMut::Vacant(entry) => {
+                let (key, value) = entry.try_insert(Box::new([2]), 'c').unwrap();
+                assert_eq!(key.as_ref(), &[2]);
+                assert_eq!(*value, 'c');
+            },
+            RawEntryMut::Occupied(_) => panic!("key should not be present"),
+        }
+        assert_eq!(map.len(), 2);
+    }
+}
@@ -0,0 +1,480 @@
+//! Merkle-style content hashes of the entries of a [`TreeMap`].
+//!
+//! [`ContentDigests`] hashes every subtree of a map, so that two maps (for
+//! example, replicas on different machines) can check whether they hold the
+//! same entries under a prefix by comparing a single digest.
+//!
+//! The digest of a subtree only depends on the keys and values in it, and not
+//! on the node types used or the order of the operations that built the tree.
+//! The digest of a leaf covers its full key and value, while the digest of an
+//! inner node covers the key byte and digest of each child, in order. The
+//! crate does not provide a hash function, any cryptographic hash can be used
+//! by implementing [`ContentHasher`] for it.
+
+use crate::{
+    allocator::Allocator, minimum_unchecked, search_prefix_unchecked, AsBytes, ConcreteNodePtr,
+    InnerNode, LeafNode, NodePtr, OpaqueNodePtr, TreeIterator, TreeMap,
+};
+use std::{collections::HashMap, fmt, marker::PhantomData};
+
+/// A hash function used to compute [`ContentDigests`].
+///
+/// A hasher is created for each node, fed all the bytes describing the node
+/// and then finished into a digest.
+///
+/// # Examples
+///
+/// An implementation for a hash function from another crate usually just
+/// forwards to its API:
+///
+/// ```rust,ignore
+/// use blart::merkle::ContentHasher;
+/// use sha2::{Digest, Sha256};
+///
+/// struct Sha256Hasher(Sha256);
+///
+/// impl ContentHasher for Sha256Hasher {
+///     type Digest = [u8; 32];
+///
+///     fn new() -> Self {
+///         Sha256Hasher(Sha256::new())
+///     }
+///
+///     fn update(&mut self, bytes: &[u8]) {
+///         self.0.update(bytes);
+///     }
+///
+///     fn finish(self) -> Self::Digest {
+///         self.0.finalize().into()
+///     }
+/// }
+/// ```
+pub trait ContentHasher: Sized {
+    /// The output of the hash function.
+    type Digest: AsRef<[u8]> + Clone + Eq + fmt::Debug;
+
+    /// Create a hasher with an empty input.
+    fn new() -> Self;
+
+    /// Append the bytes to the input of the hasher.
+    fn update(&mut self, bytes: &[u8]);
+
+    /// Return the digest of all the bytes passed to [`ContentHasher::update`].
+    fn finish(self) -> Self::Digest;
+}
+
+/// A description of the entries under a prefix of a map, which is exchanged
+/// between replicas by [`delta_sync`](crate::delta_sync::delta_sync).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PrefixSummary<D> {
+    /// There are no entries under the prefix.
+    Empty,
+    /// There is a single entry under the prefix.
+    Leaf {
+        /// The key of the entry
+        key: Box<[u8]>,
+        /// The digest of the entry
+        digest: D,
+    },
+    /// There are multiple entries under the prefix.
+    Branch {
+        /// The longest prefix shared by all the entries, which starts with the
+        /// summarized prefix
+        common_prefix: Box<[u8]>,
+        /// The digest of all the entries
+        digest: D,
+        /// The distinct bytes that follow `common_prefix` in the keys, in
+        /// ascending order, and the digest of the entries under each
+        children: Vec<(u8, D)>,
+    },
+}
+
+impl<D> PrefixSummary<D> {
+    /// Returns the digest of all the entries under the prefix, or `None` if
+    /// there are no entries.
+    pub fn digest(&self) -> Option<&D> {
+        match self {
+            PrefixSummary::Empty => None,
+            PrefixSummary::Leaf { digest, .. } | PrefixSummary::Branch { digest, .. } => {
+                Some(digest)
+            },
+        }
+    }
+}
+
+/// The first input byte for the digest of a leaf.
+const LEAF_TAG: u8 = 0;
+/// The first input byte for the digest of an inner node.
+const INNER_TAG: u8 = 1;
+
+/// The digests of all the subtrees of a [`TreeMap`].
+///
+/// The digests are computed once when this is created, with a single pass over
+/// the tree. The map is borrowed for as long as the digests are kept, so they
+/// can not become stale.
+///
+/// # Examples
+///
+/// ```rust
+/// use blart::{merkle::ContentHasher, TreeMap};
+///
+/// /// The 64-bit FNV-1a hash, which is *not* suitable for untrusted replicas.
+/// struct Fnv(u64);
+///
+/// impl ContentHasher for Fnv {
+///     type Digest = [u8; 8];
+///
+///     fn new() -> Self {
+///         Fnv(0xcbf2_9ce4_8422_2325)
+///     }
+///
+///     fn update(&mut self, bytes: &[u8]) {
+///         for byte in bytes {
+///             self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x100_0000_01b3);
+///         }
+///     }
+///
+///     fn finish(self) -> Self::Digest {
+///         self.0.to_le_bytes()
+///     }
+/// }
+///
+/// let mut local = TreeMap::<[u8; 2], u32>::new();
+/// let mut remote = TreeMap::<[u8; 2], u32>::new();
+/// for idx in 0..100u16 {
+///     local.insert(idx.to_be_bytes(), u32::from(idx));
+///     remote.insert((99 - idx).to_be_bytes(), u32::from(99 - idx));
+/// }
+/// remote.insert([0, 7], 0);
+///
+/// let local_digests = local.content_digests::<Fnv>();
+/// let remote_digests = remote.content_digests::<Fnv>();
+///
+/// assert_ne!(local_digests.root_digest(), remote_digests.root_digest());
+/// assert_ne!(local_digests.prefix_digest(&[0]), remote_digests.prefix_digest(&[0]));
+/// assert_eq!(local_digests.prefix_digest(&[0, 8]), remote_digests.prefix_digest(&[0, 8]));
+/// ```
+pub struct ContentDigests<'a, K, V, H: ContentHasher> {
+    _marker: PhantomData<&'a TreeMap<K, V>>,
+    /// The root of the map the digests were computed for, if present.
+    root: Option<OpaqueNodePtr<K, V>>,
+    /// The digest of each inner node, leaf digests are computed when needed.
+    inner_digests: HashMap<OpaqueNodePtr<K, V>, H::Digest>,
+}
+
+impl<'a, K, V, H> ContentDigests<'a, K, V, H>
+where
+    K: AsBytes,
+    V: AsBytes,
+    H: ContentHasher,
+{
+    /// Compute the digests of all the subtrees of the given map.
+    pub fn new<A: Allocator>(map: &'a TreeMap<K, V, A>) -> Self {
+        let mut digests = ContentDigests {
+            _marker: PhantomData,
+            root: map.root(),
+            inner_digests: HashMap::new(),
+        };
+        if let Some(root) = digests.root {
+            digests.compute_digest(root);
+        }
+        digests
+    }
+
+    /// Returns the digest of all the entries in the map, or `None` if the map
+    /// is empty.
+    pub fn root_digest(&self) -> Option<H::Digest> {
+        self.node_digest(self.root?)
+    }
+
+    /// Returns the digest of all the entries whose key starts with the given
+    /// prefix, or `None` if there are no such entries.
+    ///
+    /// Two maps have the same digest for a prefix if and only if they have the
+    /// same entries under that prefix, barring hash collisions.
+    pub fn prefix_digest(&self, prefix: &[u8]) -> Option<H::Digest> {
+        let (subtree, _) = self.prefix_subtree(prefix)?;
+        self.node_digest(subtree)
+    }
+
+    /// Returns the digest of the entries under the prefix, along with the
+    /// digests of the entries under each of the next distinct bytes.
+    pub fn summary(&self, prefix: &[u8]) -> PrefixSummary<H::Digest> {
+        fn branch_summary<K, V, H, N>(
+            digests: &ContentDigests<'_, K, V, H>,
+            inner_ptr: NodePtr<N>,
+            depth: usize,
+        ) -> PrefixSummary<H::Digest>
+        where
+            K: AsBytes,
+            V: AsBytes,
+            H: ContentHasher,
+            N: InnerNode<Key = K, Value = V>,
+        {
+            // SAFETY: The map is borrowed for the lifetime of the digests, so there are no
+            // concurrent mutations of the tree.
+            let inner_node = unsafe { inner_ptr.as_ref() };
+            // SAFETY: See above
+            let min_leaf = unsafe { minimum_unchecked(inner_ptr.to_opaque()) };
+            // SAFETY: See above
+            let min_key = unsafe { min_leaf.as_key_ref() }.as_bytes();
+            // All keys in the subtree share the bytes up to the end of the compressed path
+            // of its root
+            let common_prefix = min_key[..depth + inner_node.header().prefix_size()].into();
+
+            // SAFETY: The iterator does not outlive the node reference, and there are no
+            // concurrent mutations of the node.
+            let children = unsafe { inner_node.iter() }
+                .map(|(key_byte, child)| {
+                    // PANIC SAFETY: All inner nodes have a digest, computed in `new`
+                    (key_byte, digests.node_digest(child).unwrap())
+                })
+                .collect();
+
+            PrefixSummary::Branch {
+                common_prefix,
+                // PANIC SAFETY: All inner nodes have a digest, computed in `new`
+                digest: digests.node_digest(inner_ptr.to_opaque()).unwrap(),
+                children,
+            }
+        }
+
+        let (subtree, depth) = match self.prefix_subtree(prefix) {
+            Some(subtree) => subtree,
+            None => return PrefixSummary::Empty,
+        };
+
+        match subtree.to_node_ptr() {
+            ConcreteNodePtr::Node4(inner_ptr) => branch_summary(self, inner_ptr, depth),
+            ConcreteNodePtr::Node16(inner_ptr) => branch_summary(self, inner_ptr, depth),
+            ConcreteNodePtr::Node48(inner_ptr) => branch_summary(self, inner_ptr, depth),
+            ConcreteNodePtr::Node256(inner_ptr) => branch_summary(self, inner_ptr, depth),
+            ConcreteNodePtr::LeafNode(leaf_ptr) => PrefixSummary::Leaf {
+                // SAFETY: The map is borrowed for the lifetime of the digests, so there are no
+                // concurrent mutations of the tree.
+                key: unsafe { leaf_ptr.as_key_ref() }.as_bytes().into(),
+                digest: digest_leaf::<K, V, H>(leaf_ptr),
+            },
+        }
+    }
+
+    /// Returns an iterator over the entries whose key starts with the prefix,
+    /// in key order.
+    pub fn prefix_entries(&self, prefix: &[u8]) -> impl Iterator<Item = (&'a K, &'a V)> {
+        self.prefix_subtree(prefix)
+            .into_iter()
+            .flat_map(|(subtree, _)| {
+                // SAFETY: The map is borrowed for the lifetime `'a`, so there are no mutations
+                // of the tree while the iterator or the returned references are
+                // live.
+                unsafe { TreeIterator::new(subtree) }
+            })
+            .map(|leaf_ptr| {
+                // SAFETY: See above
+                unsafe { leaf_ptr.as_key_value_ref() }
+            })
+    }
+
+    /// Return the root of the subtree which contains exactly the keys that
+    /// start with the prefix, and the number of key bytes used before reaching
+    /// it.
+    pub(crate) fn prefix_subtree(&self, prefix: &[u8]) -> Option<(OpaqueNodePtr<K, V>, usize)> {
+        let root = self.root?;
+        // SAFETY: The map is borrowed for the lifetime of the digests, so there are no
+        // concurrent mutations of the tree.
+        let (subtree, matched_len) = unsafe { search_prefix_unchecked(root, prefix)? };
+        Some((subtree, prefix.len() - matched_len))
+    }
+
+    /// Returns the digest of the given node of the tree.
+    pub(crate) fn node_digest(&self, node: OpaqueNodePtr<K, V>) -> Option<H::Digest> {
+        match node.to_node_ptr() {
+            ConcreteNodePtr::LeafNode(leaf_ptr) => Some(digest_leaf::<K, V, H>(leaf_ptr)),
+            _ => self.inner_digests.get(&node).cloned(),
+        }
+    }
+
+    fn compute_digest(&mut self, node: OpaqueNodePtr<K, V>) -> H::Digest {
+        fn digest_inner_node<K, V, H, N>(
+            digests: &mut ContentDigests<'_, K, V, H>,
+            inner_ptr: NodePtr<N>,
+        ) -> H::Digest
+        where
+            K: AsBytes,
+            V: AsBytes,
+            H: ContentHasher,
+            N: InnerNode<Key = K, Value = V>,
+        {
+            // SAFETY: The map is borrowed for the lifetime of the digests, so there are no
+            // concurrent mutations of the tree.
+            let inner_node = unsafe { inner_ptr.as_ref() };
+            let mut hasher = H::new();
+            hasher.update(&[INNER_TAG]);
+            // SAFETY: The iterator does not outlive the node reference, and there are no
+            // concurrent mutations of the node.
+            for (key_byte, child) in unsafe { inner_node.iter() } {
+                let child_digest = digests.compute_digest(child);
+                hasher.update(&[key_byte]);
+                hasher.update(child_digest.as_ref());
+            }
+
+            let digest = hasher.finish();
+            digests
+                .inner_digests
+                .insert(inner_ptr.to_opaque(), digest.clone());
+            digest
+        }
+
+        match node.to_node_ptr() {
+            ConcreteNodePtr::Node4(inner_ptr) => digest_inner_node(self, inner_ptr),
+            ConcreteNodePtr::Node16(inner_ptr) => digest_inner_node(self, inner_ptr),
+            ConcreteNodePtr::Node48(inner_ptr) => digest_inner_node(self, inner_ptr),
+            ConcreteNodePtr::Node256(inner_ptr) => digest_inner_node(self, inner_ptr),
+            ConcreteNodePtr::LeafNode(leaf_ptr) => digest_leaf::<K, V, H>(leaf_ptr),
+        }
+    }
+}
+
+/// Return the digest of the key and value of the leaf.
+fn digest_leaf<K, V, H>(leaf_ptr: NodePtr<LeafNode<K, V>>) -> H::Digest
+where
+    K: AsBytes,
+    V: AsBytes,
+    H: ContentHasher,
+{
+    // SAFETY: Leaf digests are only computed while the map is borrowed, so there
+    // are no concurrent mutations of the leaf.
+    let (key, value) = unsafe { leaf_ptr.as_key_value_ref() };
+    let (key, value) = (key.as_bytes(), value.as_bytes());
+
+    let mut hasher = H::new();
+    hasher.update(&[LEAF_TAG]);
+    // The lengths keep the boundary between the key and value unambiguous
+    hasher.update(&(key.len() as u64).to_le_bytes());
+    hasher.update(key);
+    hasher.update(&(value.len() as u64).to_le_bytes());
+    hasher.update(value);
+    hasher.finish()
+}
+
+impl<'a, K, V, H: ContentHasher> fmt::Debug for ContentDigests<'a, K, V, H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ContentDigests")
+            .field("num_inner_digests", &self.inner_digests.len())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::ResizePolicy;
+
+    /// The 64-bit FNV-1a hash.
+    pub(crate) struct Fnv(u64);
+
+    impl ContentHasher for Fnv {
+        type Digest = [u8; 8];
+
+        fn new() -> Self {
+            Fnv(0xcbf2_9ce4_8422_2325)
+        }
+
+        fn update(&mut self, bytes: &[u8]) {
+            for byte in bytes {
+                self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x100_0000_01b3);
+            }
+        }
+
+        fn finish(self) -> Self::Digest {
+            self.0.to_le_bytes()
+        }
+    }
+
+    #[test]
+    fn digests_only_depend_on_contents() {
+        let mut forward = TreeMap::<[u8; 2], u16>::new();
+        let mut backward = TreeMap::with_resize_policy(ResizePolicy::HYSTERESIS);
+        for idx in 0..1000u16 {
+            forward.insert(idx.to_be_bytes(), idx);
+            backward.insert((999 - idx).to_be_bytes(), 999 - idx);
+        }
+        // Shrinks inner nodes differently under the two policies
+        for idx in 0..1000u16 {
+            if idx % 256 >= 10 {
+                forward.remove(&idx.to_be_bytes());
+                backward.remove(&idx.to_be_bytes());
+            }
+        }
+        assert_eq!(forward.len(), 40);
+
+        let forward_digests = forward.content_digests::<Fnv>();
+        let backward_digests = backward.content_digests::<Fnv>();
+        assert!(forward_digests.root_digest().is_some());
+        assert_eq!(
+            forward_digests.root_digest(),
+            backward_digests.root_digest()
+        );
+        for prefix in [&[][..], &[0], &[1], &[3], &[2, 5], &[9]] {
+            assert_eq!(
+                forward_digests.prefix_digest(prefix),
+                backward_digests.prefix_digest(prefix),
+                "{prefix:?}"
+            );
+        }
+        assert_eq!(forward_digests.prefix_digest(&[9]), None);
+
+        // A single-entry prefix has the digest of the leaf
+        let mut single = TreeMap::new();
+        single.insert([2u8, 5], 517u16);
+        let single_digests = single.content_digests::<Fnv>();
+        assert_eq!(
+            single_digests.root_digest(),
+            forward_digests.prefix_digest(&[2, 5])
+        );
+        assert_eq!(
+            single_digests.prefix_digest(&[2]),
+            forward_digests.prefix_digest(&[2, 5])
+        );
+    }
+
+    #[test]
+    fn digests_detect_changed_entries() {
+        let mut map = TreeMap::<Box<[u8]>, String>::new();
+        for word in ["apple", "apricot", "banana", "blueberry", "cherry"] {
+            let mut key = word.as_bytes().to_vec();
+            key.push(0);
+            map.try_insert(key.into(), word.to_uppercase()).unwrap();
+        }
+        let before = ContentDigests::<_, _, Fnv>::new(&map);
+        let (root, apples, bananas) = (
+            before.root_digest(),
+            before.prefix_digest(b"ap"),
+            before.prefix_digest(b"b"),
+        );
+        drop(before);
+
+        *map.get_mut(b"apricot\0".as_ref()).unwrap() = String::from("Apricot");
+        let after = map.content_digests::<Fnv>();
+        assert_ne!(after.root_digest(), root);
+        assert_ne!(after.prefix_digest(b"ap"), apples);
+        assert_eq!(after.prefix_digest(b"b"), bananas);
+
+        // Moving bytes between the key and the value changes the digest
+        let mut split_a = TreeMap::<Box<[u8]>, Box<[u8]>>::new();
+        split_a.try_insert(Box::new([1, 2]), Box::new([3])).unwrap();
+        let mut split_b = TreeMap::<Box<[u8]>, Box<[u8]>>::new();
+        split_b.try_insert(Box::new([1]), Box::new([2, 3])).unwrap();
+        assert_ne!(
+            split_a.content_digests::<Fnv>().root_digest(),
+            split_b.content_digests::<Fnv>().root_digest()
+        );
+
+        assert_eq!(
+            TreeMap::<[u8; 1], u8>::new()
+                .content_digests::<Fnv>()
+                .root_digest(),
+            None
+        );
+    }
+}
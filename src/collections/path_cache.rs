@@ -0,0 +1,465 @@
+//! A [`TreeMap`] wrapper which resumes lookups from the path of the previous
+//! lookup.
+//!
+//! A search for a key reaches the same nodes as a search for any other key
+//! with the same leading bytes, until the point where the two keys differ. A
+//! [`PathCachedTreeMap`] remembers the nodes visited by the last lookup, and
+//! starts the next lookup from the deepest of those nodes that the new key
+//! also reaches. When consecutive lookups use nearby keys, most of the descent
+//! from the root is skipped.
+//!
+//! Inserts and removes also start from the cached path. They only replace
+//! nodes at and below the point where the tree changes, so only that part of
+//! the cached path is dropped afterwards. Mutations through
+//! [`PathCachedTreeMap::map_mut`] may change any node, so they clear the whole
+//! cache.
+
+use crate::{
+    search_from_unchecked, AsBytes, InsertPrefixError, LeafNode, NoPrefixesBytes, NodePtr,
+    OpaqueNodePtr, TreeMap,
+};
+use std::{borrow::Borrow, cell::RefCell, fmt};
+
+/// The nodes visited by the last lookup.
+struct PathCache<K, V> {
+    /// The bytes of the looked up key.
+    key_bytes: Vec<u8>,
+    /// The visited nodes, along with the number of key bytes used before
+    /// reaching each one, in order from the root.
+    path: Vec<(usize, OpaqueNodePtr<K, V>)>,
+}
+
+impl<K, V> PathCache<K, V> {
+    fn clear(&mut self) {
+        self.key_bytes.clear();
+        self.path.clear();
+    }
+
+    /// Replace the cached key with `key_bytes`, keeping only the cached nodes
+    /// that a search for the new key also reaches.
+    ///
+    /// Returns the deepest of those nodes along with its depth, or `root` at
+    /// depth 0 if there are none. The returned node is removed from the path,
+    /// so that a search from it pushes it again.
+    fn resume(
+        &mut self,
+        key_bytes: &[u8],
+        root: OpaqueNodePtr<K, V>,
+    ) -> (usize, OpaqueNodePtr<K, V>) {
+        let common_len = self
+            .key_bytes
+            .iter()
+            .zip(key_bytes)
+            .take_while(|(a, b)| a == b)
+            .count();
+        // The new key reaches every cached node whose depth is covered by the common
+        // bytes. The root at depth 0 is always reached.
+        let num_reached = self.path.partition_point(|(depth, _)| *depth <= common_len);
+        let start = if num_reached == 0 {
+            (0, root)
+        } else {
+            self.path[num_reached - 1]
+        };
+        self.path.truncate(num_reached.saturating_sub(1));
+
+        self.key_bytes.clear();
+        self.key_bytes.extend_from_slice(key_bytes);
+        start
+    }
+}
+
+/// A [`TreeMap`] which starts each lookup from the deepest node that it shares
+/// with the previous lookup.
+///
+/// The cache is kept in a [`RefCell`], so this map is not [`Sync`].
+///
+/// # Examples
+///
+/// ```rust
+/// use blart::path_cache::PathCachedTreeMap;
+///
+/// let mut map = PathCachedTreeMap::<[u8; 8], u64>::new();
+/// for idx in 0..1000u64 {
+///     map.insert(idx.to_be_bytes(), idx);
+/// }
+///
+/// // Adjacent keys share all but the last few bytes, so all but the first
+/// // lookup resume from deep in the tree
+/// for idx in 100..200u64 {
+///     assert_eq!(map.get(&idx.to_be_bytes()), Some(&idx));
+/// }
+/// ```
+pub struct PathCachedTreeMap<K, V> {
+    map: TreeMap<K, V>,
+    cache: RefCell<PathCache<K, V>>,
+}
+
+impl<K, V> PathCachedTreeMap<K, V> {
+    /// Create a new, empty map.
+    pub fn new() -> Self {
+        Self::from(TreeMap::new())
+    }
+
+    /// Returns a reference to the underlying map.
+    pub fn map(&self) -> &TreeMap<K, V> {
+        &self.map
+    }
+
+    /// Return the underlying map, discarding the cache.
+    pub fn into_map(self) -> TreeMap<K, V> {
+        self.map
+    }
+
+    /// Returns the number of elements in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if the map contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Returns a mutable reference to the underlying map.
+    ///
+    /// The cache is cleared, because the map may be mutated through the
+    /// reference.
+    pub fn map_mut(&mut self) -> &mut TreeMap<K, V> {
+        self.cache.get_mut().clear();
+        &mut self.map
+    }
+
+    /// Clear the map, removing all elements.
+    pub fn clear(&mut self) {
+        self.map_mut().clear();
+    }
+
+    /// Returns the number of key bytes of the last lookup which are covered by
+    /// the cached path.
+    ///
+    /// A lookup of a key which shares at least this many leading bytes with
+    /// the last key skips the whole cached path.
+    pub fn cached_depth(&self) -> usize {
+        self.cache
+            .borrow()
+            .path
+            .last()
+            .map_or(0, |(depth, _)| *depth)
+    }
+}
+
+impl<K, V> PathCachedTreeMap<K, V>
+where
+    K: AsBytes,
+{
+    fn find_leaf<Q>(&self, key: &Q) -> Option<NodePtr<LeafNode<K, V>>>
+    where
+        K: Borrow<Q>,
+        Q: AsBytes + ?Sized,
+    {
+        let root = self.map.root()?;
+        let mut cache = self.cache.borrow_mut();
+        let (start_depth, start) = cache.resume(key.as_bytes(), root);
+
+        // SAFETY: Every mutation drops the cached nodes that it may change, so the
+        // start node is still part of the tree. There are no concurrent mutations,
+        // because they require a unique reference to the map.
+        unsafe { search_from_unchecked(start, start_depth, key, &mut cache.path) }
+    }
+
+    /// Returns a reference to the value corresponding to the key.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: AsBytes + ?Sized,
+    {
+        Some(self.get_key_value(key)?.1)
+    }
+
+    /// Returns the key-value pair corresponding to the supplied key.
+    pub fn get_key_value<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: AsBytes + ?Sized,
+    {
+        let leaf = self.find_leaf(key)?;
+        // SAFETY: The returned references are bound to the shared reference of the map,
+        // so there are no concurrent writes to the leaf.
+        Some(unsafe { leaf.as_key_value_ref() })
+    }
+
+    /// Returns a mutable reference to the value corresponding to the key.
+    ///
+    /// Changing a value does not change the structure of the tree, so this
+    /// uses and keeps the cache.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: AsBytes + ?Sized,
+    {
+        let leaf = self.find_leaf(key)?;
+        // SAFETY: The returned reference is bound to the unique reference of the map,
+        // so there are no other accesses to the leaf.
+        Some(unsafe { leaf.as_value_mut() })
+    }
+
+    /// Returns `true` if the map contains a value for the specified key.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: AsBytes + ?Sized,
+    {
+        self.find_leaf(key).is_some()
+    }
+
+    /// Insert a key-value pair into the map, returning the previous value if
+    /// the key was already present.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V>
+    where
+        K: NoPrefixesBytes,
+    {
+        match self.try_insert(key, value) {
+            Ok(value) => value,
+            Err(_err) => unreachable!(
+                "This branch should be unreachable because of the safety contract of \
+                 `NoPrefixesBytes`"
+            ),
+        }
+    }
+
+    /// Insert a key-value pair into the map, returning the previous value if
+    /// the key was already present.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the given key is a prefix of an existing key, or an
+    /// existing key is a prefix of the given key.
+    pub fn try_insert(&mut self, key: K, value: V) -> Result<Option<V>, InsertPrefixError> {
+        let Some(root) = self.map.root() else {
+            return self.map.try_insert(key, value);
+        };
+
+        let cache = self.cache.get_mut();
+        let (start_depth, start_node) = cache.resume(key.as_bytes(), root);
+        let start_parent = cache
+            .path
+            .last()
+            .map(|(_, parent)| (*parent, key.as_bytes()[start_depth - 1]));
+
+        // SAFETY: The start node and its parent are still part of the tree, see
+        // `find_leaf`. The last key reached them after the first `start_depth`
+        // bytes, which this key shares, so they are on the search path of this key.
+        let (old_value, leaf_node_ptr, insert_point) = unsafe {
+            self.map
+                .try_insert_from(start_node, start_depth, start_parent, key, value)?
+        };
+
+        // The insert point may have been replaced, so the path to the new leaf is found
+        // again from the deepest cached node above it.
+        let (restart_depth, restart_node) = if insert_point != start_node {
+            (start_depth, start_node)
+        } else {
+            // PANIC SAFETY: The tree is not empty after an insert
+            cache
+                .path
+                .pop()
+                .unwrap_or_else(|| (0, self.map.root().expect("tree should have a root")))
+        };
+        // SAFETY: The restart node is above the insert point on the path of the key, so
+        // the insert did not change it. The tree is not modified while the key
+        // reference is live.
+        unsafe {
+            search_from_unchecked(
+                restart_node,
+                restart_depth,
+                leaf_node_ptr.as_key_ref(),
+                &mut cache.path,
+            )
+        };
+
+        Ok(old_value)
+    }
+
+    /// Removes a key from the map, returning the value at the key if the key
+    /// was previously in the map.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: AsBytes + ?Sized,
+    {
+        Some(self.remove_entry(key)?.1)
+    }
+
+    /// Removes a key from the map, returning the stored key and value if the
+    /// key was previously in the map.
+    pub fn remove_entry<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: AsBytes + ?Sized,
+    {
+        let leaf_node_ptr = self.find_leaf(key)?;
+        let key_bytes = key.as_bytes();
+
+        // The search ended at the leaf, so the path ends with the leaf, preceded by its
+        // parent and grandparent if they exist.
+        let path = &mut self.cache.get_mut().path;
+        let leaf_idx = path.len() - 1;
+        let with_child_key_byte = |parent_idx: usize| {
+            let (child_depth, _) = path[parent_idx + 1];
+            (key_bytes[child_depth - 1], path[parent_idx].1)
+        };
+        let parent_node_ptr = leaf_idx.checked_sub(1).map(with_child_key_byte);
+        let grandparent_node_ptr = leaf_idx.checked_sub(2).map(with_child_key_byte);
+
+        // The parent may be shrunk or removed, while the grandparent is only changed in
+        // place
+        path.truncate(leaf_idx.saturating_sub(1));
+
+        // SAFETY: The path to the leaf was just found in the tree, and the tree is
+        // uniquely borrowed.
+        Some(unsafe {
+            self.map
+                .remove_found_leaf(leaf_node_ptr, parent_node_ptr, grandparent_node_ptr)
+        })
+    }
+}
+
+impl<K, V> Default for PathCachedTreeMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> From<TreeMap<K, V>> for PathCachedTreeMap<K, V> {
+    fn from(map: TreeMap<K, V>) -> Self {
+        PathCachedTreeMap {
+            map,
+            cache: RefCell::new(PathCache {
+                key_bytes: Vec::new(),
+                path: Vec::new(),
+            }),
+        }
+    }
+}
+
+impl<K, V> fmt::Debug for PathCachedTreeMap<K, V>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PathCachedTreeMap")
+            .field("map", &self.map)
+            .finish_non_exhaustive()
+    }
+}
+
+// SAFETY: The cached nodes are owned by the map, so the wrapper can be sent
+// whenever the map can.
+unsafe impl<K: Send, V: Send> Send for PathCachedTreeMap<K, V> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequential_lookups_resume_from_cached_path() {
+        let mut map = PathCachedTreeMap::new();
+        for idx in 0..5000u32 {
+            map.insert(idx.to_be_bytes(), idx);
+        }
+        // Each insert caches the path to the new leaf
+        assert!(map.cached_depth() >= 3);
+
+        assert_eq!(map.get(&1234u32.to_be_bytes()), Some(&1234));
+        // The leaf of a 4 byte key is reached after using all but at most one byte
+        assert!(map.cached_depth() >= 3);
+
+        for idx in 0..5000u32 {
+            assert_eq!(map.get(&idx.to_be_bytes()), Some(&idx));
+        }
+        for idx in (0..6000u32).rev() {
+            let expected = (idx < 5000).then_some(idx);
+            assert_eq!(map.get(&idx.to_be_bytes()).copied(), expected);
+        }
+        assert!(!map.contains_key(&[0, 0, 255, 255]));
+
+        *map.get_mut(&7u32.to_be_bytes()).unwrap() = 70;
+        assert!(map.cached_depth() >= 3);
+        assert_eq!(map.get(&7u32.to_be_bytes()), Some(&70));
+    }
+
+    #[test]
+    fn mutations_keep_unchanged_part_of_cached_path() {
+        let mut map = PathCachedTreeMap::<Box<[u8]>, usize>::new();
+        for idx in 0..300usize {
+            let key = [1, 2, 3, (idx / 256) as u8, idx as u8];
+            map.try_insert(Box::new(key), idx).unwrap();
+        }
+
+        assert_eq!(map.get([1, 2, 3, 0, 4].as_ref()), Some(&4));
+        assert!(map.cached_depth() > 0);
+
+        // Removing keys shrinks the parent of the leaves, so only the root stays cached
+        for idx in 0..250 {
+            assert_eq!(map.remove([1, 2, 3, 0, idx as u8].as_ref()), Some(idx));
+            assert_eq!(map.cached_depth(), 0);
+            assert_eq!(map.get([1, 2, 3, 0, 255].as_ref()), Some(&255));
+            assert_eq!(map.cached_depth(), 5);
+        }
+        assert!(map.try_insert(Box::new([1, 2, 3]), 0).is_err());
+        assert_eq!(
+            map.remove_entry([1, 2, 3, 1, 0].as_ref()),
+            Some((Box::from([1, 2, 3, 1, 0]), 256))
+        );
+        assert_eq!(map.get([1, 2, 3, 1, 1].as_ref()), Some(&257));
+
+        // The insert point is the root, whose prefix is split
+        assert_eq!(map.try_insert(Box::new([1, 7]), 1000), Ok(None));
+        // The new root holds the prefix `[1]`, and the new leaf follows the `7` byte
+        assert_eq!(map.cached_depth(), 2);
+        assert_eq!(map.get([1, 2, 3, 1, 1].as_ref()), Some(&257));
+
+        map.clear();
+        assert_eq!(map.cached_depth(), 0);
+        assert_eq!(map.get([1, 2, 3, 1, 1].as_ref()), None);
+        assert!(map.is_empty());
+        assert!(map.into_map().is_empty());
+    }
+
+    #[test]
+    fn interleaved_mutations_match_btree_map() {
+        use crate::visitor::WellFormedChecker;
+        use std::collections::BTreeMap;
+
+        let mut map = PathCachedTreeMap::new();
+        let mut expected = BTreeMap::new();
+        let mut state = 7u32;
+        let mut next = || {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            state >> 8
+        };
+
+        for round in 0..20_000u32 {
+            // Mostly nearby keys, so that most operations resume from deep in the tree
+            let key = (round / 4 + next() % 64).to_be_bytes();
+            match next() % 4 {
+                0 => assert_eq!(map.remove(&key), expected.remove(&key)),
+                1 => assert_eq!(map.get(&key), expected.get(&key)),
+                _ => {
+                    assert_eq!(map.insert(key, round), expected.insert(key, round));
+                    assert_eq!(map.get(&key), Some(&round));
+                },
+            }
+
+            if round % 1000 == 0 {
+                assert!(
+                    unsafe { WellFormedChecker::check_tree(map.map().root().unwrap()) }.is_ok()
+                );
+            }
+        }
+
+        assert_eq!(map.len(), expected.len());
+        assert!(map.map().iter().eq(expected.iter()));
+    }
+}
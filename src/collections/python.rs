@@ -0,0 +1,455 @@
+//! Python bindings for [`TreeMap`], built with [pyo3].
+//!
+//! This module is only available with the `python` feature. It provides the
+//! `TreeMap` and `TreeSet` Python classes, which accept `bytes` and `str`
+//! keys, and support lookups, prefix queries and range queries.
+//!
+//! Every key is stored with a tag for its Python type, and its bytes are
+//! escaped and terminated so that no stored key is a prefix of another. This
+//! means that `b"ab"` and `b"abc"` can be stored in the same map, and that a
+//! `bytes` key never compares equal to a `str` key. All `bytes` keys sort
+//! before all `str` keys, and keys of the same type sort by their bytes, which
+//! for `str` keys is the order of their UTF-8 encoding.
+//!
+//! Values are arbitrary Python objects, which are stored by reference and
+//! never copied. For values that support the buffer protocol, like `bytes` or
+//! `bytearray`, `TreeMap.view` returns a `memoryview` of the stored value.
+//!
+//! To build the Python extension, create a `cdylib` crate which depends on
+//! `blart` with the `python` feature, and register the classes in its module:
+//!
+//! ```rust,ignore
+//! use pyo3::prelude::*;
+//!
+//! #[pymodule]
+//! fn blart(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
+//!     blart::python::register(m)
+//! }
+//! ```
+//!
+//! The classes can then be used like the builtin collections:
+//!
+//! ```python
+//! from blart import TreeMap
+//!
+//! tree = TreeMap()
+//! tree[b"apple"] = b"red"
+//! tree[b"apricot"] = b"orange"
+//! tree["banana"] = b"yellow"
+//!
+//! assert tree.prefix(b"ap") == [(b"apple", b"red"), (b"apricot", b"orange")]
+//! assert tree.range(b"b", None) == [("banana", b"yellow")]
+//! assert bytes(tree.view(b"apple")) == b"red"
+//! ```
+//!
+//! [pyo3]: https://pyo3.rs
+
+use crate::TreeMap;
+use pyo3::{
+    exceptions::{PyKeyError, PyTypeError, PyValueError},
+    prelude::*,
+    types::{PyBytes, PyIterator, PyList, PyMemoryView, PyString},
+};
+
+/// The tag of keys which were converted from `bytes`.
+const BYTES_TAG: u8 = 1;
+
+/// The tag of keys which were converted from `str`.
+const STR_TAG: u8 = 2;
+
+/// Add the `TreeMap` and `TreeSet` classes to the given Python module.
+pub fn register(module: &PyModule) -> PyResult<()> {
+    module.add_class::<PyTreeMap>()?;
+    module.add_class::<PyTreeSet>()?;
+    Ok(())
+}
+
+/// An ordered map from `bytes` or `str` keys to Python objects.
+#[pyclass(name = "TreeMap", module = "blart", unsendable)]
+#[derive(Default)]
+pub struct PyTreeMap {
+    map: TreeMap<Box<[u8]>, PyObject>,
+}
+
+#[pymethods]
+impl PyTreeMap {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    fn __len__(&self) -> usize {
+        self.map.len()
+    }
+
+    fn __contains__(&self, key: &PyAny) -> PyResult<bool> {
+        Ok(self.map.contains_key(encode_key(key)?.as_ref()))
+    }
+
+    fn __getitem__(&self, py: Python<'_>, key: &PyAny) -> PyResult<PyObject> {
+        match self.map.get(encode_key(key)?.as_ref()) {
+            Some(value) => Ok(value.clone_ref(py)),
+            None => Err(PyKeyError::new_err(key.to_object(py))),
+        }
+    }
+
+    fn __setitem__(&mut self, key: &PyAny, value: PyObject) -> PyResult<()> {
+        self.map
+            .try_insert(encode_key(key)?, value)
+            .map_err(|err| PyValueError::new_err(err.to_string()))?;
+        Ok(())
+    }
+
+    fn __delitem__(&mut self, py: Python<'_>, key: &PyAny) -> PyResult<()> {
+        match self.map.remove(encode_key(key)?.as_ref()) {
+            Some(_) => Ok(()),
+            None => Err(PyKeyError::new_err(key.to_object(py))),
+        }
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyResult<Py<PyIterator>> {
+        let keys = slf.keys(slf.py());
+        Ok(PyIterator::from_object(keys)?.into())
+    }
+
+    /// Return the value for the key, or `default` if the key is not present.
+    #[pyo3(signature = (key, default = None))]
+    fn get(
+        &self,
+        py: Python<'_>,
+        key: &PyAny,
+        default: Option<PyObject>,
+    ) -> PyResult<Option<PyObject>> {
+        Ok(self
+            .map
+            .get(encode_key(key)?.as_ref())
+            .map(|value| value.clone_ref(py))
+            .or(default))
+    }
+
+    /// Remove the key and return its value, or `default` if the key is not
+    /// present. Raises `KeyError` if the key is not present and there is no
+    /// default.
+    #[pyo3(signature = (key, default = None))]
+    fn pop(
+        &mut self,
+        py: Python<'_>,
+        key: &PyAny,
+        default: Option<PyObject>,
+    ) -> PyResult<PyObject> {
+        match (self.map.remove(encode_key(key)?.as_ref()), default) {
+            (Some(value), _) | (None, Some(value)) => Ok(value),
+            (None, None) => Err(PyKeyError::new_err(key.to_object(py))),
+        }
+    }
+
+    /// Return a `memoryview` of the value for the key, without copying it.
+    ///
+    /// Raises `TypeError` if the value does not support the buffer protocol.
+    fn view<'py>(&self, py: Python<'py>, key: &PyAny) -> PyResult<&'py PyMemoryView> {
+        match self.map.get(encode_key(key)?.as_ref()) {
+            Some(value) => PyMemoryView::from(value.as_ref(py)),
+            None => Err(PyKeyError::new_err(key.to_object(py))),
+        }
+    }
+
+    /// Remove all entries.
+    fn clear(&mut self) {
+        self.map.clear();
+    }
+
+    /// Return a list of the keys, in order.
+    fn keys<'py>(&self, py: Python<'py>) -> &'py PyList {
+        PyList::new(py, self.map.keys().map(|key| decode_key(py, key)))
+    }
+
+    /// Return a list of the values, in key order.
+    fn values<'py>(&self, py: Python<'py>) -> &'py PyList {
+        PyList::new(py, self.map.values())
+    }
+
+    /// Return a list of the `(key, value)` pairs, in key order.
+    fn items<'py>(&self, py: Python<'py>) -> &'py PyList {
+        entries_list(py, self.map.iter())
+    }
+
+    /// Return a list of the `(key, value)` pairs whose key starts with the
+    /// prefix, in key order.
+    ///
+    /// The prefix must have the same type as the keys it should match.
+    fn prefix<'py>(&self, py: Python<'py>, prefix: &PyAny) -> PyResult<&'py PyList> {
+        let prefix = encode_prefix(prefix)?;
+        Ok(entries_list(py, self.map.prefix_iter(&prefix)))
+    }
+
+    /// Return a list of the `(key, value)` pairs whose key is at least `start`
+    /// and less than `stop`, in key order.
+    ///
+    /// A bound of `None` leaves that end of the range open.
+    #[pyo3(signature = (start = None, stop = None))]
+    fn range<'py>(
+        &self,
+        py: Python<'py>,
+        start: Option<&PyAny>,
+        stop: Option<&PyAny>,
+    ) -> PyResult<&'py PyList> {
+        let (start, stop) = encode_bounds(start, stop)?;
+        Ok(entries_list(py, range_entries(&self.map, start, stop)))
+    }
+
+    fn __repr__(&self, py: Python<'_>) -> PyResult<String> {
+        let items = self.items(py);
+        Ok(format!("TreeMap({})", items.repr()?))
+    }
+}
+
+/// An ordered set of `bytes` or `str` keys.
+#[pyclass(name = "TreeSet", module = "blart", unsendable)]
+#[derive(Default)]
+pub struct PyTreeSet {
+    map: TreeMap<Box<[u8]>, ()>,
+}
+
+#[pymethods]
+impl PyTreeSet {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    fn __len__(&self) -> usize {
+        self.map.len()
+    }
+
+    fn __contains__(&self, key: &PyAny) -> PyResult<bool> {
+        Ok(self.map.contains_key(encode_key(key)?.as_ref()))
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyResult<Py<PyIterator>> {
+        let keys = slf.keys(slf.py());
+        Ok(PyIterator::from_object(keys)?.into())
+    }
+
+    /// Add the key to the set.
+    fn add(&mut self, key: &PyAny) -> PyResult<()> {
+        self.map
+            .try_insert(encode_key(key)?, ())
+            .map_err(|err| PyValueError::new_err(err.to_string()))?;
+        Ok(())
+    }
+
+    /// Remove the key from the set, if it is present.
+    fn discard(&mut self, key: &PyAny) -> PyResult<()> {
+        self.map.remove(encode_key(key)?.as_ref());
+        Ok(())
+    }
+
+    /// Remove the key from the set. Raises `KeyError` if it is not present.
+    fn remove(&mut self, py: Python<'_>, key: &PyAny) -> PyResult<()> {
+        match self.map.remove(encode_key(key)?.as_ref()) {
+            Some(()) => Ok(()),
+            None => Err(PyKeyError::new_err(key.to_object(py))),
+        }
+    }
+
+    /// Remove all keys.
+    fn clear(&mut self) {
+        self.map.clear();
+    }
+
+    /// Return a list of the keys, in order.
+    fn keys<'py>(&self, py: Python<'py>) -> &'py PyList {
+        PyList::new(py, self.map.keys().map(|key| decode_key(py, key)))
+    }
+
+    /// Return a list of the keys which start with the prefix, in order.
+    ///
+    /// The prefix must have the same type as the keys it should match.
+    fn prefix<'py>(&self, py: Python<'py>, prefix: &PyAny) -> PyResult<&'py PyList> {
+        let prefix = encode_prefix(prefix)?;
+        let keys: Vec<_> = self
+            .map
+            .prefix_iter(&prefix)
+            .map(|(key, _)| decode_key(py, key))
+            .collect();
+        Ok(PyList::new(py, keys))
+    }
+
+    /// Return a list of the keys which are at least `start` and less than
+    /// `stop`, in order.
+    ///
+    /// A bound of `None` leaves that end of the range open.
+    #[pyo3(signature = (start = None, stop = None))]
+    fn range<'py>(
+        &self,
+        py: Python<'py>,
+        start: Option<&PyAny>,
+        stop: Option<&PyAny>,
+    ) -> PyResult<&'py PyList> {
+        let (start, stop) = encode_bounds(start, stop)?;
+        let keys: Vec<_> = range_entries(&self.map, start, stop)
+            .map(|(key, _)| decode_key(py, key))
+            .collect();
+        Ok(PyList::new(py, keys))
+    }
+
+    fn __repr__(&self, py: Python<'_>) -> PyResult<String> {
+        let keys = self.keys(py);
+        Ok(format!("TreeSet({})", keys.repr()?))
+    }
+}
+
+/// Build a list of `(key, value)` tuples from the given entries.
+fn entries_list<'py, 'a>(
+    py: Python<'py>,
+    entries: impl Iterator<Item = (&'a Box<[u8]>, &'a PyObject)>,
+) -> &'py PyList {
+    let entries: Vec<_> = entries
+        .map(|(key, value)| (decode_key(py, key), value.clone_ref(py)))
+        .collect();
+    PyList::new(py, entries)
+}
+
+/// Returns an iterator over the entries whose encoded key is at least `start`
+/// and less than `stop`, in key order.
+///
+/// This walks the entries from the start of the map, until range iteration is
+/// available on the [`TreeMap`] itself.
+fn range_entries<V>(
+    map: &TreeMap<Box<[u8]>, V>,
+    start: Option<Box<[u8]>>,
+    stop: Option<Box<[u8]>>,
+) -> impl Iterator<Item = (&Box<[u8]>, &V)> {
+    map.iter()
+        .skip_while(move |(key, _)| matches!(&start, Some(start) if *key < start))
+        .take_while(move |(key, _)| !matches!(&stop, Some(stop) if *key >= stop))
+}
+
+/// Encode the optional bounds of a range query.
+fn encode_bounds(
+    start: Option<&PyAny>,
+    stop: Option<&PyAny>,
+) -> PyResult<(Option<Box<[u8]>>, Option<Box<[u8]>>)> {
+    Ok((
+        start.map(encode_key).transpose()?,
+        stop.map(encode_key).transpose()?,
+    ))
+}
+
+/// Return the type tag and the bytes of a Python key.
+fn key_parts(key: &PyAny) -> PyResult<(u8, &[u8])> {
+    if let Ok(bytes) = key.downcast::<PyBytes>() {
+        Ok((BYTES_TAG, bytes.as_bytes()))
+    } else if let Ok(string) = key.downcast::<PyString>() {
+        Ok((STR_TAG, string.to_str()?.as_bytes()))
+    } else {
+        Err(PyTypeError::new_err(format!(
+            "keys must be bytes or str, not {}",
+            key.get_type().name()?
+        )))
+    }
+}
+
+/// Encode a Python key into the bytes stored in the map.
+fn encode_key(key: &PyAny) -> PyResult<Box<[u8]>> {
+    let (tag, raw) = key_parts(key)?;
+    Ok(encode_raw_key(tag, raw))
+}
+
+/// Encode a Python key prefix, so that it is a prefix of the encoding of every
+/// key of the same type which starts with it.
+fn encode_prefix(prefix: &PyAny) -> PyResult<Vec<u8>> {
+    let (tag, raw) = key_parts(prefix)?;
+    Ok(encode_raw_prefix(tag, raw))
+}
+
+/// Convert the bytes stored in the map back into the Python key.
+fn decode_key(py: Python<'_>, key: &[u8]) -> PyObject {
+    let (tag, raw) = decode_raw_key(key);
+    if tag == STR_TAG {
+        // PANIC SAFETY: Keys with the `str` tag were encoded from a valid `str`
+        let string = std::str::from_utf8(&raw).expect("str key should be valid UTF-8");
+        PyString::new(py, string).into()
+    } else {
+        PyBytes::new(py, &raw).into()
+    }
+}
+
+/// Write the tag, followed by the raw bytes with every zero byte escaped as
+/// `0x00 0xFF`.
+fn encode_raw_prefix(tag: u8, raw: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(raw.len() + 3);
+    encoded.push(tag);
+    for &byte in raw {
+        encoded.push(byte);
+        if byte == 0 {
+            encoded.push(0xFF);
+        }
+    }
+    encoded
+}
+
+/// Encode the raw prefix, and terminate it with `0x00 0x00`.
+///
+/// The terminator never appears in the escaped bytes, so no encoded key is a
+/// prefix of another one. It also sorts before every escaped byte, so the
+/// encoding keeps the order of the raw bytes.
+fn encode_raw_key(tag: u8, raw: &[u8]) -> Box<[u8]> {
+    let mut encoded = encode_raw_prefix(tag, raw);
+    encoded.extend_from_slice(&[0, 0]);
+    encoded.into_boxed_slice()
+}
+
+/// Split an encoded key into its tag and its raw bytes.
+fn decode_raw_key(encoded: &[u8]) -> (u8, Vec<u8>) {
+    // PANIC SAFETY: Every encoded key starts with a tag
+    let (&tag, escaped) = encoded
+        .split_first()
+        .expect("encoded key should have a tag");
+    let mut raw = Vec::with_capacity(escaped.len());
+    let mut bytes = escaped.iter();
+    while let Some(&byte) = bytes.next() {
+        // A zero byte is either followed by `0xFF` for an escaped zero, or by a zero
+        // for the terminator
+        if byte == 0 && bytes.next() == Some(&0) {
+            break;
+        }
+        raw.push(byte);
+    }
+    (tag, raw)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoded_keys_round_trip() {
+        for raw in [&b""[..], b"a", b"\0", b"a\0b", b"\0\0", b"\xff\0"] {
+            let encoded = encode_raw_key(BYTES_TAG, raw);
+            assert_eq!(decode_raw_key(&encoded), (BYTES_TAG, raw.to_vec()));
+            assert!(encoded.starts_with(&encode_raw_prefix(BYTES_TAG, raw)));
+        }
+    }
+
+    #[test]
+    fn encoded_keys_keep_order_and_are_prefix_free() {
+        let mut raw_keys: Vec<&[u8]> = vec![
+            b"", b"\0", b"\0\0", b"\0\x01", b"\x01", b"a", b"a\0", b"a\0\xff", b"ab", b"\xff",
+        ];
+        raw_keys.sort();
+        let encoded: Vec<_> = raw_keys
+            .iter()
+            .map(|raw| encode_raw_key(BYTES_TAG, raw))
+            .collect();
+
+        for (idx, key) in encoded.iter().enumerate() {
+            for (other_idx, other) in encoded.iter().enumerate() {
+                assert_eq!(idx.cmp(&other_idx), key.cmp(other));
+                if idx != other_idx {
+                    assert!(!other.starts_with(key));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,472 @@
+//! Module containing implementations of the `TreeSet` and associated
+//! iterators.
+//!
+//! A [`TreeSet`] is a [`TreeMap`] whose values are `()`, so every operation
+//! goes through the same tree code as the map.
+
+use crate::{map, AsBytes, InsertPrefixError, NoPrefixesBytes, TreeMap};
+use std::{borrow::Borrow, fmt, iter::FusedIterator, ops::RangeBounds};
+
+/// An ordered set based on an adaptive radix tree.
+///
+/// Elements are ordered by their byte representation, see [`AsBytes`].
+///
+/// # Examples
+///
+/// ```rust
+/// use blart::set::TreeSet;
+///
+/// let mut set = TreeSet::<[u8; 2]>::new();
+/// assert!(set.insert([1, 2]));
+/// assert!(set.insert([0, 9]));
+/// assert!(!set.insert([1, 2]));
+///
+/// assert!(set.contains(&[0, 9]));
+/// assert_eq!(set.first(), Some(&[0, 9]));
+/// assert_eq!(set.iter().collect::<Vec<_>>(), [&[0, 9], &[1, 2]]);
+/// ```
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TreeSet<T> {
+    map: TreeMap<T, ()>,
+}
+
+impl<T> TreeSet<T> {
+    /// Create a new, empty set.
+    pub fn new() -> Self {
+        TreeSet {
+            map: TreeMap::new(),
+        }
+    }
+
+    /// Returns a reference to the underlying map, whose values are all `()`.
+    pub fn map(&self) -> &TreeMap<T, ()> {
+        &self.map
+    }
+
+    /// Return the underlying map, whose values are all `()`.
+    pub fn into_map(self) -> TreeMap<T, ()> {
+        self.map
+    }
+
+    /// Returns the number of elements in the set.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if the set contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Clear the set, removing all elements.
+    pub fn clear(&mut self) {
+        self.map.clear();
+    }
+
+    /// Returns a reference to the minimum element of the set, if any.
+    pub fn first(&self) -> Option<&T> {
+        Some(self.map.first_key_value()?.0)
+    }
+
+    /// Returns a reference to the maximum element of the set, if any.
+    pub fn last(&self) -> Option<&T> {
+        Some(self.map.last_key_value()?.0)
+    }
+
+    /// Removes and returns the minimum element of the set, if any.
+    pub fn pop_first(&mut self) -> Option<T> {
+        Some(self.map.pop_first()?.0)
+    }
+
+    /// Removes and returns the maximum element of the set, if any.
+    pub fn pop_last(&mut self) -> Option<T> {
+        Some(self.map.pop_last()?.0)
+    }
+
+    /// Gets an iterator that visits the elements of the set in ascending
+    /// order.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter(self.map.keys())
+    }
+}
+
+impl<T: AsBytes> TreeSet<T> {
+    /// Returns `true` if the set contains an element with the same bytes as
+    /// the value.
+    pub fn contains<Q>(&self, value: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: AsBytes + ?Sized,
+    {
+        self.map.contains_key(value)
+    }
+
+    /// Returns a reference to the element in the set with the same bytes as
+    /// the value, if any.
+    pub fn get<Q>(&self, value: &Q) -> Option<&T>
+    where
+        T: Borrow<Q>,
+        Q: AsBytes + ?Sized,
+    {
+        Some(self.map.get_key_value(value)?.0)
+    }
+
+    /// Adds a value to the set, returning `true` if it was not already
+    /// present.
+    ///
+    /// If the set already has an element with the same bytes, it is kept and
+    /// the new value is dropped.
+    pub fn insert(&mut self, value: T) -> bool
+    where
+        T: NoPrefixesBytes,
+    {
+        match self.try_insert(value) {
+            Ok(inserted) => inserted,
+            Err(_) => unreachable!(
+                "we use the `NoPrefixesBytes` bound on the key type to guarantee that no prefix \
+                 errors occur"
+            ),
+        }
+    }
+
+    /// Adds a value to the set, returning `true` if it was not already
+    /// present.
+    ///
+    /// # Errors
+    ///
+    /// If the value is a prefix of an element in the set, or an element is a
+    /// prefix of the value, then an error is returned and the set is
+    /// unchanged.
+    pub fn try_insert(&mut self, value: T) -> Result<bool, InsertPrefixError> {
+        if self.map.contains_key(&value) {
+            return Ok(false);
+        }
+
+        self.map.try_insert(value, ())?;
+        Ok(true)
+    }
+
+    /// Removes the element with the same bytes as the value, returning `true`
+    /// if it was present.
+    pub fn remove<Q>(&mut self, value: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: AsBytes + ?Sized,
+    {
+        self.map.remove(value).is_some()
+    }
+
+    /// Removes and returns the element with the same bytes as the value, if
+    /// any.
+    pub fn take<Q>(&mut self, value: &Q) -> Option<T>
+    where
+        T: Borrow<Q>,
+        Q: AsBytes + ?Sized,
+    {
+        Some(self.map.remove_entry(value)?.0)
+    }
+
+    /// Constructs a double-ended iterator over a sub-range of elements in the
+    /// set, see [`TreeMap::range`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::set::TreeSet;
+    ///
+    /// let set: TreeSet<u8> = [3, 5, 8].into_iter().collect();
+    /// assert_eq!(set.range(4..).collect::<Vec<_>>(), [&5, &8]);
+    /// assert_eq!(set.range(..=5).next_back(), Some(&5));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if range `start > end`. Panics if range `start == end` and both
+    /// bounds are `Excluded`.
+    pub fn range<Q, R>(&self, range: R) -> Range<'_, T>
+    where
+        T: Borrow<Q>,
+        Q: AsBytes + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        Range(self.map.range(range))
+    }
+
+    /// Returns an iterator over the elements of the set whose bytes start
+    /// with `prefix`, in ascending order.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::set::TreeSet;
+    ///
+    /// let mut set = TreeSet::<Box<[u8]>>::new();
+    /// for word in ["apple", "apricot", "banana"] {
+    ///     set.try_insert(word.as_bytes().into()).unwrap();
+    /// }
+    ///
+    /// assert_eq!(set.prefix(b"ap").count(), 2);
+    /// assert_eq!(set.prefix(b"c").count(), 0);
+    /// ```
+    pub fn prefix<'a>(&'a self, prefix: &[u8]) -> impl Iterator<Item = &'a T> + 'a {
+        self.map.prefix_iter(prefix).map(|(value, _)| value)
+    }
+
+    /// Moves all elements from other into self, leaving other empty.
+    ///
+    /// See [`TreeMap::append`] for details.
+    pub fn append(&mut self, other: &mut TreeSet<T>)
+    where
+        T: NoPrefixesBytes,
+    {
+        self.map.append(&mut other.map);
+    }
+
+    /// Splits the set into two at the given value. Returns everything after
+    /// the given value, including the value.
+    pub fn split_off<Q>(&mut self, value: &Q) -> TreeSet<T>
+    where
+        T: Borrow<Q>,
+        Q: AsBytes + ?Sized,
+    {
+        TreeSet {
+            map: self.map.split_off(value),
+        }
+    }
+
+    /// Retains only the elements specified by the predicate.
+    ///
+    /// In other words, remove all elements `e` for which `f(&e)` returns
+    /// false. The elements are visited in ascending order.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        self.map.retain(|value, _| f(value));
+    }
+}
+
+impl<T> Default for TreeSet<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone> Clone for TreeSet<T> {
+    fn clone(&self) -> Self {
+        TreeSet {
+            map: self.map.clone(),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for TreeSet<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+impl<T> From<TreeMap<T, ()>> for TreeSet<T> {
+    fn from(map: TreeMap<T, ()>) -> Self {
+        TreeSet { map }
+    }
+}
+
+impl<T: NoPrefixesBytes> Extend<T> for TreeSet<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for value in iter {
+            self.insert(value);
+        }
+    }
+}
+
+impl<T: NoPrefixesBytes> FromIterator<T> for TreeSet<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut set = TreeSet::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl<T: NoPrefixesBytes, const N: usize> From<[T; N]> for TreeSet<T> {
+    fn from(arr: [T; N]) -> Self {
+        arr.into_iter().collect()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a TreeSet<T> {
+    type IntoIter = Iter<'a, T>;
+    type Item = &'a T;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<T> IntoIterator for TreeSet<T> {
+    type IntoIter = IntoIter<T>;
+    type Item = T;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter(self.map.into_keys())
+    }
+}
+
+/// An iterator over the elements of a `TreeSet`.
+///
+/// This `struct` is created by the [`iter`] method on `TreeSet`. See its
+/// documentation for more.
+///
+/// [`iter`]: TreeSet::iter
+pub struct Iter<'s, T>(map::Keys<'s, T, ()>);
+
+impl<'s, T> Iterator for Iter<'s, T> {
+    type Item = &'s T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'s, T> DoubleEndedIterator for Iter<'s, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back()
+    }
+}
+
+impl<'s, T> ExactSizeIterator for Iter<'s, T> {
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl<'s, T> FusedIterator for Iter<'s, T> {}
+
+/// An owning iterator over the elements of a `TreeSet`.
+///
+/// This `struct` is created by the `into_iter` method on `TreeSet`, provided
+/// by the [`IntoIterator`] trait.
+pub struct IntoIter<T>(map::IntoKeys<T, ()>);
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<T> DoubleEndedIterator for IntoIter<T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back()
+    }
+}
+
+impl<T> ExactSizeIterator for IntoIter<T> {
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl<T> FusedIterator for IntoIter<T> {}
+
+/// An iterator over a sub-range of elements in a `TreeSet`.
+///
+/// This `struct` is created by the [`range`] method on `TreeSet`. See its
+/// documentation for more.
+///
+/// [`range`]: TreeSet::range
+pub struct Range<'s, T>(map::Range<'s, T, ()>);
+
+impl<'s, T: AsBytes> Iterator for Range<'s, T> {
+    type Item = &'s T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.0.next()?.0)
+    }
+}
+
+impl<'s, T: AsBytes> DoubleEndedIterator for Range<'s, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        Some(self.0.next_back()?.0)
+    }
+}
+
+impl<'s, T: AsBytes> FusedIterator for Range<'s, T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ordered_queries() {
+        let key = |x: u16| x.to_be_bytes();
+        let mut set: TreeSet<[u8; 2]> = (0..100).map(|x| key(x * 3)).collect();
+        assert_eq!(set.len(), 100);
+        assert_eq!(set.first(), Some(&key(0)));
+        assert_eq!(set.last(), Some(&key(297)));
+
+        assert_eq!(
+            set.range(key(10)..key(20)).copied().collect::<Vec<_>>(),
+            [key(12), key(15), key(18)]
+        );
+        assert_eq!(
+            set.range(key(10)..=key(21))
+                .rev()
+                .copied()
+                .collect::<Vec<_>>(),
+            [key(21), key(18), key(15), key(12)]
+        );
+        assert_eq!(set.range(key(298)..).next(), None);
+
+        assert_eq!(set.pop_first(), Some(key(0)));
+        assert_eq!(set.pop_last(), Some(key(297)));
+        assert!(!set.contains(&key(0)));
+        assert_eq!(set.take(&key(3)), Some(key(3)));
+        assert!(!set.remove(&key(3)));
+        assert_eq!(set.len(), 97);
+
+        let mut upper = set.split_off(&key(150));
+        assert_eq!(set.last(), Some(&key(147)));
+        assert_eq!(upper.first(), Some(&key(150)));
+        assert_eq!(set.len() + upper.len(), 97);
+        set.append(&mut upper);
+        assert!(upper.is_empty());
+        assert_eq!(set.len(), 97);
+        assert_eq!(set.iter().len(), 97);
+        upper = set.split_off(&key(150));
+        assert_eq!(set.len() + upper.len(), 97);
+
+        set.retain(|x| u16::from_be_bytes(*x) % 2 == 0);
+        assert!(set.iter().all(|x| u16::from_be_bytes(*x) % 2 == 0));
+        assert_eq!(set.iter().len(), set.len());
+    }
+
+    #[test]
+    fn prefix_and_insert_errors() {
+        let mut set = TreeSet::<Box<[u8]>>::new();
+        assert_eq!(set.try_insert(Box::from(&b"abc"[..])), Ok(true));
+        assert_eq!(set.try_insert(Box::from(&b"abd"[..])), Ok(true));
+        assert_eq!(set.try_insert(Box::from(&b"b"[..])), Ok(true));
+        assert_eq!(set.try_insert(Box::from(&b"abc"[..])), Ok(false));
+        assert!(set.try_insert(Box::from(&b"ab"[..])).is_err());
+        assert_eq!(set.len(), 3);
+
+        let ab: Vec<_> = set.prefix(b"ab").map(|value| &value[..]).collect();
+        assert_eq!(ab, [&b"abc"[..], &b"abd"[..]]);
+        assert_eq!(set.prefix(b"c").count(), 0);
+
+        let cloned = set.clone();
+        assert_eq!(cloned, set);
+        assert_eq!(format!("{set:?}"), "{[97, 98, 99], [97, 98, 100], [98]}");
+        assert_eq!(set.into_iter().next_back(), Some(Box::from(&b"b"[..])));
+    }
+}
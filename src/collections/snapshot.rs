@@ -0,0 +1,1110 @@
+//! Saving and loading a [`TreeMap`] as a compact stream of bytes.
+//!
+//! A snapshot starts with the magic bytes `BLRT`, a format version byte, and
+//! the number of entries as a little-endian `u64`. The entries follow in key
+//! order, each written as the [LEB128] encoded length of the key, the key
+//! bytes, the LEB128 encoded length of the value, and the value bytes.
+//!
+//! The value bytes are produced and consumed by caller-provided functions, so
+//! that any value type can be stored. Since the entries are stored in key
+//! order, reading a snapshot builds the tree bottom up with
+//! [`TreeMap::try_from_sorted_iter`], instead of inserting every key.
+//! [`serialize::to_bytes`] and [`serialize::from_bytes`] do the same with an
+//! in-memory buffer.
+//!
+//! [`write_prefix_snapshot`] writes only the entries whose keys start with a
+//! given prefix, in the same format. [`graft_snapshot`] loads such a snapshot
+//! into an existing map, replacing the entries that were stored under the
+//! prefix, so that one namespace of a shared map can be backed up and restored
+//! on its own.
+//!
+//! With the `tokio` feature enabled, [`write_snapshot_async`] and
+//! [`read_snapshot_async`] do the same on an `AsyncWrite` or `AsyncRead`,
+//! yielding to the runtime after every chunk of data, so that persisting a
+//! large map does not block the other tasks of the runtime.
+//!
+//! [LEB128]: https://en.wikipedia.org/wiki/LEB128
+//! [`serialize::to_bytes`]: crate::serialize::to_bytes
+//! [`serialize::from_bytes`]: crate::serialize::from_bytes
+
+use crate::{AsBytes, InsertPrefixError, TreeMap};
+use std::{error::Error, fmt, io};
+
+const MAGIC: [u8; 4] = *b"BLRT";
+
+const FORMAT_VERSION: u8 = 1;
+
+/// The length of the magic bytes, the version and the number of entries.
+const HEADER_LEN: usize = 13;
+
+/// The number of bytes which are encoded or read in one step, before they are
+/// written out or decoded.
+const CHUNK_LEN: usize = 64 * 1024;
+
+/// The error returned by a decoding function, when the bytes of an entry
+/// cannot be turned back into a key and a value.
+pub type DecodeEntryError = Box<dyn Error + Send + Sync>;
+
+/// Write a snapshot of all the entries in the map to the writer.
+///
+/// The `encode_value` function must append the bytes of the value to the
+/// given buffer.
+///
+/// # Errors
+///
+/// Returns any error from writing to `writer`.
+///
+/// # Examples
+///
+/// ```rust
+/// use blart::{snapshot, TreeMap};
+///
+/// let mut map = TreeMap::<[u8; 2], u32>::new();
+/// for idx in 0..100u16 {
+///     map.insert(idx.to_be_bytes(), u32::from(idx) * 3);
+/// }
+///
+/// let mut bytes = Vec::new();
+/// snapshot::write_snapshot(&map, &mut bytes, |value, buf| {
+///     buf.extend_from_slice(&value.to_le_bytes())
+/// })
+/// .unwrap();
+///
+/// let loaded = snapshot::read_snapshot(bytes.as_slice(), |key, value| {
+///     Ok((key.try_into()?, u32::from_le_bytes(value.try_into()?)))
+/// })
+/// .unwrap();
+/// assert_eq!(loaded, map);
+/// ```
+pub fn write_snapshot<K, V, W, F>(
+    map: &TreeMap<K, V>,
+    mut writer: W,
+    encode_value: F,
+) -> io::Result<()>
+where
+    K: AsBytes,
+    W: io::Write,
+    F: FnMut(&V, &mut Vec<u8>),
+{
+    let mut encoder = Encoder::new(map.len(), encode_value);
+
+    for (key, value) in map.iter() {
+        if encoder.push(key.as_bytes(), value) {
+            writer.write_all(encoder.buffer())?;
+            encoder.clear();
+        }
+    }
+
+    writer.write_all(encoder.buffer())?;
+    writer.flush()
+}
+
+/// Write a snapshot of the entries in the map whose keys start with `prefix`
+/// to the writer.
+///
+/// The snapshot has the same format as the one written by
+/// [`write_snapshot`], so it can be loaded on its own with [`read_snapshot`],
+/// or put back into a map with [`graft_snapshot`].
+///
+/// # Errors
+///
+/// Returns any error from writing to `writer`.
+///
+/// # Examples
+///
+/// ```rust
+/// use blart::{snapshot, TreeMap};
+///
+/// let mut map = TreeMap::<[u8; 2], u8>::new();
+/// for tenant in 0..4u8 {
+///     for idx in 0..10u8 {
+///         map.insert([tenant, idx], tenant * idx);
+///     }
+/// }
+///
+/// let mut bytes = Vec::new();
+/// snapshot::write_prefix_snapshot(&map, &[2], &mut bytes, |value, buf| buf.push(*value))
+///     .unwrap();
+///
+/// let tenant: TreeMap<[u8; 2], u8> = snapshot::read_snapshot(bytes.as_slice(), |key, value| {
+///     Ok((key.try_into()?, value[0]))
+/// })
+/// .unwrap();
+/// assert_eq!(tenant.len(), 10);
+/// assert!(tenant.keys().all(|key| key[0] == 2));
+/// ```
+pub fn write_prefix_snapshot<K, V, W, F>(
+    map: &TreeMap<K, V>,
+    prefix: &[u8],
+    mut writer: W,
+    encode_value: F,
+) -> io::Result<()>
+where
+    K: AsBytes,
+    W: io::Write,
+    F: FnMut(&V, &mut Vec<u8>),
+{
+    let mut encoder = Encoder::new(map.prefix_iter(prefix).count(), encode_value);
+
+    for (key, value) in map.prefix_iter(prefix) {
+        if encoder.push(key.as_bytes(), value) {
+            writer.write_all(encoder.buffer())?;
+            encoder.clear();
+        }
+    }
+
+    writer.write_all(encoder.buffer())?;
+    writer.flush()
+}
+
+/// Read a map from a snapshot that was written by [`write_snapshot`].
+///
+/// The `decode_entry` function receives the bytes of each key and value, and
+/// must return the decoded key and value.
+///
+/// The reader is read in chunks, so it may be read past the end of the
+/// snapshot if there is other data after it.
+///
+/// # Errors
+///
+///  - Returns [`SnapshotError::Io`] if reading fails.
+///  - Returns [`SnapshotError::InvalidFormat`] if the data is not a valid
+///    snapshot, it ends before the last entry, or the decoded keys are not in
+///    ascending order.
+///  - Returns [`SnapshotError::InvalidEntry`] if `decode_entry` fails, or if
+///    the decoded key cannot be inserted into the map because it is a prefix of
+///    another key, or another key is a prefix of it.
+pub fn read_snapshot<K, V, R, F>(reader: R, decode_entry: F) -> Result<TreeMap<K, V>, SnapshotError>
+where
+    K: AsBytes,
+    R: io::Read,
+    F: FnMut(&[u8], &[u8]) -> Result<(K, V), DecodeEntryError>,
+{
+    read_prefix_snapshot(reader, &[], decode_entry)
+}
+
+/// Read a snapshot that was written by [`write_prefix_snapshot`] into the
+/// map, replacing all the entries whose keys start with `prefix`.
+///
+/// The map is only changed once the whole snapshot has been read. The entries
+/// that were previously stored under the prefix are returned.
+///
+/// # Errors
+///
+///  - Returns the same errors as [`read_snapshot`].
+///  - Returns [`SnapshotError::InvalidFormat`] if a key in the snapshot does
+///    not start with `prefix`.
+///  - Returns [`SnapshotError::InvalidEntry`] if the map has a key which is a
+///    prefix of the keys in the snapshot, which cannot be stored alongside
+///    them.
+///
+/// # Examples
+///
+/// ```rust
+/// use blart::{snapshot, TreeMap};
+///
+/// let mut map = TreeMap::<[u8; 2], u8>::new();
+/// for tenant in 0..4u8 {
+///     for idx in 0..10u8 {
+///         map.insert([tenant, idx], tenant * idx);
+///     }
+/// }
+/// let backup = map.clone();
+///
+/// let mut bytes = Vec::new();
+/// snapshot::write_prefix_snapshot(&map, &[2], &mut bytes, |value, buf| buf.push(*value))
+///     .unwrap();
+///
+/// // Replace the entries of one tenant, and restore them from the snapshot
+/// map.insert([2, 0], 100);
+/// map.insert([2, 200], 100);
+/// let replaced = snapshot::graft_snapshot(&mut map, &[2], bytes.as_slice(), |key, value| {
+///     Ok((key.try_into()?, value[0]))
+/// })
+/// .unwrap();
+///
+/// assert_eq!(replaced.len(), 11);
+/// assert_eq!(map, backup);
+/// ```
+pub fn graft_snapshot<K, V, R, F>(
+    map: &mut TreeMap<K, V>,
+    prefix: &[u8],
+    reader: R,
+    decode_entry: F,
+) -> Result<TreeMap<K, V>, SnapshotError>
+where
+    K: AsBytes,
+    R: io::Read,
+    F: FnMut(&[u8], &[u8]) -> Result<(K, V), DecodeEntryError>,
+{
+    let loaded = read_prefix_snapshot(reader, prefix, decode_entry)?;
+    graft(map, prefix, loaded)
+}
+
+/// Read a snapshot, checking that every key starts with `prefix`.
+fn read_prefix_snapshot<K, V, R, F>(
+    mut reader: R,
+    prefix: &[u8],
+    decode_entry: F,
+) -> Result<TreeMap<K, V>, SnapshotError>
+where
+    K: AsBytes,
+    R: io::Read,
+    F: FnMut(&[u8], &[u8]) -> Result<(K, V), DecodeEntryError>,
+{
+    let mut header = [0; HEADER_LEN];
+    reader
+        .read_exact(&mut header)
+        .map_err(SnapshotError::from_header_read)?;
+
+    let mut loader = Loader::new(&header, prefix, decode_entry)?;
+    while !loader.decode_buffered()? {
+        let num_read = loop {
+            match reader.read(loader.read_buffer()) {
+                Ok(num_read) => break num_read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {},
+                Err(err) => return Err(SnapshotError::Io(err)),
+            }
+        };
+        loader.commit_read(num_read)?;
+    }
+
+    Ok(loader.finish())
+}
+
+/// Write a snapshot of all the entries in the map to the async writer, the
+/// same way as [`write_snapshot`].
+///
+/// The task yields to the runtime after each chunk of the snapshot is
+/// written.
+///
+/// # Errors
+///
+/// Returns any error from writing to `writer`.
+#[cfg(feature = "tokio")]
+pub async fn write_snapshot_async<K, V, W, F>(
+    map: &TreeMap<K, V>,
+    mut writer: W,
+    encode_value: F,
+) -> io::Result<()>
+where
+    K: AsBytes,
+    W: tokio::io::AsyncWrite + Unpin,
+    F: FnMut(&V, &mut Vec<u8>),
+{
+    use tokio::io::AsyncWriteExt;
+
+    let mut encoder = Encoder::new(map.len(), encode_value);
+
+    for (key, value) in map.iter() {
+        if encoder.push(key.as_bytes(), value) {
+            writer.write_all(encoder.buffer()).await?;
+            encoder.clear();
+            tokio::task::yield_now().await;
+        }
+    }
+
+    writer.write_all(encoder.buffer()).await?;
+    writer.flush().await
+}
+
+/// Write a snapshot of the entries in the map whose keys start with `prefix`
+/// to the async writer, the same way as [`write_prefix_snapshot`].
+///
+/// The task yields to the runtime after each chunk of the snapshot is
+/// written.
+///
+/// # Errors
+///
+/// Returns any error from writing to `writer`.
+#[cfg(feature = "tokio")]
+pub async fn write_prefix_snapshot_async<K, V, W, F>(
+    map: &TreeMap<K, V>,
+    prefix: &[u8],
+    mut writer: W,
+    encode_value: F,
+) -> io::Result<()>
+where
+    K: AsBytes,
+    W: tokio::io::AsyncWrite + Unpin,
+    F: FnMut(&V, &mut Vec<u8>),
+{
+    use tokio::io::AsyncWriteExt;
+
+    let mut encoder = Encoder::new(map.prefix_iter(prefix).count(), encode_value);
+
+    for (key, value) in map.prefix_iter(prefix) {
+        if encoder.push(key.as_bytes(), value) {
+            writer.write_all(encoder.buffer()).await?;
+            encoder.clear();
+            tokio::task::yield_now().await;
+        }
+    }
+
+    writer.write_all(encoder.buffer()).await?;
+    writer.flush().await
+}
+
+/// Read a map from a snapshot on the async reader, the same way as
+/// [`read_snapshot`].
+///
+/// The task yields to the runtime after each chunk of the snapshot is
+/// decoded.
+///
+/// # Errors
+///
+/// Returns the same errors as [`read_snapshot`].
+#[cfg(feature = "tokio")]
+pub async fn read_snapshot_async<K, V, R, F>(
+    reader: R,
+    decode_entry: F,
+) -> Result<TreeMap<K, V>, SnapshotError>
+where
+    K: AsBytes,
+    R: tokio::io::AsyncRead + Unpin,
+    F: FnMut(&[u8], &[u8]) -> Result<(K, V), DecodeEntryError>,
+{
+    read_prefix_snapshot_async(reader, &[], decode_entry).await
+}
+
+/// Read a snapshot from the async reader into the map, replacing all the
+/// entries whose keys start with `prefix`, the same way as
+/// [`graft_snapshot`].
+///
+/// The task yields to the runtime after each chunk of the snapshot is
+/// decoded.
+///
+/// # Errors
+///
+/// Returns the same errors as [`graft_snapshot`].
+#[cfg(feature = "tokio")]
+pub async fn graft_snapshot_async<K, V, R, F>(
+    map: &mut TreeMap<K, V>,
+    prefix: &[u8],
+    reader: R,
+    decode_entry: F,
+) -> Result<TreeMap<K, V>, SnapshotError>
+where
+    K: AsBytes,
+    R: tokio::io::AsyncRead + Unpin,
+    F: FnMut(&[u8], &[u8]) -> Result<(K, V), DecodeEntryError>,
+{
+    let loaded = read_prefix_snapshot_async(reader, prefix, decode_entry).await?;
+    graft(map, prefix, loaded)
+}
+
+/// Read a snapshot from the async reader, checking that every key starts
+/// with `prefix`.
+#[cfg(feature = "tokio")]
+async fn read_prefix_snapshot_async<K, V, R, F>(
+    mut reader: R,
+    prefix: &[u8],
+    decode_entry: F,
+) -> Result<TreeMap<K, V>, SnapshotError>
+where
+    K: AsBytes,
+    R: tokio::io::AsyncRead + Unpin,
+    F: FnMut(&[u8], &[u8]) -> Result<(K, V), DecodeEntryError>,
+{
+    use tokio::io::AsyncReadExt;
+
+    let mut header = [0; HEADER_LEN];
+    reader
+        .read_exact(&mut header)
+        .await
+        .map_err(SnapshotError::from_header_read)?;
+
+    let mut loader = Loader::new(&header, prefix, decode_entry)?;
+    while !loader.decode_buffered()? {
+        tokio::task::yield_now().await;
+
+        let num_read = reader
+            .read(loader.read_buffer())
+            .await
+            .map_err(SnapshotError::Io)?;
+        loader.commit_read(num_read)?;
+    }
+
+    Ok(loader.finish())
+}
+
+/// Replace the entries of the map whose keys start with `prefix` with the
+/// loaded entries, returning the replaced entries.
+fn graft<K, V>(
+    map: &mut TreeMap<K, V>,
+    prefix: &[u8],
+    loaded: TreeMap<K, V>,
+) -> Result<TreeMap<K, V>, SnapshotError>
+where
+    K: AsBytes,
+{
+    // Keys which do not start with the prefix can only conflict with the loaded
+    // keys if they are shorter than the prefix, and a prefix of it
+    if let Some((first_key, _)) = loaded.first_key_value() {
+        let has_conflict = (0..prefix.len())
+            .any(|prefix_len| map.leaf_by_key_bytes(&prefix[..prefix_len]).is_some());
+        if has_conflict {
+            return Err(SnapshotError::InvalidEntry {
+                index: 0,
+                source: Box::new(InsertPrefixError {
+                    byte_repr: first_key.as_bytes().into(),
+                }),
+            });
+        }
+    }
+
+    let replaced_keys: Vec<Box<[u8]>> = map
+        .prefix_iter(prefix)
+        .map(|(key, _)| key.as_bytes().into())
+        .collect();
+    let mut replaced = TreeMap::new();
+    for key_bytes in replaced_keys {
+        // PANIC SAFETY: The keys were just collected from the map, and they came from a
+        // map so they are not prefixes of each other
+        let (key, value) = map
+            .remove_entry_by_key_bytes(&key_bytes)
+            .expect("replaced key should be present in the map");
+        replaced
+            .try_insert(key, value)
+            .expect("keys of a map should not be prefixes of each other");
+    }
+
+    for (key, value) in loaded {
+        // PANIC SAFETY: The loaded keys all start with the prefix, the keys which
+        // started with the prefix were removed, and no remaining key is a
+        // prefix of the prefix
+        map.try_insert(key, value)
+            .expect("grafted key should not conflict with the remaining keys");
+    }
+
+    Ok(replaced)
+}
+
+/// An error from reading a snapshot.
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// Reading from the underlying reader failed
+    Io(io::Error),
+    /// The data is not a valid snapshot
+    InvalidFormat {
+        /// The byte offset in the snapshot at which the problem was found
+        offset: u64,
+        /// A description of the problem
+        reason: &'static str,
+    },
+    /// An entry could not be decoded, or could not be inserted into the map
+    InvalidEntry {
+        /// The position of the entry in the snapshot
+        index: u64,
+        /// The underlying error
+        source: DecodeEntryError,
+    },
+}
+
+impl SnapshotError {
+    fn from_header_read(err: io::Error) -> Self {
+        if err.kind() == io::ErrorKind::UnexpectedEof {
+            SnapshotError::InvalidFormat {
+                offset: 0,
+                reason: "the snapshot header is truncated",
+            }
+        } else {
+            SnapshotError::Io(err)
+        }
+    }
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(err) => write!(f, "Failed to read snapshot: {err}"),
+            SnapshotError::InvalidFormat { offset, reason } => {
+                write!(f, "Invalid snapshot at byte offset {offset}: {reason}")
+            },
+            SnapshotError::InvalidEntry { index, source } => {
+                write!(f, "Invalid snapshot entry at index {index}: {source}")
+            },
+        }
+    }
+}
+
+impl Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SnapshotError::Io(err) => Some(err),
+            SnapshotError::InvalidFormat { .. } => None,
+            SnapshotError::InvalidEntry { source, .. } => Some(source.as_ref()),
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> Self {
+        SnapshotError::Io(err)
+    }
+}
+
+/// Encodes entries into a buffer, which is written out by the caller.
+struct Encoder<F> {
+    buffer: Vec<u8>,
+    value_bytes: Vec<u8>,
+    encode_value: F,
+}
+
+impl<F> Encoder<F> {
+    fn new(num_entries: usize, encode_value: F) -> Self {
+        let mut buffer = Vec::with_capacity(CHUNK_LEN);
+        buffer.extend_from_slice(&MAGIC);
+        buffer.push(FORMAT_VERSION);
+        buffer.extend_from_slice(&(num_entries as u64).to_le_bytes());
+
+        Encoder {
+            buffer,
+            value_bytes: Vec::new(),
+            encode_value,
+        }
+    }
+
+    /// Encode an entry, returning `true` if the buffer should be written out.
+    fn push<V>(&mut self, key: &[u8], value: &V) -> bool
+    where
+        F: FnMut(&V, &mut Vec<u8>),
+    {
+        self.value_bytes.clear();
+        (self.encode_value)(value, &mut self.value_bytes);
+
+        write_leb128(&mut self.buffer, key.len() as u64);
+        self.buffer.extend_from_slice(key);
+        write_leb128(&mut self.buffer, self.value_bytes.len() as u64);
+        self.buffer.extend_from_slice(&self.value_bytes);
+
+        self.buffer.len() >= CHUNK_LEN
+    }
+
+    fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    fn clear(&mut self) {
+        self.buffer.clear();
+    }
+}
+
+/// Decodes entries from buffered chunks of a snapshot, to build a new map once
+/// all of them are decoded.
+struct Loader<K, V, F> {
+    /// The decoded entries, in ascending key order
+    entries: Vec<(K, V)>,
+    decode_entry: F,
+    /// The bytes that every key in the snapshot must start with
+    key_prefix: Box<[u8]>,
+    /// The bytes read but not yet decoded start at `start`
+    buffer: Vec<u8>,
+    start: usize,
+    /// The offset in the snapshot of the first byte in `buffer`
+    buffer_offset: u64,
+    num_entries: u64,
+    num_decoded: u64,
+}
+
+impl<K, V, F> Loader<K, V, F>
+where
+    K: AsBytes,
+    F: FnMut(&[u8], &[u8]) -> Result<(K, V), DecodeEntryError>,
+{
+    fn new(
+        header: &[u8; HEADER_LEN],
+        key_prefix: &[u8],
+        decode_entry: F,
+    ) -> Result<Self, SnapshotError> {
+        if header[..4] != MAGIC {
+            return Err(SnapshotError::InvalidFormat {
+                offset: 0,
+                reason: "the data does not start with the snapshot magic bytes",
+            });
+        }
+        if header[4] != FORMAT_VERSION {
+            return Err(SnapshotError::InvalidFormat {
+                offset: 4,
+                reason: "unsupported snapshot format version",
+            });
+        }
+        // PANIC SAFETY: The header has exactly 8 bytes after the version byte
+        let num_entries = u64::from_le_bytes(header[5..].try_into().unwrap());
+
+        Ok(Loader {
+            entries: Vec::new(),
+            decode_entry,
+            key_prefix: key_prefix.into(),
+            buffer: Vec::new(),
+            start: 0,
+            buffer_offset: HEADER_LEN as u64,
+            num_entries,
+            num_decoded: 0,
+        })
+    }
+
+    /// Decode all complete entries in the buffer, returning `true` once every
+    /// entry of the snapshot has been decoded.
+    fn decode_buffered(&mut self) -> Result<bool, SnapshotError> {
+        while self.num_decoded < self.num_entries {
+            let remaining = &self.buffer[self.start..];
+            let invalid_format = |reason| SnapshotError::InvalidFormat {
+                offset: self.buffer_offset + self.start as u64,
+                reason,
+            };
+
+            let (key_range, value_range) = match split_entry(remaining).map_err(invalid_format)? {
+                Some(ranges) => ranges,
+                None => return Ok(false),
+            };
+            let entry_len = value_range.end;
+            if !remaining[key_range.clone()].starts_with(&self.key_prefix) {
+                return Err(invalid_format(
+                    "the snapshot contains a key that does not start with the prefix",
+                ));
+            }
+
+            let (key, value) = (self.decode_entry)(&remaining[key_range], &remaining[value_range])
+                .map_err(|source| SnapshotError::InvalidEntry {
+                    index: self.num_decoded,
+                    source,
+                })?;
+
+            // The entries are written in key order, so only the previous key can conflict
+            // with this one
+            if let Some((previous_key, _)) = self.entries.last() {
+                let (previous_bytes, key_bytes) = (previous_key.as_bytes(), key.as_bytes());
+                if previous_bytes == key_bytes {
+                    return Err(invalid_format("the snapshot contains a duplicate key"));
+                }
+                if key_bytes.starts_with(previous_bytes) || previous_bytes.starts_with(key_bytes) {
+                    return Err(SnapshotError::InvalidEntry {
+                        index: self.num_decoded,
+                        source: Box::new(InsertPrefixError {
+                            byte_repr: key_bytes.into(),
+                        }),
+                    });
+                }
+                if key_bytes < previous_bytes {
+                    return Err(invalid_format(
+                        "the snapshot keys are not in ascending order",
+                    ));
+                }
+            }
+            self.entries.push((key, value));
+
+            self.start += entry_len;
+            self.num_decoded += 1;
+        }
+
+        Ok(true)
+    }
+
+    /// Return a buffer to read the next chunk of the snapshot into, which
+    /// must be followed by a call to [`Loader::commit_read`].
+    fn read_buffer(&mut self) -> &mut [u8] {
+        self.buffer.drain(..self.start);
+        self.buffer_offset += self.start as u64;
+        self.start = 0;
+
+        let filled = self.buffer.len();
+        self.buffer.resize(filled + CHUNK_LEN, 0);
+        &mut self.buffer[filled..]
+    }
+
+    /// Keep the first `num_read` bytes of the last buffer returned by
+    /// [`Loader::read_buffer`].
+    fn commit_read(&mut self, num_read: usize) -> Result<(), SnapshotError> {
+        let filled = self.buffer.len() - CHUNK_LEN;
+        self.buffer.truncate(filled + num_read);
+
+        if num_read == 0 {
+            Err(SnapshotError::InvalidFormat {
+                offset: self.buffer_offset + self.buffer.len() as u64,
+                reason: "the snapshot ends before its last entry",
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Build the map from the decoded entries, using the bulk construction
+    /// since the entries are already sorted.
+    fn finish(self) -> TreeMap<K, V> {
+        // PANIC SAFETY: Every decoded key was checked to be greater than the key
+        // before it, and not to be a prefix of it or have it as a prefix
+        TreeMap::try_from_sorted_iter(self.entries)
+            .expect("decoded keys should be sorted and not prefixes of each other")
+    }
+}
+
+type EntryRanges = (std::ops::Range<usize>, std::ops::Range<usize>);
+
+/// Find the key and value of the entry at the start of `bytes`, returning
+/// `None` if the entry is not complete yet.
+fn split_entry(bytes: &[u8]) -> Result<Option<EntryRanges>, &'static str> {
+    let (key_len, key_start) = match read_leb128(bytes)? {
+        Some(decoded) => decoded,
+        None => return Ok(None),
+    };
+    let key_end = match key_start.checked_add(key_len) {
+        Some(key_end) if key_end <= bytes.len() => key_end,
+        Some(_) => return Ok(None),
+        None => return Err("entry length is too large"),
+    };
+
+    let (value_len, value_len_size) = match read_leb128(&bytes[key_end..])? {
+        Some(decoded) => decoded,
+        None => return Ok(None),
+    };
+    let value_start = key_end + value_len_size;
+    let value_end = match value_start.checked_add(value_len) {
+        Some(value_end) if value_end <= bytes.len() => value_end,
+        Some(_) => return Ok(None),
+        None => return Err("entry length is too large"),
+    };
+
+    Ok(Some((key_start..key_end, value_start..value_end)))
+}
+
+fn write_leb128(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Read a LEB128 encoded length, returning the length and the number of bytes
+/// it was encoded with, or `None` if the encoding is not complete yet.
+fn read_leb128(bytes: &[u8]) -> Result<Option<(usize, usize)>, &'static str> {
+    let mut value: u64 = 0;
+    for (idx, byte) in bytes.iter().enumerate() {
+        if idx >= 10 || (idx == 9 && *byte > 1) {
+            return Err("entry length is too large");
+        }
+        value |= u64::from(byte & 0x7F) << (7 * idx);
+        if byte & 0x80 == 0 {
+            return match usize::try_from(value) {
+                Ok(value) => Ok(Some((value, idx + 1))),
+                Err(_) => Err("entry length is too large"),
+            };
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode_u32(value: &u32, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn decode_u32(key: &[u8], value: &[u8]) -> Result<(Box<[u8]>, u32), DecodeEntryError> {
+        Ok((key.into(), u32::from_le_bytes(value.try_into()?)))
+    }
+
+    fn sample_map() -> TreeMap<Box<[u8]>, u32> {
+        let mut map = TreeMap::new();
+        for idx in 0..5000u32 {
+            // Keys of different lengths, some longer than 127 bytes
+            let len = 4 + (idx % 150) as usize;
+            let mut key = vec![0xAB; len];
+            key[..4].copy_from_slice(&idx.to_be_bytes());
+            map.try_insert(key.into_boxed_slice(), idx).unwrap();
+        }
+        map
+    }
+
+    #[test]
+    fn roundtrip() {
+        let map = sample_map();
+        let mut bytes = Vec::new();
+        write_snapshot(&map, &mut bytes, encode_u32).unwrap();
+        assert!(bytes.len() > CHUNK_LEN);
+
+        let loaded = read_snapshot(bytes.as_slice(), decode_u32).unwrap();
+        assert_eq!(loaded, map);
+
+        let empty = TreeMap::<Box<[u8]>, u32>::new();
+        let mut bytes = Vec::new();
+        write_snapshot(&empty, &mut bytes, encode_u32).unwrap();
+        assert_eq!(bytes.len(), HEADER_LEN);
+        assert!(read_snapshot(bytes.as_slice(), decode_u32)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn roundtrip_values_larger_than_chunk() {
+        let mut map = TreeMap::<[u8; 1], Vec<u8>>::new();
+        map.insert([1], vec![7; 3 * CHUNK_LEN + 5]);
+        map.insert([2], Vec::new());
+
+        let mut bytes = Vec::new();
+        write_snapshot(&map, &mut bytes, |value, buf| buf.extend_from_slice(value)).unwrap();
+        let loaded = read_snapshot(bytes.as_slice(), |key, value| {
+            Ok((key.try_into()?, value.to_vec()))
+        })
+        .unwrap();
+        assert_eq!(loaded, map);
+    }
+
+    #[test]
+    fn invalid_snapshots() {
+        let map = sample_map();
+        let mut bytes = Vec::new();
+        write_snapshot(&map, &mut bytes, encode_u32).unwrap();
+
+        let result = read_snapshot(&bytes[..HEADER_LEN - 1], decode_u32);
+        assert!(matches!(
+            result,
+            Err(SnapshotError::InvalidFormat { offset: 0, .. })
+        ));
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        let result = read_snapshot(bad_magic.as_slice(), decode_u32);
+        assert!(matches!(
+            result,
+            Err(SnapshotError::InvalidFormat { offset: 0, .. })
+        ));
+
+        let mut bad_version = bytes.clone();
+        bad_version[4] = FORMAT_VERSION + 1;
+        let result = read_snapshot(bad_version.as_slice(), decode_u32);
+        assert!(matches!(
+            result,
+            Err(SnapshotError::InvalidFormat { offset: 4, .. })
+        ));
+
+        let result = read_snapshot(&bytes[..bytes.len() - 1], decode_u32);
+        match result {
+            Err(SnapshotError::InvalidFormat { offset, .. }) => {
+                assert_eq!(offset, bytes.len() as u64 - 1)
+            },
+            other => panic!("expected a truncation error, got {other:?}"),
+        }
+
+        // The value of the first entry has the wrong length for a `u32`
+        let mut first_entry = Vec::new();
+        write_leb128(&mut first_entry, 1);
+        first_entry.push(5);
+        write_leb128(&mut first_entry, 2);
+        first_entry.extend_from_slice(&[0, 0]);
+        let mut short_value = bytes[..5].to_vec();
+        short_value.extend_from_slice(&1u64.to_le_bytes());
+        short_value.extend_from_slice(&first_entry);
+        let result = read_snapshot(short_value.as_slice(), decode_u32);
+        assert!(matches!(
+            result,
+            Err(SnapshotError::InvalidEntry { index: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn prefix_and_duplicate_keys_are_rejected() {
+        fn snapshot_of(keys: &[&[u8]]) -> Vec<u8> {
+            let mut bytes = MAGIC.to_vec();
+            bytes.push(FORMAT_VERSION);
+            bytes.extend_from_slice(&(keys.len() as u64).to_le_bytes());
+            for key in keys {
+                write_leb128(&mut bytes, key.len() as u64);
+                bytes.extend_from_slice(key);
+                write_leb128(&mut bytes, 4);
+                bytes.extend_from_slice(&[0; 4]);
+            }
+            bytes
+        }
+
+        let result = read_snapshot(snapshot_of(&[&[1, 2], &[1, 2, 3]]).as_slice(), decode_u32);
+        assert!(matches!(
+            result,
+            Err(SnapshotError::InvalidEntry { index: 1, .. })
+        ));
+
+        let result = read_snapshot(snapshot_of(&[&[1, 2], &[1, 2]]).as_slice(), decode_u32);
+        assert!(matches!(result, Err(SnapshotError::InvalidFormat { .. })));
+
+        let result = read_snapshot(snapshot_of(&[&[1, 3], &[1, 2]]).as_slice(), decode_u32);
+        assert!(matches!(
+            result,
+            Err(SnapshotError::InvalidFormat {
+                reason: "the snapshot keys are not in ascending order",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn prefix_snapshot_roundtrip_and_graft() {
+        let map = sample_map();
+        let prefix = [0, 0, 0x02];
+        let mut namespace = TreeMap::new();
+        for (key, value) in map.iter().filter(|(key, _)| key.starts_with(&prefix)) {
+            namespace.try_insert(key.clone(), *value).unwrap();
+        }
+        assert_eq!(namespace.len(), 256);
+
+        let mut bytes = Vec::new();
+        write_prefix_snapshot(&map, &prefix, &mut bytes, encode_u32).unwrap();
+        assert_eq!(
+            read_snapshot(bytes.as_slice(), decode_u32).unwrap(),
+            namespace
+        );
+
+        // Grafting into a map which has other entries under the prefix replaces them
+        let mut modified = map.clone();
+        let removed_key = namespace.keys().nth(5).unwrap().clone();
+        modified.remove(&removed_key).unwrap();
+        modified
+            .try_insert(Box::new([0, 0, 0x02, 5, 0]), 7)
+            .unwrap();
+        for (key, value) in modified.iter_mut() {
+            if key.starts_with(&prefix) {
+                *value += 1;
+            }
+        }
+        let replaced =
+            graft_snapshot(&mut modified, &prefix, bytes.as_slice(), decode_u32).unwrap();
+        assert_eq!(replaced.len(), 256);
+        assert_eq!(replaced.get([0, 0, 0x02, 5, 0].as_ref()), Some(&8));
+        assert!(replaced
+            .iter()
+            .filter(|(key, _)| key.len() > 5)
+            .all(|(key, value)| namespace.get(key) == Some(&(value - 1))));
+        assert_eq!(modified, map);
+
+        // Grafting into a map without the prefix, or into an empty map
+        let mut without_namespace = map.clone();
+        for key in namespace.keys() {
+            without_namespace.remove(key).unwrap();
+        }
+        let replaced = graft_snapshot(
+            &mut without_namespace,
+            &prefix,
+            bytes.as_slice(),
+            decode_u32,
+        )
+        .unwrap();
+        assert!(replaced.is_empty());
+        assert_eq!(without_namespace, map);
+
+        let mut empty = TreeMap::new();
+        graft_snapshot(&mut empty, &prefix, bytes.as_slice(), decode_u32).unwrap();
+        assert_eq!(empty, namespace);
+
+        // A snapshot of a prefix without entries clears the namespace
+        let mut bytes = Vec::new();
+        write_prefix_snapshot(&map, &[0, 0, 0x20], &mut bytes, encode_u32).unwrap();
+        assert_eq!(bytes.len(), HEADER_LEN);
+        let mut cleared = map.clone();
+        let replaced = graft_snapshot(&mut cleared, &[0, 0], bytes.as_slice(), decode_u32).unwrap();
+        assert_eq!(replaced, map);
+        assert!(cleared.is_empty());
+    }
+
+    #[test]
+    fn graft_rejects_invalid_namespaces() {
+        let map = sample_map();
+        let mut bytes = Vec::new();
+        write_prefix_snapshot(&map, &[0, 0, 0x02], &mut bytes, encode_u32).unwrap();
+
+        // The snapshot has keys outside the prefix
+        let mut target = map.clone();
+        let result = graft_snapshot(&mut target, &[0, 0, 0x02, 0], bytes.as_slice(), decode_u32);
+        assert!(matches!(result, Err(SnapshotError::InvalidFormat { .. })));
+        assert_eq!(target, map);
+
+        // The map has a key which is a prefix of the grafted keys
+        let mut target = TreeMap::<Box<[u8]>, u32>::new();
+        target.try_insert(Box::new([0, 0]), 1).unwrap();
+        let result = graft_snapshot(&mut target, &[0, 0, 0x02], bytes.as_slice(), decode_u32);
+        assert!(matches!(
+            result,
+            Err(SnapshotError::InvalidEntry { index: 0, .. })
+        ));
+        assert_eq!(target.len(), 1);
+    }
+
+    #[test]
+    fn leb128_limits() {
+        let mut bytes = Vec::new();
+        write_leb128(&mut bytes, u64::MAX);
+        assert_eq!(bytes.len(), 10);
+        if usize::BITS == 64 {
+            assert_eq!(read_leb128(&bytes), Ok(Some((usize::MAX, 10))));
+        }
+        assert_eq!(read_leb128(&bytes[..9]), Ok(None));
+        assert!(read_leb128(&[0xFF; 11]).is_err());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn async_roundtrip() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+
+        let map = sample_map();
+        let mut bytes = Vec::new();
+        runtime
+            .block_on(write_snapshot_async(&map, &mut bytes, encode_u32))
+            .unwrap();
+
+        let mut blocking_bytes = Vec::new();
+        write_snapshot(&map, &mut blocking_bytes, encode_u32).unwrap();
+        assert_eq!(bytes, blocking_bytes);
+
+        let loaded = runtime
+            .block_on(read_snapshot_async(bytes.as_slice(), decode_u32))
+            .unwrap();
+        assert_eq!(loaded, map);
+
+        let result = runtime.block_on(read_snapshot_async(&bytes[..bytes.len() - 3], decode_u32));
+        assert!(matches!(result, Err(SnapshotError::InvalidFormat { .. })));
+
+        let prefix = [0, 0, 0x02];
+        let mut prefix_bytes = Vec::new();
+        runtime
+            .block_on(write_prefix_snapshot_async(
+                &map,
+                &prefix,
+                &mut prefix_bytes,
+                encode_u32,
+            ))
+            .unwrap();
+        let mut blocking_bytes = Vec::new();
+        write_prefix_snapshot(&map, &prefix, &mut blocking_bytes, encode_u32).unwrap();
+        assert_eq!(prefix_bytes, blocking_bytes);
+
+        let mut target = map.clone();
+        for (_, value) in target.iter_mut() {
+            *value = 0;
+        }
+        let replaced = runtime
+            .block_on(graft_snapshot_async(
+                &mut target,
+                &prefix,
+                prefix_bytes.as_slice(),
+                decode_u32,
+            ))
+            .unwrap();
+        assert_eq!(replaced.len(), 256);
+        assert!(target.iter().all(|(key, value)| {
+            if key.starts_with(&prefix) {
+                map.get(key) == Some(value)
+            } else {
+                *value == 0
+            }
+        }));
+    }
+}
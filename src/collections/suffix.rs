@@ -0,0 +1,376 @@
+//! A [`TreeMap`] wrapper which also answers suffix queries.
+//!
+//! A [`SuffixTreeMap`] keeps a second tree, keyed on the reversed bytes of
+//! every key in the map. A suffix of a key is a prefix of its reversed bytes,
+//! so [`SuffixTreeMap::ends_with`] is a prefix search in the second tree.
+//! Every mutation goes through the wrapper, which keeps the two trees in sync.
+//!
+//! Two keys of the map can still reverse to bytes where one is a prefix of the
+//! other, like `ab` and `b`. So the reversed bytes are escaped and terminated,
+//! every `0x00` byte becomes `0x00 0xFF` and the end of the key is marked with
+//! `0x00 0x00`, which makes the reversed keys prefix-free.
+
+use crate::{AsBytes, InsertPrefixError, NoPrefixesBytes, TreeMap};
+use std::{borrow::Borrow, fmt};
+
+/// The byte that follows a `0x00` byte of the key in the reversed tree.
+const ESCAPED_ZERO: u8 = 0xFF;
+
+/// The bytes that end every key in the reversed tree.
+const TERMINATOR: [u8; 2] = [0x00, 0x00];
+
+/// Append the escaped, reversed bytes of `bytes` to `out`.
+fn push_reversed(bytes: &[u8], out: &mut Vec<u8>) {
+    for &byte in bytes.iter().rev() {
+        out.push(byte);
+        if byte == 0 {
+            out.push(ESCAPED_ZERO);
+        }
+    }
+}
+
+/// Return the key of the reversed tree for the given key bytes.
+fn reversed_key(key_bytes: &[u8]) -> Box<[u8]> {
+    let mut reversed = Vec::with_capacity(key_bytes.len() + TERMINATOR.len());
+    push_reversed(key_bytes, &mut reversed);
+    reversed.extend_from_slice(&TERMINATOR);
+    reversed.into_boxed_slice()
+}
+
+/// Return the key bytes which the given key of the reversed tree was made
+/// from, the inverse of [`reversed_key`].
+fn original_key_bytes(reversed: &[u8]) -> Vec<u8> {
+    let escaped = &reversed[..reversed.len() - TERMINATOR.len()];
+    let mut key_bytes = Vec::with_capacity(escaped.len());
+    let mut bytes = escaped.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte == 0 {
+            // Skip the escape byte
+            bytes.next();
+        }
+        key_bytes.push(byte);
+    }
+    key_bytes.reverse();
+    key_bytes
+}
+
+/// A [`TreeMap`] which also keeps the reversed bytes of its keys in a second
+/// tree, to look up keys by suffix.
+///
+/// Lookups by key and by prefix use the map, and lookups by suffix use the
+/// second tree. Mutations keep the two in sync, at the cost of an extra insert
+/// or remove in the second tree for every new or removed key.
+///
+/// # Examples
+///
+/// ```rust
+/// use blart::suffix::SuffixTreeMap;
+///
+/// let mut files = SuffixTreeMap::<Box<[u8]>, u32>::new();
+/// files.try_insert(Box::from(&b"src/lib.rs"[..]), 1).unwrap();
+/// files.try_insert(Box::from(&b"src/map.rs"[..]), 2).unwrap();
+/// files.try_insert(Box::from(&b"docs/lib.md"[..]), 3).unwrap();
+///
+/// let rust_files: Vec<_> = files.ends_with(b".rs").map(|(_, id)| *id).collect();
+/// assert_eq!(rust_files, [1, 2]);
+///
+/// let src_libs: Vec<_> = files
+///     .starts_and_ends_with(b"src/", b"lib.rs")
+///     .map(|(_, id)| *id)
+///     .collect();
+/// assert_eq!(src_libs, [1]);
+/// ```
+pub struct SuffixTreeMap<K, V> {
+    map: TreeMap<K, V>,
+    /// The reversed key bytes of every key in `map`.
+    ///
+    /// This may hold extra keys if an insert unwinds after the reversed key was
+    /// added, so the reversed keys are always checked against `map`.
+    reversed: TreeMap<Box<[u8]>, ()>,
+}
+
+impl<K, V> SuffixTreeMap<K, V> {
+    /// Create a new, empty map.
+    pub fn new() -> Self {
+        SuffixTreeMap {
+            map: TreeMap::new(),
+            reversed: TreeMap::new(),
+        }
+    }
+
+    /// Returns a reference to the underlying map.
+    pub fn map(&self) -> &TreeMap<K, V> {
+        &self.map
+    }
+
+    /// Return the underlying map, discarding the reversed keys.
+    pub fn into_map(self) -> TreeMap<K, V> {
+        self.map
+    }
+
+    /// Returns the number of elements in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if the map contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Clear the map and the reversed keys, removing all elements.
+    pub fn clear(&mut self) {
+        self.reversed.clear();
+        self.map.clear();
+    }
+}
+
+impl<K, V> SuffixTreeMap<K, V>
+where
+    K: AsBytes,
+{
+    /// Returns a reference to the value corresponding to the key.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: AsBytes + ?Sized,
+    {
+        self.map.get(key)
+    }
+
+    /// Returns a mutable reference to the value corresponding to the key.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: AsBytes + ?Sized,
+    {
+        self.map.get_mut(key)
+    }
+
+    /// Returns `true` if the map contains a value for the specified key.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: AsBytes + ?Sized,
+    {
+        self.map.contains_key(key)
+    }
+
+    /// Returns an iterator over the entries whose key starts with `prefix`,
+    /// in key order.
+    pub fn starts_with<'a>(&'a self, prefix: &[u8]) -> impl Iterator<Item = (&'a K, &'a V)> + 'a {
+        self.map.prefix_iter(prefix)
+    }
+
+    /// Returns an iterator over the entries whose key ends with `suffix`, in
+    /// the order of their reversed keys.
+    pub fn ends_with<'a>(&'a self, suffix: &[u8]) -> impl Iterator<Item = (&'a K, &'a V)> + 'a {
+        let mut reversed_suffix = Vec::with_capacity(suffix.len());
+        push_reversed(suffix, &mut reversed_suffix);
+
+        self.reversed
+            .prefix_iter(&reversed_suffix)
+            .filter_map(move |(reversed, _)| {
+                let leaf_node_ptr = self.map.leaf_by_key_bytes(&original_key_bytes(reversed))?;
+                // SAFETY: The map is borrowed for the lifetime of the iterator, so there
+                // are no mutations of the tree while the returned references are live.
+                Some(unsafe { leaf_node_ptr.as_key_value_ref() })
+            })
+    }
+
+    /// Returns an iterator over the entries whose key starts with `prefix` and
+    /// ends with `suffix`, in key order.
+    ///
+    /// The prefix and the suffix may overlap in the key, so `abc` both starts
+    /// with `ab` and ends with `bc`. The entries are found by prefix and then
+    /// checked for the suffix.
+    pub fn starts_and_ends_with<'a>(
+        &'a self,
+        prefix: &[u8],
+        suffix: &'a [u8],
+    ) -> impl Iterator<Item = (&'a K, &'a V)> + 'a {
+        self.map
+            .prefix_iter(prefix)
+            .filter(move |(key, _)| key.as_bytes().ends_with(suffix))
+    }
+
+    /// Insert a key-value pair into the map, returning the previous value if
+    /// the key was already present.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V>
+    where
+        K: NoPrefixesBytes,
+    {
+        match self.try_insert(key, value) {
+            Ok(value) => value,
+            Err(_err) => unreachable!(
+                "This branch should be unreachable because of the safety contract of \
+                 `NoPrefixesBytes`"
+            ),
+        }
+    }
+
+    /// Insert a key-value pair into the map, returning the previous value if
+    /// the key was already present.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the given key is a prefix of an existing key, or an
+    /// existing key is a prefix of the given key.
+    pub fn try_insert(&mut self, key: K, value: V) -> Result<Option<V>, InsertPrefixError> {
+        let reversed = reversed_key(key.as_bytes());
+        let is_new_reversed = !self.reversed.contains_key(&reversed);
+        if is_new_reversed {
+            // The reversed key is added first, so that an unwind in either insert
+            // can only leave an extra reversed key behind.
+            //
+            // PANIC SAFETY: The reversed keys are escaped and terminated, so they are
+            // prefix-free.
+            self.reversed
+                .try_insert(reversed.clone(), ())
+                .expect("reversed keys should be prefix-free");
+        }
+
+        let result = self.map.try_insert(key, value);
+        if result.is_err() && is_new_reversed {
+            self.reversed.remove(&reversed);
+        }
+        result
+    }
+
+    /// Removes a key from the map, returning the value at the key if the key
+    /// was previously in the map.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: AsBytes + ?Sized,
+    {
+        Some(self.remove_entry(key)?.1)
+    }
+
+    /// Removes a key from the map, returning the stored key and value if the
+    /// key was previously in the map.
+    pub fn remove_entry<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: AsBytes + ?Sized,
+    {
+        let entry = self.map.remove_entry(key)?;
+        self.reversed.remove(&reversed_key(entry.0.as_bytes()));
+        Some(entry)
+    }
+
+    /// Removes and returns the first element in the map.
+    pub fn pop_first(&mut self) -> Option<(K, V)> {
+        let entry = self.map.pop_first()?;
+        self.reversed.remove(&reversed_key(entry.0.as_bytes()));
+        Some(entry)
+    }
+
+    /// Removes and returns the last element in the map.
+    pub fn pop_last(&mut self) -> Option<(K, V)> {
+        let entry = self.map.pop_last()?;
+        self.reversed.remove(&reversed_key(entry.0.as_bytes()));
+        Some(entry)
+    }
+}
+
+impl<K, V> Default for SuffixTreeMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: AsBytes, V> From<TreeMap<K, V>> for SuffixTreeMap<K, V> {
+    fn from(map: TreeMap<K, V>) -> Self {
+        let mut reversed = TreeMap::new();
+        for key in map.keys() {
+            // PANIC SAFETY: The reversed keys are escaped and terminated, so they are
+            // prefix-free.
+            reversed
+                .try_insert(reversed_key(key.as_bytes()), ())
+                .expect("reversed keys should be prefix-free");
+        }
+        SuffixTreeMap { map, reversed }
+    }
+}
+
+impl<K, V> fmt::Debug for SuffixTreeMap<K, V>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SuffixTreeMap")
+            .field("map", &self.map)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reversed_keys_are_escaped() {
+        for key in [&[][..], &[0], &[0, 0], &[1, 0, 2], &[0xFF, 0]] {
+            let reversed = reversed_key(key);
+            assert!(reversed.ends_with(&TERMINATOR));
+            assert_eq!(original_key_bytes(&reversed), key);
+        }
+        assert_eq!(reversed_key(&[1, 0, 2]).as_ref(), [2, 0, 0xFF, 1, 0, 0]);
+    }
+
+    #[test]
+    fn suffix_queries_track_mutations() {
+        let mut map = SuffixTreeMap::<[u8; 2], char>::new();
+        map.insert([0, 0], 'a');
+        map.insert([1, 0], 'b');
+        map.insert([0, 1], 'c');
+        map.insert([2, 0], 'd');
+        assert_eq!(map.insert([2, 0], 'e'), Some('d'));
+        assert_eq!(map.reversed.len(), 4);
+
+        let ends_with = |map: &SuffixTreeMap<[u8; 2], char>, suffix: &[u8]| {
+            map.ends_with(suffix).map(|(_, c)| *c).collect::<String>()
+        };
+        assert_eq!(ends_with(&map, &[0]), "abe");
+        assert_eq!(ends_with(&map, &[0, 0]), "a");
+        assert_eq!(ends_with(&map, &[1]), "c");
+        assert_eq!(ends_with(&map, &[]), "abec");
+        assert_eq!(ends_with(&map, &[3]), "");
+
+        assert_eq!(map.remove(&[1, 0]), Some('b'));
+        assert_eq!(map.pop_first(), Some(([0, 0], 'a')));
+        assert_eq!(ends_with(&map, &[0]), "e");
+        assert_eq!(map.reversed.len(), 2);
+
+        map.clear();
+        assert!(map.reversed.is_empty());
+        assert_eq!(ends_with(&map, &[]), "");
+    }
+
+    #[test]
+    fn prefix_errors_and_from_map() {
+        let mut tree = TreeMap::<Box<[u8]>, u32>::new();
+        tree.try_insert(Box::from(&b"abc"[..]), 1).unwrap();
+        tree.try_insert(Box::from(&b"bc"[..]), 2).unwrap();
+
+        let mut map = SuffixTreeMap::from(tree);
+        // The reversed keys of `bc` and `abc` have a common prefix, but both fit
+        let both: Vec<_> = map.ends_with(b"bc").map(|(_, v)| *v).collect();
+        assert_eq!(both, [2, 1]);
+
+        assert!(map.try_insert(Box::from(&b"ab"[..]), 3).is_err());
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.reversed.len(), 2);
+        assert_eq!(map.ends_with(b"b").count(), 0);
+
+        let abc: Vec<_> = map
+            .starts_and_ends_with(b"ab", b"bc")
+            .map(|(_, v)| *v)
+            .collect();
+        assert_eq!(abc, [1]);
+        assert_eq!(map.starts_with(b"b").count(), 1);
+        assert_eq!(map.into_map().len(), 2);
+    }
+}
@@ -0,0 +1,780 @@
+//! Recording and replaying the stream of operations applied to a
+//! [`TreeMap`].
+//!
+//! A [`TracedTreeMap`] wraps a [`TreeMap`] and records every operation that
+//! is performed on it into a [`Trace`]. The trace can be serialized with
+//! [`Trace::as_bytes`], loaded again with [`Trace::from_bytes`] and replayed
+//! against a fresh map with [`Trace::replay`], which makes it possible to
+//! benchmark changes against a real workload or to reproduce a performance
+//! problem. [`Trace::anonymize`] can be used to hide the key bytes from
+//! casual inspection while keeping the shape of the tree the same, but it is
+//! not a privacy guarantee, see its documentation.
+//!
+//! # Format
+//!
+//! The trace starts with a single format version byte, followed by the
+//! operations. Each operation is a one byte tag. Operations that take a key
+//! are followed by the number of leading bytes the key shares with the key of
+//! the previous such operation, the number of remaining bytes, and then the
+//! remaining bytes themselves. Both numbers are encoded as unsigned LEB128, so
+//! workloads with many similar keys produce small traces.
+
+use crate::{AsBytes, InsertPrefixError, NoPrefixesBytes, TreeMap};
+use std::{borrow::Borrow, error::Error, fmt};
+
+const FORMAT_VERSION: u8 = 1;
+
+const TAG_GET: u8 = 0;
+const TAG_INSERT: u8 = 1;
+const TAG_REMOVE: u8 = 2;
+const TAG_POP_FIRST: u8 = 3;
+const TAG_POP_LAST: u8 = 4;
+const TAG_CLEAR: u8 = 5;
+
+/// A single operation recorded in a [`Trace`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceOp<'a> {
+    /// A lookup of the given key
+    Get(&'a [u8]),
+    /// An insert of the given key
+    Insert(&'a [u8]),
+    /// A removal of the given key
+    Remove(&'a [u8]),
+    /// A removal of the minimum entry
+    PopFirst,
+    /// A removal of the maximum entry
+    PopLast,
+    /// A removal of all entries
+    Clear,
+}
+
+impl<'a> TraceOp<'a> {
+    fn tag(&self) -> u8 {
+        match self {
+            TraceOp::Get(_) => TAG_GET,
+            TraceOp::Insert(_) => TAG_INSERT,
+            TraceOp::Remove(_) => TAG_REMOVE,
+            TraceOp::PopFirst => TAG_POP_FIRST,
+            TraceOp::PopLast => TAG_POP_LAST,
+            TraceOp::Clear => TAG_CLEAR,
+        }
+    }
+
+    fn key(&self) -> Option<&'a [u8]> {
+        match self {
+            TraceOp::Get(key) | TraceOp::Insert(key) | TraceOp::Remove(key) => Some(key),
+            TraceOp::PopFirst | TraceOp::PopLast | TraceOp::Clear => None,
+        }
+    }
+}
+
+/// A compact, append-only record of operations applied to a [`TreeMap`].
+#[derive(Clone, PartialEq, Eq)]
+pub struct Trace {
+    bytes: Vec<u8>,
+    num_ops: usize,
+    last_key: Vec<u8>,
+}
+
+impl Trace {
+    /// Create a new, empty trace.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::trace::Trace;
+    ///
+    /// let trace = Trace::new();
+    /// assert!(trace.is_empty());
+    /// ```
+    pub fn new() -> Self {
+        Trace {
+            bytes: vec![FORMAT_VERSION],
+            num_ops: 0,
+            last_key: Vec::new(),
+        }
+    }
+
+    /// Returns the number of operations in the trace.
+    pub fn len(&self) -> usize {
+        self.num_ops
+    }
+
+    /// Returns `true` if the trace contains no operations.
+    pub fn is_empty(&self) -> bool {
+        self.num_ops == 0
+    }
+
+    /// Append an operation to the end of the trace.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::trace::{Trace, TraceOp};
+    ///
+    /// let mut trace = Trace::new();
+    /// trace.push(TraceOp::Insert(&[1, 2, 3]));
+    /// trace.push(TraceOp::Get(&[1, 2, 4]));
+    /// trace.push(TraceOp::PopFirst);
+    ///
+    /// let mut reader = trace.reader();
+    /// assert_eq!(reader.next_op(), Some(TraceOp::Insert(&[1, 2, 3])));
+    /// assert_eq!(reader.next_op(), Some(TraceOp::Get(&[1, 2, 4])));
+    /// assert_eq!(reader.next_op(), Some(TraceOp::PopFirst));
+    /// assert_eq!(reader.next_op(), None);
+    /// ```
+    pub fn push(&mut self, op: TraceOp<'_>) {
+        self.bytes.push(op.tag());
+
+        if let Some(key) = op.key() {
+            let shared = key
+                .iter()
+                .zip(self.last_key.iter())
+                .take_while(|(a, b)| a == b)
+                .count();
+            let suffix = &key[shared..];
+
+            write_leb128(&mut self.bytes, shared);
+            write_leb128(&mut self.bytes, suffix.len());
+            self.bytes.extend_from_slice(suffix);
+
+            self.last_key.truncate(shared);
+            self.last_key.extend_from_slice(suffix);
+        }
+
+        self.num_ops += 1;
+    }
+
+    /// Returns a reader over the operations in the trace, in the order they
+    /// were recorded.
+    pub fn reader(&self) -> TraceReader<'_> {
+        TraceReader {
+            decoder: Decoder::new(&self.bytes),
+        }
+    }
+
+    /// Returns the serialized form of the trace.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Load a trace from its serialized form, as produced by
+    /// [`Trace::as_bytes`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bytes are not a valid trace.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::trace::{Trace, TraceOp};
+    ///
+    /// let mut trace = Trace::new();
+    /// trace.push(TraceOp::Insert(b"hello"));
+    /// trace.push(TraceOp::Remove(b"help"));
+    ///
+    /// let loaded = Trace::from_bytes(trace.as_bytes()).unwrap();
+    /// assert_eq!(loaded, trace);
+    ///
+    /// assert!(Trace::from_bytes(&[]).is_err());
+    /// ```
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, TraceDecodeError> {
+        let mut decoder = Decoder::new(bytes);
+        let mut num_ops = 0;
+        while decoder.next_op()?.is_some() {
+            num_ops += 1;
+        }
+
+        Ok(Trace {
+            bytes: bytes.to_vec(),
+            num_ops,
+            last_key: decoder.key,
+        })
+    }
+
+    /// Produce a copy of this trace where each key byte has been replaced
+    /// using a byte permutation derived from `seed`.
+    ///
+    /// Since the same permutation is applied to every byte, keys that share a
+    /// prefix still share a prefix of the same length after anonymization, so
+    /// the replayed trace builds a tree of exactly the same shape. The
+    /// permutation does not preserve the order of keys though, so
+    /// [`TraceOp::PopFirst`] and [`TraceOp::PopLast`] may remove different
+    /// entries than in the original workload.
+    ///
+    /// This only hides the key bytes from casual inspection, it is not a
+    /// privacy guarantee. Every occurrence of a byte is replaced by the same
+    /// byte, so the frequencies of the bytes are unchanged, and frequency
+    /// analysis or a few known keys are enough to recover the permutation and
+    /// with it the original keys. Do not share an anonymized trace of keys that
+    /// must stay confidential.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::trace::{Trace, TraceOp};
+    ///
+    /// let mut trace = Trace::new();
+    /// trace.push(TraceOp::Insert(b"secret"));
+    ///
+    /// let anonymized = trace.anonymize(1234);
+    /// assert_eq!(anonymized.len(), 1);
+    /// assert_ne!(anonymized, trace);
+    /// ```
+    pub fn anonymize(&self, seed: u64) -> Trace {
+        let permutation = byte_permutation(seed);
+        let mut anonymized = Trace::new();
+        let mut reader = self.reader();
+        let mut key = Vec::new();
+
+        while let Some(op) = reader.next_op() {
+            if let Some(op_key) = op.key() {
+                key.clear();
+                key.extend(op_key.iter().map(|byte| permutation[usize::from(*byte)]));
+            }
+
+            anonymized.push(match op {
+                TraceOp::Get(_) => TraceOp::Get(&key),
+                TraceOp::Insert(_) => TraceOp::Insert(&key),
+                TraceOp::Remove(_) => TraceOp::Remove(&key),
+                TraceOp::PopFirst => TraceOp::PopFirst,
+                TraceOp::PopLast => TraceOp::PopLast,
+                TraceOp::Clear => TraceOp::Clear,
+            });
+        }
+
+        anonymized
+    }
+
+    /// Apply every operation in the trace to `map`, in order.
+    ///
+    /// Since the trace only records keys, `make_entry` is called with the key
+    /// bytes of every insert to produce the key and value to insert.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use blart::{trace::TracedTreeMap, TreeMap};
+    ///
+    /// let mut traced = TracedTreeMap::<Box<[u8]>, usize>::new();
+    /// traced.try_insert(Box::new([1, 2]), 0).unwrap();
+    /// traced.try_insert(Box::new([1, 3]), 1).unwrap();
+    /// traced.get([1, 2].as_ref());
+    /// traced.remove([1, 3].as_ref());
+    ///
+    /// let (original, trace) = traced.into_parts();
+    ///
+    /// let mut replayed = TreeMap::new();
+    /// let summary = trace.replay(&mut replayed, |key| (Box::from(key), 0));
+    ///
+    /// assert_eq!(summary.get_hits, 1);
+    /// assert_eq!(replayed.len(), original.len());
+    /// assert!(replayed.contains_key([1, 2].as_ref()));
+    /// ```
+    pub fn replay<K, V>(
+        &self,
+        map: &mut TreeMap<K, V>,
+        mut make_entry: impl FnMut(&[u8]) -> (K, V),
+    ) -> ReplaySummary
+    where
+        K: AsBytes + Borrow<[u8]>,
+    {
+        let mut summary = ReplaySummary::default();
+        let mut reader = self.reader();
+
+        while let Some(op) = reader.next_op() {
+            match op {
+                TraceOp::Get(key) => {
+                    if map.get(key).is_some() {
+                        summary.get_hits += 1;
+                    } else {
+                        summary.get_misses += 1;
+                    }
+                },
+                TraceOp::Insert(key) => {
+                    let (key, value) = make_entry(key);
+                    if map.try_insert(key, value).is_err() {
+                        summary.insert_errors += 1;
+                    }
+                },
+                TraceOp::Remove(key) => {
+                    map.remove(key);
+                },
+                TraceOp::PopFirst => {
+                    map.pop_first();
+                },
+                TraceOp::PopLast => {
+                    map.pop_last();
+                },
+                TraceOp::Clear => map.clear(),
+            }
+        }
+
+        summary
+    }
+}
+
+impl Default for Trace {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut list = f.debug_list();
+        let mut reader = self.reader();
+        while let Some(op) = reader.next_op() {
+            list.entry(&op);
+        }
+        list.finish()
+    }
+}
+
+/// Counts of the outcomes of the operations applied by [`Trace::replay`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ReplaySummary {
+    /// The number of [`TraceOp::Get`] operations that found a value
+    pub get_hits: usize,
+    /// The number of [`TraceOp::Get`] operations that did not find a value
+    pub get_misses: usize,
+    /// The number of [`TraceOp::Insert`] operations that failed because of a
+    /// prefix conflict with an existing key
+    pub insert_errors: usize,
+}
+
+/// A reader over the operations of a [`Trace`].
+///
+/// Keys are delta-encoded against the previous key, so the reader reuses a
+/// single buffer for the current key and yields operations that borrow from
+/// the reader.
+pub struct TraceReader<'a> {
+    decoder: Decoder<'a>,
+}
+
+impl<'a> TraceReader<'a> {
+    /// Returns the next operation, or `None` if the end of the trace has been
+    /// reached.
+    pub fn next_op(&mut self) -> Option<TraceOp<'_>> {
+        // PANIC SAFETY: A `Trace` only contains bytes that were either produced by
+        // `Trace::push` or validated by `Trace::from_bytes`.
+        self.decoder
+            .next_op()
+            .expect("trace bytes should have been validated")
+    }
+}
+
+impl<'a> fmt::Debug for TraceReader<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TraceReader")
+            .field("offset", &self.decoder.offset)
+            .finish()
+    }
+}
+
+/// The error returned when loading a [`Trace`] from invalid bytes.
+#[derive(Clone, PartialEq, Eq)]
+pub struct TraceDecodeError {
+    /// The byte offset in the input at which decoding failed
+    pub offset: usize,
+    reason: &'static str,
+}
+
+impl fmt::Debug for TraceDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TraceDecodeError")
+            .field("offset", &self.offset)
+            .field("reason", &self.reason)
+            .finish()
+    }
+}
+
+impl fmt::Display for TraceDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid trace at byte offset {}: {}",
+            self.offset, self.reason
+        )
+    }
+}
+
+impl Error for TraceDecodeError {}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    key: Vec<u8>,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Decoder {
+            bytes,
+            offset: 0,
+            key: Vec::new(),
+        }
+    }
+
+    fn error(&self, reason: &'static str) -> TraceDecodeError {
+        TraceDecodeError {
+            offset: self.offset,
+            reason,
+        }
+    }
+
+    fn read_byte(&mut self) -> Result<u8, TraceDecodeError> {
+        let byte = *self
+            .bytes
+            .get(self.offset)
+            .ok_or_else(|| self.error("unexpected end of input"))?;
+        self.offset += 1;
+        Ok(byte)
+    }
+
+    fn read_leb128(&mut self) -> Result<usize, TraceDecodeError> {
+        let mut value: usize = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.read_byte()?;
+            let low_bits = usize::from(byte & 0x7F);
+            if shift >= usize::BITS || (low_bits << shift) >> shift != low_bits {
+                return Err(self.error("length does not fit in a usize"));
+            }
+            value |= low_bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+            shift += 7;
+        }
+    }
+
+    fn read_key(&mut self) -> Result<(), TraceDecodeError> {
+        let shared = self.read_leb128()?;
+        if shared > self.key.len() {
+            return Err(self.error("shared prefix is longer than the previous key"));
+        }
+        let suffix_len = self.read_leb128()?;
+        let suffix = self
+            .bytes
+            .get(self.offset..)
+            .and_then(|rest| rest.get(..suffix_len))
+            .ok_or_else(|| self.error("unexpected end of input"))?;
+
+        self.key.truncate(shared);
+        self.key.extend_from_slice(suffix);
+        self.offset += suffix_len;
+        Ok(())
+    }
+
+    fn next_op(&mut self) -> Result<Option<TraceOp<'_>>, TraceDecodeError> {
+        if self.offset == 0 && self.read_byte()? != FORMAT_VERSION {
+            return Err(TraceDecodeError {
+                offset: 0,
+                reason: "unsupported format version",
+            });
+        }
+
+        if self.offset == self.bytes.len() {
+            return Ok(None);
+        }
+
+        let op = match self.read_byte()? {
+            TAG_GET => {
+                self.read_key()?;
+                TraceOp::Get(&self.key)
+            },
+            TAG_INSERT => {
+                self.read_key()?;
+                TraceOp::Insert(&self.key)
+            },
+            TAG_REMOVE => {
+                self.read_key()?;
+                TraceOp::Remove(&self.key)
+            },
+            TAG_POP_FIRST => TraceOp::PopFirst,
+            TAG_POP_LAST => TraceOp::PopLast,
+            TAG_CLEAR => TraceOp::Clear,
+            _ => {
+                self.offset -= 1;
+                return Err(self.error("unknown operation tag"));
+            },
+        };
+
+        Ok(Some(op))
+    }
+}
+
+fn write_leb128(out: &mut Vec<u8>, mut value: usize) {
+    loop {
+        // PANIC SAFETY: The value is masked to 7 bits, so it always fits in a u8
+        let byte = u8::try_from(value & 0x7F).unwrap();
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Produce a pseudo-random permutation of all byte values from the given
+/// seed, using a Fisher-Yates shuffle driven by SplitMix64.
+fn byte_permutation(seed: u64) -> [u8; 256] {
+    let mut state = seed;
+    let mut next_random = move || {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    };
+
+    let mut permutation = [0u8; 256];
+    for (byte, slot) in (0..=u8::MAX).zip(permutation.iter_mut()) {
+        *slot = byte;
+    }
+    for idx in (1..permutation.len()).rev() {
+        // PANIC SAFETY: The result of the modulo is at most `idx`, which is at most 255
+        let swap_idx = usize::try_from(next_random() % (idx as u64 + 1)).unwrap();
+        permutation.swap(idx, swap_idx);
+    }
+
+    permutation
+}
+
+/// A [`TreeMap`] which records every operation performed on it into a
+/// [`Trace`].
+///
+/// # Examples
+///
+/// ```rust
+/// use blart::trace::{TraceOp, TracedTreeMap};
+///
+/// let mut map = TracedTreeMap::<Box<[u8]>, char>::new();
+///
+/// map.try_insert(Box::new([1, 2, 3]), 'a').unwrap();
+/// assert_eq!(map.get([1, 2, 3].as_ref()), Some(&'a'));
+///
+/// let mut reader = map.trace().reader();
+/// assert_eq!(reader.next_op(), Some(TraceOp::Insert(&[1, 2, 3])));
+/// assert_eq!(reader.next_op(), Some(TraceOp::Get(&[1, 2, 3])));
+/// assert_eq!(reader.next_op(), None);
+/// ```
+#[derive(Debug)]
+pub struct TracedTreeMap<K, V> {
+    map: TreeMap<K, V>,
+    trace: Trace,
+}
+
+impl<K, V> TracedTreeMap<K, V> {
+    /// Create a new, empty map with an empty trace.
+    pub fn new() -> Self {
+        TracedTreeMap {
+            map: TreeMap::new(),
+            trace: Trace::new(),
+        }
+    }
+
+    /// Returns a reference to the underlying map.
+    pub fn map(&self) -> &TreeMap<K, V> {
+        &self.map
+    }
+
+    /// Returns a reference to the trace recorded so far.
+    pub fn trace(&self) -> &Trace {
+        &self.trace
+    }
+
+    /// Split this into the underlying map and the recorded trace.
+    pub fn into_parts(self) -> (TreeMap<K, V>, Trace) {
+        (self.map, self.trace)
+    }
+
+    /// Returns a reference to the value corresponding to the key, and records
+    /// a [`TraceOp::Get`].
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q> + AsBytes,
+        Q: AsBytes + ?Sized,
+    {
+        self.trace.push(TraceOp::Get(key.as_bytes()));
+        self.map.get(key)
+    }
+
+    /// Inserts a key-value pair into the map, and records a
+    /// [`TraceOp::Insert`].
+    ///
+    /// See [`TreeMap::insert`].
+    pub fn insert(&mut self, key: K, value: V) -> Option<V>
+    where
+        K: NoPrefixesBytes,
+    {
+        self.trace.push(TraceOp::Insert(key.as_bytes()));
+        self.map.insert(key, value)
+    }
+
+    /// Inserts a key-value pair into the map, and records a
+    /// [`TraceOp::Insert`].
+    ///
+    /// # Errors
+    ///
+    /// See [`TreeMap::try_insert`].
+    pub fn try_insert(&mut self, key: K, value: V) -> Result<Option<V>, InsertPrefixError>
+    where
+        K: AsBytes,
+    {
+        self.trace.push(TraceOp::Insert(key.as_bytes()));
+        self.map.try_insert(key, value)
+    }
+
+    /// Removes a key from the map, and records a [`TraceOp::Remove`].
+    ///
+    /// See [`TreeMap::remove`].
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q> + AsBytes,
+        Q: AsBytes + ?Sized,
+    {
+        self.trace.push(TraceOp::Remove(key.as_bytes()));
+        self.map.remove(key)
+    }
+
+    /// Removes the minimum entry from the map, and records a
+    /// [`TraceOp::PopFirst`].
+    pub fn pop_first(&mut self) -> Option<(K, V)> {
+        self.trace.push(TraceOp::PopFirst);
+        self.map.pop_first()
+    }
+
+    /// Removes the maximum entry from the map, and records a
+    /// [`TraceOp::PopLast`].
+    pub fn pop_last(&mut self) -> Option<(K, V)> {
+        self.trace.push(TraceOp::PopLast);
+        self.map.pop_last()
+    }
+
+    /// Removes all entries from the map, and records a [`TraceOp::Clear`].
+    pub fn clear(&mut self) {
+        self.trace.push(TraceOp::Clear);
+        self.map.clear()
+    }
+}
+
+impl<K, V> Default for TracedTreeMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        deallocate_tree, tests_common::generate_key_fixed_length, visitor::TreeStatsCollector,
+    };
+
+    #[test]
+    fn shared_prefixes_are_delta_encoded() {
+        let mut trace = Trace::new();
+        trace.push(TraceOp::Insert(&[7; 200]));
+        let len_after_first = trace.as_bytes().len();
+        let mut key = [7; 200];
+        key[199] = 8;
+        trace.push(TraceOp::Get(&key));
+
+        // tag, shared length (2 bytes of LEB128), suffix length, and one suffix byte
+        assert_eq!(trace.as_bytes().len() - len_after_first, 5);
+    }
+
+    #[test]
+    fn from_bytes_rejects_invalid_traces() {
+        let mut trace = Trace::new();
+        trace.push(TraceOp::Insert(&[1, 2, 3]));
+        trace.push(TraceOp::Remove(&[1, 2]));
+        let bytes = trace.as_bytes();
+
+        // Cutting the trace anywhere except at the boundary between two operations
+        // makes it invalid
+        let op_boundaries = [1, 7];
+        for truncated_len in 0..bytes.len() {
+            if !op_boundaries.contains(&truncated_len) {
+                assert!(Trace::from_bytes(&bytes[..truncated_len]).is_err());
+            }
+        }
+        assert_eq!(Trace::from_bytes(&bytes[..7]).unwrap().len(), 1);
+
+        assert_eq!(
+            Trace::from_bytes(&[FORMAT_VERSION, 42]).unwrap_err().offset,
+            1
+        );
+        // The shared prefix of the first key cannot be longer than the empty key
+        assert!(Trace::from_bytes(&[FORMAT_VERSION, TAG_GET, 1, 0]).is_err());
+        assert!(Trace::from_bytes(&[2]).is_err());
+    }
+
+    #[test]
+    fn replay_reproduces_map() {
+        let mut traced = TracedTreeMap::new();
+        for (value, key) in generate_key_fixed_length([3, 2, 4]).enumerate() {
+            traced.try_insert(key, value).unwrap();
+        }
+        for key in generate_key_fixed_length([3, 2, 4]).step_by(3) {
+            traced.get(key.as_ref());
+            traced.remove(key.as_ref());
+        }
+        traced.pop_first();
+        traced.pop_last();
+        traced.get([0, 0, 0].as_ref());
+
+        let (original, trace) = traced.into_parts();
+        let trace = Trace::from_bytes(trace.as_bytes()).unwrap();
+
+        let mut values = 0..;
+        let mut replayed = TreeMap::new();
+        let summary = trace.replay(&mut replayed, |key| {
+            (Box::<[u8]>::from(key), values.next().unwrap())
+        });
+
+        assert_eq!(summary.get_hits, 20);
+        assert_eq!(summary.get_misses, 1);
+        assert_eq!(summary.insert_errors, 0);
+        assert_eq!(replayed, original);
+    }
+
+    #[test]
+    fn anonymized_trace_builds_same_shape() {
+        let keys: Vec<_> = generate_key_fixed_length([2, 3, 1]).collect();
+        let mut trace = Trace::new();
+        for key in &keys {
+            trace.push(TraceOp::Insert(key));
+        }
+        trace.push(TraceOp::Remove(&keys[5]));
+
+        let anonymized = trace.anonymize(0xDEAD_BEEF);
+        assert_eq!(anonymized.len(), trace.len());
+
+        let mut original_map = TreeMap::new();
+        trace.replay(&mut original_map, |key| (Box::<[u8]>::from(key), ()));
+        let mut anonymized_map = TreeMap::new();
+        anonymized.replay(&mut anonymized_map, |key| (Box::<[u8]>::from(key), ()));
+
+        assert_eq!(original_map.len(), keys.len() - 1);
+        assert_eq!(anonymized_map.len(), keys.len() - 1);
+        assert!(original_map.keys().ne(anonymized_map.keys()));
+
+        let original_root = original_map.into_raw().unwrap();
+        let anonymized_root = anonymized_map.into_raw().unwrap();
+        // SAFETY: Both trees are exclusively owned here and not mutated while the
+        // statistics are collected, and they are deallocated exactly once.
+        unsafe {
+            assert_eq!(
+                TreeStatsCollector::collect(original_root),
+                TreeStatsCollector::collect(anonymized_root)
+            );
+            deallocate_tree(original_root);
+            deallocate_tree(anonymized_root);
+        }
+    }
+}
@@ -0,0 +1,437 @@
+//! A [`TreeMap`] wrapper that can roll mutations back to a savepoint.
+//!
+//! While at least one [`Savepoint`] is active, every mutation of an
+//! [`UndoTreeMap`] records its inverse operation in an undo log. Calling
+//! [`UndoTreeMap::rollback`] applies the inverse operations in reverse order,
+//! restoring the contents of the map as they were when the savepoint was
+//! created, without ever cloning the whole tree. When no savepoint is active,
+//! nothing is recorded.
+
+use crate::{AsBytes, InsertPrefixError, NoPrefixesBytes, TreeMap};
+use std::borrow::Borrow;
+
+/// The inverse of a single mutation of the map.
+#[derive(Debug)]
+enum UndoEntry<K, V> {
+    /// The key was newly inserted, undo by removing it
+    Remove(K),
+    /// The key was removed or its value was overwritten, undo by inserting
+    /// the old entry again
+    Restore(K, V),
+}
+
+/// A handle to a point in the history of an [`UndoTreeMap`], created by
+/// [`UndoTreeMap::savepoint`].
+#[derive(Debug, PartialEq, Eq)]
+#[must_use = "a savepoint should be released or rolled back"]
+pub struct Savepoint {
+    id: u64,
+}
+
+/// A [`TreeMap`] which records the inverse of every mutation while a
+/// [`Savepoint`] is active, so that the mutations can be rolled back.
+///
+/// Savepoints nest: rolling back to or releasing a savepoint also rolls back
+/// or releases every savepoint created after it.
+///
+/// Mutations that return the previous value clone it while a savepoint is
+/// active, since one copy is returned to the caller and another is kept in the
+/// undo log.
+///
+/// # Examples
+///
+/// ```rust
+/// use blart::undo::UndoTreeMap;
+///
+/// let mut map = UndoTreeMap::<Box<[u8]>, char>::new();
+/// map.try_insert(Box::new([1, 2, 3]), 'a').unwrap();
+///
+/// let savepoint = map.savepoint();
+/// map.try_insert(Box::new([1, 2, 3]), 'b').unwrap();
+/// map.try_insert(Box::new([4, 5, 6]), 'c').unwrap();
+/// assert_eq!(map.remove([1, 2, 3].as_ref()), Some('b'));
+/// assert_eq!(map.len(), 1);
+///
+/// map.rollback(savepoint);
+/// assert_eq!(map.get([1, 2, 3].as_ref()), Some(&'a'));
+/// assert_eq!(map.get([4, 5, 6].as_ref()), None);
+/// assert_eq!(map.len(), 1);
+/// ```
+#[derive(Debug)]
+pub struct UndoTreeMap<K, V> {
+    map: TreeMap<K, V>,
+    log: Vec<UndoEntry<K, V>>,
+    /// The active savepoints, as pairs of the savepoint ID and the length of
+    /// the undo log when the savepoint was created.
+    savepoints: Vec<(u64, usize)>,
+    next_savepoint_id: u64,
+}
+
+impl<K, V> UndoTreeMap<K, V> {
+    /// Create a new, empty map with no active savepoints.
+    pub fn new() -> Self {
+        Self::from_map(TreeMap::new())
+    }
+
+    /// Wrap an existing map, with no active savepoints.
+    pub fn from_map(map: TreeMap<K, V>) -> Self {
+        UndoTreeMap {
+            map,
+            log: Vec::new(),
+            savepoints: Vec::new(),
+            next_savepoint_id: 0,
+        }
+    }
+
+    /// Returns a reference to the underlying map.
+    pub fn map(&self) -> &TreeMap<K, V> {
+        &self.map
+    }
+
+    /// Return the underlying map, keeping all mutations and discarding any
+    /// active savepoints.
+    pub fn into_map(self) -> TreeMap<K, V> {
+        self.map
+    }
+
+    /// Returns the number of elements in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if the map contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Returns a reference to the value corresponding to the key.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q> + AsBytes,
+        Q: AsBytes + ?Sized,
+    {
+        self.map.get(key)
+    }
+
+    /// Returns `true` if the map contains a value for the specified key.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q> + AsBytes,
+        Q: AsBytes + ?Sized,
+    {
+        self.map.contains_key(key)
+    }
+
+    /// Returns `true` if at least one savepoint is active, meaning that
+    /// mutations are being recorded.
+    pub fn is_recording(&self) -> bool {
+        !self.savepoints.is_empty()
+    }
+
+    /// Create a new savepoint at the current state of the map.
+    pub fn savepoint(&mut self) -> Savepoint {
+        let id = self.next_savepoint_id;
+        self.next_savepoint_id += 1;
+        self.savepoints.push((id, self.log.len()));
+
+        Savepoint { id }
+    }
+
+    /// Return the position of the given savepoint in the stack of active
+    /// savepoints.
+    ///
+    /// # Panics
+    ///
+    ///  - Panics if the savepoint is not active.
+    fn savepoint_position(&self, savepoint: &Savepoint) -> usize {
+        self.savepoints
+            .iter()
+            .rposition(|(id, _)| *id == savepoint.id)
+            .expect("savepoint should not have been released or rolled back already")
+    }
+
+    /// Release the given savepoint, and every savepoint created after it,
+    /// keeping all mutations made since.
+    ///
+    /// # Panics
+    ///
+    ///  - Panics if the savepoint was already released or rolled back.
+    pub fn release(&mut self, savepoint: Savepoint) {
+        let position = self.savepoint_position(&savepoint);
+        self.savepoints.truncate(position);
+
+        if self.savepoints.is_empty() {
+            self.log.clear();
+        }
+    }
+
+    fn record(&mut self, entry: UndoEntry<K, V>) {
+        if self.is_recording() {
+            self.log.push(entry);
+        }
+    }
+}
+
+impl<K, V> UndoTreeMap<K, V>
+where
+    K: AsBytes,
+{
+    /// Undo every mutation made since the given savepoint was created, and
+    /// release it along with every savepoint created after it.
+    ///
+    /// # Panics
+    ///
+    ///  - Panics if the savepoint was already released or rolled back.
+    pub fn rollback(&mut self, savepoint: Savepoint) {
+        let position = self.savepoint_position(&savepoint);
+        let (_, log_len) = self.savepoints[position];
+        self.savepoints.truncate(position);
+
+        while self.log.len() > log_len {
+            // PANIC SAFETY: The log is longer than `log_len`, so it is not empty
+            match self.log.pop().unwrap() {
+                UndoEntry::Remove(key) => {
+                    self.map.remove(&key);
+                },
+                UndoEntry::Restore(key, value) => {
+                    // PANIC SAFETY: The key was present in the map at this point in its
+                    // history, and every key inserted since has already been removed again,
+                    // so there can be no prefix conflict.
+                    self.map
+                        .try_insert(key, value)
+                        .expect("restored key should not conflict with existing keys");
+                },
+            }
+        }
+
+        if self.savepoints.is_empty() {
+            self.log.clear();
+        }
+    }
+}
+
+impl<K, V> UndoTreeMap<K, V>
+where
+    K: AsBytes + Clone,
+    V: Clone,
+{
+    /// Returns a mutable reference to the value corresponding to the key.
+    ///
+    /// While a savepoint is active, this records a copy of the current value,
+    /// since any modification through the reference cannot be observed.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: AsBytes + ?Sized,
+    {
+        if self.is_recording() {
+            let (key, value) = self.map.get_key_value(key)?;
+            let entry = UndoEntry::Restore(key.clone(), value.clone());
+            self.log.push(entry);
+        }
+
+        self.map.get_mut(key)
+    }
+
+    /// Insert a key-value pair into the map, see [`TreeMap::insert`].
+    pub fn insert(&mut self, key: K, value: V) -> Option<V>
+    where
+        K: NoPrefixesBytes,
+    {
+        match self.try_insert(key, value) {
+            Ok(value) => value,
+            Err(_err) => unreachable!(
+                "This branch should be unreachable because of the safety contract of \
+                 `NoPrefixesBytes`"
+            ),
+        }
+    }
+
+    /// Insert a key-value pair into the map, see [`TreeMap::try_insert`].
+    ///
+    /// # Errors
+    ///
+    ///  - If the map has an existing key, such that the new key is a prefix of
+    ///    the existing key or vice versa, then it returns an error. Nothing is
+    ///    recorded in that case.
+    pub fn try_insert(&mut self, key: K, value: V) -> Result<Option<V>, InsertPrefixError> {
+        if !self.is_recording() {
+            return self.map.try_insert(key, value);
+        }
+
+        let undo_key = key.clone();
+        match self.map.try_insert(key, value)? {
+            Some(old_value) => {
+                self.log
+                    .push(UndoEntry::Restore(undo_key, old_value.clone()));
+                Ok(Some(old_value))
+            },
+            None => {
+                self.log.push(UndoEntry::Remove(undo_key));
+                Ok(None)
+            },
+        }
+    }
+
+    /// Removes a key from the map, returning the stored key and value if the
+    /// key was previously in the map.
+    pub fn remove_entry<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: AsBytes + ?Sized,
+    {
+        let (key, value) = self.map.remove_entry(key)?;
+        self.record_removal(key, value)
+    }
+
+    /// Removes a key from the map, returning the value at the key if the key
+    /// was previously in the map.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: AsBytes + ?Sized,
+    {
+        Some(self.remove_entry(key)?.1)
+    }
+
+    /// Removes and returns the first element in the map.
+    pub fn pop_first(&mut self) -> Option<(K, V)> {
+        let (key, value) = self.map.pop_first()?;
+        self.record_removal(key, value)
+    }
+
+    /// Removes and returns the last element in the map.
+    pub fn pop_last(&mut self) -> Option<(K, V)> {
+        let (key, value) = self.map.pop_last()?;
+        self.record_removal(key, value)
+    }
+
+    /// Clear the map, removing all elements.
+    ///
+    /// While a savepoint is active, this records every entry of the map, so
+    /// it costs the same as removing each entry individually.
+    pub fn clear(&mut self) {
+        if !self.is_recording() {
+            self.map.clear();
+            return;
+        }
+
+        while let Some((key, value)) = self.map.pop_last() {
+            self.log.push(UndoEntry::Restore(key, value));
+        }
+    }
+
+    fn record_removal(&mut self, key: K, value: V) -> Option<(K, V)> {
+        if self.is_recording() {
+            let removed = (key.clone(), value.clone());
+            self.record(UndoEntry::Restore(key, value));
+            Some(removed)
+        } else {
+            Some((key, value))
+        }
+    }
+}
+
+impl<K, V> Default for UndoTreeMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_common::generate_key_fixed_length;
+
+    fn contents(map: &UndoTreeMap<Box<[u8]>, usize>) -> Vec<(Box<[u8]>, usize)> {
+        map.map()
+            .iter()
+            .map(|(key, value)| (key.clone(), *value))
+            .collect()
+    }
+
+    #[test]
+    fn rollback_restores_all_mutation_kinds() {
+        let mut map = UndoTreeMap::new();
+        for (value, key) in generate_key_fixed_length([2, 2, 2]).enumerate() {
+            map.try_insert(key, value).unwrap();
+        }
+        let before = contents(&map);
+
+        let savepoint = map.savepoint();
+        map.try_insert(Box::from([1, 1, 1]), 100).unwrap();
+        map.try_insert(before[0].0.clone(), 101).unwrap();
+        map.remove(before[1].0.as_ref());
+        map.pop_first();
+        map.pop_last();
+        *map.get_mut(before[5].0.as_ref()).unwrap() = 102;
+        assert_ne!(contents(&map), before);
+
+        map.rollback(savepoint);
+        assert_eq!(contents(&map), before);
+        assert!(!map.is_recording());
+    }
+
+    #[test]
+    fn rollback_after_clear() {
+        let mut map = UndoTreeMap::new();
+        for (value, key) in generate_key_fixed_length([3, 3]).enumerate() {
+            map.try_insert(key, value).unwrap();
+        }
+        let before = contents(&map);
+
+        let savepoint = map.savepoint();
+        map.clear();
+        map.try_insert(Box::from([7, 7]), 0).unwrap();
+        assert_eq!(map.len(), 1);
+
+        map.rollback(savepoint);
+        assert_eq!(contents(&map), before);
+    }
+
+    #[test]
+    fn nested_savepoints() {
+        let mut map = UndoTreeMap::new();
+        map.try_insert(Box::<[u8]>::from([1]), 1).unwrap();
+
+        let outer = map.savepoint();
+        map.try_insert(Box::from([2]), 2).unwrap();
+        let inner = map.savepoint();
+        map.try_insert(Box::from([3]), 3).unwrap();
+
+        map.rollback(inner);
+        assert_eq!(map.len(), 2);
+        assert!(map.is_recording());
+
+        let inner = map.savepoint();
+        map.try_insert(Box::from([4]), 4).unwrap();
+        // Keep the changes since `inner`, they still belong to `outer`
+        map.release(inner);
+        assert_eq!(map.len(), 3);
+
+        map.rollback(outer);
+        assert_eq!(contents(&map), vec![(Box::from([1]), 1)]);
+    }
+
+    #[test]
+    fn release_keeps_mutations_and_stops_recording() {
+        let mut map = UndoTreeMap::new();
+        let savepoint = map.savepoint();
+        map.try_insert(Box::<[u8]>::from([1]), 1).unwrap();
+        map.release(savepoint);
+
+        assert!(!map.is_recording());
+        assert!(map.log.is_empty());
+        assert_eq!(map.into_map().len(), 1);
+    }
+
+    #[test]
+    #[should_panic = "savepoint should not have been released or rolled back already"]
+    fn rollback_of_released_savepoint_panics() {
+        let mut map = UndoTreeMap::<Box<[u8]>, usize>::new();
+        let outer = map.savepoint();
+        let inner = map.savepoint();
+        map.release(outer);
+        map.rollback(inner);
+    }
+}
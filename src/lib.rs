@@ -10,6 +10,7 @@
         strict_provenance,
     )
 )]
+#![cfg_attr(feature = "allocator_api", feature(allocator_api))]
 #![allow(unstable_name_collisions)]
 #![deny(
     missing_docs,
@@ -36,10 +37,12 @@
 //!
 //! [ART paper]: https://www-db.in.tum.de/~leis/papers/ART.pdf
 
+pub mod allocator;
 mod bytes;
 mod collections;
 mod nightly_rust_apis;
 mod nodes;
+pub mod serialize;
 pub mod tagged_pointer;
 #[doc(hidden)]
 pub mod tests_common;
@@ -47,6 +50,7 @@ pub mod tests_common;
 pub use bytes::*;
 pub use collections::*;
 pub use nodes::*;
+pub use serialize::{from_bytes, to_bytes};
 
 #[doc = include_str!("../README.md")]
 #[cfg(doctest)]
@@ -1,10 +1,25 @@
 //! Trie node lookup and manipulation
 
-use crate::{ConcreteNodePtr, InnerNode, NodePtr, OpaqueNodePtr};
+use crate::{
+    allocator::{Allocator, Global},
+    ConcreteNodePtr, InnerNode, NodePtr, OpaqueNodePtr,
+};
 
 mod insert;
 pub use insert::*;
 
+mod bulk;
+pub use bulk::*;
+
+mod clone;
+pub use clone::*;
+
+mod merge;
+pub use merge::*;
+
+mod split;
+pub use split::*;
+
 mod minmax;
 pub use minmax::*;
 
@@ -14,57 +29,228 @@ pub use lookup::*;
 mod iterator;
 pub use iterator::*;
 
+mod fuzzy;
+pub use fuzzy::*;
+
 mod delete;
 pub use delete::*;
 
+mod retain;
+pub(crate) use retain::*;
+
+mod search_stats;
+pub use search_stats::*;
+
 /// Deallocate the given node and all children of the given node.
 ///
 /// This will also deallocate the leaf nodes with their value type data.
 ///
+/// The nodes waiting to be deallocated are kept on a heap allocated stack
+/// instead of the call stack, so the depth of the tree does not affect the
+/// stack usage of this function. Trees built from very long keys, which may be
+/// arbitrarily deep, can be deallocated on a thread with a small stack.
+///
 /// # Safety
 ///
 ///  - This function must only be called once for this root node and all
 ///    descendants, otherwise a double-free could result.
+///  - The nodes must have been allocated by the [`Global`] allocator.
 pub unsafe fn deallocate_tree<K, V>(root: OpaqueNodePtr<K, V>) {
-    fn deallocate_inner_node<K, V, N>(stack: &mut Vec<OpaqueNodePtr<K, V>>, inner_ptr: NodePtr<N>)
-    where
-        N: InnerNode<Key = K, Value = V>,
-    {
-        {
-            // SAFETY: The scope of this reference is bounded and we enforce that no
-            // mutation of the reference memory takes place within the lifetime. The
-            // deallocation of the node happens outside of this block, after the lifetime
-            // ends.
-            let inner_node = unsafe { inner_ptr.as_ref() };
-
-            // SAFETY: This iterator only lives for this block, a subset of the shared
-            // lifetime of the `inner_node` variable. By the safety requirements of the
-            // `deallocate_tree` function, no other mutation of this node can happen while
-            // this iterator is live.
-            let iter = unsafe { inner_node.iter() };
-            stack.extend(iter.map(|(_, child)| child));
-        }
-
-        // SAFETY: The single call per node requirement is enforced by the safety
-        // requirements on this function.
-        drop(unsafe { NodePtr::deallocate_node_ptr(inner_ptr) });
-    }
+    // SAFETY: Covered by the safety requirements of this function
+    unsafe { deallocate_tree_in(root, &Global) }
+}
 
+/// Deallocate the given node and all children of the given node, returning
+/// the memory to the given allocator.
+///
+/// See [`deallocate_tree`] for more details.
+///
+/// # Safety
+///
+///  - This function must only be called once for this root node and all
+///    descendants, otherwise a double-free could result.
+///  - The nodes must have been allocated by the given allocator.
+pub unsafe fn deallocate_tree_in<K, V, A: Allocator>(root: OpaqueNodePtr<K, V>, alloc: &A) {
     let mut stack = Vec::new();
 
     stack.push(root);
 
     while let Some(next_node_ptr) = stack.pop() {
         match next_node_ptr.to_node_ptr() {
-            ConcreteNodePtr::Node4(inner_ptr) => deallocate_inner_node(&mut stack, inner_ptr),
-            ConcreteNodePtr::Node16(inner_ptr) => deallocate_inner_node(&mut stack, inner_ptr),
-            ConcreteNodePtr::Node48(inner_ptr) => deallocate_inner_node(&mut stack, inner_ptr),
-            ConcreteNodePtr::Node256(inner_ptr) => deallocate_inner_node(&mut stack, inner_ptr),
+            ConcreteNodePtr::Node4(inner_ptr) => unsafe {
+                // SAFETY: The single call per node requirement is enforced by the safety
+                // requirements on this function.
+                deallocate_inner_node(&mut stack, inner_ptr, alloc)
+            },
+            ConcreteNodePtr::Node16(inner_ptr) => unsafe {
+                // SAFETY: The single call per node requirement is enforced by the safety
+                // requirements on this function.
+                deallocate_inner_node(&mut stack, inner_ptr, alloc)
+            },
+            ConcreteNodePtr::Node48(inner_ptr) => unsafe {
+                // SAFETY: The single call per node requirement is enforced by the safety
+                // requirements on this function.
+                deallocate_inner_node(&mut stack, inner_ptr, alloc)
+            },
+            ConcreteNodePtr::Node256(inner_ptr) => unsafe {
+                // SAFETY: The single call per node requirement is enforced by the safety
+                // requirements on this function.
+                deallocate_inner_node(&mut stack, inner_ptr, alloc)
+            },
             ConcreteNodePtr::LeafNode(inner) => {
                 // SAFETY: The single call per node requirement is enforced by the safety
                 // requirements on this function.
-                drop(unsafe { NodePtr::deallocate_node_ptr(inner) })
+                drop(unsafe { NodePtr::deallocate_node_ptr_in(inner, alloc) })
             },
         }
     }
 }
+
+/// Deallocate the given node and all children of the given node, using the
+/// [`rayon`] thread pool to deallocate separate subtrees in parallel.
+///
+/// The top levels of the tree are deallocated on the calling thread, until
+/// there are enough independent subtrees to keep every thread in the pool
+/// busy. Each of those subtrees is then deallocated with [`deallocate_tree`]
+/// on the pool.
+///
+/// # Safety
+///
+///  - This function must only be called once for this root node and all
+///    descendants, otherwise a double-free could result.
+///  - The nodes must have been allocated by the [`Global`] allocator.
+#[cfg(feature = "rayon")]
+pub unsafe fn par_deallocate_tree<K, V>(root: OpaqueNodePtr<K, V>)
+where
+    K: Send,
+    V: Send,
+{
+    // SAFETY: Covered by the safety requirements of this function
+    unsafe { par_deallocate_tree_in(root, &Global) }
+}
+
+/// Deallocate the given node and all children of the given node, using the
+/// [`rayon`] thread pool to deallocate separate subtrees in parallel, and
+/// returning the memory to the given allocator.
+///
+/// See [`par_deallocate_tree`] for more details.
+///
+/// # Safety
+///
+///  - This function must only be called once for this root node and all
+///    descendants, otherwise a double-free could result.
+///  - The nodes must have been allocated by the given allocator.
+#[cfg(feature = "rayon")]
+pub unsafe fn par_deallocate_tree_in<K, V, A>(root: OpaqueNodePtr<K, V>, alloc: &A)
+where
+    K: Send,
+    V: Send,
+    A: Allocator + Sync,
+{
+    use rayon::prelude::*;
+
+    /// The number of subtrees to aim for per thread, so that an unbalanced
+    /// tree still spreads the work across the pool.
+    const SUBTREES_PER_THREAD: usize = 4;
+
+    /// A subtree whose nodes are only accessed by the thread that owns this
+    /// value.
+    struct Subtree<K, V>(OpaqueNodePtr<K, V>);
+
+    // SAFETY: The `Subtree` uniquely owns all the nodes under it, so sending it to
+    // another thread sends the keys and values, which requires that they are
+    // `Send`.
+    unsafe impl<K: Send, V: Send> Send for Subtree<K, V> {}
+
+    let target_subtrees = rayon::current_num_threads() * SUBTREES_PER_THREAD;
+    let mut subtrees = vec![root];
+
+    while subtrees.len() < target_subtrees {
+        let mut next_subtrees = Vec::with_capacity(subtrees.len() * 4);
+        let mut expanded = false;
+
+        for node_ptr in subtrees {
+            match node_ptr.to_node_ptr() {
+                ConcreteNodePtr::Node4(inner_ptr) => unsafe {
+                    // SAFETY: The single call per node requirement is enforced by the safety
+                    // requirements on this function.
+                    deallocate_inner_node(&mut next_subtrees, inner_ptr, alloc)
+                },
+                ConcreteNodePtr::Node16(inner_ptr) => unsafe {
+                    // SAFETY: The single call per node requirement is enforced by the safety
+                    // requirements on this function.
+                    deallocate_inner_node(&mut next_subtrees, inner_ptr, alloc)
+                },
+                ConcreteNodePtr::Node48(inner_ptr) => unsafe {
+                    // SAFETY: The single call per node requirement is enforced by the safety
+                    // requirements on this function.
+                    deallocate_inner_node(&mut next_subtrees, inner_ptr, alloc)
+                },
+                ConcreteNodePtr::Node256(inner_ptr) => unsafe {
+                    // SAFETY: The single call per node requirement is enforced by the safety
+                    // requirements on this function.
+                    deallocate_inner_node(&mut next_subtrees, inner_ptr, alloc)
+                },
+                ConcreteNodePtr::LeafNode(_) => {
+                    next_subtrees.push(node_ptr);
+                    continue;
+                },
+            }
+            expanded = true;
+        }
+
+        subtrees = next_subtrees;
+
+        if !expanded {
+            // Every remaining subtree is a single leaf
+            break;
+        }
+    }
+
+    subtrees
+        .into_iter()
+        .map(Subtree)
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .for_each(|subtree| {
+            // SAFETY: Every subtree is distinct and is deallocated exactly once, and the
+            // nodes above them were already deallocated without visiting them.
+            unsafe { deallocate_tree_in(subtree.0, alloc) }
+        });
+}
+
+/// Deallocate the given inner node, after pushing all its children onto the
+/// stack.
+///
+/// # Safety
+///
+///  - This function must only be called once for this inner node, and no other
+///    access or mutation of the node can happen while this function runs.
+///  - The node must have been allocated by the given allocator.
+unsafe fn deallocate_inner_node<K, V, N, A>(
+    stack: &mut Vec<OpaqueNodePtr<K, V>>,
+    inner_ptr: NodePtr<N>,
+    alloc: &A,
+) where
+    N: InnerNode<Key = K, Value = V>,
+    A: Allocator,
+{
+    {
+        // SAFETY: The scope of this reference is bounded and we enforce that no
+        // mutation of the reference memory takes place within the lifetime. The
+        // deallocation of the node happens outside of this block, after the lifetime
+        // ends.
+        let inner_node = unsafe { inner_ptr.as_ref() };
+
+        // SAFETY: This iterator only lives for this block, a subset of the shared
+        // lifetime of the `inner_node` variable. By the safety requirements of this
+        // function, no other mutation of this node can happen while this iterator is
+        // live.
+        let iter = unsafe { inner_node.iter() };
+        stack.extend(iter.map(|(_, child)| child));
+    }
+
+    // SAFETY: The single call per node requirement is enforced by the safety
+    // requirements on this function.
+    drop(unsafe { NodePtr::deallocate_node_ptr_in(inner_ptr, alloc) });
+}
@@ -0,0 +1,273 @@
+use crate::{
+    allocator::{Allocator, Global},
+    AsBytes, InnerNode, InnerNode16, InnerNode256, InnerNode4, InnerNode48, InsertPrefixError,
+    LeafNode, NodePtr, OpaqueNodePtr,
+};
+
+/// Build a tree holding the given entries, by partitioning the sorted keys on
+/// the byte that separates them and allocating each inner node with its final
+/// size.
+///
+/// If the entries are not sorted by their key bytes, they are sorted first.
+/// For keys with equal bytes, only the last entry is kept, the same as
+/// inserting the entries one at a time.
+///
+/// Returns `None` if there are no entries.
+///
+/// # Errors
+///
+/// If any key is a prefix of another key, this function will return an error
+/// for the longer key, and the entries are dropped.
+pub fn build_tree_from_entries<K, V>(
+    entries: Vec<(K, V)>,
+) -> Result<Option<BuildResult<K, V>>, InsertPrefixError>
+where
+    K: AsBytes,
+{
+    build_tree_from_entries_in(entries, &Global)
+}
+
+/// Build a tree holding the given entries, allocating the nodes with the given
+/// allocator.
+///
+/// See [`build_tree_from_entries`] for more details.
+///
+/// # Errors
+///
+/// If any key is a prefix of another key, this function will return an error
+/// for the longer key, and the entries are dropped.
+pub fn build_tree_from_entries_in<K, V, A>(
+    mut entries: Vec<(K, V)>,
+    alloc: &A,
+) -> Result<Option<BuildResult<K, V>>, InsertPrefixError>
+where
+    K: AsBytes,
+    A: Allocator,
+{
+    let is_sorted = entries
+        .windows(2)
+        .all(|pair| pair[0].0.as_bytes() <= pair[1].0.as_bytes());
+    if !is_sorted {
+        // The sort is stable, so the order of entries with equal keys is kept
+        entries.sort_by(|(a, _), (b, _)| a.as_bytes().cmp(b.as_bytes()));
+    }
+
+    // For equal keys `dedup_by` keeps the first entry, so the later entry is moved
+    // into its place before the earlier one is removed.
+    entries.dedup_by(|next, kept| {
+        let is_duplicate = next.0.as_bytes() == kept.0.as_bytes();
+        if is_duplicate {
+            std::mem::swap(next, kept);
+        }
+        is_duplicate
+    });
+
+    // Once the keys are sorted, a key which is a prefix of any other key is also a
+    // prefix of the key directly after it, so only neighbours are compared.
+    if let Some(pair) = entries
+        .windows(2)
+        .find(|pair| pair[1].0.as_bytes().starts_with(pair[0].0.as_bytes()))
+    {
+        return Err(InsertPrefixError {
+            byte_repr: pair[1].0.as_bytes().into(),
+        });
+    }
+
+    let num_entries = entries.len();
+    let leaves: Vec<_> = entries
+        .into_iter()
+        .map(|(key, value)| NodePtr::allocate_node_ptr_in(LeafNode::new(key, value), alloc))
+        .collect();
+    if leaves.is_empty() {
+        return Ok(None);
+    }
+
+    // SAFETY: The leaves were just allocated and are not reachable from anywhere
+    // else, they are sorted by key bytes, and no key is equal to or a prefix of
+    // another key.
+    let root = unsafe { build_from_sorted_leaves(&leaves, alloc) };
+    Ok(Some(BuildResult { root, num_entries }))
+}
+
+/// The result of building a tree from a list of entries.
+#[derive(Debug)]
+pub struct BuildResult<K, V> {
+    /// The root of the new tree
+    pub root: OpaqueNodePtr<K, V>,
+    /// The number of entries in the new tree, after removing the entries with
+    /// duplicate keys
+    pub num_entries: usize,
+}
+
+/// An inner node which is being built from the leaves in
+/// `leaves[start..end]`.
+struct PendingNode<K, V> {
+    /// The index of the first leaf under this node.
+    start: usize,
+    /// The index after the last leaf under this node.
+    end: usize,
+    /// The number of key bytes before the prefix of this node.
+    depth: usize,
+    /// The index of the key byte that separates the children of this node,
+    /// directly after the prefix.
+    child_depth: usize,
+    /// The index of the first leaf which is not under any of the children
+    /// built so far.
+    next_child_start: usize,
+    /// The children built so far, in key order.
+    children: Vec<(u8, OpaqueNodePtr<K, V>)>,
+}
+
+/// Return the key bytes of the given leaf.
+///
+/// # Safety
+///
+///  - The leaf cannot be mutated while the returned reference is live.
+unsafe fn leaf_key_bytes<'a, K: AsBytes + 'a, V: 'a>(leaf: NodePtr<LeafNode<K, V>>) -> &'a [u8] {
+    // SAFETY: Covered by the safety requirements of the containing function
+    unsafe { leaf.as_key_ref() }.as_bytes()
+}
+
+impl<K: AsBytes, V> PendingNode<K, V> {
+    /// Start a new inner node for the given range of leaves, which all share
+    /// the first `depth` key bytes.
+    ///
+    /// # Safety
+    ///
+    ///  - The leaves must not be mutated while this function runs.
+    unsafe fn new(
+        leaves: &[NodePtr<LeafNode<K, V>>],
+        start: usize,
+        end: usize,
+        depth: usize,
+    ) -> Self {
+        // SAFETY: Covered by the safety requirements of the containing function
+        let (first, last) = unsafe {
+            (
+                leaf_key_bytes(leaves[start]),
+                leaf_key_bytes(leaves[end - 1]),
+            )
+        };
+        // The leaves are sorted, so the bytes they all share are the bytes shared by
+        // the first and last leaf.
+        let prefix_len = first[depth..]
+            .iter()
+            .zip(&last[depth..])
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        PendingNode {
+            start,
+            end,
+            depth,
+            child_depth: depth + prefix_len,
+            next_child_start: start,
+            children: Vec::new(),
+        }
+    }
+
+    /// Allocate the inner node with the given allocator, using the smallest
+    /// node type that fits all the children.
+    fn allocate<A: Allocator>(self, prefix: &[u8], alloc: &A) -> OpaqueNodePtr<K, V> {
+        fn allocate_inner_node<K, V, N: InnerNode<Key = K, Value = V>, A: Allocator>(
+            mut node: N,
+            prefix: &[u8],
+            children: Vec<(u8, OpaqueNodePtr<K, V>)>,
+            alloc: &A,
+        ) -> OpaqueNodePtr<K, V> {
+            node.header_mut().extend_prefix(prefix);
+            for (key_fragment, child) in children {
+                node.write_child(key_fragment, child);
+            }
+            NodePtr::allocate_node_ptr_in(node, alloc).to_opaque()
+        }
+
+        match self.children.len() {
+            0..=4 => allocate_inner_node(InnerNode4::empty(), prefix, self.children, alloc),
+            5..=16 => allocate_inner_node(InnerNode16::empty(), prefix, self.children, alloc),
+            17..=48 => allocate_inner_node(InnerNode48::empty(), prefix, self.children, alloc),
+            _ => allocate_inner_node(InnerNode256::empty(), prefix, self.children, alloc),
+        }
+    }
+}
+
+/// Build the inner nodes above the given leaves, and return the root of the
+/// tree.
+///
+/// The nodes waiting for their children are kept on a heap allocated stack,
+/// so that the depth of the tree does not affect the stack usage of this
+/// function.
+///
+/// # Safety
+///
+///  - The leaves must not be reachable from any other tree, and must not be
+///    accessed while this function runs.
+///  - The leaves must be sorted by their key bytes, and no key can be equal to
+///    or a prefix of another key.
+///  - There must be at least one leaf.
+unsafe fn build_from_sorted_leaves<K: AsBytes, V, A: Allocator>(
+    leaves: &[NodePtr<LeafNode<K, V>>],
+    alloc: &A,
+) -> OpaqueNodePtr<K, V> {
+    if leaves.len() == 1 {
+        return leaves[0].to_opaque();
+    }
+
+    // SAFETY: The leaves are not accessed anywhere else, by the safety requirements
+    // of this function. All key references created below are only used while the
+    // leaves are not mutated.
+    let mut stack = vec![unsafe { PendingNode::new(leaves, 0, leaves.len(), 0) }];
+
+    loop {
+        // PANIC SAFETY: The stack is only empty after the root node is popped, which
+        // returns from the loop.
+        let node = stack.last_mut().expect("stack should not be empty");
+
+        if node.next_child_start < node.end {
+            let child_start = node.next_child_start;
+            // SAFETY: See the comment on the stack above. The sorted keys are not
+            // prefixes of each other, so every key under this node has a byte at
+            // `child_depth`.
+            let key_fragment = unsafe { leaf_key_bytes(leaves[child_start]) }[node.child_depth];
+            let child_end = child_start
+                + 1
+                + leaves[(child_start + 1)..node.end]
+                    .iter()
+                    // SAFETY: See above
+                    .take_while(
+                        |leaf| unsafe { leaf_key_bytes(**leaf) }[node.child_depth] == key_fragment,
+                    )
+                    .count();
+            node.next_child_start = child_end;
+
+            if child_end - child_start == 1 {
+                node.children
+                    .push((key_fragment, leaves[child_start].to_opaque()));
+            } else {
+                let child_depth = node.child_depth + 1;
+                // SAFETY: See the comment on the stack above
+                stack
+                    .push(unsafe { PendingNode::new(leaves, child_start, child_end, child_depth) });
+            }
+        } else {
+            // PANIC SAFETY: The node was just looked at on the top of the stack
+            let node = stack.pop().expect("stack should not be empty");
+            // SAFETY: See the comment on the stack above
+            let key_bytes = unsafe { leaf_key_bytes(leaves[node.start]) };
+            let prefix = &key_bytes[node.depth..node.child_depth];
+
+            match stack.last_mut() {
+                Some(parent) => {
+                    let key_fragment = key_bytes[parent.child_depth];
+                    parent
+                        .children
+                        .push((key_fragment, node.allocate(prefix, alloc)));
+                },
+                None => return node.allocate(prefix, alloc),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
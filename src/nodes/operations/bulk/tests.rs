@@ -0,0 +1,107 @@
+use super::*;
+use crate::{
+    deallocate_tree,
+    tests_common::{
+        convert_tree_to_dot_string, generate_key_fixed_length, generate_key_with_prefix,
+        generate_keys_skewed, setup_tree_from_entries, PrefixExpansion,
+    },
+    visitor::{DotPrinterSettings, WellFormedChecker},
+};
+
+/// Check that building the tree from the given keys creates exactly the same
+/// nodes as inserting the keys one at a time.
+fn assert_same_tree_as_inserts(keys: Vec<Box<[u8]>>) {
+    let entries: Vec<_> = keys
+        .into_iter()
+        .enumerate()
+        .map(|(idx, key)| (key, idx))
+        .collect();
+    let settings = || DotPrinterSettings {
+        display_node_address: false,
+    };
+
+    let BuildResult {
+        root: built_root,
+        num_entries,
+    } = build_tree_from_entries(entries.clone()).unwrap().unwrap();
+    let inserted_root = setup_tree_from_entries(entries.iter().cloned());
+
+    assert_eq!(num_entries, entries.len());
+    assert_eq!(
+        unsafe { WellFormedChecker::check_tree(built_root) }.unwrap(),
+        unsafe { WellFormedChecker::check_tree(inserted_root) }.unwrap()
+    );
+    assert_eq!(
+        convert_tree_to_dot_string(built_root, settings()).unwrap(),
+        convert_tree_to_dot_string(inserted_root, settings()).unwrap()
+    );
+
+    unsafe {
+        deallocate_tree(built_root);
+        deallocate_tree(inserted_root);
+    }
+}
+
+#[test]
+fn build_matches_inserts() {
+    assert_same_tree_as_inserts(vec![Box::new([1, 2, 3])]);
+    assert_same_tree_as_inserts(generate_keys_skewed(64).collect());
+    // Every node type, with 4, 5, 17 and 49 children at each level
+    assert_same_tree_as_inserts(generate_key_fixed_length([3, 4, 16, 48]).collect());
+    assert_same_tree_as_inserts(
+        generate_key_with_prefix(
+            [7, 3, 20],
+            [
+                PrefixExpansion {
+                    base_index: 0,
+                    expanded_length: 12,
+                },
+                PrefixExpansion {
+                    base_index: 2,
+                    expanded_length: 3,
+                },
+            ],
+        )
+        .collect(),
+    );
+}
+
+#[test]
+fn build_sorts_and_keeps_last_duplicate() {
+    let entries: Vec<(Box<[u8]>, char)> = vec![
+        (Box::new([3, 1]), 'a'),
+        (Box::new([1, 2]), 'b'),
+        (Box::new([3, 1]), 'c'),
+        (Box::new([2]), 'd'),
+        (Box::new([1, 2]), 'e'),
+    ];
+
+    let BuildResult { root, num_entries } = build_tree_from_entries(entries).unwrap().unwrap();
+    assert_eq!(num_entries, 3);
+
+    let leaves: Vec<_> = unsafe { crate::TreeIterator::new(root) }
+        .map(|leaf| unsafe { leaf.as_key_value_ref() })
+        .map(|(key, value)| (key.to_vec(), *value))
+        .collect();
+    assert_eq!(
+        leaves,
+        [(vec![1, 2], 'e'), (vec![2], 'd'), (vec![3, 1], 'c')]
+    );
+
+    unsafe { deallocate_tree(root) };
+}
+
+#[test]
+fn build_rejects_prefix_keys() {
+    assert!(build_tree_from_entries(Vec::<(Box<[u8]>, ())>::new())
+        .unwrap()
+        .is_none());
+
+    let entries: Vec<(Box<[u8]>, ())> = vec![
+        (Box::new([1, 2, 3]), ()),
+        (Box::new([4]), ()),
+        (Box::new([1, 2]), ()),
+    ];
+    let err = build_tree_from_entries(entries).unwrap_err();
+    assert_eq!(err.byte_repr.as_ref(), &[1, 2, 3]);
+}
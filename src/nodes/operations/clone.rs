@@ -0,0 +1,188 @@
+use crate::{
+    allocator::{Allocator, Global},
+    deallocate_tree_in,
+    visitor::inner_node_children,
+    ConcreteNodePtr, InnerNode, InnerNode16, InnerNode256, InnerNode4, InnerNode48, LeafNode,
+    NodePtr, OpaqueNodePtr,
+};
+
+/// A node of the source tree waiting to be copied, with the copy of its parent
+/// and its key fragment in the parent, or `None` for the root.
+type PendingCopy<K, V> = (OpaqueNodePtr<K, V>, Option<(OpaqueNodePtr<K, V>, u8)>);
+
+/// Copy every node of the given tree, and return the root of the copy.
+///
+/// The copy has exactly the same shape as the original tree: every inner node
+/// is copied with the same node type and compressed path, and every leaf is
+/// copied by cloning its key and value.
+///
+/// The nodes waiting to be copied are kept on a heap allocated stack, so the
+/// depth of the tree does not affect the stack usage of this function.
+///
+/// If cloning a key or value panics, the nodes copied so far are deallocated
+/// and the original tree is left unchanged.
+///
+/// # Safety
+///
+///  - For the duration of this function, the given node and all its children
+///    nodes must not get mutated.
+pub unsafe fn clone_tree<K, V>(root: OpaqueNodePtr<K, V>) -> OpaqueNodePtr<K, V>
+where
+    K: Clone,
+    V: Clone,
+{
+    // SAFETY: Covered by the safety requirements of this function
+    unsafe { clone_tree_in(root, &Global) }
+}
+
+/// Copy every node of the given tree with the given allocator, and return the
+/// root of the copy.
+///
+/// See [`clone_tree`] for details.
+///
+/// # Safety
+///
+///  - For the duration of this function, the given node and all its children
+///    nodes must not get mutated.
+pub unsafe fn clone_tree_in<K, V, A>(root: OpaqueNodePtr<K, V>, alloc: &A) -> OpaqueNodePtr<K, V>
+where
+    K: Clone,
+    V: Clone,
+    A: Allocator,
+{
+    let mut copy = PartialCopy { root: None, alloc };
+    let mut stack: Vec<PendingCopy<K, V>> = vec![(root, None)];
+
+    while let Some((node, parent)) = stack.pop() {
+        // SAFETY: The safety requirements of this function forbid any mutation of the
+        // source node while it is copied.
+        let node_copy = unsafe { copy_node(node, alloc) };
+
+        match parent {
+            // SAFETY: The parent copy was allocated by this function and is only
+            // referenced from its own parent copy, which is not accessed here. It was
+            // copied from a node with this child, so it has room for it.
+            Some((parent_copy, key_fragment)) => unsafe {
+                write_child(parent_copy, key_fragment, node_copy)
+            },
+            None => copy.root = Some(node_copy),
+        }
+
+        // SAFETY: The iterator is consumed in this statement, and the safety
+        // requirements of this function forbid mutation of the source node. The
+        // children are pushed in reverse order, so that they are written into
+        // the copy in key order.
+        if let Some(children) = unsafe { inner_node_children(node) } {
+            stack.extend(
+                children
+                    .rev()
+                    .map(|(key_fragment, child)| (child, Some((node_copy, key_fragment)))),
+            );
+        }
+    }
+
+    // PANIC SAFETY: The root is always copied on the first iteration of the loop
+    copy.root.take().expect("root should have been copied")
+}
+
+/// The copy of a tree which is still being built, which is deallocated if it is
+/// dropped before the copy is complete.
+struct PartialCopy<'a, K, V, A: Allocator> {
+    root: Option<OpaqueNodePtr<K, V>>,
+    alloc: &'a A,
+}
+
+impl<K, V, A: Allocator> Drop for PartialCopy<'_, K, V, A> {
+    fn drop(&mut self) {
+        if let Some(root) = self.root.take() {
+            // SAFETY: The copied nodes are only referenced from this partial copy, and
+            // every inner node copy only holds the children that were already copied,
+            // so each node is deallocated exactly once. The nodes were allocated by this
+            // allocator.
+            unsafe { deallocate_tree_in(root, self.alloc) }
+        }
+    }
+}
+
+/// Allocate a copy of the given node, without any children if it is an inner
+/// node.
+///
+/// # Safety
+///
+///  - For the duration of this function, the given node must not get mutated.
+unsafe fn copy_node<K, V, A>(node: OpaqueNodePtr<K, V>, alloc: &A) -> OpaqueNodePtr<K, V>
+where
+    K: Clone,
+    V: Clone,
+    A: Allocator,
+{
+    fn copy_inner_node<N: InnerNode, A: Allocator>(
+        source: &N,
+        mut node: N,
+        alloc: &A,
+    ) -> OpaqueNodePtr<N::Key, N::Value> {
+        *node.header_mut() = source.header().clone();
+        node.header_mut().set_num_children(0);
+        NodePtr::allocate_node_ptr_in(node, alloc).to_opaque()
+    }
+
+    // SAFETY: The references only live for this function, and the safety
+    // requirements of this function forbid mutation of the node.
+    unsafe {
+        match node.to_node_ptr() {
+            ConcreteNodePtr::Node4(inner_ptr) => {
+                copy_inner_node(inner_ptr.as_ref(), InnerNode4::empty(), alloc)
+            },
+            ConcreteNodePtr::Node16(inner_ptr) => {
+                copy_inner_node(inner_ptr.as_ref(), InnerNode16::empty(), alloc)
+            },
+            ConcreteNodePtr::Node48(inner_ptr) => {
+                copy_inner_node(inner_ptr.as_ref(), InnerNode48::empty(), alloc)
+            },
+            ConcreteNodePtr::Node256(inner_ptr) => {
+                copy_inner_node(inner_ptr.as_ref(), InnerNode256::empty(), alloc)
+            },
+            ConcreteNodePtr::LeafNode(leaf_ptr) => {
+                let leaf = leaf_ptr.as_ref();
+                let leaf_copy = LeafNode::new(leaf.key_ref().clone(), leaf.value_ref().clone());
+                NodePtr::allocate_node_ptr_in(leaf_copy, alloc).to_opaque()
+            },
+        }
+    }
+}
+
+/// Write the child into the given inner node.
+///
+/// # Safety
+///
+///  - No other access or mutation to the `parent` node can happen while this
+///    function runs.
+///  - The `parent` node must not be full.
+unsafe fn write_child<K, V>(
+    parent: OpaqueNodePtr<K, V>,
+    key_fragment: u8,
+    child: OpaqueNodePtr<K, V>,
+) {
+    // SAFETY: The mutable references only live for this function, and the safety
+    // requirements of this function forbid any other access to the node.
+    unsafe {
+        match parent.to_node_ptr() {
+            ConcreteNodePtr::Node4(inner_ptr) => {
+                inner_ptr.as_mut().write_child(key_fragment, child)
+            },
+            ConcreteNodePtr::Node16(inner_ptr) => {
+                inner_ptr.as_mut().write_child(key_fragment, child)
+            },
+            ConcreteNodePtr::Node48(inner_ptr) => {
+                inner_ptr.as_mut().write_child(key_fragment, child)
+            },
+            ConcreteNodePtr::Node256(inner_ptr) => {
+                inner_ptr.as_mut().write_child(key_fragment, child)
+            },
+            ConcreteNodePtr::LeafNode(_) => unreachable!("only inner nodes are parents"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,128 @@
+use super::*;
+use crate::{
+    deallocate_tree, insert_unchecked,
+    tests_common::{
+        convert_tree_to_dot_string, generate_key_fixed_length, generate_key_with_prefix,
+        generate_keys_skewed, setup_tree_from_entries, PrefixExpansion,
+    },
+    visitor::{DotPrinterSettings, WellFormedChecker},
+    TreeIterator,
+};
+use std::{
+    cell::Cell,
+    panic::{self, AssertUnwindSafe},
+    rc::Rc,
+};
+
+/// Check that the copy of the tree built from the given keys has the same
+/// shape and entries as the original, and does not share any node with it.
+fn assert_clone_matches_original(keys: Vec<Box<[u8]>>) {
+    let root = setup_tree_from_entries(keys.into_iter().zip(0..));
+    let settings = || DotPrinterSettings {
+        display_node_address: false,
+    };
+
+    let copy = unsafe { clone_tree(root) };
+
+    assert_eq!(
+        unsafe { WellFormedChecker::check_tree(copy) }.unwrap(),
+        unsafe { WellFormedChecker::check_tree(root) }.unwrap()
+    );
+    assert_eq!(
+        convert_tree_to_dot_string(copy, settings()).unwrap(),
+        convert_tree_to_dot_string(root, settings()).unwrap()
+    );
+    let original_leaves: Vec<_> = unsafe { TreeIterator::new(root) }.collect();
+    let copied_leaves: Vec<_> = unsafe { TreeIterator::new(copy) }.collect();
+    assert_eq!(original_leaves.len(), copied_leaves.len());
+    for (original, copied) in original_leaves.into_iter().zip(copied_leaves) {
+        assert_ne!(original, copied);
+        assert_eq!(unsafe { original.as_key_value_ref() }, unsafe {
+            copied.as_key_value_ref()
+        });
+    }
+
+    // The trees are independent, so they can be deallocated separately
+    unsafe {
+        deallocate_tree(root);
+        deallocate_tree(copy);
+    }
+}
+
+#[test]
+fn clone_matches_original() {
+    assert_clone_matches_original(vec![Box::new([1, 2, 3])]);
+    assert_clone_matches_original(generate_keys_skewed(64).collect());
+    // Every node type, with 4, 5, 17 and 49 children at each level
+    assert_clone_matches_original(generate_key_fixed_length([3, 4, 16, 48]).collect());
+    assert_clone_matches_original(
+        generate_key_with_prefix(
+            [7, 3, 20],
+            [PrefixExpansion {
+                base_index: 1,
+                expanded_length: 30,
+            }],
+        )
+        .collect(),
+    );
+}
+
+/// A value which counts the live copies of itself, and panics when cloned
+/// after a given number of clones.
+struct CountedValue {
+    live: Rc<Cell<usize>>,
+    clones_left: Rc<Cell<usize>>,
+}
+
+impl Clone for CountedValue {
+    fn clone(&self) -> Self {
+        if self.clones_left.get() == 0 {
+            panic!("out of clones");
+        }
+        self.clones_left.set(self.clones_left.get() - 1);
+        self.live.set(self.live.get() + 1);
+
+        CountedValue {
+            live: Rc::clone(&self.live),
+            clones_left: Rc::clone(&self.clones_left),
+        }
+    }
+}
+
+impl Drop for CountedValue {
+    fn drop(&mut self) {
+        self.live.set(self.live.get() - 1);
+    }
+}
+
+#[test]
+fn panic_while_cloning_deallocates_partial_copy() {
+    let live = Rc::new(Cell::new(0));
+    let clones_left = Rc::new(Cell::new(0));
+
+    let mut keys = generate_key_fixed_length([4, 4]);
+    let new_value = || {
+        live.set(live.get() + 1);
+        CountedValue {
+            live: Rc::clone(&live),
+            clones_left: Rc::clone(&clones_left),
+        }
+    };
+    let mut root =
+        NodePtr::allocate_node_ptr(LeafNode::new(keys.next().unwrap(), new_value())).to_opaque();
+    for key in keys {
+        root = unsafe { insert_unchecked(root, key, new_value()) }
+            .unwrap()
+            .new_root;
+    }
+    assert_eq!(live.get(), 25);
+
+    clones_left.set(10);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| unsafe { clone_tree(root) }));
+    assert!(result.is_err());
+    assert_eq!(live.get(), 25);
+
+    unsafe { WellFormedChecker::check_tree(root) }.unwrap();
+    unsafe { deallocate_tree(root) };
+    assert_eq!(live.get(), 0);
+}
@@ -1,8 +1,10 @@
 use std::borrow::Borrow;
 
 use crate::{
-    nodes::operations::lookup, AsBytes, ConcreteNodePtr, InnerNode, LeafNode, NodePtr,
-    OpaqueNodePtr,
+    allocator::{Allocator, Global},
+    minimum_unchecked,
+    nodes::operations::lookup,
+    AsBytes, ConcreteNodePtr, InnerNode, LeafNode, NodePtr, OpaqueNodePtr, ResizePolicy,
 };
 
 /// Removes a key from the tree, returning the [`LeafNode`] corresponding to the
@@ -22,13 +24,276 @@ pub unsafe fn delete_unchecked<Q, K, V>(
 where
     K: Borrow<Q> + AsBytes,
     Q: AsBytes + ?Sized,
+{
+    // SAFETY: Requirements covered by containing function
+    unsafe { delete_with_policy_unchecked(root, key, ResizePolicy::DEFAULT) }
+}
+
+/// Removes a key from the tree, returning the [`LeafNode`] corresponding to the
+/// key if the key was previously in the tree, and shrinking nodes according
+/// to the given [`ResizePolicy`].
+///
+/// # Safety
+///
+///  - The `root` [`OpaqueNodePtr`] must be a unique pointer to the underlying
+///    tree
+///  - This function cannot be called concurrently to any reads or writes of the
+///    `root` node or any child node of `root`. This function will arbitrarily
+///    read or write to any child in the given tree.
+pub unsafe fn delete_with_policy_unchecked<Q, K, V>(
+    root: OpaqueNodePtr<K, V>,
+    key: &Q,
+    policy: ResizePolicy,
+) -> Option<DeleteResult<K, V>>
+where
+    K: Borrow<Q> + AsBytes,
+    Q: AsBytes + ?Sized,
+{
+    // SAFETY: Requirements covered by containing function
+    unsafe { delete_with_policy_unchecked_in(root, key, policy, &Global) }
+}
+
+/// Removes a key from the tree, returning the [`LeafNode`] corresponding to the
+/// key if the key was previously in the tree, shrinking nodes according to
+/// the given [`ResizePolicy`], and returning the removed nodes to the given
+/// allocator.
+///
+/// # Safety
+///
+///  - The `root` [`OpaqueNodePtr`] must be a unique pointer to the underlying
+///    tree
+///  - This function cannot be called concurrently to any reads or writes of the
+///    `root` node or any child node of `root`. This function will arbitrarily
+///    read or write to any child in the given tree.
+///  - The nodes of the tree must have been allocated by the given allocator.
+pub unsafe fn delete_with_policy_unchecked_in<Q, K, V, A>(
+    root: OpaqueNodePtr<K, V>,
+    key: &Q,
+    policy: ResizePolicy,
+    alloc: &A,
+) -> Option<DeleteResult<K, V>>
+where
+    K: Borrow<Q> + AsBytes,
+    Q: AsBytes + ?Sized,
+    A: Allocator,
 {
     // SAFETY: Requirements covered by containing function
     unsafe {
         let delete_search_result = search_for_node_to_delete(root, key)?;
 
-        Some(inner_delete_unchecked(root, delete_search_result))
+        Some(inner_delete_unchecked(
+            root,
+            delete_search_result,
+            policy,
+            alloc,
+        ))
+    }
+}
+
+/// Removes the key with the given bytes from the tree, returning the
+/// [`LeafNode`] corresponding to the key if the key was previously in the tree,
+/// and shrinking nodes according to the given [`ResizePolicy`].
+///
+/// Unlike [`delete_with_policy_unchecked`], the search key does not need to be
+/// a borrowed form of the key type, only the bytes of the key are compared.
+///
+/// # Safety
+///
+///  - The `root` [`OpaqueNodePtr`] must be a unique pointer to the underlying
+///    tree
+///  - This function cannot be called concurrently to any reads or writes of the
+///    `root` node or any child node of `root`. This function will arbitrarily
+///    read or write to any child in the given tree.
+///  - The nodes of the tree must have been allocated by the given allocator.
+pub(crate) unsafe fn delete_bytes_with_policy_unchecked<K, V, A: Allocator>(
+    root: OpaqueNodePtr<K, V>,
+    key_bytes: &[u8],
+    policy: ResizePolicy,
+    alloc: &A,
+) -> Option<DeleteResult<K, V>>
+where
+    K: AsBytes,
+{
+    // SAFETY: Requirements covered by containing function
+    unsafe {
+        let delete_search_result = search_for_node_to_delete(root, key_bytes)?;
+
+        Some(inner_delete_unchecked(
+            root,
+            delete_search_result,
+            policy,
+            alloc,
+        ))
+    }
+}
+
+/// Removes the given leaf from the tree, where the path to it is already
+/// known, shrinking nodes according to the given [`ResizePolicy`].
+///
+/// The `parent_node_ptr` is the parent of the leaf and the key byte of the leaf
+/// in it, or `None` if the leaf is the root. The `grandparent_node_ptr` is the
+/// parent of that node and the key byte of the parent in it, or `None` if the
+/// parent is the root.
+///
+/// # Safety
+///
+///  - The `root` [`OpaqueNodePtr`] must be a unique pointer to the underlying
+///    tree, and the leaf, parent and grandparent must be on a path from the
+///    `root` as described above.
+///  - This function cannot be called concurrently to any reads or writes of the
+///    `root` node or any child node of `root`. This function will arbitrarily
+///    read or write to any child in the given tree.
+///  - The nodes of the tree must have been allocated by the given allocator.
+pub(crate) unsafe fn delete_found_leaf_with_policy_unchecked<K, V, A: Allocator>(
+    root: OpaqueNodePtr<K, V>,
+    leaf_node_ptr: NodePtr<LeafNode<K, V>>,
+    parent_node_ptr: Option<(u8, OpaqueNodePtr<K, V>)>,
+    grandparent_node_ptr: Option<(u8, OpaqueNodePtr<K, V>)>,
+    policy: ResizePolicy,
+    alloc: &A,
+) -> DeleteResult<K, V> {
+    let delete_search_result = DeleteSearchResult {
+        grandparent_node_ptr,
+        parent_node_ptr,
+        leaf_node_ptr,
+    };
+
+    // SAFETY: Requirements covered by containing function
+    unsafe { inner_delete_unchecked(root, delete_search_result, policy, alloc) }
+}
+
+/// The new root of a tree after a leaf was unlinked from it, which is `None` if
+/// the tree is now empty, and the unlinked leaf.
+pub(crate) type UnlinkResult<K, V> = (Option<OpaqueNodePtr<K, V>>, NodePtr<LeafNode<K, V>>);
+
+/// The new root of a tree after a subtree was unlinked from it, which is `None`
+/// if the tree is now empty, and the root of the unlinked subtree.
+pub(crate) type UnlinkPrefixResult<K, V> = (Option<OpaqueNodePtr<K, V>>, OpaqueNodePtr<K, V>);
+
+/// Removes the leaf holding the key with the given bytes from the tree, without
+/// deallocating it, and shrinking nodes according to the given
+/// [`ResizePolicy`].
+///
+/// Returns the new root of the tree, which is `None` if the tree is now empty,
+/// and a pointer to the removed leaf. The leaf is no longer reachable from the
+/// tree, so the caller is responsible for deallocating it or linking it back
+/// into a tree.
+///
+/// # Safety
+///
+///  - The `root` [`OpaqueNodePtr`] must be a unique pointer to the underlying
+///    tree
+///  - This function cannot be called concurrently to any reads or writes of the
+///    `root` node or any child node of `root`. This function will arbitrarily
+///    read or write to any child in the given tree.
+///  - The nodes of the tree must have been allocated by the given allocator.
+pub(crate) unsafe fn unlink_bytes_with_policy_unchecked<K, V, A: Allocator>(
+    root: OpaqueNodePtr<K, V>,
+    key_bytes: &[u8],
+    policy: ResizePolicy,
+    alloc: &A,
+) -> Option<UnlinkResult<K, V>>
+where
+    K: AsBytes,
+{
+    // SAFETY: Requirements covered by containing function
+    unsafe {
+        let delete_search_result = search_for_node_to_delete(root, key_bytes)?;
+
+        Some(inner_unlink_unchecked(
+            root,
+            delete_search_result,
+            policy,
+            alloc,
+        ))
+    }
+}
+
+/// Removes the subtree holding every key that starts with `prefix` from the
+/// tree, without deallocating it, and shrinking nodes according to the given
+/// [`ResizePolicy`].
+///
+/// Returns the new root of the tree, which is `None` if the tree is now empty,
+/// and the root of the removed subtree. The compressed path of the subtree root
+/// is trimmed, so that it only holds the key bytes which follow `prefix`. The
+/// subtree is no longer reachable from the tree, so the caller is responsible
+/// for deallocating it or linking it back into a tree.
+///
+/// Returns `None` if no key in the tree starts with `prefix`.
+///
+/// # Safety
+///
+///  - The `root` [`OpaqueNodePtr`] must be a unique pointer to the underlying
+///    tree
+///  - This function cannot be called concurrently to any reads or writes of the
+///    `root` node or any child node of `root`. This function will arbitrarily
+///    read or write to any child in the given tree.
+///  - The nodes of the tree must have been allocated by the given allocator.
+pub(crate) unsafe fn unlink_prefix_with_policy_unchecked<K, V, A: Allocator>(
+    root: OpaqueNodePtr<K, V>,
+    prefix: &[u8],
+    policy: ResizePolicy,
+    alloc: &A,
+) -> Option<UnlinkPrefixResult<K, V>>
+where
+    K: AsBytes,
+{
+    // SAFETY: Requirements covered by containing function
+    let (subtree, implicit_bytes) = unsafe { lookup::search_prefix_unchecked(root, prefix)? };
+
+    let new_root = if subtree == root {
+        None
+    } else {
+        // The subtree is on the search path of every key it holds, so the path to any
+        // of them gives the parent and grandparent of the subtree root.
+        //
+        // SAFETY: Requirements covered by containing function. The key reference does
+        // not outlive this block, and the tree is only modified after its last use.
+        let (parent_node_ptr, grandparent_node_ptr) = unsafe {
+            let key = minimum_unchecked(subtree).as_key_ref();
+            let mut path = Vec::new();
+            lookup::search_from_unchecked(root, 0, key, &mut path);
+
+            // PANIC SAFETY: The subtree root is on the path of the key, and it is not the
+            // root of the tree, so it has a parent.
+            let subtree_idx = path
+                .iter()
+                .position(|(_, node)| *node == subtree)
+                .expect("subtree root should be on the path of its keys");
+            let child_key_byte = |child_idx: usize| {
+                let (child_depth, _) = path[child_idx];
+                key.as_bytes()[child_depth - 1]
+            };
+
+            let parent_node_ptr = (child_key_byte(subtree_idx), path[subtree_idx - 1].1);
+            let grandparent_node_ptr = subtree_idx
+                .checked_sub(2)
+                .map(|grandparent_idx| (child_key_byte(subtree_idx - 1), path[grandparent_idx].1));
+            (parent_node_ptr, grandparent_node_ptr)
+        };
+
+        // SAFETY: `root` is a unique pointer to the tree and there will be no
+        // concurrent reads or writes to any portion of the tree, so all these nodes
+        // will be unique pointers and not read/written.
+        Some(unsafe {
+            inner_unlink_non_root_unchecked(
+                parent_node_ptr,
+                grandparent_node_ptr,
+                root,
+                policy,
+                alloc,
+            )
+        })
+    };
+
+    // SAFETY: The subtree is no longer reachable from the tree, so this is the only
+    // access to its root node. The subtree root is reached by the bytes of `prefix`
+    // before the implicit bytes of its compressed path.
+    if !subtree.is::<LeafNode<K, V>>() {
+        unsafe { subtree.ltrim_full_prefix(implicit_bytes, prefix.len() - implicit_bytes) };
     }
+
+    Some((new_root, subtree))
 }
 
 /// Find and delete the minimum leaf in the tree, returning the minimum
@@ -47,11 +312,50 @@ where
 ///    `root` node or any child node of `root`. This function will arbitrarily
 ///    read or write to any child in the given tree.
 pub unsafe fn delete_minimum_unchecked<K, V>(root: OpaqueNodePtr<K, V>) -> DeleteResult<K, V> {
+    // SAFETY: Requirements covered by containing function
+    unsafe { delete_minimum_with_policy_unchecked(root, ResizePolicy::DEFAULT) }
+}
+
+/// Find and delete the minimum leaf in the tree, returning the minimum
+/// [`LeafNode`], and shrinking nodes according to the given [`ResizePolicy`].
+///
+/// # Safety
+///
+///  - The `root` [`OpaqueNodePtr`] must be a unique pointer to the underlying
+///    tree
+///  - This function cannot be called concurrently to any reads or writes of the
+///    `root` node or any child node of `root`. This function will arbitrarily
+///    read or write to any child in the given tree.
+pub unsafe fn delete_minimum_with_policy_unchecked<K, V>(
+    root: OpaqueNodePtr<K, V>,
+    policy: ResizePolicy,
+) -> DeleteResult<K, V> {
+    // SAFETY: Requirements covered by containing function
+    unsafe { delete_minimum_with_policy_unchecked_in(root, policy, &Global) }
+}
+
+/// Find and delete the minimum leaf in the tree, returning the minimum
+/// [`LeafNode`], shrinking nodes according to the given [`ResizePolicy`], and
+/// returning the removed nodes to the given allocator.
+///
+/// # Safety
+///
+///  - The `root` [`OpaqueNodePtr`] must be a unique pointer to the underlying
+///    tree
+///  - This function cannot be called concurrently to any reads or writes of the
+///    `root` node or any child node of `root`. This function will arbitrarily
+///    read or write to any child in the given tree.
+///  - The nodes of the tree must have been allocated by the given allocator.
+pub unsafe fn delete_minimum_with_policy_unchecked_in<K, V, A: Allocator>(
+    root: OpaqueNodePtr<K, V>,
+    policy: ResizePolicy,
+    alloc: &A,
+) -> DeleteResult<K, V> {
     // SAFETY: Requirements covered by containing function
     unsafe {
         let delete_search_result = find_minimum_to_delete(root);
 
-        inner_delete_unchecked(root, delete_search_result)
+        inner_delete_unchecked(root, delete_search_result, policy, alloc)
     }
 }
 
@@ -71,11 +375,50 @@ pub unsafe fn delete_minimum_unchecked<K, V>(root: OpaqueNodePtr<K, V>) -> Delet
 ///    `root` node or any child node of `root`. This function will arbitrarily
 ///    read or write to any child in the given tree.
 pub unsafe fn delete_maximum_unchecked<K, V>(root: OpaqueNodePtr<K, V>) -> DeleteResult<K, V> {
+    // SAFETY: Requirements covered by containing function
+    unsafe { delete_maximum_with_policy_unchecked(root, ResizePolicy::DEFAULT) }
+}
+
+/// Find and delete the maximum leaf in the tree, returning the maximum
+/// [`LeafNode`], and shrinking nodes according to the given [`ResizePolicy`].
+///
+/// # Safety
+///
+///  - The `root` [`OpaqueNodePtr`] must be a unique pointer to the underlying
+///    tree
+///  - This function cannot be called concurrently to any reads or writes of the
+///    `root` node or any child node of `root`. This function will arbitrarily
+///    read or write to any child in the given tree.
+pub unsafe fn delete_maximum_with_policy_unchecked<K, V>(
+    root: OpaqueNodePtr<K, V>,
+    policy: ResizePolicy,
+) -> DeleteResult<K, V> {
+    // SAFETY: Requirements covered by containing function
+    unsafe { delete_maximum_with_policy_unchecked_in(root, policy, &Global) }
+}
+
+/// Find and delete the maximum leaf in the tree, returning the maximum
+/// [`LeafNode`], shrinking nodes according to the given [`ResizePolicy`], and
+/// returning the removed nodes to the given allocator.
+///
+/// # Safety
+///
+///  - The `root` [`OpaqueNodePtr`] must be a unique pointer to the underlying
+///    tree
+///  - This function cannot be called concurrently to any reads or writes of the
+///    `root` node or any child node of `root`. This function will arbitrarily
+///    read or write to any child in the given tree.
+///  - The nodes of the tree must have been allocated by the given allocator.
+pub unsafe fn delete_maximum_with_policy_unchecked_in<K, V, A: Allocator>(
+    root: OpaqueNodePtr<K, V>,
+    policy: ResizePolicy,
+    alloc: &A,
+) -> DeleteResult<K, V> {
     // SAFETY: Requirements covered by containing function
     unsafe {
         let delete_search_result = find_maximum_to_delete(root);
 
-        inner_delete_unchecked(root, delete_search_result)
+        inner_delete_unchecked(root, delete_search_result, policy, alloc)
     }
 }
 
@@ -89,28 +432,52 @@ pub unsafe fn delete_maximum_unchecked<K, V>(root: OpaqueNodePtr<K, V>) -> Delet
 ///  - This function cannot be called concurrently to any reads or writes of the
 ///    `root` node or any child node of `root`. This function will arbitrarily
 ///    read or write to any child in the given tree.
-unsafe fn inner_delete_unchecked<K, V>(
+///  - The nodes of the tree must have been allocated by the given allocator.
+unsafe fn inner_delete_unchecked<K, V, A: Allocator>(
+    root: OpaqueNodePtr<K, V>,
+    delete_search_result: DeleteSearchResult<K, V>,
+    policy: ResizePolicy,
+    alloc: &A,
+) -> DeleteResult<K, V> {
+    // SAFETY: Requirements covered by containing function
+    let (new_root, leaf_node_ptr) =
+        unsafe { inner_unlink_unchecked(root, delete_search_result, policy, alloc) };
+
+    // SAFETY: The leaf is no longer reachable from the tree, and the original
+    // `root` node pointer is a unique pointer to the tree (required by safety
+    // doc), so no other code will deallocate it.
+    let deleted_leaf = unsafe { NodePtr::deallocate_node_ptr_in(leaf_node_ptr, alloc) };
+
+    DeleteResult {
+        new_root,
+        deleted_leaf,
+    }
+}
+
+/// Remove the found leaf from the tree, returning the new tree root and the
+/// leaf, which is no longer reachable from the tree but is not deallocated.
+///
+/// # Safety
+///
+///  - The `root` [`OpaqueNodePtr`] must be a unique pointer to the underlying
+///    tree
+///  - This function cannot be called concurrently to any reads or writes of the
+///    `root` node or any child node of `root`. This function will arbitrarily
+///    read or write to any child in the given tree.
+///  - The nodes of the tree must have been allocated by the given allocator.
+unsafe fn inner_unlink_unchecked<K, V, A: Allocator>(
     root: OpaqueNodePtr<K, V>,
     DeleteSearchResult {
         grandparent_node_ptr,
         parent_node_ptr,
         leaf_node_ptr,
     }: DeleteSearchResult<K, V>,
-) -> DeleteResult<K, V> {
+    policy: ResizePolicy,
+    alloc: &A,
+) -> UnlinkResult<K, V> {
     match (parent_node_ptr, grandparent_node_ptr) {
-        (None, None) => {
-            // The leaf node was also the root node
-
-            // SAFETY: The original `root` node pointer is a unique pointer to the tree
-            // (required by safety doc), which means that leaf_node_ptr is also unique and
-            // can be deallocated.
-            let leaf_node = unsafe { NodePtr::deallocate_node_ptr(leaf_node_ptr) };
-
-            DeleteResult {
-                new_root: None,
-                deleted_leaf: leaf_node,
-            }
-        },
+        // The leaf node was also the root node
+        (None, None) => (None, leaf_node_ptr),
         (None, Some(granparent_node_ptr)) => {
             // search_for_node_to_delete should maintain this invariant
             panic!(
@@ -122,22 +489,25 @@ unsafe fn inner_delete_unchecked<K, V>(
             // SAFETY: `root` is a unique pointer to the tree and there will be no
             // concurrent reads or writes to any portion of the tree, so all these child
             // nodes will be unique pointers and not read/written.
-            inner_delete_non_root_unchecked(
-                leaf_node_ptr,
+            let new_root = inner_unlink_non_root_unchecked(
                 parent_node_ptr,
                 grandparent_node_ptr,
                 root,
-            )
+                policy,
+                alloc,
+            );
+            (Some(new_root), leaf_node_ptr)
         },
     }
 }
 
 /// Remove a child node from the given inner node, return the child node
-/// pointer if it was compressed.
+/// pointer if it was compressed or the new inner node if it was shrunk.
 ///
 /// The inner node will be compressed if there was only a single child
 /// remaining after the delete. Compressing the node involves prepending the
 /// inner node key prefix and child key byte to the child's key prefix.
+/// Otherwise, the inner node is shrunk if the [`ResizePolicy`] requires it.
 ///
 /// # Safety
 ///
@@ -145,9 +515,12 @@ unsafe fn inner_delete_unchecked<K, V>(
 ///    any other mutable references.
 ///  - There must not be any mutable references to the children of the given
 ///    inner node either.
-unsafe fn remove_child_from_inner_node_and_compress<N: InnerNode>(
+///  - The inner node must have been allocated by the given allocator.
+unsafe fn remove_child_from_inner_node_and_compress<N: InnerNode, A: Allocator>(
     inner_node_ptr: NodePtr<N>,
     key_fragment: u8,
+    policy: ResizePolicy,
+    alloc: &A,
 ) -> Option<OpaqueNodePtr<N::Key, N::Value>> {
     // SAFETY: The `inner_node` reference is scoped to this function and dropped
     // before cases where the inner node is deallocated. It is a unique reference,
@@ -179,7 +552,7 @@ unsafe fn remove_child_from_inner_node_and_compress<N: InnerNode>(
             // This needs to go in reverse order, since prepend_prefix always writes to the
             // front
             child_header.prepend_prefix(&[child_key_byte]);
-            child_header.prepend_prefix(inner_node.header().read_prefix());
+            child_header.prepend_header_prefix(inner_node.header());
         }
         // the else case here is that the child does not have a header, and
         // is a leaf
@@ -189,21 +562,21 @@ unsafe fn remove_child_from_inner_node_and_compress<N: InnerNode>(
         unsafe {
             #[allow(clippy::drop_ref)]
             drop(inner_node);
-            drop(NodePtr::deallocate_node_ptr(inner_node_ptr));
+            drop(NodePtr::deallocate_node_ptr_in(inner_node_ptr, alloc));
         }
 
         Some(child_node_ptr)
-    } else if N::TYPE.should_shrink_inner_node(inner_node.header().num_children()) {
+    } else if policy.should_shrink(N::TYPE, inner_node.header().num_children()) {
         let new_inner_node = inner_node.shrink();
 
-        let new_inner_node_ptr = NodePtr::allocate_node_ptr(new_inner_node).to_opaque();
+        let new_inner_node_ptr = NodePtr::allocate_node_ptr_in(new_inner_node, alloc).to_opaque();
 
         // SAFETY: Since this function requires a unique pointer to the original
         // `inner_node_ptr`, we know that no other code will deallocate the pointer
         unsafe {
             #[allow(clippy::drop_ref)]
             drop(inner_node);
-            drop(NodePtr::deallocate_node_ptr(inner_node_ptr));
+            drop(NodePtr::deallocate_node_ptr_in(inner_node_ptr, alloc));
         }
 
         Some(new_inner_node_ptr)
@@ -212,7 +585,8 @@ unsafe fn remove_child_from_inner_node_and_compress<N: InnerNode>(
     }
 }
 
-/// Delete the given non-root leaf node.
+/// Remove the leaf child at `parent_key_byte` from the parent node, writing the
+/// changed parent to the grandparent, and return the new tree root.
 ///
 /// # Safety
 ///
@@ -222,30 +596,50 @@ unsafe fn remove_child_from_inner_node_and_compress<N: InnerNode>(
 ///    `parent_node_ptr` either.
 ///  - `grandparent_node_ptr` must be a unique pointer to the node and must not
 ///    have any other mutable references.
-///  - `leaf_node_ptr` must be a unique pointer to the node and not have any
-///    other mutable references.
-unsafe fn inner_delete_non_root_unchecked<K, V>(
-    leaf_node_ptr: NodePtr<LeafNode<K, V>>,
+///  - The parent node must have been allocated by the given allocator.
+unsafe fn inner_unlink_non_root_unchecked<K, V, A: Allocator>(
     (parent_key_byte, parent_node_ptr): (u8, OpaqueNodePtr<K, V>),
     grandparent_node_ptr: Option<(u8, OpaqueNodePtr<K, V>)>,
     original_root: OpaqueNodePtr<K, V>,
-) -> DeleteResult<K, V> {
+    policy: ResizePolicy,
+    alloc: &A,
+) -> OpaqueNodePtr<K, V> {
     let new_parent_node_ptr = match parent_node_ptr.to_node_ptr() {
         ConcreteNodePtr::Node4(parent_node_ptr) => unsafe {
             // SAFETY: Covered by containing function safety doc
-            remove_child_from_inner_node_and_compress(parent_node_ptr, parent_key_byte)
+            remove_child_from_inner_node_and_compress(
+                parent_node_ptr,
+                parent_key_byte,
+                policy,
+                alloc,
+            )
         },
         ConcreteNodePtr::Node16(parent_node_ptr) => unsafe {
             // SAFETY: Covered by containing function safety doc
-            remove_child_from_inner_node_and_compress(parent_node_ptr, parent_key_byte)
+            remove_child_from_inner_node_and_compress(
+                parent_node_ptr,
+                parent_key_byte,
+                policy,
+                alloc,
+            )
         },
         ConcreteNodePtr::Node48(parent_node_ptr) => unsafe {
             // SAFETY: Covered by containing function safety doc
-            remove_child_from_inner_node_and_compress(parent_node_ptr, parent_key_byte)
+            remove_child_from_inner_node_and_compress(
+                parent_node_ptr,
+                parent_key_byte,
+                policy,
+                alloc,
+            )
         },
         ConcreteNodePtr::Node256(parent_node_ptr) => unsafe {
             // SAFETY: Covered by containing function safety doc
-            remove_child_from_inner_node_and_compress(parent_node_ptr, parent_key_byte)
+            remove_child_from_inner_node_and_compress(
+                parent_node_ptr,
+                parent_key_byte,
+                policy,
+                alloc,
+            )
         },
         ConcreteNodePtr::LeafNode(_) => panic!("Cannot have delete from leaf node"),
     };
@@ -290,18 +684,9 @@ unsafe fn inner_delete_non_root_unchecked<K, V>(
         }
     }
 
-    // SAFETY: `leaf_node_ptr` is a unique pointer to the leaf node, no other code
-    // will deallocate this
-    let leaf_node = unsafe { NodePtr::deallocate_node_ptr(leaf_node_ptr) };
-
-    let new_root = match (new_parent_node_ptr, grandparent_node_ptr) {
+    match (new_parent_node_ptr, grandparent_node_ptr) {
         (Some(new_parent_node_ptr), None) => new_parent_node_ptr,
         _ => original_root,
-    };
-
-    DeleteResult {
-        new_root: Some(new_root),
-        deleted_leaf: leaf_node,
     }
 }
 
@@ -359,7 +744,7 @@ unsafe fn search_for_node_to_delete<Q, K, V>(
     key: &Q,
 ) -> Option<DeleteSearchResult<K, V>>
 where
-    K: Borrow<Q> + AsBytes,
+    K: AsBytes,
     Q: AsBytes + ?Sized,
 {
     let mut current_grandparent = None;
@@ -372,29 +757,29 @@ where
             ConcreteNodePtr::Node4(inner_ptr) => unsafe {
                 // SAFETY: The safety requirement is covered by the safety requirement on the
                 // containing function
-                lookup::check_prefix_lookup_child(inner_ptr, key, &mut current_depth)
+                lookup::check_prefix_lookup_child(inner_ptr, key, &mut current_depth, &mut ())
             },
             ConcreteNodePtr::Node16(inner_ptr) => unsafe {
                 // SAFETY: The safety requirement is covered by the safety requirement on the
                 // containing function
-                lookup::check_prefix_lookup_child(inner_ptr, key, &mut current_depth)
+                lookup::check_prefix_lookup_child(inner_ptr, key, &mut current_depth, &mut ())
             },
             ConcreteNodePtr::Node48(inner_ptr) => unsafe {
                 // SAFETY: The safety requirement is covered by the safety requirement on the
                 // containing function
-                lookup::check_prefix_lookup_child(inner_ptr, key, &mut current_depth)
+                lookup::check_prefix_lookup_child(inner_ptr, key, &mut current_depth, &mut ())
             },
             ConcreteNodePtr::Node256(inner_ptr) => unsafe {
                 // SAFETY: The safety requirement is covered by the safety requirement on the
                 // containing function
-                lookup::check_prefix_lookup_child(inner_ptr, key, &mut current_depth)
+                lookup::check_prefix_lookup_child(inner_ptr, key, &mut current_depth, &mut ())
             },
             ConcreteNodePtr::LeafNode(leaf_node_ptr) => {
                 let leaf_node = leaf_node_ptr.read();
 
                 // Specifically we are matching the leaf node stored key against the full search
                 // key to confirm that it is the right value.
-                if leaf_node.matches_full_key(key) {
+                if leaf_node.matches_key_bytes(key.as_bytes()) {
                     return Some(DeleteSearchResult {
                         grandparent_node_ptr: current_grandparent,
                         parent_node_ptr: current_parent,
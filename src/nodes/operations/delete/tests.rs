@@ -301,3 +301,73 @@ fn delete_maximum_entire_small_tree() {
     assert_eq!(d4.deleted_leaf.key_ref().as_ref(), &[1, 2, 3, 4, 5, 6]);
     assert!(d4.new_root.is_none());
 }
+
+#[test]
+fn delete_with_hysteresis_policy_delays_shrink() {
+    use crate::visitor::WellFormedChecker;
+
+    // A root with 17 children, which is an `InnerNode48`
+    let entries_it = (0..17u8).map(|byte| (Box::<[u8]>::from([byte]), byte));
+    let mut default_root = setup_tree_from_entries(entries_it.clone());
+    let mut hysteresis_root = setup_tree_from_entries(entries_it);
+    assert_eq!(default_root.node_type(), NodeType::Node48);
+
+    for byte in (11..17u8).rev() {
+        // SAFETY: The trees are uniquely owned by this test
+        let default_result = unsafe { delete_unchecked(default_root, [byte].as_ref()).unwrap() };
+        default_root = default_result.new_root.unwrap();
+        let hysteresis_result = unsafe {
+            delete_with_policy_unchecked(hysteresis_root, [byte].as_ref(), ResizePolicy::HYSTERESIS)
+                .unwrap()
+        };
+        hysteresis_root = hysteresis_result.new_root.unwrap();
+
+        // The default policy shrinks as soon as the children fit in an `InnerNode16`,
+        // the hysteresis policy waits until the children are well below that
+        let num_children = usize::from(byte);
+        assert_eq!(default_root.node_type(), NodeType::Node16);
+        let expected_type = if num_children <= 11 {
+            NodeType::Node16
+        } else {
+            NodeType::Node48
+        };
+        assert_eq!(hysteresis_root.node_type(), expected_type, "{num_children}");
+
+        // SAFETY: There are no concurrent mutations of the trees
+        unsafe {
+            WellFormedChecker::check_tree(default_root).unwrap();
+            WellFormedChecker::check_tree_with_policy(hysteresis_root, ResizePolicy::HYSTERESIS)
+                .unwrap();
+        }
+    }
+
+    // The hysteresis tree is not well-formed by the default policy while it holds
+    // an `InnerNode48` with few children.
+    let mut churn_root = setup_tree_from_entries((0..17u8).map(|byte| (Box::from([byte]), byte)));
+    churn_root = unsafe {
+        delete_maximum_with_policy_unchecked(churn_root, ResizePolicy::HYSTERESIS)
+            .new_root
+            .unwrap()
+    };
+    assert_eq!(churn_root.node_type(), NodeType::Node48);
+    assert!(unsafe { WellFormedChecker::check_tree(churn_root) }.is_err());
+    churn_root = unsafe {
+        delete_minimum_with_policy_unchecked(churn_root, ResizePolicy::HYSTERESIS)
+            .new_root
+            .unwrap()
+    };
+    assert_eq!(churn_root.node_type(), NodeType::Node48);
+
+    // SAFETY: The trees are not used after this point
+    unsafe {
+        deallocate_tree(default_root);
+        deallocate_tree(hysteresis_root);
+        deallocate_tree(churn_root);
+    }
+}
+
+#[test]
+#[should_panic = "InnerNode48 shrink threshold must fit in an InnerNode16"]
+fn resize_policy_rejects_threshold_above_smaller_capacity() {
+    let _ = ResizePolicy::new(4, 17, 48);
+}
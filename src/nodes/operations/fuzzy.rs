@@ -0,0 +1,175 @@
+use crate::{AsBytes, ConcreteNodePtr, InnerNode, LeafNode, NodePtr, OpaqueNodePtr};
+use std::iter::FusedIterator;
+
+/// A node waiting to be visited by [`FuzzyLeaves`], with the number of key
+/// bytes on the path to the node and the edit distance row for those bytes.
+type PendingNode<K, V> = (OpaqueNodePtr<K, V>, usize, Box<[usize]>);
+
+/// An iterator over the leaves of a tree whose key is within a maximum
+/// Levenshtein distance of a search key, in ascending key order.
+///
+/// The iterator walks the tree depth-first while maintaining one row of the
+/// edit distance table for every node waiting to be visited. The row is
+/// advanced by each byte of the compressed path of an inner node and by the
+/// key byte of each child, and a subtree is skipped as soon as every entry of
+/// its row exceeds the maximum distance, since no key in that subtree can get
+/// closer to the search key.
+///
+/// # Safety
+///
+/// This iterator maintains pointers to internal nodes from the trie. No
+/// mutating operation can occur while an instance of the iterator is live.
+pub struct FuzzyLeaves<K, V> {
+    /// The key that the leaf keys are compared against.
+    key: Box<[u8]>,
+    /// The largest edit distance of the returned leaf keys.
+    max_edit_distance: usize,
+    /// The nodes left to visit, where the top of the stack is the next node
+    /// in key order.
+    stack: Vec<PendingNode<K, V>>,
+}
+
+impl<K, V> FuzzyLeaves<K, V> {
+    /// Create a new iterator over the leaves descended from the given node
+    /// whose key is at most `max_edit_distance` single byte insertions,
+    /// deletions, or substitutions away from `key`.
+    ///
+    /// # Safety
+    ///
+    ///  - This iterator cannot be used concurrently with any mutating operation
+    ///    on `root` or any child node of `root`. The iterator will arbitrarily
+    ///    read to any child in the given tree.
+    pub unsafe fn new(root: OpaqueNodePtr<K, V>, key: &[u8], max_edit_distance: usize) -> Self {
+        // Matching the empty path against the first `idx` bytes of the search key
+        // takes `idx` deletions.
+        let first_row = (0..=key.len()).collect();
+
+        FuzzyLeaves {
+            key: key.into(),
+            max_edit_distance,
+            stack: vec![(root, 0, first_row)],
+        }
+    }
+
+    /// Return the edit distance row after appending `byte` to the path
+    /// described by `row`, or `None` if no extension of the new path can be
+    /// within the maximum edit distance of the search key.
+    fn advance_row(&self, row: &[usize], byte: u8) -> Option<Box<[usize]>> {
+        let mut next_row = Vec::with_capacity(row.len());
+        next_row.push(row[0] + 1);
+
+        for (idx, key_byte) in self.key.iter().enumerate() {
+            let substitution = row[idx] + usize::from(*key_byte != byte);
+            let insertion = row[idx + 1] + 1;
+            let deletion = next_row[idx] + 1;
+
+            next_row.push(substitution.min(insertion).min(deletion));
+        }
+
+        // The entries of the row can only grow as the path gets longer, so once the
+        // smallest entry is out of bounds every longer path is too.
+        let smallest_distance = *next_row.iter().min()?;
+        (smallest_distance <= self.max_edit_distance).then(|| next_row.into_boxed_slice())
+    }
+
+    /// Advance the row over the compressed path of the given inner node, then
+    /// push every child which may still hold a matching key onto the stack.
+    ///
+    /// # Safety
+    ///
+    ///  - No other access or mutation to the `inner_ptr` Node can happen while
+    ///    this function runs.
+    unsafe fn push_children<N>(&mut self, inner_ptr: NodePtr<N>, depth: usize, row: Box<[usize]>)
+    where
+        N: InnerNode<Key = K, Value = V>,
+        K: AsBytes,
+    {
+        // SAFETY: The lifetime produced from this is bounded to this scope and does
+        // not escape. Further, no other code mutates the node referenced, which is
+        // further enforced the "no concurrent reads or writes" requirement on this
+        // function.
+        let inner_node = unsafe { inner_ptr.as_ref() };
+        // SAFETY: The node is at the given depth, and the tree is not mutated while
+        // this function runs.
+        let compressed_path = unsafe { inner_node.read_full_prefix(depth) };
+
+        let mut row = row;
+        for byte in compressed_path {
+            row = match self.advance_row(&row, *byte) {
+                Some(next_row) => next_row,
+                None => return,
+            };
+        }
+
+        let child_depth = depth + compressed_path.len() + 1;
+        // SAFETY: The iterator does not outlive this function, and the safety
+        // requirements of this function forbid any concurrent mutation of the node.
+        // Children are pushed in reverse, so that the smallest child is visited first.
+        for (key_byte, child) in unsafe { inner_node.iter() }.rev() {
+            if let Some(child_row) = self.advance_row(&row, key_byte) {
+                self.stack.push((child, child_depth, child_row));
+            }
+        }
+    }
+}
+
+impl<K: AsBytes, V> Iterator for FuzzyLeaves<K, V> {
+    type Item = NodePtr<LeafNode<K, V>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        'nodes: while let Some((node, depth, row)) = self.stack.pop() {
+            let leaf_node_ptr = match node.to_node_ptr() {
+                ConcreteNodePtr::Node4(inner_ptr) => {
+                    // SAFETY: The safety requirement is covered by the safety requirement on
+                    // the iterator
+                    unsafe { self.push_children(inner_ptr, depth, row) };
+                    continue;
+                },
+                ConcreteNodePtr::Node16(inner_ptr) => {
+                    // SAFETY: The safety requirement is covered by the safety requirement on
+                    // the iterator
+                    unsafe { self.push_children(inner_ptr, depth, row) };
+                    continue;
+                },
+                ConcreteNodePtr::Node48(inner_ptr) => {
+                    // SAFETY: The safety requirement is covered by the safety requirement on
+                    // the iterator
+                    unsafe { self.push_children(inner_ptr, depth, row) };
+                    continue;
+                },
+                ConcreteNodePtr::Node256(inner_ptr) => {
+                    // SAFETY: The safety requirement is covered by the safety requirement on
+                    // the iterator
+                    unsafe { self.push_children(inner_ptr, depth, row) };
+                    continue;
+                },
+                ConcreteNodePtr::LeafNode(leaf_node_ptr) => leaf_node_ptr,
+            };
+
+            // SAFETY: The lifetime of the key reference is bounded to this block, and
+            // the safety requirements of the iterator forbid any concurrent mutation of
+            // the leaf.
+            let leaf_key = unsafe { leaf_node_ptr.as_key_ref() }.as_bytes();
+
+            // The path to the leaf only covers the first `depth` bytes of its key
+            let mut row = row;
+            for byte in &leaf_key[depth..] {
+                row = match self.advance_row(&row, *byte) {
+                    Some(next_row) => next_row,
+                    None => continue 'nodes,
+                };
+            }
+
+            if row[self.key.len()] <= self.max_edit_distance {
+                return Some(leaf_node_ptr);
+            }
+        }
+
+        None
+    }
+}
+
+impl<K: AsBytes, V> FusedIterator for FuzzyLeaves<K, V> {}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,118 @@
+use super::*;
+use crate::{
+    deallocate_tree,
+    tests_common::{generate_key_fixed_length, generate_keys_skewed, setup_tree_from_entries},
+};
+
+/// Compute the Levenshtein distance between two byte strings with the full
+/// edit distance table.
+fn edit_distance(a: &[u8], b: &[u8]) -> usize {
+    let mut table = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (idx, row) in table.iter_mut().enumerate() {
+        row[0] = idx;
+    }
+    for (idx, distance) in table[0].iter_mut().enumerate() {
+        *distance = idx;
+    }
+
+    for a_idx in 1..=a.len() {
+        for b_idx in 1..=b.len() {
+            let substitution =
+                table[a_idx - 1][b_idx - 1] + usize::from(a[a_idx - 1] != b[b_idx - 1]);
+            table[a_idx][b_idx] = substitution
+                .min(table[a_idx - 1][b_idx] + 1)
+                .min(table[a_idx][b_idx - 1] + 1);
+        }
+    }
+
+    table[a.len()][b.len()]
+}
+
+fn fuzzy_keys(root: OpaqueNodePtr<Box<[u8]>, usize>, key: &[u8], max: usize) -> Vec<Box<[u8]>> {
+    // SAFETY: There are no mutations of the tree while the iterator is live
+    unsafe { FuzzyLeaves::new(root, key, max) }
+        .map(|leaf_ptr| leaf_ptr.read().key_ref().clone())
+        .collect()
+}
+
+fn assert_matches_brute_force(keys: &[Box<[u8]>], search_keys: &[&[u8]], max: usize) {
+    let root = setup_tree_from_entries(keys.iter().cloned().zip(0..));
+
+    let mut sorted_keys = keys.to_vec();
+    sorted_keys.sort();
+
+    for search_key in search_keys {
+        let expected: Vec<_> = sorted_keys
+            .iter()
+            .filter(|key| edit_distance(key, search_key) <= max)
+            .cloned()
+            .collect();
+
+        assert_eq!(
+            fuzzy_keys(root, search_key, max),
+            expected,
+            "search key {search_key:?} with max distance {max}"
+        );
+    }
+
+    // SAFETY: The tree is not used after this point
+    unsafe { deallocate_tree(root) };
+}
+
+#[test]
+fn fuzzy_search_single_leaf() {
+    let root = setup_tree_from_entries([(Box::from(&b"kitten"[..]), 0)].into_iter());
+
+    assert!(fuzzy_keys(root, b"sitting", 2).is_empty());
+    assert_eq!(fuzzy_keys(root, b"sitting", 3), [Box::from(&b"kitten"[..])]);
+    assert_eq!(fuzzy_keys(root, b"kitten", 0), [Box::from(&b"kitten"[..])]);
+
+    // SAFETY: The tree is not used after this point
+    unsafe { deallocate_tree(root) };
+}
+
+#[test]
+fn fuzzy_search_words() {
+    let keys: Vec<Box<[u8]>> = [
+        "book\0", "books\0", "boot\0", "boo\0", "cake\0", "back\0", "brook\0", "look\0",
+    ]
+    .into_iter()
+    .map(|word| Box::from(word.as_bytes()))
+    .collect();
+    let search_keys: &[&[u8]] = &[b"book\0", b"bok\0", b"\0", b"cook\0", b"brooks\0"];
+
+    for max in 0..4 {
+        assert_matches_brute_force(&keys, search_keys, max);
+    }
+}
+
+#[test]
+fn fuzzy_search_long_compressed_paths() {
+    let mut keys: Vec<Box<[u8]>> = generate_key_fixed_length([3, 2, 4]).collect();
+    // Keys sharing a compressed path that is too long to store inline
+    for last in [7, 9, 200] {
+        let mut key = vec![255; 12];
+        key[0] = 128;
+        key.push(last);
+        keys.push(key.into_boxed_slice());
+    }
+
+    let mut near_miss = vec![255; 12];
+    near_miss[0] = 128;
+    near_miss[5] = 0;
+    let search_keys: &[&[u8]] = &[&[0, 1, 2], &[1, 1], &near_miss, &[128, 255, 255, 7], &[]];
+
+    for max in 0..3 {
+        assert_matches_brute_force(&keys, search_keys, max);
+    }
+}
+
+#[test]
+fn fuzzy_search_skewed_keys() {
+    let keys: Vec<_> = generate_keys_skewed(16).collect();
+    let search_keys: &[&[u8]] = &[&[u8::MAX; 4], &[u8::MAX, 0, u8::MAX], &[0; 16]];
+
+    for max in [0, 1, 3] {
+        assert_matches_brute_force(&keys, search_keys, max);
+    }
+}
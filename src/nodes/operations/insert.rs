@@ -1,4 +1,9 @@
-use crate::{AsBytes, ConcreteNodePtr, InnerNode, InnerNode4, LeafNode, NodePtr, OpaqueNodePtr};
+use crate::{
+    allocator::{Allocator, Global},
+    nodes::operations::lookup,
+    AsBytes, ConcreteNodePtr, InnerNode, InnerNode4, LeafNode, Node, NodePtr, OpaqueNodePtr,
+    SearchRecorder, SearchStats,
+};
 use std::{error::Error, fmt, ops::ControlFlow};
 
 /// Insert the given key-value pair into the tree.
@@ -14,6 +19,16 @@ use std::{error::Error, fmt, ops::ControlFlow};
 ///     another key that exists in the trie. Or if the given key is prefixed by
 ///     an existing key in the trie.
 ///
+/// # Panics
+///
+/// This function provides the strong exception-safety guarantee: if it
+/// unwinds, for example because the [`AsBytes`] implementation of `K` panics
+/// or a node allocation fails, the tree is left exactly as it was before the
+/// call. All calls to [`AsBytes::as_bytes`] happen before the tree is
+/// modified, and newly allocated nodes are deallocated again if the insert
+/// unwinds before they are linked into the tree. In that case the given key
+/// and value are dropped.
+///
 /// # Safety
 ///
 ///  - The `root` [`OpaqueNodePtr`] must be a unique pointer to the underlying
@@ -29,175 +44,863 @@ pub unsafe fn insert_unchecked<K, V>(
 where
     K: AsBytes,
 {
-    fn write_new_child_in_existing_node<K, V>(
-        inner_node_ptr: OpaqueNodePtr<K, V>,
-        new_leaf_node: LeafNode<K, V>,
-        key_bytes_used: usize,
-    ) -> OpaqueNodePtr<K, V>
+    // SAFETY: The safety requirements are covered by the containing function
+    unsafe { insert_unchecked_in(root, key, value, &Global) }
+}
+
+/// Insert the given key-value pair into the tree, allocating new nodes with
+/// the given allocator.
+///
+/// See [`insert_unchecked`] for details of the return value, panics and
+/// errors.
+///
+/// # Safety
+///
+///  - The `root` [`OpaqueNodePtr`] must be a unique pointer to the underlying
+///    tree
+///  - This function cannot be called concurrently to any reads or writes of the
+///    `root` node or any child node of `root`. This function will arbitrarily
+///    read or write to any child in the given tree.
+///  - The nodes of the tree must have been allocated by the given allocator.
+pub unsafe fn insert_unchecked_in<K, V, A>(
+    root: OpaqueNodePtr<K, V>,
+    key: K,
+    value: V,
+    alloc: &A,
+) -> Result<InsertResult<K, V>, InsertPrefixError>
+where
+    K: AsBytes,
+    A: Allocator,
+{
+    // SAFETY: The safety requirements are covered by the containing function
+    unsafe { insert_recorded(root, key, value, &mut (), alloc) }
+}
+
+/// Insert the given key-value pair into the tree, and return counters
+/// describing the work performed while searching for the insert point.
+///
+/// See [`insert_unchecked`] for details of the return value, panics and
+/// errors.
+///
+/// # Safety
+///
+///  - The `root` [`OpaqueNodePtr`] must be a unique pointer to the underlying
+///    tree
+///  - This function cannot be called concurrently to any reads or writes of the
+///    `root` node or any child node of `root`. This function will arbitrarily
+///    read or write to any child in the given tree.
+pub unsafe fn insert_instrumented_unchecked<K, V>(
+    root: OpaqueNodePtr<K, V>,
+    key: K,
+    value: V,
+) -> (Result<InsertResult<K, V>, InsertPrefixError>, SearchStats)
+where
+    K: AsBytes,
+{
+    let mut stats = SearchStats::default();
+    // SAFETY: The safety requirements are covered by the containing function
+    let result = unsafe { insert_recorded(root, key, value, &mut stats, &Global) };
+
+    (result, stats)
+}
+
+/// Insert the given key-value pair into the tree, reporting every step of the
+/// search for the insert point to the given recorder.
+///
+/// # Safety
+///
+///  - The `root` [`OpaqueNodePtr`] must be a unique pointer to the underlying
+///    tree
+///  - This function cannot be called concurrently to any reads or writes of the
+///    `root` node or any child node of `root`. This function will arbitrarily
+///    read or write to any child in the given tree.
+///  - The nodes of the tree must have been allocated by the given allocator.
+pub(crate) unsafe fn insert_recorded<K, V, R, A>(
+    root: OpaqueNodePtr<K, V>,
+    key: K,
+    value: V,
+    recorder: &mut R,
+    alloc: &A,
+) -> Result<InsertResult<K, V>, InsertPrefixError>
+where
+    K: AsBytes,
+    R: SearchRecorder,
+    A: Allocator,
+{
+    // SAFETY: The safety requirements are covered by the containing function
+    unsafe { insert_leaf_recorded(root, NewLeaf::Entry(key, value), recorder, alloc) }
+        .map_err(|(err, _)| err)
+}
+
+/// The error from inserting an unlinked leaf, along with the leaf which is
+/// still unlinked.
+pub(crate) type UnlinkedInsertError<K, V> = (InsertPrefixError, NodePtr<LeafNode<K, V>>);
+
+/// Insert a leaf which was unlinked from this tree, reusing its allocation.
+///
+/// If the key of the leaf already exists in the tree, the existing leaf keeps
+/// its place and takes over the key and value of the given leaf, which is then
+/// deallocated.
+///
+/// On error, the tree is unchanged and the leaf is returned, still unlinked.
+///
+/// # Safety
+///
+///  - The `root` [`OpaqueNodePtr`] must be a unique pointer to the underlying
+///    tree
+///  - `leaf_node_ptr` must be a unique pointer to a leaf which is not reachable
+///    from any tree.
+///  - This function cannot be called concurrently to any reads or writes of the
+///    `root` node or any child node of `root`. This function will arbitrarily
+///    read or write to any child in the given tree.
+///  - The nodes of the tree and the leaf must have been allocated by the given
+///    allocator.
+pub(crate) unsafe fn insert_unlinked_leaf_unchecked<K, V, A>(
+    root: OpaqueNodePtr<K, V>,
+    leaf_node_ptr: NodePtr<LeafNode<K, V>>,
+    alloc: &A,
+) -> Result<InsertResult<K, V>, UnlinkedInsertError<K, V>>
+where
+    K: AsBytes,
+    A: Allocator,
+{
+    // SAFETY: The safety requirements are covered by the containing function
+    unsafe { insert_leaf_recorded(root, NewLeaf::Unlinked(leaf_node_ptr), &mut (), alloc) }.map_err(
+        |(err, new_leaf)| match new_leaf {
+            NewLeaf::Unlinked(leaf_node_ptr) => (err, leaf_node_ptr),
+            NewLeaf::Entry(..) => unreachable!("the leaf is returned as it was given"),
+        },
+    )
+}
+
+/// Link a subtree which was unlinked from this tree back in, so that it holds
+/// the keys which start with `prefix`.
+///
+/// The compressed path of the subtree root must only hold the bytes that
+/// follow `prefix`, and every key in the subtree must start with `prefix`.
+/// Returns the new root of the tree.
+///
+/// # Errors
+///
+/// Returns an [`InsertPrefixError`] holding `prefix`, if any key in the tree
+/// starts with `prefix`, or is a prefix of it. In that case the tree and the
+/// subtree are unchanged.
+///
+/// If this unwinds, the tree is unchanged, but the compressed path of the
+/// subtree root may already have been extended.
+///
+/// # Safety
+///
+///  - The `root` [`OpaqueNodePtr`] must be a unique pointer to the underlying
+///    tree
+///  - `subtree` must be a unique pointer to a subtree which is not reachable
+///    from any tree.
+///  - This function cannot be called concurrently to any reads or writes of the
+///    `root` node or any child node of `root`. This function will arbitrarily
+///    read or write to any child in the given tree.
+///  - The nodes of the tree and the subtree must have been allocated by the
+///    given allocator.
+pub(crate) unsafe fn graft_subtree_unchecked<K, V, A>(
+    root: OpaqueNodePtr<K, V>,
+    prefix: &[u8],
+    subtree: OpaqueNodePtr<K, V>,
+    alloc: &A,
+) -> Result<OpaqueNodePtr<K, V>, InsertPrefixError>
+where
+    K: AsBytes,
+    A: Allocator,
+{
+    /// Add the bytes of `prefix` which follow the child key byte of the subtree
+    /// to its compressed path.
+    fn extend_subtree_path<K, V>(subtree: OpaqueNodePtr<K, V>, remaining_prefix: &[u8]) {
+        // SAFETY: The subtree is not reachable from any tree, so this is the only
+        // access to its root node.
+        if let Some(header) = unsafe { subtree.header_mut() } {
+            header.prepend_prefix(remaining_prefix);
+        }
+    }
+
+    let prefix_error = || InsertPrefixError {
+        byte_repr: prefix.into(),
+    };
+
+    // SAFETY: Requirements covered by containing function
+    let InsertSearchResult {
+        parent_ptr_and_child_key_byte,
+        insert_type,
+        key_bytes_used,
+    } = unsafe { search_for_insert_point_recorded(root, prefix, &mut ())? };
+
+    let new_inner_node = match insert_type {
+        InsertSearchResultType::MismatchPrefix {
+            matched_prefix_size,
+            mismatched_inner_node_ptr,
+        } => {
+            let split_depth = key_bytes_used + matched_prefix_size;
+            if split_depth >= prefix.len() {
+                // Every key under the mismatched node starts with the prefix
+                return Err(prefix_error());
+            }
+            extend_subtree_path(subtree, &prefix[(split_depth + 1)..]);
+
+            // SAFETY: The requirements of the "no concurrent (read or write) access" are
+            // enforced by the containing function requirements, and the mismatched node is
+            // at the depth of the key bytes used by the search.
+            //
+            // PANIC SAFETY: This is guaranteed not to panic because the `MismatchPrefix`
+            // variant is only returned in cases where there was a mismatch in the header
+            // prefix, implying that the header is present.
+            let mismatched_byte = unsafe {
+                mismatched_inner_node_ptr
+                    .read_full_prefix(key_bytes_used)
+                    .unwrap()[matched_prefix_size]
+            };
+
+            let mut new_n4 = InnerNode4::empty();
+            new_n4.write_child(mismatched_byte, mismatched_inner_node_ptr);
+            new_n4.write_child(prefix[split_depth], subtree);
+            new_n4
+                .header
+                .extend_prefix(&prefix[key_bytes_used..split_depth]);
+            let new_n4 = NodePtr::allocate_node_ptr_in(new_n4, alloc);
+
+            // SAFETY: No other access to the mismatched node occurs while its prefix is
+            // trimmed, see above.
+            unsafe {
+                mismatched_inner_node_ptr.ltrim_full_prefix(matched_prefix_size + 1, key_bytes_used)
+            };
+
+            new_n4.to_opaque()
+        },
+        InsertSearchResultType::SplitLeaf { leaf_node_ptr } => {
+            // SAFETY: The lifetime of the key reference is restricted to this block, and
+            // the leaf is not mutated while it is live.
+            let leaf_key_bytes = unsafe { leaf_node_ptr.as_key_ref() }.as_bytes();
+
+            let split_depth = key_bytes_used
+                + leaf_key_bytes[key_bytes_used..]
+                    .iter()
+                    .zip(prefix[key_bytes_used..].iter())
+                    .take_while(|(k1, k2)| k1 == k2)
+                    .count();
+            if split_depth >= prefix.len() || split_depth >= leaf_key_bytes.len() {
+                // The leaf key starts with the prefix, or is a prefix of it
+                return Err(prefix_error());
+            }
+            extend_subtree_path(subtree, &prefix[(split_depth + 1)..]);
+
+            let mut new_n4 = InnerNode4::empty();
+            new_n4
+                .header
+                .extend_prefix(&prefix[key_bytes_used..split_depth]);
+            new_n4.write_child(leaf_key_bytes[split_depth], leaf_node_ptr.to_opaque());
+            new_n4.write_child(prefix[split_depth], subtree);
+            NodePtr::allocate_node_ptr_in(new_n4, alloc).to_opaque()
+        },
+        InsertSearchResultType::IntoExisting { inner_node_ptr } => {
+            // PANIC SAFETY: The search for the insert point guarantees that the prefix has
+            // a byte at `key_bytes_used`, otherwise it would have returned an error.
+            let child_key_byte = prefix[key_bytes_used];
+            extend_subtree_path(subtree, &prefix[(key_bytes_used + 1)..]);
+
+            // SAFETY: The inner node is part of the tree, which is uniquely accessed by the
+            // safety requirements of the containing function.
+            unsafe {
+                write_new_child_in_existing_node(inner_node_ptr, subtree, child_key_byte, alloc)
+            }
+        },
+    };
+
+    match parent_ptr_and_child_key_byte {
+        Some((parent_ptr, parent_key_fragment)) => {
+            parent_write_child(parent_ptr, parent_key_fragment, new_inner_node);
+            Ok(root)
+        },
+        None => Ok(new_inner_node),
+    }
+}
+
+/// The result of an insert which started from a node inside the tree, along
+/// with the node at the insert point.
+pub(crate) type InsertFromResult<K, V> =
+    Result<(InsertResult<K, V>, OpaqueNodePtr<K, V>), InsertPrefixError>;
+
+/// Insert the given key-value pair into the tree, searching for the insert
+/// point from the given node of the tree instead of its root.
+///
+/// The `start_node` must be on the search path of `key`, it must sit after
+/// `start_depth` key bytes, and `start_parent` must be its parent and the key
+/// byte of the `start_node` in it, or `None` if it is the root.
+///
+/// Returns the insert result along with the node at the insert point. That
+/// node may be replaced, or moved below a new inner node, while the nodes
+/// above it on the path of the key are unchanged.
+///
+/// This provides the same exception-safety guarantee as [`insert_unchecked`].
+///
+/// # Errors
+///
+/// If the given `key` is a prefix of an existing key, or an existing key is a
+/// prefix of the given `key`, this function will return an error.
+///
+/// # Safety
+///
+///  - The `root` [`OpaqueNodePtr`] must be a unique pointer to the underlying
+///    tree, and the `start_node` must be a node of that tree.
+///  - This function cannot be called concurrently to any reads or writes of the
+///    `root` node or any child node of `root`. This function will arbitrarily
+///    read or write to any child in the given tree.
+///  - The nodes of the tree must have been allocated by the given allocator.
+pub(crate) unsafe fn insert_from_unchecked<K, V, A>(
+    root: OpaqueNodePtr<K, V>,
+    start_node: OpaqueNodePtr<K, V>,
+    start_depth: usize,
+    start_parent: Option<(OpaqueNodePtr<K, V>, u8)>,
+    key: K,
+    value: V,
+    alloc: &A,
+) -> InsertFromResult<K, V>
+where
+    K: AsBytes,
+    A: Allocator,
+{
+    // SAFETY: The start node is on the search path of the key, and the rest of the
+    // requirements are covered by the containing function.
+    let search_result = unsafe {
+        search_for_insert_point_from_recorded(start_node, start_depth, start_parent, &key, &mut ())?
+    };
+    let insert_point = match &search_result.insert_type {
+        InsertSearchResultType::MismatchPrefix {
+            mismatched_inner_node_ptr: inner_node_ptr,
+            ..
+        }
+        | InsertSearchResultType::IntoExisting { inner_node_ptr } => *inner_node_ptr,
+        InsertSearchResultType::SplitLeaf { leaf_node_ptr } => leaf_node_ptr.to_opaque(),
+    };
+
+    // SAFETY: The search result was just found for this key in the tree, and the
+    // rest of the requirements are covered by the containing function.
+    let insert_result =
+        unsafe { insert_leaf_at_point(root, search_result, NewLeaf::Entry(key, value), alloc) }
+            .map_err(|(err, _)| err)?;
+
+    Ok((insert_result, insert_point))
+}
+
+/// The path from the root of a tree down to its maximum leaf.
+///
+/// Every key which is greater than all the keys in the tree is inserted
+/// somewhere on this path, so a run of such inserts can start searching for
+/// the insert point near the bottom of the tree instead of at the root.
+pub(crate) struct RightmostPath<K, V> {
+    /// The inner nodes on the path, starting at the root, each with the number
+    /// of key bytes that come before its compressed path.
+    inner_nodes: Vec<(OpaqueNodePtr<K, V>, usize)>,
+    /// The leaf at the end of the path.
+    maximum_leaf: NodePtr<LeafNode<K, V>>,
+}
+
+impl<K, V> RightmostPath<K, V> {
+    /// Find the path from the given root down to the maximum leaf of the tree.
+    ///
+    /// # Safety
+    ///
+    ///  - This function cannot be called concurrently with any mutating
+    ///    operation on `root` or any child node of `root`. This function will
+    ///    arbitrarily read to any child in the given tree.
+    pub(crate) unsafe fn new(root: OpaqueNodePtr<K, V>) -> Self {
+        let mut inner_nodes = Vec::new();
+        // SAFETY: Covered by the safety requirements of this function
+        let maximum_leaf = unsafe { Self::descend_to_maximum(&mut inner_nodes, root, 0) };
+
+        RightmostPath {
+            inner_nodes,
+            maximum_leaf,
+        }
+    }
+
+    /// Return the maximum key of the tree.
+    ///
+    /// # Safety
+    ///
+    ///  - The tree must not be modified while the returned reference is live.
+    pub(crate) unsafe fn maximum_key(&self) -> &K {
+        // SAFETY: Covered by the safety requirements of this function
+        unsafe { self.maximum_leaf.as_key_ref() }
+    }
+
+    /// Insert the given key-value pair into the tree, where the key is greater
+    /// than every key in the tree. The path is updated to end at the new leaf.
+    ///
+    /// This provides the same exception-safety guarantee as
+    /// [`insert_unchecked`], and the path is unchanged if this unwinds.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`InsertPrefixError`] if the maximum key of the tree is a
+    /// prefix of the given key.
+    ///
+    /// # Safety
+    ///
+    ///  - The `root` [`OpaqueNodePtr`] must be a unique pointer to the root of
+    ///    the tree this path was found in, and the tree must not have been
+    ///    modified since, other than by this function.
+    ///  - The `key` must be greater than every key in the tree.
+    ///  - This function cannot be called concurrently to any reads or writes of
+    ///    the `root` node or any child node of `root`. This function will
+    ///    arbitrarily read or write to any child in the given tree.
+    ///  - The nodes of the tree must have been allocated by the given
+    ///    allocator.
+    pub(crate) unsafe fn append_unchecked<A: Allocator>(
+        &mut self,
+        root: OpaqueNodePtr<K, V>,
+        key: K,
+        value: V,
+        alloc: &A,
+    ) -> Result<InsertResult<K, V>, InsertPrefixError>
     where
         K: AsBytes,
     {
-        fn write_new_child_in_existing_inner_node<K, V, N>(
-            inner_node_ptr: NodePtr<N>,
-            new_leaf_node: LeafNode<K, V>,
-            key_bytes_used: usize,
-        ) -> OpaqueNodePtr<K, V>
-        where
-            N: InnerNode<Key = K, Value = V>,
-            K: AsBytes,
+        let key_bytes = key.as_bytes();
+        // SAFETY: The tree is not modified while the key reference is live
+        let maximum_key_bytes = unsafe { self.maximum_leaf.as_key_ref() }.as_bytes();
+        let diverging_depth = key_bytes
+            .iter()
+            .zip(maximum_key_bytes)
+            .take_while(|(k1, k2)| k1 == k2)
+            .count();
+
+        // The key follows the path of the maximum key down to the point where the two
+        // keys diverge, so the search starts at the deepest inner node whose
+        // compressed path begins before that point.
+        let (start_node, start_depth, start_parent) = match self
+            .inner_nodes
+            .iter()
+            .rposition(|(_, depth)| *depth <= diverging_depth)
         {
-            // SAFETY: The `inner_node` reference lasts only for the duration of this
-            // function, and the node will not be read or written via any other source
-            // because of the safety requirements on `insert_unchecked`.
-            let inner_node = unsafe { inner_node_ptr.as_mut() };
-            let new_leaf_key_byte = new_leaf_node.key_ref().as_bytes()[key_bytes_used];
-            let new_leaf_ptr = NodePtr::allocate_node_ptr(new_leaf_node).to_opaque();
-            if inner_node.is_full() {
-                // we will create a new node of the next larger type and copy all the
-                // children over.
-
-                let mut new_node = inner_node.grow();
-                new_node.write_child(new_leaf_key_byte, new_leaf_ptr);
-
-                let new_inner_node = NodePtr::allocate_node_ptr(new_node).to_opaque();
-
-                // SAFETY: The `deallocate_node` function is only called a
-                // single time. The uniqueness requirement is passed up to the
-                // `insert_unchecked` safety requirements.
-                unsafe {
-                    #[allow(clippy::drop_ref)]
-                    drop(inner_node);
-                    drop(NodePtr::deallocate_node_ptr(inner_node_ptr));
-                };
+            Some(start_idx) => {
+                let (start_node, start_depth) = self.inner_nodes[start_idx];
+                let start_parent = start_idx
+                    .checked_sub(1)
+                    .map(|parent_idx| (self.inner_nodes[parent_idx].0, key_bytes[start_depth - 1]));
+                (start_node, start_depth, start_parent)
+            },
+            None => (self.maximum_leaf.to_opaque(), 0, None),
+        };
 
-                new_inner_node
-            } else {
-                inner_node.write_child(new_leaf_key_byte, new_leaf_ptr);
+        // SAFETY: The start node is on the search path of the key, and the rest of the
+        // requirements are covered by the containing function.
+        let search_result = unsafe {
+            search_for_insert_point_from_recorded(
+                start_node,
+                start_depth,
+                start_parent,
+                &key,
+                &mut (),
+            )?
+        };
 
-                inner_node_ptr.to_opaque()
+        // The nodes from the insert point down are replaced, so the path is found
+        // again from the parent of the insert point.
+        let insert_point_idx = match &search_result.insert_type {
+            InsertSearchResultType::MismatchPrefix {
+                mismatched_inner_node_ptr: inner_node_ptr,
+                ..
             }
+            | InsertSearchResultType::IntoExisting { inner_node_ptr } => self
+                .inner_nodes
+                .iter()
+                .rposition(|(node, _)| node == inner_node_ptr)
+                // PANIC SAFETY: The search started on the path, and only continues down
+                // the last child of each node since the key is greater than the
+                // maximum key.
+                .expect("insert point should be on the rightmost path"),
+            InsertSearchResultType::SplitLeaf { .. } => self.inner_nodes.len(),
+        };
+
+        // SAFETY: The search result was just found for this key in the tree, and the
+        // rest of the requirements are covered by the containing function.
+        let insert_result =
+            unsafe { insert_leaf_at_point(root, search_result, NewLeaf::Entry(key, value), alloc) }
+                .map_err(|(err, _)| err)?;
+
+        let (restart_node, restart_depth) = match insert_point_idx.checked_sub(1) {
+            Some(parent_idx) => self.inner_nodes[parent_idx],
+            None => (insert_result.new_root, 0),
+        };
+        self.inner_nodes
+            .truncate(insert_point_idx.saturating_sub(1));
+        // SAFETY: Covered by the safety requirements of this function
+        self.maximum_leaf =
+            unsafe { Self::descend_to_maximum(&mut self.inner_nodes, restart_node, restart_depth) };
+        debug_assert_eq!(self.maximum_leaf, insert_result.leaf_node_ptr);
+
+        Ok(insert_result)
+    }
+
+    /// Follow the last child of each inner node from the given node, which
+    /// comes after `depth` key bytes, adding the inner nodes to the path and
+    /// returning the leaf at the end.
+    ///
+    /// # Safety
+    ///
+    ///  - This function cannot be called concurrently with any mutating
+    ///    operation on `node` or any child node of `node`.
+    unsafe fn descend_to_maximum(
+        inner_nodes: &mut Vec<(OpaqueNodePtr<K, V>, usize)>,
+        mut node: OpaqueNodePtr<K, V>,
+        mut depth: usize,
+    ) -> NodePtr<LeafNode<K, V>> {
+        fn last_child<N: InnerNode>(
+            inner_ptr: NodePtr<N>,
+        ) -> (usize, OpaqueNodePtr<N::Key, N::Value>) {
+            // SAFETY: The lifetime produced from this is bounded to this scope and does
+            // not escape, and there are no concurrent mutations of the node, by the
+            // safety requirements of `descend_to_maximum`.
+            let inner_node = unsafe { inner_ptr.as_ref() };
+            // SAFETY: The iterator is limited to this scope, see above.
+            let mut iter = unsafe { inner_node.iter() };
+            // PANIC SAFETY: A well-formed tree has no empty inner nodes
+            let (_, child) = iter
+                .next_back()
+                .expect("an inner node must always have at least one child");
+
+            (inner_node.header().prefix_size(), child)
         }
 
-        match inner_node_ptr.to_node_ptr() {
-            ConcreteNodePtr::Node4(inner_ptr) => {
-                write_new_child_in_existing_inner_node(inner_ptr, new_leaf_node, key_bytes_used)
-            },
-            ConcreteNodePtr::Node16(inner_ptr) => {
-                write_new_child_in_existing_inner_node(inner_ptr, new_leaf_node, key_bytes_used)
-            },
-            ConcreteNodePtr::Node48(inner_ptr) => {
-                write_new_child_in_existing_inner_node(inner_ptr, new_leaf_node, key_bytes_used)
-            },
-            ConcreteNodePtr::Node256(inner_ptr) => {
-                write_new_child_in_existing_inner_node(inner_ptr, new_leaf_node, key_bytes_used)
-            },
-            ConcreteNodePtr::LeafNode(_) => {
-                panic!("Cannot have insert into existing with leaf node")
-            },
+        loop {
+            let (prefix_size, child) = match node.to_node_ptr() {
+                ConcreteNodePtr::Node4(inner_ptr) => last_child(inner_ptr),
+                ConcreteNodePtr::Node16(inner_ptr) => last_child(inner_ptr),
+                ConcreteNodePtr::Node48(inner_ptr) => last_child(inner_ptr),
+                ConcreteNodePtr::Node256(inner_ptr) => last_child(inner_ptr),
+                ConcreteNodePtr::LeafNode(leaf_node_ptr) => return leaf_node_ptr,
+            };
+
+            inner_nodes.push((node, depth));
+            depth += prefix_size + 1;
+            node = child;
         }
     }
+}
 
-    /// Write a new child node to an inner node at the specified key byte.
-    fn parent_write_child<K, V>(
-        parent_inner_node: OpaqueNodePtr<K, V>,
-        key_byte: u8,
-        new_child: OpaqueNodePtr<K, V>,
-    ) {
-        fn write_inner_node<K, V, N>(
-            parent_inner_node: NodePtr<N>,
-            key_byte: u8,
-            new_child: OpaqueNodePtr<K, V>,
-        ) where
-            N: InnerNode<Key = K, Value = V>,
-        {
-            // SAFETY: The lifetime produced from this is bounded to this scope and does not
-            // escape. Further, no other code mutates the node referenced, which is further
-            // enforced the "no concurrent reads or writes" requirement on the
-            // `maximum_unchecked` function.
-            let parent_node = unsafe { parent_inner_node.as_mut() };
+/// The leaf that an insert adds to the tree.
+enum NewLeaf<K, V> {
+    /// A key and value that still need a leaf allocation
+    Entry(K, V),
+    /// A leaf which is not reachable from any tree, which is reused as-is
+    Unlinked(NodePtr<LeafNode<K, V>>),
+}
 
-            parent_node.write_child(key_byte, new_child);
+impl<K, V> NewLeaf<K, V> {
+    /// Return the key that the leaf will be stored with.
+    ///
+    /// # Safety
+    ///
+    ///  - For the unlinked leaf, there must not be any mutation of the leaf
+    ///    while the returned reference is live.
+    unsafe fn key(&self) -> &K {
+        match self {
+            NewLeaf::Entry(key, _) => key,
+            // SAFETY: Covered by the safety requirements of this function
+            NewLeaf::Unlinked(leaf_node_ptr) => unsafe { leaf_node_ptr.as_key_ref() },
         }
+    }
 
-        match parent_inner_node.to_node_ptr() {
-            ConcreteNodePtr::Node4(inner_ptr) => write_inner_node(inner_ptr, key_byte, new_child),
-            ConcreteNodePtr::Node16(inner_ptr) => write_inner_node(inner_ptr, key_byte, new_child),
-            ConcreteNodePtr::Node48(inner_ptr) => write_inner_node(inner_ptr, key_byte, new_child),
-            ConcreteNodePtr::Node256(inner_ptr) => write_inner_node(inner_ptr, key_byte, new_child),
-            ConcreteNodePtr::LeafNode(_) => {
-                panic!("A leaf pointer cannot be the parent of another node")
+    /// Allocate the leaf with the given allocator if needed, and guard it until
+    /// it is linked into the tree.
+    fn allocate<A: Allocator>(self, alloc: &A) -> UnlinkedNode<'_, LeafNode<K, V>, A> {
+        match self {
+            NewLeaf::Entry(key, value) => UnlinkedNode::allocate(LeafNode::new(key, value), alloc),
+            NewLeaf::Unlinked(leaf_node_ptr) => UnlinkedNode {
+                ptr: Some(leaf_node_ptr),
+                alloc,
+            },
+        }
+    }
+
+    /// Take the key and value out of the leaf, returning it to the given
+    /// allocator if needed.
+    ///
+    /// # Safety
+    ///
+    ///  - If the leaf is unlinked, it must have been allocated by the given
+    ///    allocator.
+    unsafe fn into_leaf_node<A: Allocator>(self, alloc: &A) -> LeafNode<K, V> {
+        match self {
+            NewLeaf::Entry(key, value) => LeafNode::new(key, value),
+            // SAFETY: The unlinked leaf is not reachable from any tree, so this is the
+            // only place where it is deallocated. It was allocated by the given allocator,
+            // by the safety requirements of this function.
+            NewLeaf::Unlinked(leaf_node_ptr) => unsafe {
+                NodePtr::deallocate_node_ptr_in(leaf_node_ptr, alloc)
             },
         }
     }
+}
+
+/// Insert the given leaf into the tree, reporting every step of the search for
+/// the insert point to the given recorder.
+///
+/// On error, the given leaf is returned along with the error.
+///
+/// # Safety
+///
+///  - The `root` [`OpaqueNodePtr`] must be a unique pointer to the underlying
+///    tree
+///  - If the leaf is unlinked, `leaf_node_ptr` must be a unique pointer to a
+///    leaf which is not reachable from any tree.
+///  - This function cannot be called concurrently to any reads or writes of the
+///    `root` node or any child node of `root`. This function will arbitrarily
+///    read or write to any child in the given tree.
+///  - The nodes of the tree and the unlinked leaf must have been allocated by
+///    the given allocator.
+unsafe fn insert_leaf_recorded<K, V, R, A>(
+    root: OpaqueNodePtr<K, V>,
+    new_leaf: NewLeaf<K, V>,
+    recorder: &mut R,
+    alloc: &A,
+) -> Result<InsertResult<K, V>, (InsertPrefixError, NewLeaf<K, V>)>
+where
+    K: AsBytes,
+    R: SearchRecorder,
+    A: Allocator,
+{
+    // SAFETY: The unlinked leaf is only mutated when it is linked into the tree,
+    // after the last use of the key reference.
+    let key = unsafe { new_leaf.key() };
+
+    // SAFETY: Requirements covered by containing function
+    let search_result = match unsafe { search_for_insert_point_recorded(root, key, recorder) } {
+        Ok(search_result) => search_result,
+        Err(err) => return Err((err, new_leaf)),
+    };
 
     // SAFETY: Requirements covered by containing function
+    unsafe { insert_leaf_at_point(root, search_result, new_leaf, alloc) }
+}
+
+/// The result of searching for the entry of a key in the tree.
+pub(crate) enum EntrySearchResult<K, V> {
+    /// The leaf which already holds the key.
+    Occupied(NodePtr<LeafNode<K, V>>),
+    /// The insert point for the key, which does not conflict with any of the
+    /// existing keys.
+    Vacant(InsertSearchResult<K, V>),
+}
+
+/// Search for the leaf holding the given key, or the point where a leaf for
+/// the key would be inserted.
+///
+/// Unlike [`search_for_insert_point`], a vacant result is only returned when
+/// the key can be inserted at that point without an error.
+///
+/// # Safety
+///
+///  - This function cannot be called concurrently to any writes of the `root`
+///    node or any child node of `root`.
+///
+/// # Errors
+///
+/// If the given `key` is a prefix of an existing key, or an existing key is a
+/// prefix of the given `key`, this function will return an error.
+pub(crate) unsafe fn search_for_entry_unchecked<K, V>(
+    root: OpaqueNodePtr<K, V>,
+    key: &K,
+) -> Result<EntrySearchResult<K, V>, InsertPrefixError>
+where
+    K: AsBytes,
+{
+    // SAFETY: The safety requirements are covered by the containing function
+    let search_result = unsafe { search_for_insert_point(root, key)? };
+    let key_bytes = key.as_bytes();
+    let key_bytes_used = search_result.key_bytes_used;
+
+    // These are the same checks that `insert_leaf_at_point` makes before changing
+    // the tree, so that inserting at the vacant point cannot fail.
+    let has_unique_byte = match &search_result.insert_type {
+        InsertSearchResultType::MismatchPrefix {
+            matched_prefix_size,
+            ..
+        } => key_bytes_used + matched_prefix_size < key_bytes.len(),
+        InsertSearchResultType::SplitLeaf { leaf_node_ptr } => {
+            // SAFETY: The lifetime of the leaf reference is restricted to this block, and
+            // there are no concurrent writes by the safety requirements of the containing
+            // function.
+            let leaf_node = unsafe { leaf_node_ptr.as_ref() };
+            if leaf_node.matches_full_key(key) {
+                return Ok(EntrySearchResult::Occupied(*leaf_node_ptr));
+            }
+
+            let leaf_key_bytes = leaf_node.key_ref().as_bytes();
+            let new_key_bytes_used = key_bytes_used
+                + leaf_key_bytes[key_bytes_used..]
+                    .iter()
+                    .zip(key_bytes[key_bytes_used..].iter())
+                    .take_while(|(k1, k2)| k1 == k2)
+                    .count();
+            new_key_bytes_used < key_bytes.len() && new_key_bytes_used < leaf_key_bytes.len()
+        },
+        InsertSearchResultType::IntoExisting { .. } => true,
+    };
+
+    if has_unique_byte {
+        Ok(EntrySearchResult::Vacant(search_result))
+    } else {
+        Err(InsertPrefixError {
+            byte_repr: key_bytes.into(),
+        })
+    }
+}
+
+/// Insert the given key and value at a vacant insert point, which was found
+/// by [`search_for_entry_unchecked`].
+///
+/// # Safety
+///
+///  - The `root` [`OpaqueNodePtr`] must be a unique pointer to the underlying
+///    tree
+///  - The `search_result` must be a vacant result of
+///    [`search_for_entry_unchecked`] for this key in this tree, and the tree
+///    must not have been modified since it was found.
+///  - This function cannot be called concurrently to any reads or writes of the
+///    `root` node or any child node of `root`. This function will arbitrarily
+///    read or write to any child in the given tree.
+///  - The nodes of the tree must have been allocated by the given allocator.
+pub(crate) unsafe fn insert_at_entry_point_unchecked<K, V, A>(
+    root: OpaqueNodePtr<K, V>,
+    search_result: InsertSearchResult<K, V>,
+    key: K,
+    value: V,
+    alloc: &A,
+) -> InsertResult<K, V>
+where
+    K: AsBytes,
+    A: Allocator,
+{
+    // SAFETY: Requirements covered by containing function
+    match unsafe { insert_leaf_at_point(root, search_result, NewLeaf::Entry(key, value), alloc) } {
+        Ok(insert_result) => insert_result,
+        Err(_err) => unreachable!(
+            "This branch should be unreachable because the entry search checked that the key does \
+             not conflict with an existing key"
+        ),
+    }
+}
+
+/// Insert the given leaf into the tree at the insert point found for its key.
+///
+/// On error, the given leaf is returned along with the error.
+///
+/// # Safety
+///
+///  - The `root` [`OpaqueNodePtr`] must be a unique pointer to the underlying
+///    tree
+///  - The `search_result` must be the insert point for the key of the leaf in
+///    this tree, and the tree must not have been modified since it was found.
+///  - If the leaf is unlinked, `leaf_node_ptr` must be a unique pointer to a
+///    leaf which is not reachable from any tree.
+///  - This function cannot be called concurrently to any reads or writes of the
+///    `root` node or any child node of `root`. This function will arbitrarily
+///    read or write to any child in the given tree.
+///  - The nodes of the tree and the unlinked leaf must have been allocated by
+///    the given allocator.
+unsafe fn insert_leaf_at_point<K, V, A>(
+    root: OpaqueNodePtr<K, V>,
+    search_result: InsertSearchResult<K, V>,
+    new_leaf: NewLeaf<K, V>,
+    alloc: &A,
+) -> Result<InsertResult<K, V>, (InsertPrefixError, NewLeaf<K, V>)>
+where
+    K: AsBytes,
+    A: Allocator,
+{
+    // SAFETY: The unlinked leaf is only mutated when it is linked into the tree,
+    // after the last use of the key reference.
+    let key = unsafe { new_leaf.key() };
     let InsertSearchResult {
         parent_ptr_and_child_key_byte,
         insert_type,
-        mut key_bytes_used,
-    } = unsafe { search_for_insert_point(root, &key)? };
+        key_bytes_used,
+    } = search_result;
 
-    let new_inner_node = match insert_type {
+    let (new_inner_node, new_leaf_ptr) = match insert_type {
         InsertSearchResultType::MismatchPrefix {
             matched_prefix_size,
             mismatched_inner_node_ptr,
         } => {
-            // SAFETY: The lifetime of the header reference is restricted to this block and
-            // within the block no other access occurs. The requirements of
-            // the "no concurrent (read or write) access" is also enforced by the
-            // `insert_unchecked` caller requirements.
-            //
-            // PANIC SAFETY: This is guaranteed not to panic because the `MismatchPrefix`
-            // variant is only returned in cases where there was a mismatch in the header
-            // prefix, implying that the header is present.
-            let header = unsafe { mismatched_inner_node_ptr.header_mut().unwrap() };
-
-            if (key_bytes_used + matched_prefix_size) >= key.as_bytes().len() {
+            let key_bytes = key.as_bytes();
+            if (key_bytes_used + matched_prefix_size) >= key_bytes.len() {
                 // then the key has insufficient bytes to be unique. It must be
                 // a prefix of an existing key
 
-                return Err(InsertPrefixError {
-                    byte_repr: key.as_bytes().into(),
-                });
+                let err = InsertPrefixError {
+                    byte_repr: key_bytes.into(),
+                };
+                return Err((err, new_leaf));
             }
 
-            let new_leaf_key_byte = key.as_bytes()[key_bytes_used + matched_prefix_size];
+            let new_leaf_key_byte = key_bytes[key_bytes_used + matched_prefix_size];
 
-            let new_leaf_pointer =
-                NodePtr::allocate_node_ptr(LeafNode::new(key, value)).to_opaque();
+            // SAFETY: The requirements of the "no concurrent (read or write) access" are
+            // enforced by the `insert_unchecked` caller requirements, and the mismatched
+            // node is at the depth of the key bytes used by the search.
+            //
+            // PANIC SAFETY: This is guaranteed not to panic because the `MismatchPrefix`
+            // variant is only returned in cases where there was a mismatch in the header
+            // prefix, implying that the header is present.
+            let mismatched_byte = unsafe {
+                mismatched_inner_node_ptr
+                    .read_full_prefix(key_bytes_used)
+                    .unwrap()[matched_prefix_size]
+            };
 
             // prefix mismatch, need to split prefix into two separate nodes and take the
             // common prefix into a new parent node
             let mut new_n4 = InnerNode4::empty();
 
-            new_n4.write_child(
-                header.read_prefix()[matched_prefix_size],
-                mismatched_inner_node_ptr,
-            );
-            new_n4.write_child(new_leaf_key_byte, new_leaf_pointer);
-
+            // The matched bytes of the prefix are equal to the key bytes
             new_n4
                 .header
-                .extend_prefix(&header.read_prefix()[..matched_prefix_size]);
-            header.ltrim_prefix(matched_prefix_size + 1);
+                .extend_prefix(&key_bytes[key_bytes_used..(key_bytes_used + matched_prefix_size)]);
+
+            let new_leaf_pointer = new_leaf.allocate(alloc);
 
-            NodePtr::allocate_node_ptr(new_n4).to_opaque()
+            new_n4.write_child(mismatched_byte, mismatched_inner_node_ptr);
+            new_n4.write_child(new_leaf_key_byte, new_leaf_pointer.ptr().to_opaque());
+
+            let new_n4 = UnlinkedNode::allocate(new_n4, alloc);
+
+            // The existing node is only modified once the new nodes have been allocated,
+            // so that an unwind before this point leaves the tree unchanged.
+            //
+            // SAFETY: No other access to the mismatched node occurs while its prefix is
+            // trimmed, see above.
+            unsafe {
+                mismatched_inner_node_ptr.ltrim_full_prefix(matched_prefix_size + 1, key_bytes_used)
+            };
+
+            let new_leaf_ptr = new_leaf_pointer.link();
+            (new_n4.link().to_opaque(), new_leaf_ptr)
         },
         InsertSearchResultType::SplitLeaf { leaf_node_ptr } => {
             let leaf_node = leaf_node_ptr.read();
 
-            if leaf_node.matches_full_key(&key) {
+            if leaf_node.matches_full_key(key) {
                 // This means that the key provided exactly matched the existing leaf key, so we
                 // will simply replace the contents of the leaf node.
                 #[allow(clippy::undropped_manually_drops)]
                 drop(leaf_node);
 
-                let new_leaf_node = LeafNode::new(key, value);
+                // SAFETY: The unlinked leaf was allocated by the given allocator, by the
+                // safety requirements of the containing function.
+                let new_leaf_node = unsafe { new_leaf.into_leaf_node(alloc) };
                 // SAFETY: The leaf node will not be accessed concurrently because of the safety
                 // doc on the containing function
                 let old_leaf_node = unsafe { NodePtr::replace(leaf_node_ptr, new_leaf_node) };
                 return Ok(InsertResult {
                     existing_leaf: Some(old_leaf_node),
+                    leaf_node_ptr,
                     // Because we replaced the leaf instead of creating a new leaf, we don't have to
                     // write back to the parent. In this case, the root is guaranteed to be
                     // unchanged, even if the old leaf was the root.
@@ -205,45 +908,62 @@ where
                 });
             }
 
-            let mut new_n4 = InnerNode4::empty();
-            let prefix_size = leaf_node.key_ref().as_bytes()[key_bytes_used..]
+            let key_bytes = key.as_bytes();
+            let leaf_key_bytes = leaf_node.key_ref().as_bytes();
+
+            let prefix_size = leaf_key_bytes[key_bytes_used..]
                 .iter()
-                .zip(key.as_bytes()[key_bytes_used..].iter())
+                .zip(key_bytes[key_bytes_used..].iter())
                 .take_while(|(k1, k2)| k1 == k2)
                 .count();
-            new_n4
-                .header
-                .extend_prefix(&key.as_bytes()[key_bytes_used..(key_bytes_used + prefix_size)]);
-            key_bytes_used += prefix_size;
+            let new_key_bytes_used = key_bytes_used + prefix_size;
 
-            if key_bytes_used >= key.as_bytes().len()
-                || key_bytes_used >= leaf_node.key_ref().as_bytes().len()
-            {
+            if new_key_bytes_used >= key_bytes.len() || new_key_bytes_used >= leaf_key_bytes.len() {
                 // then the key has insufficient bytes to be unique. It must be
                 // a prefix of an existing key OR an existing key is a prefix of it
 
-                return Err(InsertPrefixError {
-                    byte_repr: key.as_bytes().into(),
-                });
+                let err = InsertPrefixError {
+                    byte_repr: key_bytes.into(),
+                };
+                return Err((err, new_leaf));
             }
 
-            let new_leaf_key_byte = key.as_bytes()[key_bytes_used];
-            let new_leaf_pointer = NodePtr::allocate_node_ptr(LeafNode::new(key, value));
+            let mut new_n4 = InnerNode4::empty();
+            new_n4
+                .header
+                .extend_prefix(&key_bytes[key_bytes_used..new_key_bytes_used]);
+
+            let new_leaf_key_byte = key_bytes[new_key_bytes_used];
+            let existing_leaf_key_byte = leaf_key_bytes[new_key_bytes_used];
+            let new_leaf_pointer = new_leaf.allocate(alloc);
 
-            new_n4.write_child(
-                leaf_node.key_ref().as_bytes()[key_bytes_used],
-                leaf_node_ptr.to_opaque(),
-            );
-            new_n4.write_child(new_leaf_key_byte, new_leaf_pointer.to_opaque());
+            new_n4.write_child(existing_leaf_key_byte, leaf_node_ptr.to_opaque());
+            new_n4.write_child(new_leaf_key_byte, new_leaf_pointer.ptr().to_opaque());
 
-            NodePtr::allocate_node_ptr(new_n4).to_opaque()
+            let new_n4 = UnlinkedNode::allocate(new_n4, alloc);
+
+            let new_leaf_ptr = new_leaf_pointer.link();
+            (new_n4.link().to_opaque(), new_leaf_ptr)
         },
         InsertSearchResultType::IntoExisting { inner_node_ptr } => {
-            write_new_child_in_existing_node(
-                inner_node_ptr,
-                LeafNode::new(key, value),
-                key_bytes_used,
-            )
+            // PANIC SAFETY: The search for the insert point guarantees that the key has a
+            // byte at `key_bytes_used`, otherwise it would have returned an
+            // error.
+            let new_leaf_key_byte = key.as_bytes()[key_bytes_used];
+
+            let new_leaf_pointer = new_leaf.allocate(alloc);
+            // SAFETY: The inner node is part of the tree, which is uniquely accessed by the
+            // safety requirements of the containing function.
+            let new_inner_node = unsafe {
+                write_new_child_in_existing_node(
+                    inner_node_ptr,
+                    new_leaf_pointer.ptr().to_opaque(),
+                    new_leaf_key_byte,
+                    alloc,
+                )
+            };
+
+            (new_inner_node, new_leaf_pointer.link())
         },
     };
 
@@ -258,6 +978,7 @@ where
         //   2. Or some parent of the parent was root, in which case it was unchanged
         Ok(InsertResult {
             existing_leaf: None,
+            leaf_node_ptr: new_leaf_ptr,
             new_root: root,
         })
     } else {
@@ -265,16 +986,199 @@ where
         // occurred at the root, in which case return the new inner node as root
         Ok(InsertResult {
             existing_leaf: None,
+            leaf_node_ptr: new_leaf_ptr,
             new_root: new_inner_node,
         })
     }
 }
 
+/// Write a new child to the given inner node at the specified key byte,
+/// growing the inner node if it is full, and return the inner node which now
+/// holds the child.
+///
+/// If this unwinds, the given inner node is unchanged, and the new child is not
+/// linked into it.
+///
+/// # Safety
+///
+///  - The `inner_node_ptr` must be a unique pointer to the node, and must not
+///    be accessed concurrently. If the node is grown, it is deallocated.
+///  - The inner node must have been allocated by the given allocator.
+unsafe fn write_new_child_in_existing_node<K, V, A: Allocator>(
+    inner_node_ptr: OpaqueNodePtr<K, V>,
+    new_child: OpaqueNodePtr<K, V>,
+    new_child_key_byte: u8,
+    alloc: &A,
+) -> OpaqueNodePtr<K, V> {
+    /// # Safety
+    ///
+    ///  - The `inner_node_ptr` must be a unique pointer to the node, and must
+    ///    not be accessed concurrently.
+    ///  - The inner node must have been allocated by the given allocator.
+    unsafe fn write_new_child_in_existing_inner_node<K, V, N, A>(
+        inner_node_ptr: NodePtr<N>,
+        new_child: OpaqueNodePtr<K, V>,
+        new_child_key_byte: u8,
+        alloc: &A,
+    ) -> OpaqueNodePtr<K, V>
+    where
+        N: InnerNode<Key = K, Value = V>,
+        A: Allocator,
+    {
+        // SAFETY: The `inner_node` reference lasts only for the duration of this
+        // function, and the node will not be read or written via any other source
+        // because of the safety requirements on the containing function.
+        let inner_node = unsafe { inner_node_ptr.as_mut() };
+        if inner_node.is_full() {
+            // we will create a new node of the next larger type and copy all the
+            // children over.
+
+            let mut new_node = inner_node.grow();
+            new_node.write_child(new_child_key_byte, new_child);
+
+            // The allocation succeeded, nothing after this point can unwind before the
+            // new node is part of the tree.
+            let new_inner_node = NodePtr::allocate_node_ptr_in(new_node, alloc).to_opaque();
+
+            // SAFETY: The `deallocate_node` function is only called a
+            // single time. The uniqueness requirement is passed up to the
+            // containing function safety requirements.
+            unsafe {
+                #[allow(clippy::drop_ref)]
+                drop(inner_node);
+                drop(NodePtr::deallocate_node_ptr_in(inner_node_ptr, alloc));
+            };
+
+            new_inner_node
+        } else {
+            inner_node.write_child(new_child_key_byte, new_child);
+
+            inner_node_ptr.to_opaque()
+        }
+    }
+
+    // SAFETY: Covered by the safety requirements of the containing function
+    unsafe {
+        match inner_node_ptr.to_node_ptr() {
+            ConcreteNodePtr::Node4(inner_ptr) => write_new_child_in_existing_inner_node(
+                inner_ptr,
+                new_child,
+                new_child_key_byte,
+                alloc,
+            ),
+            ConcreteNodePtr::Node16(inner_ptr) => write_new_child_in_existing_inner_node(
+                inner_ptr,
+                new_child,
+                new_child_key_byte,
+                alloc,
+            ),
+            ConcreteNodePtr::Node48(inner_ptr) => write_new_child_in_existing_inner_node(
+                inner_ptr,
+                new_child,
+                new_child_key_byte,
+                alloc,
+            ),
+            ConcreteNodePtr::Node256(inner_ptr) => write_new_child_in_existing_inner_node(
+                inner_ptr,
+                new_child,
+                new_child_key_byte,
+                alloc,
+            ),
+            ConcreteNodePtr::LeafNode(_) => {
+                panic!("Cannot have insert into existing with leaf node")
+            },
+        }
+    }
+}
+
+/// Write a new child node to an inner node at the specified key byte.
+pub(crate) fn parent_write_child<K, V>(
+    parent_inner_node: OpaqueNodePtr<K, V>,
+    key_byte: u8,
+    new_child: OpaqueNodePtr<K, V>,
+) {
+    fn write_inner_node<K, V, N>(
+        parent_inner_node: NodePtr<N>,
+        key_byte: u8,
+        new_child: OpaqueNodePtr<K, V>,
+    ) where
+        N: InnerNode<Key = K, Value = V>,
+    {
+        // SAFETY: The lifetime produced from this is bounded to this scope and does not
+        // escape. Further, no other code mutates the node referenced, which is further
+        // enforced the "no concurrent reads or writes" requirement on the
+        // `maximum_unchecked` function.
+        let parent_node = unsafe { parent_inner_node.as_mut() };
+
+        parent_node.write_child(key_byte, new_child);
+    }
+
+    match parent_inner_node.to_node_ptr() {
+        ConcreteNodePtr::Node4(inner_ptr) => write_inner_node(inner_ptr, key_byte, new_child),
+        ConcreteNodePtr::Node16(inner_ptr) => write_inner_node(inner_ptr, key_byte, new_child),
+        ConcreteNodePtr::Node48(inner_ptr) => write_inner_node(inner_ptr, key_byte, new_child),
+        ConcreteNodePtr::Node256(inner_ptr) => write_inner_node(inner_ptr, key_byte, new_child),
+        ConcreteNodePtr::LeafNode(_) => {
+            panic!("A leaf pointer cannot be the parent of another node")
+        },
+    }
+}
+
+/// A newly allocated node which is not yet reachable from the tree.
+///
+/// If this guard is dropped before [`UnlinkedNode::link`] is called, for
+/// example during an unwind in the middle of an insert, the node is
+/// deallocated again so that the allocation is not leaked.
+struct UnlinkedNode<'a, N: Node, A: Allocator> {
+    ptr: Option<NodePtr<N>>,
+    alloc: &'a A,
+}
+
+impl<'a, N: Node, A: Allocator> UnlinkedNode<'a, N, A> {
+    /// Allocate the given node with the given allocator and guard the
+    /// allocation.
+    fn allocate(node: N, alloc: &'a A) -> Self {
+        UnlinkedNode {
+            ptr: Some(NodePtr::allocate_node_ptr_in(node, alloc)),
+            alloc,
+        }
+    }
+
+    /// Return a pointer to the guarded node, without releasing the guard.
+    fn ptr(&self) -> NodePtr<N> {
+        // PANIC SAFETY: The pointer is only taken in `link`, which consumes the
+        // guard.
+        self.ptr.unwrap()
+    }
+
+    /// Release the guard, because the node is now (or is about to be)
+    /// reachable from the tree.
+    fn link(mut self) -> NodePtr<N> {
+        // PANIC SAFETY: The pointer is only taken here, which consumes the
+        // guard.
+        self.ptr.take().unwrap()
+    }
+}
+
+impl<'a, N: Node, A: Allocator> Drop for UnlinkedNode<'a, N, A> {
+    fn drop(&mut self) {
+        if let Some(ptr) = self.ptr.take() {
+            // SAFETY: The node was allocated with this allocator by
+            // `UnlinkedNode::allocate`, or by the caller of `NewLeaf::allocate`, and was
+            // never linked into the tree, so this is the only place where it is
+            // deallocated.
+            drop(unsafe { NodePtr::deallocate_node_ptr_in(ptr, self.alloc) });
+        }
+    }
+}
+
 /// The results of a successful tree insert
 #[derive(Debug)]
 pub struct InsertResult<K, V> {
     /// The existing leaf referenced by the insert key, if present
     pub existing_leaf: Option<LeafNode<K, V>>,
+    /// The leaf holding the inserted key and value
+    pub leaf_node_ptr: NodePtr<LeafNode<K, V>>,
     /// The new tree root after the successful insert
     pub new_root: OpaqueNodePtr<K, V>,
 }
@@ -405,14 +1309,81 @@ pub unsafe fn search_for_insert_point<K, V>(
 where
     K: AsBytes,
 {
-    fn test_prefix_identify_insert<K, V, N>(
+    // SAFETY: The safety requirements are covered by the containing function
+    unsafe { search_for_insert_point_recorded(root, key, &mut ()) }
+}
+
+/// Perform an iterative search for the insert point for the given key,
+/// reporting every step of the search to the given recorder.
+///
+/// # Safety
+///
+///  - The `root` [`OpaqueNodePtr`] must be a unique pointer to the underlying
+///    tree
+///  - This function cannot be called concurrently to any reads or writes of the
+///    `root` node or any child node of `root`. This function will arbitrarily
+///    read or write to any child in the given tree.
+///
+/// # Errors
+///
+/// If the given `key` is a prefix of an existing key, this function will return
+/// an error.
+unsafe fn search_for_insert_point_recorded<Q, K, V, R>(
+    root: OpaqueNodePtr<K, V>,
+    key: &Q,
+    recorder: &mut R,
+) -> Result<InsertSearchResult<K, V>, InsertPrefixError>
+where
+    K: AsBytes,
+    Q: AsBytes + ?Sized,
+    R: SearchRecorder,
+{
+    // SAFETY: The safety requirements are covered by the containing function
+    unsafe { search_for_insert_point_from_recorded(root, 0, None, key, recorder) }
+}
+
+/// Perform an iterative search for the insert point for the given key,
+/// starting at the given node of the tree instead of its root.
+///
+/// The `start_node` must be on the search path of `key`, it must sit after
+/// `start_depth` key bytes, and `start_parent` must be its parent and the key
+/// byte of the `start_node` in it, or `None` if it is the root.
+///
+/// # Safety
+///
+///  - The `start_node` [`OpaqueNodePtr`] must be a unique pointer to the
+///    underlying tree
+///  - This function cannot be called concurrently to any reads or writes of the
+///    `start_node` node or any child node of `start_node`. This function will
+///    arbitrarily read or write to any child in the given tree.
+///
+/// # Errors
+///
+/// If the given `key` is a prefix of an existing key, this function will return
+/// an error.
+unsafe fn search_for_insert_point_from_recorded<Q, K, V, R>(
+    start_node: OpaqueNodePtr<K, V>,
+    start_depth: usize,
+    start_parent: Option<(OpaqueNodePtr<K, V>, u8)>,
+    key: &Q,
+    recorder: &mut R,
+) -> Result<InsertSearchResult<K, V>, InsertPrefixError>
+where
+    K: AsBytes,
+    Q: AsBytes + ?Sized,
+    R: SearchRecorder,
+{
+    fn test_prefix_identify_insert<Q, K, V, N, R>(
         inner_ptr: NodePtr<N>,
-        key: &K,
+        key: &Q,
         current_depth: &mut usize,
+        recorder: &mut R,
     ) -> Result<ControlFlow<usize, Option<OpaqueNodePtr<K, V>>>, InsertPrefixError>
     where
         N: InnerNode<Key = K, Value = V>,
         K: AsBytes,
+        Q: AsBytes + ?Sized,
+        R: SearchRecorder,
     {
         // SAFETY: The lifetime produced from this is bounded to this scope and does not
         // escape. Further, no other code mutates the node referenced, which is further
@@ -420,7 +1391,16 @@ where
         // `search_unchecked` function.
         let inner_node = unsafe { inner_ptr.as_ref() };
         let header = inner_node.header();
-        let matched_prefix_size = header.match_prefix(&key.as_bytes()[*current_depth..]);
+        let remaining_key = &key.as_bytes()[*current_depth..];
+        // SAFETY: The node is at the current depth, and the tree is not mutated during
+        // the search, see above.
+        let matched_prefix_size =
+            unsafe { inner_node.match_full_prefix(remaining_key, *current_depth) };
+        recorder.record_prefix_comparison(lookup::prefix_bytes_compared(
+            matched_prefix_size,
+            header.prefix_size(),
+            remaining_key.len(),
+        ));
         if matched_prefix_size != header.prefix_size() {
             return Ok(ControlFlow::Break(matched_prefix_size));
         }
@@ -439,28 +1419,31 @@ where
             });
         };
 
-        Ok(ControlFlow::Continue(
-            inner_node.lookup_child(next_key_fragment),
-        ))
+        let child_lookup = inner_node.lookup_child(next_key_fragment);
+        recorder.record_child_lookup(N::TYPE.uses_simd_child_lookup());
+
+        Ok(ControlFlow::Continue(child_lookup))
     }
 
-    let mut current_parent = None;
-    let mut current_node = root;
-    let mut current_depth = 0;
+    let mut current_parent = start_parent;
+    let mut current_node = start_node;
+    let mut current_depth = start_depth;
 
     loop {
+        recorder.record_node(current_node.node_type());
+
         let lookup_result = match current_node.to_node_ptr() {
             ConcreteNodePtr::Node4(inner_ptr) => {
-                test_prefix_identify_insert(inner_ptr, key, &mut current_depth)
+                test_prefix_identify_insert(inner_ptr, key, &mut current_depth, recorder)
             },
             ConcreteNodePtr::Node16(inner_ptr) => {
-                test_prefix_identify_insert(inner_ptr, key, &mut current_depth)
+                test_prefix_identify_insert(inner_ptr, key, &mut current_depth, recorder)
             },
             ConcreteNodePtr::Node48(inner_ptr) => {
-                test_prefix_identify_insert(inner_ptr, key, &mut current_depth)
+                test_prefix_identify_insert(inner_ptr, key, &mut current_depth, recorder)
             },
             ConcreteNodePtr::Node256(inner_ptr) => {
-                test_prefix_identify_insert(inner_ptr, key, &mut current_depth)
+                test_prefix_identify_insert(inner_ptr, key, &mut current_depth, recorder)
             },
             ConcreteNodePtr::LeafNode(leaf_node_ptr) => {
                 return Ok(InsertSearchResult {
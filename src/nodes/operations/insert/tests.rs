@@ -1,7 +1,14 @@
 use crate::{
-    deallocate_tree, insert_unchecked, search_unchecked,
+    deallocate_tree, insert_instrumented_unchecked, insert_unchecked, search_unchecked,
     tests_common::{generate_keys_skewed, setup_tree_from_entries},
-    InnerNode, InnerNode4, InsertPrefixError, LeafNode, NodePtr, NodeType,
+    visitor::WellFormedChecker,
+    AsBytes, ConcreteNodePtr, InnerNode, InnerNode4, InsertPrefixError, LeafNode, NodePtr,
+    NodeType,
+};
+use std::{
+    cell::Cell,
+    panic::{self, AssertUnwindSafe},
+    rc::Rc,
 };
 
 #[test]
@@ -202,6 +209,42 @@ fn insert_split_prefix_at_implicit_byte() {
     unsafe { deallocate_tree(current_root) };
 }
 
+#[test]
+fn insert_split_prefix_after_stored_bytes() {
+    let keys: Vec<Box<[u8]>> = (1..=2).map(|b| (0..20).chain([b]).collect()).collect();
+    let mut root = setup_tree_from_entries(keys.iter().cloned().zip(0..));
+
+    // The new key diverges from the prefix of the root at a byte which is not
+    // stored in the header
+    let new_key: Box<[u8]> = (0..12).chain([100]).collect();
+    root = unsafe { insert_unchecked(root, new_key.clone(), 2) }
+        .unwrap()
+        .new_root;
+
+    let ConcreteNodePtr::Node4(new_root) = root.to_node_ptr() else {
+        panic!("root should be a new node 4");
+    };
+    let new_root = unsafe { new_root.as_ref() };
+    assert_eq!(new_root.header().prefix_size(), 12);
+    assert_eq!(new_root.header().read_prefix(), &[0, 1, 2, 3, 4, 5, 6, 7]);
+
+    // The split node keeps the bytes after the mismatched byte, read from a leaf
+    let split_node = new_root.lookup_child(12).unwrap();
+    let split_header = unsafe { split_node.header_mut() }.unwrap();
+    assert_eq!(split_header.prefix_size(), 7);
+    assert_eq!(split_header.read_prefix(), &[13, 14, 15, 16, 17, 18, 19]);
+
+    unsafe { WellFormedChecker::check_tree(root) }.unwrap();
+    for (value, key) in keys.iter().chain([&new_key]).enumerate() {
+        assert_eq!(
+            unsafe { search_unchecked(root, key).unwrap().read().value_ref() },
+            &value
+        );
+    }
+
+    unsafe { deallocate_tree(root) };
+}
+
 #[test]
 fn insert_fails_new_key_prefix_of_existing_entry() {
     let mut current_root =
@@ -358,3 +401,234 @@ fn insert_existing_key_overwrite() {
 
     unsafe { deallocate_tree(current_root) }
 }
+
+thread_local! {
+    static AS_BYTES_CALLS_BEFORE_PANIC: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// A key type whose [`AsBytes`] implementation panics after a configurable
+/// number of calls.
+#[derive(Debug, Clone)]
+struct PanickingKey(Box<[u8]>);
+
+impl AsBytes for PanickingKey {
+    fn as_bytes(&self) -> &[u8] {
+        AS_BYTES_CALLS_BEFORE_PANIC.with(|calls| match calls.get() {
+            Some(0) => {
+                calls.set(None);
+                panic!("injected panic in `as_bytes`");
+            },
+            Some(remaining) => calls.set(Some(remaining - 1)),
+            None => {},
+        });
+
+        &self.0
+    }
+}
+
+#[test]
+fn insert_panic_in_as_bytes_leaves_tree_unchanged() {
+    let mut current_root = NodePtr::allocate_node_ptr(LeafNode::new(
+        PanickingKey(Box::new([1, 1, 1, 0])),
+        Rc::new(()),
+    ))
+    .to_opaque();
+    let mut expected_keys = vec![[1, 1, 1, 0]];
+
+    for key in [[1, 1, 1, 1], [1, 1, 1, 2], [1, 1, 1, 3], [2, 2, 2, 2]] {
+        current_root = unsafe {
+            insert_unchecked(current_root, PanickingKey(Box::new(key)), Rc::new(()))
+                .unwrap()
+                .new_root
+        };
+        expected_keys.push(key);
+    }
+
+    // Each of these keys hits a different insert case: growing a full node,
+    // splitting a mismatched prefix, splitting a leaf, writing into an existing
+    // node with space, and overwriting an existing leaf.
+    for new_key in [
+        [1, 1, 1, 9],
+        [1, 1, 9, 9],
+        [2, 2, 3, 3],
+        [3, 3, 3, 3],
+        [1, 1, 1, 2],
+    ] {
+        let num_nodes_before = unsafe { WellFormedChecker::check_tree(current_root).unwrap() };
+
+        for calls_before_panic in 0.. {
+            let value = Rc::new(());
+            let insert_value = Rc::clone(&value);
+
+            AS_BYTES_CALLS_BEFORE_PANIC.with(|calls| calls.set(Some(calls_before_panic)));
+            let result = panic::catch_unwind(AssertUnwindSafe(|| unsafe {
+                insert_unchecked(current_root, PanickingKey(Box::new(new_key)), insert_value)
+            }));
+            AS_BYTES_CALLS_BEFORE_PANIC.with(|calls| calls.set(None));
+
+            match result {
+                Ok(insert_result) => {
+                    current_root = insert_result.unwrap().new_root;
+                    break;
+                },
+                Err(_) => {
+                    // The key and value of the failed insert were dropped, and the tree is
+                    // exactly as it was before.
+                    assert_eq!(Rc::strong_count(&value), 1);
+                    assert_eq!(
+                        unsafe { WellFormedChecker::check_tree(current_root).unwrap() },
+                        num_nodes_before
+                    );
+                    for key in &expected_keys {
+                        assert!(unsafe {
+                            search_unchecked(current_root, &PanickingKey(Box::new(*key))).is_some()
+                        });
+                    }
+                    if !expected_keys.contains(&new_key) {
+                        assert!(unsafe {
+                            search_unchecked(current_root, &PanickingKey(Box::new(new_key)))
+                                .is_none()
+                        });
+                    }
+                },
+            }
+        }
+
+        if !expected_keys.contains(&new_key) {
+            expected_keys.push(new_key);
+        }
+    }
+
+    for key in &expected_keys {
+        assert!(unsafe { search_unchecked(current_root, &PanickingKey(Box::new(*key))).is_some() });
+    }
+
+    unsafe { deallocate_tree(current_root) }
+}
+
+/// Insert `new_key` into a tree built from `keys`, failing each allocation the
+/// insert makes in turn. Checks that the tree is unchanged after every failure
+/// and returns the number of injected failures.
+#[cfg(feature = "alloc-failure-injection")]
+fn insert_with_each_allocation_failing(keys: &[Box<[u8]>], new_key: &[u8]) -> usize {
+    use crate::tests_common::alloc_failure::fail_each_allocation;
+
+    let mut root = setup_tree_from_entries(
+        keys.iter()
+            .cloned()
+            .enumerate()
+            .map(|(idx, key)| (key, idx)),
+    );
+    let num_nodes_before = unsafe { WellFormedChecker::check_tree(root).unwrap() };
+
+    let num_failures = fail_each_allocation(
+        &mut root,
+        |root| {
+            *root = unsafe {
+                insert_unchecked(*root, Box::from(new_key), keys.len())
+                    .unwrap()
+                    .new_root
+            };
+        },
+        |root, _| {
+            assert_eq!(
+                unsafe { WellFormedChecker::check_tree(*root).unwrap() },
+                num_nodes_before
+            );
+            for (idx, key) in keys.iter().enumerate() {
+                assert_eq!(
+                    unsafe { search_unchecked(*root, key).unwrap().read().value_ref() },
+                    &idx
+                );
+            }
+            assert!(unsafe { search_unchecked(*root, new_key).is_none() });
+        },
+    );
+
+    assert_eq!(
+        unsafe { search_unchecked(root, new_key).unwrap().read().value_ref() },
+        &keys.len()
+    );
+    unsafe { deallocate_tree(root) };
+
+    num_failures
+}
+
+#[test]
+#[cfg(feature = "alloc-failure-injection")]
+fn insert_grow_with_allocation_failures() {
+    for num_children in [4u8, 16, 48] {
+        let keys: Vec<Box<[u8]>> = (0..num_children).map(|b| Box::from([1, b])).collect();
+
+        // One allocation for the new leaf, one for the grown node
+        assert_eq!(
+            insert_with_each_allocation_failing(&keys, &[1, num_children]),
+            2
+        );
+    }
+}
+
+#[test]
+#[cfg(feature = "alloc-failure-injection")]
+fn insert_split_long_prefix_with_allocation_failures() {
+    let keys: Vec<Box<[u8]>> = (1..=2)
+        .map(|b| [7; 20].into_iter().chain([b]).collect())
+        .collect();
+    let new_key: Vec<u8> = [7; 10].into_iter().chain([8; 11]).collect();
+
+    // The new leaf and the new inner node, prefixes are stored inline
+    assert_eq!(insert_with_each_allocation_failing(&keys, &new_key), 2);
+}
+
+#[test]
+#[cfg(feature = "alloc-failure-injection")]
+fn insert_split_leaf_with_allocation_failures() {
+    let keys: Vec<Box<[u8]>> = vec![[5; 30].into_iter().chain([1]).collect()];
+    let new_key: Vec<u8> = [5; 30].into_iter().chain([2]).collect();
+
+    // The new leaf and the new inner node
+    assert_eq!(insert_with_each_allocation_failing(&keys, &new_key), 2);
+}
+
+#[test]
+fn instrumented_insert_counts_search_for_insert_point() {
+    let keys: [Box<[u8]>; 2] = [Box::new([1, 2, 3, 4]), Box::new([1, 2, 5, 6])];
+    let root = setup_tree_from_entries(keys.iter().cloned().zip(0..));
+
+    // Splits the leaf under byte `5` of the root
+    let (result, stats) =
+        unsafe { insert_instrumented_unchecked(root, Box::from([1, 2, 5, 7]), 2) };
+    let root = result.unwrap().new_root;
+    assert_eq!(stats.node4_visited, 1);
+    assert_eq!(stats.leaves_visited, 1);
+    assert_eq!(stats.prefix_bytes_compared, 2);
+    assert_eq!(stats.scalar_child_lookups, 1);
+    assert_eq!(stats.simd_child_lookups, 0);
+
+    // Mismatches on the root prefix
+    let (result, stats) =
+        unsafe { insert_instrumented_unchecked(root, Box::from([1, 3, 5, 7]), 3) };
+    let root = result.unwrap().new_root;
+    assert_eq!(stats.nodes_visited(), 1);
+    assert_eq!(stats.prefix_bytes_compared, 2);
+    assert_eq!(stats.scalar_child_lookups, 0);
+    assert_eq!(stats.simd_child_lookups, 0);
+
+    unsafe { deallocate_tree(root) };
+
+    // The child lookup of an `InnerNode16` uses SIMD instructions where they are
+    // available
+    let keys: Vec<Box<[u8]>> = (0..8).map(|last| Box::from([1, last, 0])).collect();
+    let root = setup_tree_from_entries(keys.into_iter().zip(0..));
+    let (result, stats) = unsafe { insert_instrumented_unchecked(root, Box::from([1, 5, 1]), 8) };
+    let root = result.unwrap().new_root;
+    let simd_enabled = cfg!(all(
+        any(target_arch = "x86", target_arch = "x86_64"),
+        target_feature = "sse2"
+    ));
+    assert_eq!(stats.node16_visited, 1);
+    assert_eq!(stats.simd_child_lookups, usize::from(simd_enabled));
+    assert_eq!(stats.scalar_child_lookups, usize::from(!simd_enabled));
+
+    unsafe { deallocate_tree(root) };
+}
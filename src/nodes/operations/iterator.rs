@@ -1,10 +1,11 @@
 use crate::{
-    ConcreteNodePtr, InnerNode, InnerNode256Iter, InnerNode48Iter, InnerNodeCompressedIter,
-    LeafNode, NodePtr, OpaqueNodePtr,
+    AsBytes, ConcreteNodePtr, InnerNode, InnerNode256Iter, InnerNode48Iter,
+    InnerNodeCompressedIter, LeafNode, NodePtr, OpaqueNodePtr,
 };
 use std::{
     collections::VecDeque,
     iter::{self, FusedIterator},
+    ops::Bound,
 };
 
 /// An iterator over all the leaves in a tree.
@@ -50,6 +51,62 @@ impl<K, V> TreeIterator<K, V> {
             }
         }
     }
+
+    /// Create a new iterator over the leaves descended from the given node,
+    /// skipping every subtree whose keys are all before `start` or all after
+    /// `end`.
+    ///
+    /// A `None` bound is unbounded on that side. The iterator seeks to the
+    /// bounds using the compressed paths of the inner nodes, so the leaves on
+    /// the path to each bound may still be outside of it. Every other leaf
+    /// returned is within the bounds, so the caller only needs to check the
+    /// keys at the start and end of the iteration.
+    ///
+    /// # Safety
+    ///
+    /// See safety requirements on type [`InnerNodeTreeIterator`].
+    pub(crate) unsafe fn seek_range(
+        root: OpaqueNodePtr<K, V>,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+    ) -> Self
+    where
+        K: AsBytes,
+    {
+        let mut trie_range_iter = InnerNodeTreeIterator {
+            node_iters: VecDeque::new(),
+        };
+        let mut current_node = root;
+        let mut current_depth = 0;
+
+        loop {
+            // SAFETY: Safety requirements are covered by the containing function
+            let next_step = unsafe {
+                match current_node.to_node_ptr() {
+                    ConcreteNodePtr::Node4(inner) => {
+                        trie_range_iter.seek_shared(inner, start, end, &mut current_depth)
+                    },
+                    ConcreteNodePtr::Node16(inner) => {
+                        trie_range_iter.seek_shared(inner, start, end, &mut current_depth)
+                    },
+                    ConcreteNodePtr::Node48(inner) => {
+                        trie_range_iter.seek_shared(inner, start, end, &mut current_depth)
+                    },
+                    ConcreteNodePtr::Node256(inner) => {
+                        trie_range_iter.seek_shared(inner, start, end, &mut current_depth)
+                    },
+                    ConcreteNodePtr::LeafNode(leaf_node_ptr) => {
+                        return TreeIterator::Singleton(iter::once(leaf_node_ptr));
+                    },
+                }
+            };
+
+            match next_step {
+                Some(child) => current_node = child,
+                None => return TreeIterator::InnerNode(trie_range_iter),
+            }
+        }
+    }
 }
 
 impl<K, V> Iterator for TreeIterator<K, V> {
@@ -61,6 +118,13 @@ impl<K, V> Iterator for TreeIterator<K, V> {
             TreeIterator::InnerNode(ref mut inner) => inner.next(),
         }
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            TreeIterator::Singleton(ref inner) => inner.size_hint(),
+            TreeIterator::InnerNode(ref inner) => inner.size_hint(),
+        }
+    }
 }
 
 impl<K, V> DoubleEndedIterator for TreeIterator<K, V> {
@@ -133,6 +197,287 @@ impl<K, V> InnerNodeTreeIterator<K, V> {
     }
 }
 
+/// Where the keys below an inner node are relative to one bound of a range,
+/// after comparing the compressed path of the node to the bound.
+#[derive(Debug, Clone, Copy)]
+enum PathOrdering {
+    /// Every key below the node is before the bound.
+    Less,
+    /// Every key below the node is after the bound.
+    Greater,
+    /// The keys below the node match the bound up to the end of the compressed
+    /// path, and the child for the given key byte leads towards the bound.
+    Child(u8),
+}
+
+impl PathOrdering {
+    /// Compare the compressed path of an inner node at the given depth to the
+    /// bound.
+    fn new(prefix: &[u8], bound: &[u8], depth: usize) -> Self {
+        let remaining_bound = &bound[depth..];
+        let matched_prefix_size = prefix
+            .iter()
+            .zip(remaining_bound)
+            .take_while(|(a, b)| **a == **b)
+            .count();
+
+        match (
+            prefix.get(matched_prefix_size),
+            remaining_bound.get(matched_prefix_size),
+        ) {
+            (Some(prefix_byte), Some(bound_byte)) if prefix_byte < bound_byte => PathOrdering::Less,
+            (None, Some(bound_byte)) => PathOrdering::Child(*bound_byte),
+            // The bound is exhausted, so it is a strict prefix of every key below the
+            // node, or the path diverges above the bound
+            _ => PathOrdering::Greater,
+        }
+    }
+}
+
+impl<K: AsBytes, V> InnerNodeTreeIterator<K, V> {
+    /// Compare an inner node which is on the path to both bounds, and either
+    /// return the child that is also on both paths, or push the iterators for
+    /// the keys between the bounds.
+    ///
+    /// # Safety
+    ///
+    /// See safety requirements on type [`InnerNodeTreeIterator`].
+    unsafe fn seek_shared<N>(
+        &mut self,
+        inner: NodePtr<N>,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        depth: &mut usize,
+    ) -> Option<OpaqueNodePtr<K, V>>
+    where
+        N: InnerNode<Key = K, Value = V>,
+    {
+        // SAFETY: The lifetime of the returned reference is restricted to this
+        // function, and no mutation of the tree happens while the iterator is live.
+        let node = unsafe { inner.as_ref() };
+        // SAFETY: The node is at the current depth, and the tree is not mutated while
+        // the iterator is live.
+        let prefix = unsafe { node.read_full_prefix(*depth) };
+        let start_ordering = start.map_or(PathOrdering::Greater, |start| {
+            PathOrdering::new(prefix, start, *depth)
+        });
+        let end_ordering = end.map_or(PathOrdering::Less, |end| {
+            PathOrdering::new(prefix, end, *depth)
+        });
+        *depth += prefix.len() + 1;
+
+        let (lower, front_child) = match start_ordering {
+            PathOrdering::Less => return None,
+            PathOrdering::Greater => (Bound::Unbounded, None),
+            PathOrdering::Child(start_byte) => {
+                match node.lookup_child(start_byte) {
+                    Some(child) if !child.is::<LeafNode<K, V>>() => {
+                        (Bound::Excluded(start_byte), Some(child))
+                    },
+                    // A leaf at the start byte is returned by the iterator for this node
+                    _ => (Bound::Included(start_byte), None),
+                }
+            },
+        };
+        let (upper, back_child) = match end_ordering {
+            PathOrdering::Greater => return None,
+            PathOrdering::Less => (Bound::Unbounded, None),
+            PathOrdering::Child(end_byte) => match node.lookup_child(end_byte) {
+                Some(child) if !child.is::<LeafNode<K, V>>() => {
+                    (Bound::Excluded(end_byte), Some(child))
+                },
+                // A leaf at the end byte is returned by the iterator for this node
+                _ => (Bound::Included(end_byte), None),
+            },
+        };
+
+        if let (PathOrdering::Child(start_byte), PathOrdering::Child(end_byte)) =
+            (start_ordering, end_ordering)
+        {
+            if start_byte > end_byte {
+                return None;
+            }
+
+            if start_byte == end_byte && front_child.is_some() {
+                // Both bounds continue into the same inner node
+                return front_child;
+            }
+        }
+
+        // SAFETY: The lifetime of the returned iterator does not overlap with any
+        // mutation of the tree, see the safety requirements.
+        self.node_iters
+            .push_back(unsafe { node.range((lower, upper)) }.into());
+
+        if let (Some(child), Some(start)) = (front_child, start) {
+            // SAFETY: Covered by the safety requirements of this function
+            unsafe { self.seek_front(child, start, *depth) };
+        }
+        if let (Some(child), Some(end)) = (back_child, end) {
+            // SAFETY: Covered by the safety requirements of this function
+            unsafe { self.seek_back(child, end, *depth) };
+        }
+
+        None
+    }
+
+    /// Push the iterators for the keys below the given node which are after
+    /// the start bound, deepest first.
+    ///
+    /// # Safety
+    ///
+    /// See safety requirements on type [`InnerNodeTreeIterator`].
+    unsafe fn seek_front(&mut self, node: OpaqueNodePtr<K, V>, start: &[u8], depth: usize) {
+        let mut current_node = Some(node);
+        let mut current_depth = depth;
+
+        while let Some(node) = current_node {
+            // SAFETY: Covered by the safety requirements of this function
+            current_node = unsafe {
+                match node.to_node_ptr() {
+                    ConcreteNodePtr::Node4(inner) => {
+                        self.seek_front_step(inner, start, &mut current_depth)
+                    },
+                    ConcreteNodePtr::Node16(inner) => {
+                        self.seek_front_step(inner, start, &mut current_depth)
+                    },
+                    ConcreteNodePtr::Node48(inner) => {
+                        self.seek_front_step(inner, start, &mut current_depth)
+                    },
+                    ConcreteNodePtr::Node256(inner) => {
+                        self.seek_front_step(inner, start, &mut current_depth)
+                    },
+                    ConcreteNodePtr::LeafNode(_) => {
+                        unreachable!("only inner nodes are sought into")
+                    },
+                }
+            };
+        }
+    }
+
+    /// Push the iterator for the children of the given node which are after
+    /// the start bound, and return the child that leads towards the bound.
+    ///
+    /// # Safety
+    ///
+    /// See safety requirements on type [`InnerNodeTreeIterator`].
+    unsafe fn seek_front_step<N>(
+        &mut self,
+        inner: NodePtr<N>,
+        start: &[u8],
+        depth: &mut usize,
+    ) -> Option<OpaqueNodePtr<K, V>>
+    where
+        N: InnerNode<Key = K, Value = V>,
+    {
+        // SAFETY: The lifetime of the returned reference is restricted to this
+        // function, and no mutation of the tree happens while the iterator is live.
+        let node = unsafe { inner.as_ref() };
+        // SAFETY: The node is at the current depth, and the tree is not mutated while
+        // the iterator is live.
+        let prefix = unsafe { node.read_full_prefix(*depth) };
+
+        let (child_range, next_child) = match PathOrdering::new(prefix, start, *depth) {
+            PathOrdering::Less => return None,
+            PathOrdering::Greater => ((Bound::Unbounded, Bound::Unbounded), None),
+            PathOrdering::Child(start_byte) => {
+                *depth += prefix.len() + 1;
+                match node.lookup_child(start_byte) {
+                    Some(child) if !child.is::<LeafNode<K, V>>() => {
+                        ((Bound::Excluded(start_byte), Bound::Unbounded), Some(child))
+                    },
+                    // A leaf at the start byte is returned by the iterator for this node
+                    _ => ((Bound::Included(start_byte), Bound::Unbounded), None),
+                }
+            },
+        };
+
+        // SAFETY: The lifetime of the returned iterator does not overlap with any
+        // mutation of the tree, see the safety requirements.
+        self.node_iters
+            .push_front(unsafe { node.range(child_range) }.into());
+        next_child
+    }
+
+    /// Push the iterators for the keys below the given node which are before
+    /// the end bound, deepest last.
+    ///
+    /// # Safety
+    ///
+    /// See safety requirements on type [`InnerNodeTreeIterator`].
+    unsafe fn seek_back(&mut self, node: OpaqueNodePtr<K, V>, end: &[u8], depth: usize) {
+        let mut current_node = Some(node);
+        let mut current_depth = depth;
+
+        while let Some(node) = current_node {
+            // SAFETY: Covered by the safety requirements of this function
+            current_node = unsafe {
+                match node.to_node_ptr() {
+                    ConcreteNodePtr::Node4(inner) => {
+                        self.seek_back_step(inner, end, &mut current_depth)
+                    },
+                    ConcreteNodePtr::Node16(inner) => {
+                        self.seek_back_step(inner, end, &mut current_depth)
+                    },
+                    ConcreteNodePtr::Node48(inner) => {
+                        self.seek_back_step(inner, end, &mut current_depth)
+                    },
+                    ConcreteNodePtr::Node256(inner) => {
+                        self.seek_back_step(inner, end, &mut current_depth)
+                    },
+                    ConcreteNodePtr::LeafNode(_) => {
+                        unreachable!("only inner nodes are sought into")
+                    },
+                }
+            };
+        }
+    }
+
+    /// Push the iterator for the children of the given node which are before
+    /// the end bound, and return the child that leads towards the bound.
+    ///
+    /// # Safety
+    ///
+    /// See safety requirements on type [`InnerNodeTreeIterator`].
+    unsafe fn seek_back_step<N>(
+        &mut self,
+        inner: NodePtr<N>,
+        end: &[u8],
+        depth: &mut usize,
+    ) -> Option<OpaqueNodePtr<K, V>>
+    where
+        N: InnerNode<Key = K, Value = V>,
+    {
+        // SAFETY: The lifetime of the returned reference is restricted to this
+        // function, and no mutation of the tree happens while the iterator is live.
+        let node = unsafe { inner.as_ref() };
+        // SAFETY: The node is at the current depth, and the tree is not mutated while
+        // the iterator is live.
+        let prefix = unsafe { node.read_full_prefix(*depth) };
+
+        let (child_range, next_child) = match PathOrdering::new(prefix, end, *depth) {
+            PathOrdering::Greater => return None,
+            PathOrdering::Less => ((Bound::Unbounded, Bound::Unbounded), None),
+            PathOrdering::Child(end_byte) => {
+                *depth += prefix.len() + 1;
+                match node.lookup_child(end_byte) {
+                    Some(child) if !child.is::<LeafNode<K, V>>() => {
+                        ((Bound::Unbounded, Bound::Excluded(end_byte)), Some(child))
+                    },
+                    // A leaf at the end byte is returned by the iterator for this node
+                    _ => ((Bound::Unbounded, Bound::Included(end_byte)), None),
+                }
+            },
+        };
+
+        // SAFETY: The lifetime of the returned iterator does not overlap with any
+        // mutation of the tree, see the safety requirements.
+        self.node_iters
+            .push_back(unsafe { node.range(child_range) }.into());
+        next_child
+    }
+}
+
 impl<K, V> Iterator for InnerNodeTreeIterator<K, V> {
     type Item = NodePtr<LeafNode<K, V>>;
 
@@ -155,6 +500,19 @@ impl<K, V> Iterator for InnerNodeTreeIterator<K, V> {
 
         None
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // Every child that has not been visited yet has at least one leaf below it.
+        // The number of leaves below each child is not tracked, so there is no upper
+        // bound.
+        let unvisited_children = self
+            .node_iters
+            .iter()
+            .map(|node_iter| node_iter.size_hint().0)
+            .sum();
+
+        (unvisited_children, None)
+    }
 }
 
 impl<K, V> DoubleEndedIterator for InnerNodeTreeIterator<K, V> {
@@ -1,6 +1,7 @@
 use crate::{
-    deallocate_tree, insert_unchecked, tests_common::generate_key_fixed_length, LeafNode, NodePtr,
-    TreeIterator,
+    deallocate_tree, insert_unchecked,
+    tests_common::{generate_key_fixed_length, generate_key_with_prefix, PrefixExpansion},
+    LeafNode, NodePtr, TreeIterator,
 };
 
 fn map_item_to_ref<'a, K, V>(leaf_node_ptr: NodePtr<LeafNode<K, V>>) -> (&'a K, &'a V) {
@@ -35,6 +36,8 @@ fn small_tree_iterator_front_and_back() {
     };
 
     let mut trie_iter = unsafe { TreeIterator::new(root) };
+    // Every key starts with a different byte, so each child of the root is a leaf
+    assert_eq!(trie_iter.size_hint(), (9, None));
 
     assert_eq!(
         trie_iter.next().map(map_item_to_ref),
@@ -53,6 +56,7 @@ fn small_tree_iterator_front_and_back() {
         Some((&[173, 226, 147].into(), &7))
     );
 
+    assert_eq!(trie_iter.size_hint(), (5, None));
     let rest = trie_iter.map(map_item_to_ref).collect::<Vec<_>>();
     assert_eq!(rest.len(), 5);
     assert_eq!(
@@ -132,3 +136,97 @@ fn large_fixed_length_key_iterator_front_back() {
 
     unsafe { deallocate_tree(root) }
 }
+
+#[test]
+fn seek_range_only_visits_boundary_leaves_outside_range() {
+    #[cfg(not(miri))]
+    const LEVEL_WIDTHS: [u8; 3] = [4, 3, 5];
+    #[cfg(miri)]
+    const LEVEL_WIDTHS: [u8; 3] = [2, 1, 2];
+
+    let keys = generate_key_with_prefix(
+        LEVEL_WIDTHS,
+        [
+            PrefixExpansion {
+                base_index: 1,
+                expanded_length: 3,
+            },
+            PrefixExpansion {
+                base_index: 2,
+                expanded_length: 2,
+            },
+        ],
+    )
+    .collect::<Vec<_>>();
+    let mut root = NodePtr::allocate_node_ptr(LeafNode::new(keys[0].clone(), 0)).to_opaque();
+    for (idx, key) in keys.iter().enumerate().skip(1) {
+        root = unsafe { insert_unchecked(root, key.clone(), idx).unwrap().new_root };
+    }
+
+    // Bounds on the keys of the tree, and bounds which end inside or diverge from
+    // the compressed paths
+    let mut bounds: Vec<Box<[u8]>> = vec![Box::from([]), Box::from([255; 8])];
+    for key in keys.iter().step_by(7) {
+        bounds.push(key.clone());
+        bounds.push(key[..key.len() / 2].into());
+        bounds.push(key[..2].into());
+        bounds.push([&key[..], &[0]].concat().into());
+        let mut changed = key.to_vec();
+        changed[1] = changed[1].wrapping_add(1);
+        bounds.push(changed.into());
+    }
+
+    let leaf_key =
+        |leaf_node_ptr: NodePtr<LeafNode<Box<[u8]>, usize>>| map_item_to_ref(leaf_node_ptr).0;
+    for start in &bounds {
+        for end in &bounds {
+            if start > end {
+                continue;
+            }
+
+            let expected = unsafe { TreeIterator::new(root) }
+                .map(leaf_key)
+                .filter(|key| start <= *key && *key <= end)
+                .collect::<Vec<_>>();
+            let sought = unsafe { TreeIterator::seek_range(root, Some(start), Some(end)) }
+                .map(leaf_key)
+                .collect::<Vec<_>>();
+            let sought_in_range = sought
+                .iter()
+                .copied()
+                .filter(|key| start <= *key && *key <= end)
+                .collect::<Vec<_>>();
+
+            assert_eq!(sought_in_range, expected, "{start:?}..={end:?}");
+            assert!(
+                sought.len() <= expected.len() + 2,
+                "{start:?}..={end:?} visited {} leaves for {} in range",
+                sought.len(),
+                expected.len()
+            );
+            assert!(sought.windows(2).all(|pair| pair[0] < pair[1]));
+
+            let sought_back = unsafe { TreeIterator::seek_range(root, Some(start), Some(end)) }
+                .rev()
+                .map(leaf_key)
+                .collect::<Vec<_>>();
+            assert!(sought_back.iter().rev().eq(sought.iter()));
+        }
+    }
+
+    let half = &keys[keys.len() / 2];
+    assert_eq!(
+        unsafe { TreeIterator::seek_range(root, None, Some(half)) }
+            .map(leaf_key)
+            .next_back(),
+        Some(half)
+    );
+    assert_eq!(
+        unsafe { TreeIterator::seek_range(root, Some(half), None) }
+            .map(leaf_key)
+            .next(),
+        Some(half)
+    );
+
+    unsafe { deallocate_tree(root) }
+}
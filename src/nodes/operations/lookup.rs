@@ -1,6 +1,9 @@
-use std::borrow::Borrow;
+use std::{borrow::Borrow, ops::ControlFlow};
 
-use crate::{AsBytes, ConcreteNodePtr, InnerNode, LeafNode, NodePtr, OpaqueNodePtr};
+use crate::{
+    maximum_unchecked, minimum_unchecked, AsBytes, ConcreteNodePtr, InnerNode, LeafNode, NodePtr,
+    OpaqueNodePtr, SearchRecorder, SearchStats,
+};
 
 /// Search in the given tree for the value stored with the given key.
 ///
@@ -16,37 +19,357 @@ pub unsafe fn search_unchecked<Q, K, V>(
 where
     K: Borrow<Q> + AsBytes,
     Q: AsBytes + ?Sized,
+{
+    // SAFETY: The safety requirements are covered by the containing function
+    unsafe { search_recorded(root, key, &mut ()) }
+}
+
+/// Search in the given tree for the value stored with the given key, and
+/// return counters describing the work performed by the search.
+///
+/// # Safety
+///
+///  - This function cannot be called concurrently with any mutating operation
+///    on `root` or any child node of `root`. This function will arbitrarily
+///    read to any child in the given tree.
+pub unsafe fn search_instrumented_unchecked<Q, K, V>(
+    root: OpaqueNodePtr<K, V>,
+    key: &Q,
+) -> (Option<NodePtr<LeafNode<K, V>>>, SearchStats)
+where
+    K: Borrow<Q> + AsBytes,
+    Q: AsBytes + ?Sized,
+{
+    let mut stats = SearchStats::default();
+    // SAFETY: The safety requirements are covered by the containing function
+    let leaf = unsafe { search_recorded(root, key, &mut stats) };
+
+    (leaf, stats)
+}
+
+/// Search in the given tree for the value stored with the given key, reporting
+/// every step of the search to the given recorder.
+///
+/// # Safety
+///
+///  - This function cannot be called concurrently with any mutating operation
+///    on `root` or any child node of `root`. This function will arbitrarily
+///    read to any child in the given tree.
+unsafe fn search_recorded<Q, K, V, R>(
+    root: OpaqueNodePtr<K, V>,
+    key: &Q,
+    recorder: &mut R,
+) -> Option<NodePtr<LeafNode<K, V>>>
+where
+    K: AsBytes,
+    Q: AsBytes + ?Sized,
+    R: SearchRecorder,
 {
     let mut current_node = root;
     let mut current_depth = 0;
 
     loop {
+        recorder.record_node(current_node.node_type());
+
         current_node = match current_node.to_node_ptr() {
             ConcreteNodePtr::Node4(inner_ptr) => unsafe {
                 // SAFETY: The safety requirement is covered by the safety requirement on the
                 // containing function
-                check_prefix_lookup_child(inner_ptr, key, &mut current_depth)
+                check_prefix_lookup_child(inner_ptr, key, &mut current_depth, recorder)
             },
             ConcreteNodePtr::Node16(inner_ptr) => unsafe {
                 // SAFETY: The safety requirement is covered by the safety requirement on the
                 // containing function
-                check_prefix_lookup_child(inner_ptr, key, &mut current_depth)
+                check_prefix_lookup_child(inner_ptr, key, &mut current_depth, recorder)
             },
             ConcreteNodePtr::Node48(inner_ptr) => unsafe {
                 // SAFETY: The safety requirement is covered by the safety requirement on the
                 // containing function
-                check_prefix_lookup_child(inner_ptr, key, &mut current_depth)
+                check_prefix_lookup_child(inner_ptr, key, &mut current_depth, recorder)
             },
             ConcreteNodePtr::Node256(inner_ptr) => unsafe {
                 // SAFETY: The safety requirement is covered by the safety requirement on the
                 // containing function
-                check_prefix_lookup_child(inner_ptr, key, &mut current_depth)
+                check_prefix_lookup_child(inner_ptr, key, &mut current_depth, recorder)
             },
             ConcreteNodePtr::LeafNode(leaf_node_ptr) => {
                 let leaf_node = leaf_node_ptr.read();
 
                 // Specifically we are matching the leaf node stored key against the full search
                 // key to confirm that it is the right value.
+                if leaf_node.matches_key_bytes(key.as_bytes()) {
+                    return Some(leaf_node_ptr);
+                } else {
+                    return None;
+                }
+            },
+        }?;
+    }
+}
+
+/// Search in the given tree for the value stored with the given key bytes.
+///
+/// Unlike [`search_unchecked`], the search key does not need to be a borrowed
+/// form of the key type, only the bytes of the key are compared.
+///
+/// # Safety
+///
+///  - This function cannot be called concurrently with any mutating operation
+///    on `root` or any child node of `root`. This function will arbitrarily
+///    read to any child in the given tree.
+pub(crate) unsafe fn search_bytes_unchecked<K, V>(
+    root: OpaqueNodePtr<K, V>,
+    key_bytes: &[u8],
+) -> Option<NodePtr<LeafNode<K, V>>>
+where
+    K: AsBytes,
+{
+    // SAFETY: The safety requirements are covered by the containing function
+    unsafe { search_recorded(root, key_bytes, &mut ()) }
+}
+
+/// Describes where a search for a key which is not present in the tree stopped.
+#[derive(Debug)]
+pub struct SearchMiss<K, V> {
+    /// The number of leading bytes of the search key which are shared with
+    /// the `nearest` key
+    pub matched_bytes: usize,
+    /// The node at which the search for the key could not continue
+    pub diverged_at: OpaqueNodePtr<K, V>,
+    /// The number of key bytes consumed by the nodes above `diverged_at`
+    pub diverged_depth: usize,
+    /// A leaf whose key shares the longest possible prefix with the search key
+    pub nearest: NodePtr<LeafNode<K, V>>,
+}
+
+/// Search in the given tree for the value stored with the given key, and
+/// describe where the search stopped if the key is not present.
+///
+/// On a miss, the nearest key is chosen among the keys that share the longest
+/// prefix with the search key. When the search stops at a missing child, it is
+/// taken from the child with the closest key byte, preferring the smaller byte
+/// on a tie. Of the keys in that subtree, the one closest to the search key in
+/// sorted order is returned.
+///
+/// # Safety
+///
+///  - This function cannot be called concurrently with any mutating operation
+///    on `root` or any child node of `root`. This function will arbitrarily
+///    read to any child in the given tree.
+pub unsafe fn search_with_diagnostics_unchecked<Q, K, V>(
+    root: OpaqueNodePtr<K, V>,
+    key: &Q,
+) -> Result<NodePtr<LeafNode<K, V>>, SearchMiss<K, V>>
+where
+    K: Borrow<Q> + AsBytes,
+    Q: AsBytes + ?Sized,
+{
+    /// The subtree holding the nearest key to a search key that diverged at
+    /// some node, along with the number of matched key bytes and whether the
+    /// search key sorts after every key in the subtree.
+    type Divergence<K, V> = (usize, OpaqueNodePtr<K, V>, bool);
+
+    /// Check the prefix of the given inner node and lookup the child for the
+    /// next key byte, returning the child and the new depth on success.
+    ///
+    /// # Safety
+    ///
+    ///  - No other access or mutation to the `inner_ptr` Node can happen while
+    ///    this function runs.
+    unsafe fn diagnose_inner_node<K, V, N>(
+        inner_ptr: NodePtr<N>,
+        key_bytes: &[u8],
+        current_depth: usize,
+    ) -> Result<(OpaqueNodePtr<K, V>, usize), Divergence<K, V>>
+    where
+        N: InnerNode<Key = K, Value = V>,
+        K: AsBytes,
+    {
+        // SAFETY: The lifetime produced from this is bounded to this scope and does
+        // not escape. Further, no other code mutates the node referenced, which is
+        // further enforced the "no concurrent reads or writes" requirement on the
+        // `search_with_diagnostics_unchecked` function.
+        let inner_node = unsafe { inner_ptr.as_ref() };
+        let header = inner_node.header();
+        let remaining_key = &key_bytes[current_depth..];
+
+        // SAFETY: The node is at the current depth, and the tree is not mutated during
+        // the search, see above.
+        let matched_prefix_size =
+            unsafe { inner_node.match_full_prefix(remaining_key, current_depth) };
+        if matched_prefix_size != header.prefix_size() {
+            // SAFETY: See above
+            let full_prefix = unsafe { inner_node.read_full_prefix(current_depth) };
+            // Every key below this node shares exactly the matched bytes with the
+            // search key, so the nearest one is at either end of the subtree
+            let after_subtree = match (
+                remaining_key.get(matched_prefix_size),
+                full_prefix.get(matched_prefix_size),
+            ) {
+                (Some(key_byte), Some(prefix_byte)) => key_byte > prefix_byte,
+                _ => false,
+            };
+            return Err((
+                current_depth + matched_prefix_size,
+                inner_ptr.to_opaque(),
+                after_subtree,
+            ));
+        }
+
+        let current_depth = current_depth + matched_prefix_size;
+        let key_byte = match key_bytes.get(current_depth) {
+            Some(key_byte) => *key_byte,
+            // The search key is a prefix of every key below this node, so it sorts
+            // before all of them
+            None => return Err((current_depth, inner_ptr.to_opaque(), false)),
+        };
+
+        if let Some(child) = inner_node.lookup_child(key_byte) {
+            return Ok((child, current_depth + 1));
+        }
+
+        // SAFETY: The iterator does not outlive this function, and the safety
+        // requirements of the containing function forbid any concurrent mutation
+        // of the node.
+        let (child_byte, child) = unsafe { inner_node.iter() }
+            .min_by_key(|(child_byte, _)| (child_byte.abs_diff(key_byte), *child_byte))
+            // PANIC SAFETY: Inner nodes in a well-formed tree always have at least
+            // one child
+            .expect("inner node should have at least one child");
+        Err((current_depth, child, child_byte < key_byte))
+    }
+
+    let key_bytes = key.as_bytes();
+    let mut current_node = root;
+    let mut current_depth = 0;
+
+    loop {
+        let step = match current_node.to_node_ptr() {
+            ConcreteNodePtr::Node4(inner_ptr) => unsafe {
+                // SAFETY: The safety requirement is covered by the safety requirement on the
+                // containing function
+                diagnose_inner_node(inner_ptr, key_bytes, current_depth)
+            },
+            ConcreteNodePtr::Node16(inner_ptr) => unsafe {
+                // SAFETY: The safety requirement is covered by the safety requirement on the
+                // containing function
+                diagnose_inner_node(inner_ptr, key_bytes, current_depth)
+            },
+            ConcreteNodePtr::Node48(inner_ptr) => unsafe {
+                // SAFETY: The safety requirement is covered by the safety requirement on the
+                // containing function
+                diagnose_inner_node(inner_ptr, key_bytes, current_depth)
+            },
+            ConcreteNodePtr::Node256(inner_ptr) => unsafe {
+                // SAFETY: The safety requirement is covered by the safety requirement on the
+                // containing function
+                diagnose_inner_node(inner_ptr, key_bytes, current_depth)
+            },
+            ConcreteNodePtr::LeafNode(leaf_node_ptr) => {
+                // SAFETY: The lifetime of the key reference is bounded to this block, and
+                // the safety requirements of the containing function forbid any
+                // concurrent mutation of the leaf.
+                let leaf_key = unsafe { leaf_node_ptr.as_key_ref() }.as_bytes();
+                if leaf_key == key_bytes {
+                    return Ok(leaf_node_ptr);
+                }
+
+                let matched_bytes = leaf_key
+                    .iter()
+                    .zip(key_bytes)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                return Err(SearchMiss {
+                    matched_bytes,
+                    diverged_at: current_node,
+                    diverged_depth: current_depth,
+                    nearest: leaf_node_ptr,
+                });
+            },
+        };
+
+        match step {
+            Ok((child, depth)) => {
+                current_node = child;
+                current_depth = depth;
+            },
+            Err((matched_bytes, subtree, after_subtree)) => {
+                // SAFETY: The safety requirements are covered by the containing function
+                let nearest = unsafe {
+                    if after_subtree {
+                        maximum_unchecked(subtree)
+                    } else {
+                        minimum_unchecked(subtree)
+                    }
+                };
+                return Err(SearchMiss {
+                    matched_bytes,
+                    diverged_at: current_node,
+                    diverged_depth: current_depth,
+                    nearest,
+                });
+            },
+        }
+    }
+}
+
+/// Search for the value stored with the given key, starting from a node inside
+/// the tree instead of the root, and record every node visited by the search.
+///
+/// The `start` node must be the node that a search for `key` from the root
+/// reaches after using the first `start_depth` bytes of the key. For example,
+/// any node on the search path of another key which shares the first
+/// `start_depth` bytes with `key`. Otherwise, the result of the search is
+/// unspecified.
+///
+/// Every visited node is appended to `path` along with the number of key bytes
+/// used before reaching it, starting with `start` itself.
+///
+/// # Safety
+///
+///  - This function cannot be called concurrently with any mutating operation
+///    on `start` or any child node of `start`. This function will arbitrarily
+///    read to any child in the given tree.
+pub unsafe fn search_from_unchecked<Q, K, V>(
+    start: OpaqueNodePtr<K, V>,
+    start_depth: usize,
+    key: &Q,
+    path: &mut Vec<(usize, OpaqueNodePtr<K, V>)>,
+) -> Option<NodePtr<LeafNode<K, V>>>
+where
+    K: Borrow<Q> + AsBytes,
+    Q: AsBytes + ?Sized,
+{
+    let mut current_node = start;
+    let mut current_depth = start_depth;
+
+    loop {
+        path.push((current_depth, current_node));
+
+        current_node = match current_node.to_node_ptr() {
+            ConcreteNodePtr::Node4(inner_ptr) => unsafe {
+                // SAFETY: The safety requirement is covered by the safety requirement on the
+                // containing function
+                check_prefix_lookup_child(inner_ptr, key, &mut current_depth, &mut ())
+            },
+            ConcreteNodePtr::Node16(inner_ptr) => unsafe {
+                // SAFETY: The safety requirement is covered by the safety requirement on the
+                // containing function
+                check_prefix_lookup_child(inner_ptr, key, &mut current_depth, &mut ())
+            },
+            ConcreteNodePtr::Node48(inner_ptr) => unsafe {
+                // SAFETY: The safety requirement is covered by the safety requirement on the
+                // containing function
+                check_prefix_lookup_child(inner_ptr, key, &mut current_depth, &mut ())
+            },
+            ConcreteNodePtr::Node256(inner_ptr) => unsafe {
+                // SAFETY: The safety requirement is covered by the safety requirement on the
+                // containing function
+                check_prefix_lookup_child(inner_ptr, key, &mut current_depth, &mut ())
+            },
+            ConcreteNodePtr::LeafNode(leaf_node_ptr) => {
+                let leaf_node = leaf_node_ptr.read();
+
                 if leaf_node.matches_full_key(key) {
                     return Some(leaf_node_ptr);
                 } else {
@@ -67,15 +390,17 @@ where
 ///
 ///  - No other access or mutation to the `inner_ptr` Node can happen while this
 ///    function runs.
-pub(crate) unsafe fn check_prefix_lookup_child<Q, K, V, N>(
+pub(crate) unsafe fn check_prefix_lookup_child<Q, K, V, N, R>(
     inner_ptr: NodePtr<N>,
     key: &Q,
     current_depth: &mut usize,
+    recorder: &mut R,
 ) -> Option<OpaqueNodePtr<K, V>>
 where
     N: InnerNode<Key = K, Value = V>,
-    K: Borrow<Q> + AsBytes,
+    K: AsBytes,
     Q: AsBytes + ?Sized,
+    R: SearchRecorder,
 {
     // SAFETY: The lifetime produced from this is bounded to this scope and does not
     // escape. Further, no other code mutates the node referenced, which is further
@@ -83,13 +408,37 @@ where
     // `search_unchecked` function.
     let inner_node = unsafe { inner_ptr.as_ref() };
     let header = inner_node.header();
-    let matched_prefix_size = header.match_prefix(&key.as_bytes()[*current_depth..]);
-    if matched_prefix_size != header.prefix_size() {
+    let remaining_key = &key.as_bytes()[*current_depth..];
+    let stored_prefix_size = header.read_prefix().len();
+
+    let matched_prefix_size = header.match_prefix(remaining_key);
+    recorder.record_prefix_comparison(prefix_bytes_compared(
+        matched_prefix_size,
+        stored_prefix_size,
+        remaining_key.len(),
+    ));
+    if matched_prefix_size != stored_prefix_size {
         return None;
     }
 
+    if !header.may_match_full_prefix(remaining_key) {
+        // The fingerprint of the prefix bytes which are not stored in the header did
+        // not match, so the key cannot be in this subtree
+        recorder.record_fingerprint_rejection();
+        return None;
+    }
+
+    // The bytes of the prefix which are not stored in the header are skipped
+    // without comparing them. If they do not match despite the fingerprint, the
+    // search ends at a leaf with a different key, which is rejected by the full
+    // key comparison there.
+    let skipped_prefix_size = header.prefix_size() - stored_prefix_size;
+    if skipped_prefix_size > 0 {
+        recorder.record_skipped_prefix_bytes(skipped_prefix_size);
+    }
+
     // Since the prefix matched, advance the depth by the size of the prefix
-    *current_depth += matched_prefix_size;
+    *current_depth += header.prefix_size();
 
     let next_key_fragment = if *current_depth < key.as_bytes().len() {
         key.as_bytes()[*current_depth]
@@ -101,6 +450,7 @@ where
     };
 
     let child_lookup = inner_node.lookup_child(next_key_fragment);
+    recorder.record_child_lookup(N::TYPE.uses_simd_child_lookup());
 
     if child_lookup.is_some() {
         // Since the prefix matched and it found a child, advance the depth by 1 more
@@ -111,5 +461,276 @@ where
     child_lookup
 }
 
+/// Return the number of bytes that [`Header::match_prefix`] had to compare to
+/// produce the given result.
+///
+/// The comparison stops at the first mismatched byte, or at the end of either
+/// the prefix or the key.
+///
+/// [`Header::match_prefix`]: crate::Header::match_prefix
+pub(crate) fn prefix_bytes_compared(
+    matched_prefix_size: usize,
+    prefix_size: usize,
+    remaining_key_len: usize,
+) -> usize {
+    (matched_prefix_size + 1)
+        .min(prefix_size)
+        .min(remaining_key_len)
+}
+
+/// Search in the given tree for the root of the subtree that contains all the
+/// keys which start with the given prefix.
+///
+/// On success, this function returns the highest node whose subtree contains
+/// exactly the set of keys that start with `prefix`, along with the number of
+/// `prefix` bytes that were matched against that node's own compressed path,
+/// rather than consumed by the nodes above it. The depth of the returned node
+/// (the number of key bytes consumed before reaching it) is the length of
+/// `prefix` minus this count.
+///
+/// If the returned node is a leaf, then it is the only key in the tree that
+/// starts with `prefix`, and the count is the number of `prefix` bytes which
+/// were checked against the leaf key.
+///
+/// An empty prefix always returns the root node. If there are no keys that
+/// start with `prefix`, then `None` is returned.
+///
+/// # Safety
+///
+///  - This function cannot be called concurrently with any mutating operation
+///    on `root` or any child node of `root`. This function will arbitrarily
+///    read to any child in the given tree.
+pub unsafe fn search_prefix_unchecked<K, V>(
+    root: OpaqueNodePtr<K, V>,
+    prefix: &[u8],
+) -> Option<(OpaqueNodePtr<K, V>, usize)>
+where
+    K: AsBytes,
+{
+    /// Match the remaining search prefix against the compressed path of the
+    /// given inner node. Break with the number of implicitly matched bytes if
+    /// the search prefix ended inside (or exactly at the end of) the
+    /// compressed path, otherwise continue with the child for the next byte.
+    ///
+    /// # Safety
+    ///
+    ///  - No other access or mutation to the `inner_ptr` Node can happen while
+    ///    this function runs.
+    unsafe fn check_prefix_lookup_child_for_prefix<K, V, N>(
+        inner_ptr: NodePtr<N>,
+        prefix: &[u8],
+        current_depth: &mut usize,
+    ) -> ControlFlow<Option<usize>, OpaqueNodePtr<K, V>>
+    where
+        N: InnerNode<Key = K, Value = V>,
+        K: AsBytes,
+    {
+        // SAFETY: The lifetime produced from this is bounded to this scope and does
+        // not escape. Further, no other code mutates the node referenced, which is
+        // further enforced the "no concurrent reads or writes" requirement on the
+        // `search_prefix_unchecked` function.
+        let inner_node = unsafe { inner_ptr.as_ref() };
+        let header = inner_node.header();
+        let remaining_prefix = &prefix[*current_depth..];
+        // SAFETY: The node is at the current depth, and the tree is not mutated during
+        // the search, see above.
+        let matched_prefix_size =
+            unsafe { inner_node.match_full_prefix(remaining_prefix, *current_depth) };
+
+        if matched_prefix_size == remaining_prefix.len() {
+            // The search prefix is exhausted somewhere inside the compressed path, so
+            // every key under this node starts with the search prefix.
+            return ControlFlow::Break(Some(matched_prefix_size));
+        }
+
+        if matched_prefix_size != header.prefix_size() {
+            return ControlFlow::Break(None);
+        }
+
+        *current_depth += matched_prefix_size;
+
+        match inner_node.lookup_child(prefix[*current_depth]) {
+            Some(child) => {
+                *current_depth += 1;
+                ControlFlow::Continue(child)
+            },
+            None => ControlFlow::Break(None),
+        }
+    }
+
+    let mut current_node = root;
+    let mut current_depth = 0;
+
+    loop {
+        let next_step = match current_node.to_node_ptr() {
+            ConcreteNodePtr::Node4(inner_ptr) => unsafe {
+                // SAFETY: The safety requirement is covered by the safety requirement on the
+                // containing function
+                check_prefix_lookup_child_for_prefix(inner_ptr, prefix, &mut current_depth)
+            },
+            ConcreteNodePtr::Node16(inner_ptr) => unsafe {
+                // SAFETY: The safety requirement is covered by the safety requirement on the
+                // containing function
+                check_prefix_lookup_child_for_prefix(inner_ptr, prefix, &mut current_depth)
+            },
+            ConcreteNodePtr::Node48(inner_ptr) => unsafe {
+                // SAFETY: The safety requirement is covered by the safety requirement on the
+                // containing function
+                check_prefix_lookup_child_for_prefix(inner_ptr, prefix, &mut current_depth)
+            },
+            ConcreteNodePtr::Node256(inner_ptr) => unsafe {
+                // SAFETY: The safety requirement is covered by the safety requirement on the
+                // containing function
+                check_prefix_lookup_child_for_prefix(inner_ptr, prefix, &mut current_depth)
+            },
+            ConcreteNodePtr::LeafNode(leaf_node_ptr) => {
+                // SAFETY: The lifetime of the key reference is bounded to this block, and
+                // the safety requirements of the containing function forbid any concurrent
+                // mutation of the leaf.
+                let leaf_key = unsafe { leaf_node_ptr.as_key_ref() };
+
+                // The leaf is the only key in this subtree, so it must contain the entire
+                // search prefix itself.
+                return if leaf_key.as_bytes().starts_with(prefix) {
+                    Some((current_node, prefix.len() - current_depth))
+                } else {
+                    None
+                };
+            },
+        };
+
+        match next_step {
+            ControlFlow::Continue(child) => current_node = child,
+            ControlFlow::Break(Some(implicit_bytes)) => {
+                return Some((current_node, implicit_bytes))
+            },
+            ControlFlow::Break(None) => return None,
+        }
+    }
+}
+
+/// The bytes that follow a prefix in the keys of a tree.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct PrefixBranches {
+    /// `true` if the tree contains a key that is exactly equal to the prefix
+    pub terminal: bool,
+    /// The distinct bytes that immediately follow the prefix in the keys that
+    /// are longer than the prefix, in ascending order
+    pub next_bytes: Vec<u8>,
+}
+
+/// Search in the given tree for the set of bytes which immediately follow the
+/// given prefix in some key, and whether a key exists exactly at the prefix.
+///
+/// This only visits the nodes on the path to the given prefix, plus the
+/// children of the last node, instead of the entire subtree of keys that
+/// start with the prefix.
+///
+/// # Safety
+///
+///  - This function cannot be called concurrently with any mutating operation
+///    on `root` or any child node of `root`. This function will arbitrarily
+///    read to any child in the given tree.
+pub unsafe fn search_prefix_branches_unchecked<K, V>(
+    root: OpaqueNodePtr<K, V>,
+    prefix: &[u8],
+) -> PrefixBranches
+where
+    K: AsBytes,
+{
+    /// Return the bytes following the first `matched_prefix_size` bytes of the
+    /// compressed path of the given inner node.
+    ///
+    /// # Safety
+    ///
+    ///  - No other access or mutation to the `inner_ptr` Node can happen while
+    ///    this function runs.
+    unsafe fn inner_node_branches<K, V, N>(
+        inner_ptr: NodePtr<N>,
+        current_depth: usize,
+        matched_prefix_size: usize,
+    ) -> Vec<u8>
+    where
+        N: InnerNode<Key = K, Value = V>,
+        K: AsBytes,
+    {
+        // SAFETY: The lifetime produced from this is bounded to this scope and does
+        // not escape. Further, no other code mutates the node referenced, which is
+        // further enforced the "no concurrent reads or writes" requirement on the
+        // `search_prefix_branches_unchecked` function.
+        let inner_node = unsafe { inner_ptr.as_ref() };
+        // SAFETY: The node is at the given depth, see above.
+        let compressed_path = unsafe { inner_node.read_full_prefix(current_depth) };
+
+        if let Some(next_byte) = compressed_path.get(matched_prefix_size) {
+            // Every key under this node continues with the rest of the compressed path
+            vec![*next_byte]
+        } else {
+            // SAFETY: The iterator does not outlive this function, and the safety
+            // requirements of the containing function forbid any concurrent mutation
+            // of the node.
+            unsafe { inner_node.iter() }
+                .map(|(key_byte, _)| key_byte)
+                .collect()
+        }
+    }
+
+    // SAFETY: The safety requirements are covered by the containing function
+    let (node, matched_prefix_size) = match unsafe { search_prefix_unchecked(root, prefix) } {
+        Some(found) => found,
+        None => return PrefixBranches::default(),
+    };
+    let current_depth = prefix.len() - matched_prefix_size;
+
+    let next_bytes = match node.to_node_ptr() {
+        ConcreteNodePtr::Node4(inner_ptr) => unsafe {
+            // SAFETY: The safety requirement is covered by the safety requirement on the
+            // containing function
+            inner_node_branches(inner_ptr, current_depth, matched_prefix_size)
+        },
+        ConcreteNodePtr::Node16(inner_ptr) => unsafe {
+            // SAFETY: The safety requirement is covered by the safety requirement on the
+            // containing function
+            inner_node_branches(inner_ptr, current_depth, matched_prefix_size)
+        },
+        ConcreteNodePtr::Node48(inner_ptr) => unsafe {
+            // SAFETY: The safety requirement is covered by the safety requirement on the
+            // containing function
+            inner_node_branches(inner_ptr, current_depth, matched_prefix_size)
+        },
+        ConcreteNodePtr::Node256(inner_ptr) => unsafe {
+            // SAFETY: The safety requirement is covered by the safety requirement on the
+            // containing function
+            inner_node_branches(inner_ptr, current_depth, matched_prefix_size)
+        },
+        ConcreteNodePtr::LeafNode(leaf_node_ptr) => {
+            // SAFETY: The lifetime of the key reference is bounded to this block, and
+            // the safety requirements of the containing function forbid any concurrent
+            // mutation of the leaf.
+            let leaf_key = unsafe { leaf_node_ptr.as_key_ref() }.as_bytes();
+
+            // `search_prefix_unchecked` only returns leaves whose key starts with the
+            // prefix
+            return match leaf_key.get(prefix.len()) {
+                Some(next_byte) => PrefixBranches {
+                    terminal: false,
+                    next_bytes: vec![*next_byte],
+                },
+                None => PrefixBranches {
+                    terminal: true,
+                    next_bytes: Vec::new(),
+                },
+            };
+        },
+    };
+
+    // Keys are prefix-free, so when the prefix ends at an inner node, there cannot
+    // also be a key which ends there.
+    PrefixBranches {
+        terminal: false,
+        next_bytes,
+    }
+}
+
 #[cfg(test)]
 mod tests;
@@ -1,6 +1,11 @@
 use crate::{
-    nodes::NodePtr, search_unchecked, InnerNode, InnerNode16, InnerNode256, InnerNode4,
-    InnerNode48, LeafNode,
+    deallocate_tree,
+    nodes::NodePtr,
+    search_from_unchecked, search_instrumented_unchecked, search_prefix_branches_unchecked,
+    search_prefix_unchecked, search_unchecked, search_with_diagnostics_unchecked,
+    tests_common::{generate_key_fixed_length, setup_tree_from_entries},
+    InnerNode, InnerNode16, InnerNode256, InnerNode4, InnerNode48, LeafNode, NodeType, SearchStats,
+    TreeIterator, NUM_PREFIX_BYTES,
 };
 
 #[test]
@@ -694,3 +699,382 @@ fn lookup_on_n4_n4_layer_tree() {
         assert!(search_unchecked(root, [1, 2, 4, 7, 80, 3].as_ref()).is_none());
     }
 }
+
+#[test]
+fn prefix_search_on_leaf() {
+    let mut leaf: LeafNode<Box<[u8]>, i32> = LeafNode::new(Box::from([1, 2, 3]), 123);
+    let leaf_ptr = NodePtr::from(&mut leaf).to_opaque();
+
+    // SAFETY: The tree is only the single leaf, which is not mutated during the
+    // searches.
+    unsafe {
+        assert_eq!(search_prefix_unchecked(leaf_ptr, &[]), Some((leaf_ptr, 0)));
+        assert_eq!(
+            search_prefix_unchecked(leaf_ptr, &[1, 2]),
+            Some((leaf_ptr, 2))
+        );
+        assert_eq!(
+            search_prefix_unchecked(leaf_ptr, &[1, 2, 3]),
+            Some((leaf_ptr, 3))
+        );
+        assert_eq!(search_prefix_unchecked(leaf_ptr, &[1, 2, 3, 4]), None);
+        assert_eq!(search_prefix_unchecked(leaf_ptr, &[1, 3]), None);
+    }
+}
+
+#[test]
+fn prefix_search_on_compressed_path() {
+    let mut l1: LeafNode<Box<[u8]>, i32> = LeafNode::new(Box::from([1, 2, 3, 5, 6]), 12356);
+    let mut l2: LeafNode<Box<[u8]>, i32> = LeafNode::new(Box::from([1, 2, 3, 5, 7]), 12357);
+    let mut l3: LeafNode<Box<[u8]>, i32> = LeafNode::new(Box::from([1, 2, 4, 8, 9]), 12489);
+
+    let l1_ptr = NodePtr::from(&mut l1).to_opaque();
+    let l2_ptr = NodePtr::from(&mut l2).to_opaque();
+    let l3_ptr = NodePtr::from(&mut l3).to_opaque();
+
+    let mut n4_inner = InnerNode4::empty();
+    n4_inner.header.extend_prefix(&[5]);
+    n4_inner.write_child(6, l1_ptr);
+    n4_inner.write_child(7, l2_ptr);
+    let n4_inner_ptr = NodePtr::from(&mut n4_inner).to_opaque();
+
+    let mut n4_root = InnerNode4::empty();
+    n4_root.header.extend_prefix(&[1, 2]);
+    n4_root.write_child(3, n4_inner_ptr);
+    n4_root.write_child(4, l3_ptr);
+    let root = NodePtr::from(&mut n4_root).to_opaque();
+
+    // SAFETY: The nodes are stack allocated and outlive all the searches, and there
+    // is no mutation while searching.
+    unsafe {
+        assert_eq!(search_prefix_unchecked(root, &[]), Some((root, 0)));
+        assert_eq!(search_prefix_unchecked(root, &[1]), Some((root, 1)));
+        assert_eq!(search_prefix_unchecked(root, &[1, 2]), Some((root, 2)));
+        assert_eq!(
+            search_prefix_unchecked(root, &[1, 2, 3]),
+            Some((n4_inner_ptr, 0))
+        );
+        assert_eq!(
+            search_prefix_unchecked(root, &[1, 2, 3, 5]),
+            Some((n4_inner_ptr, 1))
+        );
+        assert_eq!(
+            search_prefix_unchecked(root, &[1, 2, 3, 5, 7]),
+            Some((l2_ptr, 0))
+        );
+        assert_eq!(search_prefix_unchecked(root, &[1, 2, 4]), Some((l3_ptr, 0)));
+        assert_eq!(
+            search_prefix_unchecked(root, &[1, 2, 4, 8]),
+            Some((l3_ptr, 1))
+        );
+
+        assert_eq!(search_prefix_unchecked(root, &[1, 3]), None);
+        assert_eq!(search_prefix_unchecked(root, &[1, 2, 5]), None);
+        assert_eq!(search_prefix_unchecked(root, &[1, 2, 3, 6]), None);
+        assert_eq!(search_prefix_unchecked(root, &[1, 2, 3, 5, 8]), None);
+        assert_eq!(search_prefix_unchecked(root, &[1, 2, 4, 8, 9, 0]), None);
+    }
+}
+
+#[test]
+fn prefix_search_subtree_contains_exactly_prefixed_keys() {
+    let keys: Vec<_> = generate_key_fixed_length([3, 2, 4]).collect();
+    let root = setup_tree_from_entries(keys.iter().cloned().zip(0..));
+
+    let prefixes: [&[u8]; 6] = [&[], &[0], &[85, 0], &[255, 255], &[255, 255, 64], &[1]];
+    for prefix in prefixes {
+        let expected: Vec<_> = keys.iter().filter(|key| key.starts_with(prefix)).collect();
+
+        // SAFETY: There are no concurrent mutations of the tree during the search or
+        // the iteration.
+        let found: Vec<_> = unsafe {
+            match search_prefix_unchecked(root, prefix) {
+                Some((subtree, implicit_bytes)) => {
+                    assert!(implicit_bytes <= prefix.len());
+                    if subtree.node_type() != NodeType::Leaf {
+                        let header = subtree.header_mut().unwrap();
+                        assert!(implicit_bytes <= header.prefix_size());
+                    }
+                    TreeIterator::new(subtree)
+                        .map(|leaf_ptr| leaf_ptr.as_key_ref())
+                        .collect()
+                },
+                None => Vec::new(),
+            }
+        };
+
+        assert_eq!(found, expected, "prefix {prefix:?}");
+    }
+
+    // SAFETY: The tree is not used after this point
+    unsafe { deallocate_tree(root) };
+}
+
+#[test]
+fn prefix_branches_match_brute_force() {
+    let mut keys: Vec<_> = generate_key_fixed_length([3, 2, 4]).collect();
+    // Keys sharing a compressed path that is too long to store inline
+    for last in [7, 9, 200] {
+        let mut key = vec![255; 12];
+        key[0] = 128;
+        key.push(last);
+        keys.push(key.into_boxed_slice());
+    }
+    let root = setup_tree_from_entries(keys.iter().cloned().zip(0..));
+
+    let mut prefixes: Vec<&[u8]> = vec![&[0, 1], &[128, 255, 0], &[1, 2, 3, 4, 5]];
+    for key in &keys {
+        prefixes.extend((0..=key.len()).map(|len| &key[..len]));
+    }
+
+    for prefix in prefixes {
+        let mut expected_next_bytes: Vec<_> = keys
+            .iter()
+            .filter(|key| key.starts_with(prefix) && key.len() > prefix.len())
+            .map(|key| key[prefix.len()])
+            .collect();
+        expected_next_bytes.sort_unstable();
+        expected_next_bytes.dedup();
+        let expected_terminal = keys.iter().any(|key| &key[..] == prefix);
+
+        // SAFETY: There are no concurrent mutations of the tree during the search
+        let branches = unsafe { search_prefix_branches_unchecked(root, prefix) };
+        assert_eq!(
+            branches.next_bytes, expected_next_bytes,
+            "prefix {prefix:?}"
+        );
+        assert_eq!(branches.terminal, expected_terminal, "prefix {prefix:?}");
+    }
+
+    // SAFETY: The tree is not used after this point
+    unsafe { deallocate_tree(root) };
+}
+
+#[test]
+fn search_with_diagnostics_finds_longest_shared_prefix() {
+    let mut keys: Vec<_> = generate_key_fixed_length([3, 2, 4]).collect();
+    for last in [7, 9, 200] {
+        let mut key = vec![255; 12];
+        key[0] = 128;
+        key.push(last);
+        keys.push(key.into_boxed_slice());
+    }
+    let root = setup_tree_from_entries(keys.iter().cloned().zip(0..));
+
+    fn shared_len(a: &[u8], b: &[u8]) -> usize {
+        a.iter().zip(b).take_while(|(a, b)| a == b).count()
+    }
+
+    let mut queries: Vec<Box<[u8]>> = vec![
+        Box::new([]),
+        Box::new([0, 1]),
+        Box::new([5, 0, 0]),
+        Box::new([128, 255, 0]),
+        Box::new([
+            128, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 8,
+        ]),
+        Box::new([1, 2, 3, 4, 5]),
+    ];
+    queries.extend(keys.iter().cloned());
+
+    for query in &queries {
+        // SAFETY: There are no concurrent mutations of the tree during the search
+        let result = unsafe { search_with_diagnostics_unchecked(root, query.as_ref()) };
+        if keys.contains(query) {
+            // SAFETY: There are no concurrent mutations of the tree during the search
+            let leaf = unsafe { search_unchecked(root, query.as_ref()) }.unwrap();
+            assert_eq!(result.unwrap(), leaf, "query {query:?}");
+            continue;
+        }
+
+        let miss = result.unwrap_err();
+        let longest_shared = keys.iter().map(|key| shared_len(key, query)).max().unwrap();
+        // SAFETY: There are no concurrent mutations of the tree during the search
+        let nearest = unsafe { miss.nearest.as_key_ref() };
+        assert_eq!(miss.matched_bytes, longest_shared, "query {query:?}");
+        assert_eq!(
+            shared_len(nearest, query),
+            longest_shared,
+            "query {query:?}"
+        );
+        assert!(miss.diverged_depth <= miss.matched_bytes, "query {query:?}");
+    }
+
+    // SAFETY: The tree is not used after this point
+    unsafe { deallocate_tree(root) };
+}
+
+#[test]
+fn search_with_diagnostics_picks_closest_key() {
+    let keys: [&[u8]; 4] = [&[1, 2, 3], &[1, 2, 9], &[1, 5, 0], &[6, 6, 6]];
+    let root = setup_tree_from_entries(keys.iter().map(|key| Box::<[u8]>::from(*key)).zip(0..));
+
+    let nearest = |query: &[u8]| {
+        // SAFETY: There are no concurrent mutations of the tree during the search
+        let miss = unsafe { search_with_diagnostics_unchecked(root, query) }.unwrap_err();
+        // SAFETY: There are no concurrent mutations of the tree during the search
+        let nearest = unsafe { miss.nearest.as_key_ref() }.clone();
+        (miss.matched_bytes, miss.diverged_at.node_type(), nearest)
+    };
+
+    // Missing child, the closest child byte is below the search byte
+    assert_eq!(
+        nearest(&[1, 2, 4]),
+        (2, NodeType::Node4, Box::from([1, 2, 3]))
+    );
+    // Missing child, the closest child byte is above the search byte
+    assert_eq!(
+        nearest(&[1, 2, 8]),
+        (2, NodeType::Node4, Box::from([1, 2, 9]))
+    );
+    // Missing child at the root, the whole subtree sorts before the search key
+    assert_eq!(
+        nearest(&[2, 0, 0]),
+        (0, NodeType::Node4, Box::from([1, 5, 0]))
+    );
+    // The search key is a prefix of the keys below a node
+    assert_eq!(nearest(&[1, 2]), (2, NodeType::Node4, Box::from([1, 2, 3])));
+    // Mismatch against a leaf
+    assert_eq!(
+        nearest(&[6, 6, 7]),
+        (2, NodeType::Leaf, Box::from([6, 6, 6]))
+    );
+
+    // SAFETY: The tree is not used after this point
+    unsafe { deallocate_tree(root) };
+}
+
+#[test]
+fn search_from_path_node_matches_root_search() {
+    let keys: Vec<_> = generate_key_fixed_length([3, 2, 4]).collect();
+    let root = setup_tree_from_entries(keys.iter().cloned().zip(0..));
+
+    let mut path = Vec::new();
+    for (idx, key) in keys.iter().enumerate() {
+        path.clear();
+        // SAFETY: There are no concurrent mutations of the tree during the search
+        let leaf = unsafe { search_from_unchecked(root, 0, key.as_ref(), &mut path) };
+        assert_eq!(unsafe { leaf.unwrap().as_value_ref() }, &idx);
+        assert_eq!(path[0], (0, root));
+        assert!(path.windows(2).all(|pair| pair[0].0 < pair[1].0));
+
+        // Starting from any node on the path finds the same leaf, including for
+        // other keys that reach the same node
+        for &(depth, node) in &path {
+            for other_key in keys.iter().filter(|other| other[..depth] == key[..depth]) {
+                // SAFETY: There are no concurrent mutations of the tree during the search
+                let from_node = unsafe {
+                    search_from_unchecked(node, depth, other_key.as_ref(), &mut Vec::new())
+                };
+                // SAFETY: There are no concurrent mutations of the tree during the search
+                let from_root = unsafe { search_unchecked(root, other_key.as_ref()) };
+                assert_eq!(from_node, from_root);
+            }
+        }
+    }
+
+    // SAFETY: The tree is not used after this point
+    unsafe { deallocate_tree(root) };
+}
+
+#[test]
+fn instrumented_search_counts_visited_nodes() {
+    let keys: [Box<[u8]>; 3] = [
+        Box::new([1, 2, 3, 4]),
+        Box::new([1, 2, 5, 6]),
+        Box::new([1, 2, 5, 7]),
+    ];
+    let root = setup_tree_from_entries(keys.iter().cloned().zip(0..));
+
+    // The root has prefix `[1, 2]` and the node under byte `5` has an empty prefix
+    let (leaf, stats) = unsafe { search_instrumented_unchecked(root, [1, 2, 5, 7].as_ref()) };
+    assert_eq!(unsafe { leaf.unwrap().as_value_ref() }, &2);
+    assert_eq!(
+        stats,
+        SearchStats {
+            node4_visited: 2,
+            leaves_visited: 1,
+            prefix_bytes_compared: 2,
+            scalar_child_lookups: 2,
+            ..Default::default()
+        }
+    );
+    assert_eq!(stats.nodes_visited(), 3);
+
+    // The search stops at the first mismatched byte of the root prefix
+    let (leaf, stats) = unsafe { search_instrumented_unchecked(root, [1, 3, 5, 7].as_ref()) };
+    assert!(leaf.is_none());
+    assert_eq!(
+        stats,
+        SearchStats {
+            node4_visited: 1,
+            prefix_bytes_compared: 2,
+            ..Default::default()
+        }
+    );
+
+    // SAFETY: The tree is not used after this point
+    unsafe { deallocate_tree(root) };
+
+    // The child lookup of an `InnerNode16` uses SIMD instructions where they are
+    // available
+    let keys: Vec<Box<[u8]>> = (0..8).map(|last| Box::from([1, last])).collect();
+    let root = setup_tree_from_entries(keys.into_iter().zip(0..));
+    let (leaf, stats) = unsafe { search_instrumented_unchecked(root, [1, 5].as_ref()) };
+    assert_eq!(unsafe { leaf.unwrap().as_value_ref() }, &5);
+    let simd_enabled = cfg!(all(
+        any(target_arch = "x86", target_arch = "x86_64"),
+        target_feature = "sse2"
+    ));
+    assert_eq!(
+        stats,
+        SearchStats {
+            node16_visited: 1,
+            leaves_visited: 1,
+            prefix_bytes_compared: 1,
+            simd_child_lookups: usize::from(simd_enabled),
+            scalar_child_lookups: usize::from(!simd_enabled),
+            ..Default::default()
+        }
+    );
+
+    // SAFETY: The tree is not used after this point
+    unsafe { deallocate_tree(root) };
+}
+
+#[test]
+fn instrumented_search_skips_prefix_bytes_not_stored() {
+    let keys: Vec<Box<[u8]>> = (1..=3)
+        .map(|last| [7; 32].into_iter().chain([last]).collect())
+        .collect();
+    let root = setup_tree_from_entries(keys.iter().cloned().zip(0..));
+
+    let (leaf, stats) = unsafe { search_instrumented_unchecked(root, keys[1].as_ref()) };
+    assert_eq!(unsafe { leaf.unwrap().as_value_ref() }, &1);
+    assert_eq!(stats.prefix_bytes_compared, NUM_PREFIX_BYTES);
+    assert_eq!(stats.prefix_bytes_skipped, 32 - NUM_PREFIX_BYTES);
+
+    assert_eq!(stats.fingerprint_rejections, 0);
+
+    // A mismatch in the skipped bytes is found at the inner node by the fingerprint
+    let mut missing_key = keys[1].to_vec();
+    missing_key[20] = 0;
+    let (leaf, stats) = unsafe { search_instrumented_unchecked(root, missing_key.as_slice()) };
+    assert!(leaf.is_none());
+    assert_eq!(stats.leaves_visited, 0);
+    assert_eq!(stats.fingerprint_rejections, 1);
+    assert_eq!(stats.prefix_bytes_skipped, 0);
+
+    // A mismatch in the stored bytes is found at the inner node
+    let mut missing_key = keys[1].to_vec();
+    missing_key[3] = 0;
+    let (leaf, stats) = unsafe { search_instrumented_unchecked(root, missing_key.as_slice()) };
+    assert!(leaf.is_none());
+    assert_eq!(stats.leaves_visited, 0);
+    assert_eq!(stats.prefix_bytes_compared, 4);
+    assert_eq!(stats.prefix_bytes_skipped, 0);
+    assert_eq!(stats.fingerprint_rejections, 0);
+
+    // SAFETY: The tree is not used after this point
+    unsafe { deallocate_tree(root) };
+}
@@ -0,0 +1,405 @@
+use crate::{
+    allocator::{Allocator, Global},
+    visitor::inner_node_children,
+    AsBytes, ConcreteNodePtr, InnerNode, InnerNode16, InnerNode256, InnerNode4, InnerNode48,
+    LeafNode, NodePtr, OpaqueNodePtr,
+};
+use std::cmp::Ordering;
+
+/// A child of an inner node, with its key byte in that node.
+pub(crate) type KeyedChild<K, V> = (u8, OpaqueNodePtr<K, V>);
+
+/// The results of a successful merge operation
+#[derive(Debug)]
+pub struct MergeResult<K, V> {
+    /// The root of the merged tree.
+    pub new_root: OpaqueNodePtr<K, V>,
+    /// The leaves of the first tree which had the same key as a leaf of the
+    /// second tree, and were replaced by that leaf.
+    pub replaced_leaves: Vec<LeafNode<K, V>>,
+}
+
+/// Merge the second tree into the first, and return the root of the merged
+/// tree.
+///
+/// The nodes of both trees are reused. A subtree which only has keys that are
+/// missing from the other tree is moved into the merged tree as it is, and
+/// only the inner nodes on the paths shared by both trees are rebuilt with the
+/// union of their children. When both trees have a leaf with the same key, the
+/// leaf of the second tree is kept and the leaf of the first tree is returned
+/// in the result.
+///
+/// # Panics
+///
+///  - Panics if a key of one tree is a prefix of a key of the other tree. The
+///    nodes of both trees may be leaked in that case.
+///
+/// # Safety
+///
+///  - `root` and `other_root` must be unique pointers to the roots of two
+///    different trees.
+///  - This function cannot be called concurrently to any reads or writes of
+///    either tree. This function will arbitrarily read or write to any node in
+///    the given trees.
+pub unsafe fn merge_trees<K, V>(
+    root: OpaqueNodePtr<K, V>,
+    other_root: OpaqueNodePtr<K, V>,
+) -> MergeResult<K, V>
+where
+    K: AsBytes,
+{
+    // SAFETY: Covered by the safety requirements of this function
+    unsafe { merge_trees_in(root, other_root, &Global) }
+}
+
+/// Merge the second tree into the first with the given allocator, and return
+/// the root of the merged tree.
+///
+/// See [`merge_trees`] for details.
+///
+/// # Panics
+///
+///  - Panics if a key of one tree is a prefix of a key of the other tree. The
+///    nodes of both trees may be leaked in that case.
+///
+/// # Safety
+///
+///  - `root` and `other_root` must be unique pointers to the roots of two
+///    different trees.
+///  - This function cannot be called concurrently to any reads or writes of
+///    either tree. This function will arbitrarily read or write to any node in
+///    the given trees.
+///  - The nodes of both trees must have been allocated by the given allocator.
+pub unsafe fn merge_trees_in<K, V, A>(
+    root: OpaqueNodePtr<K, V>,
+    other_root: OpaqueNodePtr<K, V>,
+    alloc: &A,
+) -> MergeResult<K, V>
+where
+    K: AsBytes,
+    A: Allocator,
+{
+    let mut replaced_leaves = Vec::new();
+    // SAFETY: Covered by the safety requirements of this function
+    let new_root = unsafe { merge_nodes(root, other_root, 0, &mut replaced_leaves, alloc) };
+
+    MergeResult {
+        new_root,
+        replaced_leaves,
+    }
+}
+
+/// The bytes on the path to a node which follow the key bytes of its parent.
+struct NodePath {
+    /// The compressed path of an inner node, or the rest of the key of a leaf.
+    bytes: Vec<u8>,
+    /// True if the node is a leaf, in which case no key in the subtree extends
+    /// past the path.
+    is_leaf: bool,
+}
+
+impl NodePath {
+    /// Read the path of the given node, which is reached by `depth` key bytes.
+    ///
+    /// # Safety
+    ///
+    ///  - For the duration of this function, the given node must not get
+    ///    mutated.
+    unsafe fn read<K: AsBytes, V>(node: OpaqueNodePtr<K, V>, depth: usize) -> Self {
+        // SAFETY: The references only live for this function, and the safety
+        // requirements of this function forbid mutation of the node. The node is
+        // reached by `depth` key bytes.
+        let bytes = unsafe {
+            match node.cast::<LeafNode<K, V>>() {
+                Some(leaf_ptr) => &leaf_ptr.as_key_ref().as_bytes()[depth..],
+                None => node.read_full_prefix(depth).unwrap(),
+            }
+        };
+
+        NodePath {
+            bytes: bytes.to_vec(),
+            is_leaf: node.is::<LeafNode<K, V>>(),
+        }
+    }
+
+    /// Return the number of path bytes that an inner node consumes before its
+    /// children, or `usize::MAX` for a leaf.
+    fn inner_len(&self) -> usize {
+        if self.is_leaf {
+            usize::MAX
+        } else {
+            self.bytes.len()
+        }
+    }
+}
+
+/// Merge two nodes which are both reached by the same first `depth` key bytes,
+/// and return the merged node.
+///
+/// # Safety
+///
+///  - `node` and `other_node` must be unique pointers to nodes of two different
+///    trees, and no other access to these nodes or their children can happen
+///    while this function runs.
+///  - The nodes must have been allocated by the given allocator.
+unsafe fn merge_nodes<K, V, A>(
+    node: OpaqueNodePtr<K, V>,
+    other_node: OpaqueNodePtr<K, V>,
+    depth: usize,
+    replaced_leaves: &mut Vec<LeafNode<K, V>>,
+    alloc: &A,
+) -> OpaqueNodePtr<K, V>
+where
+    K: AsBytes,
+    A: Allocator,
+{
+    // SAFETY: Covered by the safety requirements of this function
+    let (path, other_path) = unsafe {
+        (
+            NodePath::read(node, depth),
+            NodePath::read(other_node, depth),
+        )
+    };
+    let matched = path
+        .bytes
+        .iter()
+        .zip(&other_path.bytes)
+        .take_while(|(byte, other_byte)| byte == other_byte)
+        .count();
+    let (len, other_len) = (path.inner_len(), other_path.inner_len());
+
+    if path.is_leaf && other_path.is_leaf && path.bytes == other_path.bytes {
+        // PANIC SAFETY: The path was read from a leaf
+        let leaf_ptr = node.cast::<LeafNode<K, V>>().unwrap();
+        // SAFETY: The leaf is replaced by the other leaf in the merged tree, so this is
+        // the only pointer to it.
+        replaced_leaves.push(unsafe { NodePtr::deallocate_node_ptr_in(leaf_ptr, alloc) });
+        return other_node;
+    }
+
+    if matched < len && matched < other_len {
+        // The paths diverge, so the nodes become the only two children of a new node
+        let (Some(&key_byte), Some(&other_key_byte)) =
+            (path.bytes.get(matched), other_path.bytes.get(matched))
+        else {
+            panic!("a key of one tree should not be a prefix of a key of the other tree");
+        };
+
+        // SAFETY: Covered by the safety requirements of this function
+        unsafe {
+            ltrim_prefix(node, matched + 1, depth);
+            ltrim_prefix(other_node, matched + 1, depth);
+        }
+
+        let children = if key_byte < other_key_byte {
+            [(key_byte, node), (other_key_byte, other_node)]
+        } else {
+            [(other_key_byte, other_node), (key_byte, node)]
+        };
+        // SAFETY: Both nodes were unlinked from their parents, so these are the only
+        // pointers to them.
+        let new_node = unsafe { build_inner_node_in(&path.bytes[..matched], &children, alloc) };
+        // PANIC SAFETY: There are two children, so the node is not empty
+        return new_node.unwrap();
+    }
+
+    // One of the paths ends inside the other, so the shorter one is an inner node
+    // which the other node is merged into.
+    let (prefix, children, other_children) = match len.cmp(&other_len) {
+        Ordering::Equal => {
+            // SAFETY: Covered by the safety requirements of this function
+            let children = unsafe { take_children_in(node, alloc) };
+            // SAFETY: Covered by the safety requirements of this function
+            let other_children = unsafe { take_children_in(other_node, alloc) };
+            (path.bytes, children, other_children)
+        },
+        Ordering::Less => {
+            let Some(&other_key_byte) = other_path.bytes.get(len) else {
+                panic!("a key of one tree should not be a prefix of a key of the other tree");
+            };
+            // SAFETY: Covered by the safety requirements of this function
+            unsafe { ltrim_prefix(other_node, len + 1, depth) };
+            // SAFETY: Covered by the safety requirements of this function
+            let children = unsafe { take_children_in(node, alloc) };
+            (path.bytes, children, vec![(other_key_byte, other_node)])
+        },
+        Ordering::Greater => {
+            let Some(&key_byte) = path.bytes.get(other_len) else {
+                panic!("a key of one tree should not be a prefix of a key of the other tree");
+            };
+            // SAFETY: Covered by the safety requirements of this function
+            unsafe { ltrim_prefix(node, other_len + 1, depth) };
+            // SAFETY: Covered by the safety requirements of this function
+            let other_children = unsafe { take_children_in(other_node, alloc) };
+            (other_path.bytes, vec![(key_byte, node)], other_children)
+        },
+    };
+
+    let child_depth = depth + prefix.len() + 1;
+    let mut merged_children = Vec::with_capacity(children.len() + other_children.len());
+    let mut children = children.into_iter().peekable();
+    let mut other_children = other_children.into_iter().peekable();
+    loop {
+        let next = match (children.peek(), other_children.peek()) {
+            (Some((key_byte, _)), Some((other_key_byte, _))) => key_byte.cmp(other_key_byte),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => break,
+        };
+
+        match next {
+            // PANIC SAFETY: The peeked child is present
+            Ordering::Less => merged_children.push(children.next().unwrap()),
+            // PANIC SAFETY: The peeked child is present
+            Ordering::Greater => merged_children.push(other_children.next().unwrap()),
+            Ordering::Equal => {
+                // PANIC SAFETY: Both peeked children are present
+                let (key_byte, child) = children.next().unwrap();
+                let (_, other_child) = other_children.next().unwrap();
+                // SAFETY: The children were unlinked from their parents, so these are the
+                // only pointers to them.
+                let merged_child =
+                    unsafe { merge_nodes(child, other_child, child_depth, replaced_leaves, alloc) };
+                merged_children.push((key_byte, merged_child));
+            },
+        }
+    }
+
+    // SAFETY: The merged children were unlinked from their parents, which were
+    // deallocated, so these are the only pointers to them.
+    let new_node = unsafe { build_inner_node_in(&prefix, &merged_children, alloc) };
+    // PANIC SAFETY: Both nodes had at least one child
+    new_node.unwrap()
+}
+
+/// Remove the given number of bytes from the start of the compressed path, if
+/// the node is an inner node which is reached by `depth` key bytes.
+///
+/// # Safety
+///
+///  - No other access or mutation to the node or its children can happen while
+///    this function runs.
+unsafe fn ltrim_prefix<K: AsBytes, V>(node: OpaqueNodePtr<K, V>, num_bytes: usize, depth: usize) {
+    if !node.is::<LeafNode<K, V>>() {
+        // SAFETY: Covered by the safety requirements of this function
+        unsafe { node.ltrim_full_prefix(num_bytes, depth) };
+    }
+}
+
+/// Deallocate the given inner node without its children, and return the
+/// children in key order.
+///
+/// # Safety
+///
+///  - `node` must be a unique pointer to an inner node, and no other access to
+///    the node can happen while this function runs.
+///  - The node must have been allocated by the given allocator.
+///
+/// # Panics
+///
+///  - Panics if the node is a leaf.
+pub(crate) unsafe fn take_children_in<K, V, A: Allocator>(
+    node: OpaqueNodePtr<K, V>,
+    alloc: &A,
+) -> Vec<KeyedChild<K, V>> {
+    // SAFETY: The iterator is consumed in this statement, before the node is
+    // deallocated.
+    let children = unsafe { inner_node_children(node) }
+        .expect("node should be an inner node")
+        .collect();
+
+    // SAFETY: The node is a unique pointer, by the safety requirements of this
+    // function, and the children are no longer reachable from it once it is
+    // deallocated.
+    unsafe {
+        match node.to_node_ptr() {
+            ConcreteNodePtr::Node4(inner_ptr) => {
+                drop(NodePtr::deallocate_node_ptr_in(inner_ptr, alloc));
+            },
+            ConcreteNodePtr::Node16(inner_ptr) => {
+                drop(NodePtr::deallocate_node_ptr_in(inner_ptr, alloc));
+            },
+            ConcreteNodePtr::Node48(inner_ptr) => {
+                drop(NodePtr::deallocate_node_ptr_in(inner_ptr, alloc));
+            },
+            ConcreteNodePtr::Node256(inner_ptr) => {
+                drop(NodePtr::deallocate_node_ptr_in(inner_ptr, alloc));
+            },
+            ConcreteNodePtr::LeafNode(_) => unreachable!("node was checked to be an inner node"),
+        }
+    }
+
+    children
+}
+
+/// Allocate the smallest inner node that fits the given children, with the
+/// given compressed path, and return it.
+///
+/// A single child is not wrapped in a new node, instead the compressed path and
+/// key byte are prepended to the compressed path of the child, and the child is
+/// returned. If there are no children, returns `None`.
+///
+/// # Safety
+///
+///  - The children must be sorted by key byte, and must not be reachable from
+///    any other node.
+///  - No other access to the children can happen while this function runs.
+pub(crate) unsafe fn build_inner_node_in<K, V, A: Allocator>(
+    prefix: &[u8],
+    children: &[KeyedChild<K, V>],
+    alloc: &A,
+) -> Option<OpaqueNodePtr<K, V>> {
+    fn fill_inner_node<N: InnerNode, A: Allocator>(
+        mut node: N,
+        prefix: &[u8],
+        children: &[KeyedChild<N::Key, N::Value>],
+        alloc: &A,
+    ) -> OpaqueNodePtr<N::Key, N::Value> {
+        node.header_mut().extend_prefix(prefix);
+        for (key_byte, child) in children {
+            node.write_child(*key_byte, *child);
+        }
+        NodePtr::allocate_node_ptr_in(node, alloc).to_opaque()
+    }
+
+    match children.len() {
+        0 => None,
+        1 => {
+            let (key_byte, child) = children[0];
+            // SAFETY: The reference only lives for this block, and the safety
+            // requirements of this function forbid any other access to the child.
+            if let Some(child_header) = unsafe { child.header_mut() } {
+                let new_bytes = [prefix, &[key_byte]].concat();
+                child_header.prepend_prefix(&new_bytes);
+            }
+            Some(child)
+        },
+        2..=4 => Some(fill_inner_node(
+            InnerNode4::empty(),
+            prefix,
+            children,
+            alloc,
+        )),
+        5..=16 => Some(fill_inner_node(
+            InnerNode16::empty(),
+            prefix,
+            children,
+            alloc,
+        )),
+        17..=48 => Some(fill_inner_node(
+            InnerNode48::empty(),
+            prefix,
+            children,
+            alloc,
+        )),
+        _ => Some(fill_inner_node(
+            InnerNode256::empty(),
+            prefix,
+            children,
+            alloc,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests;
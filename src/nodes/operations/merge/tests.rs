@@ -0,0 +1,103 @@
+use super::*;
+use crate::{
+    deallocate_tree,
+    tests_common::{generate_key_fixed_length, generate_keys_skewed, setup_tree_from_entries},
+    visitor::WellFormedChecker,
+    TreeIterator,
+};
+
+type Entries = Vec<(Box<[u8]>, usize)>;
+
+fn tree_entries(root: OpaqueNodePtr<Box<[u8]>, usize>) -> Entries {
+    // SAFETY: There are no mutations of the tree while the iterator is live
+    unsafe { TreeIterator::new(root) }
+        .map(|leaf_ptr| {
+            let (key, value) = unsafe { leaf_ptr.as_key_value_ref() };
+            (key.clone(), *value)
+        })
+        .collect()
+}
+
+/// Merge the trees built from the given entries, and check that the merged
+/// tree is well-formed and holds the union of the entries, where the second
+/// tree wins on duplicate keys.
+fn assert_merge_matches_union(entries: Entries, other_entries: Entries) {
+    let mut expected = std::collections::BTreeMap::new();
+    expected.extend(entries.iter().cloned());
+    expected.extend(other_entries.iter().cloned());
+    let num_duplicates = entries.len() + other_entries.len() - expected.len();
+
+    let root = setup_tree_from_entries(entries.into_iter());
+    let other_root = setup_tree_from_entries(other_entries.into_iter());
+
+    let MergeResult {
+        new_root,
+        replaced_leaves,
+    } = unsafe { merge_trees(root, other_root) };
+
+    unsafe { WellFormedChecker::check_tree(new_root) }.unwrap();
+    assert_eq!(replaced_leaves.len(), num_duplicates);
+    assert_eq!(
+        tree_entries(new_root),
+        expected.into_iter().collect::<Entries>()
+    );
+
+    unsafe { deallocate_tree(new_root) };
+}
+
+#[test]
+fn merge_interleaved_keys() {
+    let keys: Vec<_> = generate_key_fixed_length([3, 3, 3]).collect();
+    let (even, odd): (Vec<_>, Vec<_>) = keys.into_iter().zip(0..).partition(|(_, n)| n % 2 == 0);
+
+    assert_merge_matches_union(even.clone(), odd.clone());
+    assert_merge_matches_union(odd, even);
+}
+
+#[test]
+fn merge_overlapping_keys() {
+    let keys: Vec<_> = generate_keys_skewed(48).collect();
+    let entries: Entries = keys[..30].iter().cloned().zip(0..).collect();
+    let other_entries: Entries = keys[10..].iter().cloned().zip(100..).collect();
+
+    assert_merge_matches_union(entries.clone(), other_entries.clone());
+    assert_merge_matches_union(other_entries, entries);
+}
+
+#[test]
+fn merge_grows_shared_nodes() {
+    // Both trees have a single inner node with the same compressed path, and the
+    // merged node needs every node type in turn.
+    for num_children in [3, 10, 30, 100] {
+        let key = |byte: u8| Box::from([7, 7, byte, 1].as_slice());
+        let entries = (0..num_children).map(|byte| (key(2 * byte), 0)).collect();
+        let other_entries = (0..num_children)
+            .map(|byte| (key(2 * byte + 1), 1))
+            .collect();
+
+        assert_merge_matches_union(entries, other_entries);
+    }
+}
+
+#[test]
+fn merge_diverging_compressed_paths() {
+    let keys = |path: [u8; 3]| -> Entries {
+        (0..5)
+            .map(|last| {
+                (
+                    Box::from([path.as_slice(), &[last]].concat()),
+                    usize::from(last),
+                )
+            })
+            .collect()
+    };
+
+    // The compressed paths of the roots diverge after the first byte
+    assert_merge_matches_union(keys([1, 2, 3]), keys([1, 9, 9]));
+    // The compressed path of one root ends inside the other
+    assert_merge_matches_union(keys([1, 2, 3]), [keys([1, 5, 5]), keys([1, 6, 6])].concat());
+    assert_merge_matches_union([keys([1, 5, 5]), keys([1, 6, 6])].concat(), keys([1, 2, 3]));
+    // A single leaf going into a child which is an inner node
+    assert_merge_matches_union(keys([1, 2, 3]), vec![(Box::from([1, 2, 4, 0]), 9)]);
+    assert_merge_matches_union(vec![(Box::from([1, 2, 3, 4]), 9)], keys([1, 2, 3]));
+}
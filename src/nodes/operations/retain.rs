@@ -0,0 +1,311 @@
+use crate::{
+    allocator::Allocator, ConcreteNodePtr, InnerNode, LeafNode, NodePtr, OpaqueNodePtr,
+    ResizePolicy,
+};
+
+/// An inner node on the current path of a [`RetainWalk`].
+struct RetainFrame<K, V> {
+    /// The inner node, which may already have lost some of its children.
+    node: OpaqueNodePtr<K, V>,
+    /// The key byte of this node in its parent, which is ignored for the root.
+    key_byte: u8,
+    /// The smallest key byte which was not visited yet, or `None` once every
+    /// child was visited.
+    next_key_byte: Option<u8>,
+}
+
+/// A single walk over the leaves of a tree in key order, which unlinks every
+/// leaf that is rejected by a predicate.
+///
+/// Rejected leaves are removed from their parent in place. Once every child of
+/// an inner node was visited, the node is compressed: it is deallocated if it
+/// has no children left, replaced by its child if it has a single one, and
+/// otherwise shrunk as many times as the [`ResizePolicy`] requires. The
+/// replacement is written into the parent right away, so the tree never holds
+/// a pointer to a deallocated node.
+///
+/// Until the walk is finished, the inner nodes on its current path may have
+/// fewer children than their node type allows. Once
+/// [`RetainWalk::next_rejected`] returns `None`, the tree is well-formed again
+/// and [`RetainWalk::root`] returns its new root.
+///
+/// The inner nodes on the current path are kept on a heap allocated stack, so
+/// the depth of the tree does not affect the stack usage of the walk.
+pub(crate) struct RetainWalk<K, V> {
+    root: Option<OpaqueNodePtr<K, V>>,
+    stack: Vec<RetainFrame<K, V>>,
+    started: bool,
+}
+
+impl<K, V> RetainWalk<K, V> {
+    /// Start a walk over the tree with the given root.
+    ///
+    /// # Safety
+    ///
+    ///  - `root` must be a unique pointer to the tree, and the tree must not be
+    ///    accessed other than through this walk until it is finished.
+    pub(crate) unsafe fn new(root: Option<OpaqueNodePtr<K, V>>) -> Self {
+        RetainWalk {
+            root,
+            stack: Vec::new(),
+            started: false,
+        }
+    }
+
+    /// Return the root of the tree, which is `None` if every leaf was removed.
+    pub(crate) fn root(&self) -> Option<OpaqueNodePtr<K, V>> {
+        self.root
+    }
+
+    /// Continue the walk up to the next leaf for which `reject` returns true,
+    /// unlink it from the tree and return it. Returns `None` once every leaf
+    /// was visited.
+    ///
+    /// The returned leaf is no longer reachable from the tree, so the caller
+    /// is responsible for deallocating it.
+    ///
+    /// If `reject` panics, the leaf it was called with stays in the tree and
+    /// the walk can be continued past it.
+    ///
+    /// # Safety
+    ///
+    ///  - The nodes of the tree must have been allocated by the given
+    ///    allocator.
+    pub(crate) unsafe fn next_rejected<F, A>(
+        &mut self,
+        mut reject: F,
+        policy: ResizePolicy,
+        alloc: &A,
+    ) -> Option<NodePtr<LeafNode<K, V>>>
+    where
+        F: FnMut(&K, &mut V) -> bool,
+        A: Allocator,
+    {
+        if !self.started {
+            self.started = true;
+            let root = self.root?;
+
+            match root.cast::<LeafNode<K, V>>() {
+                Some(leaf_ptr) => {
+                    // SAFETY: The reference only lives for the call of the closure, and the
+                    // safety requirements of `new` forbid any other access to the tree.
+                    let (key, value) = unsafe { leaf_ptr.as_key_ref_value_mut() };
+                    if reject(key, value) {
+                        self.root = None;
+                        return Some(leaf_ptr);
+                    }
+                    return None;
+                },
+                None => self.stack.push(RetainFrame {
+                    node: root,
+                    key_byte: 0,
+                    next_key_byte: Some(0),
+                }),
+            }
+        }
+
+        loop {
+            let frame = self.stack.last_mut()?;
+
+            // SAFETY: The safety requirements of `new` forbid any other access to the
+            // tree, and the node is not mutated while the iterator is live.
+            let next_child = frame
+                .next_key_byte
+                .and_then(|key_byte| unsafe { first_child_from(frame.node, key_byte) });
+
+            let Some((key_byte, child)) = next_child else {
+                let frame = self.stack.pop().expect("stack should not be empty");
+                // SAFETY: Every child of the node was visited, and the safety requirements
+                // of `new` forbid any other access to the tree.
+                let replacement = unsafe { compress_node(frame.node, policy, alloc) };
+
+                match self.stack.last() {
+                    // SAFETY: The parent is an inner node on the path of the walk, and the
+                    // safety requirements of `new` forbid any other access to it.
+                    Some(parent) => unsafe {
+                        match replacement {
+                            Some(new_node) if new_node == frame.node => {},
+                            Some(new_node) => write_child(parent.node, frame.key_byte, new_node),
+                            None => remove_child(parent.node, frame.key_byte),
+                        }
+                    },
+                    None => self.root = replacement,
+                }
+                continue;
+            };
+
+            // The key byte is moved past the child before the closure is called, so
+            // that a panicking closure leaves the walk after this leaf.
+            frame.next_key_byte = key_byte.checked_add(1);
+
+            match child.cast::<LeafNode<K, V>>() {
+                Some(leaf_ptr) => {
+                    // SAFETY: The reference only lives for the call of the closure, and the
+                    // safety requirements of `new` forbid any other access to the tree.
+                    let (key, value) = unsafe { leaf_ptr.as_key_ref_value_mut() };
+                    if reject(key, value) {
+                        // SAFETY: The parent is an inner node on the path of the walk, and
+                        // the safety requirements of `new` forbid any other access to it.
+                        unsafe { remove_child(frame.node, key_byte) };
+                        return Some(leaf_ptr);
+                    }
+                },
+                None => self.stack.push(RetainFrame {
+                    node: child,
+                    key_byte,
+                    next_key_byte: Some(0),
+                }),
+            }
+        }
+    }
+}
+
+/// Return the child of the inner node with the smallest key byte that is
+/// greater than or equal to `key_byte`.
+///
+/// # Safety
+///
+///  - For the duration of this function, the given node must not get mutated.
+unsafe fn first_child_from<K, V>(
+    node: OpaqueNodePtr<K, V>,
+    key_byte: u8,
+) -> Option<(u8, OpaqueNodePtr<K, V>)> {
+    // SAFETY: The references and iterators only live for this function, and the
+    // safety requirements of this function forbid mutation of the node.
+    unsafe {
+        match node.to_node_ptr() {
+            ConcreteNodePtr::Node4(inner_ptr) => inner_ptr.as_ref().range(key_byte..).next(),
+            ConcreteNodePtr::Node16(inner_ptr) => inner_ptr.as_ref().range(key_byte..).next(),
+            ConcreteNodePtr::Node48(inner_ptr) => inner_ptr.as_ref().range(key_byte..).next(),
+            ConcreteNodePtr::Node256(inner_ptr) => inner_ptr.as_ref().range(key_byte..).next(),
+            ConcreteNodePtr::LeafNode(_) => unreachable!("only inner nodes are walked"),
+        }
+    }
+}
+
+/// Overwrite the child at the given key byte of the inner node.
+///
+/// # Safety
+///
+///  - No other access or mutation to the `parent` node can happen while this
+///    function runs.
+unsafe fn write_child<K, V>(parent: OpaqueNodePtr<K, V>, key_byte: u8, child: OpaqueNodePtr<K, V>) {
+    // SAFETY: The mutable references only live for this function, and the safety
+    // requirements of this function forbid any other access to the node.
+    unsafe {
+        match parent.to_node_ptr() {
+            ConcreteNodePtr::Node4(inner_ptr) => inner_ptr.as_mut().write_child(key_byte, child),
+            ConcreteNodePtr::Node16(inner_ptr) => inner_ptr.as_mut().write_child(key_byte, child),
+            ConcreteNodePtr::Node48(inner_ptr) => inner_ptr.as_mut().write_child(key_byte, child),
+            ConcreteNodePtr::Node256(inner_ptr) => inner_ptr.as_mut().write_child(key_byte, child),
+            ConcreteNodePtr::LeafNode(_) => unreachable!("only inner nodes are parents"),
+        }
+    }
+}
+
+/// Remove the child at the given key byte from the inner node, without
+/// compressing the node.
+///
+/// # Safety
+///
+///  - No other access or mutation to the `parent` node can happen while this
+///    function runs.
+unsafe fn remove_child<K, V>(parent: OpaqueNodePtr<K, V>, key_byte: u8) {
+    // SAFETY: The mutable references only live for this function, and the safety
+    // requirements of this function forbid any other access to the node.
+    let removed = unsafe {
+        match parent.to_node_ptr() {
+            ConcreteNodePtr::Node4(inner_ptr) => inner_ptr.as_mut().remove_child(key_byte),
+            ConcreteNodePtr::Node16(inner_ptr) => inner_ptr.as_mut().remove_child(key_byte),
+            ConcreteNodePtr::Node48(inner_ptr) => inner_ptr.as_mut().remove_child(key_byte),
+            ConcreteNodePtr::Node256(inner_ptr) => inner_ptr.as_mut().remove_child(key_byte),
+            ConcreteNodePtr::LeafNode(_) => unreachable!("only inner nodes are parents"),
+        }
+    };
+    debug_assert!(removed.is_some(), "child should be present");
+}
+
+/// Compress the inner node after some of its children were removed, and
+/// return the node which takes its place, or `None` if it had no children
+/// left.
+///
+/// # Safety
+///
+///  - `node` must be a unique pointer to an inner node, and no other access to
+///    the node or its children can happen while this function runs.
+///  - The node must have been allocated by the given allocator.
+unsafe fn compress_node<K, V, A: Allocator>(
+    node: OpaqueNodePtr<K, V>,
+    policy: ResizePolicy,
+    alloc: &A,
+) -> Option<OpaqueNodePtr<K, V>> {
+    // SAFETY: Covered by the safety requirements of this function
+    unsafe {
+        match node.to_node_ptr() {
+            ConcreteNodePtr::Node4(inner_ptr) => compress_inner_node(inner_ptr, policy, alloc),
+            ConcreteNodePtr::Node16(inner_ptr) => compress_inner_node(inner_ptr, policy, alloc),
+            ConcreteNodePtr::Node48(inner_ptr) => compress_inner_node(inner_ptr, policy, alloc),
+            ConcreteNodePtr::Node256(inner_ptr) => compress_inner_node(inner_ptr, policy, alloc),
+            ConcreteNodePtr::LeafNode(_) => unreachable!("only inner nodes are compressed"),
+        }
+    }
+}
+
+/// Compress the inner node after some of its children were removed.
+///
+/// See [`compress_node`] for details.
+///
+/// # Safety
+///
+///  - `inner_node_ptr` must be a unique pointer to the node, and no other
+///    access to the node or its children can happen while this function runs.
+///  - The node must have been allocated by the given allocator.
+unsafe fn compress_inner_node<N: InnerNode, A: Allocator>(
+    inner_node_ptr: NodePtr<N>,
+    policy: ResizePolicy,
+    alloc: &A,
+) -> Option<OpaqueNodePtr<N::Key, N::Value>> {
+    // SAFETY: The reference is dropped before the node is deallocated, and it is
+    // unique by the safety requirements of this function.
+    let inner_node = unsafe { inner_node_ptr.as_mut() };
+    let num_children = inner_node.header().num_children();
+
+    let replacement = match num_children {
+        0 => None,
+        1 => {
+            // SAFETY: The iterator is consumed in this statement, and the node is not
+            // mutated while it is live.
+            let (child_key_byte, child_node_ptr) = unsafe { inner_node.iter() }
+                .next()
+                .expect("expected single child");
+
+            // SAFETY: By the safety requirements of the function, there are no other
+            // references to this child node.
+            if let Some(child_header) = unsafe { child_node_ptr.header_mut() } {
+                // This needs to go in reverse order, since prepend_prefix always writes to the
+                // front
+                child_header.prepend_prefix(&[child_key_byte]);
+                child_header.prepend_header_prefix(inner_node.header());
+            }
+
+            Some(child_node_ptr)
+        },
+        _ if policy.should_shrink(N::TYPE, num_children) => {
+            let shrunk_node_ptr = NodePtr::allocate_node_ptr_in(inner_node.shrink(), alloc);
+
+            // SAFETY: The shrunk node was just allocated, so this is the only pointer to
+            // it. It may need to shrink again if many children were removed.
+            unsafe { compress_inner_node(shrunk_node_ptr, policy, alloc) }
+        },
+        _ => return Some(inner_node_ptr.to_opaque()),
+    };
+
+    // SAFETY: The node is a unique pointer, by the safety requirements of this
+    // function, and its remaining children are owned by the replacement.
+    drop(unsafe { NodePtr::deallocate_node_ptr_in(inner_node_ptr, alloc) });
+
+    replacement
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,129 @@
+use super::*;
+use crate::{
+    allocator::Global,
+    deallocate_tree,
+    tests_common::{
+        generate_key_fixed_length, generate_key_with_prefix, generate_keys_skewed,
+        setup_tree_from_entries, PrefixExpansion,
+    },
+    visitor::WellFormedChecker,
+    TreeIterator,
+};
+use std::panic::{self, AssertUnwindSafe};
+
+fn tree_keys(root: Option<OpaqueNodePtr<Box<[u8]>, usize>>) -> Vec<Box<[u8]>> {
+    root.map_or_else(Vec::new, |root| {
+        // SAFETY: There are no mutations of the tree while the iterator is live
+        unsafe { TreeIterator::new(root) }
+            .map(|leaf_ptr| unsafe { leaf_ptr.as_key_ref() }.clone())
+            .collect()
+    })
+}
+
+/// Remove the keys at the indices rejected by the predicate in a single walk,
+/// and check that the removed and remaining keys are the expected ones and
+/// that the remaining tree is well-formed.
+fn assert_retain_matches_keys(
+    keys: Vec<Box<[u8]>>,
+    policy: ResizePolicy,
+    mut reject: impl FnMut(usize) -> bool,
+) {
+    let mut sorted_keys = keys.clone();
+    sorted_keys.sort();
+    let root = setup_tree_from_entries(sorted_keys.iter().cloned().zip(0..));
+
+    let mut walk = unsafe { RetainWalk::new(Some(root)) };
+    let mut removed_keys = Vec::new();
+    while let Some(leaf_ptr) =
+        unsafe { walk.next_rejected(|_, value| reject(*value), policy, &Global) }
+    {
+        let (key, _) = unsafe { NodePtr::deallocate_node_ptr(leaf_ptr) }.into_entry();
+        removed_keys.push(key);
+    }
+
+    let (expected_removed, expected_remaining): (Vec<_>, Vec<_>) = sorted_keys
+        .into_iter()
+        .enumerate()
+        .partition(|(idx, _)| reject(*idx));
+    let expected_removed: Vec<_> = expected_removed.into_iter().map(|(_, key)| key).collect();
+    let expected_remaining: Vec<_> = expected_remaining.into_iter().map(|(_, key)| key).collect();
+
+    assert_eq!(removed_keys, expected_removed);
+    assert_eq!(tree_keys(walk.root()), expected_remaining);
+    if let Some(root) = walk.root() {
+        unsafe { WellFormedChecker::check_tree_with_policy(root, policy) }.unwrap();
+        unsafe { deallocate_tree(root) };
+    }
+}
+
+#[test]
+fn retain_removes_rejected_leaves() {
+    for policy in [ResizePolicy::DEFAULT, ResizePolicy::HYSTERESIS] {
+        assert_retain_matches_keys(vec![Box::new([1, 2, 3])], policy, |_| true);
+        assert_retain_matches_keys(vec![Box::new([1, 2, 3])], policy, |_| false);
+        assert_retain_matches_keys(generate_keys_skewed(64).collect(), policy, |idx| {
+            idx % 3 != 0
+        });
+        // Every node type, with 4, 5, 17 and 49 children at each level
+        let keys: Vec<_> = generate_key_fixed_length([3, 4, 16, 48]).collect();
+        assert_retain_matches_keys(keys.clone(), policy, |_| false);
+        assert_retain_matches_keys(keys.clone(), policy, |_| true);
+        assert_retain_matches_keys(keys.clone(), policy, |idx| idx % 2 == 0);
+        assert_retain_matches_keys(keys.clone(), policy, |idx| idx % 49 != 7);
+        assert_retain_matches_keys(keys, policy, |idx| idx < 10_000);
+    }
+}
+
+#[test]
+fn retain_collapses_long_prefixes() {
+    let keys: Vec<_> = generate_key_with_prefix(
+        [7, 3, 20],
+        [PrefixExpansion {
+            base_index: 1,
+            expanded_length: 30,
+        }],
+    )
+    .collect();
+
+    assert_retain_matches_keys(keys.clone(), ResizePolicy::DEFAULT, |idx| idx % 60 != 0);
+    assert_retain_matches_keys(keys, ResizePolicy::DEFAULT, |idx| (idx / 20) % 3 != 1);
+}
+
+#[test]
+fn retain_panic_keeps_leaf_and_continues() {
+    // 16 leaves with values 0 to 15
+    let keys: Vec<_> = generate_key_fixed_length([3, 3]).collect();
+    let root = setup_tree_from_entries(keys.into_iter().zip(0..));
+
+    let mut walk = unsafe { RetainWalk::new(Some(root)) };
+    let mut removed_values = Vec::new();
+    loop {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| unsafe {
+            walk.next_rejected(
+                |_, value: &mut usize| {
+                    assert_ne!(*value, 5, "reject panicked");
+                    *value < 5
+                },
+                ResizePolicy::DEFAULT,
+                &Global,
+            )
+        }));
+        let Ok(leaf_ptr) = result else {
+            break;
+        };
+        let (_, value) = unsafe { NodePtr::deallocate_node_ptr(leaf_ptr.unwrap()) }.into_entry();
+        removed_values.push(value);
+    }
+    assert_eq!(removed_values, [0, 1, 2, 3, 4]);
+
+    // Finish the walk while keeping every other leaf
+    assert!(unsafe { walk.next_rejected(|_, _| false, ResizePolicy::DEFAULT, &Global) }.is_none());
+
+    let root = walk.root().unwrap();
+    let values: Vec<_> = unsafe { TreeIterator::new(root) }
+        .map(|leaf_ptr| *unsafe { leaf_ptr.as_value_ref() })
+        .collect();
+    assert_eq!(values, (5..16).collect::<Vec<_>>());
+    unsafe { WellFormedChecker::check_tree(root) }.unwrap();
+    unsafe { deallocate_tree(root) };
+}
@@ -0,0 +1,106 @@
+use crate::NodeType;
+
+/// Counters describing the work performed by a single search of the tree.
+///
+/// These are returned by the instrumented variants of the tree operations, like
+/// [`search_instrumented_unchecked`](crate::search_instrumented_unchecked) and
+/// [`insert_instrumented_unchecked`](crate::insert_instrumented_unchecked), so
+/// that the cost of an individual query can be attributed to the shape of the
+/// tree it ran against.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SearchStats {
+    /// The number of [`InnerNode4`](crate::InnerNode4) nodes visited
+    pub node4_visited: usize,
+    /// The number of [`InnerNode16`](crate::InnerNode16) nodes visited
+    pub node16_visited: usize,
+    /// The number of [`InnerNode48`](crate::InnerNode48) nodes visited
+    pub node48_visited: usize,
+    /// The number of [`InnerNode256`](crate::InnerNode256) nodes visited
+    pub node256_visited: usize,
+    /// The number of [`LeafNode`](crate::LeafNode)s visited
+    pub leaves_visited: usize,
+    /// The number of bytes of compressed node prefixes that were compared
+    /// against the search key
+    pub prefix_bytes_compared: usize,
+    /// The number of bytes of compressed node prefixes that were skipped
+    /// without comparing them, because they are not stored in the node header
+    pub prefix_bytes_skipped: usize,
+    /// The number of node prefixes that were rejected because the fingerprint
+    /// of the bytes not stored in the node header did not match the key
+    pub fingerprint_rejections: usize,
+    /// The number of child lookups in inner nodes that used a SIMD
+    /// implementation
+    pub simd_child_lookups: usize,
+    /// The number of child lookups in inner nodes that used a scalar
+    /// implementation
+    pub scalar_child_lookups: usize,
+}
+
+impl SearchStats {
+    /// The total number of nodes visited, of any type.
+    pub fn nodes_visited(&self) -> usize {
+        self.node4_visited
+            + self.node16_visited
+            + self.node48_visited
+            + self.node256_visited
+            + self.leaves_visited
+    }
+}
+
+/// Receives events from a search down the tree.
+///
+/// The implementation for `()` ignores all events, so that the uninstrumented
+/// operations compile down to the same code as before.
+pub(crate) trait SearchRecorder {
+    /// Called once for every node the search visits.
+    fn record_node(&mut self, _node_type: NodeType) {}
+
+    /// Called after comparing `num_bytes` bytes of a node prefix against the
+    /// search key.
+    fn record_prefix_comparison(&mut self, _num_bytes: usize) {}
+
+    /// Called after skipping `num_bytes` bytes of a node prefix which are not
+    /// stored in the node header.
+    fn record_skipped_prefix_bytes(&mut self, _num_bytes: usize) {}
+
+    /// Called when a node prefix is rejected by its fingerprint.
+    fn record_fingerprint_rejection(&mut self) {}
+
+    /// Called after looking up a child of an inner node.
+    fn record_child_lookup(&mut self, _used_simd: bool) {}
+}
+
+impl SearchRecorder for () {}
+
+impl SearchRecorder for SearchStats {
+    fn record_node(&mut self, node_type: NodeType) {
+        let counter = match node_type {
+            NodeType::Node4 => &mut self.node4_visited,
+            NodeType::Node16 => &mut self.node16_visited,
+            NodeType::Node48 => &mut self.node48_visited,
+            NodeType::Node256 => &mut self.node256_visited,
+            NodeType::Leaf => &mut self.leaves_visited,
+        };
+        *counter += 1;
+    }
+
+    fn record_prefix_comparison(&mut self, num_bytes: usize) {
+        self.prefix_bytes_compared += num_bytes;
+    }
+
+    fn record_skipped_prefix_bytes(&mut self, num_bytes: usize) {
+        self.prefix_bytes_skipped += num_bytes;
+    }
+
+    fn record_fingerprint_rejection(&mut self) {
+        self.fingerprint_rejections += 1;
+    }
+
+    fn record_child_lookup(&mut self, used_simd: bool) {
+        if used_simd {
+            self.simd_child_lookups += 1;
+        } else {
+            self.scalar_child_lookups += 1;
+        }
+    }
+}
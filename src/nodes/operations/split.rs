@@ -0,0 +1,158 @@
+use crate::{
+    allocator::{Allocator, Global},
+    build_inner_node_in, take_children_in, AsBytes, LeafNode, OpaqueNodePtr,
+};
+
+/// The results of a successful split operation
+#[derive(Debug)]
+pub struct SplitResult<K, V> {
+    /// The root of the tree holding the keys which are less than the split
+    /// key, or `None` if there are no such keys.
+    pub lower_root: Option<OpaqueNodePtr<K, V>>,
+    /// The root of the tree holding the keys which are greater than or equal
+    /// to the split key, or `None` if there are no such keys.
+    pub upper_root: Option<OpaqueNodePtr<K, V>>,
+}
+
+/// Split the tree into the keys which are less than `split_key`, and the keys
+/// which are greater than or equal to it.
+///
+/// Only the inner nodes on the search path of the split key are rebuilt, every
+/// other subtree is moved into one of the two trees as it is. The rebuilt
+/// nodes are the smallest node type that fits their children, and a node with
+/// a single child is replaced by that child.
+///
+/// # Safety
+///
+///  - The `root` [`OpaqueNodePtr`] must be a unique pointer to the underlying
+///    tree
+///  - This function cannot be called concurrently to any reads or writes of the
+///    `root` node or any child node of `root`. This function will arbitrarily
+///    read or write to any child in the given tree.
+pub unsafe fn split_tree<K, V>(root: OpaqueNodePtr<K, V>, split_key: &[u8]) -> SplitResult<K, V>
+where
+    K: AsBytes,
+{
+    // SAFETY: Covered by the safety requirements of this function
+    unsafe { split_tree_in(root, split_key, &Global) }
+}
+
+/// Split the tree into the keys which are less than `split_key`, and the keys
+/// which are greater than or equal to it, with the given allocator.
+///
+/// See [`split_tree`] for details.
+///
+/// # Safety
+///
+///  - The `root` [`OpaqueNodePtr`] must be a unique pointer to the underlying
+///    tree
+///  - This function cannot be called concurrently to any reads or writes of the
+///    `root` node or any child node of `root`. This function will arbitrarily
+///    read or write to any child in the given tree.
+///  - The nodes of the tree must have been allocated by the given allocator.
+pub unsafe fn split_tree_in<K, V, A>(
+    root: OpaqueNodePtr<K, V>,
+    split_key: &[u8],
+    alloc: &A,
+) -> SplitResult<K, V>
+where
+    K: AsBytes,
+    A: Allocator,
+{
+    // SAFETY: Covered by the safety requirements of this function
+    unsafe { split_node(root, 0, split_key, alloc) }
+}
+
+/// Split the subtree of the given node, which is reached by the first `depth`
+/// bytes of the split key.
+///
+/// # Safety
+///
+///  - `node` must be a unique pointer to the node, and no other access to the
+///    node or its children can happen while this function runs.
+///  - The nodes must have been allocated by the given allocator.
+unsafe fn split_node<K, V, A>(
+    node: OpaqueNodePtr<K, V>,
+    depth: usize,
+    split_key: &[u8],
+    alloc: &A,
+) -> SplitResult<K, V>
+where
+    K: AsBytes,
+    A: Allocator,
+{
+    let lower = SplitResult {
+        lower_root: Some(node),
+        upper_root: None,
+    };
+    let upper = SplitResult {
+        lower_root: None,
+        upper_root: Some(node),
+    };
+
+    if let Some(leaf_ptr) = node.cast::<LeafNode<K, V>>() {
+        // SAFETY: The reference only lives for this statement, and the safety
+        // requirements of this function forbid any other access to the node.
+        let leaf_key = unsafe { leaf_ptr.as_key_ref() }.as_bytes();
+        return if leaf_key < split_key { lower } else { upper };
+    }
+
+    // SAFETY: The reference only lives for this statement, the safety requirements
+    // of this function forbid any other access to the node, and it is reached by
+    // `depth` key bytes.
+    //
+    // PANIC SAFETY: The node is an inner node, checked above
+    let prefix = unsafe { node.read_full_prefix(depth) }.unwrap().to_vec();
+
+    let key_bytes = &split_key[depth..];
+    let matched = prefix
+        .iter()
+        .zip(key_bytes)
+        .take_while(|(prefix_byte, key_byte)| prefix_byte == key_byte)
+        .count();
+
+    // If the split key leaves the compressed path, or ends before the children of
+    // this node, the whole subtree is on one side of it.
+    if matched < prefix.len() {
+        return match key_bytes.get(matched) {
+            Some(key_byte) if *key_byte > prefix[matched] => lower,
+            _ => upper,
+        };
+    }
+    let Some(&split_byte) = key_bytes.get(prefix.len()) else {
+        return upper;
+    };
+
+    // SAFETY: Covered by the safety requirements of this function
+    let children = unsafe { take_children_in(node, alloc) };
+    let mut lower_children = Vec::new();
+    let mut upper_children = Vec::new();
+    for (key_byte, child) in children {
+        if key_byte < split_byte {
+            lower_children.push((key_byte, child));
+        } else if key_byte > split_byte {
+            upper_children.push((key_byte, child));
+        } else {
+            // SAFETY: The child was unlinked from its deallocated parent, so this is the
+            // only pointer to it.
+            let SplitResult {
+                lower_root,
+                upper_root,
+            } = unsafe { split_node(child, depth + prefix.len() + 1, split_key, alloc) };
+            lower_children.extend(lower_root.map(|child| (key_byte, child)));
+            upper_children.extend(upper_root.map(|child| (key_byte, child)));
+        }
+    }
+
+    // SAFETY: The children were unlinked from their deallocated parent, and each
+    // one is only on one side of the split.
+    unsafe {
+        SplitResult {
+            lower_root: build_inner_node_in(&prefix, &lower_children, alloc),
+            upper_root: build_inner_node_in(&prefix, &upper_children, alloc),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
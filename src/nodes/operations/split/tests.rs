@@ -0,0 +1,106 @@
+use super::*;
+use crate::{
+    deallocate_tree,
+    tests_common::{generate_key_fixed_length, generate_keys_skewed, setup_tree_from_entries},
+    visitor::WellFormedChecker,
+    TreeIterator,
+};
+
+fn tree_keys(root: Option<OpaqueNodePtr<Box<[u8]>, usize>>) -> Vec<Box<[u8]>> {
+    root.map_or_else(Vec::new, |root| {
+        // SAFETY: There are no mutations of the tree while the iterator is live
+        unsafe { TreeIterator::new(root) }
+            .map(|leaf_ptr| unsafe { leaf_ptr.as_key_ref() }.clone())
+            .collect()
+    })
+}
+
+/// Split the tree built from the given keys at every one of the split keys,
+/// and check that both halves are well-formed and hold the expected keys.
+fn assert_split_matches_keys(keys: Vec<Box<[u8]>>, split_keys: &[&[u8]]) {
+    let mut sorted_keys = keys.clone();
+    sorted_keys.sort();
+
+    for split_key in split_keys {
+        let root = setup_tree_from_entries(keys.iter().cloned().zip(0..));
+
+        let SplitResult {
+            lower_root,
+            upper_root,
+        } = unsafe { split_tree(root, split_key) };
+
+        for root in lower_root.iter().chain(&upper_root) {
+            unsafe { WellFormedChecker::check_tree(*root) }.unwrap();
+        }
+        let split_idx = sorted_keys.partition_point(|key| key.as_ref() < *split_key);
+        assert_eq!(
+            tree_keys(lower_root),
+            &sorted_keys[..split_idx],
+            "{split_key:?}"
+        );
+        assert_eq!(
+            tree_keys(upper_root),
+            &sorted_keys[split_idx..],
+            "{split_key:?}"
+        );
+
+        for root in lower_root.into_iter().chain(upper_root) {
+            unsafe { deallocate_tree(root) };
+        }
+    }
+}
+
+#[test]
+fn split_fixed_length_keys() {
+    let keys: Vec<_> = generate_key_fixed_length([3, 16, 2]).collect();
+
+    assert_split_matches_keys(
+        keys,
+        &[
+            &[],
+            &[0, 0, 0],
+            &[1, 8, 1],
+            &[1, 8],
+            &[2],
+            &[1, 16, 2, 0],
+            &[3, 16, 2],
+            &[255],
+        ],
+    );
+}
+
+#[test]
+fn split_skewed_keys() {
+    let keys: Vec<_> = generate_keys_skewed(32).collect();
+
+    assert_split_matches_keys(
+        keys,
+        &[
+            &[u8::MAX],
+            &[u8::MAX; 10],
+            &[u8::MAX; 31],
+            &[u8::MAX, 0],
+            &[0],
+        ],
+    );
+}
+
+#[test]
+fn split_inside_compressed_path() {
+    let keys: Vec<Box<[u8]>> = (0..5)
+        .flat_map(|last| [[1, 2, 3, 4, last], [1, 2, 9, 9, last]])
+        .map(|key| Box::from(key.as_slice()))
+        .collect();
+
+    assert_split_matches_keys(
+        keys,
+        &[
+            &[1, 2, 3],
+            &[1, 2, 3, 5],
+            &[1, 2, 5],
+            &[1, 2, 9, 9, 3],
+            &[1, 3],
+            &[0, 9],
+        ],
+    );
+}
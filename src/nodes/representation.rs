@@ -1,8 +1,13 @@
 //! Trie node representation
 
 pub use self::iterators::*;
-use crate::{tagged_pointer::TaggedPointer, AsBytes, InnerNodeIter};
+use crate::{
+    allocator::{Allocator, Global},
+    tagged_pointer::TaggedPointer,
+    AsBytes, InnerNodeIter,
+};
 use std::{
+    alloc::{handle_alloc_error, Layout},
     borrow::Borrow,
     cmp::Ordering,
     error::Error,
@@ -10,12 +15,19 @@ use std::{
     hash::Hash,
     marker::PhantomData,
     mem::{self, ManuallyDrop, MaybeUninit},
-    ops::Range,
+    ops::{Range, RangeBounds},
     ptr::{self, NonNull},
 };
-use tinyvec::TinyVec;
 
 mod iterators;
+#[cfg(feature = "concurrent")]
+mod optimistic;
+mod simd;
+#[cfg(feature = "concurrent")]
+mod version;
+
+#[cfg(feature = "concurrent")]
+pub(crate) use self::version::*;
 
 #[cfg(test)]
 mod tests;
@@ -74,14 +86,10 @@ impl NodeType {
     ///
     /// # Panics
     ///  - Panics if `node_type` equals [`NodeType::Leaf`]
+    ///
+    /// This uses the [`ResizePolicy::DEFAULT`] policy.
     pub fn should_shrink_inner_node(self, num_children: usize) -> bool {
-        match self {
-            NodeType::Node4 => false,
-            NodeType::Node16 => num_children <= 4,
-            NodeType::Node48 => num_children <= 16,
-            NodeType::Node256 => num_children <= 48,
-            NodeType::Leaf => panic!("cannot shrink leaf"),
-        }
+        ResizePolicy::DEFAULT.should_shrink(self, num_children)
     }
 
     /// Return the range of number of children that each node type accepts.
@@ -97,85 +105,507 @@ impl NodeType {
             NodeType::Leaf => Range { start: 0, end: 0 },
         }
     }
+
+    /// Return true if looking up a child of an inner node with this
+    /// [`NodeType`] compares the key bytes with SIMD instructions.
+    ///
+    /// Only [`InnerNode16`] uses them, since a scan of the 4 keys of an
+    /// [`InnerNode4`] is cheaper, and the larger node types index their
+    /// children by key byte.
+    pub(crate) const fn uses_simd_child_lookup(self) -> bool {
+        matches!(self, NodeType::Node16) && simd::ENABLED
+    }
+}
+
+/// Controls when inner nodes shrink to the next smaller node type.
+///
+/// Inner nodes always grow to the next larger node type when a child is
+/// added to a full node. After a child is removed, a node shrinks if its
+/// number of children is at or below the threshold for its node type.
+///
+/// The [default](ResizePolicy::DEFAULT) shrinks nodes as soon as their
+/// children fit in the smaller node type, which is what the ART paper
+/// describes. Under churn around a capacity boundary this can grow and shrink
+/// the same node on every insert and delete. A policy with lower thresholds,
+/// like [`ResizePolicy::HYSTERESIS`], leaves a gap between the sizes where a
+/// node grows and shrinks, at the cost of keeping some nodes larger than
+/// necessary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ResizePolicy {
+    node16_shrink_at: usize,
+    node48_shrink_at: usize,
+    node256_shrink_at: usize,
+}
+
+impl ResizePolicy {
+    /// Shrink nodes as soon as their children fit in the smaller node type.
+    pub const DEFAULT: ResizePolicy = ResizePolicy::new(4, 16, 48);
+    /// Shrink nodes only once they are well below the capacity of the smaller
+    /// node type, so that alternating inserts and deletes do not repeatedly
+    /// resize the same node.
+    pub const HYSTERESIS: ResizePolicy = ResizePolicy::new(3, 11, 40);
+
+    /// Create a new policy that shrinks each node type when it has at most
+    /// the given number of children.
+    ///
+    /// A threshold of 0 means that the node type never shrinks.
+    ///
+    /// # Panics
+    ///
+    ///  - Panics if a threshold is larger than the capacity of the next smaller
+    ///    node type.
+    pub const fn new(
+        node16_shrink_at: usize,
+        node48_shrink_at: usize,
+        node256_shrink_at: usize,
+    ) -> Self {
+        assert!(
+            node16_shrink_at <= NodeType::Node4.upper_capacity(),
+            "InnerNode16 shrink threshold must fit in an InnerNode4"
+        );
+        assert!(
+            node48_shrink_at <= NodeType::Node16.upper_capacity(),
+            "InnerNode48 shrink threshold must fit in an InnerNode16"
+        );
+        assert!(
+            node256_shrink_at <= NodeType::Node48.upper_capacity(),
+            "InnerNode256 shrink threshold must fit in an InnerNode48"
+        );
+
+        ResizePolicy {
+            node16_shrink_at,
+            node48_shrink_at,
+            node256_shrink_at,
+        }
+    }
+
+    /// Return the largest number of children at which an inner node of the
+    /// given type shrinks, or 0 if it never shrinks.
+    pub const fn shrink_threshold(self, node_type: NodeType) -> usize {
+        match node_type {
+            NodeType::Node4 | NodeType::Leaf => 0,
+            NodeType::Node16 => self.node16_shrink_at,
+            NodeType::Node48 => self.node48_shrink_at,
+            NodeType::Node256 => self.node256_shrink_at,
+        }
+    }
+
+    /// Return true if an [`InnerNode`] with the given [`NodeType`] and
+    /// specified number of children should be shrunk.
+    ///
+    /// # Panics
+    ///  - Panics if `node_type` equals [`NodeType::Leaf`]
+    pub const fn should_shrink(self, node_type: NodeType, num_children: usize) -> bool {
+        match node_type {
+            NodeType::Leaf => panic!("cannot shrink leaf"),
+            _ => num_children <= self.shrink_threshold(node_type),
+        }
+    }
+
+    /// Return the range of number of children that each node type can have in
+    /// a tree that uses this policy.
+    pub const fn children_range(self, node_type: NodeType) -> Range<usize> {
+        let capacity_range = node_type.capacity_range();
+        match node_type {
+            NodeType::Node4 | NodeType::Leaf => capacity_range,
+            _ => Range {
+                start: self.shrink_threshold(node_type) + 1,
+                end: capacity_range.end,
+            },
+        }
+    }
+}
+
+impl Default for ResizePolicy {
+    fn default() -> Self {
+        ResizePolicy::DEFAULT
+    }
 }
 
 /// The common header for all inner nodes
-#[derive(Debug, Clone, PartialEq, Eq, Default)]
+///
+/// The number of children, a fingerprint of the prefix and the length of the
+/// prefix are packed together into a single `meta` word. Only the first
+/// [`NUM_PREFIX_BYTES`] bytes of the prefix are stored in the header, but the
+/// length always counts the full prefix.
+///
+/// When the prefix is longer than the stored bytes, lookups compare only the
+/// stored bytes and skip the rest of the prefix (optimistic path compression).
+/// This is safe because the leaves hold complete keys, so the skipped bytes
+/// are checked against the key at the end of the search. Operations that need
+/// the whole prefix, like insertion, read the missing bytes from the key of
+/// any leaf under the node, see [`InnerNode::read_full_prefix`].
+///
+/// The fingerprint is a 16-bit hash of the prefix bytes which are not stored
+/// in the header, which lets a lookup reject a mismatching key at the node,
+/// instead of following the skipped bytes down to a leaf.
+#[derive(Clone)]
 pub struct Header {
-    /// Number of children of this inner node. This field has no meaning for
-    /// a leaf node.
-    pub num_children: u16,
-    /// The key prefix for this node.
-    pub prefix: TinyVec<[u8; NUM_PREFIX_BYTES]>,
+    /// The number of children of this inner node in the low
+    /// [`Header::NUM_CHILDREN_BITS`] bits, the prefix fingerprint in the next
+    /// [`Header::FINGERPRINT_BITS`] bits, and the number of bytes in the full
+    /// prefix in the high bits, starting at [`Header::PREFIX_LEN_SHIFT`].
+    meta: u64,
+    /// The first bytes of the key prefix for this node. Only the first
+    /// `min(prefix_size, NUM_PREFIX_BYTES)` bytes are meaningful, the rest are
+    /// always zero.
+    prefix: [u8; NUM_PREFIX_BYTES],
 }
 
 impl Header {
+    /// The number of bits of the `meta` field, directly above the number of
+    /// children, that store the prefix fingerprint.
+    const FINGERPRINT_BITS: u32 = 16;
+    /// The number of low bits of the `meta` field that store the number of
+    /// children.
+    const NUM_CHILDREN_BITS: u32 = 16;
+    /// The mask to extract the number of children from the `meta` field.
+    const NUM_CHILDREN_MASK: u64 = (1 << Self::NUM_CHILDREN_BITS) - 1;
+    /// The position of the lowest bit of the prefix length in the `meta`
+    /// field.
+    const PREFIX_LEN_SHIFT: u32 = Self::NUM_CHILDREN_BITS + Self::FINGERPRINT_BITS;
+
     /// Create a new `Header` for an empty node.
     pub fn empty() -> Self {
         Header {
-            num_children: 0,
-            prefix: TinyVec::new(),
+            meta: 0,
+            prefix: [0; NUM_PREFIX_BYTES],
         }
     }
 
+    /// Overwrite the length of the full prefix, and the fingerprint of the
+    /// bytes which are not stored in the header.
+    ///
+    /// # Panics
+    ///
+    ///  - Panics if the length does not fit in a `u32`.
+    fn set_prefix_size(&mut self, prefix_size: usize, fingerprint: u16) {
+        let prefix_size = u32::try_from(prefix_size).expect("prefix length should fit in a u32");
+
+        self.meta = (u64::from(prefix_size) << Self::PREFIX_LEN_SHIFT)
+            | (u64::from(fingerprint) << Self::NUM_CHILDREN_BITS)
+            | (self.meta & Self::NUM_CHILDREN_MASK);
+    }
+
+    /// Return the fingerprint of the prefix bytes which are not stored in the
+    /// header, which is the fingerprint of no bytes if the full prefix is
+    /// stored.
+    fn unstored_fingerprint(&self) -> u16 {
+        // PANIC SAFETY: The value is masked to 16 bits, so it always fits in a u16
+        u16::try_from((self.meta >> Self::NUM_CHILDREN_BITS) & 0xFFFF).unwrap()
+    }
+
+    /// Return the number of prefix bytes which are not stored in the header.
+    fn num_unstored_bytes(&self) -> usize {
+        self.prefix_size().saturating_sub(NUM_PREFIX_BYTES)
+    }
+
     /// Write prefix bytes to this header, appending to existing bytes if
     /// present.
+    ///
+    /// Only the bytes that fit in the header are stored, the others are only
+    /// counted in the prefix length.
     pub fn extend_prefix(&mut self, new_bytes: &[u8]) {
-        self.prefix.extend(new_bytes.iter().copied());
+        let old_len = self.prefix_size();
+        let num_stored = new_bytes
+            .len()
+            .min(NUM_PREFIX_BYTES.saturating_sub(old_len));
+        self.prefix[old_len.min(NUM_PREFIX_BYTES)..][..num_stored]
+            .copy_from_slice(&new_bytes[..num_stored]);
+
+        let fingerprint = concat_fingerprints(
+            self.unstored_fingerprint(),
+            prefix_fingerprint(new_bytes[num_stored..].iter().copied()),
+            new_bytes.len() - num_stored,
+        );
+        self.set_prefix_size(old_len + new_bytes.len(), fingerprint);
     }
 
     /// Write bytes to the start of the key prefix.
+    ///
+    /// To prepend the prefix of another header, which may not be stored in
+    /// full, use [`Header::prepend_header_prefix`].
     pub fn prepend_prefix(&mut self, new_bytes: &[u8]) {
-        self.prefix
-            .splice(0..0, new_bytes.iter().copied())
-            .for_each(|_| ());
+        let old_stored = self.read_prefix();
+        let mut prefix = [0; NUM_PREFIX_BYTES];
+        let num_new_stored = new_bytes.len().min(NUM_PREFIX_BYTES);
+        prefix[..num_new_stored].copy_from_slice(&new_bytes[..num_new_stored]);
+        let num_old_stored = old_stored.len().min(NUM_PREFIX_BYTES - num_new_stored);
+        prefix[num_new_stored..(num_new_stored + num_old_stored)]
+            .copy_from_slice(&old_stored[..num_old_stored]);
+
+        // The bytes which move out of the header are known, and they come before the
+        // bytes which were not stored before
+        let moved_bytes = new_bytes.iter().chain(old_stored).copied();
+        let fingerprint = concat_fingerprints(
+            prefix_fingerprint(moved_bytes.skip(NUM_PREFIX_BYTES)),
+            self.unstored_fingerprint(),
+            self.num_unstored_bytes(),
+        );
+
+        self.prefix = prefix;
+        self.set_prefix_size(new_bytes.len() + self.prefix_size(), fingerprint);
+    }
+
+    /// Write the prefix of another header to the start of the key prefix.
+    ///
+    /// The other prefix does not need to be stored in full, so this is used
+    /// when an inner node is replaced by its only child, and its prefix moves
+    /// into the child.
+    pub fn prepend_header_prefix(&mut self, other: &Header) {
+        if other.stores_full_prefix() {
+            self.prepend_prefix(other.read_prefix());
+            return;
+        }
+
+        // The stored bytes of the other header fill the header, so every byte of this
+        // prefix follows the bytes which are not stored of the other prefix
+        let own_stored = self.read_prefix();
+        let fingerprint = concat_fingerprints(
+            concat_fingerprints(
+                other.unstored_fingerprint(),
+                prefix_fingerprint(own_stored.iter().copied()),
+                own_stored.len(),
+            ),
+            self.unstored_fingerprint(),
+            self.num_unstored_bytes(),
+        );
+
+        self.prefix = other.prefix;
+        self.set_prefix_size(other.prefix_size() + self.prefix_size(), fingerprint);
     }
 
     /// Remove the specified number of bytes from the start of the prefix.
     ///
+    /// This only works if the full prefix is stored in the header, otherwise
+    /// the bytes that move into the header have to be provided with
+    /// [`Header::ltrim_prefix_with`].
+    ///
     /// # Panics
     ///
     ///  - Panics if the number of bytes to remove is greater than the prefix
     ///    size.
+    ///  - Panics if the full prefix is not stored in the header.
     pub fn ltrim_prefix(&mut self, num_bytes: usize) {
-        // this is an explicit match instead of a direct `self.prefix.drain` because it
-        // is more efficient. See `TinyVec::drain` documentation.
-        match self.prefix {
-            TinyVec::Inline(ref mut vec) => {
-                vec.drain(..num_bytes).for_each(|_| ());
-            },
-            TinyVec::Heap(ref mut vec) => {
-                vec.drain(..num_bytes).for_each(|_| ());
-            },
-        }
+        assert!(
+            self.stores_full_prefix(),
+            "cannot trim a prefix which is not fully stored in the header"
+        );
+
+        let full_prefix = self.prefix;
+        self.ltrim_prefix_with(num_bytes, &full_prefix);
     }
 
-    /// Read the initialized portion of the prefix present in the header.
+    /// Remove the specified number of bytes from the start of the prefix,
+    /// reading the bytes which are not stored in the header from
+    /// `full_prefix`.
+    ///
+    /// `full_prefix` must start with the complete prefix of this node, for
+    /// example the bytes of a leaf key below this node starting at the depth
+    /// of the node.
+    ///
+    /// # Panics
+    ///
+    ///  - Panics if the number of bytes to remove is greater than the prefix
+    ///    size.
+    ///  - Panics if `full_prefix` is shorter than the prefix.
+    pub fn ltrim_prefix_with(&mut self, num_bytes: usize, full_prefix: &[u8]) {
+        let prefix_size = self.prefix_size();
+        assert!(
+            num_bytes <= prefix_size,
+            "cannot remove more bytes than are present in the prefix"
+        );
+        assert!(
+            full_prefix.len() >= prefix_size,
+            "the full prefix must hold at least the prefix size"
+        );
+        debug_assert_eq!(
+            &full_prefix[..self.read_prefix().len()],
+            self.read_prefix(),
+            "the full prefix must start with the stored prefix bytes"
+        );
+        debug_assert!(
+            self.may_match_full_prefix(full_prefix),
+            "the full prefix must match the fingerprint of the prefix bytes which are not stored"
+        );
+
+        let new_len = prefix_size - num_bytes;
+        let num_stored = new_len.min(NUM_PREFIX_BYTES);
+        let mut prefix = [0; NUM_PREFIX_BYTES];
+        prefix[..num_stored].copy_from_slice(&full_prefix[num_bytes..(num_bytes + num_stored)]);
+        let unstored_bytes = &full_prefix[(num_bytes + num_stored)..prefix_size];
+
+        self.prefix = prefix;
+        self.set_prefix_size(new_len, prefix_fingerprint(unstored_bytes.iter().copied()));
+    }
+
+    /// Read the portion of the prefix which is stored in the header.
+    ///
+    /// This is the full prefix if [`Header::stores_full_prefix`] returns
+    /// `true`, otherwise it is only the first [`NUM_PREFIX_BYTES`] bytes.
     pub fn read_prefix(&self) -> &[u8] {
-        self.prefix.as_ref()
+        &self.prefix[..self.prefix_size().min(NUM_PREFIX_BYTES)]
     }
 
-    /// Return the number of bytes in the prefix.
+    /// Return the number of bytes in the full prefix.
     pub fn prefix_size(&self) -> usize {
-        self.prefix.len()
+        // PANIC SAFETY: The prefix size was converted from a `u32` when it was
+        // written.
+        usize::try_from(self.meta >> Self::PREFIX_LEN_SHIFT).unwrap()
+    }
+
+    /// Return true if the full prefix is stored in the header, and false if
+    /// only its first [`NUM_PREFIX_BYTES`] bytes are.
+    pub fn stores_full_prefix(&self) -> bool {
+        self.prefix_size() <= NUM_PREFIX_BYTES
     }
 
-    /// Compares the compressed path of a node with the key and returns the
-    /// number of equal bytes.
+    /// Return the fingerprint of the prefix bytes which are not stored in the
+    /// header, or `None` if the full prefix is stored.
+    pub fn prefix_fingerprint(&self) -> Option<u16> {
+        (!self.stores_full_prefix()).then(|| self.unstored_fingerprint())
+    }
+
+    /// Returns `false` if the prefix is definitely not a prefix of
+    /// `possible_key`.
+    ///
+    /// This only compares the fingerprint of the prefix bytes which are not
+    /// stored in the header against a fingerprint of the same bytes of the
+    /// key. A return value of `true` does not imply the prefix matches, the
+    /// stored bytes must be compared with [`Header::match_prefix`], and the
+    /// other bytes are confirmed at the leaf.
+    pub fn may_match_full_prefix(&self, possible_key: &[u8]) -> bool {
+        match self.prefix_fingerprint() {
+            Some(fingerprint) => possible_key
+                .get(NUM_PREFIX_BYTES..self.prefix_size())
+                .is_some_and(|key_bytes| {
+                    prefix_fingerprint(key_bytes.iter().copied()) == fingerprint
+                }),
+            None => true,
+        }
+    }
+
+    /// Compares the stored portion of the compressed path of a node with the
+    /// key and returns the number of equal bytes.
+    ///
+    /// If the prefix is not fully stored, a return value equal to the length of
+    /// [`Header::read_prefix`] only means the key matches the stored bytes.
     pub fn match_prefix(&self, possible_key: &[u8]) -> usize {
-        self.read_prefix()
-            .iter()
-            .zip(possible_key)
-            .take_while(|(a, b)| **a == **b)
-            .count()
+        // The stored bytes fit in a single word, so they are compared all at once and
+        // the first mismatch is the lowest set byte of the difference
+        let len = self.read_prefix().len().min(possible_key.len());
+        let mut key_bytes = [0; NUM_PREFIX_BYTES];
+        key_bytes[..len].copy_from_slice(&possible_key[..len]);
+        let difference = u64::from_le_bytes(self.prefix) ^ u64::from_le_bytes(key_bytes);
+
+        // PANIC SAFETY: The number of trailing zero bytes is at most 8
+        usize::try_from(difference.trailing_zeros() / u8::BITS)
+            .unwrap()
+            .min(len)
     }
 
     /// Return the number of children of this node.
     pub fn num_children(&self) -> usize {
-        usize::from(self.num_children)
+        // PANIC SAFETY: The number of children is masked to 16 bits, which always
+        // fits in a `usize`.
+        usize::try_from(self.meta & Self::NUM_CHILDREN_MASK).unwrap()
+    }
+
+    /// Overwrite the number of children of this node.
+    ///
+    /// # Panics
+    ///
+    ///  - Panics if `num_children` is greater than 256.
+    pub(crate) fn set_num_children(&mut self, num_children: usize) {
+        assert!(
+            num_children <= NodeType::Node256.upper_capacity(),
+            "an inner node can have at most 256 children"
+        );
+
+        self.meta = (self.meta & !Self::NUM_CHILDREN_MASK) | (num_children as u64);
+    }
+
+    /// Increment the number of children of this node by one.
+    pub(crate) fn inc_num_children(&mut self) {
+        self.set_num_children(self.num_children() + 1);
+    }
+
+    /// Decrement the number of children of this node by one.
+    pub(crate) fn dec_num_children(&mut self) {
+        self.set_num_children(self.num_children() - 1);
+    }
+}
+
+/// The modulus of the prefix fingerprint, which is the largest prime below
+/// 2<sup>16</sup>.
+const FINGERPRINT_MODULUS: u64 = 65_521;
+
+/// The base of the polynomial hash used for the prefix fingerprint.
+const FINGERPRINT_BASE: u64 = 257;
+
+/// Compute the 16-bit fingerprint of the given prefix bytes, a polynomial hash
+/// modulo [`FINGERPRINT_MODULUS`].
+///
+/// Since the hash is a polynomial, the fingerprint of two joined byte strings
+/// follows from the fingerprints of both parts, see [`concat_fingerprints`].
+/// This lets the header keep the fingerprint up to date when bytes are added
+/// in front of bytes it does not store.
+fn prefix_fingerprint(bytes: impl Iterator<Item = u8>) -> u16 {
+    let hash = bytes.fold(0, |hash, byte| {
+        (hash * FINGERPRINT_BASE + u64::from(byte)) % FINGERPRINT_MODULUS
+    });
+
+    // PANIC SAFETY: The hash is reduced modulo a number below 2^16
+    u16::try_from(hash).unwrap()
+}
+
+/// Return the fingerprint of the bytes with fingerprint `front`, followed by
+/// the `back_len` bytes with fingerprint `back`.
+fn concat_fingerprints(front: u16, back: u16, back_len: usize) -> u16 {
+    // Shift the front bytes past the back bytes, by multiplying with the base
+    // raised to the number of back bytes
+    let mut shift = 1;
+    let mut power = FINGERPRINT_BASE;
+    let mut exponent = back_len;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            shift = shift * power % FINGERPRINT_MODULUS;
+        }
+        power = power * power % FINGERPRINT_MODULUS;
+        exponent >>= 1;
+    }
+
+    let hash = (u64::from(front) * shift + u64::from(back)) % FINGERPRINT_MODULUS;
+    // PANIC SAFETY: The hash is reduced modulo a number below 2^16
+    u16::try_from(hash).unwrap()
+}
+
+impl fmt::Debug for Header {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Header")
+            .field("num_children", &self.num_children())
+            .field("prefix_size", &self.prefix_size())
+            .field("prefix", &self.read_prefix())
+            .finish()
     }
 }
 
+impl Default for Header {
+    fn default() -> Self {
+        Header::empty()
+    }
+}
+
+impl PartialEq for Header {
+    fn eq(&self, other: &Self) -> bool {
+        self.num_children() == other.num_children()
+            && self.prefix_size() == other.prefix_size()
+            && self.read_prefix() == other.read_prefix()
+    }
+}
+
+impl Eq for Header {}
+
 /// A placeholder type that has the required amount of alignment.
 ///
 /// An alignment of 8 gives us 3 unused bits in any pointer to this type.
@@ -324,6 +754,89 @@ impl<K, V> OpaqueNodePtr<K, V> {
         // passed up to the caller of this function.
         Some(unsafe { &mut *header_ptr })
     }
+
+    /// Read the full prefix of the underlying inner node, which is reached by
+    /// the first `current_depth` bytes of its keys, or return `None` for a
+    /// leaf node.
+    ///
+    /// See [`InnerNode::read_full_prefix`] for details.
+    ///
+    /// # Safety
+    ///
+    ///  - `current_depth` must be the depth of this node in the tree, and the
+    ///    tree must be well-formed.
+    ///  - For the lifetime `'a`, the node, its children and the minimum leaf
+    ///    below it must not get mutated or deallocated.
+    pub(crate) unsafe fn read_full_prefix<'a>(self, current_depth: usize) -> Option<&'a [u8]>
+    where
+        K: AsBytes + 'a,
+        V: 'a,
+    {
+        // SAFETY: Covered by the safety requirements of this function
+        unsafe {
+            match self.to_node_ptr() {
+                ConcreteNodePtr::Node4(inner_ptr) => {
+                    Some(inner_ptr.as_ref().read_full_prefix(current_depth))
+                },
+                ConcreteNodePtr::Node16(inner_ptr) => {
+                    Some(inner_ptr.as_ref().read_full_prefix(current_depth))
+                },
+                ConcreteNodePtr::Node48(inner_ptr) => {
+                    Some(inner_ptr.as_ref().read_full_prefix(current_depth))
+                },
+                ConcreteNodePtr::Node256(inner_ptr) => {
+                    Some(inner_ptr.as_ref().read_full_prefix(current_depth))
+                },
+                ConcreteNodePtr::LeafNode(_) => None,
+            }
+        }
+    }
+
+    /// Remove the specified number of bytes from the start of the prefix of
+    /// the underlying inner node, which is reached by the first
+    /// `current_depth` bytes of its keys.
+    ///
+    /// If the header does not store the full prefix, the bytes that move into
+    /// the header are read from the key of the minimum leaf below this node.
+    ///
+    /// # Safety
+    ///
+    ///  - `current_depth` must be the depth of this node in the tree, and the
+    ///    tree must be well-formed.
+    ///  - No other access or mutation to the node can happen while this
+    ///    function runs, and the tree below it must not get mutated.
+    ///
+    /// # Panics
+    ///
+    ///  - Panics if the node is a leaf node.
+    ///  - Panics if the number of bytes to remove is greater than the prefix
+    ///    size.
+    pub(crate) unsafe fn ltrim_full_prefix(self, num_bytes: usize, current_depth: usize)
+    where
+        K: AsBytes,
+    {
+        // SAFETY: The reference only lives for this statement, and the safety
+        // requirements of this function forbid any other access to the node.
+        let stores_full_prefix = unsafe { self.header_mut() }
+            .expect("only inner nodes have a prefix")
+            .stores_full_prefix();
+
+        if stores_full_prefix {
+            // SAFETY: Covered by the safety requirements of this function
+            unsafe { self.header_mut() }
+                .unwrap()
+                .ltrim_prefix(num_bytes);
+        } else {
+            // SAFETY: The leaf is a separate allocation from this node, and the safety
+            // requirements of this function forbid mutation of the tree below it, so the
+            // key can be borrowed while the header is modified.
+            let key = unsafe { crate::minimum_unchecked(self).as_key_ref() }.as_bytes();
+            // SAFETY: Covered by the safety requirements of this function
+            unsafe { self.header_mut() }
+                .unwrap()
+                .ltrim_prefix_with(num_bytes, &key[current_depth..]);
+        }
+    }
 }
 
 /// An enum that encapsulates pointers to every type of Node
@@ -369,12 +882,34 @@ impl<N: Node> NodePtr<N> {
         unsafe { NodePtr(NonNull::new_unchecked(ptr)) }
     }
 
-    /// Allocate the given [`Node`] on the [`std::alloc::Global`] heap and
-    /// return a [`NodePtr`] that wrap the raw pointer.
+    /// Allocate the given [`Node`] on the [`Global`] heap and return a
+    /// [`NodePtr`] that wrap the raw pointer.
     pub fn allocate_node_ptr(node: N) -> Self {
-        // SAFETY: The pointer from [`Box::into_raw`] is non-null, aligned, and valid
-        // for reads and writes of the [`Node`] `N`.
-        unsafe { NodePtr::new(Box::into_raw(Box::new(node))) }
+        Self::allocate_node_ptr_in(node, &Global)
+    }
+
+    /// Allocate the given [`Node`] with the given [`Allocator`] and return a
+    /// [`NodePtr`] that wrap the raw pointer.
+    ///
+    /// # Panics
+    ///
+    /// If the allocator fails, this calls [`handle_alloc_error`], which
+    /// aborts the process by default.
+    pub fn allocate_node_ptr_in<A: Allocator>(node: N, alloc: &A) -> Self {
+        #[cfg(feature = "alloc-failure-injection")]
+        crate::tests_common::alloc_failure::record_allocation();
+
+        let layout = Layout::new::<N>();
+        let ptr = match alloc.allocate(layout) {
+            Ok(ptr) => ptr.cast::<N>(),
+            Err(_) => handle_alloc_error(layout),
+        };
+
+        // SAFETY: The allocation is valid for writes of the layout of `N`, and is not
+        // referenced anywhere else yet.
+        unsafe { ptr.as_ptr().write(node) };
+
+        NodePtr(ptr)
     }
 
     /// Deallocate a [`Node`] object created with the
@@ -385,8 +920,28 @@ impl<N: Node> NodePtr<N> {
     ///  - This function can only be called once for a given node object.
     #[must_use]
     pub unsafe fn deallocate_node_ptr(node: Self) -> N {
-        // SAFETY: Covered by safety condition on functiom
-        unsafe { *Box::from_raw(node.to_ptr()) }
+        // SAFETY: Covered by safety condition on function
+        unsafe { Self::deallocate_node_ptr_in(node, &Global) }
+    }
+
+    /// Deallocate a [`Node`] object created with the
+    /// [`NodePtr::allocate_node_ptr_in`] function.
+    ///
+    /// # Safety
+    ///
+    ///  - This function can only be called once for a given node object.
+    ///  - The node must have been allocated by the given allocator.
+    #[must_use]
+    pub unsafe fn deallocate_node_ptr_in<A: Allocator>(node: Self, alloc: &A) -> N {
+        // SAFETY: The node is valid for reads, and it is not read again after this
+        // function by the safety conditions on the function.
+        let node_value = unsafe { node.0.as_ptr().read() };
+
+        // SAFETY: The node was allocated by this allocator with the layout of `N`, by
+        // the safety conditions on the function.
+        unsafe { alloc.deallocate(node.0.cast::<u8>(), Layout::new::<N>()) };
+
+        node_value
     }
 
     /// Moves `new_value` into the referenced `dest`, returning the previous
@@ -675,6 +1230,68 @@ pub trait InnerNode: Node {
     /// Access the header information for this node.
     fn header_mut(&mut self) -> &mut Header;
 
+    /// Read the full prefix of this node, which is reached by the first
+    /// `current_depth` bytes of the keys below it.
+    ///
+    /// If the header does not store the full prefix, the prefix is read from
+    /// the key of the minimum leaf below this node.
+    ///
+    /// # Safety
+    ///
+    ///  - `current_depth` must be the depth of this node in the tree, and the
+    ///    tree must be well-formed.
+    ///  - For the duration of the returned reference, the children of this node
+    ///    and the minimum leaf below it must not get mutated or deallocated.
+    unsafe fn read_full_prefix(&self, current_depth: usize) -> &[u8]
+    where
+        <Self as Node>::Key: AsBytes,
+    {
+        let header = self.header();
+        if header.stores_full_prefix() {
+            return header.read_prefix();
+        }
+
+        // SAFETY: The iterator is consumed in this statement, and the safety
+        // requirements of this function forbid mutation of the children.
+        let (_, first_child) = unsafe { self.iter() }
+            .next()
+            .expect("an inner node must always have at least one child");
+        // SAFETY: The safety requirements of this function forbid mutation of the
+        // tree below this node, for the lifetime of the returned reference.
+        let key = unsafe { crate::minimum_unchecked(first_child).as_key_ref() }.as_bytes();
+
+        &key[current_depth..(current_depth + header.prefix_size())]
+    }
+
+    /// Compares the full compressed path of this node with the key and returns
+    /// the number of equal bytes.
+    ///
+    /// Unlike [`Header::match_prefix`], this reads the bytes of the prefix
+    /// which are not stored in the header from a leaf, if the stored bytes
+    /// match.
+    ///
+    /// # Safety
+    ///
+    /// See the safety requirements of [`InnerNode::read_full_prefix`].
+    unsafe fn match_full_prefix(&self, possible_key: &[u8], current_depth: usize) -> usize
+    where
+        <Self as Node>::Key: AsBytes,
+    {
+        let header = self.header();
+        let matched_prefix_size = header.match_prefix(possible_key);
+        if header.stores_full_prefix() || matched_prefix_size < header.read_prefix().len() {
+            return matched_prefix_size;
+        }
+
+        // SAFETY: Covered by the safety requirements of this function
+        let full_prefix = unsafe { self.read_full_prefix(current_depth) };
+        matched_prefix_size
+            + simd::common_prefix_len(
+                &full_prefix[matched_prefix_size..],
+                &possible_key[matched_prefix_size..],
+            )
+    }
+
     /// Returns true if this node has no more space to store children.
     fn is_full(&self) -> bool {
         self.header().num_children() >= Self::TYPE.upper_capacity()
@@ -690,6 +1307,16 @@ pub trait InnerNode: Node {
     /// with any mutating operations on the node.
     unsafe fn iter(&self) -> Self::Iter;
 
+    /// Create an iterator over the (key bytes, child pointers) in this inner
+    /// node, where the key bytes are within the given range.
+    ///
+    /// # Safety
+    ///
+    /// The iterator type does not carry any lifetime, so the caller of this
+    /// function must enforce that the lifetime of the iterator does not overlap
+    /// with any mutating operations on the node.
+    unsafe fn range<R: RangeBounds<u8>>(&self, range: R) -> Self::Iter;
+
     /// Split the inner node at the given key-byte, returning a new
     /// [`InnerNode`] of the same type that contains all children including and
     /// after the given key fragment. The original inner node has those children
@@ -764,13 +1391,12 @@ impl<K, V, const SIZE: usize> InnerNodeCompressed<K, V, SIZE> {
     fn lookup_child_index(&self, key_fragment: u8) -> Option<usize> {
         let (keys, _) = self.initialized_portion();
 
-        for (child_index, key) in keys.iter().enumerate() {
-            if *key == key_fragment {
-                return Some(child_index);
-            }
+        if SIZE <= 4 {
+            // A scan of a few bytes is cheaper than setting up the SIMD comparison
+            keys.iter().position(|key| *key == key_fragment)
+        } else {
+            simd::find_byte(keys, key_fragment)
         }
-
-        None
     }
 
     /// Return the initialized portions of the keys and child pointer arrays.
@@ -820,7 +1446,7 @@ impl<K, V, const SIZE: usize> InnerNodeCompressed<K, V, SIZE> {
                 self.keys[child_index].write(key_fragment);
                 self.child_pointers[child_index].write(child_pointer);
 
-                self.header.num_children += 1;
+                self.header.inc_num_children();
             },
         }
     }
@@ -842,7 +1468,7 @@ impl<K, V, const SIZE: usize> InnerNodeCompressed<K, V, SIZE> {
                 self.keys
                     .copy_within((child_index + 1)..self.header.num_children(), child_index);
 
-                self.header.num_children -= 1;
+                self.header.dec_num_children();
                 // SAFETY: This child pointer value is initialized because we got it by
                 // searching through the initialized keys and got the `Ok(index)` value.
                 Some(unsafe { MaybeUninit::assume_init(child_ptr) })
@@ -859,7 +1485,7 @@ impl<K, V, const SIZE: usize> InnerNodeCompressed<K, V, SIZE> {
             SIZE,
             NEW_SIZE,
             NEW_SIZE,
-            self.header.num_children
+            self.header.num_children()
         );
 
         let header = self.header.clone();
@@ -908,7 +1534,7 @@ impl<K, V, const SIZE: usize> InnerNodeCompressed<K, V, SIZE> {
 
         // Create new split node with a copy of the key prefix
         let mut split_node = Self::empty();
-        split_node.header.prefix = self.header.prefix.clone();
+        split_node.header = self.header.clone();
 
         let split_num_children = self.header.num_children() - split_index;
 
@@ -919,9 +1545,9 @@ impl<K, V, const SIZE: usize> InnerNodeCompressed<K, V, SIZE> {
             .copy_from_slice(&self.child_pointers[split_index..self.header.num_children()]);
 
         // update number of children on both sides of the split
-        split_node.header.num_children = u16::try_from(split_num_children)
-            .expect("this number should be derived from something that fit in a u16");
-        self.header.num_children -= split_node.header.num_children;
+        split_node.header.set_num_children(split_num_children);
+        self.header
+            .set_num_children(self.header.num_children() - split_num_children);
 
         split_node
     }
@@ -988,6 +1614,12 @@ impl<K, V> InnerNode for InnerNode4<K, V> {
         unsafe { InnerNodeCompressedIter::new(self) }
     }
 
+    unsafe fn range<R: RangeBounds<u8>>(&self, range: R) -> Self::Iter {
+        // SAFETY: The safety requirements on the `range` function match the `range`
+        // constructor of the iterator
+        unsafe { InnerNodeCompressedIter::range(self, range) }
+    }
+
     fn split_at(&mut self, key_fragment: u8) -> Self {
         InnerNodeCompressed::split_at(self, key_fragment)
     }
@@ -1049,6 +1681,12 @@ impl<K, V> InnerNode for InnerNode16<K, V> {
         unsafe { InnerNodeCompressedIter::new(self) }
     }
 
+    unsafe fn range<R: RangeBounds<u8>>(&self, range: R) -> Self::Iter {
+        // SAFETY: The safety requirements on the `range` function match the `range`
+        // constructor of the iterator
+        unsafe { InnerNodeCompressedIter::range(self, range) }
+    }
+
     fn split_at(&mut self, key_fragment: u8) -> Self {
         InnerNodeCompressed::split_at(self, key_fragment)
     }
@@ -1230,7 +1868,7 @@ impl<K, V> InnerNode for InnerNode48<K, V> {
 
             self.child_indices[key_fragment_idx] =
                 RestrictedNodeIndex::<48>::try_from(child_index).unwrap();
-            self.header.num_children += 1;
+            self.header.inc_num_children();
             child_index
         } else {
             // overwrite existing
@@ -1275,7 +1913,7 @@ impl<K, V> InnerNode for InnerNode48<K, V> {
             }
 
             self.child_indices[usize::from(key_fragment)] = RestrictedNodeIndex::EMPTY;
-            self.header.num_children -= 1;
+            self.header.dec_num_children();
             // SAFETY: This child pointer value is initialized because we got it by using a
             // non-`RestrictedNodeIndex::<>::EMPTY` index from the child indices array.
             Some(unsafe { MaybeUninit::assume_init(child_ptr) })
@@ -1303,10 +1941,10 @@ impl<K, V> InnerNode for InnerNode48<K, V> {
 
     fn shrink(&self) -> Self::ShrunkNode {
         assert!(
-            self.header.num_children <= 16,
+            self.header.num_children() <= 16,
             "Cannot shrink a Node48 when it has more than 16 children. Currently has [{}] \
              children.",
-            self.header.num_children
+            self.header.num_children()
         );
 
         let header = self.header.clone();
@@ -1365,6 +2003,12 @@ impl<K, V> InnerNode for InnerNode48<K, V> {
         unsafe { InnerNode48Iter::new(self) }
     }
 
+    unsafe fn range<R: RangeBounds<u8>>(&self, range: R) -> Self::Iter {
+        // SAFETY: The safety requirements on the `range` function match the `range`
+        // constructor of the iterator
+        unsafe { InnerNode48Iter::range(self, range) }
+    }
+
     fn split_at(&mut self, key_fragment: u8) -> Self {
         let split_index = usize::from(key_fragment);
         let (keep_child_indices, split_child_indices) =
@@ -1372,7 +2016,7 @@ impl<K, V> InnerNode for InnerNode48<K, V> {
 
         // Create new split node with a copy of the key prefix
         let mut split_node = Self::empty();
-        split_node.header.prefix = self.header.prefix.clone();
+        split_node.header = self.header.clone();
 
         // Move the split off child pointers to the second half of the new node
         split_node.child_indices[split_index..].copy_from_slice(split_child_indices);
@@ -1407,7 +2051,9 @@ impl<K, V> InnerNode for InnerNode48<K, V> {
             }
         }
 
-        split_node.header.num_children = split_node_num_children;
+        split_node
+            .header
+            .set_num_children(usize::from(split_node_num_children));
 
         // Now we need to compact the original array of child pointers and update the
         // `keep_child_indices` list. We need a new child pointers array so we don't
@@ -1434,7 +2080,8 @@ impl<K, V> InnerNode for InnerNode48<K, V> {
         }
 
         self.child_pointers = new_keep_child_pointers;
-        self.header.num_children = keep_node_num_children;
+        self.header
+            .set_num_children(usize::from(keep_node_num_children));
 
         split_node
     }
@@ -1520,7 +2167,7 @@ impl<K, V> InnerNode for InnerNode256<K, V> {
         let existing_pointer = self.child_pointers[key_fragment_idx];
         self.child_pointers[key_fragment_idx] = Some(child_pointer);
         if existing_pointer.is_none() {
-            self.header.num_children += 1;
+            self.header.inc_num_children();
         }
     }
 
@@ -1531,7 +2178,7 @@ impl<K, V> InnerNode for InnerNode256<K, V> {
         let removed_child = self.child_pointers[usize::from(key_fragment)].take();
 
         if removed_child.is_some() {
-            self.header.num_children -= 1;
+            self.header.dec_num_children();
         }
 
         removed_child
@@ -1543,10 +2190,10 @@ impl<K, V> InnerNode for InnerNode256<K, V> {
 
     fn shrink(&self) -> Self::ShrunkNode {
         assert!(
-            self.header.num_children <= 48,
+            self.header.num_children() <= 48,
             "Cannot shrink a Node256 when it has more than 48 children. Currently has [{}] \
              children.",
-            self.header.num_children
+            self.header.num_children()
         );
 
         let header = self.header.clone();
@@ -1587,13 +2234,19 @@ impl<K, V> InnerNode for InnerNode256<K, V> {
         unsafe { InnerNode256Iter::new(self) }
     }
 
+    unsafe fn range<R: RangeBounds<u8>>(&self, range: R) -> Self::Iter {
+        // SAFETY: The safety requirements on the `range` function match the `range`
+        // constructor of the iterator
+        unsafe { InnerNode256Iter::range(self, range) }
+    }
+
     fn split_at(&mut self, key_fragment: u8) -> Self {
         let split_index = usize::from(key_fragment);
         let (_, split_child_pointers) = self.child_pointers.split_at_mut(split_index);
 
         // Create new split node with a copy of the key prefix
         let mut split_node = Self::empty();
-        split_node.header.prefix = self.header.prefix.clone();
+        split_node.header = self.header.clone();
 
         // Move the split off child pointers to the second half of the new node
         split_node.child_pointers[split_index..].copy_from_slice(split_child_pointers);
@@ -1611,8 +2264,11 @@ impl<K, V> InnerNode for InnerNode256<K, V> {
         }
 
         // Update the number of children in each split node
-        self.header.num_children -= split_child_pointer_count;
-        split_node.header.num_children = split_child_pointer_count;
+        self.header
+            .set_num_children(self.header.num_children() - usize::from(split_child_pointer_count));
+        split_node
+            .header
+            .set_num_children(usize::from(split_child_pointer_count));
 
         split_node
     }
@@ -1690,6 +2346,14 @@ impl<K, V> LeafNode<K, V> {
     {
         self.key.borrow().as_bytes().eq(possible_key.as_bytes())
     }
+
+    /// Check that the provided bytes are the bytes of the stored key.
+    pub(crate) fn matches_key_bytes(&self, key_bytes: &[u8]) -> bool
+    where
+        K: AsBytes,
+    {
+        self.key.as_bytes().eq(key_bytes)
+    }
 }
 
 impl<K, V> Node for LeafNode<K, V> {
@@ -69,11 +69,11 @@ impl<K, V> InnerNodeCompressedIter<K, V> {
             (
                 NonNull::new_unchecked(<*mut u8>::add(
                     keys_start.as_ptr(),
-                    usize::from(node.header.num_children),
+                    node.header.num_children(),
                 )),
                 NonNull::new_unchecked(<*mut OpaqueNodePtr<K, V>>::add(
                     child_pointers_start.as_ptr(),
-                    usize::from(node.header.num_children),
+                    node.header.num_children(),
                 )),
             )
         };
@@ -127,13 +127,14 @@ impl<K, V> InnerNodeCompressedIter<K, V> {
                 },
                 Bound::Excluded(min_key_byte_excluded) => {
                     match keys.binary_search(min_key_byte_excluded) {
-                        // Same logic for `Ok` and `Err` applies as above. However, we want to
-                        // exclude the index we found, so we add 1 so iteration starts at the next
-                        // index. Adding 1 here could take us out of bounds of the keys, we have to
-                        // check later on to bound the `_start` pointers back into range.
-                        //
-                        // TODO: Should be worried about overflow?
-                        Ok(idx) | Err(idx) => idx + 1,
+                        // In the `Ok` case we want to exclude the index we found, so we add 1 so
+                        // iteration starts at the next index. Adding 1 here could take us out of
+                        // bounds of the keys, we have to check later on to bound the `_start`
+                        // pointers back into range.
+                        Ok(idx) => idx + 1,
+                        // In the `Err` case the element at the index is already larger than the
+                        // excluded key byte, so iteration starts there.
+                        Err(idx) => idx,
                     }
                 },
                 Bound::Unbounded => {
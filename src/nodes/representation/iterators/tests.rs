@@ -209,6 +209,10 @@ macro_rules! node_range_test_cases {
                 (Bound::Excluded(&0u8), Bound::Included(&255));
                 [(3, l1_ptr), (85, l4_ptr), (255, l2_ptr)]
             },
+            {
+                (Bound::Excluded(&2), Bound::Excluded(&86));
+                [(3, l1_ptr), (85, l4_ptr)]
+            },
             {
                 (Bound::Excluded(&255), Bound::<&u8>::Unbounded);
                 []
@@ -0,0 +1,301 @@
+//! Reads of inner nodes which may overlap with a writer, for the optimistic
+//! lock coupling in [`ConcurrentTreeMap`][crate::ConcurrentTreeMap].
+//!
+//! Readers do not hold the lock of the nodes they read, so a writer may be
+//! changing a node while it is read. The node is therefore never accessed
+//! through a reference while it is read optimistically. Instead, the fields
+//! that a reader needs are copied with [`ptr::read_volatile`] through raw
+//! pointers, into values which are valid for any bit pattern, or which stay
+//! wrapped in [`MaybeUninit`].
+//!
+//! This is the argument of a seqlock. A copy may mix bytes from before and
+//! after a write, but nothing is derived from it that could cause undefined
+//! behaviour, like an out of bounds index, and no pointer in it is followed
+//! until the version of the node is checked with
+//! [`NodeVersion::check`][super::NodeVersion::check]. A writer takes the lock,
+//! which changes the version, before it changes the node, see
+//! [`NodeVersion::upgrade_to_write_lock`][super::NodeVersion::upgrade_to_write_lock].
+//! So if the version is
+//! unchanged after the copy, no write overlapped with it, and the copy is
+//! equal to the node.
+
+use super::{Header, InnerNode256, InnerNode48, InnerNodeCompressed, NodeType, OpaqueNodePtr};
+use std::{mem::MaybeUninit, ptr};
+
+/// The largest number of children of a node which stores its key bytes in an
+/// array, an [`InnerNode16`][super::InnerNode16].
+const MAX_COMPRESSED_CHILDREN: usize = 16;
+
+impl Header {
+    /// Copy the header that `header_ptr` points to, while a writer may be
+    /// changing it.
+    ///
+    /// The copy is only meaningful once the version of the node is checked
+    /// afterwards, but every bit pattern is a valid header, so its methods can
+    /// be called before that.
+    ///
+    /// # Safety
+    ///
+    ///  - `header_ptr` must point to the header of an inner node which is not
+    ///    deallocated during this function.
+    unsafe fn read_optimistic(header_ptr: *const Header) -> Header {
+        // SAFETY: The fields are plain integers, which are valid for any bit
+        // pattern. The header is allocated by the safety requirements of this
+        // function.
+        unsafe {
+            Header {
+                meta: ptr::read_volatile(ptr::addr_of!((*header_ptr).meta)),
+                prefix: ptr::read_volatile(ptr::addr_of!((*header_ptr).prefix)),
+            }
+        }
+    }
+}
+
+/// A child pointer of an inner node, copied while a writer may be changing the
+/// node.
+///
+/// The pointer can only be taken out with [`OptimisticChild::assume_validated`]
+/// after the version of the node was checked.
+pub(crate) struct OptimisticChild<K, V>(CopiedChild<K, V>);
+
+enum CopiedChild<K, V> {
+    /// The key bytes and child pointers of an [`InnerNodeCompressed`], which
+    /// are searched for the key byte once the copy is validated. The number of
+    /// children is capped, so that a torn copy stays in bounds.
+    Compressed {
+        key_byte: u8,
+        num_children: usize,
+        keys: [MaybeUninit<u8>; MAX_COMPRESSED_CHILDREN],
+        child_pointers: [MaybeUninit<OpaqueNodePtr<K, V>>; MAX_COMPRESSED_CHILDREN],
+    },
+    /// The child pointer of the slot which was found, if any.
+    Slot(Option<MaybeUninit<OpaqueNodePtr<K, V>>>),
+}
+
+impl<K, V> OptimisticChild<K, V> {
+    /// Return the copied child pointer.
+    ///
+    /// # Safety
+    ///
+    ///  - The version of the node must have been read with
+    ///    [`NodeVersion::read_lock`][super::NodeVersion::read_lock] before the
+    ///    copy, and checked with
+    ///    [`NodeVersion::check`][super::NodeVersion::check] after it.
+    pub(crate) unsafe fn assume_validated(self) -> Option<OpaqueNodePtr<K, V>> {
+        match self.0 {
+            CopiedChild::Compressed {
+                key_byte,
+                num_children,
+                keys,
+                child_pointers,
+            } => {
+                // SAFETY: The node did not change during the copy, so the copy holds the
+                // first `num_children` key bytes and child pointers of the node, which are
+                // initialized.
+                let idx = keys[..num_children]
+                    .iter()
+                    .position(|key| unsafe { key.assume_init() } == key_byte)?;
+                Some(unsafe { child_pointers[idx].assume_init() })
+            },
+            // SAFETY: The node did not change during the copy, so the slot was occupied
+            // and its pointer is initialized.
+            CopiedChild::Slot(child) => child.map(|child| unsafe { child.assume_init() }),
+        }
+    }
+}
+
+impl<K, V> OpaqueNodePtr<K, V> {
+    /// Return a pointer to the header of this inner node, without creating a
+    /// reference to the node, or `None` if this is a leaf.
+    ///
+    /// # Safety
+    ///
+    ///  - The node must not be deallocated during this function.
+    unsafe fn header_ptr(self) -> Option<*const Header> {
+        // SAFETY: The node is allocated, so the field projections stay in bounds
+        unsafe {
+            match self.node_type() {
+                NodeType::Node4 => {
+                    let node_ptr = self.0.cast::<InnerNodeCompressed<K, V, 4>>().to_ptr();
+                    Some(ptr::addr_of!((*node_ptr).header))
+                },
+                NodeType::Node16 => {
+                    let node_ptr = self.0.cast::<InnerNodeCompressed<K, V, 16>>().to_ptr();
+                    Some(ptr::addr_of!((*node_ptr).header))
+                },
+                NodeType::Node48 => {
+                    let node_ptr = self.0.cast::<InnerNode48<K, V>>().to_ptr();
+                    Some(ptr::addr_of!((*node_ptr).header))
+                },
+                NodeType::Node256 => {
+                    let node_ptr = self.0.cast::<InnerNode256<K, V>>().to_ptr();
+                    Some(ptr::addr_of!((*node_ptr).header))
+                },
+                NodeType::Leaf => None,
+            }
+        }
+    }
+
+    /// Copy the header of this inner node while a writer may be changing it,
+    /// or return `None` if this is a leaf.
+    ///
+    /// The copy is only meaningful once the version of the node is checked
+    /// afterwards.
+    ///
+    /// # Safety
+    ///
+    ///  - The node must not be deallocated during this function.
+    pub(crate) unsafe fn read_header_optimistic(self) -> Option<Header> {
+        // SAFETY: Covered by the safety requirements of this function
+        unsafe { Some(Header::read_optimistic(self.header_ptr()?)) }
+    }
+
+    /// Copy the child of this inner node with the given key byte, while a
+    /// writer may be changing the node.
+    ///
+    /// # Safety
+    ///
+    ///  - The node must not be deallocated during this function.
+    ///
+    /// # Panics
+    ///
+    ///  - Panics if the node is a leaf.
+    pub(crate) unsafe fn read_child_optimistic(self, key_byte: u8) -> OptimisticChild<K, V> {
+        // SAFETY: Covered by the safety requirements of this function
+        let child = unsafe {
+            match self.node_type() {
+                NodeType::Node4 => copy_compressed::<K, V, 4>(self.0.cast().to_ptr(), key_byte),
+                NodeType::Node16 => copy_compressed::<K, V, 16>(self.0.cast().to_ptr(), key_byte),
+                NodeType::Node48 => {
+                    let node_ptr = self.0.cast::<InnerNode48<K, V>>().to_ptr();
+                    // The index is a plain byte, so any value can be read and only the
+                    // indices in bounds are used
+                    let index_ptr = ptr::addr_of!((*node_ptr).child_indices[usize::from(key_byte)]);
+                    let index = usize::from(ptr::read_volatile(index_ptr.cast::<u8>()));
+                    CopiedChild::Slot((index < 48).then(|| {
+                        ptr::read_volatile(ptr::addr_of!((*node_ptr).child_pointers[index]))
+                    }))
+                },
+                NodeType::Node256 => {
+                    let node_ptr = self.0.cast::<InnerNode256<K, V>>().to_ptr();
+                    CopiedChild::Slot(copy_node256_slot(node_ptr, usize::from(key_byte)))
+                },
+                NodeType::Leaf => unreachable!("only inner nodes have children"),
+            }
+        };
+
+        OptimisticChild(child)
+    }
+
+    /// Copy any child of this inner node, while a writer may be changing the
+    /// node.
+    ///
+    /// # Safety
+    ///
+    ///  - The node must not be deallocated during this function.
+    ///
+    /// # Panics
+    ///
+    ///  - Panics if the node is a leaf.
+    pub(crate) unsafe fn read_any_child_optimistic(self) -> OptimisticChild<K, V> {
+        // SAFETY: Covered by the safety requirements of this function
+        let child = unsafe {
+            match self.node_type() {
+                NodeType::Node4 => {
+                    CopiedChild::Slot(copy_compressed_first::<K, V, 4>(self.0.cast().to_ptr()))
+                },
+                NodeType::Node16 => {
+                    CopiedChild::Slot(copy_compressed_first::<K, V, 16>(self.0.cast().to_ptr()))
+                },
+                NodeType::Node48 => {
+                    let node_ptr = self.0.cast::<InnerNode48<K, V>>().to_ptr();
+                    let indices: [u8; 256] =
+                        ptr::read_volatile(ptr::addr_of!((*node_ptr).child_indices).cast());
+                    CopiedChild::Slot(
+                        indices
+                            .into_iter()
+                            .map(usize::from)
+                            .find(|index| *index < 48)
+                            .map(|index| {
+                                ptr::read_volatile(ptr::addr_of!((*node_ptr).child_pointers[index]))
+                            }),
+                    )
+                },
+                NodeType::Node256 => {
+                    let node_ptr = self.0.cast::<InnerNode256<K, V>>().to_ptr();
+                    CopiedChild::Slot(
+                        (0..256).find_map(|key_byte| copy_node256_slot(node_ptr, key_byte)),
+                    )
+                },
+                NodeType::Leaf => unreachable!("only inner nodes have children"),
+            }
+        };
+
+        OptimisticChild(child)
+    }
+}
+
+/// Copy the key bytes and child pointers of a compressed node, to be searched
+/// for `key_byte` once the copy is validated.
+///
+/// # Safety
+///
+///  - The node must not be deallocated during this function.
+unsafe fn copy_compressed<K, V, const SIZE: usize>(
+    node_ptr: *const InnerNodeCompressed<K, V, SIZE>,
+    key_byte: u8,
+) -> CopiedChild<K, V> {
+    let mut keys = [MaybeUninit::uninit(); MAX_COMPRESSED_CHILDREN];
+    let mut child_pointers = [MaybeUninit::uninit(); MAX_COMPRESSED_CHILDREN];
+    // SAFETY: The node is allocated by the safety requirements of this function.
+    // The arrays are copied as `MaybeUninit`, so their uninitialized entries are
+    // not read as values.
+    unsafe {
+        let header = Header::read_optimistic(ptr::addr_of!((*node_ptr).header));
+        let node_keys = ptr::read_volatile(ptr::addr_of!((*node_ptr).keys));
+        let node_child_pointers = ptr::read_volatile(ptr::addr_of!((*node_ptr).child_pointers));
+        keys[..SIZE].copy_from_slice(&node_keys);
+        child_pointers[..SIZE].copy_from_slice(&node_child_pointers);
+
+        CopiedChild::Compressed {
+            key_byte,
+            num_children: header.num_children().min(SIZE),
+            keys,
+            child_pointers,
+        }
+    }
+}
+
+/// Copy the first child pointer of a compressed node, if it has any children.
+///
+/// # Safety
+///
+///  - The node must not be deallocated during this function.
+unsafe fn copy_compressed_first<K, V, const SIZE: usize>(
+    node_ptr: *const InnerNodeCompressed<K, V, SIZE>,
+) -> Option<MaybeUninit<OpaqueNodePtr<K, V>>> {
+    // SAFETY: The node is allocated by the safety requirements of this function,
+    // and the child pointer is copied as `MaybeUninit`.
+    unsafe {
+        let header = Header::read_optimistic(ptr::addr_of!((*node_ptr).header));
+        (header.num_children() > 0)
+            .then(|| ptr::read_volatile(ptr::addr_of!((*node_ptr).child_pointers[0])))
+    }
+}
+
+/// Copy the child pointer in the given slot of an [`InnerNode256`], if the slot
+/// is occupied.
+///
+/// # Safety
+///
+///  - The node must not be deallocated during this function.
+unsafe fn copy_node256_slot<K, V>(
+    node_ptr: *const InnerNode256<K, V>,
+    key_byte: usize,
+) -> Option<MaybeUninit<OpaqueNodePtr<K, V>>> {
+    // SAFETY: The slots are always initialized, and a child pointer is a non-null
+    // pointer, so any bit pattern is a valid slot. The pointer is not followed
+    // before the copy is validated.
+    unsafe { ptr::read_volatile(ptr::addr_of!((*node_ptr).child_pointers[key_byte])) }
+        .map(MaybeUninit::new)
+}
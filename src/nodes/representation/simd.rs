@@ -0,0 +1,148 @@
+//! Byte search and comparison routines used by the node representations, with
+//! SSE2 versions on x86 targets and a scalar fallback everywhere else.
+
+/// The number of bytes compared by a single SIMD instruction.
+const LANES: usize = 16;
+
+/// True if the routines in this module use SIMD instructions on this target.
+pub(crate) const ENABLED: bool = cfg!(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    target_feature = "sse2"
+));
+
+/// Return the index of the first byte in `keys` equal to `key_fragment`.
+///
+/// # Panics
+///
+///  - Panics if `keys` is longer than 16 bytes.
+pub(crate) fn find_byte(keys: &[u8], key_fragment: u8) -> Option<usize> {
+    assert!(
+        keys.len() <= LANES,
+        "keys should fit in a single SIMD register"
+    );
+
+    #[cfg(all(
+        any(target_arch = "x86", target_arch = "x86_64"),
+        target_feature = "sse2"
+    ))]
+    {
+        // The keys past the initialized portion of a node are not readable, so they
+        // are copied into a zeroed buffer and masked off after the comparison
+        let mut lanes = [0u8; LANES];
+        lanes[..keys.len()].copy_from_slice(keys);
+        let valid_mask = (1u32 << keys.len()) - 1;
+
+        let matches = sse2::eq_mask(&lanes, &[key_fragment; LANES]) & valid_mask;
+        if matches == 0 {
+            None
+        } else {
+            Some(matches.trailing_zeros() as usize)
+        }
+    }
+
+    #[cfg(not(all(
+        any(target_arch = "x86", target_arch = "x86_64"),
+        target_feature = "sse2"
+    )))]
+    {
+        keys.iter().position(|key| *key == key_fragment)
+    }
+}
+
+/// Return the number of leading bytes which are equal in `a` and `b`.
+pub(crate) fn common_prefix_len(a: &[u8], b: &[u8]) -> usize {
+    let len = a.len().min(b.len());
+    #[allow(unused_mut)]
+    let mut matched = 0;
+
+    #[cfg(all(
+        any(target_arch = "x86", target_arch = "x86_64"),
+        target_feature = "sse2"
+    ))]
+    {
+        for (a_chunk, b_chunk) in a[..len]
+            .chunks_exact(LANES)
+            .zip(b[..len].chunks_exact(LANES))
+        {
+            // PANIC SAFETY: `chunks_exact` only returns chunks of exactly `LANES` bytes
+            let equal = sse2::eq_mask(
+                a_chunk.try_into().expect("chunk should have LANES bytes"),
+                b_chunk.try_into().expect("chunk should have LANES bytes"),
+            );
+            if equal != 0xFFFF {
+                return matched + equal.trailing_ones() as usize;
+            }
+            matched += LANES;
+        }
+    }
+
+    matched
+        + a[matched..len]
+            .iter()
+            .zip(&b[matched..len])
+            .take_while(|(a, b)| a == b)
+            .count()
+}
+
+#[cfg(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    target_feature = "sse2"
+))]
+mod sse2 {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::{__m128i, _mm_cmpeq_epi8, _mm_loadu_si128, _mm_movemask_epi8};
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::{__m128i, _mm_cmpeq_epi8, _mm_loadu_si128, _mm_movemask_epi8};
+
+    /// Compare two 16 byte arrays, returning a mask with bit `i` set if the
+    /// bytes at index `i` are equal.
+    pub(super) fn eq_mask(a: &[u8; 16], b: &[u8; 16]) -> u32 {
+        // SAFETY: The `sse2` target feature is enabled, which is checked by the `cfg`
+        // on this module. The unaligned loads read exactly 16 bytes from each array.
+        let mask = unsafe {
+            let a = _mm_loadu_si128(a.as_ptr().cast::<__m128i>());
+            let b = _mm_loadu_si128(b.as_ptr().cast::<__m128i>());
+            _mm_movemask_epi8(_mm_cmpeq_epi8(a, b))
+        };
+
+        // The mask only has the lower 16 bits set, one for each byte
+        mask as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_byte_matches_scalar_search() {
+        let keys: Vec<u8> = (0..16).map(|idx| idx * 7).collect();
+        for len in 0..=16 {
+            for key_fragment in 0..=u8::MAX {
+                assert_eq!(
+                    find_byte(&keys[..len], key_fragment),
+                    keys[..len].iter().position(|key| *key == key_fragment),
+                    "{len} {key_fragment}"
+                );
+            }
+        }
+
+        // Only the first of the duplicate bytes is found
+        assert_eq!(find_byte(&[3, 1, 1, 1], 1), Some(1));
+    }
+
+    #[test]
+    fn common_prefix_len_matches_scalar_comparison() {
+        let a: Vec<u8> = (0..70).collect();
+        for len in 0..=a.len() {
+            assert_eq!(common_prefix_len(&a, &a[..len]), len);
+            assert_eq!(common_prefix_len(&a[..len], &a), len);
+
+            for diverge in 0..len {
+                let mut b = a[..len].to_vec();
+                b[diverge] ^= 0x80;
+                assert_eq!(common_prefix_len(&a, &b), diverge, "{len} {diverge}");
+            }
+        }
+    }
+}
@@ -68,7 +68,7 @@ fn opaque_node_ptr_is_correct() {
 #[test]
 #[cfg(target_pointer_width = "64")]
 fn node_sizes() {
-    const EXPECTED_HEADER_SIZE: usize = 40;
+    const EXPECTED_HEADER_SIZE: usize = 16;
 
     assert_eq!(mem::size_of::<Header>(), EXPECTED_HEADER_SIZE);
     // key map: 4 * (1 byte) = 4 bytes
@@ -98,6 +98,11 @@ fn node_sizes() {
         EXPECTED_HEADER_SIZE + 2048
     );
 
+    // Boxed slice values are stored inline in the leaf as a pointer and a length,
+    // so the value bytes are one pointer hop away from the leaf
+    assert_eq!(mem::size_of::<LeafNode<[u8; 8], Box<[u8]>>>(), 24);
+    assert_eq!(mem::size_of::<LeafNode<Box<[u8]>, Box<str>>>(), 32);
+
     // Assert that pointer is expected size and has non-null optimization
     assert_eq!(mem::size_of::<Option<OpaqueNodePtr<Box<[u8]>, ()>>>(), 8);
     assert_eq!(mem::size_of::<OpaqueNodePtr<Box<[u8]>, ()>>(), 8);
@@ -151,6 +156,184 @@ fn node_alignment() {
     assert!(n256_ptr.trailing_zeros() >= 3);
 }
 
+#[test]
+fn header_prefix_stores_only_first_bytes() {
+    let mut header = Header::empty();
+    assert_eq!(header.read_prefix(), &[] as &[u8]);
+    assert_eq!(header.prefix_size(), 0);
+    assert!(header.stores_full_prefix());
+
+    header.extend_prefix(&[1, 2, 3, 4]);
+    assert_eq!(header.read_prefix(), &[1, 2, 3, 4]);
+    assert!(header.stores_full_prefix());
+
+    header.extend_prefix(&[5, 6, 7, 8]);
+    assert_eq!(header.read_prefix(), &[1, 2, 3, 4, 5, 6, 7, 8]);
+    assert_eq!(header.prefix_size(), NUM_PREFIX_BYTES);
+    assert!(header.stores_full_prefix());
+
+    header.extend_prefix(&[9]);
+    assert_eq!(header.read_prefix(), &[1, 2, 3, 4, 5, 6, 7, 8]);
+    assert_eq!(header.prefix_size(), 9);
+    assert!(!header.stores_full_prefix());
+
+    header.prepend_prefix(&[0]);
+    assert_eq!(header.read_prefix(), &[0, 1, 2, 3, 4, 5, 6, 7]);
+    assert_eq!(header.prefix_size(), 10);
+    // Only the stored bytes are compared
+    assert_eq!(header.match_prefix(&[0, 1, 2, 3, 4, 5, 6, 10]), 7);
+    assert_eq!(header.match_prefix(&[0, 1, 2, 3, 4, 5, 6, 7, 0, 0]), 8);
+
+    let cloned = header.clone();
+    assert_eq!(cloned, header);
+
+    let full_prefix = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+    header.ltrim_prefix_with(1, &full_prefix);
+    assert_eq!(header.read_prefix(), &[1, 2, 3, 4, 5, 6, 7, 8]);
+    assert_eq!(header.prefix_size(), 9);
+    assert!(!header.stores_full_prefix());
+
+    header.ltrim_prefix_with(3, &full_prefix[1..]);
+    assert_eq!(header.read_prefix(), &[4, 5, 6, 7, 8, 9]);
+    assert!(header.stores_full_prefix());
+
+    header.ltrim_prefix(6);
+    assert_eq!(header.read_prefix(), &[] as &[u8]);
+
+    assert_eq!(cloned.read_prefix(), &[0, 1, 2, 3, 4, 5, 6, 7]);
+    assert_ne!(cloned, header);
+}
+
+#[test]
+fn header_eq_compares_full_prefix_length() {
+    let mut header = Header::empty();
+    header.extend_prefix(&[1; 9]);
+    let mut other = Header::empty();
+    other.extend_prefix(&[1; 10]);
+
+    assert_eq!(header.read_prefix(), other.read_prefix());
+    assert_ne!(header, other);
+
+    other.ltrim_prefix_with(1, &[1; 10]);
+    assert_eq!(header, other);
+}
+
+#[test]
+fn header_prepend_prefix_not_fully_stored() {
+    let mut child = Header::empty();
+    child.extend_prefix(&[20, 21, 22]);
+
+    let mut parent = Header::empty();
+    parent.extend_prefix(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
+
+    // Prepending the prefix of another header only needs its stored bytes and
+    // fingerprint
+    child.prepend_prefix(&[11]);
+    child.prepend_header_prefix(&parent);
+    assert_eq!(child.prefix_size(), 14);
+    assert_eq!(child.read_prefix(), &[1, 2, 3, 4, 5, 6, 7, 8]);
+    let mut expected = Header::empty();
+    expected.extend_prefix(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 20, 21, 22]);
+    assert_eq!(child.prefix_fingerprint(), expected.prefix_fingerprint());
+
+    let mut short_child = Header::empty();
+    short_child.extend_prefix(&[20, 21, 22]);
+    let mut short_parent = Header::empty();
+    short_parent.extend_prefix(&[1, 2, 3, 4, 5]);
+    short_child.prepend_header_prefix(&short_parent);
+    assert_eq!(short_child.prefix_size(), 8);
+    assert_eq!(short_child.read_prefix(), &[1, 2, 3, 4, 5, 20, 21, 22]);
+    assert_eq!(short_child.prefix_fingerprint(), None);
+}
+
+#[test]
+fn header_fingerprint_only_for_unstored_bytes() {
+    let full_prefix: Vec<u8> = (1..=20).collect();
+    let from_full = |bytes: &[u8]| {
+        let mut header = Header::empty();
+        header.extend_prefix(bytes);
+        header
+    };
+
+    let mut header = from_full(&full_prefix[..NUM_PREFIX_BYTES]);
+    assert_eq!(header.prefix_fingerprint(), None);
+    assert!(header.may_match_full_prefix(&[0; 10]));
+
+    // Every way of building the same prefix ends up with the same fingerprint
+    header.extend_prefix(&full_prefix[NUM_PREFIX_BYTES..12]);
+    header.extend_prefix(&full_prefix[12..]);
+    let fingerprint = header.prefix_fingerprint().unwrap();
+    assert_eq!(
+        from_full(&full_prefix).prefix_fingerprint(),
+        Some(fingerprint)
+    );
+    let mut prepended = from_full(&full_prefix[5..]);
+    prepended.prepend_prefix(&full_prefix[..5]);
+    assert_eq!(prepended.prefix_fingerprint(), Some(fingerprint));
+    let mut prepended = from_full(&full_prefix[15..]);
+    prepended.prepend_header_prefix(&from_full(&full_prefix[..15]));
+    assert_eq!(prepended.prefix_fingerprint(), Some(fingerprint));
+
+    assert!(header.may_match_full_prefix(&[full_prefix.as_slice(), &[21, 22]].concat()));
+    // Only the bytes which are not stored are fingerprinted
+    let mut key = full_prefix.clone();
+    key[0] = 0;
+    assert!(header.may_match_full_prefix(&key));
+    key[15] = 0;
+    assert!(!header.may_match_full_prefix(&key));
+    // The key is too short to contain the prefix
+    assert!(!header.may_match_full_prefix(&full_prefix[..19]));
+
+    // Trimming recomputes the fingerprint without touching the number of children
+    header.set_num_children(7);
+    header.ltrim_prefix_with(3, &full_prefix);
+    assert_eq!(
+        header.prefix_fingerprint(),
+        from_full(&full_prefix[3..]).prefix_fingerprint()
+    );
+    assert_eq!(header.num_children(), 7);
+    header.ltrim_prefix_with(10, &full_prefix[3..]);
+    assert_eq!(header.prefix_fingerprint(), None);
+    assert_eq!(header.num_children(), 7);
+}
+
+#[test]
+#[should_panic = "cannot trim a prefix which is not fully stored in the header"]
+fn header_ltrim_prefix_not_fully_stored_panic() {
+    let mut header = Header::empty();
+    header.extend_prefix(&[1; 12]);
+    header.ltrim_prefix(1);
+}
+
+#[test]
+fn header_num_children_independent_of_prefix() {
+    let mut header = Header::empty();
+
+    header.set_num_children(256);
+    header.extend_prefix(&[7; 20]);
+    assert_eq!(header.num_children(), 256);
+    assert_eq!(header.read_prefix(), &[7; NUM_PREFIX_BYTES]);
+    assert_eq!(header.prefix_size(), 20);
+
+    header.dec_num_children();
+    header.ltrim_prefix_with(15, &[7; 20]);
+    assert_eq!(header.num_children(), 255);
+    assert_eq!(header.read_prefix(), &[7; 5]);
+
+    header.set_num_children(0);
+    header.inc_num_children();
+    assert_eq!(header.num_children(), 1);
+    assert_eq!(header.prefix_size(), 5);
+}
+
+#[test]
+#[should_panic]
+fn header_ltrim_too_many_bytes_panic() {
+    let mut header = Header::empty();
+    header.extend_prefix(&[1, 2]);
+    header.ltrim_prefix(3);
+}
+
 fn inner_node_write_child_test(
     mut node: impl InnerNode<Key = Box<[u8]>, Value = ()>,
     num_children: usize,
@@ -258,7 +441,10 @@ fn inner_node_split_at_on_test_keys_moved(
 
     let split_node = node.split_at(split_key_fragment);
 
-    assert_eq!(node.header().prefix, split_node.header().prefix);
+    assert_eq!(
+        node.header().read_prefix(),
+        split_node.header().read_prefix()
+    );
 
     for (idx, key_fragment) in children_key_fragments.iter().copied().enumerate() {
         let leaf_pointer = NodePtr::from(&mut leaves[idx]).to_opaque();
@@ -292,7 +478,7 @@ fn node4_lookup() {
 
     assert!(n.lookup_child(123).is_none());
 
-    n.header.num_children = 3;
+    n.header.set_num_children(3);
     n.keys[0].write(3);
     n.keys[1].write(123);
     n.keys[2].write(1);
@@ -394,7 +580,7 @@ fn node16_lookup() {
 
     assert!(n.lookup_child(123).is_none());
 
-    n.header.num_children = 3;
+    n.header.set_num_children(3);
     n.keys[0].write(3);
     n.keys[1].write(123);
     n.keys[2].write(1);
@@ -499,7 +685,7 @@ fn node48_lookup() {
 
     assert!(n.lookup_child(123).is_none());
 
-    n.header.num_children = 3;
+    n.header.set_num_children(3);
 
     n.child_indices[1] = 2usize.try_into().unwrap();
     n.child_indices[3] = 0usize.try_into().unwrap();
@@ -604,7 +790,7 @@ fn node256_lookup() {
 
     assert!(n.lookup_child(123).is_none());
 
-    n.header.num_children = 3;
+    n.header.set_num_children(3);
 
     n.child_pointers[1] = Some(l1_ptr);
     n.child_pointers[123] = Some(l2_ptr);
@@ -702,12 +888,12 @@ fn header_read_write_prefix() {
     h.extend_prefix(&[9, 10, 11, 12]);
 
     assert_eq!(h.prefix_size(), 12);
-    assert_eq!(h.read_prefix(), &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+    assert_eq!(h.read_prefix(), &[1, 2, 3, 4, 5, 6, 7, 8]);
 
     h.extend_prefix(&[]);
 
     assert_eq!(h.prefix_size(), 12);
-    assert_eq!(h.read_prefix(), &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+    assert_eq!(h.read_prefix(), &[1, 2, 3, 4, 5, 6, 7, 8]);
 }
 
 #[test]
@@ -735,7 +921,7 @@ fn header_prepend_prefix() {
     h.extend_prefix(&[7, 8, 9]);
 
     assert_eq!(h.prefix_size(), 9);
-    assert_eq!(h.read_prefix(), &[4, 5, 6, 1, 2, 3, 7, 8, 9]);
+    assert_eq!(h.read_prefix(), &[4, 5, 6, 1, 2, 3, 7, 8]);
 }
 
 #[test]
@@ -757,10 +943,11 @@ fn header_check_prefix() {
     assert_eq!(h.match_prefix(&[]), 0);
 
     assert_eq!(h.match_prefix(&[1, 2, 3, 4, 5, 6, 7, 8]), 8);
-    assert_eq!(h.match_prefix(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]), 12);
+    // Only the stored bytes are compared
+    assert_eq!(h.match_prefix(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]), 8);
     assert_eq!(
         h.match_prefix(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14]),
-        12
+        8
     );
     assert_eq!(
         h.match_prefix(&[1, 2, 3, 4, 5, 6, 7, 8, 100, 200, 254, 255]),
@@ -768,12 +955,37 @@ fn header_check_prefix() {
     );
 }
 
+#[test]
+fn header_match_prefix_finds_first_mismatch() {
+    let prefix = [1, 2, 3, 4, 5, 6, 7, 8];
+    for prefix_len in 0..=prefix.len() {
+        let mut h = Header::empty();
+        h.extend_prefix(&prefix[..prefix_len]);
+
+        for key_len in 0..=prefix.len() {
+            let expected = prefix_len.min(key_len);
+            assert_eq!(h.match_prefix(&prefix[..key_len]), expected);
+
+            for mismatch in 0..key_len {
+                let mut key = prefix[..key_len].to_vec();
+                key[mismatch] ^= 0x80;
+                assert_eq!(
+                    h.match_prefix(&key),
+                    expected.min(mismatch),
+                    "{prefix_len} {key_len} {mismatch}"
+                );
+            }
+        }
+    }
+}
+
 #[test]
 fn empty_prefix_bytes_match() {
     let mut h = Header::empty();
 
-    h.extend_prefix(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14]);
-    h.ltrim_prefix(NUM_PREFIX_BYTES);
+    let full_prefix = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14];
+    h.extend_prefix(&full_prefix);
+    h.ltrim_prefix_with(NUM_PREFIX_BYTES, &full_prefix);
     // 6 bytes are represented
 
     assert_eq!(h.match_prefix(&[1, 2, 3]), 0);
@@ -0,0 +1,284 @@
+//! The versioned lock word stored in front of every node of a
+//! [`ConcurrentTreeMap`][crate::ConcurrentTreeMap], used for optimistic lock
+//! coupling.
+//!
+//! The scheme follows "The ART of Practical Synchronization" (Leis et al.,
+//! 2016). Readers never write to a node: they read its version, read the node
+//! contents, then check that the version did not change. Writers take an
+//! exclusive lock on each node they modify, and bump the version when they
+//! release it, so that any reader that overlapped with the change restarts.
+
+use super::{LeafNode, OpaqueNodePtr};
+use crate::{
+    allocator::{AllocError, Allocator, Global},
+    nightly_rust_apis::non_null_slice_from_raw_parts,
+};
+use std::{
+    alloc::Layout,
+    hint,
+    ptr::NonNull,
+    sync::atomic::{self, AtomicU64, Ordering},
+};
+
+/// The error returned when an optimistic read or a lock upgrade observed a
+/// concurrent change, and the whole operation must start over from the root.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Restart;
+
+/// A lock word holding a version counter, a write lock bit, and an obsolete
+/// bit.
+///
+/// The lowest bit is set once the node has been unlinked from the tree, the
+/// second lowest bit is set while a writer holds the lock, and the remaining
+/// bits count the number of times the lock was released.
+pub(crate) struct NodeVersion(AtomicU64);
+
+impl NodeVersion {
+    /// The bit which is set while a writer holds the lock.
+    const LOCKED: u64 = 0b10;
+    /// The bit which is set once the node is no longer part of the tree.
+    const OBSOLETE: u64 = 0b01;
+
+    /// Create a new unlocked version.
+    pub(crate) const fn new() -> Self {
+        NodeVersion(AtomicU64::new(0))
+    }
+
+    /// Start an optimistic read of the node, returning the version to validate
+    /// the read against.
+    ///
+    /// This waits while a writer holds the lock.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Restart`] if the node is obsolete.
+    pub(crate) fn read_lock(&self) -> Result<u64, Restart> {
+        loop {
+            let version = self.0.load(Ordering::Acquire);
+            if version & Self::LOCKED != 0 {
+                hint::spin_loop();
+                continue;
+            }
+
+            return if version & Self::OBSOLETE != 0 {
+                Err(Restart)
+            } else {
+                Ok(version)
+            };
+        }
+    }
+
+    /// Check that the node did not change since `version` was read with
+    /// [`NodeVersion::read_lock`], so that everything read from the node in
+    /// the meantime is consistent.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Restart`] if the version changed.
+    pub(crate) fn check(&self, version: u64) -> Result<(), Restart> {
+        // The reads of the node contents must happen before the version is read
+        // again, so that a concurrent write is always detected.
+        atomic::fence(Ordering::Acquire);
+        if self.0.load(Ordering::Relaxed) == version {
+            Ok(())
+        } else {
+            Err(Restart)
+        }
+    }
+
+    /// Take the write lock, if the node did not change since `version` was
+    /// read with [`NodeVersion::read_lock`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Restart`] if the version changed.
+    pub(crate) fn upgrade_to_write_lock(&self, version: u64) -> Result<(), Restart> {
+        self.0
+            .compare_exchange(
+                version,
+                version + Self::LOCKED,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .map_err(|_| Restart)?;
+        // The new version must be visible before any change to the node, so that a
+        // reader which sees a change also sees the new version when it checks.
+        atomic::fence(Ordering::Release);
+        Ok(())
+    }
+
+    /// Take the write lock, waiting while another writer holds it.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Restart`] if the node is obsolete.
+    pub(crate) fn write_lock(&self) -> Result<(), Restart> {
+        loop {
+            let version = self.read_lock()?;
+            if self.upgrade_to_write_lock(version).is_ok() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Release the write lock, and bump the version.
+    pub(crate) fn write_unlock(&self) {
+        debug_assert!(self.0.load(Ordering::Relaxed) & Self::LOCKED != 0);
+        // Adding the lock bit again clears it and carries into the counter
+        self.0.fetch_add(Self::LOCKED, Ordering::Release);
+    }
+
+    /// Release the write lock, and mark the node as obsolete, so that every
+    /// reader that finds it restarts.
+    pub(crate) fn write_unlock_obsolete(&self) {
+        debug_assert!(self.0.load(Ordering::Relaxed) & Self::LOCKED != 0);
+        self.0
+            .fetch_add(Self::LOCKED | Self::OBSOLETE, Ordering::Release);
+    }
+}
+
+impl Default for NodeVersion {
+    fn default() -> Self {
+        NodeVersion::new()
+    }
+}
+
+/// An [`Allocator`] which places a new [`NodeVersion`] right in front of
+/// every block it allocates.
+///
+/// The nodes of a [`ConcurrentTreeMap`][crate::ConcurrentTreeMap] are
+/// allocated with it, so that the node types and their [`Header`] stay the
+/// same size for every other tree. Leaves get a lock word as well, so that the
+/// whole tree can be deallocated with one allocator, but they never use it.
+///
+/// [`Header`]: super::Header
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct VersionedAlloc;
+
+impl VersionedAlloc {
+    /// Return the layout of a block with a lock word in front of a value with
+    /// the given layout, and the offset of the value in the block.
+    ///
+    /// The offset is a multiple of the alignment of the lock word, so the lock
+    /// word always fits right in front of the value.
+    fn layout_with_version(layout: Layout) -> (Layout, usize) {
+        Layout::new::<NodeVersion>()
+            .extend(layout)
+            .expect("node layout with a lock word should not overflow")
+    }
+}
+
+// SAFETY: The blocks are allocated by the global allocator, which lives for the
+// whole program, and `VersionedAlloc` holds no state to invalidate when it
+// moves.
+unsafe impl Allocator for VersionedAlloc {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let (block_layout, offset) = Self::layout_with_version(layout);
+        let block = Global.allocate(block_layout)?.cast::<u8>();
+
+        // SAFETY: The value starts `offset` bytes into the block, and the lock word
+        // takes up the aligned bytes right in front of it, which are part of the block
+        // and not used by anything else.
+        unsafe {
+            let value_ptr = block.as_ptr().add(offset);
+            value_ptr
+                .cast::<NodeVersion>()
+                .sub(1)
+                .write(NodeVersion::new());
+            Ok(non_null_slice_from_raw_parts(
+                NonNull::new_unchecked(value_ptr),
+                layout.size(),
+            ))
+        }
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let (block_layout, offset) = Self::layout_with_version(layout);
+        // SAFETY: The pointer was returned by `allocate` with the same layout, so the
+        // block starts `offset` bytes before it. The lock word needs no drop.
+        unsafe {
+            let block = NonNull::new_unchecked(ptr.as_ptr().sub(offset));
+            Global.deallocate(block, block_layout);
+        }
+    }
+}
+
+impl<K, V> OpaqueNodePtr<K, V> {
+    /// Return the lock word in front of this inner node, or `None` if this is
+    /// a leaf.
+    ///
+    /// Only the lock word is borrowed, which is accessed atomically, so this
+    /// can be called while a writer changes the node.
+    ///
+    /// # Safety
+    ///
+    ///  - The node must have been allocated with [`VersionedAlloc`], and must
+    ///    not be deallocated for the lifetime `'h`.
+    pub(crate) unsafe fn version<'h>(self) -> Option<&'h NodeVersion> {
+        if self.is::<LeafNode<K, V>>() {
+            return None;
+        }
+
+        // SAFETY: The node was allocated by `VersionedAlloc`, which placed an
+        // initialized lock word right in front of it, and the block is allocated for
+        // `'h` by the safety requirements of this function.
+        Some(unsafe { &*self.0.to_ptr().cast::<NodeVersion>().sub(1) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{InnerNode, InnerNode4, NodePtr};
+
+    #[test]
+    fn write_unlock_invalidates_reads() {
+        let version = NodeVersion::new();
+        let read = version.read_lock().unwrap();
+        assert_eq!(version.check(read), Ok(()));
+
+        version.upgrade_to_write_lock(read).unwrap();
+        assert_eq!(version.check(read), Err(Restart));
+        // Only one writer can upgrade from the same version
+        assert_eq!(version.upgrade_to_write_lock(read), Err(Restart));
+        version.write_unlock();
+
+        assert_eq!(version.check(read), Err(Restart));
+        let new_read = version.read_lock().unwrap();
+        assert_ne!(read, new_read);
+        assert_eq!(version.check(new_read), Ok(()));
+    }
+
+    #[test]
+    fn versioned_alloc_keeps_lock_word_in_front_of_node() {
+        let node_ptr =
+            NodePtr::allocate_node_ptr_in(InnerNode4::<u8, u8>::empty(), &VersionedAlloc);
+        let node = node_ptr.to_opaque();
+
+        // SAFETY: The node was allocated with `VersionedAlloc` and is only
+        // deallocated at the end of the test
+        let version = unsafe { node.version() }.unwrap();
+        let read = version.read_lock().unwrap();
+        version.upgrade_to_write_lock(read).unwrap();
+        // SAFETY: The lock is held, and no other reference to the node exists
+        unsafe { node.header_mut() }
+            .unwrap()
+            .extend_prefix(&[1, 2, 3]);
+        version.write_unlock();
+        assert_eq!(version.check(read), Err(Restart));
+
+        // SAFETY: Same as above
+        let node = unsafe { NodePtr::deallocate_node_ptr_in(node_ptr, &VersionedAlloc) };
+        assert_eq!(node.header().read_prefix(), &[1, 2, 3]);
+    }
+
+    #[test]
+    fn obsolete_node_cannot_be_read_or_locked() {
+        let version = NodeVersion::new();
+        version.write_lock().unwrap();
+        version.write_unlock_obsolete();
+
+        assert_eq!(version.read_lock(), Err(Restart));
+        assert_eq!(version.write_lock(), Err(Restart));
+    }
+}
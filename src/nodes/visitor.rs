@@ -1,12 +1,18 @@
 //! Utilities for inspecting the trie structure.
+//!
+//! The [`Visitor`] trait recurses once for every level of the tree, so a
+//! custom visitor uses call stack space proportional to the depth of the tree.
+//! The visitors provided by this module instead keep the nodes left to visit
+//! on a heap allocated stack, so they can inspect trees of any depth.
 
 mod pretty_printer;
 mod tree_stats;
 mod well_formed;
 
 use crate::{
-    ConcreteNodePtr, InnerNode16, InnerNode256, InnerNode256Iter, InnerNode4, InnerNode48,
-    InnerNode48Iter, InnerNodeCompressedIter, LeafNode, Node, NodePtr, OpaqueNodePtr,
+    ConcreteNodePtr, InnerNode, InnerNode16, InnerNode256, InnerNode256Iter, InnerNode4,
+    InnerNode48, InnerNode48Iter, InnerNodeCompressedIter, InnerNodeIter, LeafNode, Node, NodePtr,
+    OpaqueNodePtr,
 };
 pub use pretty_printer::*;
 pub use tree_stats::*;
@@ -190,3 +196,27 @@ fn combine_inner_node_child_output<K, T, V: Visitor<K, T>>(
         visitor.default_output()
     }
 }
+
+/// Returns an iterator over the children of the given node, or `None` if the
+/// node is a leaf.
+///
+/// # Safety
+///
+///  - For the lifetime of the returned iterator, the given node must not get
+///    mutated.
+pub(crate) unsafe fn inner_node_children<K, V>(
+    node: OpaqueNodePtr<K, V>,
+) -> Option<InnerNodeIter<K, V>> {
+    // SAFETY: The node references only live for this function, and the safety
+    // requirements of this function forbid mutation of the node while the returned
+    // iterator is live.
+    unsafe {
+        match node.to_node_ptr() {
+            ConcreteNodePtr::Node4(inner_ptr) => Some(inner_ptr.as_ref().iter().into()),
+            ConcreteNodePtr::Node16(inner_ptr) => Some(inner_ptr.as_ref().iter().into()),
+            ConcreteNodePtr::Node48(inner_ptr) => Some(inner_ptr.as_ref().iter().into()),
+            ConcreteNodePtr::Node256(inner_ptr) => Some(inner_ptr.as_ref().iter().into()),
+            ConcreteNodePtr::LeafNode(_) => None,
+        }
+    }
+}
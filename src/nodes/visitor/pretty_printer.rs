@@ -1,10 +1,11 @@
 use crate::{
-    visitor::{Visitable, Visitor},
-    InnerNode, NodeType, OpaqueNodePtr,
+    visitor::inner_node_children, ConcreteNodePtr, InnerNode, InnerNodeIter, LeafNode, NodeType,
+    OpaqueNodePtr,
 };
 use std::{
     fmt::Debug,
     io::{self, Write},
+    iter::Enumerate,
 };
 
 /// Settings which customize the output of the [`DotPrinter`] visitor.
@@ -46,10 +47,103 @@ impl<O: Write> DotPrinter<O> {
         };
 
         visitor.output_prelude()?;
-        let _ = tree.visit_with(&mut visitor)?;
+        // SAFETY: Covered by the safety requirements of this function
+        unsafe { visitor.write_tree(*tree)? };
         visitor.output_epilogue()
     }
 
+    /// Write every node of the tree in depth-first order, keeping the inner
+    /// nodes whose children are still being written on a heap allocated stack.
+    ///
+    /// # Safety
+    ///  - For the duration of this function, the given node and all its
+    ///    children nodes must not get mutated.
+    unsafe fn write_tree<K, V>(&mut self, root: OpaqueNodePtr<K, V>) -> io::Result<()>
+    where
+        K: Debug,
+        V: Debug,
+    {
+        // SAFETY: Covered by the safety requirements of this function
+        let (root_id, root_children) = unsafe { self.write_node(root)? };
+        let mut stack = match root_children {
+            Some(children) => vec![PrintFrame {
+                node_id: root_id,
+                children,
+                parent_edge: None,
+            }],
+            None => return Ok(()),
+        };
+
+        while let Some(frame) = stack.last_mut() {
+            match frame.children.next() {
+                Some((key_frag_id, (_, child))) => {
+                    let node_id = frame.node_id;
+                    // SAFETY: Covered by the safety requirements of this function
+                    match unsafe { self.write_node(child)? } {
+                        (child_id, None) => self.write_edge(node_id, key_frag_id, child_id)?,
+                        (child_id, Some(children)) => stack.push(PrintFrame {
+                            node_id: child_id,
+                            children,
+                            parent_edge: Some((node_id, key_frag_id)),
+                        }),
+                    }
+                },
+                None => {
+                    let node_id = frame.node_id;
+                    if let Some((parent_id, key_frag_id)) = frame.parent_edge {
+                        self.write_edge(parent_id, key_frag_id, node_id)?;
+                    }
+                    stack.pop();
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the given node, returning its id and the children that still need
+    /// to be written if it is an inner node.
+    ///
+    /// # Safety
+    ///  - For the lifetime of the returned iterator, the given node must not
+    ///    get mutated.
+    #[allow(clippy::type_complexity)]
+    unsafe fn write_node<K, V>(
+        &mut self,
+        node: OpaqueNodePtr<K, V>,
+    ) -> io::Result<(usize, Option<Enumerate<InnerNodeIter<K, V>>>)>
+    where
+        K: Debug,
+        V: Debug,
+    {
+        // SAFETY: The references only live for this function, and the safety
+        // requirements forbid mutation of the node.
+        let node_id = unsafe {
+            match node.to_node_ptr() {
+                ConcreteNodePtr::Node4(inner_ptr) => self.write_inner_node(inner_ptr.as_ref())?,
+                ConcreteNodePtr::Node16(inner_ptr) => self.write_inner_node(inner_ptr.as_ref())?,
+                ConcreteNodePtr::Node48(inner_ptr) => self.write_inner_node(inner_ptr.as_ref())?,
+                ConcreteNodePtr::Node256(inner_ptr) => self.write_inner_node(inner_ptr.as_ref())?,
+                ConcreteNodePtr::LeafNode(leaf_ptr) => {
+                    return Ok((self.write_leaf(leaf_ptr.as_ref())?, None));
+                },
+            }
+        };
+
+        // SAFETY: Covered by the safety requirements of this function
+        let children = unsafe { inner_node_children(node) };
+        Ok((node_id, children.map(Iterator::enumerate)))
+    }
+
+    fn write_edge(
+        &mut self,
+        node_id: usize,
+        key_frag_id: usize,
+        child_id: usize,
+    ) -> io::Result<()> {
+        writeln!(self.output, "n{node_id}:c{key_frag_id} -> n{child_id}:h0")
+    }
+
     fn output_prelude(&mut self) -> io::Result<()> {
         writeln!(self.output, "strict digraph G {{")?;
         writeln!(self.output, "node [shape=record]")
@@ -108,53 +202,14 @@ impl<O: Write> DotPrinter<O> {
         }
         writeln!(self.output, "}}}}\"]")?;
 
-        // SAFETY: The `child_it` does not live beyond the following loop and will not
-        // overlap with any mutating access or operation, which is guaranteed by the
-        // `print_tree` caller requirements.
-        let child_it = unsafe { inner_node.iter() };
-        for (key_frag_id, (_, child)) in child_it.enumerate() {
-            let child_id = child.visit_with(self)?;
-
-            writeln!(self.output, "n{node_id}:c{key_frag_id} -> n{child_id}:h0")?;
-        }
-
         Ok(node_id)
     }
-}
-
-impl<K, T, O> Visitor<K, T> for DotPrinter<O>
-where
-    K: Debug,
-    T: Debug,
-    O: Write,
-{
-    type Output = io::Result<usize>;
-
-    fn default_output(&self) -> Self::Output {
-        unimplemented!("this visitor should never use the default output")
-    }
-
-    fn combine_output(&self, _: Self::Output, _: Self::Output) -> Self::Output {
-        unimplemented!("this visitor should never combine outputs")
-    }
 
-    fn visit_node4(&mut self, t: &crate::InnerNode4<K, T>) -> Self::Output {
-        self.write_inner_node(t)
-    }
-
-    fn visit_node16(&mut self, t: &crate::InnerNode16<K, T>) -> Self::Output {
-        self.write_inner_node(t)
-    }
-
-    fn visit_node48(&mut self, t: &crate::InnerNode48<K, T>) -> Self::Output {
-        self.write_inner_node(t)
-    }
-
-    fn visit_node256(&mut self, t: &crate::InnerNode256<K, T>) -> Self::Output {
-        self.write_inner_node(t)
-    }
-
-    fn visit_leaf(&mut self, t: &crate::LeafNode<K, T>) -> Self::Output {
+    fn write_leaf<K, T>(&mut self, t: &LeafNode<K, T>) -> io::Result<usize>
+    where
+        K: Debug,
+        T: Debug,
+    {
         let node_id = self.get_id();
         write!(self.output, "n{node_id} ")?;
         write!(self.output, "[label=\"{{")?;
@@ -182,6 +237,15 @@ where
     }
 }
 
+/// An inner node whose children are being written.
+struct PrintFrame<K, V> {
+    node_id: usize,
+    /// The children which have not been written yet
+    children: Enumerate<InnerNodeIter<K, V>>,
+    /// The node id and child index of the edge from the parent to this node
+    parent_edge: Option<(usize, usize)>,
+}
+
 #[cfg(test)]
 mod tests {
     use crate::deallocate_tree;
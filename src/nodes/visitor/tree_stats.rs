@@ -1,6 +1,6 @@
 use crate::{
-    visitor::{Visitable, Visitor},
-    AsBytes, NodeType, OpaqueNodePtr,
+    visitor::{inner_node_children, Visitable, Visitor},
+    AsBytes, ConcreteNodePtr, InnerNode, LeafNode, NodeType, OpaqueNodePtr, TreeIterator,
 };
 use std::mem;
 
@@ -20,9 +20,34 @@ impl TreeStatsCollector {
     where
         K: AsBytes,
     {
-        let mut collector = TreeStatsCollector;
+        let mut stats = TreeStats::default();
+        let mut stack = vec![(root, 0)];
+
+        while let Some((node, depth)) = stack.pop() {
+            // SAFETY: The references only live for this loop iteration, and the safety
+            // requirements of this function forbid mutation of the tree.
+            let node_stats = unsafe {
+                match node.to_node_ptr() {
+                    ConcreteNodePtr::Node4(inner_ptr) => inner_node_stats(inner_ptr.as_ref()),
+                    ConcreteNodePtr::Node16(inner_ptr) => inner_node_stats(inner_ptr.as_ref()),
+                    ConcreteNodePtr::Node48(inner_ptr) => inner_node_stats(inner_ptr.as_ref()),
+                    ConcreteNodePtr::Node256(inner_ptr) => inner_node_stats(inner_ptr.as_ref()),
+                    ConcreteNodePtr::LeafNode(leaf_ptr) => TreeStats {
+                        max_depth: depth,
+                        ..leaf_node_stats(leaf_ptr.as_ref())
+                    },
+                }
+            };
+            stats = stats.merge(node_stats);
+
+            // SAFETY: The iterator only lives for this statement, and the safety
+            // requirements of this function forbid mutation of the tree.
+            if let Some(children) = unsafe { inner_node_children(node) } {
+                stack.extend(children.map(|(_, child)| (child, depth + 1)));
+            }
+        }
 
-        root.visit_with(&mut collector)
+        stats
     }
 
     /// Iterate through the given tree and return the number of leaf nodes.
@@ -31,27 +56,8 @@ impl TreeStatsCollector {
     ///  - For the duration of this function, the given node and all its
     ///    children nodes must not get mutated.
     pub unsafe fn count_leaf_nodes<K, V>(root: OpaqueNodePtr<K, V>) -> usize {
-        struct LeafNodeCounter;
-
-        impl<K, V> Visitor<K, V> for LeafNodeCounter {
-            type Output = usize;
-
-            fn default_output(&self) -> Self::Output {
-                0
-            }
-
-            fn combine_output(&self, o1: Self::Output, o2: Self::Output) -> Self::Output {
-                o1 + o2
-            }
-
-            fn visit_leaf(&mut self, _t: &crate::LeafNode<K, V>) -> Self::Output {
-                1
-            }
-        }
-
-        let mut counter = LeafNodeCounter;
-
-        root.visit_with(&mut counter)
+        // SAFETY: Covered by the safety requirements of this function
+        unsafe { TreeIterator::new(root) }.count()
     }
 }
 
@@ -89,9 +95,63 @@ pub struct TreeStats {
 
     /// The total number of bytes used by inner nodes.
     pub total_inner_node_bytes: usize,
+
+    /// The total number of bytes used by leaf nodes, not including any memory
+    /// owned by the keys and values.
+    pub total_leaf_node_bytes: usize,
+
+    /// The total length of the compressed paths of all inner nodes.
+    pub total_prefix_bytes: usize,
+
+    /// The largest number of inner nodes on the path from the root to a leaf.
+    pub max_depth: usize,
 }
 
 impl TreeStats {
+    fn merge(self, other: Self) -> Self {
+        TreeStats {
+            node16_count: self.node16_count + other.node16_count,
+            node256_count: self.node256_count + other.node256_count,
+            node48_count: self.node48_count + other.node48_count,
+            node4_count: self.node4_count + other.node4_count,
+            leaf_count: self.leaf_count + other.leaf_count,
+            empty_capacity: self.empty_capacity + other.empty_capacity,
+            total_inner_node_bytes: self.total_inner_node_bytes + other.total_inner_node_bytes,
+            total_key_bytes: self.total_key_bytes + other.total_key_bytes,
+            total_leaf_node_bytes: self.total_leaf_node_bytes + other.total_leaf_node_bytes,
+            total_prefix_bytes: self.total_prefix_bytes + other.total_prefix_bytes,
+            max_depth: self.max_depth.max(other.max_depth),
+        }
+    }
+
+    /// Returns the number of inner nodes of any type.
+    pub fn inner_node_count(&self) -> usize {
+        self.node4_count + self.node16_count + self.node48_count + self.node256_count
+    }
+
+    /// Returns the total number of bytes allocated for the nodes of the tree.
+    ///
+    /// This does not include any memory owned by the keys and values.
+    pub fn total_allocated_bytes(&self) -> usize {
+        self.total_inner_node_bytes + self.total_leaf_node_bytes
+    }
+
+    /// Returns the fraction of the child slots of all inner nodes which are
+    /// occupied, between 0 and 1.
+    ///
+    /// If there are no inner nodes, this returns 1, since no slot is empty.
+    pub fn average_fill_factor(&self) -> f64 {
+        let total_capacity = self.node4_count * NodeType::Node4.upper_capacity()
+            + self.node16_count * NodeType::Node16.upper_capacity()
+            + self.node48_count * NodeType::Node48.upper_capacity()
+            + self.node256_count * NodeType::Node256.upper_capacity();
+        if total_capacity == 0 {
+            return 1.0;
+        }
+
+        ((total_capacity - self.empty_capacity) as f64) / (total_capacity as f64)
+    }
+
     /// Returns the number of bytes of overhead per byte of key stored in the
     /// tree.
     ///
@@ -112,82 +172,68 @@ where
     }
 
     fn combine_output(&self, o1: Self::Output, o2: Self::Output) -> Self::Output {
-        TreeStats {
-            node16_count: o1.node16_count + o2.node16_count,
-            node256_count: o1.node256_count + o2.node256_count,
-            node48_count: o1.node48_count + o2.node48_count,
-            node4_count: o1.node4_count + o2.node4_count,
-            leaf_count: o1.leaf_count + o2.leaf_count,
-            empty_capacity: o1.empty_capacity + o2.empty_capacity,
-            total_inner_node_bytes: o1.total_inner_node_bytes + o2.total_inner_node_bytes,
-            total_key_bytes: o1.total_key_bytes + o2.total_key_bytes,
-        }
+        o1.merge(o2)
     }
 
     fn visit_node4(&mut self, t: &crate::InnerNode4<K, V>) -> Self::Output {
-        let mut output = t.super_visit_with(self);
-        output.node4_count += 1;
-        output.empty_capacity += NodeType::Node4.upper_capacity() - t.header.num_children();
-        output.total_inner_node_bytes += mem::size_of_val(t)
-            + if t.header.prefix.is_heap() {
-                t.header.prefix.len()
-            } else {
-                0
-            };
-        output
+        below_inner_node(t.super_visit_with(self)).merge(inner_node_stats(t))
     }
 
     fn visit_node16(&mut self, t: &crate::InnerNode16<K, V>) -> Self::Output {
-        let mut output = t.super_visit_with(self);
-        output.node16_count += 1;
-        output.empty_capacity += NodeType::Node16.upper_capacity() - t.header.num_children();
-        output.total_inner_node_bytes += mem::size_of_val(t)
-            + if t.header.prefix.is_heap() {
-                t.header.prefix.len()
-            } else {
-                0
-            };
-        output
+        below_inner_node(t.super_visit_with(self)).merge(inner_node_stats(t))
     }
 
     fn visit_node48(&mut self, t: &crate::InnerNode48<K, V>) -> Self::Output {
-        let mut output = t.super_visit_with(self);
-        output.node48_count += 1;
-        output.empty_capacity += NodeType::Node48.upper_capacity() - t.header.num_children();
-        output.total_inner_node_bytes += mem::size_of_val(t)
-            + if t.header.prefix.is_heap() {
-                t.header.prefix.len()
-            } else {
-                0
-            };
-        output
+        below_inner_node(t.super_visit_with(self)).merge(inner_node_stats(t))
     }
 
     fn visit_node256(&mut self, t: &crate::InnerNode256<K, V>) -> Self::Output {
-        let mut output = t.super_visit_with(self);
-        output.node256_count += 1;
-        output.empty_capacity += NodeType::Node256.upper_capacity() - t.header.num_children();
-        output.total_inner_node_bytes += mem::size_of_val(t)
-            + if t.header.prefix.is_heap() {
-                t.header.prefix.len()
-            } else {
-                0
-            };
-        output
+        below_inner_node(t.super_visit_with(self)).merge(inner_node_stats(t))
     }
 
     fn visit_leaf(&mut self, t: &crate::LeafNode<K, V>) -> Self::Output {
-        let mut output = TreeStats::default();
-        output.leaf_count += 1;
-        output.total_key_bytes += t.key_ref().as_bytes().len();
-        output
+        leaf_node_stats(t)
+    }
+}
+
+/// Returns the stats of the children of an inner node, with the depths counted
+/// from the inner node.
+fn below_inner_node(children_stats: TreeStats) -> TreeStats {
+    TreeStats {
+        max_depth: children_stats.max_depth + 1,
+        ..children_stats
+    }
+}
+
+/// Returns the stats of a single inner node, not including its children.
+fn inner_node_stats<N: InnerNode>(t: &N) -> TreeStats {
+    let mut output = TreeStats::default();
+    match N::TYPE {
+        NodeType::Node4 => output.node4_count += 1,
+        NodeType::Node16 => output.node16_count += 1,
+        NodeType::Node48 => output.node48_count += 1,
+        NodeType::Node256 => output.node256_count += 1,
+        NodeType::Leaf => unreachable!("inner nodes do not have the leaf node type"),
     }
+    output.empty_capacity += N::TYPE.upper_capacity() - t.header().num_children();
+    output.total_prefix_bytes += t.header().prefix_size();
+    output.total_inner_node_bytes += mem::size_of_val(t);
+    output
+}
+
+/// Returns the stats of a single leaf node.
+fn leaf_node_stats<K: AsBytes, V>(t: &LeafNode<K, V>) -> TreeStats {
+    let mut output = TreeStats::default();
+    output.leaf_count += 1;
+    output.total_key_bytes += t.key_ref().as_bytes().len();
+    output.total_leaf_node_bytes += mem::size_of_val(t);
+    output
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::deallocate_tree;
+    use crate::{deallocate_tree, InnerNode16, InnerNode4};
 
     #[test]
     fn mostly_empty_tree_stats_fixed_length_tree() {
@@ -208,7 +254,10 @@ mod tests {
                 leaf_count: 16,
                 empty_capacity: 30,
                 total_key_bytes: 64,
-                total_inner_node_bytes: 1200
+                total_inner_node_bytes: 15 * mem::size_of::<InnerNode4<Box<[u8]>, usize>>(),
+                total_leaf_node_bytes: 16 * mem::size_of::<LeafNode<Box<[u8]>, usize>>(),
+                total_prefix_bytes: 0,
+                max_depth: 4,
             }
         );
 
@@ -234,10 +283,57 @@ mod tests {
                 leaf_count: 64,
                 empty_capacity: 0,
                 total_key_bytes: 128,
-                total_inner_node_bytes: 1464,
+                total_inner_node_bytes: 16 * mem::size_of::<InnerNode4<Box<[u8]>, usize>>()
+                    + mem::size_of::<InnerNode16<Box<[u8]>, usize>>(),
+                total_leaf_node_bytes: 64 * mem::size_of::<LeafNode<Box<[u8]>, usize>>(),
+                total_prefix_bytes: 0,
+                max_depth: 2,
             }
         );
 
         unsafe { deallocate_tree(root) };
     }
+
+    #[test]
+    fn prefix_and_depth_stats_match_visitor() {
+        // Every key shares a compressed path of 20 bytes, which is longer than the
+        // prefix stored in the inner node.
+        let keys = [[0u8, 0], [0, 1], [1, 0], [1, 1], [2, 0]].map(|suffix| {
+            let mut key = vec![7u8; 20];
+            key.extend_from_slice(&suffix);
+            key.into_boxed_slice()
+        });
+        let root = crate::tests_common::setup_tree_from_entries(keys.into_iter().zip(0..));
+
+        let stats = unsafe { TreeStatsCollector::collect(root) };
+        assert_eq!(stats.node4_count, 3);
+        assert_eq!(stats.inner_node_count(), 3);
+        assert_eq!(stats.leaf_count, 5);
+        assert_eq!(stats.total_prefix_bytes, 20);
+        assert_eq!(stats.max_depth, 2);
+        assert_eq!(stats.average_fill_factor(), 7.0 / 12.0);
+        assert_eq!(
+            stats.total_allocated_bytes(),
+            stats.total_inner_node_bytes + 5 * mem::size_of::<LeafNode<Box<[u8]>, usize>>()
+        );
+
+        assert_eq!(root.visit_with(&mut TreeStatsCollector), stats);
+
+        unsafe { deallocate_tree(root) };
+    }
+
+    #[test]
+    fn single_leaf_tree_stats() {
+        let root =
+            crate::tests_common::setup_tree_from_entries([(Box::from([1, 2, 3]), 0)].into_iter());
+
+        let stats = unsafe { TreeStatsCollector::collect(root) };
+        assert_eq!(stats.leaf_count, 1);
+        assert_eq!(stats.inner_node_count(), 0);
+        assert_eq!(stats.max_depth, 0);
+        assert_eq!(stats.average_fill_factor(), 1.0);
+        assert_eq!(root.visit_with(&mut TreeStatsCollector), stats);
+
+        unsafe { deallocate_tree(root) };
+    }
 }
@@ -1,11 +1,12 @@
 use crate::{
-    nodes::visitor::{Visitable, Visitor},
-    AsBytes, InnerNode, NodeType, OpaqueNodePtr,
+    minimum_unchecked, nodes::visitor::inner_node_children, AsBytes, ConcreteNodePtr, InnerNode,
+    InnerNodeIter, NodeType, OpaqueNodePtr, ResizePolicy,
 };
 use std::{
     collections::{hash_map::Entry, HashMap},
     error::Error,
     fmt,
+    ops::Range,
 };
 
 /// A portion of an entire key that should uniquely identify each node in
@@ -50,6 +51,8 @@ pub enum MalformedTreeError<K, V> {
         inner_node_type: NodeType,
         /// The number of children found at the inner node
         num_children: usize,
+        /// The range of the number of children that the inner node should have
+        expected_children: Range<usize>,
     },
     /// The expected key prefix did not match the actual prefix that was present
     /// in the leaf
@@ -81,11 +84,13 @@ where
                 key_prefix,
                 inner_node_type,
                 num_children,
+                expected_children,
             } => f
                 .debug_struct("WrongChildrenCount")
                 .field("key_prefix", key_prefix)
                 .field("inner_node_type", inner_node_type)
                 .field("num_children", num_children)
+                .field("expected_children", expected_children)
                 .finish(),
             Self::PrefixMismatch {
                 expected_prefix,
@@ -121,13 +126,13 @@ where
                 key_prefix,
                 inner_node_type,
                 num_children,
+                expected_children,
             } => {
                 write!(
                     f,
                     "Found an inner node of type [{inner_node_type:?}] at location \
                      [{key_prefix:?}] that had the wrong number of children! Expected children in \
-                     range [{:?}], but found [{num_children}] children",
-                    inner_node_type.capacity_range(),
+                     range [{expected_children:?}], but found [{num_children}] children",
                 )
             },
             MalformedTreeError::PrefixMismatch {
@@ -164,10 +169,12 @@ where
                 key_prefix,
                 inner_node_type,
                 num_children,
+                expected_children,
             } => Self::WrongChildrenCount {
                 key_prefix: key_prefix.clone(),
                 inner_node_type: *inner_node_type,
                 num_children: *num_children,
+                expected_children: expected_children.clone(),
             },
             Self::PrefixMismatch {
                 expected_prefix,
@@ -207,16 +214,19 @@ where
                     key_prefix: l_key_prefix,
                     inner_node_type: l_inner_node_type,
                     num_children: l_num_children,
+                    expected_children: l_expected_children,
                 },
                 Self::WrongChildrenCount {
                     key_prefix: r_key_prefix,
                     inner_node_type: r_inner_node_type,
                     num_children: r_num_children,
+                    expected_children: r_expected_children,
                 },
             ) => {
                 l_key_prefix == r_key_prefix
                     && l_inner_node_type == r_inner_node_type
                     && l_num_children == r_num_children
+                    && l_expected_children == r_expected_children
             },
             (
                 Self::PrefixMismatch {
@@ -242,7 +252,8 @@ impl<K, V> Error for MalformedTreeError<K, V> where K: AsBytes {}
 /// In this context, well-formed means that in the tree:
 ///  1. there are no loops between nodes
 ///  2. every inner node has a number of children that is in range for the
-///     inner node type. For example, InnerNode16 has between 5 and 16 children.
+///     inner node type. For example, InnerNode16 has between 5 and 16 children. The
+///     lower bound depends on the [`ResizePolicy`] the tree was modified with.
 ///  3. the elements of the key (as part of inner node prefixes and child
 ///     pointers) combine to match the leaf node key prefix
 ///
@@ -257,6 +268,7 @@ impl<K, V> Error for MalformedTreeError<K, V> where K: AsBytes {}
 pub struct WellFormedChecker<K, V> {
     current_key_prefix: Vec<u8>,
     seen_nodes: HashMap<OpaqueNodePtr<K, V>, KeyPrefix>,
+    resize_policy: ResizePolicy,
 }
 
 impl<K, V> WellFormedChecker<K, V>
@@ -275,124 +287,231 @@ where
     ///
     /// Returns an error if the given tree is not well-formed.
     pub unsafe fn check_tree(tree: OpaqueNodePtr<K, V>) -> Result<usize, MalformedTreeError<K, V>> {
-        let mut visitor = WellFormedChecker {
+        // SAFETY: Covered by the safety requirements of this function
+        unsafe { Self::check_tree_with_policy(tree, ResizePolicy::DEFAULT) }
+    }
+
+    /// Traverse the given tree and check that it is well-formed for a tree
+    /// which shrinks inner nodes according to the given [`ResizePolicy`].
+    /// Returns the number of nodes in the tree.
+    ///
+    /// # Safety
+    ///
+    ///  - For the duration of this function, the given node and all its
+    ///    children nodes must not get mutated.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the given tree is not well-formed.
+    pub unsafe fn check_tree_with_policy(
+        tree: OpaqueNodePtr<K, V>,
+        resize_policy: ResizePolicy,
+    ) -> Result<usize, MalformedTreeError<K, V>> {
+        let mut checker = WellFormedChecker {
             current_key_prefix: vec![],
             seen_nodes: HashMap::new(),
+            resize_policy,
         };
 
         // We see the root node at the empty prefix
-        visitor.seen_nodes.insert(tree, KeyPrefix::default());
+        checker.seen_nodes.insert(tree, KeyPrefix::default());
 
-        tree.visit_with(&mut visitor)
+        // SAFETY: Covered by the safety requirements of this function
+        unsafe { checker.check_from_root(tree) }
     }
 
-    fn visit_inner_node<N>(&mut self, inner_node: &N) -> Result<usize, MalformedTreeError<K, V>>
-    where
-        N: InnerNode<Key = K, Value = V>,
-    {
-        let original_key_prefix_len = self.current_key_prefix.len();
-
-        // update running key prefix with inner node partial prefix
-        self.current_key_prefix.extend(&inner_node.header().prefix);
-
-        // SAFETY: The `child_it` does not live beyond the following loop and will not
-        // overlap with any mutating access or operation, which is guaranteed by the
-        // `check_tree` caller requirements.
-        let child_it = unsafe { inner_node.iter() };
-
-        let mut running_node_count = 0;
-        let mut num_children: usize = 0;
-        for (key_byte, child_pointer) in child_it {
-            // update running key prefix with child pointer key fragment
-            self.current_key_prefix.push(key_byte);
-
-            let current_key_prefix: KeyPrefix = self.current_key_prefix.as_slice().into();
-
-            match self.seen_nodes.entry(child_pointer) {
-                Entry::Occupied(entry) => {
-                    return Err(MalformedTreeError::LoopFound {
-                        node_ptr: child_pointer,
-                        first_observed: entry.get().clone(),
-                        later_observed: current_key_prefix,
+    /// Check the tree in depth-first order, keeping the inner nodes which are
+    /// still being checked on a heap allocated stack.
+    ///
+    /// # Safety
+    ///
+    ///  - For the duration of this function, the given node and all its
+    ///    children nodes must not get mutated.
+    unsafe fn check_from_root(
+        &mut self,
+        root: OpaqueNodePtr<K, V>,
+    ) -> Result<usize, MalformedTreeError<K, V>> {
+        // SAFETY: Covered by the safety requirements of this function
+        let root_frame = match unsafe { self.enter_node(root)? } {
+            Some(frame) => frame,
+            None => return Ok(1),
+        };
+        let mut stack = vec![root_frame];
+
+        while let Some(frame) = stack.last_mut() {
+            if let Some((key_byte, child_pointer)) = frame.children.next() {
+                frame.num_children += 1;
+
+                // update running key prefix with child pointer key fragment
+                self.current_key_prefix.push(key_byte);
+
+                let current_key_prefix: KeyPrefix = self.current_key_prefix.as_slice().into();
+                match self.seen_nodes.entry(child_pointer) {
+                    Entry::Occupied(entry) => {
+                        return Err(MalformedTreeError::LoopFound {
+                            node_ptr: child_pointer,
+                            first_observed: entry.get().clone(),
+                            later_observed: current_key_prefix,
+                        });
+                    },
+                    Entry::Vacant(entry) => {
+                        entry.insert(current_key_prefix);
+                    },
+                }
+
+                // SAFETY: Covered by the safety requirements of this function
+                match unsafe { self.enter_node(child_pointer)? } {
+                    Some(child_frame) => stack.push(child_frame),
+                    None => {
+                        frame.node_count += 1;
+                        self.pop_key_byte(key_byte);
+                    },
+                }
+            } else {
+                // PANIC SAFETY: The loop condition checked that the stack is not empty
+                let frame = stack.pop().unwrap();
+
+                // remove inner node partial key prefix
+                self.current_key_prefix
+                    .truncate(frame.original_key_prefix_len);
+
+                let expected_children = self.resize_policy.children_range(frame.node_type);
+                if !(expected_children.contains(&frame.num_children)) {
+                    let current_key_prefix: KeyPrefix = self.current_key_prefix.as_slice().into();
+                    return Err(MalformedTreeError::WrongChildrenCount {
+                        key_prefix: current_key_prefix,
+                        inner_node_type: frame.node_type,
+                        num_children: frame.num_children,
+                        expected_children,
                     });
-                },
-                Entry::Vacant(entry) => {
-                    entry.insert(current_key_prefix);
-                },
+                }
+
+                match stack.last_mut() {
+                    Some(parent) => {
+                        parent.node_count += frame.node_count + 1;
+                        // remove child pointer key fragment
+                        self.current_key_prefix
+                            .pop()
+                            .expect("should match push of key byte");
+                    },
+                    None => return Ok(frame.node_count + 1),
+                }
             }
-
-            running_node_count += child_pointer.visit_with(self)?;
-
-            // remove child pointer key fragment
-            assert_eq!(
-                self.current_key_prefix
-                    .pop()
-                    .expect("should match push of key byte"),
-                key_byte
-            );
-
-            num_children += 1;
-        }
-
-        // remove inner node partial key prefix
-        self.current_key_prefix.truncate(original_key_prefix_len);
-
-        if !(N::TYPE.capacity_range().contains(&num_children)) {
-            let current_key_prefix: KeyPrefix = self.current_key_prefix.as_slice().into();
-            return Err(MalformedTreeError::WrongChildrenCount {
-                key_prefix: current_key_prefix,
-                inner_node_type: N::TYPE,
-                num_children,
-            });
         }
 
-        Ok(running_node_count + 1)
+        unreachable!("the root frame is only popped by returning")
     }
-}
-
-impl<K, V> Visitor<K, V> for WellFormedChecker<K, V>
-where
-    K: Clone + AsBytes,
-{
-    type Output = Result<usize, MalformedTreeError<K, V>>;
 
-    fn default_output(&self) -> Self::Output {
-        // Chose zero so that any places that call `default_output` don't influce the
-        // overall count
-        Ok(0)
-    }
-
-    fn combine_output(&self, o1: Self::Output, o2: Self::Output) -> Self::Output {
-        Ok(o1? + o2?)
-    }
+    /// Start checking the given node, returning the frame to check the children
+    /// of an inner node, or `None` after checking a leaf.
+    ///
+    /// # Safety
+    ///
+    ///  - For the duration of this function, the given node and all its
+    ///    children nodes must not get mutated.
+    unsafe fn enter_node(
+        &mut self,
+        node: OpaqueNodePtr<K, V>,
+    ) -> Result<Option<CheckFrame<K, V>>, MalformedTreeError<K, V>> {
+        let original_key_prefix_len = self.current_key_prefix.len();
 
-    fn visit_node4(&mut self, t: &crate::InnerNode4<K, V>) -> Self::Output {
-        self.visit_inner_node(t)
-    }
+        // SAFETY: The references only live for this function, and the safety
+        // requirements forbid mutation of the tree.
+        let (node_type, header) = unsafe {
+            match node.to_node_ptr() {
+                ConcreteNodePtr::Node4(inner_ptr) => (NodeType::Node4, inner_ptr.as_ref().header()),
+                ConcreteNodePtr::Node16(inner_ptr) => {
+                    (NodeType::Node16, inner_ptr.as_ref().header())
+                },
+                ConcreteNodePtr::Node48(inner_ptr) => {
+                    (NodeType::Node48, inner_ptr.as_ref().header())
+                },
+                ConcreteNodePtr::Node256(inner_ptr) => {
+                    (NodeType::Node256, inner_ptr.as_ref().header())
+                },
+                ConcreteNodePtr::LeafNode(leaf_ptr) => {
+                    let leaf = leaf_ptr.as_ref();
+                    if !leaf
+                        .key_ref()
+                        .as_bytes()
+                        .starts_with(&self.current_key_prefix)
+                    {
+                        let current_key_prefix: KeyPrefix =
+                            self.current_key_prefix.as_slice().into();
+                        return Err(MalformedTreeError::PrefixMismatch {
+                            expected_prefix: current_key_prefix,
+                            entire_key: leaf.key_ref().clone(),
+                        });
+                    }
+
+                    return Ok(None);
+                },
+            }
+        };
 
-    fn visit_node16(&mut self, t: &crate::InnerNode16<K, V>) -> Self::Output {
-        self.visit_inner_node(t)
-    }
+        // update running key prefix with inner node partial prefix
+        self.current_key_prefix
+            .extend_from_slice(header.read_prefix());
+
+        if !header.stores_full_prefix() {
+            // The rest of the prefix is not stored in the header, so it is taken from the
+            // minimum leaf below the node, after checking it against the fingerprint. That
+            // leaf is still checked against the stored bytes, like every other leaf.
+            //
+            // SAFETY: The leaf reference only lives for this block, and the safety
+            // requirements forbid mutation of the tree.
+            let leaf = unsafe { minimum_unchecked(node).as_ref() };
+            let leaf_key = leaf.key_ref().as_bytes();
+            let num_skipped = header.prefix_size() - header.read_prefix().len();
+            let skipped_start = self.current_key_prefix.len();
+            match leaf_key.get(skipped_start..(skipped_start + num_skipped)) {
+                Some(skipped_bytes)
+                    if header.may_match_full_prefix(&leaf_key[original_key_prefix_len..]) =>
+                {
+                    self.current_key_prefix.extend_from_slice(skipped_bytes)
+                },
+                _ => {
+                    return Err(MalformedTreeError::PrefixMismatch {
+                        expected_prefix: self.current_key_prefix.as_slice().into(),
+                        entire_key: leaf.key_ref().clone(),
+                    })
+                },
+            }
+        }
 
-    fn visit_node48(&mut self, t: &crate::InnerNode48<K, V>) -> Self::Output {
-        self.visit_inner_node(t)
+        Ok(Some(CheckFrame {
+            // SAFETY: The `children` iterator only lives while the node is being checked,
+            // which will not overlap with any mutating access or operation, which is
+            // guaranteed by the `check_tree` caller requirements.
+            // PANIC SAFETY: The node was matched as an inner node above
+            children: unsafe { inner_node_children(node) }.unwrap(),
+            node_type,
+            original_key_prefix_len,
+            num_children: 0,
+            node_count: 0,
+        }))
     }
 
-    fn visit_node256(&mut self, t: &crate::InnerNode256<K, V>) -> Self::Output {
-        self.visit_inner_node(t)
+    fn pop_key_byte(&mut self, key_byte: u8) {
+        assert_eq!(
+            self.current_key_prefix
+                .pop()
+                .expect("should match push of key byte"),
+            key_byte
+        );
     }
+}
 
-    fn visit_leaf(&mut self, t: &crate::LeafNode<K, V>) -> Self::Output {
-        if !t.key_ref().as_bytes().starts_with(&self.current_key_prefix) {
-            let current_key_prefix: KeyPrefix = self.current_key_prefix.as_slice().into();
-            return Err(MalformedTreeError::PrefixMismatch {
-                expected_prefix: current_key_prefix,
-                entire_key: t.key_ref().clone(),
-            });
-        }
-
-        Ok(1)
-    }
+/// The state of an inner node whose children are being checked.
+struct CheckFrame<K, V> {
+    /// The children which have not been checked yet
+    children: InnerNodeIter<K, V>,
+    node_type: NodeType,
+    /// The length of the running key prefix before the prefix of this node
+    original_key_prefix_len: usize,
+    num_children: usize,
+    /// The number of nodes below this node which have been checked
+    node_count: usize,
 }
 
 #[cfg(test)]
@@ -539,10 +658,12 @@ mod tests {
                 key_prefix,
                 inner_node_type,
                 num_children,
+                expected_children,
             } => {
                 assert_eq!(key_prefix, []);
                 assert_eq!(inner_node_type, NodeType::Node16);
                 assert_eq!(num_children, 2);
+                assert_eq!(expected_children, 5..17);
             },
             _ => {
                 panic!("expected a WrongChildrenCount error")
@@ -0,0 +1,100 @@
+//! Serializing a [`TreeMap`] to bytes and back.
+//!
+//! [`to_bytes`] and [`from_bytes`] store the entries of a map in the compact
+//! binary format of the [`snapshot`] module, with the keys and values in
+//! order. Since the entries are stored in key order, reading the bytes builds
+//! the tree bottom up with the bulk loading path, instead of inserting every
+//! key.
+//!
+//! With the `serde` feature enabled, [`TreeMap`] and [`TreeSet`] also
+//! implement `Serialize` and `Deserialize`, as an ordered map and an ordered
+//! sequence.
+//!
+//! [`snapshot`]: crate::snapshot
+//! [`TreeSet`]: crate::TreeSet
+
+use crate::{
+    snapshot::{read_snapshot, write_snapshot, DecodeEntryError, SnapshotError},
+    AsBytes, TreeMap,
+};
+
+#[cfg(feature = "serde")]
+mod serde_impls;
+
+/// Return a snapshot of all the entries in the map, in the format written by
+/// [`write_snapshot`].
+///
+/// # Examples
+///
+/// ```rust
+/// use blart::{serialize, TreeMap};
+///
+/// let map: TreeMap<_, _> = (0..1000u32).map(|n| (n.to_be_bytes(), n % 7)).collect();
+///
+/// let bytes = serialize::to_bytes(&map, |value, buf| buf.push(*value as u8));
+/// let loaded = serialize::from_bytes(&bytes, |key, value| {
+///     Ok((key.try_into()?, u32::from(value[0])))
+/// })
+/// .unwrap();
+/// assert_eq!(loaded, map);
+/// ```
+pub fn to_bytes<K, V, F>(map: &TreeMap<K, V>, encode_value: F) -> Vec<u8>
+where
+    K: AsBytes,
+    F: FnMut(&V, &mut Vec<u8>),
+{
+    let mut bytes = Vec::new();
+    // PANIC SAFETY: Writing to a `Vec` does not fail
+    write_snapshot(map, &mut bytes, encode_value).expect("writing to a Vec should not fail");
+    bytes
+}
+
+/// Read a map from a snapshot in the given bytes, the same way as
+/// [`read_snapshot`].
+///
+/// Any bytes after the end of the snapshot are ignored.
+///
+/// # Errors
+///
+/// Returns the same errors as [`read_snapshot`], apart from
+/// [`SnapshotError::Io`].
+pub fn from_bytes<K, V, F>(bytes: &[u8], decode_entry: F) -> Result<TreeMap<K, V>, SnapshotError>
+where
+    K: AsBytes,
+    F: FnMut(&[u8], &[u8]) -> Result<(K, V), DecodeEntryError>,
+{
+    read_snapshot(bytes, decode_entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes_round_trip_and_ignore_trailing_bytes() {
+        let map: TreeMap<_, _> = (0..300u16).map(|n| (n.to_be_bytes(), n)).collect();
+
+        let mut bytes = to_bytes(&map, |value, buf| {
+            buf.extend_from_slice(&value.to_le_bytes())
+        });
+        bytes.extend_from_slice(b"trailing");
+        let loaded = from_bytes(&bytes, |key, value| {
+            Ok((key.try_into()?, u16::from_le_bytes(value.try_into()?)))
+        })
+        .unwrap();
+        assert_eq!(loaded, map);
+
+        let empty = to_bytes(&TreeMap::<[u8; 2], u16>::new(), |_, _| {});
+        assert!(from_bytes(&empty, |_, _| -> Result<([u8; 2], u16), _> {
+            unreachable!("there are no entries")
+        })
+        .unwrap()
+        .is_empty());
+        assert!(matches!(
+            from_bytes(&bytes[..4], |_, _| -> Result<([u8; 2], u16), _> {
+                unreachable!("the header is truncated")
+            }),
+            Err(SnapshotError::InvalidFormat { .. })
+        ));
+    }
+}
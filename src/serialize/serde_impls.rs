@@ -0,0 +1,183 @@
+//! [`serde`] support for [`TreeMap`] and [`TreeSet`], which are serialized as
+//! an ordered map and an ordered sequence.
+//!
+//! Deserializing collects all the entries and then builds the tree with
+//! [`TreeMap::try_from_sorted_iter`], so the entries do not have to be in
+//! order, but input that was serialized from a tree is loaded the fastest.
+//! Like repeated inserts, only the last value is kept for a duplicate key. A
+//! key which is a prefix of another key is an error.
+
+use crate::{AsBytes, TreeMap, TreeSet};
+use serde::{
+    de::{Error, MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use std::{fmt, marker::PhantomData};
+
+/// The largest number of entries to allocate space for up front, so that a
+/// wrong size hint in the input cannot cause a huge allocation.
+const MAX_PREALLOCATED_ENTRIES: usize = 64 * 1024;
+
+impl<K, V> Serialize for TreeMap<K, V>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self)
+    }
+}
+
+struct TreeMapVisitor<K, V>(PhantomData<TreeMap<K, V>>);
+
+impl<'de, K, V> Visitor<'de> for TreeMapVisitor<K, V>
+where
+    K: Deserialize<'de> + AsBytes,
+    V: Deserialize<'de>,
+{
+    type Value = TreeMap<K, V>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("a map")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<Self::Value, A::Error> {
+        let mut entries = Vec::with_capacity(
+            access
+                .size_hint()
+                .unwrap_or(0)
+                .min(MAX_PREALLOCATED_ENTRIES),
+        );
+        while let Some(entry) = access.next_entry()? {
+            entries.push(entry);
+        }
+
+        TreeMap::try_from_sorted_iter(entries).map_err(A::Error::custom)
+    }
+}
+
+impl<'de, K, V> Deserialize<'de> for TreeMap<K, V>
+where
+    K: Deserialize<'de> + AsBytes,
+    V: Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(TreeMapVisitor(PhantomData))
+    }
+}
+
+impl<T> Serialize for TreeSet<T>
+where
+    T: Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self)
+    }
+}
+
+struct TreeSetVisitor<T>(PhantomData<TreeSet<T>>);
+
+impl<'de, T> Visitor<'de> for TreeSetVisitor<T>
+where
+    T: Deserialize<'de> + AsBytes,
+{
+    type Value = TreeSet<T>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("a sequence")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut access: A) -> Result<Self::Value, A::Error> {
+        let mut entries = Vec::with_capacity(
+            access
+                .size_hint()
+                .unwrap_or(0)
+                .min(MAX_PREALLOCATED_ENTRIES),
+        );
+        while let Some(value) = access.next_element()? {
+            entries.push((value, ()));
+        }
+
+        TreeMap::try_from_sorted_iter(entries)
+            .map(TreeSet::from)
+            .map_err(A::Error::custom)
+    }
+}
+
+impl<'de, T> Deserialize<'de> for TreeSet<T>
+where
+    T: Deserialize<'de> + AsBytes,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(TreeSetVisitor(PhantomData))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_test::{assert_de_tokens_error, assert_tokens, Token};
+
+    #[test]
+    fn map_as_ordered_map() {
+        let mut map = TreeMap::<[u8; 2], char>::new();
+        map.insert([1, 0], 'b');
+        map.insert([0, 5], 'a');
+
+        assert_tokens(
+            &map,
+            &[
+                Token::Map { len: Some(2) },
+                Token::Tuple { len: 2 },
+                Token::U8(0),
+                Token::U8(5),
+                Token::TupleEnd,
+                Token::Char('a'),
+                Token::Tuple { len: 2 },
+                Token::U8(1),
+                Token::U8(0),
+                Token::TupleEnd,
+                Token::Char('b'),
+                Token::MapEnd,
+            ],
+        );
+    }
+
+    #[test]
+    fn set_as_ordered_sequence() {
+        let set = TreeSet::from([[2u8], [0], [1]]);
+
+        assert_tokens(
+            &set,
+            &[
+                Token::Seq { len: Some(3) },
+                Token::Tuple { len: 1 },
+                Token::U8(0),
+                Token::TupleEnd,
+                Token::Tuple { len: 1 },
+                Token::U8(1),
+                Token::TupleEnd,
+                Token::Tuple { len: 1 },
+                Token::U8(2),
+                Token::TupleEnd,
+                Token::SeqEnd,
+            ],
+        );
+    }
+
+    #[test]
+    fn prefix_keys_are_rejected() {
+        assert_de_tokens_error::<TreeMap<String, u8>>(
+            &[
+                Token::Map { len: Some(2) },
+                Token::Str("ab"),
+                Token::U8(1),
+                Token::Str("a"),
+                Token::U8(2),
+                Token::MapEnd,
+            ],
+            "Attempted to insert a key [[97, 98]] which is either a prefix of an existing key or \
+             an existing key is a prefix of the new key.",
+        );
+    }
+}
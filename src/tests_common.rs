@@ -6,6 +6,9 @@ use crate::{
 };
 use std::{collections::HashSet, fmt, io, iter};
 
+#[cfg(feature = "alloc-failure-injection")]
+pub mod alloc_failure;
+
 /// Generate an iterator of bytestring keys, with increasing length up to a
 /// maximum value.
 ///
@@ -0,0 +1,120 @@
+//! Helpers to inject allocation failures into tree operations.
+//!
+//! A global allocator is not allowed to unwind, and the standard library
+//! aborts the process when an allocator returns a null pointer. Instead, every
+//! allocation the tree makes for its nodes is reported to this module, which
+//! can be armed to make the `n`th of those allocations fail by panicking with
+//! an [`InjectedAllocationFailure`] payload. This models the behaviour of
+//! `-Z oom=panic`, where a failed allocation unwinds instead of aborting.
+//!
+//! The failure counters are thread-local, so tests running in parallel do not
+//! interfere with each other.
+
+use std::{
+    cell::Cell,
+    panic::{self, AssertUnwindSafe},
+};
+
+thread_local! {
+    static ALLOCATIONS_BEFORE_FAILURE: Cell<Option<usize>> = const { Cell::new(None) };
+    static NUM_ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// The panic payload of an injected allocation failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InjectedAllocationFailure;
+
+/// Record that the tree is about to allocate, and panic with an
+/// [`InjectedAllocationFailure`] if this allocation was selected to fail.
+pub(crate) fn record_allocation() {
+    NUM_ALLOCATIONS.with(|count| count.set(count.get().wrapping_add(1)));
+
+    let should_fail = ALLOCATIONS_BEFORE_FAILURE.with(|remaining| match remaining.get() {
+        Some(0) => {
+            remaining.set(None);
+            true
+        },
+        Some(n) => {
+            remaining.set(Some(n - 1));
+            false
+        },
+        None => false,
+    });
+
+    if should_fail {
+        panic::panic_any(InjectedAllocationFailure);
+    }
+}
+
+/// A guard which keeps an allocation failure armed until it is dropped.
+///
+/// Created by [`fail_nth_allocation`].
+#[derive(Debug)]
+pub struct FailAllocationGuard {
+    _private: (),
+}
+
+impl Drop for FailAllocationGuard {
+    fn drop(&mut self) {
+        ALLOCATIONS_BEFORE_FAILURE.with(|remaining| remaining.set(None));
+    }
+}
+
+/// Make the `n`th (starting from 0) allocation of tree nodes on the current
+/// thread fail, as long as the returned guard is alive.
+///
+/// Only a single failure is injected, every allocation after the failed one
+/// succeeds.
+pub fn fail_nth_allocation(n: usize) -> FailAllocationGuard {
+    ALLOCATIONS_BEFORE_FAILURE.with(|remaining| remaining.set(Some(n)));
+
+    FailAllocationGuard { _private: () }
+}
+
+/// Run the given closure and return its result along with the number of tree
+/// allocations it performed on the current thread.
+pub fn count_allocations<R>(f: impl FnOnce() -> R) -> (R, usize) {
+    let before = NUM_ALLOCATIONS.with(Cell::get);
+    let result = f();
+    let after = NUM_ALLOCATIONS.with(Cell::get);
+
+    (result, after.wrapping_sub(before))
+}
+
+/// Run `operation` repeatedly, failing a different allocation each time,
+/// until it completes without hitting an injected failure.
+///
+/// The first run fails the first allocation, the second run fails the second
+/// allocation, and so on. After every injected failure `after_failure` is
+/// called with the state and the index of the failed allocation, so that it
+/// can check that the operation left the state consistent.
+///
+/// Returns the number of injected failures, which is the number of
+/// allocations performed by the final successful run of `operation`.
+///
+/// # Panics
+///
+/// Any panic from `operation` other than an injected allocation failure is
+/// propagated.
+pub fn fail_each_allocation<S>(
+    state: &mut S,
+    mut operation: impl FnMut(&mut S),
+    mut after_failure: impl FnMut(&mut S, usize),
+) -> usize {
+    let mut n = 0;
+    loop {
+        let guard = fail_nth_allocation(n);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| operation(state)));
+        drop(guard);
+
+        match result {
+            Ok(()) => return n,
+            Err(payload) if payload.is::<InjectedAllocationFailure>() => {
+                after_failure(state, n);
+            },
+            Err(payload) => panic::resume_unwind(payload),
+        }
+
+        n += 1;
+    }
+}
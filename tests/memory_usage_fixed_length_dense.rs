@@ -45,7 +45,7 @@ fn test_memory_usage() {
         dhat::assert_eq!(stats.curr_bytes, 0);
 
         dhat::assert_eq!(stats.max_blocks, 398);
-        dhat::assert_eq!(stats.max_bytes, 18088);
+        dhat::assert_eq!(stats.max_bytes, 14896);
 
         let num_keys = KEY_LEVEL_WIDTH
             .iter()